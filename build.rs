@@ -6,6 +6,36 @@ use std::io::prelude::*;
 
 // The below is copied from src/main.rs
 
+/// The --echo-cancel option of the subcommands recording the microphone
+macro_rules! echo_cancel_arg {
+    () => {
+        Arg::new("echo-cancel")
+            .long("echo-cancel")
+            .action(ArgAction::SetTrue)
+            .help(gettext("Record from the echo-cancelled copy of the microphone provided by PulseAudio or PipeWire, if any, so that the songs played by this computer don't drown the ones to recognize"))
+    };
+}
+
+/// The --trim-start and --trim-duration options of the subcommands sending a
+/// fingerprint, read by `trim_signature`
+macro_rules! trim_args {
+    () => {
+        [
+            Arg::new("trim-start")
+                .long("trim-start")
+                .value_name("SECONDS")
+                .default_value("0")
+                .value_parser(clap::value_parser!(f32))
+                .help(gettext("Drop the part of the fingerprint located before this position")),
+            Arg::new("trim-duration")
+                .long("trim-duration")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(f32))
+                .help(gettext("Only keep this many seconds of the fingerprint, so that no more information than needed about the recorded audio is sent")),
+        ]
+    };
+}
+
 macro_rules! base_app {
     () => {
         command!()
@@ -21,6 +51,150 @@ macro_rules! base_app {
 -vv: Set the log level to DEBUG for SongRec-related messages and INFO for library-related messages\n\
 -vvv: Set the log level to TRACE"))
         )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .global(true)
+                .help(gettext("Use a separate history, favorites and preferences, stored under the given profile name"))
+        )
+        .arg(
+            Arg::new("read-only-history")
+                .long("read-only-history")
+                .value_name("PATH")
+                .global(true)
+                .help(gettext("Browse and export the song history stored at this path (a history file, or the SongRec data folder of another computer on a network share) without ever writing to it"))
+        )
+        .arg(
+            Arg::new("timezone")
+                .long("timezone")
+                .value_name("TIME_ZONE")
+                .global(true)
+                .help(gettext("Date the recognitions, reports and logs in this time zone, e.g. \"Europe/Paris\", rather than the one of the system (also read from the SONGREC_TIMEZONE environment variable)"))
+        )
+        .arg(
+            Arg::new("no-signature-cache")
+                .long("no-signature-cache")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help(gettext("Always decode and fingerprint the audio files again, rather than reusing the signatures cached from a previous run on the same contents"))
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .value_name("LANGUAGE")
+                .global(true)
+                .help(gettext("Print the help and the messages in this language, e.g. \"fr\" or \"pt_BR\", rather than the one of the system"))
+        )
+        .arg(
+            Arg::new("no-history")
+                .long("no-history")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help(gettext("Incognito mode: display the recognized songs, but don't write them to the history, forward them to the sinks or cache anything about them on the disk"))
+        )
+        .arg(
+            Arg::new("max-decodes")
+                .long("max-decodes")
+                .value_name("COUNT")
+                .global(true)
+                .value_parser(clap::value_parser!(usize).range(1..))
+                .help(gettext("Decode at most this number of audio files or stream chunks at the same time, e.g. when indexing a library on a small machine"))
+        )
+        .arg(
+            Arg::new("memory-limit")
+                .long("memory-limit")
+                .value_name("MB")
+                .global(true)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help(gettext("Limit the memory used by the decoded audio and by FFMpeg to this number of megabytes; only the beginning of longer files is used"))
+        )
+        .arg(
+            Arg::new("requests-per-minute")
+                .long("requests-per-minute")
+                .value_name("COUNT")
+                .global(true)
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help(gettext("Send at most this number of recognition requests per minute, waiting when needed"))
+        )
+        .arg(
+            Arg::new("transliterate")
+                .long("transliterate")
+                .value_name("MODE")
+                .global(true)
+                .value_parser(["off", "replace", "dual"])
+                .help(gettext("Transliterate the titles, artists and albums written in other scripts into Latin, replacing them (\"Utro\") or appending the transliteration (\"Утро (Utro)\"), in every output and the history"))
+        )
+        .arg(
+            Arg::new("transliterate-scripts")
+                .long("transliterate-scripts")
+                .value_name("SCRIPTS")
+                .global(true)
+                .help(gettext("Transliterate only these scripts, separated by commas, among latin (for the accents), greek, cyrillic, armenian, hebrew, arabic, devanagari, thai, georgian, hangul, kana and han"))
+        )
+        .arg(
+            Arg::new("normalize")
+                .long("normalize")
+                .value_name("RULES")
+                .global(true)
+                .help(gettext("Normalize the titles and artists looked up in other services with these rules, separated by commas: featuring (drop the featured artists), remaster (drop the remaster versions) and case (write the names in capitals in title case)"))
+        )
+        .arg(
+            Arg::new("user-agent")
+                .long("user-agent")
+                .value_name("IDENTITY")
+                .global(true)
+                .help(gettext("User agent of the requests to Shazam: rotate (a different one of the built-in set for each request, the default), fixed (one of them for the whole session), the number of one of them from 1 to 100, or any other string to send as is"))
+        )
+        .arg(
+            Arg::new("profile-out")
+                .long("profile-out")
+                .value_name("PATH")
+                .global(true)
+                .help(gettext("Write a timeline of the audio, signature, HTTP and sink activity to this file, in the Chrome trace format that Perfetto opens, for analyzing performance issues"))
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .value_name("PATH")
+                .global(true)
+                .help(gettext("Also write log messages to the given file"))
+        )
+        .arg(
+            Arg::new("log-file-level")
+                .long("log-file-level")
+                .value_name("LEVEL")
+                .global(true)
+                .value_parser(["error", "warn", "info", "debug", "trace"])
+                .default_value("info")
+                .help(gettext("Log level used for the log file, independently of the standard error output"))
+        )
+        .arg(
+            Arg::new("log-max-size")
+                .long("log-max-size")
+                .value_name("MB")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10")
+                .help(gettext("Rotate the log file once it reaches this size in megabytes (0 to disable)"))
+        )
+        .arg(
+            Arg::new("log-rotate")
+                .long("log-rotate")
+                .global(true)
+                .value_parser(["never", "hourly", "daily"])
+                .default_value("never")
+                .help(gettext("Also rotate the log file at the start of each hour or day"))
+        )
+        .arg(
+            Arg::new("log-keep")
+                .long("log-keep")
+                .value_name("COUNT")
+                .global(true)
+                .value_parser(clap::value_parser!(usize))
+                .default_value("5")
+                .help(gettext("Number of rotated log files to keep"))
+        )
         .subcommand(
             Command::new("listen")
                 .about(gettext("Run as a command-line program listening the microphone and printing recognized songs to stdout, exposing current song info via MPRIS"))
@@ -35,7 +209,14 @@ macro_rules! base_app {
                     Arg::new("audio-device")
                         .short('d')
                         .long("audio-device")
-                        .help(gettext("Specify the audio device to use"))
+                        .help(gettext("Specify the audio device to use, auto to pick the most promising one, snapcast:HOST[:PORT] to record a Snapcast stream, or file:PATH to play a sound file as if it was being recorded (file:PATH?speed=4 plays it four times faster)"))
+                )
+                .arg(echo_cancel_arg!())
+                .arg(
+                    Arg::new("split-channels")
+                        .long("split-channels")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Recognize the left and right channels of the audio device separately, when two sources are panned hard (e.g. the two decks of a DJ monitoring setup), and print the channel of each song"))
                 )
                 .arg(
                     Arg::new("request-interval")
@@ -45,6 +226,80 @@ macro_rules! base_app {
                         .value_parser(clap::value_parser!(u64))
                         .help(gettext("Shazam interval between requests in seconds (increase if you are rate-limited)"))
                 )
+                .arg(
+                    Arg::new("providers")
+                        .long("providers")
+                        .value_name("PROVIDERS")
+                        .value_delimiter(',')
+                        .value_parser(["shazam", "local"])
+                        .default_value("shazam")
+                        .help(gettext("Comma-separated list of the recognition providers to query, by order of preference: \"shazam\", and \"local\" for the local fingerprint index (see the \"index\" subcommand)"))
+                )
+                .arg(
+                    Arg::new("race-providers")
+                        .long("race-providers")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Query all the providers at the same time and keep the first match, rather than trying them one after the other"))
+                )
+                .arg(
+                    Arg::new("low-data")
+                        .long("low-data")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Reduce network usage: don't download cover art, and send requests at most every 30 seconds. Enabled automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("ignore-metered")
+                        .long("ignore-metered")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("low-data")
+                        .help(gettext("Don't enable the low-data mode automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("qr")
+                        .long("qr")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print a QR code linking to each recognized song to the standard error"))
+                )
+                .arg(
+                    Arg::new("diagnostics")
+                        .long("diagnostics")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the time taken by each stage of the recognition (capture, resampling, FFT, encoding, HTTP, parsing) to the standard error"))
+                )
+                .arg(
+                    Arg::new("share-template")
+                        .long("share-template")
+                        .value_name("TEMPLATE")
+                        .conflicts_with_all(["json", "csv"])
+                        .help(gettext("Print each recognized song with this template, e.g. \"Now playing: {title} by {artist} {link}\" (other placeholders: {album}, {year}, {genre}, {date}, {bandcamp}, {isrc}, {upc})"))
+                )
+                .arg(
+                    Arg::new("script")
+                        .long("script")
+                        .value_name("PATH")
+                        .help(gettext("Run the on_song function of this Rhai script on each recognized song, in order to filter or rewrite it"))
+                )
+                .arg(
+                    Arg::new("ipv4")
+                        .short('4')
+                        .long("ipv4")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("ipv6")
+                        .help(gettext("Only connect to Shazam over IPv4"))
+                )
+                .arg(
+                    Arg::new("ipv6")
+                        .short('6')
+                        .long("ipv6")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Only connect to Shazam over IPv6"))
+                )
+                .arg(
+                    Arg::new("interface")
+                        .long("interface")
+                        .value_name("INTERFACE")
+                        .help(gettext("Send requests from this network interface, e.g. a VPN tunnel"))
+                )
                 .arg(
                     Arg::new("json")
                         .short('j')
@@ -60,12 +315,339 @@ macro_rules! base_app {
                         .action(ArgAction::SetTrue)
                         .help(gettext("Enable printing full song info in the CSV format"))
                 )
+                .arg(
+                    Arg::new("csv-columns")
+                        .long("csv-columns")
+                        .value_name("COLUMNS")
+                        .value_delimiter(',')
+                        .value_parser([
+                            "song_name", "title", "artist", "album", "track_key", "release_year",
+                            "genre", "recognition_date", "timestamp", "shazam_url", "spotify_uri",
+                            "source", "bandcamp_url", "isrc", "upc"
+                        ])
+                        .requires("csv")
+                        .help(gettext("Comma-separated list of the columns of the CSV output, in order (default: song_name,album,track_key,release_year,genre,recognition_date)"))
+                )
+                .arg(
+                    Arg::new("no-csv-header")
+                        .long("no-csv-header")
+                        .action(ArgAction::SetTrue)
+                        .requires("csv")
+                        .help(gettext("Don't print a header row before the CSV output"))
+                )
                 .arg(
                     Arg::new("disable-mpris")
                         .long("disable-mpris")
                         .action(ArgAction::SetTrue)
                         .help(gettext("Disable MPRIS support"))
                 )
+                .arg(
+                    Arg::new("metrics-address")
+                        .long("metrics-address")
+                        .value_name("ADDRESS:PORT")
+                        .help(gettext("Expose Prometheus metrics over HTTP at the given address (e.g. 127.0.0.1:9898), under /metrics"))
+                )
+                .arg(
+                    Arg::new("watch-chromecast")
+                        .long("watch-chromecast")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Also report the songs played by the Chromecast and Google Cast devices of the local network, as announced by their apps"))
+                )
+                .arg(
+                    Arg::new("ignore-own-spotify-playback")
+                        .long("ignore-own-spotify-playback")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Do not forward recognized songs to MQTT or KDE Connect when they are being played by the local Spotify client (read over MPRIS), to avoid feedback loops"))
+                )
+                .arg(
+                    Arg::new("kdeconnect")
+                        .long("kdeconnect")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Push recognized songs to paired phones through KDE Connect"))
+                )
+                .arg(
+                    Arg::new("kdeconnect-device")
+                        .long("kdeconnect-device")
+                        .value_name("DEVICE")
+                        .requires("kdeconnect")
+                        .help(gettext("Only push songs to the KDE Connect device with this name or identifier"))
+                )
+                .arg(
+                    Arg::new("announce")
+                        .long("announce")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Announce recognized songs aloud (\"Now playing: X by Y\") through speech-dispatcher"))
+                )
+                .arg(
+                    Arg::new("mqtt-broker")
+                        .long("mqtt-broker")
+                        .value_name("HOST[:PORT]")
+                        .help(gettext("Publish recognized songs to the given MQTT broker"))
+                )
+                .arg(
+                    Arg::new("mqtt-username")
+                        .long("mqtt-username")
+                        .requires("mqtt-broker")
+                        .help(gettext("Username used to authenticate to the MQTT broker, with the password read from the SONGREC_MQTT_PASSWORD environment variable"))
+                )
+                .arg(
+                    Arg::new("mqtt-topic")
+                        .long("mqtt-topic")
+                        .default_value("songrec")
+                        .help(gettext("Base MQTT topic under which song information is published"))
+                )
+                .arg(
+                    Arg::new("mqtt-discovery-prefix")
+                        .long("mqtt-discovery-prefix")
+                        .default_value("homeassistant")
+                        .help(gettext("Home Assistant MQTT discovery prefix"))
+                )
+                .arg(
+                    Arg::new("disable-mqtt-discovery")
+                        .long("disable-mqtt-discovery")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Do not publish Home Assistant MQTT discovery messages"))
+                )
+                .arg(
+                    Arg::new("mastodon-instance")
+                        .long("mastodon-instance")
+                        .value_name("URL")
+                        .help(gettext("Post recognized songs to a Mastodon account of this instance, e.g. https://mastodon.social (the access token is read from the SONGREC_MASTODON_TOKEN environment variable, or from the file given with --mastodon-token-file)"))
+                )
+                .arg(
+                    Arg::new("mastodon-token-file")
+                        .long("mastodon-token-file")
+                        .value_name("PATH")
+                        .requires("mastodon-instance")
+                        .help(gettext("File containing the access token of the Mastodon application, with the write:statuses scope"))
+                )
+                .arg(
+                    Arg::new("mastodon-template")
+                        .long("mastodon-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the Mastodon posts, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("mastodon-hashtags")
+                        .long("mastodon-hashtags")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Hashtags appended to the Mastodon posts, which may use placeholders too, e.g. \"#NowPlaying #{genre}\" (#NowPlaying by default)"))
+                )
+                .arg(
+                    Arg::new("mastodon-visibility")
+                        .long("mastodon-visibility")
+                        .value_parser(["public", "unlisted", "private", "direct"])
+                        .help(gettext("Visibility of the Mastodon posts, unlisted by default"))
+                )
+                .arg(
+                    Arg::new("mastodon-interval")
+                        .long("mastodon-interval")
+                        .value_name("SECONDS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help(gettext("Minimum number of seconds between two Mastodon posts, 180 by default"))
+                )
+                .arg(
+                    Arg::new("telegram-chat")
+                        .long("telegram-chat")
+                        .value_name("CHAT_ID")
+                        .help(gettext("Send recognized songs to this Telegram chat, through a bot whose token is read from the SONGREC_TELEGRAM_TOKEN environment variable"))
+                )
+                .arg(
+                    Arg::new("telegram-template")
+                        .long("telegram-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the Telegram messages, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("matrix-room")
+                        .long("matrix-room")
+                        .value_name("ROOM_ID")
+                        .requires("matrix-homeserver")
+                        .help(gettext("Announce recognized songs in this Matrix room, e.g. !abcdef:matrix.org, as the account whose access token is read from the SONGREC_MATRIX_TOKEN environment variable"))
+                )
+                .arg(
+                    Arg::new("matrix-homeserver")
+                        .long("matrix-homeserver")
+                        .value_name("URL")
+                        .requires("matrix-room")
+                        .help(gettext("Homeserver of the Matrix account, e.g. https://matrix.org"))
+                )
+                .arg(
+                    Arg::new("matrix-template")
+                        .long("matrix-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the Matrix messages, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("webhook-url")
+                        .long("webhook-url")
+                        .value_name("URL")
+                        .help(gettext("Post the share text of recognized songs to this webhook, as JSON, e.g. a Slack, Mattermost or Discord incoming webhook"))
+                )
+                .arg(
+                    Arg::new("webhook-template")
+                        .long("webhook-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the text posted to the webhook, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("webhook-text-field")
+                        .long("webhook-text-field")
+                        .value_name("FIELD")
+                        .default_value("text")
+                        .requires("webhook-url")
+                        .help(gettext("JSON field of the text posted to the webhook: \"text\" for Slack and Mattermost, \"content\" for Discord"))
+                )
+                .arg(
+                    Arg::new("apple-music-playlist")
+                        .long("apple-music-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .help(gettext("Add recognized songs to this Apple Music library playlist, e.g. p.AbCdEfGh, with the developer and user tokens read from the SONGREC_APPLE_MUSIC_DEVELOPER_TOKEN and SONGREC_APPLE_MUSIC_USER_TOKEN environment variables"))
+                )
+                .arg(
+                    Arg::new("apple-music-library")
+                        .long("apple-music-library")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Add recognized songs to the Apple Music library"))
+                )
+                .arg(
+                    Arg::new("apple-music-storefront")
+                        .long("apple-music-storefront")
+                        .value_name("COUNTRY")
+                        .default_value("us")
+                        .help(gettext("Country code of the Apple Music catalog searched for the songs that Shazam doesn't link to Apple Music"))
+                )
+                .arg(
+                    Arg::new("subsonic-url")
+                        .long("subsonic-url")
+                        .value_name("URL")
+                        .help(gettext("Look recognized songs up on this Subsonic-compatible server (Navidrome, Airsonic...), with the password read from the SONGREC_SUBSONIC_PASSWORD environment variable"))
+                )
+                .arg(
+                    Arg::new("subsonic-user")
+                        .long("subsonic-user")
+                        .requires("subsonic-url")
+                        .help(gettext("User name on the Subsonic server"))
+                )
+                .arg(
+                    Arg::new("subsonic-playlist")
+                        .long("subsonic-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .requires("subsonic-url")
+                        .help(gettext("Add the songs found on the Subsonic server to this playlist"))
+                )
+                .arg(
+                    Arg::new("subsonic-star")
+                        .long("subsonic-star")
+                        .action(ArgAction::SetTrue)
+                        .requires("subsonic-url")
+                        .help(gettext("Star the songs found on the Subsonic server, adding them to the favorites"))
+                )
+                .arg(
+                    Arg::new("plex-url")
+                        .long("plex-url")
+                        .value_name("URL")
+                        .help(gettext("Add recognized songs which are in the music library of this Plex server to a collection, with the token read from the SONGREC_PLEX_TOKEN environment variable"))
+                )
+                .arg(
+                    Arg::new("plex-collection")
+                        .long("plex-collection")
+                        .value_name("NAME")
+                        .default_value("Recognized by SongRec")
+                        .help(gettext("Name of the Plex collection"))
+                )
+                .arg(
+                    Arg::new("jellyfin-url")
+                        .long("jellyfin-url")
+                        .value_name("URL")
+                        .requires("jellyfin-collection")
+                        .help(gettext("Add recognized songs which are in the music library of this Jellyfin server to a collection, with the API key read from the SONGREC_JELLYFIN_API_KEY environment variable"))
+                )
+                .arg(
+                    Arg::new("jellyfin-collection")
+                        .long("jellyfin-collection")
+                        .value_name("COLLECTION_ID")
+                        .requires("jellyfin-url")
+                        .help(gettext("Identifier of the Jellyfin collection"))
+                )
+                .arg(
+                    Arg::new("tidal-playlist")
+                        .long("tidal-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .help(gettext("Add recognized songs to this Tidal playlist, with the access token read from the SONGREC_TIDAL_TOKEN environment variable, with the \"playlists.write\" scope (requires the \"tidal\" compile-time feature)"))
+                )
+                .arg(
+                    Arg::new("tidal-country")
+                        .long("tidal-country")
+                        .value_name("COUNTRY")
+                        .default_value("US")
+                        .help(gettext("Country code of the Tidal catalog to look the songs up in"))
+                )
+                .arg(
+                    Arg::new("deezer-playlist")
+                        .long("deezer-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .help(gettext("Add recognized songs to this Deezer playlist, with the access token read from the SONGREC_DEEZER_TOKEN environment variable, with the \"manage_library\" permission (requires the \"deezer\" compile-time feature)"))
+                )
+                .arg(
+                    Arg::new("sinks-config")
+                        .long("sinks-config")
+                        .value_name("PATH")
+                        .help(gettext("Forward the recognized songs to the sinks listed in this TOML file, instead of the sinks.toml file of the preferences directory"))
+                )
+                .arg(
+                    Arg::new("stream")
+                        .long("stream")
+                        .value_name("TAG=URL")
+                        .action(ArgAction::Append)
+                        .help(gettext("Also recognize the songs of this Icecast or HLS stream, tagged with this name in the history (can be repeated; the microphone is only recorded along with streams when --audio-device is given)"))
+                )
+                .arg(
+                    Arg::new("streams-config")
+                        .long("streams-config")
+                        .value_name("PATH")
+                        .help(gettext("Also recognize the songs of the streams listed in this TOML file, each one possibly with its own sinks"))
+                )
+                .arg(
+                    Arg::new("dump-capture")
+                        .long("dump-capture")
+                        .value_name("DIRECTORY")
+                        .help(gettext("Write the raw captured audio to a session file of this directory, which can be fed again to the recognition with the replay command"))
+                )
+                .arg(
+                    Arg::new("record-session")
+                        .long("record-session")
+                        .value_name("FILE")
+                        .help(gettext("Record the whole session to this Ogg Vorbis file once listening stops, with a chapter at each recognized song"))
+                )
+                .arg(
+                    Arg::new("windows-service")
+                        .long("windows-service")
+                        .hide(true)
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Run as the Windows service installed with \"songrec service install\""))
+                )
+        )
+        .subcommand(
+            Command::new("service")
+                .about(gettext("Install or remove the Windows service running \"songrec listen\" from the system startup, without any logged in user"))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("install")
+                        .about(gettext("Install the service, started with the system, which logs to the Windows event log"))
+                        .arg(
+                            Arg::new("listen-options")
+                                .num_args(0..)
+                                .trailing_var_arg(true)
+                                .allow_hyphen_values(true)
+                                .value_name("LISTEN_OPTIONS")
+                                .help(gettext("Options of the listen subcommand used by the service, after \"--\", e.g. \"-- --audio-device Microphone --telegram-chat 1234\""))
+                        )
+                )
+                .subcommand(
+                    Command::new("uninstall")
+                        .about(gettext("Stop and remove the service"))
+                )
         )
         .subcommand(
             Command::new("recognize")
@@ -82,8 +664,9 @@ macro_rules! base_app {
                         .short('d')
                         .long("audio-device")
                         .action(ArgAction::Set)
-                        .help(gettext("Specify the audio device to use"))
+                        .help(gettext("Specify the audio device to use, auto to pick the most promising one, snapcast:HOST[:PORT] to record a Snapcast stream, or file:PATH to play a sound file as if it was being recorded (file:PATH?speed=4 plays it four times faster)"))
                 )
+                .arg(echo_cancel_arg!())
                 .arg(
                     Arg::new("request-interval")
                         .short('i')
@@ -92,6 +675,80 @@ macro_rules! base_app {
                         .value_parser(clap::value_parser!(u64))
                         .help(gettext("Shazam interval between requests in seconds (increase if you are rate-limited)"))
                 )
+                .arg(
+                    Arg::new("providers")
+                        .long("providers")
+                        .value_name("PROVIDERS")
+                        .value_delimiter(',')
+                        .value_parser(["shazam", "local"])
+                        .default_value("shazam")
+                        .help(gettext("Comma-separated list of the recognition providers to query, by order of preference: \"shazam\", and \"local\" for the local fingerprint index (see the \"index\" subcommand)"))
+                )
+                .arg(
+                    Arg::new("race-providers")
+                        .long("race-providers")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Query all the providers at the same time and keep the first match, rather than trying them one after the other"))
+                )
+                .arg(
+                    Arg::new("low-data")
+                        .long("low-data")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Reduce network usage: don't download cover art, and send requests at most every 30 seconds. Enabled automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("ignore-metered")
+                        .long("ignore-metered")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("low-data")
+                        .help(gettext("Don't enable the low-data mode automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("qr")
+                        .long("qr")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print a QR code linking to each recognized song to the standard error"))
+                )
+                .arg(
+                    Arg::new("diagnostics")
+                        .long("diagnostics")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the time taken by each stage of the recognition (capture, resampling, FFT, encoding, HTTP, parsing) to the standard error"))
+                )
+                .arg(
+                    Arg::new("share-template")
+                        .long("share-template")
+                        .value_name("TEMPLATE")
+                        .conflicts_with_all(["json", "csv"])
+                        .help(gettext("Print each recognized song with this template, e.g. \"Now playing: {title} by {artist} {link}\" (other placeholders: {album}, {year}, {genre}, {date}, {bandcamp}, {isrc}, {upc})"))
+                )
+                .arg(
+                    Arg::new("script")
+                        .long("script")
+                        .value_name("PATH")
+                        .help(gettext("Run the on_song function of this Rhai script on each recognized song, in order to filter or rewrite it"))
+                )
+                .arg(
+                    Arg::new("ipv4")
+                        .short('4')
+                        .long("ipv4")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("ipv6")
+                        .help(gettext("Only connect to Shazam over IPv4"))
+                )
+                .arg(
+                    Arg::new("ipv6")
+                        .short('6')
+                        .long("ipv6")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Only connect to Shazam over IPv6"))
+                )
+                .arg(
+                    Arg::new("interface")
+                        .long("interface")
+                        .value_name("INTERFACE")
+                        .help(gettext("Send requests from this network interface, e.g. a VPN tunnel"))
+                )
                 .arg(
                     Arg::new("json")
                         .short('j')
@@ -107,15 +764,474 @@ macro_rules! base_app {
                         .action(ArgAction::SetTrue)
                         .help(gettext("Enable printing full song info in the CSV format"))
                 )
+                .arg(
+                    Arg::new("csv-columns")
+                        .long("csv-columns")
+                        .value_name("COLUMNS")
+                        .value_delimiter(',')
+                        .value_parser([
+                            "song_name", "title", "artist", "album", "track_key", "release_year",
+                            "genre", "recognition_date", "timestamp", "shazam_url", "spotify_uri",
+                            "source", "bandcamp_url", "isrc", "upc"
+                        ])
+                        .requires("csv")
+                        .help(gettext("Comma-separated list of the columns of the CSV output, in order (default: song_name,album,track_key,release_year,genre,recognition_date)"))
+                )
+                .arg(
+                    Arg::new("no-csv-header")
+                        .long("no-csv-header")
+                        .action(ArgAction::SetTrue)
+                        .requires("csv")
+                        .help(gettext("Don't print a header row before the CSV output"))
+                )
+                .arg(
+                    Arg::new("diff-tags")
+                        .long("diff-tags")
+                        .requires("input_file")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Compare the recognized song with the tags of the input file, and print the fields that differ"))
+                )
+                .arg(
+                    Arg::new("apply")
+                        .long("apply")
+                        .requires("input_file")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Like --diff-tags, but also rewrite the mismatching tags of the input file with the recognized values"))
+                )
                 .arg(
                     Arg::new("input_file")
                         .required(false)
                         .help(gettext("Recognize a file instead of using mic input"))
                 )
         )
+        .subcommand(
+            Command::new("replay")
+                .about(gettext("Feed a capture session dumped by listen --dump-capture to the recognition again, as it was recorded, and print the recognized songs"))
+                .arg(
+                    Arg::new("diagnostics")
+                        .long("diagnostics")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the time taken by each stage of the recognition (capture, resampling, FFT, encoding, HTTP, parsing) to the standard error"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .conflicts_with("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in JSON"))
+                )
+                .arg(
+                    Arg::new("csv")
+                        .short('c')
+                        .long("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in the CSV format"))
+                )
+                .arg(
+                    Arg::new("session")
+                        .required(true)
+                        .help(gettext("The capture session file to replay"))
+                )
+        )
+        .subcommand(
+            Command::new("chapters")
+                .about(gettext("Export the chapters of a session recorded by listen --record-session for the podcast players, as a Podcasting 2.0 chapters file"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help(gettext("Write the chapters file there rather than to the standard output"))
+                )
+                .arg(
+                    Arg::new("mp3")
+                        .long("mp3")
+                        .value_name("FILE")
+                        .help(gettext("Also write the chapters as the ID3v2 chapter frames of this MP3 copy of the recording"))
+                )
+                .arg(
+                    Arg::new("recording")
+                        .required(true)
+                        .help(gettext("The Ogg file recorded with listen --record-session"))
+                )
+        )
+        .subcommand(
+            Command::new("index")
+                .about(gettext("Fingerprint the audio files of a directory into the local index, without contacting Shazam"))
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help(gettext("The directory to scan recursively"))
+                )
+        )
+        .subcommand(
+            Command::new("dedupe")
+                .about(gettext("Find audio files of a directory which contain the same recording, even encoded in different formats or bitrates"))
+                .arg(
+                    Arg::new("min-similarity")
+                        .long("min-similarity")
+                        .value_name("PERCENT")
+                        .default_value("30")
+                        .value_parser(clap::value_parser!(u8).range(1..=100))
+                        .help(gettext("Proportion of the fingerprint which has to match for two files to be considered duplicates"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the groups of duplicate files in JSON"))
+                )
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help(gettext("The directory to scan recursively"))
+                )
+        )
+        .subcommand(
+            Command::new("which-file")
+                .about(gettext("Find which files of the local index contain the audio of a short clip, and at which position"))
+                .arg(
+                    Arg::new("directory")
+                        .long("directory")
+                        .value_name("DIRECTORY")
+                        .help(gettext("Update the local index with the contents of this directory before searching"))
+                )
+                .arg(
+                    Arg::new("min-matches")
+                        .long("min-matches")
+                        .value_name("COUNT")
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(usize))
+                        .help(gettext("Minimal number of matching fingerprint landmarks for a file to be reported"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the matching files in JSON"))
+                )
+                .arg(
+                    Arg::new("clip")
+                        .required(true)
+                        .help(gettext("The audio clip to search for"))
+                )
+        )
+        .subcommand(
+            Command::new("scan")
+                .about(gettext("Recognize all the audio files of a directory with Shazam, sending several requests at the same time"))
+                .arg(
+                    Arg::new("concurrency")
+                        .long("concurrency")
+                        .value_name("COUNT")
+                        .default_value("4")
+                        .value_parser(clap::value_parser!(u8).range(1..=16))
+                        .help(gettext("Number of requests sent to Shazam at the same time, and of files fingerprinted in advance"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the result of each file as a line of JSON"))
+                )
+                .arg(
+                    Arg::new("restart")
+                        .long("restart")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Start over rather than resuming the interrupted scan of this directory"))
+                )
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help(gettext("The directory to scan recursively"))
+                )
+        )
+        .subcommand(
+            Command::new("history")
+                .about(gettext("Work with the history of recognized songs"))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about(gettext("Export the song history to CSV, TSV, XLSX, JSON, an HTML page, or an M3U, XSPF or iTunes XML playlist"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Export the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("source")
+                                .long("source")
+                                .value_name("KIND")
+                                .value_parser(["microphone", "monitor", "file"])
+                                .help(gettext("Only keep the songs recognized from this kind of source: \"microphone\", \"monitor\" (the speakers), or \"file\""))
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .value_parser(["csv", "tsv", "xlsx", "itunes", "json", "m3u", "xspf", "html", "gpx"])
+                                .help(gettext("Output format, guessed from the extension of the output file by default (XLSX requires the \"xlsx\" compile-time feature). \"itunes\" produces an iTunes library XML playlist, which can be imported into Apple Music, and \"gpx\" a map of the places where the songs were heard"))
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .value_name("FILE")
+                                .help(gettext("Write to this file rather than to the standard output"))
+                        )
+                        .arg(
+                            Arg::new("columns")
+                                .long("columns")
+                                .value_name("COLUMNS")
+                                .value_delimiter(',')
+                                .value_parser([
+                                    "song_name", "title", "artist", "album", "track_key", "release_year",
+                                    "genre", "recognition_date", "timestamp", "shazam_url", "spotify_uri",
+                                    "source", "bandcamp_url", "isrc", "upc"
+                                ])
+                                .help(gettext("Comma-separated list of the columns to export, in order (default: song_name,album,track_key,release_year,genre,recognition_date)"))
+                        )
+                        .arg(
+                            Arg::new("no-header")
+                                .long("no-header")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Don't write a header row"))
+                        )
+                )
+                .subcommand(
+                    Command::new("bundle")
+                        .about(gettext("Package the songs of a period, their cover art and an HTML page listing them into a ZIP archive"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Bundle the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("source")
+                                .long("source")
+                                .value_name("KIND")
+                                .value_parser(["microphone", "monitor", "file"])
+                                .help(gettext("Only keep the songs recognized from this kind of source: \"microphone\", \"monitor\" (the speakers), or \"file\""))
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .value_name("FILE")
+                                .required(true)
+                                .help(gettext("Path of the ZIP archive to create"))
+                        )
+                        .arg(
+                            Arg::new("range")
+                                .required(true)
+                                .help(gettext("The period to bundle: a day (\"2024-08-17\") or a range (\"2024-08-17 20:00..2024-08-18 04:00\"), either bound of which may be omitted"))
+                        )
+                )
+                .subcommand(
+                    Command::new("dedupe")
+                        .about(gettext("Remove the duplicate entries of the song history, such as a song recognized several times in a row, keeping a backup of the previous file. Close the SongRec window first"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Clean up the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("window")
+                                .long("window")
+                                .value_name("MINUTES")
+                                .default_value("10")
+                                .value_parser(clap::value_parser!(u32))
+                                .help(gettext("Entries of a song recognized again within this many minutes of its previous entry are duplicates"))
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Only list the entries that would be removed"))
+                        )
+                )
+                .subcommand(
+                    Command::new("stats")
+                        .about(gettext("List how many times each song of the history was heard, and when it was first and last heard, the most heard first"))
+                        .arg(
+                            Arg::new("limit")
+                                .long("limit")
+                                .value_name("COUNT")
+                                .value_parser(clap::value_parser!(usize))
+                                .help(gettext("Only list this many songs"))
+                        )
+                        .arg(
+                            Arg::new("json")
+                                .short('j')
+                                .long("json")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Print the statistics in JSON"))
+                        )
+                )
+                .subcommand(
+                    Command::new("prune")
+                        .about(gettext("Remove the oldest entries of the song history according to the retention limits of the preferences, which the GUI also enforces at startup. Close the SongRec window first"))
+                        .arg(
+                            Arg::new("max-entries")
+                                .long("max-entries")
+                                .value_name("COUNT")
+                                .value_parser(clap::value_parser!(u32))
+                                .help(gettext("Keep this many entries at most, rather than the number set in the preferences (0 for no limit)"))
+                        )
+                        .arg(
+                            Arg::new("max-months")
+                                .long("max-months")
+                                .value_name("MONTHS")
+                                .value_parser(clap::value_parser!(u32))
+                                .help(gettext("Keep the entries of this many months at most, rather than the number set in the preferences (0 for no limit)"))
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Only count the entries that would be removed"))
+                        )
+                )
+                .subcommand(
+                    Command::new("encrypt")
+                        .about(gettext("Encrypt the song history, favorites and deleted songs from now on, with a key stored in the keyring of the session (requires the \"encryption\" compile-time feature). Close the SongRec window first"))
+                )
+                .subcommand(
+                    Command::new("decrypt")
+                        .about(gettext("Store the song history, favorites and deleted songs unencrypted again. Close the SongRec window first"))
+                )
+                .subcommand(
+                    Command::new("check")
+                        .about(gettext("Look for the rows of the song history that SongRec can't read, such as invalid text, extra columns or unknown date formats"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Check the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("fix")
+                                .long("fix")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Repair the rows that can be, and move the others to a \".rejected\" file next to the history, keeping a backup of the previous file. Close the SongRec window first"))
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("spotify")
+                .about(gettext("Work with a Spotify playlist collecting the recognized songs"))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("diff")
+                        .about(gettext("Compare the song history with a Spotify playlist, listing the recognized tracks missing from it, the ones that couldn't be found on Spotify and the ones out of order, with the access token of the SPOTIFY_ACCESS_TOKEN environment variable, which needs the \"playlist-read-private\" and \"playlist-modify-private\" or \"playlist-modify-public\" scopes"))
+                        .arg(
+                            Arg::new("playlist")
+                                .long("playlist")
+                                .value_name("PLAYLIST")
+                                .required(true)
+                                .help(gettext("The playlist, as a link, a \"spotify:playlist:\" URI or an identifier"))
+                        )
+                        .arg(
+                            Arg::new("fix")
+                                .long("fix")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Add the missing tracks to the playlist and move the ones out of order"))
+                        )
+                        .arg(
+                            Arg::new("range")
+                                .help(gettext("Only compare the songs of a period: a day (\"2024-08-17\") or a range (\"2024-08-17 20:00..2024-08-18 04:00\"), either bound of which may be omitted"))
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("report")
+                .about(gettext("Generate a digest of the songs recognized for the first time during the last day, week or month, in Markdown or HTML"))
+                .arg(
+                    Arg::new("period")
+                        .long("period")
+                        .value_name("PERIOD")
+                        .value_parser(["day", "week", "month"])
+                        .default_value("week")
+                        .help(gettext("The period ending now to report on: \"day\", \"week\" or \"month\" (30 days)"))
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(["markdown", "html"])
+                        .default_value("markdown")
+                        .help(gettext("Output format"))
+                )
+                .arg(
+                    Arg::new("mail")
+                        .long("mail")
+                        .value_name("ADDRESS")
+                        .help(gettext("Precede the report with the headers of an email to this address, to pipe it to \"sendmail -t\""))
+                )
+                .arg(
+                    Arg::new("mail-from")
+                        .long("mail-from")
+                        .value_name("ADDRESS")
+                        .requires("mail")
+                        .help(gettext("The sender of the email, rather than the address it is sent to"))
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help(gettext("Write to this file rather than to the standard output"))
+                )
+        )
+        .subcommand(
+            Command::new("charts")
+                .about(gettext("Rank the songs and artists most heard during the last week or month, with their positions during the period before"))
+                .arg(
+                    Arg::new("period")
+                        .long("period")
+                        .value_name("PERIOD")
+                        .value_parser(["week", "month"])
+                        .default_value("week")
+                        .help(gettext("The period ending now to rank: \"week\" or \"month\" (30 days)"))
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(["markdown", "json"])
+                        .default_value("markdown")
+                        .help(gettext("Output format"))
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("COUNT")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(usize))
+                        .help(gettext("How many songs and artists to rank"))
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help(gettext("Write to this file rather than to the standard output"))
+                )
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about(gettext("Check the audio devices, the access to the recognition service, the Spotify access token of the SPOTIFY_ACCESS_TOKEN environment variable, the configuration files and the history, and tell how to fix the problems found"))
+        )
         .subcommand(
             Command::new("audio-file-to-recognized-song")
                 .about(gettext("Generate a Shazam fingerprint from a sound file, perform song recognition towards Shazam's servers and print obtained information to the standard output."))
+                .args(trim_args!())
                 .arg(
                     Arg::new("input_file")
                         .required(true)
@@ -136,8 +1252,9 @@ macro_rules! base_app {
                     Arg::new("audio-device")
                         .short('d')
                         .long("audio-device")
-                        .help(gettext("Specify the audio device to use"))
+                        .help(gettext("Specify the audio device to use, auto to pick the most promising one, snapcast:HOST[:PORT] to record a Snapcast stream, or file:PATH to play a sound file as if it was being recorded (file:PATH?speed=4 plays it four times faster)"))
                 )
+                .arg(echo_cancel_arg!())
                 .arg(
                     Arg::new("request-interval")
                         .short('i')
@@ -150,21 +1267,43 @@ macro_rules! base_app {
         .subcommand(
             Command::new("audio-file-to-fingerprint")
                 .about(gettext("Generate a Shazam fingerprint from a sound file, and print it to the standard output."))
+                .args(trim_args!())
                 .arg(
                     Arg::new("input_file")
                         .required(true)
                         .help(gettext("The .WAV or .MP3 file to generate an audio fingerprint for."))
                 )
         )
+        .subcommand(
+            Command::new("fingerprint-info")
+                .about(gettext("Decode a Shazam fingerprint and print its sample rate, duration, frequency peak statistics and a heatmap of its peaks."))
+                .arg(
+                    Arg::new("fingerprint")
+                        .required(true)
+                        .help(gettext("A data-URI Shazam fingerprint, or a file containing a fingerprint (as a data URI or in binary form), or an audio file to fingerprint."))
+                )
+        )
         .subcommand(
             Command::new("fingerprint-to-recognized-song")
                 .about(gettext("Take a data-URI Shazam fingerprint, perform song recognition towards Shazam's servers and print obtained information to the standard output."))
+                .args(trim_args!())
                 .arg(
                     Arg::new("fingerprint")
                         .required(true)
                         .help(gettext("The data-URI Shazam fingerprint to recognize."))
                 )
         )
+        .subcommand(
+            Command::new("schema")
+                .about(gettext("Print the JSON schema of the machine-readable output of a subcommand, e.g. \"songrec schema history export\", to validate it or generate code from it"))
+                .arg(
+                    Arg::new("subcommand")
+                        .required(true)
+                        .num_args(1..)
+                        .value_name("SUBCOMMAND")
+                        .help(gettext("The subcommand, among listen, recognize, replay, microphone-to-recognized-song, audio-file-to-recognized-song, fingerprint-to-recognized-song, dedupe, which-file, scan, charts, history export and history stats"))
+                )
+        )
     };
 }
 
@@ -178,7 +1317,7 @@ macro_rules! gui_app {
                 .arg(
                     Arg::new("input_file")
                         .required(false)
-                        .help(gettext("An optional audio file to recognize on the launch of the application."))
+                        .help(gettext("An optional audio file to recognize on the launch of the application, or a songrec://recognize?path=FILE URI. When SongRec is already running, the file is recognized by the running instance."))
                 )
                 .arg(
                     Arg::new("disable-mpris")
@@ -192,6 +1331,12 @@ macro_rules! gui_app {
                         .action(ArgAction::SetTrue)
                         .help(gettext("Disable PipeWire native support"))
                 )
+                .arg(
+                    Arg::new("kiosk")
+                        .long("kiosk")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Show the recognized songs fullscreen, with their cover art and a QR code linking to them"))
+                )
         )
         .subcommand(
             Command::new("gui-norecording")
@@ -199,7 +1344,7 @@ macro_rules! gui_app {
                 .arg(
                     Arg::new("input_file")
                         .required(false)
-                        .help(gettext("An optional audio file to recognize on the launch of the application."))
+                        .help(gettext("An optional audio file to recognize on the launch of the application, or a songrec://recognize?path=FILE URI. When SongRec is already running, the file is recognized by the running instance."))
                 )
                 .arg(
                     Arg::new("disable-mpris")
@@ -0,0 +1,35 @@
+# "Recognize with SongRec" in the context menu of the audio and video
+# files in Nautilus (requires the nautilus-python package). The files are
+# opened through the songrec:// URI scheme, so that the running SongRec
+# recognizes them.
+
+from urllib.parse import quote
+
+from gi.repository import GObject, Gio, Nautilus
+
+
+class SongRecMenuProvider(GObject.GObject, Nautilus.MenuProvider):
+    # Nautilus 43 and later no longer pass the window
+    def get_file_items(self, *args):
+        paths = [
+            file.get_location().get_path()
+            for file in args[-1]
+            if file.get_mime_type().startswith(('audio/', 'video/'))
+            and file.get_location().get_path()
+        ]
+        if not paths:
+            return []
+
+        item = Nautilus.MenuItem(
+            name='SongRec::Recognize',
+            label='Recognize with SongRec',
+            icon='re.fossplant.songrec',
+        )
+        item.connect('activate', self.recognize, paths)
+        return [item]
+
+    def recognize(self, _menu, paths):
+        for path in paths:
+            Gio.AppInfo.launch_default_for_uri(
+                'songrec://recognize?path=' + quote(path, safe=''), None
+            )
@@ -1,24 +1,53 @@
 use log::{error, info, warn};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-use chrono::Local;
 use gettextrs::gettext;
 
 #[cfg(all(target_os = "linux", feature = "mpris"))]
 use mpris_server::PlaybackStatus;
 
-use crate::core::http_task::http_task;
+use crate::core::audio_controllers::audio_backend::get_any_backend;
+use crate::core::audio_controllers::virtual_capture::is_virtual_device;
+use crate::core::http_task::{http_task, NetworkSettings};
+use crate::core::metrics;
+#[cfg(target_os = "linux")]
+use crate::core::microphone_thread::capture_idle_time;
 use crate::core::microphone_thread::microphone_thread;
+use crate::core::microphone_thread::{set_min_request_interval, set_split_channels};
 use crate::core::processing_thread::processing_thread;
+use crate::core::providers::ProviderSettings;
+use crate::core::session_recording;
+use crate::core::stream_monitor::{spawn_stream_monitors, StreamConfig};
 use crate::core::thread_messages::{
-    spawn_big_thread, GUIMessage, MicrophoneMessage, ProcessingMessage,
+    spawn_big_thread, GUIMessage, MicrophoneMessage, ProcessingMessage, RecognitionSource,
+    SongRecognizedMessage,
 };
 
 use crate::core::preferences::{Preferences, PreferencesInterface};
+use crate::plugins::chromecast::spawn_chromecast_watcher;
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+use crate::plugins::dbus_service;
 #[cfg(all(target_os = "linux", feature = "mpris"))]
 use crate::plugins::mpris_player::{get_player, update_song};
-use crate::utils::csv_song_history::SongHistoryRecord;
+use crate::plugins::prometheus_exporter::spawn_metrics_server;
+#[cfg(feature = "scripting")]
+use crate::plugins::scripting::{RecognitionScript, ScriptVerdict};
+use crate::plugins::sinks::{load_sinks_config, should_forward, SinkConfig, SinkRegistry};
+#[cfg(target_os = "linux")]
+use crate::plugins::systemd_notify;
+use crate::utils::clock;
+use crate::utils::csv_song_history::{CsvColumn, SongHistoryRecord, DEFAULT_CSV_COLUMNS};
+use crate::utils::qr_code;
+use crate::utils::share_text::render_message_share_text;
+use crate::utils::song_link::song_link;
+use crate::utils::tag_diff::{apply_tags, diff_tags, TagDiff};
+use crate::utils::transliteration;
 
 pub enum CLIOutputType {
     SongName,
@@ -33,7 +62,240 @@ pub struct CLIParameters {
     pub audio_device: Option<String>,
     pub request_interval: u64,
     pub input_file: Option<String>,
+    pub metrics_address: Option<String>,
+    /// Also report the songs played by the Cast devices of the network
+    pub watch_chromecast: bool,
+    /// Do not forward songs that the local Spotify client is playing
+    #[cfg(all(target_os = "linux", feature = "mpris"))]
+    pub ignore_own_spotify_playback: bool,
+    /// Where the recognized songs are forwarded to
+    pub sinks: Vec<SinkConfig>,
+    /// TOML file listing more sinks, reloaded when modified
+    pub sinks_config: Option<PathBuf>,
+    /// Radio streams recognized along with the audio device, or instead of
+    /// it when no device is given
+    pub streams: Vec<StreamConfig>,
     pub output_type: CLIOutputType,
+    /// Columns of the CSV output, in order
+    pub csv_columns: Vec<CsvColumn>,
+    pub csv_header: bool,
+    pub providers: ProviderSettings,
+    pub network: NetworkSettings,
+    /// Enable the low-data mode automatically on metered connections
+    pub detect_metered_connection: bool,
+    /// Compare the recognized song with the tags of the input file
+    pub compare_tags: bool,
+    /// Also rewrite the tags that do not match
+    pub apply_tags: bool,
+    /// Print a QR code linking to each song, for phone users nearby
+    pub qr_code: bool,
+    /// Print the songs with this template rather than "Artist - Title"
+    pub share_template: Option<String>,
+    /// Rhai script filtering and rewriting the recognized songs, reloaded
+    /// when modified
+    #[cfg(feature = "scripting")]
+    pub script: Option<PathBuf>,
+    /// Directory to which the raw captured audio is dumped, for "replay"
+    pub dump_capture: Option<PathBuf>,
+    /// Ogg file to which the whole session is recorded, with a chapter at
+    /// each song
+    pub record_session: Option<PathBuf>,
+    /// Print the time taken by each stage of the recognitions
+    pub diagnostics: bool,
+    /// Record from the echo-cancelled copy of the microphone, if any
+    pub echo_cancel: bool,
+    /// Recognize the left and right channels of the device separately
+    pub split_channels: bool,
+    /// Stop listening when receiving a message, e.g. from the Windows
+    /// service manager
+    pub stop_requests: Option<async_channel::Receiver<()>>,
+}
+
+impl Default for CLIParameters {
+    fn default() -> Self {
+        CLIParameters {
+            enable_mpris: false,
+            list_devices: false,
+            recognize_once: false,
+            audio_device: None,
+            request_interval: 10,
+            input_file: None,
+            metrics_address: None,
+            watch_chromecast: false,
+            #[cfg(all(target_os = "linux", feature = "mpris"))]
+            ignore_own_spotify_playback: false,
+            sinks: vec![],
+            sinks_config: None,
+            streams: vec![],
+            output_type: CLIOutputType::SongName,
+            csv_columns: DEFAULT_CSV_COLUMNS.to_vec(),
+            csv_header: true,
+            providers: ProviderSettings::default(),
+            network: NetworkSettings::default(),
+            detect_metered_connection: true,
+            compare_tags: false,
+            apply_tags: false,
+            qr_code: false,
+            share_template: None,
+            #[cfg(feature = "scripting")]
+            script: None,
+            dump_capture: None,
+            record_session: None,
+            diagnostics: false,
+            echo_cancel: false,
+            split_channels: false,
+            stop_requests: None,
+        }
+    }
+}
+
+fn print_tag_diff(file_name: &str, message: &SongRecognizedMessage, apply: bool, json: bool) {
+    let differences = match diff_tags(file_name, message) {
+        Ok(differences) => differences,
+        Err(error) => {
+            error!(
+                "{} {}",
+                gettext("Could not read the tags of the input file:"),
+                error
+            );
+            return;
+        }
+    };
+
+    let mut applied = false;
+    if apply && !differences.is_empty() {
+        match apply_tags(file_name, &differences) {
+            Ok(()) => applied = true,
+            Err(error) => error!(
+                "{} {}",
+                gettext("Could not write the tags of the input file:"),
+                error
+            ),
+        }
+    }
+
+    let diff = TagDiff {
+        file: file_name.to_string(),
+        track_key: message.track_key.clone(),
+        differences,
+        applied,
+    };
+    if json {
+        println!("{}", serde_json::to_string(&diff).unwrap());
+    } else {
+        println!("{}", diff);
+    }
+}
+
+// Number of times we try to reopen a stalled audio capture before letting
+// the systemd watchdog restart the whole service
+#[cfg(target_os = "linux")]
+const MAX_CAPTURE_RESTARTS: u32 = 3;
+
+// How often the configuration files are checked for modifications, in seconds
+const CONFIGURATION_POLL_INTERVAL: u32 = 2;
+
+fn print_diagnostics() {
+    eprintln!("{} {}", gettext("Latency:"), metrics::stage_report());
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Ask for a configuration reload on SIGHUP, or when one of the given files
+/// is created, modified or removed. Only the sinks and the script are
+/// reloaded: the streams of "--streams-config" each run their own pipeline,
+/// which would have to be restarted, and the aliases are parsed again at the
+/// first recognition after aliases.toml changed anyway.
+fn setup_configuration_reload(
+    watched_files: Vec<PathBuf>,
+    gui_tx: async_channel::Sender<GUIMessage>,
+) {
+    #[cfg(unix)]
+    {
+        let gui_tx = gui_tx.clone();
+        glib::source::unix_signal_add_local(libc::SIGHUP, move || {
+            if gui_tx.try_send(GUIMessage::ReloadConfiguration).is_err() {
+                return glib::ControlFlow::Break;
+            }
+            glib::ControlFlow::Continue
+        });
+    }
+
+    if watched_files.is_empty() {
+        return;
+    }
+    let mut modification_times: Vec<Option<SystemTime>> = watched_files
+        .iter()
+        .map(|path| modification_time(path))
+        .collect();
+
+    glib::timeout_add_seconds_local(CONFIGURATION_POLL_INTERVAL, move || {
+        let current_times: Vec<Option<SystemTime>> = watched_files
+            .iter()
+            .map(|path| modification_time(path))
+            .collect();
+        if current_times != modification_times {
+            modification_times = current_times;
+            if gui_tx.try_send(GUIMessage::ReloadConfiguration).is_err() {
+                return glib::ControlFlow::Break;
+            }
+        }
+        glib::ControlFlow::Continue
+    });
+}
+
+#[cfg(target_os = "linux")]
+fn setup_systemd_watchdog(
+    interval: Duration,
+    microphone_tx: async_channel::Sender<MicrophoneMessage>,
+    current_device: Rc<RefCell<Option<String>>>,
+) {
+    // A capture stream that did not deliver audio for this long is
+    // considered stalled
+    let stall_threshold = interval.max(Duration::from_secs(5));
+    let mut restart_attempts: u32 = 0;
+
+    glib::timeout_add_local(interval / 2, move || {
+        if capture_idle_time() < stall_threshold {
+            if restart_attempts > 0 {
+                info!("{}", gettext("Audio capture recovered"));
+                systemd_notify::notify("STATUS=Listening");
+                restart_attempts = 0;
+            }
+            systemd_notify::notify("WATCHDOG=1");
+        } else if restart_attempts < MAX_CAPTURE_RESTARTS {
+            restart_attempts += 1;
+            let status = gettext("Audio capture stalled, restarting it (attempt %d)")
+                .replace("%d", &restart_attempts.to_string());
+            warn!("{}", status);
+            systemd_notify::notify(&format!("STATUS={}", status));
+
+            // Keep the service alive while we try to recover
+            systemd_notify::notify("WATCHDOG=1");
+
+            if let Some(device_name) = current_device.borrow().clone() {
+                microphone_tx
+                    .try_send(MicrophoneMessage::MicrophoneRecordStop)
+                    .unwrap();
+                microphone_tx
+                    .try_send(MicrophoneMessage::MicrophoneRecordStart(device_name))
+                    .unwrap();
+            }
+        } else {
+            // Stop sending keepalives, so that systemd restarts the service
+            error!(
+                "{}",
+                gettext("Audio capture is still stalled, giving up on the watchdog")
+            );
+            systemd_notify::notify("STATUS=Audio capture stalled");
+            return glib::ControlFlow::Break;
+        }
+        glib::ControlFlow::Continue
+    });
 }
 
 pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
@@ -44,17 +306,88 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
 
     let gui_tx_2 = gui_tx.clone();
     let gui_tx_3 = gui_tx.clone();
+    let gui_tx_4 = gui_tx.clone();
+    let gui_tx_6 = gui_tx.clone();
+    #[cfg(all(target_os = "linux", feature = "dbus"))]
+    let gui_tx_5 = gui_tx.clone();
     let processing_tx_2 = processing_tx.clone();
     let microphone_tx_2 = microphone_tx.clone();
     let microphone_tx_3 = microphone_tx.clone();
 
+    if let Some(stop_requests) = parameters.stop_requests.clone() {
+        let gui_tx = gui_tx.clone();
+        glib::spawn_future_local(async move {
+            if stop_requests.recv().await.is_ok() {
+                gui_tx.try_send(GUIMessage::QuitApplication).ok();
+            }
+        });
+    }
+
+    if let Some(ref metrics_address) = parameters.metrics_address {
+        spawn_metrics_server(metrics_address)?;
+    }
+
+    // Report configuration errors before we start listening
+    let file_sinks = match parameters.sinks_config {
+        Some(ref path) => load_sinks_config(path)?,
+        None => vec![],
+    };
+    #[cfg(feature = "scripting")]
+    let mut script = match parameters.script {
+        Some(ref path) => Some(RecognitionScript::load(path)?),
+        None => None,
+    };
+
+    let mut network_settings = parameters.network.clone();
+    // Check the network interface now rather than on the first request
+    network_settings.local_address()?;
+    if parameters.detect_metered_connection {
+        network_settings.detect_metered_connection();
+    }
+    set_min_request_interval(network_settings.min_request_interval());
+
+    let mut preferences = Preferences::with_interval(parameters.request_interval);
+    preferences.prefer_echo_cancelled_source = Some(parameters.echo_cancel);
     let preferences_interface = Arc::new(Mutex::new(PreferencesInterface {
         preferences_file_path: None,
-        preferences: Preferences::with_interval(parameters.request_interval),
+        preferences,
     }));
 
+    if !parameters.streams.is_empty() {
+        spawn_stream_monitors(
+            &parameters.streams,
+            network_settings.new_session()?,
+            processing_tx.clone(),
+            gui_tx.clone(),
+            preferences_interface.clone(),
+        );
+    }
+
+    if let Some(ref directory) = parameters.dump_capture {
+        microphone_tx
+            .try_send(MicrophoneMessage::DumpCapture(directory.clone()))
+            .unwrap();
+    }
+
+    if let Some(ref path) = parameters.record_session {
+        session_recording::start(path)?;
+
+        // Stop cleanly on Ctrl+C, so that the recording is encoded
+        #[cfg(unix)]
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            let gui_tx = gui_tx.clone();
+            glib::source::unix_signal_add_local(signal, move || {
+                gui_tx.try_send(GUIMessage::QuitApplication).ok();
+                glib::ControlFlow::Break
+            });
+        }
+    }
+
+    set_split_channels(parameters.split_channels);
+
     spawn_big_thread(move || {
         microphone_thread(
+            get_any_backend,
             microphone_rx,
             microphone_tx_2,
             processing_tx_2,
@@ -68,7 +401,13 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
         processing_thread(processing_rx, http_tx, gui_tx_3);
     });
 
-    glib::spawn_future_local(http_task(http_rx, gui_tx, microphone_tx_3));
+    glib::spawn_future_local(http_task(
+        http_rx,
+        gui_tx,
+        microphone_tx_3,
+        parameters.providers.clone(),
+        network_settings,
+    ));
 
     let main_loop = glib::MainLoop::new(None, false);
     let loop_inner = main_loop.clone();
@@ -92,7 +431,44 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
         #[cfg(all(target_os = "linux", feature = "mpris"))]
         let mut last_cover_path = None;
 
-        let mut last_track: Option<String> = None;
+        let mut sinks = SinkRegistry::from_configs(
+            parameters.sinks.iter().cloned().chain(file_sinks).collect(),
+        );
+        let mut stream_sinks: HashMap<String, SinkRegistry> = parameters
+            .streams
+            .iter()
+            .filter(|stream| !stream.sink.is_empty())
+            .map(|stream| {
+                (
+                    stream.tag.clone(),
+                    SinkRegistry::from_configs(stream.sink.clone()),
+                )
+            })
+            .collect();
+
+        if !do_recognize_once {
+            let mut watched_files: Vec<PathBuf> = parameters.sinks_config.iter().cloned().collect();
+            #[cfg(feature = "scripting")]
+            watched_files.extend(parameters.script.iter().cloned());
+            setup_configuration_reload(watched_files, gui_tx_4);
+
+            #[cfg(all(target_os = "linux", feature = "dbus"))]
+            dbus_service::serve(gui_tx_5);
+
+            if parameters.watch_chromecast {
+                spawn_chromecast_watcher(gui_tx_6);
+            }
+        }
+
+        // The last song of each stream, and of the audio device
+        let mut last_tracks: HashMap<Option<String>, String> = HashMap::new();
+        let mut network_requires_login = false;
+
+        // The device that we are currently recording from, and whether we
+        // already told systemd that we are ready
+        let current_device: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+        #[cfg(target_os = "linux")]
+        let mut systemd_ready = false;
 
         let audio_dev_name = parameters.audio_device.as_ref().map(|dev| dev.to_string());
         let input_file_name = parameters.input_file.as_ref().map(|dev| dev.to_string());
@@ -104,12 +480,18 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
         }
 
         let mut csv_writer = csv::Writer::from_writer(std::io::stdout());
+        let mut csv_header_written = !parameters.csv_header;
 
         while let Ok(gui_message) = gui_rx.recv().await {
             match gui_message {
                 GUIMessage::DevicesList(device_names) => {
-                    // no need to start a microphone if recognizing from file
-                    if input_file_name.is_some() {
+                    // no need to start a microphone if recognizing from file,
+                    // or if only monitoring streams
+                    if input_file_name.is_some()
+                        || (audio_dev_name.is_none()
+                            && !parameters.streams.is_empty()
+                            && !parameters.list_devices)
+                    {
                         continue;
                     }
                     for device in device_names.iter() {
@@ -132,7 +514,7 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
                                 break;
                             }
                         }
-                        if !found {
+                        if !found && !is_virtual_device(dev) {
                             error!("{}", gettext("Exiting: audio device not found"));
                             break;
                         }
@@ -145,6 +527,7 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
                         &device_names[0].inner_name
                     };
                     info!("{}", gettext("Using device %s").replace("%s", dev_name));
+                    *current_device.borrow_mut() = Some(dev_name.to_owned());
                     microphone_tx
                         .try_send(MicrophoneMessage::MicrophoneRecordStart(
                             dev_name.to_owned(),
@@ -166,6 +549,11 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
                         }
                     }
 
+                    sinks.set_listening(reachable);
+                    for registry in stream_sinks.values_mut() {
+                        registry.set_listening(reachable);
+                    }
+
                     if !reachable {
                         if input_file_name.is_some() {
                             error!("{}", gettext("Error: Network unreachable"));
@@ -175,7 +563,29 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
                         }
                     }
                 }
+                GUIMessage::NetworkRequiresLogin(requires_login) => {
+                    sinks.set_listening(!requires_login);
+                    for registry in stream_sinks.values_mut() {
+                        registry.set_listening(!requires_login);
+                    }
+
+                    if requires_login {
+                        let message = gettext(
+                            "The network requires a login, please sign in from a web browser",
+                        );
+                        if input_file_name.is_some() {
+                            error!("{} {}", gettext("Error:"), message);
+                            break;
+                        } else if !network_requires_login {
+                            warn!("{} {}", gettext("Warning:"), message);
+                        }
+                    }
+                    network_requires_login = requires_login;
+                }
                 GUIMessage::ErrorMessage(string) => {
+                    if parameters.diagnostics && string == gettext("No match for this song") {
+                        print_diagnostics();
+                    }
                     if string != gettext("No match for this song") || input_file_name.is_some() {
                         error!("{} {}", gettext("Error:"), string);
                     }
@@ -185,26 +595,174 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
                 }
                 GUIMessage::MicrophoneRecording if !do_recognize_once => {
                     info!("{}", gettext("Recording started!"));
+
+                    sinks.set_listening(true);
+                    for registry in stream_sinks.values_mut() {
+                        registry.set_listening(true);
+                    }
+
+                    #[cfg(target_os = "linux")]
+                    if !systemd_ready {
+                        systemd_ready = true;
+                        systemd_notify::notify("READY=1\nSTATUS=Listening");
+                        if let Some(interval) = systemd_notify::watchdog_interval() {
+                            setup_systemd_watchdog(
+                                interval,
+                                microphone_tx.clone(),
+                                current_device.clone(),
+                            );
+                        }
+                    }
                 }
-                GUIMessage::SongRecognized(message) => {
-                    let track_key = Some(message.track_key.clone());
-                    let song_name = format!("{} - {}", message.artist_name, message.song_name);
+                GUIMessage::ReloadConfiguration => {
+                    info!("{}", gettext("Reloading the configuration"));
+
+                    if let Some(ref path) = parameters.sinks_config {
+                        match load_sinks_config(path) {
+                            Ok(file_sinks) => sinks.replace(
+                                parameters.sinks.iter().cloned().chain(file_sinks).collect(),
+                            ),
+                            Err(error) => {
+                                error!("{} {}", gettext("Could not reload the sinks:"), error)
+                            }
+                        }
+                    }
+
+                    #[cfg(feature = "scripting")]
+                    if let Some(ref path) = parameters.script {
+                        match RecognitionScript::load(path) {
+                            Ok(new_script) => script = Some(new_script),
+                            Err(error) => {
+                                error!("{} {}", gettext("Could not reload the script:"), error)
+                            }
+                        }
+                    }
+                }
+                GUIMessage::MuteSink(name, seconds, reply_tx) => {
+                    let result = sinks.mute(&name, Duration::from_secs(seconds.into()));
+                    if let Err(ref error) = result {
+                        warn!("{} {}", gettext("Cannot mute the sink:"), error);
+                    }
+                    reply_tx.try_send(result).ok();
+                }
+                GUIMessage::QuitApplication => {
+                    info!("{}", gettext("Stopping"));
+                    break;
+                }
+                GUIMessage::CaptureEnded => {
+                    info!("{}", gettext("The end of the recorded audio was reached"));
+                    break;
+                }
+                GUIMessage::SongRecognized(mut message) => {
+                    if parameters.diagnostics {
+                        print_diagnostics();
+                    }
+
+                    // The songs reported by the metadata of a stream and the
+                    // ones recognized from its audio are logged separately, as
+                    // are the channels of a device recognized separately
+                    let (stream_tag, last_track_key) = match message.source {
+                        Some(
+                            ref source @ (RecognitionSource::Stream(ref tag)
+                            | RecognitionSource::StreamMetadata(ref tag)),
+                        ) => (Some(tag.clone()), Some(source.to_string())),
+                        Some(ref source) if source.channel().is_some() => {
+                            (None, Some(source.to_string()))
+                        }
+                        _ => (None, None),
+                    };
+                    let channel = message.source.as_ref().and_then(RecognitionSource::channel);
+
+                    if last_tracks.get(&last_track_key) != Some(&message.track_key) {
+                        #[cfg(feature = "scripting")]
+                        let ScriptVerdict { output, forward } = match script {
+                            Some(ref script) => {
+                                script.on_song(&mut message).unwrap_or_else(|error| {
+                                    error!("Could not run the script: {}", error);
+                                    ScriptVerdict::default()
+                                })
+                            }
+                            None => ScriptVerdict::default(),
+                        };
+                        #[cfg(not(feature = "scripting"))]
+                        let (output, forward) = (true, true);
+
+                        // The sinks searching catalogs get the names that
+                        // were recognized, the output their transliteration
+                        let recognized = message.clone();
+                        transliteration::apply(&mut message);
+
+                        let song_name = format!("{} - {}", message.artist_name, message.song_name);
+
+                        if matches!(
+                            message.source,
+                            Some(RecognitionSource::Microphone(_) | RecognitionSource::Monitor(_))
+                        ) {
+                            session_recording::add_chapter(&song_name);
+                        }
 
-                    if last_track != track_key {
                         // TODO re-implement this with new lib
                         #[cfg(all(target_os = "linux", feature = "mpris"))]
                         if let Some(ref player) = mpris_obj {
                             update_song(player, &message, &mut last_cover_path).await;
                         }
 
-                        last_track = track_key;
-                        match parameters.output_type {
-                            CLIOutputType::JSON => {
-                                println!("{}", message.shazam_json);
+                        if should_forward(&recognized, parameters.ignore_own_spotify_playback).await
+                            && forward
+                        {
+                            sinks.song_recognized(&recognized).await;
+                            if let Some(registry) = stream_tag
+                                .as_ref()
+                                .and_then(|tag| stream_sinks.get_mut(tag))
+                            {
+                                registry.song_recognized(&recognized).await;
+                            }
+                        }
+
+                        last_tracks.insert(last_track_key, message.track_key.clone());
+
+                        // On the standard error so that the output stays parseable
+                        if parameters.qr_code {
+                            if let Some(link) = song_link(&message.track_key, &message.shazam_json)
+                            {
+                                match qr_code::render_terminal(&link) {
+                                    Ok(qr_code) => eprintln!("{}\n{}", qr_code, link),
+                                    Err(error) => error!("Could not render a QR code: {}", error),
+                                }
                             }
-                            CLIOutputType::CSV => {
-                                csv_writer
-                                    .serialize(SongHistoryRecord {
+                        }
+
+                        if parameters.compare_tags {
+                            if let Some(ref file_name) = input_file_name {
+                                print_tag_diff(
+                                    file_name,
+                                    &message,
+                                    parameters.apply_tags,
+                                    matches!(parameters.output_type, CLIOutputType::JSON),
+                                );
+                            }
+                            break;
+                        }
+
+                        if output {
+                            match parameters.output_type {
+                                CLIOutputType::JSON => {
+                                    // With the corrections and aliases applied
+                                    // by http_task, and the transliteration
+                                    println!("{}", message.displayed_shazam_json());
+                                }
+                                CLIOutputType::CSV => {
+                                    if !csv_header_written {
+                                        csv_writer
+                                            .write_record(
+                                                parameters.csv_columns.iter().map(CsvColumn::name),
+                                            )
+                                            .unwrap();
+                                        csv_header_written = true;
+                                    }
+                                    let shazam_json =
+                                        serde_json::from_str(&message.shazam_json).ok();
+                                    let record = SongHistoryRecord {
                                         song_name,
                                         album: Some(message.album_name.unwrap_or_default()),
                                         track_key: Some(message.track_key),
@@ -212,15 +770,30 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
                                             message.release_year.unwrap_or_default(),
                                         ),
                                         genre: Some(message.genre.unwrap_or_default()),
-                                        recognition_date: Local::now().format("%c").to_string(),
-                                    })
-                                    .unwrap();
-                                csv_writer.flush().unwrap();
-                            }
-                            CLIOutputType::SongName => {
-                                println!("{}", song_name);
-                            }
-                        };
+                                        recognition_date: clock::history_date_now(),
+                                        source: message.source.map(|source| source.to_string()),
+                                    };
+                                    csv_writer
+                                        .write_record(parameters.csv_columns.iter().map(|column| {
+                                            column.value(&record, shazam_json.as_ref())
+                                        }))
+                                        .unwrap();
+                                    csv_writer.flush().unwrap();
+                                }
+                                CLIOutputType::SongName => match parameters.share_template {
+                                    Some(ref template) => {
+                                        println!(
+                                            "{}",
+                                            render_message_share_text(template, &message)
+                                        );
+                                    }
+                                    None => match channel {
+                                        Some(channel) => println!("[{}] {}", channel, song_name),
+                                        None => println!("{}", song_name),
+                                    },
+                                },
+                            };
+                        }
                     }
                     if do_recognize_once {
                         break;
@@ -230,6 +803,15 @@ pub fn cli_main(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
             }
         }
 
+        sinks.close();
+        for registry in stream_sinks.values_mut() {
+            registry.close();
+        }
+        session_recording::finish();
+
+        #[cfg(target_os = "linux")]
+        systemd_notify::notify("STOPPING=1");
+
         gui_rx.close();
         loop_inner.quit();
     });
@@ -0,0 +1,151 @@
+//! Aliases of artists and titles, so that the songs which Shazam or the
+//! radio streams spell in several ways ("Tchaikovsky, Pyotr Ilyich", "P. I.
+//! Tchaikovsky"...) are counted together. They are written by hand in the
+//! "aliases.toml" file of the preferences directory:
+//!
+//! ```toml
+//! [artists]
+//! "Tchaikovsky, Pyotr Ilyich" = "Pyotr Ilyich Tchaikovsky"
+//!
+//! [titles]
+//! "Nutcracker Suite, Op. 71a" = "The Nutcracker Suite"
+//! ```
+//!
+//! Names are compared regardless of their case. The aliases apply to the
+//! recognized songs before they are stored and forwarded to the sinks, and
+//! to the older entries of the history when it is exported or reported on.
+
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::utils::csv_song_history::SongHistoryRecord;
+use crate::utils::file_cache::FileCache;
+use crate::utils::filesystem_operations::obtain_aliases_path;
+
+static ALIASES: FileCache<Aliases> = FileCache::new();
+
+/// The names are keyed by their trimmed lowercase form once parsed
+#[derive(Debug, Default, Deserialize)]
+pub struct Aliases {
+    #[serde(default)]
+    artists: HashMap<String, String>,
+    #[serde(default)]
+    titles: HashMap<String, String>,
+}
+
+fn lookup_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+fn resolve(aliases: &HashMap<String, String>, name: &str) -> Option<String> {
+    aliases.get(&lookup_key(name)).cloned()
+}
+
+impl Aliases {
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        let aliases: Aliases = toml::from_str(contents)?;
+        let by_lookup_key = |aliases: HashMap<String, String>| {
+            aliases
+                .into_iter()
+                .map(|(alias, canonical)| (lookup_key(&alias), canonical))
+                .collect()
+        };
+        Ok(Aliases {
+            artists: by_lookup_key(aliases.artists),
+            titles: by_lookup_key(aliases.titles),
+        })
+    }
+
+    /// Parsed again when the file changes, so that edits apply without
+    /// restarting
+    pub fn load() -> Arc<Self> {
+        let Ok(path) = obtain_aliases_path() else {
+            return Arc::default();
+        };
+        ALIASES.get(&path, |path| {
+            let Ok(contents) = std::fs::read_to_string(path) else {
+                return Aliases::default();
+            };
+            Aliases::parse(&contents).unwrap_or_else(|error| {
+                warn!(
+                    "Ignoring the invalid aliases of {}: {}",
+                    path.display(),
+                    error
+                );
+                Aliases::default()
+            })
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty() && self.titles.is_empty()
+    }
+
+    pub fn artist(&self, artist: &str) -> String {
+        resolve(&self.artists, artist).unwrap_or_else(|| artist.to_string())
+    }
+
+    pub fn title(&self, title: &str) -> String {
+        resolve(&self.titles, title).unwrap_or_else(|| title.to_string())
+    }
+
+    fn apply_to_message(&self, message: &mut SongRecognizedMessage) {
+        message.artist_name = self.artist(&message.artist_name);
+        message.song_name = self.title(&message.song_name);
+    }
+
+    /// Entries of the history store "Artist - Title"
+    pub fn song_name(&self, song_name: &str) -> String {
+        match song_name.split_once(" - ") {
+            Some((artist, title)) => format!("{} - {}", self.artist(artist), self.title(title)),
+            None => self.title(song_name),
+        }
+    }
+
+    pub fn apply_to_record(&self, record: &mut SongHistoryRecord) {
+        record.song_name = self.song_name(&record.song_name);
+    }
+}
+
+/// Replace the names of a recognized song by their aliases
+pub fn apply(message: &mut SongRecognizedMessage) {
+    Aliases::load().apply_to_message(message);
+}
+
+/// Replace the names of history entries by their aliases
+pub fn apply_to_records(records: &mut [SongHistoryRecord]) {
+    let aliases = Aliases::load();
+    if aliases.is_empty() {
+        return;
+    }
+    for record in records {
+        aliases.apply_to_record(record);
+    }
+}
+
+#[test]
+fn test_aliases() {
+    let aliases = Aliases::parse(
+        "[artists]\n\
+        \"Tchaikovsky, Pyotr Ilyich\" = \"Pyotr Ilyich Tchaikovsky\"\n\
+        [titles]\n\
+        \"Nutcracker Suite, Op. 71a\" = \"The Nutcracker Suite\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        aliases.artist("tchaikovsky, pyotr ilyich "),
+        "Pyotr Ilyich Tchaikovsky"
+    );
+    assert_eq!(aliases.artist("Daft Punk"), "Daft Punk");
+
+    let mut record =
+        SongHistoryRecord::for_test("Tchaikovsky, Pyotr Ilyich - Nutcracker Suite, Op. 71a", "");
+    aliases.apply_to_record(&mut record);
+    assert_eq!(
+        record.song_name,
+        "Pyotr Ilyich Tchaikovsky - The Nutcracker Suite"
+    );
+}
@@ -1,10 +1,26 @@
 use cpal::platform::{Device, Host};
+use rodio::Source;
+use std::error::Error;
 
 use crate::core::audio_controllers::cpal::CpalBackend;
 #[cfg(all(target_os = "linux", feature = "pulse"))]
 use crate::core::audio_controllers::pulseaudio::PulseBackend;
+use crate::core::audio_controllers::virtual_capture::FileDevice;
+use crate::core::fingerprinting::algorithm::SignatureGenerator;
 
-use crate::core::thread_messages::DeviceListItem;
+use crate::core::thread_messages::{DeviceListItem, RecognitionSource};
+
+/// A device which isn't a sound card, whose samples are delivered by a
+/// `VirtualCapture`
+pub struct VirtualDevice {
+    /// Interleaved
+    pub samples: Box<dyn Iterator<Item = f32> + Send>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// 1.0 for real time
+    pub speed: f32,
+    pub source: RecognitionSource,
+}
 
 pub fn get_any_backend() -> Box<dyn AudioBackend> {
     #[cfg(not(all(target_os = "linux", feature = "pulse")))]
@@ -22,4 +38,30 @@ pub trait AudioBackend {
     fn list_devices(&mut self, host: &Host) -> Vec<DeviceListItem>;
 
     fn set_device(&mut self, host: &Host, inner_name: &str) -> Device;
+
+    /// Open the "file:" devices, or the other devices of the backend which
+    /// aren't sound cards; None for the devices opened with `set_device`
+    fn open_virtual_device(
+        &mut self,
+        device_name: &str,
+    ) -> Option<Result<VirtualDevice, Box<dyn Error>>> {
+        let file_device = FileDevice::parse(device_name)?;
+        Some(
+            SignatureGenerator::open_audio_file(&file_device.path).map(|decoder| VirtualDevice {
+                sample_rate: decoder.sample_rate().get(),
+                channels: decoder.channels().get(),
+                samples: Box::new(decoder),
+                speed: file_device.speed,
+                source: RecognitionSource::File(file_device.path),
+            }),
+        )
+    }
+
+    /// The monitor of the output that the system currently plays to
+    fn default_output_monitor(&mut self, host: &Host) -> Option<String> {
+        self.list_devices(host)
+            .into_iter()
+            .find(|device| device.is_monitor)
+            .map(|device| device.inner_name)
+    }
 }
@@ -0,0 +1,209 @@
+//! The "auto" audio device records from the most promising input, rather
+//! than from the default one: a device which recently carried some signal,
+//! then a monitor (a lossless copy of what this computer plays), then the
+//! default microphone. The choice is made again when the capture restarts
+//! or when devices appear or disappear.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use gettextrs::gettext;
+
+use crate::core::thread_messages::DeviceListItem;
+
+/// Name of the pseudo audio device
+pub const AUTO_DEVICE_NAME: &str = "auto";
+
+/// Peak amplitude above which a device is considered to carry signal
+const SIGNAL_THRESHOLD: f32 = 0.01;
+
+/// How long a device which carried signal stays promising
+const RECENT_SIGNAL: Duration = Duration::from_secs(600);
+
+/// How long a device must stay silent while being recorded to be
+/// considered silent
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SignalHistory {
+    last_signal: Option<Instant>,
+    silent_since: Option<Instant>,
+}
+
+/// The signal history of a device, updated from the capture callback
+/// without locking nor allocating: the instants are stored as milliseconds
+/// since `EPOCH`, plus one, zero meaning none
+#[derive(Default)]
+pub struct DeviceLevel {
+    last_signal: AtomicU64,
+    silent_since: AtomicU64,
+}
+
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+fn to_millis(instant: Instant) -> u64 {
+    instant.duration_since(*EPOCH).as_millis() as u64 + 1
+}
+
+fn from_millis(millis: u64) -> Option<Instant> {
+    (millis > 0).then(|| *EPOCH + Duration::from_millis(millis - 1))
+}
+
+impl DeviceLevel {
+    fn history(&self) -> SignalHistory {
+        SignalHistory {
+            last_signal: from_millis(self.last_signal.load(Ordering::Relaxed)),
+            silent_since: from_millis(self.silent_since.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+static DEVICE_LEVELS: LazyLock<Mutex<HashMap<String, Arc<DeviceLevel>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The level to update while recording from a device, obtained when its
+/// stream is set up rather than from the capture callback
+pub fn device_level(device_name: &str) -> Arc<DeviceLevel> {
+    DEVICE_LEVELS
+        .lock()
+        .unwrap()
+        .entry(device_name.to_string())
+        .or_default()
+        .clone()
+}
+
+/// The entry of the pseudo device in the lists of devices
+pub fn auto_device_item() -> DeviceListItem {
+    DeviceListItem {
+        inner_name: AUTO_DEVICE_NAME.to_string(),
+        display_name: gettext("Automatic (most promising input)"),
+        is_monitor: false,
+        is_hands_free: false,
+        sample_rate: None,
+    }
+}
+
+/// Remember the peak amplitude just measured on a device
+pub fn record_level(level: &DeviceLevel, peak_amplitude: f32) {
+    let now = to_millis(Instant::now());
+    if peak_amplitude >= SIGNAL_THRESHOLD {
+        level.last_signal.store(now, Ordering::Relaxed);
+        level.silent_since.store(0, Ordering::Relaxed);
+    } else {
+        level
+            .silent_since
+            .compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed)
+            .ok();
+    }
+}
+
+fn score_device(
+    device: &DeviceListItem,
+    is_default: bool,
+    history: Option<&SignalHistory>,
+    now: Instant,
+) -> i32 {
+    let mut score = 0;
+
+    if let Some(history) = history {
+        if history
+            .last_signal
+            .is_some_and(|last_signal| now.duration_since(last_signal) < RECENT_SIGNAL)
+        {
+            score += 100;
+        }
+        if history
+            .silent_since
+            .is_some_and(|silent_since| now.duration_since(silent_since) >= SILENCE_TIMEOUT)
+        {
+            score -= 40;
+        }
+    }
+    if device.is_monitor {
+        score += 20;
+    }
+    if is_default {
+        score += 10;
+    }
+    match device.sample_rate {
+        Some(sample_rate) if sample_rate >= 44100 => score += 5,
+        Some(sample_rate) if sample_rate <= 16000 => score -= 20,
+        _ => {}
+    }
+    if device.is_hands_free {
+        score -= 50;
+    }
+    score
+}
+
+fn pick_device_at<'a>(
+    devices: &'a [DeviceListItem],
+    histories: &HashMap<String, SignalHistory>,
+    now: Instant,
+) -> Option<&'a DeviceListItem> {
+    devices
+        .iter()
+        .enumerate()
+        .filter(|(_, device)| device.inner_name != AUTO_DEVICE_NAME)
+        // The first highest score wins
+        .rev()
+        .max_by_key(|(index, device)| {
+            score_device(device, *index == 0, histories.get(&device.inner_name), now)
+        })
+        .map(|(_, device)| device)
+}
+
+/// The device to record from, among the ones listed by the audio backend
+/// (the default one first)
+pub fn pick_device(devices: &[DeviceListItem]) -> Option<&DeviceListItem> {
+    let histories = DEVICE_LEVELS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(device_name, level)| (device_name.clone(), level.history()))
+        .collect();
+    pick_device_at(devices, &histories, Instant::now())
+}
+
+#[test]
+fn test_pick_device() {
+    let device = |inner_name: &str, is_monitor: bool, sample_rate: u32| DeviceListItem {
+        is_hands_free: sample_rate <= 16000,
+        sample_rate: Some(sample_rate),
+        ..DeviceListItem::for_test(inner_name, is_monitor)
+    };
+    let devices = vec![
+        device("headset", false, 16000),
+        device("internal-mic", false, 48000),
+        device("speakers.monitor", true, 48000),
+    ];
+    let now = Instant::now();
+    let pick = |histories: &HashMap<String, SignalHistory>| {
+        pick_device_at(&devices, histories, now).map(|device| device.inner_name.as_str())
+    };
+
+    let mut histories = HashMap::new();
+    assert_eq!(pick(&histories), Some("speakers.monitor"));
+
+    // The monitor stayed silent while it was recorded
+    histories.insert(
+        "speakers.monitor".to_string(),
+        SignalHistory {
+            last_signal: None,
+            silent_since: Some(now - Duration::from_secs(60)),
+        },
+    );
+    assert_eq!(pick(&histories), Some("internal-mic"));
+    histories.insert(
+        "headset".to_string(),
+        SignalHistory {
+            last_signal: Some(now - Duration::from_secs(10)),
+            silent_since: None,
+        },
+    );
+    assert_eq!(pick(&histories), Some("headset"));
+
+    assert_eq!(pick_device_at(&[], &histories, now), None);
+}
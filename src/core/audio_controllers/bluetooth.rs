@@ -0,0 +1,73 @@
+//! The microphone of a Bluetooth headset is only available in the
+//! hands-free profiles (HSP/HFP), which carry telephone quality audio: 8 KHz
+//! with the CVSD codec, 16 KHz with mSBC, and heavy compression in both
+//! cases. Music recorded this way is rarely recognized, so such devices are
+//! detected in order to warn the user, who will usually get better results
+//! with the built-in microphone or with the monitor of the headset output
+//! (in its A2DP profile).
+
+/// PulseAudio and PipeWire properties giving the Bluetooth profile of a
+/// source, depending on their versions
+#[cfg(any(test, all(target_os = "linux", feature = "pulse")))]
+pub const PROFILE_PROPERTIES: [&str; 3] =
+    ["api.bluez5.profile", "bluetooth.protocol", "bluez.profile"];
+
+/// Sample rate at or under which a Bluetooth capture is a hands-free one
+const MAX_HANDS_FREE_SAMPLE_RATE: u32 = 16000;
+
+/// Whether a source is a Bluetooth microphone in a hands-free profile, from
+/// its "device.bus" property, the value of its profile property if any and
+/// its sample rate
+#[cfg(any(test, all(target_os = "linux", feature = "pulse")))]
+pub fn is_hands_free_source(bus: Option<&str>, profile: Option<&str>, sample_rate: u32) -> bool {
+    if let Some(profile) = profile {
+        let profile = profile.to_lowercase();
+        return profile.contains("head_unit")
+            || profile.contains("head-unit")
+            || profile.starts_with("hsp")
+            || profile.starts_with("hfp");
+    }
+    bus == Some("bluetooth") && sample_rate <= MAX_HANDS_FREE_SAMPLE_RATE
+}
+
+/// The same, from the name of a device only known to CPAL (e.g.
+/// "bluez_input.00_1B_66_2A_3C_4D.0" or "bluealsa:DEV=00:1B:66:2A:3C:4D")
+pub fn is_hands_free_device_name(device_name: &str, sample_rate: u32) -> bool {
+    let device_name = device_name.to_lowercase();
+    (device_name.contains("bluez") || device_name.contains("bluealsa"))
+        && sample_rate <= MAX_HANDS_FREE_SAMPLE_RATE
+}
+
+#[test]
+fn test_hands_free_detection() {
+    assert!(is_hands_free_source(
+        Some("bluetooth"),
+        Some("headset-head-unit-msbc"),
+        16000
+    ));
+    assert!(is_hands_free_source(
+        Some("bluetooth"),
+        Some("handsfree_head_unit"),
+        8000
+    ));
+    assert!(!is_hands_free_source(
+        Some("bluetooth"),
+        Some("a2dp-source"),
+        48000
+    ));
+    assert!(is_hands_free_source(Some("bluetooth"), None, 8000));
+    assert!(!is_hands_free_source(Some("pci"), None, 16000));
+
+    assert!(is_hands_free_device_name(
+        "bluez_input.00_1B_66_2A_3C_4D.0",
+        16000
+    ));
+    assert!(!is_hands_free_device_name(
+        "bluez_output.00_1B_66_2A_3C_4D.1.monitor",
+        48000
+    ));
+    assert!(!is_hands_free_device_name(
+        "alsa_input.pci-0000_00_1f.3",
+        16000
+    ));
+}
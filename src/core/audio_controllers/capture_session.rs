@@ -0,0 +1,271 @@
+//! Raw capture sessions, dumped to disk by "songrec listen --dump-capture"
+//! and fed again to the recognition pipeline by "songrec replay", in order
+//! to debug recognition issues which depend on what was actually recorded.
+//!
+//! A session file starts with a "SONGREC-CAPTURE 1" line and a line of JSON
+//! describing the stream, followed by the chunks of interleaved samples as
+//! delivered by the audio backend: a little-endian u32 sample count, then
+//! the little-endian f32 samples. Keeping the chunk boundaries makes the
+//! replay feed the capture processor exactly like the original stream did.
+//! The chunks are written by their own thread, as the capture callback must
+//! not wait for the disk, and read one at a time while replaying.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+
+use crate::utils::clock;
+
+/// Prefix of the audio device names replaying a session, e.g.
+/// "session:/tmp/capture-20240101-120000.songrec-capture"
+pub const SESSION_DEVICE_PREFIX: &str = "session:";
+
+const MAGIC_LINE: &str = "SONGREC-CAPTURE 1\n";
+
+/// Far more than a backend delivers at once, so that a corrupted length
+/// doesn't allocate gigabytes
+const MAX_CHUNK_SAMPLES: usize = 1 << 22;
+
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// The chunks waiting for the writer thread, a few seconds of audio; the
+/// ones arriving while it is full are dropped
+const PENDING_CHUNKS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureSessionHeader {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// The recognition windows depend on it, so the replay uses it too
+    pub request_interval_secs: u64,
+    /// Where the recorded audio came from, as in the song history
+    pub source: String,
+}
+
+pub struct CaptureSessionWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl CaptureSessionWriter {
+    /// Create a new session file in the given directory, named after the
+    /// current time, with a number when another session was dumped during
+    /// the same second
+    pub fn create(directory: &Path, header: &CaptureSessionHeader) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(directory)?;
+        let time = clock::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut number = 1;
+        let (path, file) = loop {
+            let path = directory.join(match number {
+                1 => format!("capture-{}.songrec-capture", time),
+                _ => format!("capture-{}-{}.songrec-capture", time, number),
+            });
+            match File::create_new(&path) {
+                Ok(file) => break (path, file),
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => number += 1,
+                Err(error) => return Err(error.into()),
+            }
+        };
+
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC_LINE.as_bytes())?;
+        writer.write_all(serde_json::to_string(header)?.as_bytes())?;
+        writer.write_all(b"\n")?;
+
+        Ok(CaptureSessionWriter { path, writer })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_chunk(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        let mut bytes = Vec::with_capacity(4 + samples.len() * 4);
+        bytes.extend((samples.len() as u32).to_le_bytes());
+        bytes.extend(samples.iter().flat_map(|sample| sample.to_le_bytes()));
+        self.writer.write_all(&bytes)
+    }
+}
+
+impl Drop for CaptureSessionWriter {
+    fn drop(&mut self) {
+        self.writer.flush().ok();
+    }
+}
+
+/// Hands the chunks of the capture callback over to a thread writing them
+/// to a new session file of the directory, which is only created once they
+/// start coming (so that streams which fail to open leave no empty session
+/// behind), and closed once this is dropped
+pub struct CaptureDump {
+    chunks_tx: SyncSender<Vec<f32>>,
+    dropped_chunks: Arc<AtomicU64>,
+}
+
+impl CaptureDump {
+    pub fn start(directory: PathBuf, header: CaptureSessionHeader) -> Self {
+        let (chunks_tx, chunks_rx) = sync_channel::<Vec<f32>>(PENDING_CHUNKS);
+        let dropped_chunks = Arc::new(AtomicU64::new(0));
+        let dropped_chunks_2 = dropped_chunks.clone();
+        std::thread::spawn(move || {
+            let Ok(first_chunk) = chunks_rx.recv() else {
+                return;
+            };
+            let mut writer = match CaptureSessionWriter::create(&directory, &header) {
+                Ok(writer) => writer,
+                Err(error) => {
+                    warn!("Could not dump the capture: {}", error);
+                    return;
+                }
+            };
+            info!("Dumping the capture to {}", writer.path().display());
+            for chunk in std::iter::once(first_chunk).chain(chunks_rx) {
+                if let Err(error) = writer.write_chunk(&chunk) {
+                    warn!("Stopped dumping the capture: {}", error);
+                    return;
+                }
+            }
+            let dropped_chunks = dropped_chunks_2.load(Ordering::Relaxed);
+            if dropped_chunks > 0 {
+                warn!(
+                    "{} chunks were dropped from {}, as the disk was too slow",
+                    dropped_chunks,
+                    writer.path().display()
+                );
+            }
+        });
+        CaptureDump {
+            chunks_tx,
+            dropped_chunks,
+        }
+    }
+
+    /// Doesn't block; returns false once the writer thread stopped on an
+    /// error
+    pub fn write_chunk(&self, samples: &[f32]) -> bool {
+        match self.chunks_tx.try_send(samples.to_vec()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped_chunks.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Reads the chunks of a session one at a time, as an iterator
+pub struct CaptureSession {
+    pub header: CaptureSessionHeader,
+    path: PathBuf,
+    reader: BufReader<File>,
+}
+
+fn read_line(reader: &mut impl Read) -> Result<String, Box<dyn Error>> {
+    let mut line = vec![];
+    let mut byte = [0u8];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == b'\n' {
+            return Ok(String::from_utf8(line)?);
+        }
+        if line.len() == MAX_LINE_LENGTH {
+            return Err("Not a SongRec capture session".into());
+        }
+        line.push(byte[0]);
+    }
+}
+
+fn read_header(reader: &mut impl Read) -> Result<CaptureSessionHeader, Box<dyn Error>> {
+    if read_line(reader)? + "\n" != MAGIC_LINE {
+        return Err("Not a SongRec capture session".into());
+    }
+    Ok(serde_json::from_str(&read_line(reader)?)?)
+}
+
+impl CaptureSession {
+    pub fn load_header(path: &Path) -> Result<CaptureSessionHeader, Box<dyn Error>> {
+        read_header(&mut BufReader::new(File::open(path)?))
+            .map_err(|error| format!("{}: {}", path.display(), error).into())
+    }
+
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header =
+            read_header(&mut reader).map_err(|error| format!("{}: {}", path.display(), error))?;
+        Ok(CaptureSession {
+            header,
+            path: path.to_path_buf(),
+            reader,
+        })
+    }
+}
+
+/// A truncated last chunk, e.g. when SongRec was killed while dumping the
+/// session, is ignored; the replay stops at a corrupted one
+impl Iterator for CaptureSession {
+    type Item = Vec<f32>;
+
+    fn next(&mut self) -> Option<Vec<f32>> {
+        let mut length = [0u8; 4];
+        self.reader.read_exact(&mut length).ok()?;
+        let length = u32::from_le_bytes(length) as usize;
+        if length > MAX_CHUNK_SAMPLES {
+            warn!(
+                "{}: corrupted chunk of {} samples",
+                self.path.display(),
+                length
+            );
+            return None;
+        }
+        let mut bytes = vec![0u8; length * 4];
+        self.reader.read_exact(&mut bytes).ok()?;
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn test_capture_session() {
+    let directory = std::env::temp_dir().join(format!("songrec-test-{}", std::process::id()));
+    let header = CaptureSessionHeader {
+        channels: 2,
+        sample_rate: 44100,
+        request_interval_secs: 10,
+        source: "microphone:default".to_string(),
+    };
+
+    let path = {
+        let mut writer = CaptureSessionWriter::create(&directory, &header).unwrap();
+        writer.write_chunk(&[0.5, -0.5, 0.25, -0.25]).unwrap();
+        writer.write_chunk(&[1.0, -1.0]).unwrap();
+        writer.path().to_path_buf()
+    };
+    // Dumped during the same second, most likely
+    let other_path = CaptureSessionWriter::create(&directory, &header)
+        .unwrap()
+        .path()
+        .to_path_buf();
+    assert_ne!(other_path, path);
+
+    assert_eq!(CaptureSession::load_header(&path).unwrap(), header);
+    let session = CaptureSession::open(&path).unwrap();
+    assert_eq!(session.header, header);
+    assert_eq!(
+        session.collect::<Vec<_>>(),
+        vec![vec![0.5, -0.5, 0.25, -0.25], vec![1.0, -1.0]]
+    );
+
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert!(CaptureSession::load_header(Path::new("/nonexistent")).is_err());
+}
@@ -0,0 +1,111 @@
+//! The crystal of cheap USB sound cards may run a bit faster or slower than
+//! their nominal sample rate. Over a multi-hour listening session, the
+//! resampled audio then plays slightly faster or slower than the original,
+//! and the landmarks of the signatures drift away from Shazam's. The
+//! estimator compares the number of frames received with the elapsed time,
+//! and gives the real sample rate to resample from once the measurement
+//! is reliable.
+
+use log::info;
+use std::time::{Duration, Instant};
+
+/// Time before the measurement starts, while the audio backend may still
+/// deliver its initial buffers in a burst
+const WARM_UP: Duration = Duration::from_secs(30);
+
+/// Minimal measurement duration before compensating: the timing jitter of
+/// the audio callbacks (a few milliseconds) is then negligible
+const MIN_MEASUREMENT: Duration = Duration::from_secs(600);
+
+/// Drifts smaller than this are not worth compensating, in parts per million
+const MIN_DRIFT_PPM: f64 = 50.0;
+
+/// Larger differences don't come from a drifting clock, but from a
+/// stalled stream or a virtual device delivering samples faster than real
+/// time
+const MAX_DRIFT_PPM: f64 = 5000.0;
+
+pub struct ClockDriftEstimator {
+    nominal_sample_rate: u32,
+    started: Instant,
+    measurement_start: Option<Instant>,
+    measured_frames: u64,
+    compensated_sample_rate: u32,
+}
+
+impl ClockDriftEstimator {
+    pub fn new(nominal_sample_rate: u32, now: Instant) -> Self {
+        ClockDriftEstimator {
+            nominal_sample_rate,
+            started: now,
+            measurement_start: None,
+            measured_frames: 0,
+            compensated_sample_rate: nominal_sample_rate,
+        }
+    }
+
+    /// Account for `frames` frames received at `now`, and return the sample
+    /// rate that the audio should be resampled from
+    pub fn observe(&mut self, frames: usize, now: Instant) -> u32 {
+        let measurement_start = match self.measurement_start {
+            Some(measurement_start) => measurement_start,
+            None => {
+                if now.duration_since(self.started) >= WARM_UP {
+                    self.measurement_start = Some(now);
+                }
+                return self.compensated_sample_rate;
+            }
+        };
+        self.measured_frames += frames as u64;
+
+        let elapsed = now.duration_since(measurement_start);
+        if elapsed >= MIN_MEASUREMENT {
+            let real_sample_rate = self.measured_frames as f64 / elapsed.as_secs_f64();
+            let drift_ppm = (real_sample_rate / self.nominal_sample_rate as f64 - 1.0) * 1e6;
+
+            let compensated_sample_rate =
+                if drift_ppm.abs() < MIN_DRIFT_PPM || drift_ppm.abs() > MAX_DRIFT_PPM {
+                    self.nominal_sample_rate
+                } else {
+                    real_sample_rate.round() as u32
+                };
+            if compensated_sample_rate != self.compensated_sample_rate {
+                info!(
+                    "The audio device runs at {:.1} Hz instead of {} Hz ({:+.0} ppm), resampling from {} Hz",
+                    real_sample_rate,
+                    self.nominal_sample_rate,
+                    drift_ppm,
+                    compensated_sample_rate
+                );
+                self.compensated_sample_rate = compensated_sample_rate;
+            }
+        }
+
+        self.compensated_sample_rate
+    }
+}
+
+#[test]
+fn test_clock_drift_estimator() {
+    // A device nominally at 48 KHz, actually delivering 961 frames rather
+    // than 960 every 20 ms (+1042 ppm)
+    let start = Instant::now();
+    let mut estimator = ClockDriftEstimator::new(48000, start);
+    let mut compensated_sample_rate = 0;
+    for chunk in 0..(3600 * 50) {
+        let now = start + Duration::from_millis(20 * chunk);
+        compensated_sample_rate = estimator.observe(961, now);
+        if now - start < WARM_UP + MIN_MEASUREMENT {
+            assert_eq!(compensated_sample_rate, 48000);
+        }
+    }
+    assert_eq!(compensated_sample_rate, 48050);
+
+    // Twice as fast: not a drift
+    let mut estimator = ClockDriftEstimator::new(48000, start);
+    for chunk in 0..(3600 * 50) {
+        compensated_sample_rate =
+            estimator.observe(1920, start + Duration::from_millis(20 * chunk));
+    }
+    assert_eq!(compensated_sample_rate, 48000);
+}
@@ -3,6 +3,7 @@ use cpal::platform::{Device, Host};
 use cpal::traits::{DeviceTrait, HostTrait};
 
 use crate::core::audio_controllers::audio_backend::AudioBackend;
+use crate::core::audio_controllers::bluetooth::is_hands_free_device_name;
 use crate::core::thread_messages::DeviceListItem;
 
 pub struct CpalBackend;
@@ -47,10 +48,19 @@ impl AudioBackend for CpalBackend {
                 continue;
             }
 
+            let sample_rate = device
+                .default_input_config()
+                .ok()
+                .map(|config| config.sample_rate());
+            let is_hands_free = sample_rate
+                .is_some_and(|sample_rate| is_hands_free_device_name(&device_id, sample_rate));
+
             device_names.push(DeviceListItem {
                 inner_name: device_id,
                 display_name: device_name,
                 is_monitor: device_description.direction() != DeviceDirection::Input,
+                is_hands_free,
+                sample_rate,
             });
         }
 
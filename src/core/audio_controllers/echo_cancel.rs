@@ -0,0 +1,78 @@
+//! When the machine plays audio through its own speakers while recognizing
+//! through its microphone (e.g. a laptop in a noisy room), the echo of its
+//! own playback drowns the song to recognize. PulseAudio's and PipeWire's
+//! echo cancellation modules provide a filtered copy of the microphone,
+//! which is used instead when the user prefers it.
+
+use crate::core::thread_messages::DeviceListItem;
+
+/// Whether a source is provided by an echo cancellation module: PulseAudio
+/// names them "<microphone>.echo-cancel", PipeWire "echo-cancel-source"
+pub fn is_echo_cancelled_source(inner_name: &str) -> bool {
+    let inner_name = inner_name.to_lowercase();
+    inner_name.contains("echo-cancel") || inner_name.contains("echo_cancel")
+}
+
+/// The echo-cancelled source to record from instead of the given
+/// microphone: the one filtering this microphone, or else the only one.
+/// Monitors are recorded from as they are.
+pub fn echo_cancelled_counterpart<'a>(
+    devices: &'a [DeviceListItem],
+    microphone: &str,
+) -> Option<&'a DeviceListItem> {
+    if is_echo_cancelled_source(microphone)
+        || devices
+            .iter()
+            .any(|device| device.inner_name == microphone && device.is_monitor)
+    {
+        return None;
+    }
+    let echo_cancelled: Vec<&DeviceListItem> = devices
+        .iter()
+        .filter(|device| !device.is_monitor && is_echo_cancelled_source(&device.inner_name))
+        .collect();
+
+    echo_cancelled
+        .iter()
+        .find(|device| device.inner_name.starts_with(microphone))
+        .or(match echo_cancelled.as_slice() {
+            [only_one] => Some(only_one),
+            _ => None,
+        })
+        .copied()
+}
+
+#[test]
+fn test_echo_cancelled_counterpart() {
+    let device = DeviceListItem::for_test;
+    let devices = vec![
+        device("alsa_input.usb-mic", false),
+        device("alsa_input.pci-internal", false),
+        device("alsa_input.pci-internal.echo-cancel", false),
+        device("alsa_output.pci-internal.echo-cancel.monitor", true),
+    ];
+
+    assert_eq!(
+        echo_cancelled_counterpart(&devices, "alsa_input.pci-internal")
+            .map(|device| device.inner_name.as_str()),
+        Some("alsa_input.pci-internal.echo-cancel")
+    );
+    // A single echo-cancelled source is used for any microphone
+    assert_eq!(
+        echo_cancelled_counterpart(&devices, "alsa_input.usb-mic")
+            .map(|device| device.inner_name.as_str()),
+        Some("alsa_input.pci-internal.echo-cancel")
+    );
+    assert!(echo_cancelled_counterpart(&devices, "alsa_input.pci-internal.echo-cancel").is_none());
+    assert!(
+        echo_cancelled_counterpart(&devices, "alsa_output.pci-internal.echo-cancel.monitor")
+            .is_none()
+    );
+
+    let devices = vec![
+        device("alsa_input.usb-mic", false),
+        device("alsa_input.usb-mic.echo-cancel", false),
+        device("echo-cancel-source", false),
+    ];
+    assert!(echo_cancelled_counterpart(&devices, "alsa_input.pci-internal").is_none());
+}
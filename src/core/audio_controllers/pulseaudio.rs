@@ -4,6 +4,7 @@ use cpal::traits::HostTrait;
 use pulsectl::controllers::{AppControl, DeviceControl, SourceController};
 
 use crate::core::audio_controllers::audio_backend::AudioBackend;
+use crate::core::audio_controllers::bluetooth::{is_hands_free_source, PROFILE_PROPERTIES};
 use crate::core::thread_messages::DeviceListItem;
 
 use log::{debug, error};
@@ -71,6 +72,14 @@ impl AudioBackend for PulseBackend {
                     for dev in devices {
                         if let Some(desc) = &dev.description {
                             if let Some(name) = &dev.name {
+                                let is_hands_free = is_hands_free_source(
+                                    dev.proplist.get_str("device.bus").as_deref(),
+                                    PROFILE_PROPERTIES
+                                        .iter()
+                                        .find_map(|key| dev.proplist.get_str(key))
+                                        .as_deref(),
+                                    dev.sample_spec.rate,
+                                );
                                 if dev.name == info.default_source_name {
                                     device_names.insert(
                                         0,
@@ -78,6 +87,8 @@ impl AudioBackend for PulseBackend {
                                             inner_name: name.to_string(),
                                             display_name: desc.to_string(),
                                             is_monitor: dev.monitor.is_some(),
+                                            is_hands_free,
+                                            sample_rate: Some(dev.sample_spec.rate),
                                         },
                                     );
                                 } else if dev.monitor.is_some() {
@@ -85,12 +96,16 @@ impl AudioBackend for PulseBackend {
                                         inner_name: name.to_string(),
                                         display_name: desc.to_string(),
                                         is_monitor: true,
+                                        is_hands_free,
+                                        sample_rate: Some(dev.sample_spec.rate),
                                     });
                                 } else {
                                     device_names.push(DeviceListItem {
                                         inner_name: name.to_string(),
                                         display_name: desc.to_string(),
                                         is_monitor: false,
+                                        is_hands_free,
+                                        sample_rate: Some(dev.sample_spec.rate),
                                     });
                                 }
                             }
@@ -135,4 +150,22 @@ impl AudioBackend for PulseBackend {
 
         host.default_input_device().unwrap()
     }
+
+    /// PulseAudio and PipeWire name the monitor of each sink after it
+    fn default_output_monitor(&mut self, _host: &Host) -> Option<String> {
+        let default_sink_name = match self.handler.get_server_info() {
+            Ok(info) => info.default_sink_name?,
+            Err(error) => {
+                error!("Could not get PulseAudio server info: {:?}", error);
+                return None;
+            }
+        };
+        let monitor_name = format!("{}.monitor", default_sink_name);
+        let devices = self.handler.list_devices().ok()?;
+        devices
+            .iter()
+            .find(|dev| dev.monitor.is_some() && dev.name.as_deref() == Some(&monitor_name))
+            .or_else(|| devices.iter().find(|dev| dev.monitor.is_some()))
+            .and_then(|dev| dev.name.clone())
+    }
 }
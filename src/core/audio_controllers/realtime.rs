@@ -0,0 +1,264 @@
+//! Realtime scheduling of the threads which receive the captured audio, so
+//! that a loaded system (a build, a game, a busy browser) doesn't delay them
+//! past the end of the capture buffer, which loses samples and garbles the
+//! recognized window. On Linux the thread asks for SCHED_RR directly when
+//! RLIMIT_RTPRIO allows it, and otherwise through rtkit (the D-Bus service
+//! that PipeWire and PulseAudio use for the same purpose), falling back on
+//! a high nice level. As the capture thread must not wait on D-Bus, it only
+//! hands its thread id over to a thread which makes the rtkit calls.
+//!
+//! When realtime scheduling is denied, the capture opens its streams with a
+//! larger buffer than the default one from then on, which tolerates longer
+//! delays at the cost of latency that the recognition doesn't care about.
+
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DENIED: AtomicBool = AtomicBool::new(false);
+
+/// Whether realtime scheduling was denied, in which case the capture uses
+/// larger buffers
+pub fn is_denied() -> bool {
+    DENIED.load(Ordering::SeqCst)
+}
+
+/// Also called when CPAL reports that its own request was denied
+pub fn mark_denied() {
+    if !DENIED.swap(true, Ordering::SeqCst) {
+        info!("Realtime scheduling is unavailable, the capture uses larger buffers");
+    }
+}
+
+/// Whether realtime scheduling can be obtained, checked before the first
+/// stream is opened so that it opens with a larger buffer otherwise. Also
+/// starts the thread which asks rtkit for the promotions.
+pub fn probe() {
+    #[cfg(target_os = "linux")]
+    linux::probe();
+}
+
+/// Ask for realtime scheduling of the current thread, or at least for a
+/// higher priority. Called from the capture callback, it doesn't block.
+pub fn promote_current_thread() {
+    #[cfg(target_os = "linux")]
+    linux::promote_current_thread();
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use log::debug;
+    use soup::gio;
+    use std::error::Error;
+    use std::sync::mpsc::{sync_channel, SyncSender};
+    use std::sync::OnceLock;
+
+    use super::mark_denied;
+
+    /// Below the priority of the PipeWire and PulseAudio threads (20 by
+    /// default for rtkit), which feed ours
+    const REALTIME_PRIORITY: i32 = 10;
+
+    /// rtkit only grants realtime scheduling to the processes whose
+    /// RLIMIT_RTTIME is bounded, so that a runaway thread gets killed rather
+    /// than freezing the system
+    pub(super) const RTTIME_LIMIT_USEC: i64 = 200_000;
+
+    /// Nice level asked for when realtime scheduling is denied
+    const HIGH_PRIORITY_NICE: i32 = -11;
+
+    const RTKIT_SERVICE: &str = "org.freedesktop.RealtimeKit1";
+    const RTKIT_PATH: &str = "/org/freedesktop/RealtimeKit1";
+
+    /// The ids of the threads to promote through rtkit, set by `probe`
+    static PROMOTIONS: OnceLock<SyncSender<u64>> = OnceLock::new();
+
+    pub(super) fn clamp_priority(max_priority: i32) -> i32 {
+        REALTIME_PRIORITY.min(max_priority)
+    }
+
+    pub(super) fn clamp_rttime(max_usec: i64) -> i64 {
+        RTTIME_LIMIT_USEC.min(max_usec)
+    }
+
+    fn system_bus() -> Result<gio::DBusConnection, Box<dyn Error>> {
+        Ok(gio::bus_get_sync(
+            gio::BusType::System,
+            None::<&gio::Cancellable>,
+        )?)
+    }
+
+    fn rtkit_call(
+        connection: &gio::DBusConnection,
+        interface: &str,
+        method: &str,
+        parameters: glib::Variant,
+    ) -> Result<glib::Variant, Box<dyn Error>> {
+        Ok(connection.call_sync(
+            Some(RTKIT_SERVICE),
+            RTKIT_PATH,
+            interface,
+            method,
+            Some(&parameters),
+            None,
+            gio::DBusCallFlags::NONE,
+            1000,
+            None::<&gio::Cancellable>,
+        )?)
+    }
+
+    fn rtkit_property<T: glib::variant::FromVariant>(
+        connection: &gio::DBusConnection,
+        name: &str,
+    ) -> Result<T, Box<dyn Error>> {
+        rtkit_call(
+            connection,
+            "org.freedesktop.DBus.Properties",
+            "Get",
+            (RTKIT_SERVICE, name).into(),
+        )?
+        .child_value(0)
+        .as_variant()
+        .and_then(|value| value.get::<T>())
+        .ok_or_else(|| format!("Invalid {} property", name).into())
+    }
+
+    fn current_thread_id() -> u64 {
+        unsafe { libc::syscall(libc::SYS_gettid) as u64 }
+    }
+
+    /// SCHED_RR without rtkit, when RLIMIT_RTPRIO (e.g. set for the "audio"
+    /// group in limits.conf) or CAP_SYS_NICE allow it
+    fn set_scheduler_directly() -> bool {
+        let parameters = libc::sched_param {
+            sched_priority: REALTIME_PRIORITY,
+        };
+        // The thread itself, as Linux schedules the threads separately
+        unsafe { libc::sched_setscheduler(0, libc::SCHED_RR, &parameters) == 0 }
+    }
+
+    /// The soft limit is the one that kills a runaway thread; the hard one
+    /// is only lowered to what rtkit accepts, so that other libraries of
+    /// the process may still raise the soft one
+    fn bound_rttime(max_usec: i64) -> Result<(), Box<dyn Error>> {
+        let mut rlimit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(libc::RLIMIT_RTTIME, &mut rlimit) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        rlimit.rlim_max = rlimit.rlim_max.min(max_usec as libc::rlim_t);
+        rlimit.rlim_cur = rlimit
+            .rlim_cur
+            .min(clamp_rttime(max_usec) as libc::rlim_t)
+            .min(rlimit.rlim_max);
+        if unsafe { libc::setrlimit(libc::RLIMIT_RTTIME, &rlimit) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    fn make_thread_realtime(
+        connection: &gio::DBusConnection,
+        thread_id: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let max_rttime: i64 = rtkit_property(connection, "RTTimeUSecMax")?;
+        let max_priority: i32 = rtkit_property(connection, "MaxRealtimePriority")?;
+        bound_rttime(max_rttime)?;
+        rtkit_call(
+            connection,
+            RTKIT_SERVICE,
+            "MakeThreadRealtime",
+            (thread_id, clamp_priority(max_priority) as u32).into(),
+        )?;
+        Ok(())
+    }
+
+    fn make_thread_high_priority(
+        connection: &gio::DBusConnection,
+        thread_id: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        rtkit_call(
+            connection,
+            RTKIT_SERVICE,
+            "MakeThreadHighPriority",
+            (thread_id, HIGH_PRIORITY_NICE).into(),
+        )?;
+        Ok(())
+    }
+
+    fn promote_thread(thread_id: u64) {
+        let connection = match system_bus() {
+            Ok(connection) => connection,
+            Err(error) => {
+                debug!("rtkit is unavailable: {}", error);
+                mark_denied();
+                return;
+            }
+        };
+        match make_thread_realtime(&connection, thread_id) {
+            Ok(()) => debug!("The capture thread runs with realtime scheduling, through rtkit"),
+            Err(error) => {
+                debug!("rtkit denied realtime scheduling: {}", error);
+                mark_denied();
+                if let Err(error) = make_thread_high_priority(&connection, thread_id) {
+                    debug!("rtkit denied a higher priority: {}", error);
+                }
+            }
+        }
+    }
+
+    pub fn probe() {
+        PROMOTIONS.get_or_init(|| {
+            let (promotions_tx, promotions_rx) = sync_channel(4);
+            std::thread::spawn(move || {
+                for thread_id in promotions_rx {
+                    promote_thread(thread_id);
+                }
+            });
+            promotions_tx
+        });
+
+        let mut rtprio_limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let allowed_directly = unsafe { libc::getrlimit(libc::RLIMIT_RTPRIO, &mut rtprio_limit) }
+            == 0
+            && rtprio_limit.rlim_cur > 0;
+        if allowed_directly {
+            return;
+        }
+        match system_bus()
+            .and_then(|connection| rtkit_property::<i32>(&connection, "MaxRealtimePriority"))
+        {
+            Ok(priority) if priority > 0 => {}
+            Ok(_) => mark_denied(),
+            Err(error) => {
+                debug!("rtkit is unavailable: {}", error);
+                mark_denied();
+            }
+        }
+    }
+
+    pub fn promote_current_thread() {
+        if set_scheduler_directly() {
+            debug!("The capture thread runs with realtime scheduling");
+            return;
+        }
+        if let Some(promotions_tx) = PROMOTIONS.get() {
+            promotions_tx.try_send(current_thread_id()).ok();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_clamp_limits() {
+    use linux::{clamp_priority, clamp_rttime, RTTIME_LIMIT_USEC};
+
+    assert_eq!(clamp_priority(20), 10);
+    assert_eq!(clamp_priority(5), 5);
+    assert_eq!(clamp_rttime(i64::MAX), RTTIME_LIMIT_USEC);
+    assert_eq!(clamp_rttime(50_000), 50_000);
+}
@@ -0,0 +1,309 @@
+//! The "snapcast:HOST[:PORT]" audio device subscribes to a Snapcast server
+//! like a Snapcast client would, so that the music of a whole-house audio
+//! system can be logged from a single machine. AirPlay sources can be
+//! monitored the same way, through shairport-sync feeding a Snapcast
+//! stream.
+//!
+//! Only uncompressed streams are decoded: the stream that SongRec
+//! subscribes to must use "codec=pcm" in the configuration of snapserver.
+
+use std::error::Error;
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use gettextrs::gettext;
+use log::{info, warn};
+
+/// Prefix of the audio device names designating a Snapcast server, e.g.
+/// "snapcast:192.168.1.10" or "snapcast:music.local:1704"
+pub const SNAPCAST_DEVICE_PREFIX: &str = "snapcast:";
+
+const DEFAULT_PORT: u16 = 1704;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Snapcast servers send audio chunks continuously, even when their source
+/// is silent, so a longer pause means a broken connection
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const BASE_HEADER_SIZE: usize = 26;
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const CODEC_HEADER: u16 = 1;
+const WIRE_CHUNK: u16 = 2;
+const HELLO: u16 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapcastFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    bits_per_sample: u16,
+}
+
+pub struct SnapcastStream {
+    location: String,
+    socket: TcpStream,
+    reader: BufReader<TcpStream>,
+    pub format: SnapcastFormat,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Box<dyn Error>> {
+    Ok(u32::from_le_bytes(
+        bytes
+            .get(offset..offset + 4)
+            .ok_or("Truncated Snapcast message")?
+            .try_into()?,
+    ))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, Box<dyn Error>> {
+    Ok(u16::from_le_bytes(
+        bytes
+            .get(offset..offset + 2)
+            .ok_or("Truncated Snapcast message")?
+            .try_into()?,
+    ))
+}
+
+/// A string or a blob prefixed with its u32 size
+fn read_sized(bytes: &[u8], offset: usize) -> Result<&[u8], Box<dyn Error>> {
+    let size = read_u32(bytes, offset)? as usize;
+    bytes
+        .get(offset + 4..offset + 4 + size)
+        .ok_or_else(|| "Truncated Snapcast message".into())
+}
+
+/// The audio format of a "pcm" stream, from the RIFF header sent as the
+/// codec header
+fn parse_wave_header(header: &[u8]) -> Result<SnapcastFormat, Box<dyn Error>> {
+    if header.get(0..4) != Some(b"RIFF") || header.get(8..12) != Some(b"WAVE") {
+        return Err("Invalid Snapcast PCM header".into());
+    }
+    let format = SnapcastFormat {
+        channels: read_u16(header, 22)?,
+        sample_rate: read_u32(header, 24)?,
+        bits_per_sample: read_u16(header, 34)?,
+    };
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err("Invalid Snapcast PCM header".into());
+    }
+    match format.bits_per_sample {
+        16 | 24 | 32 => Ok(format),
+        bits => Err(format!("Unsupported Snapcast sample format: {} bits", bits).into()),
+    }
+}
+
+/// Interleaved little-endian integer samples to f32; Snapcast stores 24-bit
+/// samples in 32-bit containers
+fn decode_samples(data: &[u8], bits_per_sample: u16) -> Vec<f32> {
+    match bits_per_sample {
+        16 => data
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+            .collect(),
+        24 => data
+            .chunks_exact(4)
+            .map(|sample| {
+                i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f32 / 8388608.0
+            })
+            .collect(),
+        _ => data
+            .chunks_exact(4)
+            .map(|sample| {
+                i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f32
+                    / 2147483648.0
+            })
+            .collect(),
+    }
+}
+
+fn build_message(message_type: u16, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(BASE_HEADER_SIZE + payload.len());
+    message.extend_from_slice(&message_type.to_le_bytes());
+    // Id, refers to, sent and received times
+    message.extend_from_slice(&[0u8; 2 + 2 + 8 + 8]);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+impl SnapcastStream {
+    /// Connect to the server and wait for the format of its stream
+    pub fn connect(location: &str) -> Result<Self, Box<dyn Error>> {
+        let address = if location.contains(':') {
+            location.to_string()
+        } else {
+            format!("{}:{}", location, DEFAULT_PORT)
+        };
+        let socket_address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("{}: {}", gettext("Unknown host"), location))?;
+
+        let socket = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let hello = serde_json::json!({
+            "Arch": std::env::consts::ARCH,
+            "ClientName": "SongRec",
+            "HostName": "songrec",
+            "ID": format!("songrec-{}", std::process::id()),
+            "Instance": 1,
+            "MAC": "00:00:00:00:00:00",
+            "OS": std::env::consts::OS,
+            "SnapStreamProtocolVersion": 2,
+            "Version": env!("CARGO_PKG_VERSION"),
+        })
+        .to_string();
+        let mut payload = (hello.len() as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(hello.as_bytes());
+        (&socket).write_all(&build_message(HELLO, &payload))?;
+
+        let mut stream = SnapcastStream {
+            location: location.to_string(),
+            reader: BufReader::new(socket.try_clone()?),
+            socket,
+            format: SnapcastFormat {
+                sample_rate: 0,
+                channels: 0,
+                bits_per_sample: 0,
+            },
+        };
+
+        loop {
+            let (message_type, payload) = stream.read_message()?;
+            if message_type == CODEC_HEADER {
+                let codec = String::from_utf8_lossy(read_sized(&payload, 0)?).to_string();
+                if codec != "pcm" {
+                    return Err(gettext("The Snapcast stream uses the %s codec, please configure it with codec=pcm on the server")
+                        .replace("%s", &codec)
+                        .into());
+                }
+                let header_offset = 4 + codec.len();
+                stream.format = parse_wave_header(read_sized(&payload, header_offset)?)?;
+                info!(
+                    "Connected to the Snapcast server {}: {} Hz, {} channels, {} bits",
+                    location,
+                    stream.format.sample_rate,
+                    stream.format.channels,
+                    stream.format.bits_per_sample
+                );
+                return Ok(stream);
+            }
+        }
+    }
+
+    fn read_message(&mut self) -> Result<(u16, Vec<u8>), Box<dyn Error>> {
+        let mut header = [0u8; BASE_HEADER_SIZE];
+        self.reader.read_exact(&mut header)?;
+        let message_type = read_u16(&header, 0)?;
+        let size = read_u32(&header, 22)? as usize;
+        if size > MAX_MESSAGE_SIZE {
+            return Err("Invalid Snapcast message size".into());
+        }
+        let mut payload = vec![0u8; size];
+        self.reader.read_exact(&mut payload)?;
+        Ok((message_type, payload))
+    }
+
+    /// The samples of the next audio chunk
+    fn next_chunk(&mut self) -> Result<Vec<f32>, Box<dyn Error>> {
+        loop {
+            let (message_type, payload) = self.read_message()?;
+            match message_type {
+                WIRE_CHUNK => {
+                    // After the timestamp of the chunk
+                    return Ok(decode_samples(
+                        read_sized(&payload, 8)?,
+                        self.format.bits_per_sample,
+                    ));
+                }
+                CODEC_HEADER => return Err("The Snapcast stream changed".into()),
+                // Server settings (volume, latency) and time messages
+                _ => {}
+            }
+        }
+    }
+
+    /// Deliver the chunks of the stream until `stop` is set, reconnecting
+    /// when the connection is lost; a stream whose format changed is
+    /// abandoned, as the capture depends on it
+    pub fn deliver(mut self, stop: Arc<AtomicBool>, mut on_samples: impl FnMut(Vec<f32>)) {
+        while !stop.load(Ordering::SeqCst) {
+            match self.next_chunk() {
+                Ok(samples) => on_samples(samples),
+                Err(error) => {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    warn!("Snapcast stream {}: {}", self.location, error);
+                    self.socket.shutdown(Shutdown::Both).ok();
+                    loop {
+                        std::thread::sleep(RECONNECT_DELAY);
+                        if stop.load(Ordering::SeqCst) {
+                            return;
+                        }
+                        match SnapcastStream::connect(&self.location) {
+                            Ok(stream) if stream.format == self.format => {
+                                self = stream;
+                                break;
+                            }
+                            Ok(_) => {
+                                warn!(
+                                    "The format of the Snapcast stream {} changed, stopping",
+                                    self.location
+                                );
+                                return;
+                            }
+                            Err(error) => {
+                                warn!("Snapcast stream {}: {}", self.location, error);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_snapcast_pcm() {
+    let mut header = b"RIFF\x24\x00\x00\x00WAVEfmt \x10\x00\x00\x00\x01\x00".to_vec();
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&48000u32.to_le_bytes());
+    header.extend_from_slice(&(48000u32 * 4).to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data\x00\x00\x00\x00");
+
+    let format = parse_wave_header(&header).unwrap();
+    assert_eq!(
+        format,
+        SnapcastFormat {
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16
+        }
+    );
+    assert!(parse_wave_header(b"fLaC").is_err());
+
+    let mut data = vec![];
+    for sample in [0i16, 16384, -32768, 32767] {
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    assert_eq!(
+        decode_samples(&data, 16),
+        vec![0.0, 0.5, -1.0, 32767.0 / 32768.0]
+    );
+    assert_eq!(decode_samples(&(-4194304i32).to_le_bytes(), 24), vec![-0.5]);
+
+    let message = build_message(HELLO, b"payload");
+    assert_eq!(message.len(), BASE_HEADER_SIZE + 7);
+    assert_eq!(read_u16(&message, 0).unwrap(), HELLO);
+    assert_eq!(read_u32(&message, 22).unwrap(), 7);
+}
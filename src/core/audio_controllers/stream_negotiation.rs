@@ -0,0 +1,207 @@
+//! Some devices, or their drivers, refuse to open a capture stream with the
+//! configuration they report as their default ("unsupported stream
+//! configuration"). The capture then falls back on the other configurations
+//! that they support, by order of preference: sample formats that convert
+//! cheaply and losslessly first, the usual sample rates, and buffer sizes
+//! that drivers commonly accept.
+
+use cpal::{
+    BufferSize, SampleFormat, SupportedBufferSize, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
+
+/// The sample formats that the capture converts, by order of preference
+const PREFERRED_SAMPLE_FORMATS: [SampleFormat; 12] = [
+    SampleFormat::F32,
+    SampleFormat::I16,
+    SampleFormat::U16,
+    SampleFormat::I32,
+    SampleFormat::I24,
+    SampleFormat::F64,
+    SampleFormat::U32,
+    SampleFormat::U24,
+    SampleFormat::I8,
+    SampleFormat::U8,
+    SampleFormat::I64,
+    SampleFormat::U64,
+];
+
+const PREFERRED_SAMPLE_RATES: [u32; 2] = [48000, 44100];
+
+/// Buffer sizes tried when the default one does not work, in frames
+const FALLBACK_BUFFER_FRAMES: [u32; 2] = [1024, 4096];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamCandidate {
+    pub config: SupportedStreamConfig,
+    pub buffer_size: BufferSize,
+}
+
+impl StreamCandidate {
+    pub fn stream_config(&self) -> cpal::StreamConfig {
+        let mut stream_config = self.config.config();
+        stream_config.buffer_size = self.buffer_size;
+        stream_config
+    }
+}
+
+impl std::fmt::Display for StreamCandidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, {} channels, {} Hz",
+            self.config.sample_format(),
+            self.config.channels(),
+            self.config.sample_rate()
+        )?;
+        match self.buffer_size {
+            BufferSize::Fixed(frames) => write!(f, ", {} frames buffer", frames),
+            BufferSize::Default => write!(f, ", default buffer"),
+        }
+    }
+}
+
+fn clamp_buffer_frames(frames: u32, supported: &SupportedBufferSize) -> u32 {
+    match supported {
+        SupportedBufferSize::Range { min, max } => frames.clamp(*min, *max),
+        SupportedBufferSize::Unknown => frames,
+    }
+}
+
+fn pick_sample_rate(range: &SupportedStreamConfigRange, default_sample_rate: Option<u32>) -> u32 {
+    let supports = |rate: u32| range.min_sample_rate() <= rate && rate <= range.max_sample_rate();
+    default_sample_rate
+        .into_iter()
+        .chain(PREFERRED_SAMPLE_RATES)
+        .find(|rate| supports(*rate))
+        .unwrap_or(range.max_sample_rate())
+}
+
+/// The configurations to try opening the stream with, in order. The
+/// default configuration comes first, with the buffer size asked for by
+/// the capture if any.
+pub fn stream_candidates(
+    default_config: Option<SupportedStreamConfig>,
+    mut supported_ranges: Vec<SupportedStreamConfigRange>,
+    buffer_frames: Option<u32>,
+) -> Vec<StreamCandidate> {
+    let default_sample_rate = default_config.as_ref().map(|config| config.sample_rate());
+
+    // Mono and stereo first, as the capture downmixes anyway
+    supported_ranges.sort_by_key(|range| (range.channels() > 2, range.channels()));
+
+    let mut configs: Vec<SupportedStreamConfig> = default_config.into_iter().collect();
+    for sample_format in PREFERRED_SAMPLE_FORMATS {
+        for range in supported_ranges
+            .iter()
+            .filter(|range| range.sample_format() == sample_format)
+        {
+            configs.push(
+                range
+                    .clone()
+                    .with_sample_rate(pick_sample_rate(range, default_sample_rate)),
+            );
+        }
+    }
+
+    let mut candidates: Vec<StreamCandidate> = vec![];
+    for config in configs
+        .into_iter()
+        .filter(|config| PREFERRED_SAMPLE_FORMATS.contains(&config.sample_format()))
+    {
+        let preferred_buffer_size = match buffer_frames {
+            Some(frames) => BufferSize::Fixed(clamp_buffer_frames(frames, config.buffer_size())),
+            None => BufferSize::Default,
+        };
+        let fallback_buffer_sizes = FALLBACK_BUFFER_FRAMES
+            .iter()
+            .map(|frames| BufferSize::Fixed(clamp_buffer_frames(*frames, config.buffer_size())));
+
+        for buffer_size in std::iter::once(preferred_buffer_size).chain(fallback_buffer_sizes) {
+            let candidate = StreamCandidate {
+                config: config.clone(),
+                buffer_size,
+            };
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+/// List the configurations that a device supports, for error messages
+pub fn describe_ranges(ranges: &[SupportedStreamConfigRange]) -> String {
+    if ranges.is_empty() {
+        return "none".to_string();
+    }
+    ranges
+        .iter()
+        .map(|range| {
+            format!(
+                "{}, {} channels, {}-{} Hz",
+                range.sample_format(),
+                range.channels(),
+                range.min_sample_rate(),
+                range.max_sample_rate()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+#[test]
+fn test_stream_candidates() {
+    let supported_ranges = vec![
+        SupportedStreamConfigRange::new(
+            4,
+            8000,
+            96000,
+            SupportedBufferSize::Unknown,
+            SampleFormat::F32,
+        ),
+        SupportedStreamConfigRange::new(
+            2,
+            16000,
+            44100,
+            SupportedBufferSize::Range {
+                min: 256,
+                max: 2048,
+            },
+            SampleFormat::I16,
+        ),
+        SupportedStreamConfigRange::new(
+            2,
+            8000,
+            16000,
+            SupportedBufferSize::Unknown,
+            SampleFormat::U16,
+        ),
+    ];
+    let default_config = supported_ranges[1].clone().with_sample_rate(44100);
+
+    let candidates = stream_candidates(Some(default_config.clone()), supported_ranges, None);
+    let configs: Vec<(SampleFormat, u16, u32)> = candidates
+        .iter()
+        .map(|candidate| {
+            (
+                candidate.config.sample_format(),
+                candidate.config.channels(),
+                candidate.config.sample_rate(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        candidates[0],
+        StreamCandidate {
+            config: default_config,
+            buffer_size: BufferSize::Default
+        }
+    );
+    // The fallback buffer sizes are clamped to what the device supports
+    assert_eq!(candidates[2].buffer_size, BufferSize::Fixed(2048));
+    assert!(configs.contains(&(SampleFormat::F32, 4, 44100)));
+    assert!(configs.contains(&(SampleFormat::U16, 2, 16000)));
+    assert_eq!(candidates.len(), 3 * 3);
+}
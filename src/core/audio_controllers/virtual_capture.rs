@@ -0,0 +1,178 @@
+//! Capture streams which don't come from a sound card: samples generated or
+//! decoded on the fly are delivered by chunks from a separate thread, in real
+//! time or faster, like CPAL delivers the samples of a device.
+//!
+//! The "file:PATH" audio device plays a sound file this way, so that the
+//! listening mode can be exercised without sound hardware (e.g. in CI), and
+//! the "session:PATH" device replays a dumped capture session. The
+//! "snapcast:HOST" device receives the samples of a Snapcast server.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::core::audio_controllers::capture_session::SESSION_DEVICE_PREFIX;
+use crate::core::audio_controllers::snapcast::SNAPCAST_DEVICE_PREFIX;
+
+/// Duration of the chunks of samples delivered at once, in milliseconds
+const CHUNK_MILLIS: u32 = 20;
+
+const HOLD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Prefix of the audio device names designating a file, e.g.
+/// "file:/tmp/song.mp3" or "file:/tmp/song.mp3?speed=4"
+pub const FILE_DEVICE_PREFIX: &str = "file:";
+
+/// Whether the audio device name designates a virtual device rather than a
+/// sound card
+pub fn is_virtual_device(device_name: &str) -> bool {
+    device_name.starts_with(FILE_DEVICE_PREFIX)
+        || device_name.starts_with(SESSION_DEVICE_PREFIX)
+        || device_name.starts_with(SNAPCAST_DEVICE_PREFIX)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FileDevice {
+    pub path: String,
+    pub speed: f32,
+}
+
+impl FileDevice {
+    pub fn parse(device_name: &str) -> Option<Self> {
+        let location = device_name.strip_prefix(FILE_DEVICE_PREFIX)?;
+        if let Some((path, speed)) = location.rsplit_once("?speed=") {
+            if let Ok(speed) = speed.parse::<f32>() {
+                if speed > 0.0 {
+                    return Some(FileDevice {
+                        path: path.to_string(),
+                        speed,
+                    });
+                }
+            }
+        }
+        Some(FileDevice {
+            path: location.to_string(),
+            speed: 1.0,
+        })
+    }
+}
+
+/// The capture stops when this is dropped
+pub struct VirtualCapture {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VirtualCapture {
+    /// Deliver interleaved `samples` to `on_samples`; a `speed` of 1.0 means
+    /// real time, higher values deliver them faster. The samples are only
+    /// read (e.g. decoded) as they are delivered, and `on_end` is called
+    /// when they run out.
+    pub fn start(
+        mut samples: impl Iterator<Item = f32> + Send + 'static,
+        sample_rate: u32,
+        channels: u16,
+        speed: f32,
+        on_samples: impl FnMut(Vec<f32>) + Send + 'static,
+        on_end: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        let chunk_len = (sample_rate * CHUNK_MILLIS / 1000).max(1) as usize * channels as usize;
+        Self::start_chunks(
+            std::iter::from_fn(move || {
+                let chunk: Vec<f32> = samples.by_ref().take(chunk_len).collect();
+                (!chunk.is_empty()).then_some(chunk)
+            }),
+            Duration::from_millis(CHUNK_MILLIS as u64).div_f32(speed),
+            None,
+            on_samples,
+            on_end,
+        )
+    }
+
+    /// Deliver the given chunks, one every `chunk_interval`. While `hold` is
+    /// set (e.g. because a recognition is ongoing), the next chunk waits.
+    /// `on_end` is called once every chunk was delivered and released.
+    pub fn start_chunks(
+        chunks: impl IntoIterator<Item = Vec<f32>> + Send + 'static,
+        chunk_interval: Duration,
+        hold: Option<Arc<AtomicBool>>,
+        mut on_samples: impl FnMut(Vec<f32>) + Send + 'static,
+        on_end: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        Self::spawn(move |stop_2| {
+            let is_held = || {
+                hold.as_ref()
+                    .is_some_and(|hold| hold.load(Ordering::SeqCst))
+                    && !stop_2.load(Ordering::SeqCst)
+            };
+
+            let mut start_time = Instant::now();
+            for (index, chunk) in chunks.into_iter().enumerate() {
+                if stop_2.load(Ordering::SeqCst) {
+                    return;
+                }
+                on_samples(chunk);
+
+                if is_held() {
+                    while is_held() {
+                        std::thread::sleep(HOLD_POLL_INTERVAL);
+                    }
+                    // The waiting time should not be caught up with
+                    start_time = Instant::now() - chunk_interval * (index as u32 + 1);
+                }
+
+                // Don't drift when the callback is slow
+                let next_chunk_time = start_time + chunk_interval * (index as u32 + 1);
+                if let Some(delay) = next_chunk_time.checked_duration_since(Instant::now()) {
+                    std::thread::sleep(delay);
+                }
+            }
+            if !stop_2.load(Ordering::SeqCst) {
+                on_end();
+            }
+        })
+    }
+
+    /// Run `deliver` on a separate thread, which should return soon after
+    /// the flag it is given is set
+    pub fn spawn(deliver: impl FnOnce(Arc<AtomicBool>) + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_2 = stop.clone();
+
+        let thread = std::thread::spawn(move || deliver(stop_2));
+
+        VirtualCapture {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for VirtualCapture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[test]
+fn test_parse_file_device() {
+    assert_eq!(
+        FileDevice::parse("file:/tmp/song.mp3"),
+        Some(FileDevice {
+            path: "/tmp/song.mp3".to_string(),
+            speed: 1.0
+        })
+    );
+    assert_eq!(
+        FileDevice::parse("file:/tmp/song.mp3?speed=4"),
+        Some(FileDevice {
+            path: "/tmp/song.mp3".to_string(),
+            speed: 4.0
+        })
+    );
+    assert_eq!(FileDevice::parse("alsa_input.pci-0000_00_1f.3"), None);
+}
@@ -0,0 +1,133 @@
+//! Crash reports: a panic hook writes the panic message, a backtrace, the
+//! versions in use and the last log lines to a JSON file of the data
+//! directory, which the GUI offers to the user on its next start. The log
+//! lines, which name the recognized songs, are left out in incognito mode.
+
+use serde_json::json;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use crate::utils::clock;
+use crate::utils::filesystem_operations::{is_incognito, obtain_crash_reports_directory};
+
+/// Number of log lines kept in memory for crash reports
+const RECENT_LOG_LINES: usize = 200;
+
+/// Extension given to the reports that were already shown to the user
+const SEEN_EXTENSION: &str = "seen";
+
+/// Several threads may panic within the same second
+static REPORT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+static RECENT_LOG: LazyLock<Mutex<VecDeque<String>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::with_capacity(RECENT_LOG_LINES)));
+
+/// Log output keeping the last lines in memory
+pub struct RecentLogWriter;
+
+impl Write for RecentLogWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        let mut recent_log = RECENT_LOG.lock().unwrap();
+        if recent_log.len() >= RECENT_LOG_LINES {
+            recent_log.pop_front();
+        }
+        recent_log.push_back(String::from_utf8_lossy(buf).trim_end().to_string());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+fn write_report(
+    panic_info: &std::panic::PanicHookInfo,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let message = if let Some(message) = panic_info.payload().downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic_info.payload().downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "??".to_string()
+    };
+
+    // The panic may have happened while logging, don't wait for the lock
+    let recent_log: Vec<String> = match RECENT_LOG.try_lock() {
+        Ok(recent_log) if !is_incognito() => recent_log.iter().cloned().collect(),
+        _ => vec![],
+    };
+
+    let report = json!({
+        "songrec_version": env!("CARGO_PKG_VERSION"),
+        "glib_version": format!(
+            "{}.{}.{}",
+            glib::major_version(),
+            glib::minor_version(),
+            glib::micro_version()
+        ),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "time": clock::now_zoned().to_rfc3339(),
+        "thread": std::thread::current().name().unwrap_or("??"),
+        "message": message,
+        "location": panic_info
+            .location()
+            .map(|location| format!("{}:{}", location.file(), location.line())),
+        "backtrace": Backtrace::force_capture().to_string(),
+        "recent_log": recent_log,
+    });
+
+    let mut path = obtain_crash_reports_directory()?;
+    path.push(format!(
+        "crash-{}-{}-{}.json",
+        clock::now().format("%Y%m%d-%H%M%S"),
+        std::process::id(),
+        REPORT_COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+    Ok(path)
+}
+
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        match write_report(panic_info) {
+            Ok(path) => eprintln!("A crash report was written to {}", path.display()),
+            Err(error) => eprintln!("Could not write a crash report: {}", error),
+        }
+        default_hook(panic_info);
+    }));
+}
+
+/// Crash reports that were not shown to the user yet
+pub fn unseen_crash_reports() -> Vec<PathBuf> {
+    let mut reports: Vec<PathBuf> = match obtain_crash_reports_directory()
+        .ok()
+        .and_then(|directory| std::fs::read_dir(directory).ok())
+    {
+        Some(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .collect(),
+        None => vec![],
+    };
+    reports.sort();
+    reports
+}
+
+/// Returns the new path of the report
+pub fn mark_as_seen(report: &Path) -> PathBuf {
+    let seen_path = report.with_extension(SEEN_EXTENSION);
+    match std::fs::rename(report, &seen_path) {
+        Ok(()) => seen_path,
+        Err(_) => report.to_path_buf(),
+    }
+}
@@ -0,0 +1,243 @@
+//! Runs the whole recognition pipeline of "songrec listen" (capture,
+//! resampling, signature generation, request to the Shazam API, sinks) with
+//! an audio backend playing synthesized audio and a mock Shazam server, so
+//! that changes to the threads and channels between them can't silently
+//! break recognition.
+
+use cpal::platform::{Device, Host};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::core::audio_controllers::audio_backend::{AudioBackend, VirtualDevice};
+use crate::core::fingerprinting::signature_format::DecodedSignature;
+use crate::core::http_task::{http_task, NetworkSettings};
+use crate::core::microphone_thread::microphone_thread;
+use crate::core::preferences::{Preferences, PreferencesInterface};
+use crate::core::processing_thread::processing_thread;
+use crate::core::providers::ProviderSettings;
+use crate::core::thread_messages::*;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture, SinkRegistry};
+
+/// Like a common sound card
+const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u16 = 2;
+
+const DEVICE_NAME: &str = "synthetic";
+
+/// A scale of notes with an harmonic, four notes per second
+fn synthesize_melody(seconds: u32) -> Vec<f32> {
+    const NOTES_HZ: [f32; 8] = [440.0, 494.0, 523.0, 587.0, 659.0, 698.0, 784.0, 880.0];
+
+    let mut samples = Vec::with_capacity((SAMPLE_RATE * seconds) as usize * CHANNELS as usize);
+    for index in 0..SAMPLE_RATE * seconds {
+        let time = index as f32 / SAMPLE_RATE as f32;
+        let frequency = NOTES_HZ[(time * 4.0) as usize % NOTES_HZ.len()];
+        let value =
+            0.5 * (2.0 * PI * frequency * time).sin() + 0.2 * (4.0 * PI * frequency * time).sin();
+        for _channel in 0..CHANNELS {
+            samples.push(value);
+        }
+    }
+    samples
+}
+
+/// Answer every recognition request with the same song, keeping the
+/// signatures that were sent
+fn start_mock_shazam_server(received_signatures: Rc<RefCell<Vec<String>>>) -> soup::Server {
+    let server = glib::Object::new::<soup::Server>();
+
+    server.add_handler(None, move |_server, message, _path, _query| {
+        let body = message.request_body().unwrap().flatten();
+        let request: Value = serde_json::from_slice(&body[..]).unwrap_or_default();
+        if let Value::String(uri) = &request["signature"]["uri"] {
+            received_signatures.borrow_mut().push(uri.to_string());
+        }
+
+        let response = json!({
+            "track": {
+                "key": "20066955",
+                "title": "Around the World",
+                "subtitle": "Daft Punk",
+                "genres": { "primary": "Electronic" },
+                "sections": [{
+                    "type": "SONG",
+                    "metadata": [{ "title": "Album", "text": "Homework" }]
+                }]
+            }
+        })
+        .to_string();
+        message.set_status(200, None);
+        message.set_response(
+            Some("application/json"),
+            soup::MemoryUse::Copy,
+            response.as_bytes(),
+        );
+    });
+
+    server
+        .listen_local(0, soup::ServerListenOptions::IPV4_ONLY)
+        .unwrap();
+    server
+}
+
+/// A single microphone, playing a melody
+struct FakeBackend;
+
+impl AudioBackend for FakeBackend {
+    fn list_devices(&mut self, _host: &Host) -> Vec<DeviceListItem> {
+        vec![DeviceListItem {
+            inner_name: DEVICE_NAME.to_string(),
+            display_name: "Synthetic microphone".to_string(),
+            is_monitor: false,
+            is_hands_free: false,
+            sample_rate: Some(SAMPLE_RATE),
+        }]
+    }
+
+    fn set_device(&mut self, _host: &Host, inner_name: &str) -> Device {
+        unreachable!("{} is a virtual device", inner_name)
+    }
+
+    fn open_virtual_device(
+        &mut self,
+        device_name: &str,
+    ) -> Option<Result<VirtualDevice, Box<dyn Error>>> {
+        (device_name == DEVICE_NAME).then(|| {
+            Ok(VirtualDevice {
+                samples: Box::new(synthesize_melody(12).into_iter()),
+                sample_rate: SAMPLE_RATE,
+                channels: CHANNELS,
+                speed: 20.0,
+                source: RecognitionSource::Microphone(DEVICE_NAME.to_string()),
+            })
+        })
+    }
+}
+
+struct RecordingSink(Rc<RefCell<Vec<SongRecognizedMessage>>>);
+
+impl RecognitionSink for RecordingSink {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        self.0.borrow_mut().push(message.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+async fn run_pipeline() {
+    let received_signatures = Rc::new(RefCell::new(vec![]));
+    let server = start_mock_shazam_server(received_signatures.clone());
+    let server_uri = server.uris()[0].to_str();
+
+    let (gui_tx, gui_rx) = async_channel::unbounded();
+    let (microphone_tx, microphone_rx) = async_channel::unbounded();
+    let (processing_tx, processing_rx) = async_channel::unbounded();
+    let (http_tx, http_rx) = async_channel::unbounded();
+
+    let gui_tx_2 = gui_tx.clone();
+    std::thread::spawn(move || processing_thread(processing_rx, http_tx, gui_tx_2));
+
+    glib::spawn_future_local(http_task(
+        http_rx,
+        gui_tx.clone(),
+        microphone_tx.clone(),
+        ProviderSettings::default(),
+        NetworkSettings {
+            low_data: true,
+            shazam_endpoint: Some(server_uri.to_string()),
+            ..Default::default()
+        },
+    ));
+
+    let preferences_interface = Arc::new(Mutex::new(PreferencesInterface {
+        preferences_file_path: None,
+        preferences: Preferences::with_interval(4),
+    }));
+    let microphone_tx_2 = microphone_tx.clone();
+    let gui_tx_3 = gui_tx.clone();
+    std::thread::spawn(move || {
+        microphone_thread(
+            || Box::new(FakeBackend),
+            microphone_rx,
+            microphone_tx_2,
+            processing_tx,
+            gui_tx_3,
+            preferences_interface,
+            false,
+        )
+    });
+
+    // Fail rather than hang
+    glib::timeout_add_local_once(Duration::from_secs(30), move || {
+        gui_tx
+            .try_send(GUIMessage::ErrorMessage("Timed out".to_string()))
+            .unwrap();
+    });
+
+    let recognized_songs = Rc::new(RefCell::new(vec![]));
+    let mut sinks = SinkRegistry::default();
+    sinks.register(Box::new(RecordingSink(recognized_songs.clone())));
+
+    loop {
+        match gui_rx.recv().await.unwrap() {
+            // Record from the device the backend lists
+            GUIMessage::DevicesList(devices) => {
+                let device = devices
+                    .iter()
+                    .find(|device| device.inner_name == DEVICE_NAME)
+                    .unwrap();
+                microphone_tx
+                    .try_send(MicrophoneMessage::MicrophoneRecordStart(
+                        device.inner_name.clone(),
+                    ))
+                    .unwrap();
+            }
+            GUIMessage::SongRecognized(message) => {
+                sinks.song_recognized(&message).await;
+                break;
+            }
+            GUIMessage::ErrorMessage(error) => panic!("{}", error),
+            _ => {}
+        }
+    }
+
+    let signatures = received_signatures.borrow();
+    assert_eq!(signatures.len(), 1);
+    let signature = DecodedSignature::decode_from_uri(&signatures[0]).unwrap();
+    assert_eq!(signature.sample_rate_hz, 16000);
+    assert!(signature
+        .frequency_band_to_sound_peaks
+        .iter()
+        .any(|peaks| !peaks.is_empty()));
+
+    let songs = recognized_songs.borrow();
+    assert_eq!(songs.len(), 1);
+    assert_eq!(songs[0].song_name, "Around the World");
+    assert_eq!(songs[0].artist_name, "Daft Punk");
+    assert_eq!(songs[0].album_name.as_deref(), Some("Homework"));
+    assert_eq!(songs[0].genre.as_deref(), Some("Electronic"));
+    assert!(matches!(
+        songs[0].source,
+        Some(RecognitionSource::Microphone(ref device)) if device == DEVICE_NAME
+    ));
+    microphone_tx
+        .try_send(MicrophoneMessage::MicrophoneRecordStop)
+        .unwrap();
+}
+
+#[test]
+fn test_recognition_pipeline() {
+    // The default context may be used by other tests running concurrently
+    let context = glib::MainContext::new();
+    context
+        .with_thread_default(|| context.block_on(run_pipeline()))
+        .unwrap();
+}
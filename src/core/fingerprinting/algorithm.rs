@@ -1,22 +1,40 @@
-use realfft::RealFftPlanner;
-use rodio::conversions::SampleTypeConverter;
+use realfft::{RealFftPlanner, RealToComplex};
 use rodio::nz;
 use rustfft::num_complex::Complex;
 use rustfft::num_traits::Zero;
+use std::cell::RefCell;
 use std::error::Error;
 use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
 
+use crate::core::fingerprinting::dsp;
 use crate::core::fingerprinting::hanning::HANNING_WINDOW_2048_MULTIPLIERS;
+use crate::core::fingerprinting::signature_cache::{cached_signature, SignatureSpan};
 use crate::core::fingerprinting::signature_format::{
     DecodedSignature, FrequencyBand, FrequencyPeak,
 };
+use crate::core::metrics::{self, Stage};
+use crate::core::resource_limits;
 #[cfg(feature = "ffmpeg")]
 use crate::plugins::ffmpeg_wrapper::decode_with_ffmpeg;
 
+/// The FFT of 2048 samples is planned once for the whole process, the
+/// Hanning window being a constant table
+static FORWARD_FFT: LazyLock<Arc<dyn RealToComplex<f32>>> =
+    LazyLock::new(|| RealFftPlanner::<f32>::new().plan_fft_forward(2048));
+
+thread_local! {
+    /// The buffers of a generator weigh about 2 MB, so the processing thread
+    /// reuses them from one recognition to the next
+    static GENERATOR: RefCell<Option<SignatureGenerator>> = const { RefCell::new(None) };
+}
+
 pub struct SignatureGenerator {
     // Used when processing input:
     /// Ring buffer.
-    ring_buffer_of_samples: Box<[i16; 2048]>,
+    ring_buffer_of_samples: Box<[f32; 2048]>,
     ring_buffer_of_samples_index: usize,
 
     /// Reordered, temporary version of the ring buffer above, with floats for
@@ -32,7 +50,8 @@ pub struct SignatureGenerator {
     fft_outputs: Box<[[f32; 1025]; 256]>,
     fft_outputs_index: u8,
 
-    fft_object: RealFftPlanner<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    fft_scratch: Vec<Complex<f32>>,
 
     /// Ring buffer.
     spread_fft_outputs: Box<[[f32; 1025]; 256]>,
@@ -44,9 +63,11 @@ pub struct SignatureGenerator {
 }
 
 impl SignatureGenerator {
-    pub fn make_signature_from_file(file_path: &str) -> Result<DecodedSignature, Box<dyn Error>> {
-        // Decode the .WAV, .MP3, .OGG or .FLAC file
-
+    /// Open a .WAV, .MP3, .OGG or .FLAC file, or any format supported by
+    /// FFMpeg
+    pub fn open_audio_file(
+        file_path: &str,
+    ) -> Result<rodio::Decoder<BufReader<std::fs::File>>, Box<dyn Error>> {
         #[cfg(not(feature = "ffmpeg"))]
         let decoder = rodio::Decoder::new(BufReader::new(std::fs::File::open(file_path)?));
 
@@ -67,12 +88,29 @@ impl SignatureGenerator {
             decoder
         };
 
+        Ok(decoder?)
+    }
+
+    /// Decode the whole of an audio file into 16 KHz mono samples
+    pub fn decode_file_to_16khz_mono(file_path: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let _permit = resource_limits::acquire_decode();
+        let decoder = Self::open_audio_file(file_path)?;
+
         // Downsample the raw PCM samples to 16 KHz
 
-        let converted_file =
-            rodio::source::UniformSourceIterator::new(decoder?, nz!(1), nz!(16000));
+        let converted_file = rodio::source::UniformSourceIterator::new(decoder, nz!(1), nz!(16000));
 
-        let mut raw_pcm_samples: Vec<f32> = converted_file.collect();
+        Ok(resource_limits::collect_samples(converted_file, file_path))
+    }
+
+    pub fn make_signature_from_file(file_path: &str) -> Result<DecodedSignature, Box<dyn Error>> {
+        cached_signature(Path::new(file_path), SignatureSpan::Excerpt, || {
+            Self::compute_signature_from_file(file_path)
+        })
+    }
+
+    fn compute_signature_from_file(file_path: &str) -> Result<DecodedSignature, Box<dyn Error>> {
+        let mut raw_pcm_samples: Vec<f32> = Self::decode_file_to_16khz_mono(file_path)?;
 
         // Pad the input to at least 12 seconds in order to avoid missing data
         // at the end of the input
@@ -100,9 +138,12 @@ impl SignatureGenerator {
         ))
     }
 
-    pub fn make_signature_from_buffer(f32_mono_16khz_buffer: &[f32]) -> DecodedSignature {
-        let mut this = SignatureGenerator {
-            ring_buffer_of_samples: Box::new([0i16; 2048]),
+    fn new() -> Self {
+        let fft = FORWARD_FFT.clone();
+        let fft_scratch = fft.make_scratch_vec();
+
+        SignatureGenerator {
+            ring_buffer_of_samples: Box::new([0.0f32; 2048]),
             ring_buffer_of_samples_index: 0,
 
             reordered_ring_buffer_of_samples: Box::new([0.0f32; 2048]),
@@ -111,78 +152,111 @@ impl SignatureGenerator {
             fft_outputs: Box::new([[0.0f32; 1025]; 256]),
             fft_outputs_index: 0u8,
 
-            fft_object: RealFftPlanner::<f32>::new(),
+            fft,
+            fft_scratch,
 
             spread_fft_outputs: Box::new([[0.0f32; 1025]; 256]),
             spread_fft_outputs_index: 0u8,
 
             num_spread_ffts_done: 0,
 
-            signature: DecodedSignature {
-                sample_rate_hz: 16000,
-                number_samples: f32_mono_16khz_buffer.len() as u32,
-                frequency_band_to_sound_peaks: Default::default(),
-            },
-        };
+            signature: Self::empty_signature(0),
+        }
+    }
+
+    fn empty_signature(number_samples: u32) -> DecodedSignature {
+        DecodedSignature {
+            sample_rate_hz: 16000,
+            number_samples,
+            frequency_band_to_sound_peaks: Default::default(),
+        }
+    }
+
+    /// Forget the previous recognition, keeping the allocated buffers
+    fn reset(&mut self, number_samples: u32) {
+        self.ring_buffer_of_samples.fill(0.0);
+        self.ring_buffer_of_samples_index = 0;
+
+        self.reordered_ring_buffer_of_samples.fill(0.0);
+        self.complex_fft_output.fill(Complex::zero());
+
+        self.fft_outputs.fill([0.0f32; 1025]);
+        self.fft_outputs_index = 0;
 
-        let s16_buffer: Vec<i16> =
-            SampleTypeConverter::<_, i16>::new(f32_mono_16khz_buffer.iter().copied()).collect();
+        self.spread_fft_outputs.fill([0.0f32; 1025]);
+        self.spread_fft_outputs_index = 0;
 
-        for chunk in s16_buffer.as_chunks::<128>().0 {
-            this.do_fft(chunk);
+        self.num_spread_ffts_done = 0;
+
+        self.signature = Self::empty_signature(number_samples);
+    }
+
+    pub fn make_signature_from_buffer(f32_mono_16khz_buffer: &[f32]) -> DecodedSignature {
+        GENERATOR.with(|generator| {
+            let mut generator = generator.borrow_mut();
+            let this = generator.get_or_insert_with(SignatureGenerator::new);
+            this.reset(f32_mono_16khz_buffer.len() as u32);
+            this.generate(f32_mono_16khz_buffer)
+        })
+    }
 
-            this.do_peak_spreading();
+    fn generate(&mut self, f32_mono_16khz_buffer: &[f32]) -> DecodedSignature {
+        let fft_start = Instant::now();
 
-            this.num_spread_ffts_done += 1;
+        let quantized_buffer = dsp::quantize(f32_mono_16khz_buffer);
 
-            if this.num_spread_ffts_done >= 46 {
-                this.do_peak_recognition();
+        for chunk in quantized_buffer.as_chunks::<128>().0 {
+            self.do_fft(chunk);
+
+            self.do_peak_spreading();
+
+            self.num_spread_ffts_done += 1;
+
+            if self.num_spread_ffts_done >= 46 {
+                self.do_peak_recognition();
             }
         }
 
-        this.signature
+        metrics::observe_stage(Stage::Fft, fft_start.elapsed());
+
+        std::mem::replace(&mut self.signature, Self::empty_signature(0))
     }
 
-    fn do_fft(&mut self, s16_mono_16khz_buffer: &[i16; 128]) {
-        // Copy the 128 input s16le samples to the local ring buffer
+    fn do_fft(&mut self, quantized_mono_16khz_buffer: &[f32; 128]) {
+        // Copy the 128 input samples to the local ring buffer
 
         self.ring_buffer_of_samples
             [self.ring_buffer_of_samples_index..self.ring_buffer_of_samples_index + 128]
-            .copy_from_slice(s16_mono_16khz_buffer);
+            .copy_from_slice(quantized_mono_16khz_buffer);
 
         self.ring_buffer_of_samples_index += 128;
         self.ring_buffer_of_samples_index &= 2047;
 
         // Reorder the items (put the latest data at end) and apply Hanning window
 
-        for (index, multiplier) in HANNING_WINDOW_2048_MULTIPLIERS.iter().enumerate() {
-            self.reordered_ring_buffer_of_samples[index] = self.ring_buffer_of_samples
-                [(index + self.ring_buffer_of_samples_index) & 2047]
-                as f32
-                * multiplier;
-        }
+        dsp::apply_window(
+            &self.ring_buffer_of_samples,
+            self.ring_buffer_of_samples_index,
+            &HANNING_WINDOW_2048_MULTIPLIERS,
+            &mut self.reordered_ring_buffer_of_samples,
+        );
 
         // Perform Fast Fourier transform
 
-        self.fft_object
-            .plan_fft_forward(2048)
-            .process(
+        self.fft
+            .process_with_scratch(
                 &mut *self.reordered_ring_buffer_of_samples,
                 &mut *self.complex_fft_output,
+                &mut self.fft_scratch,
             )
             .unwrap();
 
         // Turn complex into reals, and put the results into a local array
 
-        let real_fft_results = &mut self.fft_outputs[self.fft_outputs_index as usize];
-
-        for (result, complex) in real_fft_results
-            .iter_mut()
-            .zip(self.complex_fft_output.iter())
-        {
-            *result =
-                ((complex.re.powi(2) + complex.im.powi(2)) / ((1 << 17) as f32)).max(0.0000000001);
-        }
+        dsp::compute_magnitudes(
+            &self.complex_fft_output[..],
+            &mut self.fft_outputs[self.fft_outputs_index as usize],
+        );
 
         self.fft_outputs_index = self.fft_outputs_index.wrapping_add(1);
     }
@@ -320,3 +394,22 @@ impl SignatureGenerator {
         }
     }
 }
+
+#[test]
+fn test_reused_generator_gives_same_signature() {
+    let tone: Vec<f32> = (0..16000 * 4)
+        .map(|index| (index as f32 * 440.0 * 2.0 * std::f32::consts::PI / 16000.0).sin() * 0.5)
+        .collect();
+    let noise: Vec<f32> = (0..16000 * 4)
+        .map(|index| ((index * 7919 % 1000) as f32 / 1000.0 - 0.5) * 0.8)
+        .collect();
+
+    let first = SignatureGenerator::make_signature_from_buffer(&tone)
+        .encode_to_uri()
+        .unwrap();
+    SignatureGenerator::make_signature_from_buffer(&noise);
+    let second = SignatureGenerator::make_signature_from_buffer(&tone)
+        .encode_to_uri()
+        .unwrap();
+    assert_eq!(first, second);
+}
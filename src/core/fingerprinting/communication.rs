@@ -1,15 +1,27 @@
 use gettextrs::gettext;
 use glib::source::Priority;
 use log::{debug, error, trace};
-use rand::prelude::IndexedRandom;
 use serde_json::{json, Value};
 use soup::prelude::SessionExt;
 use std::error::Error;
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
 use uuid::Uuid;
 
 use crate::core::fingerprinting::signature_format::DecodedSignature;
-use crate::core::fingerprinting::user_agent::USER_AGENTS;
+use crate::core::fingerprinting::user_agent::user_agent;
+use crate::core::metrics::{self, Stage};
+use crate::core::resource_limits;
+
+const SHAZAM_ENDPOINT: &str = "https://amp.shazam.com";
+
+/// The given server (a mock one in the tests, see `NetworkSettings`), or
+/// Shazam
+fn shazam_endpoint(endpoint: Option<&str>) -> String {
+    endpoint
+        .unwrap_or(SHAZAM_ENDPOINT)
+        .trim_end_matches('/')
+        .to_string()
+}
 
 fn log_request(message: &soup::Message, post_data: &str) {
     if let Some(headers) = message.request_headers() {
@@ -61,16 +73,94 @@ fn log_response(message: &soup::Message, response: &str) {
     }
 }
 
+fn uri_host(uri: &str) -> Option<String> {
+    glib::Uri::parse(uri, glib::UriFlags::NONE)
+        .ok()?
+        .host()
+        .map(|host| host.to_string())
+}
+
+/// Captive portals intercept requests to any host in order to serve their
+/// login page, with a "511 Network Authentication Required" status, or with
+/// an HTML page from their own host, possibly through a redirection. The
+/// error pages of the requested server itself are not mistaken for one.
+fn is_captive_portal_response(message: &soup::Message, url: &str) -> bool {
+    let status = message.status_code();
+    if status == 511 {
+        return true;
+    }
+    if !(200..400).contains(&status) {
+        return false;
+    }
+    let headers = message.response_headers();
+    let is_html = headers
+        .as_ref()
+        .and_then(|headers| headers.one("Content-Type"))
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    // Where the redirections led, or the target of the one not followed
+    let host = if (300..400).contains(&status) {
+        headers
+            .and_then(|headers| headers.one("Location"))
+            .and_then(|location| uri_host(&location))
+    } else {
+        message
+            .uri()
+            .and_then(|uri| uri.host())
+            .map(|host| host.to_string())
+    };
+    is_html && host.is_some_and(|host| Some(host) != uri_host(url))
+}
+
+/// Whether the Shazam API answers, for "songrec doctor". The recognition
+/// path is probed rather than the root of the host, which redirects to a
+/// web page: the API refuses a GET there with an error status, which tells
+/// that it is reachable, while a captive portal serves its login page.
+pub async fn check_shazam_reachable(session: &soup::Session) -> Result<(), Box<dyn Error>> {
+    session.set_user_agent(&user_agent());
+    let url = format!(
+        "{}/discovery/v5/en/US/android/-/tag/{}/{}",
+        SHAZAM_ENDPOINT,
+        Uuid::new_v4().hyphenated().to_string().to_uppercase(),
+        Uuid::new_v4().hyphenated()
+    );
+    let message = soup::Message::new("GET", &url)?;
+    session
+        .send_and_read_future(&message, Priority::DEFAULT)
+        .await?;
+    let status = message.status_code();
+    if status == 429 {
+        return Err(gettext("Your IP has been rate-limited").into());
+    }
+    if (400..500).contains(&status) {
+        return Ok(());
+    }
+    if is_captive_portal_response(&message, &url) {
+        return Err(gettext("The network requires a login").into());
+    }
+    if status >= 500 {
+        return Err(gettext("Shazam answered with the HTTP status %d")
+            .replace("%d", &status.to_string())
+            .into());
+    }
+    Ok(())
+}
+
 pub async fn recognize_song_from_signature(
     session: &soup::Session,
     signature: &DecodedSignature,
+    endpoint: Option<&str>,
 ) -> Result<Value, Box<dyn Error>> {
-    session.set_user_agent(USER_AGENTS.choose(&mut rand::rng()).unwrap());
+    resource_limits::wait_for_request_slot().await;
+    session.set_user_agent(&user_agent());
 
     let timestamp_ms = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)?
         .as_millis();
 
+    let encode_start = Instant::now();
+    let uri = signature.encode_to_uri()?;
+    metrics::observe_stage(Stage::Encode, encode_start.elapsed());
+
     let post_data = json!({
         "geolocation": {
             "altitude": 300,
@@ -80,7 +170,7 @@ pub async fn recognize_song_from_signature(
         "signature": {
             "samplems": (signature.number_samples as f32 / signature.sample_rate_hz as f32 * 1000.) as u32,
             "timestamp": timestamp_ms as u32,
-            "uri": signature.encode_to_uri()?
+            "uri": uri
         },
         "timestamp": timestamp_ms as u32,
         "timezone": "Europe/Paris"
@@ -90,7 +180,7 @@ pub async fn recognize_song_from_signature(
     let uuid_2 = Uuid::new_v4().hyphenated().to_string();
 
     let url = format!(
-        "https://amp.shazam.com/discovery/v5/en/US/android/-/tag/{}/{}\
+        "{}/discovery/v5/en/US/android/-/tag/{}/{}\
 ?sync=true\
 &webv3=true\
 &sampling=true\
@@ -98,7 +188,9 @@ pub async fn recognize_song_from_signature(
 &shazamapiversion=v3\
 &sharehub=true\
 &video=v3",
-        uuid_1, uuid_2
+        shazam_endpoint(endpoint),
+        uuid_1,
+        uuid_2
     );
 
     let message = soup::Message::from_encoded_form("POST", &url, post_data.clone().into())?;
@@ -110,14 +202,17 @@ pub async fn recognize_song_from_signature(
 
     log_request(&message, &post_data);
 
+    let http_start = Instant::now();
     let response = session
         .send_and_read_future(&message, Priority::DEFAULT)
         .await?;
+    metrics::observe_stage(Stage::Http, http_start.elapsed());
 
     let decoded_resp = String::from_utf8_lossy(&response[..]);
 
     log_response(&message, &decoded_resp);
 
+    // Checked first, as rate limits may come with an HTML page too
     if message.status_code() == 429 {
         return Err(Box::new(std::io::Error::new(
             std::io::ErrorKind::QuotaExceeded,
@@ -125,14 +220,30 @@ pub async fn recognize_song_from_signature(
         )));
     }
 
-    Ok(serde_json::from_slice(&response[..])?)
+    if is_captive_portal_response(&message, &url) {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            gettext("The network requires a login").as_str(),
+        )));
+    }
+
+    if message.status_code() >= 500 {
+        return Err(gettext("Shazam answered with the HTTP status %d")
+            .replace("%d", &message.status_code().to_string())
+            .into());
+    }
+
+    let parse_start = Instant::now();
+    let json_object = serde_json::from_slice(&response[..])?;
+    metrics::observe_stage(Stage::Parse, parse_start.elapsed());
+    Ok(json_object)
 }
 
 pub async fn obtain_raw_cover_image(
     session: &soup::Session,
     url: &str,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
-    session.set_user_agent(USER_AGENTS.choose(&mut rand::rng()).unwrap());
+    session.set_user_agent(&user_agent());
 
     let message = soup::Message::new("GET", url)?;
     message.set_force_http1(true);
@@ -0,0 +1,224 @@
+//! The two loops of the signature generation run for every bin of every FFT
+//! (16000 / 128 times per second of audio): the Hanning windowing of the
+//! ring buffer and the computation of the magnitudes. On aarch64 (e.g. a
+//! Raspberry Pi running "songrec listen"), they use NEON instructions, which
+//! are always available there. The NEON versions give exactly the same
+//! results as the portable ones: they use no fused multiply-add, and the
+//! division by 2^17 is a multiplication by its exact inverse.
+//!
+//! The audio stays in f32 from the capture to the FFT. The peak thresholds
+//! of the algorithm assume 16-bit samples though, so `quantize` brings the
+//! samples to this scale and precision once, with a dither.
+
+use rustfft::num_complex::Complex;
+
+const MAGNITUDE_SCALE: f32 = 1.0 / (1 << 17) as f32;
+const MIN_MAGNITUDE: f32 = 0.0000000001;
+
+const I16_SCALE: f32 = 32768.0;
+
+#[cfg(test)]
+thread_local! {
+    /// Set by the tests to compare the dispatched versions with the
+    /// portable ones
+    static FORCE_PORTABLE: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Whether the NEON versions are used, on aarch64
+#[cfg(target_arch = "aarch64")]
+fn use_neon() -> bool {
+    #[cfg(test)]
+    if FORCE_PORTABLE.with(|force_portable| force_portable.get()) {
+        return false;
+    }
+    true
+}
+
+/// Scale samples from [-1.0, 1.0] to the 16-bit integer range and round
+/// them, with a triangular dither of one least significant bit so that
+/// quiet passages don't turn into distorted steps. The dither comes from a
+/// fixed-seed generator, so that the same audio always gives the same
+/// signature.
+pub fn quantize(samples: &[f32]) -> Vec<f32> {
+    let mut state: u32 = 0x9E37_79B9;
+    let mut next_uniform = move || {
+        // xorshift32
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as f32 / u32::MAX as f32
+    };
+
+    samples
+        .iter()
+        .map(|sample| {
+            let dither = next_uniform() - next_uniform();
+            (sample * I16_SCALE + dither)
+                .round()
+                .clamp(i16::MIN as f32, i16::MAX as f32)
+        })
+        .collect()
+}
+
+/// Write the samples of the ring buffer, oldest first (starting at `start`),
+/// multiplied by the window
+pub fn apply_window(
+    ring_buffer: &[f32; 2048],
+    start: usize,
+    window: &[f32; 2048],
+    output: &mut [f32; 2048],
+) {
+    let (latest, oldest) = ring_buffer.split_at(start);
+    let (output_oldest, output_latest) = output.split_at_mut(oldest.len());
+    let (window_oldest, window_latest) = window.split_at(oldest.len());
+
+    multiply_samples(oldest, window_oldest, output_oldest);
+    multiply_samples(latest, window_latest, output_latest);
+}
+
+/// Squared magnitudes of the FFT bins, scaled and floored above zero so
+/// that their logarithm can be taken
+pub fn compute_magnitudes(fft_output: &[Complex<f32>], output: &mut [f32]) {
+    #[cfg(target_arch = "aarch64")]
+    if use_neon() {
+        let vectorized_len = fft_output.len().min(output.len()) / 4 * 4;
+        // SAFETY: NEON is part of the aarch64 baseline, and the slices
+        // hold at least vectorized_len items
+        unsafe {
+            neon::compute_magnitudes(&fft_output[..vectorized_len], &mut output[..vectorized_len]);
+        }
+        compute_magnitudes_portable(&fft_output[vectorized_len..], &mut output[vectorized_len..]);
+        return;
+    }
+
+    compute_magnitudes_portable(fft_output, output);
+}
+
+fn multiply_samples(samples: &[f32], window: &[f32], output: &mut [f32]) {
+    #[cfg(target_arch = "aarch64")]
+    if use_neon() {
+        let vectorized_len = samples.len() / 4 * 4;
+        // SAFETY: see above, the three slices have the same length
+        unsafe {
+            neon::multiply_samples(
+                &samples[..vectorized_len],
+                &window[..vectorized_len],
+                &mut output[..vectorized_len],
+            );
+        }
+        multiply_samples_portable(
+            &samples[vectorized_len..],
+            &window[vectorized_len..],
+            &mut output[vectorized_len..],
+        );
+        return;
+    }
+
+    multiply_samples_portable(samples, window, output);
+}
+
+fn multiply_samples_portable(samples: &[f32], window: &[f32], output: &mut [f32]) {
+    for ((result, sample), multiplier) in output.iter_mut().zip(samples).zip(window) {
+        *result = sample * multiplier;
+    }
+}
+
+fn compute_magnitudes_portable(fft_output: &[Complex<f32>], output: &mut [f32]) {
+    for (result, complex) in output.iter_mut().zip(fft_output) {
+        *result = ((complex.re * complex.re + complex.im * complex.im) * MAGNITUDE_SCALE)
+            .max(MIN_MAGNITUDE);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use rustfft::num_complex::Complex;
+    use std::arch::aarch64::*;
+
+    use super::{MAGNITUDE_SCALE, MIN_MAGNITUDE};
+
+    /// The lengths must be equal and multiples of 4
+    pub unsafe fn multiply_samples(samples: &[f32], window: &[f32], output: &mut [f32]) {
+        for index in (0..samples.len()).step_by(4) {
+            let floats = vld1q_f32(samples.as_ptr().add(index));
+            let multipliers = vld1q_f32(window.as_ptr().add(index));
+            vst1q_f32(
+                output.as_mut_ptr().add(index),
+                vmulq_f32(floats, multipliers),
+            );
+        }
+    }
+
+    /// The lengths must be equal and multiples of 4
+    pub unsafe fn compute_magnitudes(fft_output: &[Complex<f32>], output: &mut [f32]) {
+        let scale = vdupq_n_f32(MAGNITUDE_SCALE);
+        let floor = vdupq_n_f32(MIN_MAGNITUDE);
+
+        for index in (0..fft_output.len()).step_by(4) {
+            // Complex<f32> is #[repr(C)], so this loads four real parts
+            // and four imaginary parts
+            let complexes = vld2q_f32(fft_output.as_ptr().add(index) as *const f32);
+            let squared_norms = vaddq_f32(
+                vmulq_f32(complexes.0, complexes.0),
+                vmulq_f32(complexes.1, complexes.1),
+            );
+            let magnitudes = vmaxq_f32(vmulq_f32(squared_norms, scale), floor);
+            vst1q_f32(output.as_mut_ptr().add(index), magnitudes);
+        }
+    }
+}
+
+#[test]
+fn test_dsp_matches_portable_implementation() {
+    let mut ring_buffer = [0f32; 2048];
+    for (index, sample) in ring_buffer.iter_mut().enumerate() {
+        *sample = ((index as i32 * 7919) % 65536 - 32768) as f32;
+    }
+    let mut window = [0f32; 2048];
+    for (index, multiplier) in window.iter_mut().enumerate() {
+        *multiplier = (index as f32 / 2047.0 * std::f32::consts::PI).sin();
+    }
+
+    let fft_output: Vec<Complex<f32>> = (0..1025)
+        .map(|index| Complex::new(index as f32 * 3.5 - 1200.0, (index as f32).sqrt()))
+        .collect();
+
+    // Through the NEON versions on aarch64, then the portable ones. The
+    // start and the length aren't multiples of 4, so that the remainders
+    // go through the portable versions in both cases.
+    let run = |force_portable: bool| {
+        FORCE_PORTABLE.with(|force| force.set(force_portable));
+        let mut output = [0f32; 2048];
+        apply_window(&ring_buffer, 383, &window, &mut output);
+        let mut magnitudes = vec![0f32; 1025];
+        compute_magnitudes(&fft_output, &mut magnitudes);
+        FORCE_PORTABLE.with(|force| force.set(false));
+        (output, magnitudes)
+    };
+    let (output, magnitudes) = run(false);
+    let (expected_output, expected_magnitudes) = run(true);
+    assert_eq!(output, expected_output);
+    assert_eq!(magnitudes, expected_magnitudes);
+
+    for (index, result) in expected_output.iter().enumerate() {
+        assert_eq!(*result, ring_buffer[(index + 383) & 2047] * window[index]);
+    }
+    let magnitude = (2300.0f32 * 2300.0 + 1000.0) * MAGNITUDE_SCALE;
+    assert!((expected_magnitudes[1000] - magnitude).abs() <= magnitude * 1e-6);
+    assert!(magnitudes
+        .iter()
+        .all(|magnitude| *magnitude >= MIN_MAGNITUDE));
+}
+
+#[test]
+fn test_quantize() {
+    let samples = [0.0, 0.5, -0.5, 1.0, -1.0, 2.0, 0.25 / 32768.0];
+    let quantized = quantize(&samples);
+    assert_eq!(quantized, quantize(&samples));
+    for (sample, result) in samples.iter().zip(&quantized) {
+        assert_eq!(result.fract(), 0.0);
+        assert!((result - (sample * 32768.0).clamp(-32768.0, 32767.0)).abs() <= 1.5);
+    }
+    assert_eq!(quantized[3], 32767.0);
+    assert_eq!(quantized[5], 32767.0);
+}
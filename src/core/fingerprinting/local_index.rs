@@ -0,0 +1,495 @@
+//! An offline index of the fingerprints of the user's own audio files, used
+//! to compare files with each other or to find which file contains a given
+//! piece of audio, without contacting Shazam.
+//!
+//! Files are described by "landmarks": pairs of nearby frequency peaks, as
+//! produced by the signature generator, hashed together with their time
+//! difference. Two pieces of audio sharing the same sound will share many
+//! landmark hashes at a constant time offset.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::core::fingerprinting::algorithm::SignatureGenerator;
+use crate::core::fingerprinting::signature_cache::{cached_signature, SignatureSpan};
+use crate::core::fingerprinting::signature_format::DecodedSignature;
+
+const INDEX_MAGIC: &[u8; 4] = b"SRIX";
+const INDEX_VERSION: u32 = 1;
+
+/// Each peak is paired with this many following peaks
+const LANDMARK_FAN_OUT: usize = 5;
+/// Maximal distance between two paired peaks, in FFT passes (128 samples at
+/// 16 KHz, that is 8 ms)
+const LANDMARK_MAX_DELTA: u32 = 63;
+
+/// Durations are expressed in FFT passes in the index
+pub const FFT_PASSES_PER_SECOND: f32 = 16000.0 / 128.0;
+
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "ogg", "oga", "opus", "wav", "aif", "aiff", "m4a", "mp4", "aac", "wma", "mka",
+    "webm",
+];
+
+#[derive(Clone, Copy)]
+pub struct Landmark {
+    pub hash: u32,
+    pub fft_pass_number: u32,
+}
+
+pub struct IndexedFile {
+    pub path: PathBuf,
+    pub file_size: u64,
+    pub modified_secs: u64,
+    pub number_samples: u32,
+    pub landmarks: Vec<Landmark>,
+}
+
+impl IndexedFile {
+    pub fn duration_secs(&self) -> f32 {
+        self.number_samples as f32 / 16000.0
+    }
+}
+
+pub struct IndexMatch {
+    /// Position of the file within `LocalIndex::files`
+    pub file_index: usize,
+    /// Position of the start of the query within the indexed file, in FFT
+    /// passes (can be negative when the query starts before the file)
+    pub offset_fft_passes: i64,
+    /// Number of landmarks of the query found at this offset
+    pub matching_landmarks: usize,
+}
+
+impl IndexMatch {
+    pub fn offset_secs(&self) -> f32 {
+        self.offset_fft_passes as f32 / FFT_PASSES_PER_SECOND
+    }
+}
+
+#[derive(Default)]
+pub struct LocalIndex {
+    pub files: Vec<IndexedFile>,
+}
+
+pub fn landmarks_from_signature(signature: &DecodedSignature) -> Vec<Landmark> {
+    let mut peaks: Vec<(u32, u32)> = signature
+        .frequency_band_to_sound_peaks
+        .iter()
+        .flatten()
+        .map(|peak| {
+            (
+                peak.fft_pass_number,
+                // Drop the sub-bin precision and halve the resolution, so
+                // that re-encoded audio still yields the same hashes
+                (peak.corrected_peak_frequency_bin as u32 >> 7) & 0x1ff,
+            )
+        })
+        .collect();
+    peaks.sort_unstable();
+
+    let mut landmarks = vec![];
+    for (index, &(anchor_time, anchor_frequency)) in peaks.iter().enumerate() {
+        for &(target_time, target_frequency) in peaks[index + 1..]
+            .iter()
+            .filter(|(target_time, _)| *target_time > anchor_time)
+            .take_while(|(target_time, _)| *target_time - anchor_time <= LANDMARK_MAX_DELTA)
+            .take(LANDMARK_FAN_OUT)
+        {
+            landmarks.push(Landmark {
+                hash: (anchor_frequency << 15)
+                    | (target_frequency << 6)
+                    | (target_time - anchor_time),
+                fft_pass_number: anchor_time,
+            });
+        }
+    }
+    landmarks
+}
+
+pub fn landmarks_from_file(path: &Path) -> Result<(u32, Vec<Landmark>), Box<dyn Error>> {
+    let signature = cached_signature(path, SignatureSpan::Whole, || {
+        let samples = SignatureGenerator::decode_file_to_16khz_mono(
+            path.to_str().ok_or("Invalid file name")?,
+        )?;
+        Ok(SignatureGenerator::make_signature_from_buffer(&samples))
+    })?;
+    Ok((
+        signature.number_samples,
+        landmarks_from_signature(&signature),
+    ))
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+pub fn list_audio_files(directory: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = vec![];
+    let mut pending_directories = vec![directory.to_path_buf()];
+    while let Some(directory) = pending_directories.pop() {
+        for entry in std::fs::read_dir(&directory)?.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending_directories.push(path);
+            } else if is_audio_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn file_size_and_modification(path: &Path) -> Result<(u64, u64), Box<dyn Error>> {
+    let metadata = std::fs::metadata(path)?;
+    let modified_secs = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    Ok((metadata.len(), modified_secs))
+}
+
+impl LocalIndex {
+    /// Load the index from disk, or return an empty index when it does not
+    /// exist yet
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(LocalIndex::default());
+        }
+        let file = File::open(path)?;
+        // No count read from a corrupted index may exceed what it can hold
+        let index_size = file.metadata()?.len();
+        let checked_length = |count: u32, item_size: u64| -> Result<usize, Box<dyn Error>> {
+            if count as u64 * item_size > index_size {
+                return Err(format!("Corrupted fingerprint index at {}", path.display()).into());
+            }
+            Ok(count as usize)
+        };
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC || reader.read_u32::<LittleEndian>()? != INDEX_VERSION {
+            warn!(
+                "Ignoring incompatible fingerprint index at {}",
+                path.display()
+            );
+            return Ok(LocalIndex::default());
+        }
+
+        // The fixed fields of each file take 28 bytes, and each landmark 8
+        let number_files = checked_length(reader.read_u32::<LittleEndian>()?, 28)?;
+        let mut files = Vec::with_capacity(number_files);
+        for _ in 0..number_files {
+            let mut path_bytes = vec![0u8; checked_length(reader.read_u32::<LittleEndian>()?, 1)?];
+            reader.read_exact(&mut path_bytes)?;
+            let file_size = reader.read_u64::<LittleEndian>()?;
+            let modified_secs = reader.read_u64::<LittleEndian>()?;
+            let number_samples = reader.read_u32::<LittleEndian>()?;
+            let number_landmarks = checked_length(reader.read_u32::<LittleEndian>()?, 8)?;
+            let mut landmarks = Vec::with_capacity(number_landmarks);
+            for _ in 0..number_landmarks {
+                landmarks.push(Landmark {
+                    hash: reader.read_u32::<LittleEndian>()?,
+                    fft_pass_number: reader.read_u32::<LittleEndian>()?,
+                });
+            }
+            files.push(IndexedFile {
+                path: PathBuf::from(String::from_utf8(path_bytes)?),
+                file_size,
+                modified_secs,
+                number_samples,
+                landmarks,
+            });
+        }
+        Ok(LocalIndex { files })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let temporary_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u32::<LittleEndian>(INDEX_VERSION)?;
+        writer.write_u32::<LittleEndian>(self.files.len() as u32)?;
+        for file in &self.files {
+            let path_bytes = file.path.to_str().unwrap().as_bytes();
+            writer.write_u32::<LittleEndian>(path_bytes.len() as u32)?;
+            writer.write_all(path_bytes)?;
+            writer.write_u64::<LittleEndian>(file.file_size)?;
+            writer.write_u64::<LittleEndian>(file.modified_secs)?;
+            writer.write_u32::<LittleEndian>(file.number_samples)?;
+            writer.write_u32::<LittleEndian>(file.landmarks.len() as u32)?;
+            for landmark in &file.landmarks {
+                writer.write_u32::<LittleEndian>(landmark.hash)?;
+                writer.write_u32::<LittleEndian>(landmark.fft_pass_number)?;
+            }
+        }
+        writer.flush()?;
+        drop(writer);
+
+        std::fs::rename(temporary_path, path)?;
+        Ok(())
+    }
+
+    /// Fingerprint the new or modified audio files found under a directory,
+    /// and forget about the files which were removed from it. Returns the
+    /// list of the indexed files present in the directory.
+    pub fn update_directory(&mut self, directory: &Path) -> Result<Vec<usize>, Box<dyn Error>> {
+        let directory = directory.canonicalize()?;
+        let audio_files = list_audio_files(&directory)?;
+
+        self.files
+            .retain(|file| !file.path.starts_with(&directory) || file.path.exists());
+
+        let mut positions: HashMap<PathBuf, usize> = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| (file.path.clone(), index))
+            .collect();
+
+        let mut indexes = vec![];
+        for (position, path) in audio_files.iter().enumerate() {
+            if path.to_str().is_none() {
+                warn!("Skipping file with a non-UTF-8 name: {}", path.display());
+                continue;
+            }
+            // E.g. removed or made unreadable during the scan
+            let (file_size, modified_secs) = match file_size_and_modification(path) {
+                Ok(result) => result,
+                Err(error) => {
+                    warn!("Skipping {}: {}", path.display(), error);
+                    continue;
+                }
+            };
+
+            let existing = positions.get(path).copied();
+            if let Some(index) = existing {
+                let file = &self.files[index];
+                if file.file_size == file_size && file.modified_secs == modified_secs {
+                    indexes.push(index);
+                    continue;
+                }
+            }
+
+            info!(
+                "[{}/{}] Fingerprinting {}",
+                position + 1,
+                audio_files.len(),
+                path.display()
+            );
+            let (number_samples, landmarks) = match landmarks_from_file(path) {
+                Ok(result) => result,
+                Err(error) => {
+                    warn!("Could not decode {}: {}", path.display(), error);
+                    continue;
+                }
+            };
+            let indexed_file = IndexedFile {
+                path: path.clone(),
+                file_size,
+                modified_secs,
+                number_samples,
+                landmarks,
+            };
+            match existing {
+                Some(index) => {
+                    self.files[index] = indexed_file;
+                    indexes.push(index);
+                }
+                None => {
+                    self.files.push(indexed_file);
+                    positions.insert(path.clone(), self.files.len() - 1);
+                    indexes.push(self.files.len() - 1);
+                }
+            }
+        }
+        Ok(indexes)
+    }
+
+    /// Find the indexed files (among `candidates`, when provided) sharing
+    /// landmarks with the query at a consistent time offset, best matches
+    /// first.
+    pub fn search(
+        &self,
+        query: &[Landmark],
+        candidates: Option<&[usize]>,
+        min_matching_landmarks: usize,
+    ) -> Vec<IndexMatch> {
+        let mut query_hashes: HashMap<u32, Vec<u32>> = HashMap::new();
+        for landmark in query {
+            query_hashes
+                .entry(landmark.hash)
+                .or_default()
+                .push(landmark.fft_pass_number);
+        }
+
+        let all_files: Vec<usize>;
+        let candidates = match candidates {
+            Some(candidates) => candidates,
+            None => {
+                all_files = (0..self.files.len()).collect();
+                &all_files
+            }
+        };
+
+        let mut matches = vec![];
+        for &file_index in candidates {
+            let mut offset_votes: HashMap<i64, usize> = HashMap::new();
+            for landmark in &self.files[file_index].landmarks {
+                if let Some(query_times) = query_hashes.get(&landmark.hash) {
+                    for &query_time in query_times {
+                        *offset_votes
+                            .entry(landmark.fft_pass_number as i64 - query_time as i64)
+                            .or_default() += 1;
+                    }
+                }
+            }
+
+            // Tolerate a jitter of one FFT pass around the best offset
+            if let Some((&offset, _)) = offset_votes.iter().max_by_key(|(_, votes)| **votes) {
+                let matching_landmarks: usize = (offset - 1..=offset + 1)
+                    .filter_map(|offset| offset_votes.get(&offset))
+                    .sum();
+                if matching_landmarks >= min_matching_landmarks {
+                    matches.push(IndexMatch {
+                        file_index,
+                        offset_fft_passes: offset,
+                        matching_landmarks,
+                    });
+                }
+            }
+        }
+        matches.sort_by_key(|found| std::cmp::Reverse(found.matching_landmarks));
+        matches
+    }
+}
+
+pub struct DuplicateGroup {
+    /// Positions within `LocalIndex::files`, the file to keep coming first
+    pub files: Vec<usize>,
+    /// Lowest similarity ratio between the kept file and the other ones
+    pub similarity: f32,
+}
+
+const LOSSLESS_EXTENSIONS: &[&str] = &["flac", "wav", "aif", "aiff"];
+
+impl IndexedFile {
+    /// Rank used to decide which copy of a song is worth keeping: lossless
+    /// files first, then the highest bitrate
+    fn keep_rank(&self) -> (bool, u64) {
+        let is_lossless = self
+            .path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| LOSSLESS_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+            .unwrap_or(false);
+        let bytes_per_second = self.file_size / (self.duration_secs().max(1.0) as u64);
+        (is_lossless, bytes_per_second)
+    }
+}
+
+impl LocalIndex {
+    /// Group together the files (among `candidates`) which contain the
+    /// same audio, with a similar duration. `min_similarity` is the ratio
+    /// of landmarks which have to match, between 0 and 1.
+    pub fn find_duplicates(
+        &self,
+        candidates: &[usize],
+        min_similarity: f32,
+    ) -> Vec<DuplicateGroup> {
+        let mut group_of: HashMap<usize, usize> = HashMap::new();
+        let mut groups: Vec<DuplicateGroup> = vec![];
+
+        for (position, &file_index) in candidates.iter().enumerate() {
+            if group_of.contains_key(&file_index) {
+                continue;
+            }
+            let file = &self.files[file_index];
+            if file.landmarks.is_empty() {
+                continue;
+            }
+
+            let other_files: Vec<usize> = candidates[position + 1..]
+                .iter()
+                .copied()
+                .filter(|other_index| !group_of.contains_key(other_index))
+                .filter(|&other_index| {
+                    let other_duration = self.files[other_index].duration_secs();
+                    let ratio = file.duration_secs().min(other_duration)
+                        / file.duration_secs().max(other_duration).max(f32::EPSILON);
+                    ratio >= 0.9
+                })
+                .collect();
+
+            let mut members = vec![file_index];
+            let mut similarity: f32 = 1.0;
+            for found in self.search(&file.landmarks, Some(&other_files), 1) {
+                let other_landmarks = self.files[found.file_index].landmarks.len();
+                let ratio = found.matching_landmarks as f32
+                    / file.landmarks.len().min(other_landmarks).max(1) as f32;
+                if ratio >= min_similarity {
+                    members.push(found.file_index);
+                    similarity = similarity.min(ratio.min(1.0));
+                }
+            }
+
+            if members.len() > 1 {
+                members.sort_by_key(|&index| std::cmp::Reverse(self.files[index].keep_rank()));
+                for &member in &members {
+                    group_of.insert(member, groups.len());
+                }
+                groups.push(DuplicateGroup {
+                    files: members,
+                    similarity,
+                });
+            }
+        }
+        groups
+    }
+}
+
+#[test]
+fn test_search_finds_offset() {
+    let file_landmarks: Vec<Landmark> = (0..200)
+        .map(|position| Landmark {
+            hash: position * 7919 % 65536,
+            fft_pass_number: position * 3,
+        })
+        .collect();
+    let index = LocalIndex {
+        files: vec![IndexedFile {
+            path: PathBuf::from("song.flac"),
+            file_size: 0,
+            modified_secs: 0,
+            number_samples: 0,
+            landmarks: file_landmarks.clone(),
+        }],
+    };
+
+    // A clip starting 150 FFT passes after the start of the file
+    let query: Vec<Landmark> = file_landmarks[50..100]
+        .iter()
+        .map(|landmark| Landmark {
+            hash: landmark.hash,
+            fft_pass_number: landmark.fft_pass_number - 150,
+        })
+        .collect();
+
+    let matches = index.search(&query, None, 10);
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].offset_fft_passes, 150);
+    assert_eq!(matches[0].matching_landmarks, 50);
+}
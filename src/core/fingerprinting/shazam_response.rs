@@ -0,0 +1,272 @@
+//! Lenient reading of the responses of the Shazam API, which isn't
+//! documented and changes from time to time. The fields which disappear or
+//! change their type are read as missing, so that a recognition only fails
+//! when the track itself can't be identified, and the structures that this
+//! module doesn't know are captured and logged once, to notice the changes
+//! early.
+
+use log::warn;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+
+/// The fields of the responses which are known but unused
+const KNOWN_RESPONSE_FIELDS: &[&str] = &[
+    "matches",
+    "location",
+    "timestamp",
+    "timezone",
+    "tagid",
+    "retryms",
+];
+const KNOWN_TRACK_FIELDS: &[&str] = &[
+    "layout",
+    "type",
+    "share",
+    "hub",
+    "url",
+    "artists",
+    "isrc",
+    "upc",
+    "urlparams",
+    "myshazam",
+    "albumadamid",
+    "highlightsurls",
+    "relatedtracksurl",
+    "trackadamid",
+];
+
+/// A missing value when the field doesn't have the expected type
+fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).unwrap_or_default())
+}
+
+/// Only the elements having the expected type
+fn lenient_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Array(values) => values
+            .into_iter()
+            .filter_map(|value| serde_json::from_value(value).ok())
+            .collect(),
+        _ => vec![],
+    })
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ShazamResponse {
+    #[serde(deserialize_with = "lenient")]
+    pub track: Option<ShazamTrack>,
+    #[serde(flatten)]
+    other_fields: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ShazamTrack {
+    #[serde(deserialize_with = "lenient")]
+    pub key: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub title: Option<String>,
+    /// The artist
+    #[serde(deserialize_with = "lenient")]
+    pub subtitle: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub images: TrackImages,
+    #[serde(deserialize_with = "lenient")]
+    pub genres: TrackGenres,
+    #[serde(deserialize_with = "lenient_vec")]
+    pub sections: Vec<TrackSection>,
+    #[serde(flatten)]
+    other_fields: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TrackImages {
+    #[serde(deserialize_with = "lenient")]
+    pub coverart: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TrackGenres {
+    #[serde(deserialize_with = "lenient")]
+    pub primary: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TrackSection {
+    #[serde(rename = "type", deserialize_with = "lenient")]
+    pub section_type: Option<String>,
+    #[serde(deserialize_with = "lenient_vec")]
+    pub metadata: Vec<SectionMetadatum>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SectionMetadatum {
+    #[serde(deserialize_with = "lenient")]
+    pub title: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub text: Option<String>,
+}
+
+impl ShazamTrack {
+    /// A field of the "SONG" section, such as "Album" or "Released"
+    pub fn song_metadatum(&self, title: &str) -> Option<String> {
+        self.sections
+            .iter()
+            .find(|section| section.section_type.as_deref() == Some("SONG"))?
+            .metadata
+            .iter()
+            .find(|metadatum| metadatum.title.as_deref() == Some(title))?
+            .text
+            .clone()
+    }
+}
+
+/// Whether the value was read as missing although it is present
+fn is_dropped(json: &Value, parsed: bool) -> bool {
+    !parsed && !json.is_null()
+}
+
+/// The structures of the response which weren't recognized
+fn drift(response: &ShazamResponse, json: &Value) -> Vec<String> {
+    let mut notes: Vec<String> = response
+        .other_fields
+        .keys()
+        .filter(|field| !KNOWN_RESPONSE_FIELDS.contains(&field.as_str()))
+        .map(|field| format!("unknown field \"{}\"", field))
+        .collect();
+    let Some(ref track) = response.track else {
+        if is_dropped(&json["track"], false) {
+            notes.push("\"track\" isn't an object".to_string());
+        }
+        return notes;
+    };
+    notes.extend(
+        track
+            .other_fields
+            .keys()
+            .filter(|field| !KNOWN_TRACK_FIELDS.contains(&field.as_str()))
+            .map(|field| format!("unknown field \"track.{}\"", field)),
+    );
+    let json = &json["track"];
+    for path in ["images", "genres"] {
+        if is_dropped(&json[path], json[path].is_object()) {
+            notes.push(format!("unexpected type of \"track.{}\"", path));
+        }
+    }
+    for (path, value, parsed) in [
+        ("key", &json["key"], track.key.is_some()),
+        ("title", &json["title"], track.title.is_some()),
+        ("subtitle", &json["subtitle"], track.subtitle.is_some()),
+        (
+            "images.coverart",
+            &json["images"]["coverart"],
+            track.images.coverart.is_some(),
+        ),
+        (
+            "genres.primary",
+            &json["genres"]["primary"],
+            track.genres.primary.is_some(),
+        ),
+    ] {
+        if is_dropped(value, parsed) {
+            notes.push(format!("unexpected type of \"track.{}\"", path));
+        }
+    }
+    let sections = json["sections"].as_array().map_or(0, Vec::len);
+    if sections != track.sections.len() {
+        notes.push("unexpected type of \"track.sections\"".to_string());
+    }
+    notes
+}
+
+/// The structures which were already logged, so that each change of the
+/// API is only reported once per run
+static REPORTED_DRIFT: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Read a response of Shazam, logging the structures which weren't
+/// recognized
+pub fn parse_response(json: &Value) -> ShazamResponse {
+    let response: ShazamResponse = serde_json::from_value(json.clone()).unwrap_or_default();
+    let notes: Vec<String> = {
+        let mut reported = REPORTED_DRIFT.lock().unwrap();
+        drift(&response, json)
+            .into_iter()
+            .filter(|note| reported.insert(note.clone()))
+            .collect()
+    };
+    if !notes.is_empty() {
+        warn!(
+            "The response of Shazam changed, some of its data may be missing: {}",
+            notes.join(", ")
+        );
+    }
+    response
+}
+
+#[test]
+fn test_schema_drift() {
+    let json = serde_json::json!({
+        "matches": [],
+        "track": {
+            "key": "12345",
+            "title": "Get Lucky",
+            "subtitle": ["Daft Punk"],
+            "images": "https://example.com/cover.jpg",
+            "sections": [
+                "lyrics",
+                {
+                    "type": "SONG",
+                    "metadata": [
+                        {"title": "Album", "text": "Random Access Memories"},
+                        {"title": "Released", "text": 2013}
+                    ]
+                }
+            ],
+            "mood": "happy"
+        },
+        "recommendations": {}
+    });
+    let response: ShazamResponse = serde_json::from_value(json.clone()).unwrap();
+    let track = response.track.as_ref().unwrap();
+    assert_eq!(track.key.as_deref(), Some("12345"));
+    assert_eq!(track.subtitle, None);
+    assert_eq!(track.images.coverart, None);
+    assert_eq!(
+        track.song_metadatum("Album").as_deref(),
+        Some("Random Access Memories")
+    );
+    assert_eq!(track.song_metadatum("Released"), None);
+    assert_eq!(
+        drift(&response, &json),
+        vec![
+            "unknown field \"recommendations\"",
+            "unknown field \"track.mood\"",
+            "unexpected type of \"track.images\"",
+            "unexpected type of \"track.subtitle\"",
+            "unexpected type of \"track.sections\"",
+        ]
+    );
+
+    let no_match = serde_json::json!({"matches": [], "tagid": "abc"});
+    assert!(parse_response(&no_match).track.is_none());
+    assert!(drift(&ShazamResponse::default(), &no_match).is_empty());
+}
@@ -0,0 +1,258 @@
+//! Cache of the signatures computed from audio files, in the "signatures"
+//! folder of the cache directory, so that running again over the same files
+//! (to compare their tags, to try another provider, to index them) skips
+//! decoding them and their FFT. The signatures are keyed by the SHA-256 of
+//! the contents of the files, which is much faster to compute than the
+//! signature, so that renamed or moved files are still found and modified
+//! ones aren't, and by the memory limit, which may truncate the decoded
+//! audio of long files. The hash of each file is itself kept under its path,
+//! size and modification time, so that files which didn't change aren't
+//! read again.
+
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::core::fingerprinting::signature_format::DecodedSignature;
+use crate::core::resource_limits;
+use crate::utils::filesystem_operations::{is_incognito, obtain_cache_directory, write_atomically};
+
+/// Bumped when the computation of the signatures changes
+const CACHE_VERSION: u32 = 1;
+
+/// The least recently used signatures are removed beyond this number
+const MAX_ENTRIES: usize = 50000;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+static PRUNE: Once = Once::new();
+
+pub fn set_signature_cache(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// The part of the file that a signature covers
+#[derive(Clone, Copy)]
+pub enum SignatureSpan {
+    /// The 12 seconds sent to the providers
+    Excerpt,
+    /// The whole file, for the local index
+    Whole,
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn content_hash(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    Ok(hex_digest(hasher))
+}
+
+/// The content hash of the file, read from the "paths" folder of the cache
+/// when the file has the same path, size and modification time as when it
+/// was last hashed
+fn file_hash(paths_directory: &Path, path: &Path) -> Result<String, Box<dyn Error>> {
+    let metadata = std::fs::metadata(path)?;
+    let mut hasher = Sha256::new();
+    hasher.update(path.canonicalize()?.to_string_lossy().as_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(
+        metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos()
+            .to_le_bytes(),
+    );
+    let link_path = paths_directory.join(format!("{}.txt", hex_digest(hasher)));
+    if let Ok(hash) = std::fs::read_to_string(&link_path) {
+        if hash.len() == 64 {
+            touch(&link_path).ok();
+            return Ok(hash);
+        }
+    }
+    let hash = content_hash(path)?;
+    if let Err(error) = write_atomically(&link_path, hash.as_bytes()) {
+        warn!("Could not cache the hash of {}: {}", path.display(), error);
+    }
+    Ok(hash)
+}
+
+fn obtain_signatures_directory() -> Result<PathBuf, Box<dyn Error>> {
+    let mut directory = obtain_cache_directory()?;
+    directory.push("signatures");
+    if !directory.join("paths").exists() {
+        std::fs::create_dir_all(directory.join("paths"))?;
+    }
+    Ok(directory)
+}
+
+fn entry_name(hash: &str, span: SignatureSpan, max_samples: Option<usize>) -> String {
+    format!(
+        "{}-{}{}-v{}.bin",
+        hash,
+        match span {
+            SignatureSpan::Excerpt => "excerpt",
+            SignatureSpan::Whole => "whole",
+        },
+        match max_samples {
+            Some(max_samples) => format!("-max{}", max_samples),
+            None => String::new(),
+        },
+        CACHE_VERSION
+    )
+}
+
+/// Mark a signature as used, its modification time being the time of its
+/// last use
+fn touch(entry_path: &Path) -> std::io::Result<()> {
+    File::options()
+        .write(true)
+        .open(entry_path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Remove the least recently used signatures beyond MAX_ENTRIES
+fn prune(directory: &Path) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            metadata
+                .is_file()
+                .then_some((metadata.modified().ok()?, entry.path()))
+        })
+        .collect();
+    if entries.len() <= MAX_ENTRIES {
+        return;
+    }
+    entries.sort();
+    for (_, path) in &entries[..entries.len() - MAX_ENTRIES] {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+/// The signature of the file from the cache, or computed and stored
+pub fn cached_signature(
+    path: &Path,
+    span: SignatureSpan,
+    compute: impl FnOnce() -> Result<DecodedSignature, Box<dyn Error>>,
+) -> Result<DecodedSignature, Box<dyn Error>> {
+    // Nothing about the files is written in incognito mode
+    if !ENABLED.load(Ordering::SeqCst) || is_incognito() {
+        return compute();
+    }
+    let Ok(directory) = obtain_signatures_directory() else {
+        return compute();
+    };
+    let paths_directory = directory.join("paths");
+    let Ok(hash) = file_hash(&paths_directory, path) else {
+        return compute();
+    };
+    let entry_path = directory.join(entry_name(
+        &hash,
+        span,
+        resource_limits::max_decoded_samples(),
+    ));
+    if let Ok(data) = std::fs::read(&entry_path) {
+        match DecodedSignature::decode_from_binary(&data) {
+            Ok(signature) => {
+                debug!("Using the cached signature of {}", path.display());
+                touch(&entry_path).ok();
+                return Ok(signature);
+            }
+            Err(error) => warn!(
+                "Ignoring the invalid cached signature {}: {}",
+                entry_path.display(),
+                error
+            ),
+        }
+    }
+
+    let signature = compute()?;
+    if let Err(error) = signature
+        .encode_to_binary()
+        .and_then(|data| write_atomically(&entry_path, &data))
+    {
+        warn!(
+            "Could not cache the signature of {}: {}",
+            path.display(),
+            error
+        );
+    }
+    PRUNE.call_once(|| {
+        prune(&directory);
+        prune(&paths_directory);
+    });
+    Ok(signature)
+}
+
+#[test]
+fn test_entry_name() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), b"abc").unwrap();
+    let hash = content_hash(file.path()).unwrap();
+    assert_eq!(
+        hash,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_ne!(
+        entry_name(&hash, SignatureSpan::Excerpt, None),
+        entry_name(&hash, SignatureSpan::Whole, None)
+    );
+    assert_ne!(
+        entry_name(&hash, SignatureSpan::Whole, None),
+        entry_name(&hash, SignatureSpan::Whole, Some(1 << 20))
+    );
+}
+
+#[test]
+fn test_file_hash() {
+    let paths_directory = tempfile::tempdir().unwrap();
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), b"abc").unwrap();
+    let hash = file_hash(paths_directory.path(), file.path()).unwrap();
+    assert_eq!(hash, content_hash(file.path()).unwrap());
+
+    // The stored hash is used as long as the file looks the same
+    let link_path = std::fs::read_dir(paths_directory.path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let other_hash = "0".repeat(64);
+    std::fs::write(&link_path, &other_hash).unwrap();
+    assert_eq!(
+        file_hash(paths_directory.path(), file.path()).unwrap(),
+        other_hash
+    );
+
+    std::fs::write(file.path(), b"abcd").unwrap();
+    assert_eq!(
+        file_hash(paths_directory.path(), file.path()).unwrap(),
+        content_hash(file.path()).unwrap()
+    );
+}
@@ -7,6 +7,7 @@ use std::io::{Cursor, Seek, SeekFrom, Write};
 
 const DATA_URI_PREFIX: &str = "data:audio/vnd.shazam.sig;base64,";
 
+#[derive(Clone)]
 pub struct FrequencyPeak {
     pub fft_pass_number: u32,
     pub peak_magnitude: u16,
@@ -33,6 +34,7 @@ struct RawSignatureHeader {
     _fixed_value: u32, // Calculated as ((15 << 19) + 0x40000) - 0x7c0000 or 00 00 7c 00 - seems pretty constant, may be different in the "SigType.STREAMING" mode
 }
 
+#[derive(Clone)]
 pub struct DecodedSignature {
     pub sample_rate_hz: u32,
     pub number_samples: u32,
@@ -166,6 +168,47 @@ impl DecodedSignature {
         })
     }
 
+    /// Keep only the peaks found within the given time range, so that the
+    /// signature reveals no more of the recorded audio than needed. Peak
+    /// times are made relative to the start of the range.
+    pub fn trimmed(&self, start_secs: f32, max_duration_secs: Option<f32>) -> DecodedSignature {
+        let samples_per_fft_pass = 128.0;
+        let start_samples =
+            ((start_secs * self.sample_rate_hz as f32) as u32).min(self.number_samples);
+        let mut number_samples = self.number_samples - start_samples;
+        if let Some(max_duration_secs) = max_duration_secs {
+            number_samples =
+                number_samples.min((max_duration_secs * self.sample_rate_hz as f32) as u32);
+        }
+
+        let start_pass = (start_samples as f32 / samples_per_fft_pass) as u32;
+        let end_pass = start_pass + (number_samples as f32 / samples_per_fft_pass) as u32;
+
+        let mut frequency_band_to_sound_peaks: [Vec<FrequencyPeak>; 4] = Default::default();
+        for (trimmed_peaks, peaks) in frequency_band_to_sound_peaks
+            .iter_mut()
+            .zip(self.frequency_band_to_sound_peaks.iter())
+        {
+            trimmed_peaks.extend(
+                peaks
+                    .iter()
+                    .filter(|peak| {
+                        peak.fft_pass_number >= start_pass && peak.fft_pass_number < end_pass
+                    })
+                    .map(|peak| FrequencyPeak {
+                        fft_pass_number: peak.fft_pass_number - start_pass,
+                        ..peak.clone()
+                    }),
+            );
+        }
+
+        DecodedSignature {
+            sample_rate_hz: self.sample_rate_hz,
+            number_samples,
+            frequency_band_to_sound_peaks,
+        }
+    }
+
     pub fn decode_from_uri(uri: &str) -> Result<Self, Box<dyn Error>> {
         assert!(uri.starts_with(DATA_URI_PREFIX));
 
@@ -0,0 +1,133 @@
+//! Human-readable description of a decoded signature, used by the
+//! `fingerprint-info` subcommand for debugging or studying the format.
+
+use std::error::Error;
+use std::fmt::Write;
+
+use crate::core::fingerprinting::algorithm::SignatureGenerator;
+use crate::core::fingerprinting::signature_format::DecodedSignature;
+
+const BAND_NAMES: [&str; 4] = ["250-520 Hz", "520-1450 Hz", "1450-3500 Hz", "3500-5500 Hz"];
+
+const HEATMAP_WIDTH: usize = 64;
+const HEATMAP_SHADES: &[char] = &[' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+/// Peaks are computed every 128 samples
+const SAMPLES_PER_FFT_PASS: f32 = 128.0;
+
+/// Accept either a data URI, a file containing a data URI, a binary
+/// signature file, or an audio file to fingerprint.
+pub fn load_signature(input: &str) -> Result<DecodedSignature, Box<dyn Error>> {
+    if input.starts_with("data:") {
+        return DecodedSignature::decode_from_uri(input.trim());
+    }
+
+    let contents = std::fs::read(input)?;
+    if contents.starts_with(&0xcafe2580u32.to_le_bytes()) {
+        DecodedSignature::decode_from_binary(&contents)
+    } else if contents.starts_with(b"data:") {
+        DecodedSignature::decode_from_uri(std::str::from_utf8(&contents)?.trim())
+    } else {
+        SignatureGenerator::make_signature_from_file(input)
+    }
+}
+
+fn frequency_hz(corrected_peak_frequency_bin: u16) -> f32 {
+    corrected_peak_frequency_bin as f32 * (16000.0 / 2.0 / 1024.0 / 64.0)
+}
+
+pub fn describe_signature(signature: &DecodedSignature) -> String {
+    let mut output = String::new();
+
+    let duration_secs = signature.number_samples as f32 / signature.sample_rate_hz as f32;
+    let fft_passes_per_second = signature.sample_rate_hz as f32 / SAMPLES_PER_FFT_PASS;
+    let total_peaks: usize = signature
+        .frequency_band_to_sound_peaks
+        .iter()
+        .map(|peaks| peaks.len())
+        .sum();
+
+    writeln!(output, "Sample rate:  {} Hz", signature.sample_rate_hz).unwrap();
+    writeln!(output, "Samples:      {}", signature.number_samples).unwrap();
+    writeln!(output, "Duration:     {:.2} s", duration_secs).unwrap();
+    writeln!(
+        output,
+        "Peaks:        {} ({:.1}/s)",
+        total_peaks,
+        total_peaks as f32 / duration_secs.max(f32::EPSILON)
+    )
+    .unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(
+        output,
+        "{:<14} {:>6} {:>8} {:>17} {:>21}",
+        "Band", "Peaks", "Peaks/s", "Magnitude", "Frequency (Hz)"
+    )
+    .unwrap();
+    for (band_name, peaks) in BAND_NAMES
+        .iter()
+        .zip(signature.frequency_band_to_sound_peaks.iter())
+    {
+        if peaks.is_empty() {
+            writeln!(output, "{:<14} {:>6}", band_name, 0).unwrap();
+            continue;
+        }
+        let magnitudes = peaks.iter().map(|peak| peak.peak_magnitude as u32);
+        let min_magnitude = magnitudes.clone().min().unwrap();
+        let max_magnitude = magnitudes.clone().max().unwrap();
+        let mean_magnitude = magnitudes.sum::<u32>() / peaks.len() as u32;
+        let frequencies = peaks
+            .iter()
+            .map(|peak| frequency_hz(peak.corrected_peak_frequency_bin));
+        let min_frequency = frequencies.clone().fold(f32::MAX, f32::min);
+        let max_frequency = frequencies.fold(0.0, f32::max);
+
+        writeln!(
+            output,
+            "{:<14} {:>6} {:>8.1} {:>5}/{:>5}/{:>5} {:>10.0}-{:<10.0}",
+            band_name,
+            peaks.len(),
+            peaks.len() as f32 / duration_secs.max(f32::EPSILON),
+            min_magnitude,
+            mean_magnitude,
+            max_magnitude,
+            min_frequency,
+            max_frequency
+        )
+        .unwrap();
+    }
+    writeln!(output, "{:>47}", "(min/mean/max)").unwrap();
+
+    // Heatmap of the peak density, over time (columns) and bands (rows)
+
+    let total_fft_passes = (duration_secs * fft_passes_per_second).max(1.0);
+    let mut cells = [[0usize; HEATMAP_WIDTH]; 4];
+    for (band, peaks) in signature.frequency_band_to_sound_peaks.iter().enumerate() {
+        for peak in peaks {
+            let column =
+                (peak.fft_pass_number as f32 / total_fft_passes * HEATMAP_WIDTH as f32) as usize;
+            cells[band][column.min(HEATMAP_WIDTH - 1)] += 1;
+        }
+    }
+    let max_cell = cells.iter().flatten().copied().max().unwrap_or(0).max(1);
+
+    writeln!(output).unwrap();
+    for (band, row) in cells.iter().enumerate().rev() {
+        let shades: String = row
+            .iter()
+            .map(|&count| HEATMAP_SHADES[(count * (HEATMAP_SHADES.len() - 1)).div_ceil(max_cell)])
+            .collect();
+        writeln!(output, "{:>14} |{}|", BAND_NAMES[band], shades).unwrap();
+    }
+    writeln!(
+        output,
+        "{:>14}  0 s{:>width$}",
+        "",
+        format!("{:.1} s", duration_secs),
+        width = HEATMAP_WIDTH - 3
+    )
+    .unwrap();
+
+    output
+}
@@ -1,3 +1,6 @@
+use rand::prelude::IndexedRandom;
+use std::sync::{LazyLock, RwLock};
+
 // From https://github.com/SaswatPadhi/FlashProfileDemo/blob/c1e3f05d09f6443568a606dc0a439d6ebb057ae1/tests/hetero/user_agents.json
 
 pub const USER_AGENTS: [&str; 100] = [
@@ -102,3 +105,71 @@ pub const USER_AGENTS: [&str; 100] = [
     "Dalvik/1.6.0 (Linux; U; Android 4.2.2; SM-T217S Build/JDQ39)",
     "Dalvik/1.6.0 (Linux; U; Android 4.4.4; SAMSUNG-SM-N900A Build/KTU84P)",
 ];
+
+/// The client identity sent to Shazam, set with `--user-agent` or the
+/// "user_agent" preference, since the answers of the servers (rate limits,
+/// fields of the results) vary with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientIdentity {
+    /// A different user agent of the curated set for each request
+    Rotate,
+    /// One user agent of the curated set, drawn once for the session
+    Fixed,
+    /// The user agent of the curated set with this 1-based number
+    Pinned(usize),
+    Custom(String),
+}
+
+impl ClientIdentity {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "" | "rotate" => Ok(ClientIdentity::Rotate),
+            "fixed" => Ok(ClientIdentity::Fixed),
+            number if number.chars().all(|character| character.is_ascii_digit()) => {
+                match number.parse::<usize>() {
+                    Ok(index) if (1..=USER_AGENTS.len()).contains(&index) => {
+                        Ok(ClientIdentity::Pinned(index))
+                    }
+                    _ => Err(format!(
+                        "The user agent number must be between 1 and {}",
+                        USER_AGENTS.len()
+                    )),
+                }
+            }
+            custom => Ok(ClientIdentity::Custom(custom.to_string())),
+        }
+    }
+}
+
+static CLIENT_IDENTITY: RwLock<ClientIdentity> = RwLock::new(ClientIdentity::Rotate);
+
+static FIXED_USER_AGENT: LazyLock<&str> =
+    LazyLock::new(|| USER_AGENTS.choose(&mut rand::rng()).unwrap());
+
+pub fn set_client_identity(identity: ClientIdentity) {
+    *CLIENT_IDENTITY.write().unwrap() = identity;
+}
+
+/// The user agent of the next request to Shazam
+pub fn user_agent() -> String {
+    match *CLIENT_IDENTITY.read().unwrap() {
+        ClientIdentity::Rotate => USER_AGENTS.choose(&mut rand::rng()).unwrap().to_string(),
+        ClientIdentity::Fixed => FIXED_USER_AGENT.to_string(),
+        ClientIdentity::Pinned(index) => USER_AGENTS[index - 1].to_string(),
+        ClientIdentity::Custom(ref user_agent) => user_agent.clone(),
+    }
+}
+
+#[test]
+fn test_client_identity() {
+    assert_eq!(ClientIdentity::parse("rotate"), Ok(ClientIdentity::Rotate));
+    assert_eq!(ClientIdentity::parse("3"), Ok(ClientIdentity::Pinned(3)));
+    assert!(ClientIdentity::parse("0").is_err());
+    assert!(ClientIdentity::parse("101").is_err());
+    assert_eq!(
+        ClientIdentity::parse(" Dalvik/2.1.0 (Linux; U; Android 9)"),
+        Ok(ClientIdentity::Custom(
+            "Dalvik/2.1.0 (Linux; U; Android 9)".to_string()
+        ))
+    );
+}
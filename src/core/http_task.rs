@@ -1,132 +1,481 @@
 use gettextrs::gettext;
-use serde_json::Value;
+use log::{debug, error, info, warn};
 use soup::prelude::SessionExt;
 use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
 
+use crate::core::aliases;
+use crate::core::fingerprinting::local_index::LocalIndex;
+use crate::core::match_corrections;
+use crate::core::metadata_cache;
+use crate::core::metrics::{self, RecognitionOutcome};
+use crate::core::no_match_log;
+use crate::core::providers::{recognize_from_local_index, Provider, ProviderSettings};
+use crate::core::stream_monitor;
 use crate::core::thread_messages::*;
+use crate::core::unknown_clips;
+#[cfg(all(target_os = "linux", feature = "mpris"))]
+use crate::plugins::spotify_guard;
+use crate::utils::filesystem_operations::obtain_fingerprint_index_path;
+use crate::utils::track_identifiers::{isrc_from_response, upc_from_response};
 
 use crate::core::fingerprinting::communication::{
     obtain_raw_cover_image, recognize_song_from_signature,
 };
+use crate::core::fingerprinting::shazam_response;
 use crate::core::fingerprinting::signature_format::DecodedSignature;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+/// Options affecting the way we use the network
+#[derive(Clone, Debug, Default)]
+pub struct NetworkSettings {
+    /// Don't download anything which is not strictly needed for recognizing
+    /// songs, such as cover art
+    pub low_data: bool,
+    /// Only connect over this IP version
+    pub ip_version: Option<IpVersion>,
+    /// Send requests from this network interface (e.g. a VPN tunnel)
+    pub interface: Option<String>,
+    /// Server answering the recognition requests instead of Shazam (e.g. a
+    /// mock one in the tests)
+    pub shazam_endpoint: Option<String>,
+}
+
+impl NetworkSettings {
+    /// The address that outgoing connections should be bound to, if any.
+    /// Binding to the unspecified address of an IP version is enough to
+    /// prevent connecting over the other one.
+    pub fn local_address(&self) -> Result<Option<IpAddr>, Box<dyn Error>> {
+        let matches_version = |address: &IpAddr| match self.ip_version {
+            Some(IpVersion::V4) => address.is_ipv4(),
+            Some(IpVersion::V6) => address.is_ipv6(),
+            None => true,
+        };
+
+        match (&self.interface, self.ip_version) {
+            (Some(interface), _) => {
+                let mut addresses: Vec<IpAddr> = if_addrs::get_if_addrs()?
+                    .into_iter()
+                    .filter(|candidate| &candidate.name == interface)
+                    .map(|candidate| candidate.ip())
+                    .filter(matches_version)
+                    .collect();
+                // Link-local IPv6 addresses can't reach Shazam
+                addresses.sort_by_key(|address| match address {
+                    IpAddr::V4(_) => 0,
+                    IpAddr::V6(address) if address.segments()[0] & 0xffc0 != 0xfe80 => 1,
+                    IpAddr::V6(_) => 2,
+                });
+                match addresses.first() {
+                    Some(address) => Ok(Some(*address)),
+                    None => Err(gettext("No usable address on the network interface %s")
+                        .replace("%s", interface)
+                        .into()),
+                }
+            }
+            (None, Some(IpVersion::V4)) => Ok(Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED))),
+            (None, Some(IpVersion::V6)) => Ok(Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn new_session(&self) -> Result<soup::Session, Box<dyn Error>> {
+        // The default of libsoup
+        self.new_session_with_connections(2)
+    }
+
+    /// A session opening up to this number of connections to a host at the
+    /// same time, for sending several requests in parallel
+    pub fn new_session_with_connections(
+        &self,
+        max_connections: u32,
+    ) -> Result<soup::Session, Box<dyn Error>> {
+        // These are construct-only properties
+        let mut builder = glib::Object::builder::<soup::Session>()
+            .property("max-conns-per-host", max_connections as i32);
+        if let Some(address) = self.local_address()? {
+            info!("Sending requests from {}", address);
+            builder = builder.property(
+                "local-address",
+                soup::gio::InetSocketAddress::from(SocketAddr::new(address, 0)),
+            );
+        }
+        let session = builder.build();
+        session.set_timeout(20);
+        session.set_idle_timeout(2);
+        Ok(session)
+    }
+
+    /// Enable the low-data mode when the system (e.g. NetworkManager)
+    /// reports that the current connection is metered. The network monitor
+    /// of GIO is always there, as libsoup is built on it.
+    pub fn detect_metered_connection(&mut self) {
+        use soup::gio::prelude::NetworkMonitorExt;
+
+        if !self.low_data && soup::gio::NetworkMonitor::default().is_network_metered() {
+            info!(
+                "{}",
+                gettext("The network connection is metered, enabling the low-data mode")
+            );
+            self.low_data = true;
+        }
+    }
+
+    /// The minimal interval between two requests, in seconds, for the
+    /// command line and the GUI alike (see `set_min_request_interval`)
+    pub fn min_request_interval(&self) -> u64 {
+        if self.low_data {
+            LOW_DATA_MIN_REQUEST_INTERVAL
+        } else {
+            0
+        }
+    }
+}
+
+/// Minimal interval between two requests in low-data mode, in seconds
+const LOW_DATA_MIN_REQUEST_INTERVAL: u64 = 30;
+
+/// Number of consecutive name resolution failures after which we assume
+/// that the network is intercepting our requests, like captive portals do
+const DNS_FAILURES_BEFORE_LOGIN_STATE: u32 = 3;
+
+fn is_name_resolution_error(error: &(dyn Error + 'static)) -> bool {
+    error.downcast_ref::<glib::Error>().is_some_and(|error| {
+        error.matches(soup::gio::ResolverError::NotFound)
+            || error.matches(soup::gio::ResolverError::TemporaryFailure)
+    })
+}
+
+/// Whether the system (e.g. NetworkManager) detected a captive portal
+fn system_reports_captive_portal() -> bool {
+    use soup::gio::prelude::NetworkMonitorExt;
+
+    soup::gio::NetworkMonitor::default().connectivity() == soup::gio::NetworkConnectivity::Portal
+}
+
 async fn try_recognize_song(
     session: &soup::Session,
     signature: DecodedSignature,
+    network_settings: &NetworkSettings,
 ) -> Result<SongRecognizedMessage, Box<dyn Error>> {
-    let json_object = recognize_song_from_signature(session, &signature).await?;
-
-    let mut album_name: Option<String> = None;
-    let mut release_year: Option<String> = None;
-
-    // Sometimes the idea of trying to write functional poetry hurts
-
-    if let Value::Array(sections) = &json_object["track"]["sections"] {
-        for section in sections {
-            if let Value::String(string) = &section["type"] {
-                if string == "SONG" {
-                    if let Value::Array(metadata) = &section["metadata"] {
-                        for metadatum in metadata {
-                            if let Value::String(title) = &metadatum["title"] {
-                                if title == "Album" {
-                                    if let Value::String(text) = &metadatum["text"] {
-                                        album_name = Some(text.to_string());
-                                    }
-                                } else if title == "Released" {
-                                    if let Value::String(text) = &metadatum["text"] {
-                                        release_year = Some(text.to_string());
-                                    }
-                                }
-                            }
-                        }
-                        break;
-                    }
+    let request_start = Instant::now();
+    let json_object = recognize_song_from_signature(
+        session,
+        &signature,
+        network_settings.shazam_endpoint.as_deref(),
+    )
+    .await;
+    metrics::observe_api_latency(request_start.elapsed());
+    let json_object = json_object?;
+
+    // Only the track itself is required, the other fields may be missing
+    // when the API changes
+    let response = shazam_response::parse_response(&json_object);
+    let (Some(track), Some(track_key), Some(song_name)) = (
+        response.track.as_ref(),
+        response.track.as_ref().and_then(|track| track.key.clone()),
+        response
+            .track
+            .as_ref()
+            .and_then(|track| track.title.clone()),
+    ) else {
+        return Err(Box::new(std::io::Error::other(
+            gettext("No match for this song").as_str(),
+        )));
+    };
+
+    metadata_cache::store_track(&track_key, &json_object["track"]);
+
+    let cover_image = match track.images.coverart {
+        Some(ref url) => match metadata_cache::get_cover(&track_key) {
+            Some(cover) => Some(cover),
+            None if network_settings.low_data => None,
+            None => match obtain_raw_cover_image(session, url).await {
+                Ok(cover) => {
+                    metadata_cache::store_cover(&track_key, &cover);
+                    Some(cover)
                 }
-            }
+                Err(error) => {
+                    warn!("Could not download the cover of {}: {}", song_name, error);
+                    None
+                }
+            },
+        },
+        None => None,
+    };
+
+    Ok(SongRecognizedMessage {
+        artist_name: track
+            .subtitle
+            .clone()
+            .unwrap_or_else(|| gettext("Unknown artist")),
+        album_name: track.song_metadatum("Album"),
+        song_name,
+        cover_image,
+        cover_url: track.images.coverart.clone(),
+        track_key,
+        release_year: track.song_metadatum("Released"),
+        genre: track.genres.primary.clone(),
+        isrc: isrc_from_response(&json_object),
+        upc: upc_from_response(&json_object),
+        shazam_json: serde_json::to_string(&json_object).unwrap(),
+        source: None,
+    })
+}
+
+async fn recognize_with_provider(
+    session: soup::Session,
+    provider: Provider,
+    local_index: Option<Arc<LocalIndex>>,
+    signature: DecodedSignature,
+    network_settings: NetworkSettings,
+) -> Result<SongRecognizedMessage, Box<dyn Error>> {
+    match provider {
+        Provider::Shazam => try_recognize_song(&session, signature, &network_settings).await,
+        Provider::LocalIndex => {
+            let local_index = local_index.ok_or_else(|| gettext("No match for this song"))?;
+
+            // Searching the index is CPU-bound, don't block the main loop
+            let (result_tx, result_rx) = async_channel::bounded(1);
+            std::thread::spawn(move || {
+                let result = recognize_from_local_index(&local_index, &signature)
+                    .map_err(|error| error.to_string());
+                result_tx.send_blocking(result).ok();
+            });
+            Ok(result_rx.recv().await??)
         }
     }
+}
 
-    Ok(SongRecognizedMessage {
-        artist_name: match &json_object["track"]["subtitle"] {
-            Value::String(string) => string.to_string(),
-            _ => {
-                return Err(Box::new(std::io::Error::other(
-                    gettext("No match for this song").as_str(),
-                )))
-            }
-        },
-        album_name,
-        song_name: match &json_object["track"]["title"] {
-            Value::String(string) => string.to_string(),
-            _ => {
-                return Err(Box::new(std::io::Error::other(
-                    gettext("No match for this song").as_str(),
-                )))
+/// Query the configured providers, returning the first match. When no
+/// provider matched, the error of the preferred provider is returned.
+async fn recognize_with_providers(
+    session: &soup::Session,
+    settings: &ProviderSettings,
+    local_index: &Option<Arc<LocalIndex>>,
+    signature: DecodedSignature,
+    network_settings: &NetworkSettings,
+) -> Result<SongRecognizedMessage, Box<dyn Error>> {
+    let mut errors: Vec<(usize, Box<dyn Error>)> = vec![];
+
+    if settings.race && settings.providers.len() > 1 {
+        let (result_tx, result_rx) = async_channel::unbounded();
+        for (position, provider) in settings.providers.iter().enumerate() {
+            let request = recognize_with_provider(
+                session.clone(),
+                *provider,
+                local_index.clone(),
+                signature.clone(),
+                network_settings.clone(),
+            );
+            let result_tx = result_tx.clone();
+            glib::spawn_future_local(async move {
+                result_tx.send((position, request.await)).await.ok();
+            });
+        }
+        drop(result_tx);
+
+        // The slower providers are left to complete in the background
+        while let Ok((position, result)) = result_rx.recv().await {
+            match result {
+                Ok(song) => return Ok(song),
+                Err(error) => errors.push((position, error)),
             }
-        },
-        cover_image: match &json_object["track"]["images"]["coverart"] {
-            Value::String(string) => Some(obtain_raw_cover_image(session, string).await?),
-            _ => None,
-        },
-        track_key: match &json_object["track"]["key"] {
-            Value::String(string) => string.to_string(),
-            _ => {
-                return Err(Box::new(std::io::Error::other(
-                    gettext("No match for this song").as_str(),
-                )))
+        }
+    } else {
+        for (position, provider) in settings.providers.iter().enumerate() {
+            match recognize_with_provider(
+                session.clone(),
+                *provider,
+                local_index.clone(),
+                signature.clone(),
+                network_settings.clone(),
+            )
+            .await
+            {
+                Ok(song) => return Ok(song),
+                Err(error) => errors.push((position, error)),
             }
-        },
-        release_year,
-        genre: match &json_object["track"]["genres"]["primary"] {
-            Value::String(string) => Some(string.to_string()),
-            _ => None,
-        },
-        shazam_json: serde_json::to_string(&json_object).unwrap(),
-    })
+        }
+    }
+
+    errors.sort_by_key(|(position, _)| *position);
+    match errors.into_iter().next() {
+        Some((_, error)) => Err(error),
+        None => Err(gettext("No match for this song").into()),
+    }
 }
 
 pub async fn http_task(
     http_rx: async_channel::Receiver<HTTPMessage>,
     gui_tx: async_channel::Sender<GUIMessage>,
     microphone_tx: async_channel::Sender<MicrophoneMessage>,
+    provider_settings: ProviderSettings,
+    network_settings: NetworkSettings,
 ) {
-    let session = soup::Session::new();
-    session.set_timeout(20);
-    session.set_idle_timeout(2);
+    let session = match network_settings.new_session() {
+        Ok(session) => session,
+        Err(error) => {
+            // Don't fall back to the default interface, this may be what the
+            // user was trying to avoid
+            error!("Could not set up the network session: {}", error);
+            gui_tx
+                .try_send(GUIMessage::ErrorMessage(error.to_string()))
+                .unwrap();
+            return;
+        }
+    };
+
+    metadata_cache::prune();
+
+    let local_index = if provider_settings.uses(Provider::LocalIndex) {
+        match obtain_fingerprint_index_path().and_then(|path| LocalIndex::load(&path)) {
+            Ok(index) => {
+                info!(
+                    "Loaded the local fingerprint index ({} files)",
+                    index.files.len()
+                );
+                Some(Arc::new(index))
+            }
+            Err(error) => {
+                error!("Could not load the local fingerprint index: {}", error);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut consecutive_dns_failures: u32 = 0;
 
     while let Ok(message) = http_rx.recv().await {
         // XX USE SOUP3 CF. https://github.com/marin-m/SongRec/issues/223
         match message {
-            HTTPMessage::RecognizeSignature(signature) => {
-                match try_recognize_song(&session, *signature).await {
-                    Ok(recognized_song) => {
-                        gui_tx
-                            .try_send(GUIMessage::SongRecognized(Box::new(recognized_song)))
-                            .unwrap();
-                        gui_tx.try_send(GUIMessage::NetworkStatus(true)).unwrap();
-                        gui_tx.try_send(GUIMessage::RateLimitState(false)).unwrap();
-                    }
-                    Err(error) => match error.to_string().as_str() {
-                        a if a == gettext("No match for this song") => {
+            HTTPMessage::RecognizeSignature(signature, source, samples) => {
+                let stream_tag = match source {
+                    RecognitionSource::Stream(ref tag) => Some(tag.clone()),
+                    _ => None,
+                };
+                // The song played by the local Spotify client, which was
+                // already recognized
+                #[cfg(all(target_os = "linux", feature = "mpris"))]
+                let is_guarded = matches!(
+                    source,
+                    RecognitionSource::Microphone(_) | RecognitionSource::Monitor(_)
+                ) && spotify_guard::is_guarded_playback().await;
+                #[cfg(not(all(target_os = "linux", feature = "mpris")))]
+                let is_guarded = false;
+
+                if is_guarded {
+                    debug!("Not recognizing the audio played by Spotify again");
+                } else {
+                    match recognize_with_providers(
+                        &session,
+                        &provider_settings,
+                        &local_index,
+                        *signature,
+                        &network_settings,
+                    )
+                    .await
+                    {
+                        Ok(mut recognized_song) => {
+                            if let Some((device_name, high_rate)) =
+                                no_match_log::record_outcome(&source, true)
+                            {
+                                gui_tx
+                                    .try_send(GUIMessage::HighNoMatchRate(device_name, high_rate))
+                                    .unwrap();
+                            }
+                            recognized_song.source = Some(source);
+                            match_corrections::apply(&mut recognized_song);
+                            aliases::apply(&mut recognized_song);
+                            consecutive_dns_failures = 0;
+                            metrics::record_recognition(RecognitionOutcome::Match);
                             gui_tx
-                                .try_send(GUIMessage::ErrorMessage(error.to_string()))
+                                .try_send(GUIMessage::SongRecognized(Box::new(recognized_song)))
                                 .unwrap();
                             gui_tx.try_send(GUIMessage::NetworkStatus(true)).unwrap();
                             gui_tx.try_send(GUIMessage::RateLimitState(false)).unwrap();
+                            gui_tx
+                                .try_send(GUIMessage::NetworkRequiresLogin(false))
+                                .unwrap();
                         }
-                        a if a == gettext("Your IP has been rate-limited") => {
-                            gui_tx.try_send(GUIMessage::RateLimitState(true)).unwrap();
-                        }
-                        _ => {
-                            log::error!("Network reach error: {:?}", error);
-                            gui_tx.try_send(GUIMessage::NetworkStatus(false)).unwrap();
-                        }
-                    },
-                };
+                        Err(error) => match error.to_string().as_str() {
+                            a if a == gettext("No match for this song") => {
+                                consecutive_dns_failures = 0;
+                                metrics::record_recognition(RecognitionOutcome::NoMatch);
+                                if let Some(ref samples) = samples {
+                                    unknown_clips::keep_clip(samples, &source);
+                                    no_match_log::record_no_match(samples, &source);
+                                }
+                                if let Some((device_name, high_rate)) =
+                                    no_match_log::record_outcome(&source, false)
+                                {
+                                    gui_tx
+                                        .try_send(GUIMessage::HighNoMatchRate(
+                                            device_name,
+                                            high_rate,
+                                        ))
+                                        .unwrap();
+                                }
+                                gui_tx
+                                    .try_send(GUIMessage::ErrorMessage(error.to_string()))
+                                    .unwrap();
+                                gui_tx.try_send(GUIMessage::NetworkStatus(true)).unwrap();
+                                gui_tx.try_send(GUIMessage::RateLimitState(false)).unwrap();
+                                gui_tx
+                                    .try_send(GUIMessage::NetworkRequiresLogin(false))
+                                    .unwrap();
+                            }
+                            a if a == gettext("The network requires a login") => {
+                                metrics::record_recognition(RecognitionOutcome::NetworkError);
+                                gui_tx
+                                    .try_send(GUIMessage::NetworkRequiresLogin(true))
+                                    .unwrap();
+                            }
+                            a if a == gettext("Your IP has been rate-limited") => {
+                                metrics::record_recognition(RecognitionOutcome::RateLimited);
+                                gui_tx.try_send(GUIMessage::RateLimitState(true)).unwrap();
+                            }
+                            _ => {
+                                log::error!("Network reach error: {:?}", error);
+                                metrics::record_recognition(RecognitionOutcome::NetworkError);
+
+                                if is_name_resolution_error(error.as_ref()) {
+                                    consecutive_dns_failures += 1;
+                                } else {
+                                    consecutive_dns_failures = 0;
+                                }
+
+                                // Rather than reporting the same cryptic error over
+                                // and over, tell the user to sign in to the network
+                                if consecutive_dns_failures >= DNS_FAILURES_BEFORE_LOGIN_STATE
+                                    || system_reports_captive_portal()
+                                {
+                                    gui_tx
+                                        .try_send(GUIMessage::NetworkRequiresLogin(true))
+                                        .unwrap();
+                                } else {
+                                    gui_tx.try_send(GUIMessage::NetworkStatus(false)).unwrap();
+                                }
+                            }
+                        },
+                    };
+                }
 
-                microphone_tx
-                    .try_send(MicrophoneMessage::ProcessingDone)
-                    .unwrap();
+                match stream_tag {
+                    Some(tag) => stream_monitor::recognition_done(&tag),
+                    None => microphone_tx
+                        .try_send(MicrophoneMessage::ProcessingDone)
+                        .unwrap(),
+                }
             }
         }
     }
@@ -1,10 +1,16 @@
+use crate::core::crash_report::RecentLogWriter;
 #[cfg(feature = "gui")]
 use crate::core::thread_messages::GUIMessage;
+#[cfg(all(windows, feature = "windows-service"))]
+use crate::plugins::windows_service;
+use crate::utils::clock;
 use glib::{LogLevel, LogWriterOutput};
 use log::Level;
 use std::boxed::Box;
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 #[cfg(feature = "gui")]
@@ -49,13 +55,129 @@ impl Write for GUIDispatcher {
 #[cfg(feature = "gui")]
 unsafe impl std::marker::Send for GUIDispatcher {}
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl LogRotation {
+    fn period_key(&self) -> String {
+        match self {
+            LogRotation::Never => String::new(),
+            LogRotation::Hourly => clock::now().format("%Y%m%d%H").to_string(),
+            LogRotation::Daily => clock::now().format("%Y%m%d").to_string(),
+        }
+    }
+}
+
+/// Settings of the optional log file, independent of the standard error output
+pub struct LogFileOptions {
+    pub path: PathBuf,
+    pub level: log::LevelFilter,
+    pub max_size_bytes: Option<u64>,
+    pub rotation: LogRotation,
+    pub keep_files: usize,
+}
+
+/// A log file writer that renames "songrec.log" to "songrec.log.1" (and so
+/// on, up to the number of files to keep) when it grows too large or when
+/// a new hour/day begins, depending on the settings.
+struct RotatingFileWriter {
+    options: LogFileOptions,
+    file: File,
+    current_size: u64,
+    current_period: String,
+}
+
+impl RotatingFileWriter {
+    fn new(options: LogFileOptions) -> Result<Self, std::io::Error> {
+        let file = Self::open(&options.path)?;
+        let current_size = file.metadata()?.len();
+        let current_period = options.rotation.period_key();
+        Ok(Self {
+            options,
+            file,
+            current_size,
+            current_period,
+        })
+    }
+
+    fn open(path: &PathBuf) -> Result<File, std::io::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.options.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> Result<(), std::io::Error> {
+        self.file.flush()?;
+
+        if self.options.keep_files == 0 {
+            std::fs::remove_file(&self.options.path).ok();
+        } else {
+            std::fs::remove_file(self.rotated_path(self.options.keep_files)).ok();
+            for index in (1..self.options.keep_files).rev() {
+                std::fs::rename(self.rotated_path(index), self.rotated_path(index + 1)).ok();
+            }
+            std::fs::rename(&self.options.path, self.rotated_path(1))?;
+        }
+
+        self.file = Self::open(&self.options.path)?;
+        self.current_size = 0;
+        Ok(())
+    }
+
+    fn needs_rotation(&mut self, incoming: usize) -> bool {
+        let period = self.options.rotation.period_key();
+        if period != self.current_period {
+            self.current_period = period;
+            return self.current_size > 0;
+        }
+        match self.options.max_size_bytes {
+            Some(max_size) => {
+                self.current_size > 0 && self.current_size + incoming as u64 > max_size
+            }
+            None => false,
+        }
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        if self.needs_rotation(buf.len()) {
+            if let Err(error) = self.rotate() {
+                eprintln!("Could not rotate the log file: {:?}", error);
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.current_size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.file.flush()
+    }
+}
+
 pub struct Logging {
     #[cfg(feature = "gui")]
     gui_dispatcher: GUIDispatcher,
 }
 
 impl Logging {
-    pub fn setup_logging(glib_level: log::LevelFilter, songrec_level: log::LevelFilter) -> Self {
+    pub fn setup_logging(
+        glib_level: log::LevelFilter,
+        songrec_level: log::LevelFilter,
+        log_file: Option<LogFileOptions>,
+    ) -> Self {
         // TODO: Improve the format?
 
         let mut main_dispatch = fern::Dispatch::new().format(|out, message, record| {
@@ -81,6 +203,61 @@ impl Logging {
 
         main_dispatch = main_dispatch.chain(stderr_dispatch);
 
+        // Keep the last lines in memory for crash reports
+        let recent_log_writer: Box<dyn Write + Send> = Box::new(RecentLogWriter);
+        let recent_log_dispatch = fern::Dispatch::new()
+            .level(log::LevelFilter::Info)
+            .level_for("songrec", log::LevelFilter::Debug)
+            .chain(recent_log_writer);
+
+        main_dispatch = main_dispatch.chain(recent_log_dispatch);
+
+        if let Some(log_file_options) = log_file {
+            let path = log_file_options.path.clone();
+            let level = log_file_options.level;
+
+            match RotatingFileWriter::new(log_file_options) {
+                Ok(writer) => {
+                    let writer: Box<dyn Write + Send> = Box::new(writer);
+                    let file_dispatch = fern::Dispatch::new()
+                        .level(if level == log::LevelFilter::Trace {
+                            log::LevelFilter::Trace
+                        } else {
+                            level.min(log::LevelFilter::Warn)
+                        })
+                        .level_for("songrec", level)
+                        .chain(writer);
+                    main_dispatch = main_dispatch.chain(file_dispatch);
+                }
+                Err(error) => {
+                    eprintln!(
+                        "Could not open the log file {}: {:?}",
+                        path.display(),
+                        error
+                    );
+                }
+            }
+        }
+
+        // Services have no standard error to write to
+        #[cfg(all(windows, feature = "windows-service"))]
+        if windows_service::is_running_as_service() {
+            match eventlog::EventLog::new(windows_service::SERVICE_NAME, log::Level::Info) {
+                Ok(event_log) => {
+                    let event_log: Box<dyn log::Log> = Box::new(event_log);
+                    main_dispatch = main_dispatch.chain(
+                        fern::Dispatch::new()
+                            .level(log::LevelFilter::Warn)
+                            .level_for("songrec", log::LevelFilter::Info)
+                            .chain(event_log),
+                    );
+                }
+                Err(error) => {
+                    eprintln!("Could not open the Windows event log: {:?}", error);
+                }
+            }
+        }
+
         #[cfg(feature = "gui")]
         {
             let gui_dispatcher = GUIDispatcher::new();
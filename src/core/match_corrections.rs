@@ -0,0 +1,210 @@
+//! Local corrections of the songs that Shazam keeps recognizing wrongly (a
+//! cover recognized as the original, a remix as another one...). Shazam
+//! returns the same track key for identical matches, so a correction maps
+//! a track key to the song that the user entered, and is applied to every
+//! later match of this key.
+//!
+//! A match reported as wrong before the right song is entered is kept
+//! with empty fields: its matches are then still recognized, but not
+//! forwarded to the sinks, so that the wrong song isn't announced again.
+//! The report is withdrawn when the user cancels entering the right song.
+//!
+//! Corrections are stored in "match_corrections.csv", next to the history,
+//! which is only parsed again when it changes.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::utils::file_cache::FileCache;
+use crate::utils::filesystem_operations::obtain_match_corrections_csv_path;
+
+static CORRECTIONS: FileCache<Vec<MatchCorrection>> = FileCache::new();
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct MatchCorrection {
+    pub track_key: String,
+    pub artist_name: Option<String>,
+    pub song_name: Option<String>,
+    pub album_name: Option<String>,
+}
+
+impl MatchCorrection {
+    /// Whether the match was reported wrong without the right song
+    pub fn is_pending(&self) -> bool {
+        self.song_name.is_none()
+    }
+}
+
+fn parse_corrections(contents: &[u8]) -> Vec<MatchCorrection> {
+    csv::Reader::from_reader(contents)
+        .deserialize()
+        .filter_map(|row| match row {
+            Ok(correction) => Some(correction),
+            Err(error) => {
+                warn!("Ignoring an invalid match correction: {}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn load_corrections() -> Arc<Vec<MatchCorrection>> {
+    let Ok(path) = obtain_match_corrections_csv_path() else {
+        return Arc::default();
+    };
+    CORRECTIONS.get(&path, |path| match std::fs::read(path) {
+        Ok(contents) => parse_corrections(&contents),
+        Err(_) => vec![],
+    })
+}
+
+fn save_corrections(corrections: &[MatchCorrection]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for correction in corrections {
+        writer.serialize(correction)?;
+    }
+    std::fs::write(obtain_match_corrections_csv_path()?, writer.into_inner()?)?;
+    Ok(())
+}
+
+fn find_correction(track_key: &str) -> Option<MatchCorrection> {
+    load_corrections()
+        .iter()
+        .find(|correction| correction.track_key == track_key)
+        .cloned()
+}
+
+/// Record a correction, replacing the previous one of its track key
+pub fn save_correction(correction: MatchCorrection) -> Result<(), Box<dyn Error>> {
+    let mut corrections = load_corrections().to_vec();
+    corrections.retain(|other| other.track_key != correction.track_key);
+    info!(
+        "Recorded a correction of the track {}",
+        correction.track_key
+    );
+    corrections.push(correction);
+    save_corrections(&corrections)
+}
+
+/// Report the match of a track key as wrong, keeping the right song when
+/// it was already entered
+pub fn report_wrong_match(track_key: &str) -> Result<(), Box<dyn Error>> {
+    if find_correction(track_key).is_some() {
+        return Ok(());
+    }
+    save_correction(MatchCorrection {
+        track_key: track_key.to_string(),
+        artist_name: None,
+        song_name: None,
+        album_name: None,
+    })
+}
+
+/// Withdraw a report of `report_wrong_match`, unless the right song was
+/// entered since
+pub fn withdraw_report(track_key: &str) -> Result<(), Box<dyn Error>> {
+    let corrections = load_corrections();
+    if !corrections
+        .iter()
+        .any(|correction| correction.track_key == track_key && correction.is_pending())
+    {
+        return Ok(());
+    }
+    let corrections: Vec<MatchCorrection> = corrections
+        .iter()
+        .filter(|correction| correction.track_key != track_key)
+        .cloned()
+        .collect();
+    save_corrections(&corrections)
+}
+
+/// Whether the matches of this track key were reported wrong, and wait for
+/// the right song to be entered
+pub fn is_pending(track_key: &str) -> bool {
+    find_correction(track_key).is_some_and(|correction| correction.is_pending())
+}
+
+fn apply_correction(message: &mut SongRecognizedMessage, correction: &MatchCorrection) {
+    let Some(ref song_name) = correction.song_name else {
+        return;
+    };
+    message.song_name = song_name.clone();
+    message.artist_name = correction.artist_name.clone().unwrap_or_default();
+    message.album_name = correction.album_name.clone();
+    // They are the ones of the wrong song
+    message.release_year = None;
+    message.genre = None;
+    message.cover_image = None;
+    message.cover_url = None;
+    message.isrc = None;
+    message.upc = None;
+    // Nor are the links and identifiers of the response of Shazam, which
+    // the exports would read
+    let mut track = serde_json::json!({
+        "key": message.track_key,
+        "title": message.song_name,
+        "subtitle": message.artist_name,
+    });
+    if let Some(ref album_name) = message.album_name {
+        track["sections"] = serde_json::json!([{
+            "type": "SONG",
+            "metadata": [{ "title": "Album", "text": album_name }],
+        }]);
+    }
+    message.shazam_json = serde_json::json!({ "track": track }).to_string();
+}
+
+/// Replace the song of a match by its correction, when there is one
+pub fn apply(message: &mut SongRecognizedMessage) {
+    if let Some(correction) = find_correction(&message.track_key) {
+        if !correction.is_pending() {
+            info!(
+                "Correcting {} - {} into {} - {}",
+                message.artist_name,
+                message.song_name,
+                correction.artist_name.as_deref().unwrap_or_default(),
+                correction.song_name.as_deref().unwrap_or_default()
+            );
+        }
+        apply_correction(message, &correction);
+    }
+}
+
+#[test]
+fn test_match_corrections() {
+    let corrections = parse_corrections(
+        b"track_key,artist_name,song_name,album_name\n\
+        123,Daft Punk,Around the World,Homework\n\
+        456,,,\n",
+    );
+    assert_eq!(corrections.len(), 2);
+    assert!(!corrections[0].is_pending());
+    assert!(corrections[1].is_pending());
+
+    let mut message = SongRecognizedMessage {
+        artist_name: "Cover Band".to_string(),
+        album_name: None,
+        song_name: "Around the World".to_string(),
+        cover_image: None,
+        cover_url: Some("https://example.com/cover.jpg".to_string()),
+        track_key: "123".to_string(),
+        release_year: Some("2012".to_string()),
+        genre: None,
+        isrc: Some("GBAYE1200001".to_string()),
+        upc: None,
+        shazam_json: r#"{"track": {"isrc": "GBAYE1200001"}}"#.to_string(),
+        source: None,
+    };
+    apply_correction(&mut message, &corrections[0]);
+    assert_eq!(message.artist_name, "Daft Punk");
+    assert_eq!(message.album_name.as_deref(), Some("Homework"));
+    assert_eq!(message.release_year, None);
+    assert_eq!(message.cover_url, None);
+    assert_eq!(message.isrc, None);
+    let json: serde_json::Value = serde_json::from_str(&message.shazam_json).unwrap();
+    assert_eq!(json["track"]["isrc"], serde_json::Value::Null);
+    assert_eq!(json["track"]["subtitle"], "Daft Punk");
+}
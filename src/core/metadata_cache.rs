@@ -0,0 +1,117 @@
+//! On-disk cache of the track metadata and cover art returned by Shazam,
+//! keyed by Shazam track key, so that repeated matches of the same song (or
+//! later lookups of a known song) don't fetch identical data again.
+//!
+//! Entries live in a "metadata" folder of the cache directory and expire
+//! after `CACHE_TTL`. The tracks read are kept in memory as well, as the
+//! rows of the GUI history read them each time they are shown.
+
+use log::{debug, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::utils::filesystem_operations::{is_incognito, obtain_cache_directory};
+
+const CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The tracks kept in memory are forgotten beyond this number
+const MAX_MEMORY_ENTRIES: usize = 2000;
+
+/// The tracks read from the disk, or None for the ones that aren't cached
+static TRACKS: LazyLock<Mutex<HashMap<String, Option<Value>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn remember_track(track_key: &str, track: Option<Value>) {
+    let mut tracks = TRACKS.lock().unwrap();
+    if tracks.len() >= MAX_MEMORY_ENTRIES {
+        tracks.clear();
+    }
+    tracks.insert(track_key.to_string(), track);
+}
+
+fn cache_directory() -> Option<PathBuf> {
+    let mut directory = obtain_cache_directory().ok()?;
+    directory.push("metadata");
+    if !directory.exists() {
+        std::fs::create_dir_all(&directory).ok()?;
+    }
+    Some(directory)
+}
+
+/// Track keys are numeric, but don't trust them to build file names
+fn entry_path(track_key: &str, extension: &str) -> Option<PathBuf> {
+    if track_key.is_empty() || !track_key.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let mut path = cache_directory()?;
+    path.push(format!("{}.{}", track_key, extension));
+    Some(path)
+}
+
+fn is_fresh(path: &PathBuf) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map(|age| age < CACHE_TTL)
+        .unwrap_or(false)
+}
+
+fn read_entry(track_key: &str, extension: &str) -> Option<Vec<u8>> {
+    let path = entry_path(track_key, extension)?;
+    if !is_fresh(&path) {
+        return None;
+    }
+    let contents = std::fs::read(&path).ok()?;
+    debug!("Using cached {} for track {}", extension, track_key);
+    Some(contents)
+}
+
+fn write_entry(track_key: &str, extension: &str, contents: &[u8]) {
+    if is_incognito() {
+        return;
+    }
+    if let Some(path) = entry_path(track_key, extension) {
+        if let Err(error) = std::fs::write(&path, contents) {
+            warn!("Could not write {}: {}", path.display(), error);
+        }
+    }
+}
+
+pub fn store_track(track_key: &str, track: &Value) {
+    write_entry(track_key, "json", track.to_string().as_bytes());
+    remember_track(track_key, Some(track.clone()));
+}
+
+/// The "track" object of the cached Shazam response
+pub fn get_track(track_key: &str) -> Option<Value> {
+    if let Some(track) = TRACKS.lock().unwrap().get(track_key) {
+        return track.clone();
+    }
+    let track =
+        read_entry(track_key, "json").and_then(|contents| serde_json::from_slice(&contents).ok());
+    remember_track(track_key, track.clone());
+    track
+}
+
+pub fn get_cover(track_key: &str) -> Option<Vec<u8>> {
+    read_entry(track_key, "cover")
+}
+
+pub fn store_cover(track_key: &str, cover: &[u8]) {
+    write_entry(track_key, "cover", cover);
+}
+
+/// Remove the expired entries of the cache
+pub fn prune() {
+    if let Some(Ok(entries)) = cache_directory().map(std::fs::read_dir) {
+        for entry in entries.flatten() {
+            if !is_fresh(&entry.path()) {
+                std::fs::remove_file(entry.path()).ok();
+            }
+        }
+    }
+}
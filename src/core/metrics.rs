@@ -0,0 +1,263 @@
+//! Process-wide counters describing the activity of a long-running SongRec
+//! instance. They are updated from the audio, processing and HTTP code paths
+//! and rendered in the Prometheus text exposition format by the
+//! `plugins::prometheus_exporter` module.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::core::trace_profile;
+
+/// Upper bounds (in seconds) of the API latency histogram buckets.
+const LATENCY_BUCKETS_SECS: [f64; 8] = [0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0];
+
+/// Steps of the recognition of a window of captured audio, timed
+/// separately so that performance regressions can be located
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    /// Time waited for the audio of the window to be captured
+    CaptureFill,
+    /// Conversion of the window to 16 KHz mono
+    Resample,
+    /// Windowing, FFT and peak detection
+    Fft,
+    /// Serialization of the signature
+    Encode,
+    Http,
+    /// Decoding of the JSON answer
+    Parse,
+}
+
+pub const STAGES: [Stage; 6] = [
+    Stage::CaptureFill,
+    Stage::Resample,
+    Stage::Fft,
+    Stage::Encode,
+    Stage::Http,
+    Stage::Parse,
+];
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::CaptureFill => "capture_fill",
+            Stage::Resample => "resample",
+            Stage::Fft => "fft",
+            Stage::Encode => "encode",
+            Stage::Http => "http",
+            Stage::Parse => "parse",
+        }
+    }
+}
+
+pub enum RecognitionOutcome {
+    Match,
+    NoMatch,
+    RateLimited,
+    NetworkError,
+}
+
+struct Metrics {
+    matches: AtomicU64,
+    no_matches: AtomicU64,
+    rate_limited: AtomicU64,
+    network_errors: AtomicU64,
+    signature_errors: AtomicU64,
+    audio_errors: AtomicU64,
+    xruns: AtomicU64,
+
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS_SECS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+
+    stage_count: [AtomicU64; STAGES.len()],
+    stage_sum_micros: [AtomicU64; STAGES.len()],
+    stage_last_micros: [AtomicU64; STAGES.len()],
+}
+
+static METRICS: Metrics = Metrics {
+    matches: AtomicU64::new(0),
+    no_matches: AtomicU64::new(0),
+    rate_limited: AtomicU64::new(0),
+    network_errors: AtomicU64::new(0),
+    signature_errors: AtomicU64::new(0),
+    audio_errors: AtomicU64::new(0),
+    xruns: AtomicU64::new(0),
+
+    latency_buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS_SECS.len()],
+    latency_count: AtomicU64::new(0),
+    latency_sum_micros: AtomicU64::new(0),
+
+    stage_count: [const { AtomicU64::new(0) }; STAGES.len()],
+    stage_sum_micros: [const { AtomicU64::new(0) }; STAGES.len()],
+    stage_last_micros: [const { AtomicU64::new(0) }; STAGES.len()],
+};
+
+pub fn record_recognition(outcome: RecognitionOutcome) {
+    let (counter, name) = match outcome {
+        RecognitionOutcome::Match => (&METRICS.matches, "match"),
+        RecognitionOutcome::NoMatch => (&METRICS.no_matches, "no_match"),
+        RecognitionOutcome::RateLimited => (&METRICS.rate_limited, "rate_limited"),
+        RecognitionOutcome::NetworkError => (&METRICS.network_errors, "network_error"),
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    trace_profile::instant(name, "recognition");
+}
+
+pub fn record_signature_error() {
+    METRICS.signature_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_audio_error() {
+    METRICS.audio_errors.fetch_add(1, Ordering::Relaxed);
+    trace_profile::instant("audio_error", "audio");
+}
+
+pub fn record_xrun() {
+    METRICS.xruns.fetch_add(1, Ordering::Relaxed);
+    trace_profile::instant("xrun", "audio");
+}
+
+pub fn observe_api_latency(latency: Duration) {
+    let secs = latency.as_secs_f64();
+    for (bucket, upper_bound) in METRICS.latency_buckets.iter().zip(LATENCY_BUCKETS_SECS) {
+        if secs <= upper_bound {
+            bucket.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    METRICS.latency_count.fetch_add(1, Ordering::Relaxed);
+    METRICS
+        .latency_sum_micros
+        .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+}
+
+pub fn observe_stage(stage: Stage, duration: Duration) {
+    let micros = duration.as_micros() as u64;
+    METRICS.stage_count[stage as usize].fetch_add(1, Ordering::Relaxed);
+    METRICS.stage_sum_micros[stage as usize].fetch_add(micros, Ordering::Relaxed);
+    METRICS.stage_last_micros[stage as usize].store(micros, Ordering::Relaxed);
+    trace_profile::complete(stage.name(), "pipeline", duration);
+}
+
+/// Time taken by each stage of the last recognition, for --diagnostics
+pub fn stage_report() -> String {
+    STAGES
+        .iter()
+        .map(|stage| {
+            format!(
+                "{} {:.1} ms",
+                stage.name(),
+                METRICS.stage_last_micros[*stage as usize].load(Ordering::Relaxed) as f64 / 1000.0
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} {}", name, kind).unwrap();
+}
+
+/// Render all the metrics in the Prometheus text exposition format (version 0.0.4)
+pub fn render() -> String {
+    let mut output = String::new();
+
+    write_header(
+        &mut output,
+        "songrec_recognition_requests_total",
+        "counter",
+        "Recognition requests sent to Shazam, by result.",
+    );
+    for (result, counter) in [
+        ("match", &METRICS.matches),
+        ("no_match", &METRICS.no_matches),
+        ("rate_limited", &METRICS.rate_limited),
+        ("network_error", &METRICS.network_errors),
+    ] {
+        writeln!(
+            output,
+            "songrec_recognition_requests_total{{result=\"{}\"}} {}",
+            result,
+            counter.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+
+    for (name, help, counter) in [
+        (
+            "songrec_signature_errors_total",
+            "Audio files or buffers that could not be turned into a signature.",
+            &METRICS.signature_errors,
+        ),
+        (
+            "songrec_audio_errors_total",
+            "Errors reported by the audio capture backend.",
+            &METRICS.audio_errors,
+        ),
+        (
+            "songrec_audio_xruns_total",
+            "Buffer overruns or underruns reported by the audio capture backend.",
+            &METRICS.xruns,
+        ),
+    ] {
+        write_header(&mut output, name, "counter", help);
+        writeln!(output, "{} {}", name, counter.load(Ordering::Relaxed)).unwrap();
+    }
+
+    write_header(
+        &mut output,
+        "songrec_api_latency_seconds",
+        "histogram",
+        "Time taken by Shazam to answer a recognition request.",
+    );
+    for (bucket, upper_bound) in METRICS.latency_buckets.iter().zip(LATENCY_BUCKETS_SECS) {
+        writeln!(
+            output,
+            "songrec_api_latency_seconds_bucket{{le=\"{}\"}} {}",
+            upper_bound,
+            bucket.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+    let count = METRICS.latency_count.load(Ordering::Relaxed);
+    writeln!(
+        output,
+        "songrec_api_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+        count
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "songrec_api_latency_seconds_sum {}",
+        METRICS.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    )
+    .unwrap();
+    writeln!(output, "songrec_api_latency_seconds_count {}", count).unwrap();
+
+    write_header(
+        &mut output,
+        "songrec_stage_duration_seconds",
+        "summary",
+        "Time spent in each stage of the recognition pipeline.",
+    );
+    for stage in STAGES {
+        writeln!(
+            output,
+            "songrec_stage_duration_seconds_sum{{stage=\"{}\"}} {}",
+            stage.name(),
+            METRICS.stage_sum_micros[stage as usize].load(Ordering::Relaxed) as f64 / 1_000_000.0
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "songrec_stage_duration_seconds_count{{stage=\"{}\"}} {}",
+            stage.name(),
+            METRICS.stage_count[stage as usize].load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+
+    output
+}
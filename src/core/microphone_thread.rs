@@ -1,37 +1,162 @@
+use std::collections::VecDeque;
 use std::iter::Copied;
 use std::num::NonZero;
+use std::path::{Path, PathBuf};
 use std::slice::Iter;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::core::metrics::{self, Stage};
 use crate::core::preferences::PreferencesInterface;
+use crate::core::preprocessing::PreprocessingChain;
+use crate::core::session_recording;
 use crate::core::thread_messages::{MicrophoneMessage::*, *};
 
 use cpal::platform::Device;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use gettextrs::gettext;
-use log::{debug, warn};
+use log::{debug, info, warn};
 use rodio::conversions::SampleTypeConverter;
-use rodio::nz;
-
-use crate::core::audio_controllers::audio_backend::get_any_backend;
+use rodio::{nz, Source};
+
+use crate::core::audio_controllers::audio_backend::AudioBackend;
+use crate::core::audio_controllers::auto_device::{
+    self, auto_device_item, pick_device, DeviceLevel, AUTO_DEVICE_NAME,
+};
+use crate::core::audio_controllers::bluetooth::is_hands_free_device_name;
+use crate::core::audio_controllers::capture_session::{
+    CaptureDump, CaptureSession, CaptureSessionHeader, SESSION_DEVICE_PREFIX,
+};
+use crate::core::audio_controllers::clock_drift::ClockDriftEstimator;
+use crate::core::audio_controllers::echo_cancel::echo_cancelled_counterpart;
+use crate::core::audio_controllers::realtime;
+use crate::core::audio_controllers::snapcast::{SnapcastStream, SNAPCAST_DEVICE_PREFIX};
+use crate::core::audio_controllers::stream_negotiation::{describe_ranges, stream_candidates};
+use crate::core::audio_controllers::virtual_capture::{is_virtual_device, VirtualCapture};
 
 const BUFFER_SIZE_SECS: usize = 12;
 
-struct ProcessingState<'a> {
-    input_samples: Vec<f32>,
-    processing_tx: &'a async_channel::Sender<ProcessingMessage>,
+/// Below it, the captured audio is considered silent; a quiet microphone
+/// stays well above it, unlike a muted or disconnected input
+const NOISE_FLOOR_DBFS: f32 = -60.0;
+
+/// Whether the root mean square level of the samples exceeds the noise
+/// floor
+fn has_signal(samples: impl Iterator<Item = f32>) -> bool {
+    let (count, sum_of_squares) = samples.fold((0usize, 0.0f32), |(count, sum), sample| {
+        (count + 1, sum + sample * sample)
+    });
+    count > 0 && 10.0 * (sum_of_squares / count as f32).log10() > NOISE_FLOOR_DBFS
+}
+
+/// Recognize the left and right channels of the devices separately, for
+/// setups where two sources are panned hard (see `--split-channels`)
+static SPLIT_CHANNELS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_split_channels(enabled: bool) {
+    SPLIT_CHANNELS.store(enabled, Ordering::SeqCst);
+}
+
+/// The request interval of the preferences is raised to it, e.g. in the
+/// low-data mode, see `NetworkSettings::min_request_interval`
+static MIN_REQUEST_INTERVAL_SECS: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_min_request_interval(secs: u64) {
+    MIN_REQUEST_INTERVAL_SECS.store(secs, Ordering::SeqCst);
+}
+
+// Time of the last audio callback received from CPAL, used by the systemd
+// watchdog in order to detect a stalled capture
+static CAPTURE_EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+static LAST_CAPTURE_MILLIS: AtomicU64 = AtomicU64::new(0);
+
+/// How long ago the capture stream delivered audio samples for the last time
+pub fn capture_idle_time() -> Duration {
+    CAPTURE_EPOCH
+        .elapsed()
+        .saturating_sub(Duration::from_millis(
+            LAST_CAPTURE_MILLIS.load(Ordering::Relaxed),
+        ))
+}
+
+/// Xruns happening this close to each other are considered repeated
+const XRUN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Repeated xruns after which the capture buffer is enlarged
+const XRUNS_BEFORE_ENLARGING_BUFFER: usize = 5;
+
+/// Capture buffer sizes, in frames, when the default one was too small
+const FIRST_ENLARGED_BUFFER_FRAMES: u32 = 2048;
+const MAX_BUFFER_FRAMES: u32 = 16384;
+
+/// The buffer size to open the streams with: the enlarged one after xruns,
+/// and at least the first enlarged one without realtime scheduling
+fn capture_buffer_frames(enlarged_buffer_frames: Option<u32>) -> Option<u32> {
+    enlarged_buffer_frames.or(realtime::is_denied().then_some(FIRST_ENLARGED_BUFFER_FRAMES))
+}
+
+#[derive(Default)]
+struct XrunTracker {
+    recent_xruns: VecDeque<Instant>,
+}
+
+impl XrunTracker {
+    /// Return the number of xruns within the last minute
+    fn record(&mut self, now: Instant) -> usize {
+        self.recent_xruns.push_back(now);
+        while let Some(oldest) = self.recent_xruns.front() {
+            if now.duration_since(*oldest) <= XRUN_WINDOW {
+                break;
+            }
+            self.recent_xruns.pop_front();
+        }
+        self.recent_xruns.len()
+    }
+}
+
+/// Receives the samples of a capture stream, whatever their origin (CPAL or
+/// a virtual device), keeps the last 12 seconds and sends them to the
+/// processing thread every request interval
+pub struct CaptureProcessor {
+    processing_tx: async_channel::Sender<ProcessingMessage>,
     gui_tx: async_channel::Sender<GUIMessage>,
     channels: u16,
     sample_rate: u32,
-    twelve_seconds_buffer: &'a mut [f32; 16000 * BUFFER_SIZE_SECS],
-    number_unprocessed_samples: &'a mut usize,
-    number_unmeasured_samples: &'a mut usize,
-    processing_already_ongoing: &'a AtomicBool,
-    preferences_interface: &'a Arc<Mutex<PreferencesInterface>>,
+    twelve_seconds_buffer: Box<[f32]>,
+    /// The right channel when the channels are recognized separately, the
+    /// other buffer then holding the left one
+    right_channel_buffer: Option<Box<[f32]>>,
+    number_unprocessed_samples: usize, // Sample count for the interval of doing Shazam recognition (every 4 seconds)
+    number_unmeasured_samples: usize, // Sample count for doing volume measurement (every 24th of second)
+    processing_already_ongoing: Arc<AtomicBool>,
+    preferences_interface: Arc<Mutex<PreferencesInterface>>,
+    source: RecognitionSource,
+    /// Raw copy of the captured samples, for "songrec replay"
+    dump: Option<CaptureDump>,
+    /// When the samples of the next window started to be captured, and the
+    /// time spent resampling them
+    window_start: Instant,
+    resampling_time: Duration,
+    clock_drift: ClockDriftEstimator,
+    /// The steps of the preferences, see `preprocessing`
+    preprocessing: PreprocessingChain,
+    /// Whether the thread delivering the samples asked for realtime
+    /// scheduling yet
+    realtime_requested: bool,
+    /// Whether the samples only fill the buffer for now, see
+    /// `MicrophoneWarmStart`
+    pre_rolling: Arc<AtomicBool>,
+    /// Whether the last window of a microphone or monitor was silent
+    no_audio_detected: bool,
+    /// Where the peak levels of a microphone or monitor are recorded, see
+    /// `auto_device`
+    device_level: Option<Arc<DeviceLevel>>,
 }
 
+/// `make_backend` is usually `get_any_backend`
 pub fn microphone_thread(
+    make_backend: fn() -> Box<dyn AudioBackend>,
     microphone_rx: async_channel::Receiver<MicrophoneMessage>,
     microphone_tx: async_channel::Sender<MicrophoneMessage>,
     processing_tx: async_channel::Sender<ProcessingMessage>,
@@ -54,8 +179,36 @@ pub fn microphone_thread(
 
     let gui_tx_2 = gui_tx.clone();
     let microphone_tx_2 = microphone_tx.clone();
+    let xrun_tracker = Arc::new(Mutex::new(XrunTracker::default()));
+
+    // Before the first stream is opened, see capture_buffer_frames()
+    realtime::probe();
+
+    let err_fn = move |location: &str, error: cpal::Error, mut popup: bool| {
+        if error.kind() == cpal::ErrorKind::Xrun {
+            metrics::record_xrun();
+
+            let recent_xruns = xrun_tracker.lock().unwrap().record(Instant::now());
+            warn!(
+                "{}",
+                gettext("Audio buffer overrun or underrun (%d in the last minute)")
+                    .replace("%d", &recent_xruns.to_string())
+            );
+            gui_tx_2
+                .try_send(GUIMessage::CaptureXruns(recent_xruns as u32))
+                .unwrap();
+            if recent_xruns >= XRUNS_BEFORE_ENLARGING_BUFFER {
+                *xrun_tracker.lock().unwrap() = XrunTracker::default();
+                microphone_tx_2
+                    .try_send(MicrophoneMessage::EnlargeBuffer)
+                    .unwrap();
+            }
+        } else if error.kind() == cpal::ErrorKind::RealtimeDenied {
+            realtime::mark_denied();
+        } else if error.kind() != cpal::ErrorKind::DeviceChanged {
+            metrics::record_audio_error();
+        }
 
-    let err_fn = move |location: &'static str, error: cpal::Error, mut popup: bool| {
         if error.kind() == cpal::ErrorKind::DeviceChanged {
             microphone_tx_2
                 .try_send(MicrophoneMessage::RefreshDevices)
@@ -111,11 +264,25 @@ pub fn microphone_thread(
         debug!("Using audio playback backend: {:?}", host.id());
         debug!("CPAL initialized");
 
-        let mut backend = get_any_backend();
+        let mut backend = make_backend();
 
         // Run the input stream on a separate thread.
 
         let mut stream: Option<cpal::Stream> = None;
+        let mut virtual_capture: Option<VirtualCapture> = None;
+        // Whether its samples ran out while a recognition was ongoing
+        let mut virtual_capture_ended = false;
+        let mut capture_dump_directory: Option<PathBuf> = None;
+
+        // Capture buffer size, when the default one caused xruns, and the
+        // device to restart the capture from with a larger one
+        let mut buffer_frames: Option<u32> = None;
+        let mut current_device_name: Option<String> = None;
+        // The device as it was asked for (e.g. "auto"), to tell whether a
+        // recognition may start from the pre-roll
+        let mut requested_device_name: Option<String> = None;
+        // The device picked for the "auto" device, if it is being recorded
+        let mut auto_device: Option<String> = None;
 
         // Whether our data is already being processed in
         // other threads (pointer to a bool shared between
@@ -123,6 +290,13 @@ pub fn microphone_thread(
 
         let processing_already_ongoing: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
 
+        // With a warm start, the stream stays open while not recognizing,
+        // only filling the buffer (the pre-roll) so that the next
+        // recognition starts from the audio captured meanwhile, rather than
+        // waiting for a whole request interval
+        let mut warm_start = false;
+        let pre_rolling: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+
         // Send a list of the active microphone-alike devices to the GUI thread
         // (the combo box will be filed with device names when a "DevicesList"
         // inter-thread message will be received at the initialization of the
@@ -131,7 +305,8 @@ pub fn microphone_thread(
         //  - https://github.com/RustAudio/rodio/issues/270
         //  - https://github.com/RustAudio/rodio/issues/214 )
 
-        let device_names: Vec<DeviceListItem> = backend.list_devices(&host);
+        let mut device_names: Vec<DeviceListItem> = backend.list_devices(&host);
+        device_names.insert(0, auto_device_item());
 
         gui_tx
             .try_send(GUIMessage::DevicesList(device_names))
@@ -142,16 +317,189 @@ pub fn microphone_thread(
         // in particular)
 
         while let Ok(message) = microphone_rx.recv_blocking() {
+            let message = match message {
+                MicrophoneWarmStart(Some(device_name)) => {
+                    warm_start = true;
+                    let recognizing = (stream.is_some() || virtual_capture.is_some())
+                        && !pre_rolling.load(Ordering::SeqCst);
+                    let already_pre_rolling = pre_rolling.load(Ordering::SeqCst)
+                        && requested_device_name.as_ref() == Some(&device_name);
+                    if recognizing || already_pre_rolling {
+                        continue;
+                    }
+                    pre_rolling.store(true, Ordering::SeqCst);
+                    MicrophoneRecordStart(device_name)
+                }
+                MicrophoneWarmStart(None) => {
+                    warm_start = false;
+                    if !pre_rolling.load(Ordering::SeqCst) {
+                        continue;
+                    }
+                    MicrophoneRecordStop
+                }
+                MicrophoneRecordStart(device_name) if pre_rolling.load(Ordering::SeqCst) => {
+                    pre_rolling.store(false, Ordering::SeqCst);
+                    if stream.is_some() && requested_device_name.as_ref() == Some(&device_name) {
+                        // The window captured meanwhile is sent with the
+                        // next samples
+                        debug!("Recognizing from the pre-roll");
+                        gui_tx.try_send(GUIMessage::MicrophoneRecording).unwrap();
+                        continue;
+                    }
+                    MicrophoneRecordStart(device_name)
+                }
+                message => message,
+            };
+
+            // Restart the capture in the same mode, e.g. with a larger buffer
+            // (after clearing requested_device_name, so that a pre-roll on
+            // the same device restarts too)
+            let restart_message = |device_name: String| {
+                if pre_rolling.load(Ordering::SeqCst) {
+                    MicrophoneWarmStart(Some(device_name))
+                } else {
+                    MicrophoneRecordStart(device_name)
+                }
+            };
+
             match message {
                 MicrophoneRecordStart(device_name) => {
-                    let processing_tx_2 = processing_tx.clone();
-                    let gui_tx_3 = gui_tx.clone();
                     let gui_tx_4 = gui_tx.clone();
+                    auto_device = None;
+                    virtual_capture_ended = false;
+                    requested_device_name = None;
+
+                    if let Some(opened) = backend.open_virtual_device(&device_name) {
+                        stream = None;
+                        virtual_capture = None;
+                        match opened {
+                            Ok(device) => {
+                                let mut capture_processor = CaptureProcessor::new(
+                                    processing_tx.clone(),
+                                    gui_tx.clone(),
+                                    device.channels,
+                                    device.sample_rate,
+                                    processing_already_ongoing.clone(),
+                                    preferences_interface.clone(),
+                                    device.source,
+                                );
+                                if let Some(ref directory) = capture_dump_directory {
+                                    capture_processor.dump_to(directory);
+                                }
+                                let microphone_tx = microphone_tx.clone();
+                                virtual_capture = Some(VirtualCapture::start(
+                                    device.samples,
+                                    device.sample_rate,
+                                    device.channels,
+                                    device.speed,
+                                    move |samples| capture_processor.write_samples(samples),
+                                    move || {
+                                        microphone_tx
+                                            .try_send(MicrophoneMessage::VirtualCaptureEnded)
+                                            .unwrap();
+                                    },
+                                ));
+                                gui_tx_4.try_send(GUIMessage::MicrophoneRecording).unwrap();
+                            }
+                            Err(error) => {
+                                gui_tx
+                                    .try_send(GUIMessage::ErrorMessage(format!(
+                                        "{} {}: {}",
+                                        gettext("Audio error:"),
+                                        device_name,
+                                        error
+                                    )))
+                                    .unwrap();
+                            }
+                        }
+                        continue;
+                    }
 
-                    let err_fn_2 = err_fn.clone();
-                    let err_fn_cb = move |error: cpal::Error| {
-                        err_fn_2("stream error", error, true);
-                    };
+                    if let Some(location) = device_name.strip_prefix(SNAPCAST_DEVICE_PREFIX) {
+                        stream = None;
+                        virtual_capture = None;
+                        match SnapcastStream::connect(location) {
+                            Ok(snapcast_stream) => {
+                                let mut capture_processor = CaptureProcessor::new(
+                                    processing_tx.clone(),
+                                    gui_tx.clone(),
+                                    snapcast_stream.format.channels,
+                                    snapcast_stream.format.sample_rate,
+                                    processing_already_ongoing.clone(),
+                                    preferences_interface.clone(),
+                                    RecognitionSource::Monitor(device_name.clone()),
+                                );
+                                if let Some(ref directory) = capture_dump_directory {
+                                    capture_processor.dump_to(directory);
+                                }
+                                virtual_capture = Some(VirtualCapture::spawn(move |stop| {
+                                    snapcast_stream.deliver(stop, move |samples| {
+                                        capture_processor.write_samples(samples)
+                                    })
+                                }));
+                                gui_tx_4.try_send(GUIMessage::MicrophoneRecording).unwrap();
+                            }
+                            Err(error) => {
+                                gui_tx
+                                    .try_send(GUIMessage::ErrorMessage(format!(
+                                        "{} {}: {}",
+                                        gettext("Audio error:"),
+                                        location,
+                                        error
+                                    )))
+                                    .unwrap();
+                            }
+                        }
+                        continue;
+                    }
+
+                    if let Some(session_path) = device_name.strip_prefix(SESSION_DEVICE_PREFIX) {
+                        stream = None;
+                        match CaptureSession::open(Path::new(session_path)) {
+                            Ok(session) => {
+                                info!(
+                                    "Replaying the capture recorded from {}",
+                                    session.header.source
+                                );
+                                let mut capture_processor = CaptureProcessor::new(
+                                    processing_tx.clone(),
+                                    gui_tx.clone(),
+                                    session.header.channels,
+                                    session.header.sample_rate,
+                                    processing_already_ongoing.clone(),
+                                    preferences_interface.clone(),
+                                    RecognitionSource::File(session_path.to_string()),
+                                );
+                                let microphone_tx = microphone_tx.clone();
+                                // Wait for each recognition rather than skipping
+                                // windows, so that replays don't depend on the
+                                // network speed
+                                virtual_capture = Some(VirtualCapture::start_chunks(
+                                    session,
+                                    Duration::ZERO,
+                                    Some(processing_already_ongoing.clone()),
+                                    move |samples| capture_processor.write_samples(samples),
+                                    move || {
+                                        microphone_tx
+                                            .try_send(MicrophoneMessage::VirtualCaptureEnded)
+                                            .unwrap();
+                                    },
+                                ));
+                                gui_tx_4.try_send(GUIMessage::MicrophoneRecording).unwrap();
+                            }
+                            Err(error) => {
+                                gui_tx
+                                    .try_send(GUIMessage::ErrorMessage(format!(
+                                        "{} {}",
+                                        gettext("Audio error:"),
+                                        error
+                                    )))
+                                    .unwrap();
+                            }
+                        }
+                        continue;
+                    }
+                    virtual_capture = None;
 
                     if host.default_input_device().is_none() {
                         #[cfg(target_os = "linux")]
@@ -167,161 +515,249 @@ pub fn microphone_thread(
                         return;
                     };
 
-                    let device: Device = backend.set_device(&host, &device_name);
+                    let listed_devices = backend.list_devices(&host);
+
+                    let asked_device_name = device_name.clone();
+                    let device_name = match pick_device(&listed_devices) {
+                        Some(picked) if device_name == AUTO_DEVICE_NAME => {
+                            info!(
+                                "{}",
+                                gettext("Automatically selected the audio device %s")
+                                    .replace("%s", &picked.display_name)
+                            );
+                            auto_device = Some(picked.inner_name.clone());
+                            picked.inner_name.clone()
+                        }
+                        _ => device_name,
+                    };
 
-                    let config = match device.default_input_config() {
-                        Ok(res) => res,
-                        Err(err) => {
-                            #[cfg(target_os = "linux")]
-                            if prefer_pipewire == preference_order[0] {
-                                err_fn("default_input_config", err, false);
-                                continue 'pipewire_switch;
+                    let prefer_echo_cancelled = preferences_interface
+                        .lock()
+                        .unwrap()
+                        .preferences
+                        .prefer_echo_cancelled_source
+                        == Some(true);
+                    let device_name =
+                        match echo_cancelled_counterpart(&listed_devices, &device_name) {
+                            Some(echo_cancelled) if prefer_echo_cancelled => {
+                                info!(
+                                    "Recording from the echo-cancelled source {}",
+                                    echo_cancelled.display_name
+                                );
+                                echo_cancelled.inner_name.clone()
                             }
-                            err_fn("default_input_config", err, true);
-                            return;
-                        }
+                            _ => device_name,
+                        };
+
+                    let device: Device = backend.set_device(&host, &device_name);
+
+                    let listed_device = listed_devices
+                        .into_iter()
+                        .find(|item| item.inner_name == device_name);
+                    let is_monitor = listed_device.as_ref().is_some_and(|item| item.is_monitor);
+                    let recognition_source = if is_monitor {
+                        RecognitionSource::Monitor(device_name.clone())
+                    } else {
+                        RecognitionSource::Microphone(device_name.clone())
                     };
-                    let channels = config.channels();
-                    let sample_rate = config.sample_rate();
-
-                    let mut twelve_seconds_buffer = Box::new([0.0f32; 16000 * BUFFER_SIZE_SECS]);
-                    let mut number_unprocessed_samples: usize = 0; // Sample count for the interval of doing Shazam recognition (every 4 seconds)
-                    let mut number_unmeasured_samples: usize = 0; // Sample count for doing volume measurement (every 24th of second)
-
-                    let processing_already_ongoing_2 = processing_already_ongoing.clone();
-
-                    let preferences_interface = preferences_interface.clone();
-                    macro_rules! build_input_streams {
-                        ($($sample_format:tt, $generic:ty);+) => {
-                            match config.sample_format() {
-
-                                // See https://github.com/RustAudio/rodio/blob/a352fb53846b47523d828b276b6d625f251aabb2/src/microphone.rs#L280
-                                // See https://dev.to/sgchris/returning-iterators-from-functions-4cbh
-
-                                cpal::SampleFormat::F32 => match device.build_input_stream(
-                                    config.into(),
-                                    move |data, _: &_| {
-                                        write_data(ProcessingState {
-                                            input_samples: data.into_iter().copied().collect(),
-                                            processing_tx: &processing_tx_2,
-                                            gui_tx: gui_tx_3.clone(),
-                                            channels,
-                                            sample_rate,
-                                            twelve_seconds_buffer: &mut twelve_seconds_buffer,
-                                            number_unprocessed_samples: &mut number_unprocessed_samples,
-                                            number_unmeasured_samples: &mut number_unmeasured_samples,
-                                            processing_already_ongoing: &processing_already_ongoing_2,
-                                            preferences_interface: &preferences_interface,
-                                        })
-                                    },
-                                    err_fn_cb,
-                                    None,
-                                ) {
-                                    Ok(res) => {
-                                        // Re-call the function in the case the backend is PulseBackend,
-                                        // because we may have appeared in the list of PulseAudio's
-                                        // source outputs now
-                                        let microphone_tx = microphone_tx.clone();
-                                        let device_name = device_name.clone();
-                                        glib::source::timeout_add_once(std::time::Duration::from_millis(50), move || {
-                                            microphone_tx
-                                                .try_send(MicrophoneMessage::MicrophoneRecordSetDevice(
-                                                    device_name
-                                                ))
-                                                .unwrap();
-                                        });
-
-                                        res
-                                    },
-                                    Err(err) => {
-                                        #[cfg(all(target_os = "linux"))]
-                                        if prefer_pipewire == preference_order[0] {
-                                            err_fn("build_input_stream", err, false);
-                                            continue 'pipewire_switch;
-                                        }
-                                        err_fn("build_input_stream", err, true);
-                                        return;
-                                    }
-                                },
-                                $(
-                                    cpal::SampleFormat::$sample_format => match device.build_input_stream(
-                                        config.into(),
+
+                    // Try the default configuration, then the other ones
+                    // supported by the device, as some drivers refuse to open
+                    // a stream with the configuration they advertise
+                    let default_config = device.default_input_config();
+                    let supported_ranges: Vec<cpal::SupportedStreamConfigRange> = device
+                        .supported_input_configs()
+                        .map(|ranges| ranges.collect())
+                        .unwrap_or_default();
+                    let candidates = stream_candidates(
+                        default_config.as_ref().ok().cloned(),
+                        supported_ranges.clone(),
+                        capture_buffer_frames(buffer_frames),
+                    );
+
+                    let mut last_error: Option<cpal::Error> = default_config.err();
+                    let mut built_stream: Option<(cpal::Stream, u32)> = None;
+
+                    for candidate in candidates {
+                        let config = &candidate.config;
+                        let stream_config = candidate.stream_config();
+
+                        let mut capture_processor = CaptureProcessor::new(
+                            processing_tx.clone(),
+                            gui_tx.clone(),
+                            config.channels(),
+                            config.sample_rate(),
+                            processing_already_ongoing.clone(),
+                            preferences_interface.clone(),
+                            recognition_source.clone(),
+                        );
+                        if let Some(ref directory) = capture_dump_directory {
+                            capture_processor.dump_to(directory);
+                        }
+                        capture_processor.pre_roll_with(pre_rolling.clone());
+
+                        let err_fn_2 = err_fn.clone();
+                        let err_fn_cb = move |error: cpal::Error| {
+                            err_fn_2("stream error", error, true);
+                        };
+
+                        macro_rules! build_input_stream {
+                            ($($sample_format:tt, $generic:ty);+) => {
+                                match config.sample_format() {
+
+                                    // See https://github.com/RustAudio/rodio/blob/a352fb53846b47523d828b276b6d625f251aabb2/src/microphone.rs#L280
+                                    // See https://dev.to/sgchris/returning-iterators-from-functions-4cbh
+
+                                    cpal::SampleFormat::F32 => device.build_input_stream(
+                                        stream_config,
                                         move |data, _: &_| {
-                                            write_data(ProcessingState {
-                                                input_samples: SampleTypeConverter::<Copied<Iter<$generic>>, f32>::new(data.into_iter().copied()).collect(),
-                                                processing_tx: &processing_tx_2,
-                                                gui_tx: gui_tx_3.clone(),
-                                                channels,
-                                                sample_rate,
-                                                twelve_seconds_buffer: &mut twelve_seconds_buffer,
-                                                number_unprocessed_samples: &mut number_unprocessed_samples,
-                                                number_unmeasured_samples: &mut number_unmeasured_samples,
-                                                processing_already_ongoing: &processing_already_ongoing_2,
-                                                preferences_interface: &preferences_interface,
-                                            })
+                                            capture_processor.write_samples(data.into_iter().copied().collect())
                                         },
                                         err_fn_cb,
                                         None,
-                                    ) {
-                                        Ok(res) => {
-                                            // Re-call the function in the case the backend is PulseBackend,
-                                            // because we may have appeared in the list of PulseAudio's
-                                            // source outputs now
-                                            let microphone_tx = microphone_tx.clone();
-                                            let device_name = device_name.clone();
-                                            glib::source::timeout_add_once(std::time::Duration::from_millis(50), move || {
-                                                microphone_tx
-                                                    .try_send(MicrophoneMessage::MicrophoneRecordSetDevice(
-                                                        device_name
-                                                    ))
-                                                    .unwrap();
-                                            });
-
-                                            res
-                                        },
-                                        Err(err) => {
-                                            #[cfg(all(target_os = "linux"))]
-                                            if prefer_pipewire == preference_order[0] {
-                                                err_fn("build_input_stream", err, false);
-                                                continue 'pipewire_switch;
-                                            }
-                                            err_fn("build_input_stream", err, true);
-                                            return;
-                                        }
-                                    },
-                                )+
-                                _ => unreachable!(),
+                                    ),
+                                    $(
+                                        cpal::SampleFormat::$sample_format => device.build_input_stream(
+                                            stream_config,
+                                            move |data, _: &_| {
+                                                capture_processor.write_samples(SampleTypeConverter::<Copied<Iter<$generic>>, f32>::new(data.into_iter().copied()).collect())
+                                            },
+                                            err_fn_cb,
+                                            None,
+                                        ),
+                                    )+
+                                    // The candidates only have the above formats
+                                    _ => unreachable!(),
+                                }
+                            };
+                        }
+
+                        match build_input_stream!(
+                            F64, f64;
+                            I8, i8;
+                            I16, i16;
+                            I24, cpal::I24;
+                            I32, i32;
+                            I64, i64;
+                            U8, u8;
+                            U16, u16;
+                            U24, cpal::U24;
+                            U32, u32;
+                            U64, u64
+                        ) {
+                            Ok(res) => {
+                                info!("Capturing audio as {}", candidate);
+                                built_stream = Some((res, config.sample_rate()));
+                                break;
                             }
-                        };
+                            Err(err) => {
+                                warn!(
+                                    "Could not capture audio as {}: {:?} - {}",
+                                    candidate,
+                                    err.kind(),
+                                    err.message().unwrap_or_default()
+                                );
+                                last_error = Some(err);
+                            }
+                        }
                     }
 
-                    stream = Some(build_input_streams!(
-                        F64, f64;
-                        I8, i8;
-                        I16, i16;
-                        I24, cpal::I24;
-                        I32, i32;
-                        I64, i64;
-                        U8, u8;
-                        U16, u16;
-                        U24, cpal::U24;
-                        U32, u32;
-                        U64, u64
-                    ));
+                    #[cfg(target_os = "linux")]
+                    let try_next_host = prefer_pipewire == preference_order[0];
+                    #[cfg(not(target_os = "linux"))]
+                    let try_next_host = false;
+
+                    let Some((built_stream, capture_sample_rate)) = built_stream else {
+                        let supported = describe_ranges(&supported_ranges);
+                        match last_error {
+                            Some(err) => {
+                                let location = format!(
+                                    "build_input_stream ({} {})",
+                                    gettext("supported configurations:"),
+                                    supported
+                                );
+                                err_fn(&location, err, !try_next_host);
+                            }
+                            None => {
+                                let error_string = gettext(
+                                    "Audio error: The device supports no usable sample format (%s)",
+                                )
+                                .replace("%s", &supported);
+                                if try_next_host {
+                                    warn!("{}", error_string);
+                                } else {
+                                    gui_tx
+                                        .try_send(GUIMessage::ErrorMessage(error_string))
+                                        .unwrap();
+                                }
+                            }
+                        }
+                        if try_next_host {
+                            continue 'pipewire_switch;
+                        }
+                        return;
+                    };
 
+                    // Re-call the function in the case the backend is PulseBackend,
+                    // because we may have appeared in the list of PulseAudio's
+                    // source outputs now
+                    let microphone_tx = microphone_tx.clone();
+                    let set_device_name = device_name.clone();
+                    glib::source::timeout_add_once(
+                        std::time::Duration::from_millis(50),
+                        move || {
+                            microphone_tx
+                                .try_send(MicrophoneMessage::MicrophoneRecordSetDevice(
+                                    set_device_name,
+                                ))
+                                .unwrap();
+                        },
+                    );
+
+                    stream = Some(built_stream);
                     stream.as_ref().unwrap().play().unwrap();
 
-                    gui_tx_4.try_send(GUIMessage::MicrophoneRecording).unwrap();
+                    if !pre_rolling.load(Ordering::SeqCst) {
+                        gui_tx_4.try_send(GUIMessage::MicrophoneRecording).unwrap();
+                    }
+
+                    if listed_device.is_some_and(|item| item.is_hands_free)
+                        || is_hands_free_device_name(&device_name, capture_sample_rate)
+                    {
+                        warn!(
+                            "{}",
+                            gettext("The audio device is a Bluetooth headset in hands-free mode, its telephone quality audio will rarely be recognized")
+                        );
+                        gui_tx_4.try_send(GUIMessage::HandsFreeCapture).unwrap();
+                    }
+                    current_device_name = Some(device_name);
+                    requested_device_name = Some(asked_device_name);
                 }
 
                 MicrophoneRecordSetDevice(device_name) => {
-                    backend.set_device(&host, &device_name);
+                    if !is_virtual_device(&device_name) {
+                        backend.set_device(&host, &device_name);
+                    }
                 }
 
                 RefreshDevices => {
                     debug!("Refreshing audio devices...");
 
-                    let device_names: Vec<DeviceListItem> = backend.list_devices(&host);
+                    let mut device_names: Vec<DeviceListItem> = backend.list_devices(&host);
+
+                    // Devices appeared or disappeared, the most promising
+                    // one may have changed
+                    if let Some(ref auto_device_name) = auto_device {
+                        if pick_device(&device_names)
+                            .is_some_and(|picked| &picked.inner_name != auto_device_name)
+                        {
+                            requested_device_name = None;
+                            microphone_tx
+                                .try_send(restart_message(AUTO_DEVICE_NAME.to_string()))
+                                .unwrap();
+                        }
+                    }
+                    device_names.insert(0, auto_device_item());
 
                     gui_tx
                         .try_send(GUIMessage::DevicesList(device_names))
@@ -329,106 +765,438 @@ pub fn microphone_thread(
                 }
 
                 MicrophoneRecordStop => {
+                    if warm_start && stream.is_some() {
+                        // Keep filling the pre-roll for the next recognition
+                        pre_rolling.store(true, Ordering::SeqCst);
+                        continue;
+                    }
+                    pre_rolling.store(false, Ordering::SeqCst);
+
                     if let Some(some_stream) = stream {
                         drop(some_stream);
                     }
 
                     stream = None;
+                    virtual_capture = None;
+                    current_device_name = None;
+                    requested_device_name = None;
+                    auto_device = None;
+                }
+
+                EnlargeBuffer => {
+                    let current_buffer_frames = capture_buffer_frames(buffer_frames);
+                    let new_buffer_frames = current_buffer_frames
+                        .map(|frames| frames * 2)
+                        .unwrap_or(FIRST_ENLARGED_BUFFER_FRAMES)
+                        .min(MAX_BUFFER_FRAMES);
+                    if current_buffer_frames == Some(new_buffer_frames) {
+                        warn!("Repeated audio xruns, with the largest capture buffer already");
+                    } else if let Some(ref device_name) = current_device_name {
+                        info!(
+                            "Repeated audio xruns, restarting the capture with a buffer of {} frames",
+                            new_buffer_frames
+                        );
+                        buffer_frames = Some(new_buffer_frames);
+                        requested_device_name = None;
+                        microphone_tx
+                            .try_send(restart_message(device_name.clone()))
+                            .unwrap();
+                    }
                 }
 
                 ProcessingDone => {
                     processing_already_ongoing.store(false, Ordering::SeqCst);
+                    if virtual_capture_ended {
+                        virtual_capture_ended = false;
+                        gui_tx.try_send(GUIMessage::CaptureEnded).unwrap();
+                    }
                 }
+
+                // After the last recognition completes
+                VirtualCaptureEnded => {
+                    if processing_already_ongoing.load(Ordering::SeqCst) {
+                        virtual_capture_ended = true;
+                    } else {
+                        gui_tx.try_send(GUIMessage::CaptureEnded).unwrap();
+                    }
+                }
+
+                DumpCapture(directory) => {
+                    capture_dump_directory = Some(directory);
+                }
+
+                // Turned into the above ones
+                MicrophoneWarmStart(_) => {}
+
+                #[cfg(feature = "gui")]
+                FindOutputMonitor => match backend.default_output_monitor(&host) {
+                    Some(monitor_name) => {
+                        info!("Recording the output of the speakers from {}", monitor_name);
+                        gui_tx
+                            .try_send(GUIMessage::SelectMonitorDevice(monitor_name))
+                            .unwrap();
+                    }
+                    None => {
+                        gui_tx
+                            .try_send(GUIMessage::ErrorMessage(gettext(
+                                "No monitor of the speakers was found, the sound of this computer can't be recorded directly",
+                            )))
+                            .unwrap();
+                    }
+                },
             }
         }
         break;
     }
 }
 
-fn write_data(state: ProcessingState) {
-    // Reassemble data into a 12-second buffer, and do recognition
-    // every 4 seconds if the queue to "processing_tx" is empty
-
-    let input_buffer = rodio::buffer::SamplesBuffer::new(
-        NonZero::new(state.channels).unwrap(),
-        NonZero::new(state.sample_rate).unwrap(),
-        state.input_samples,
-    );
-
-    let converted_file =
-        rodio::source::UniformSourceIterator::new(input_buffer, nz!(1), nz!(16000));
+impl CaptureProcessor {
+    pub fn new(
+        processing_tx: async_channel::Sender<ProcessingMessage>,
+        gui_tx: async_channel::Sender<GUIMessage>,
+        channels: u16,
+        sample_rate: u32,
+        processing_already_ongoing: Arc<AtomicBool>,
+        preferences_interface: Arc<Mutex<PreferencesInterface>>,
+        source: RecognitionSource,
+    ) -> Self {
+        let preprocessing = PreprocessingChain::new(
+            preferences_interface
+                .lock()
+                .unwrap()
+                .preferences
+                .preprocessing
+                .as_deref()
+                .unwrap_or_default(),
+        );
+        let split_channels = SPLIT_CHANNELS.load(Ordering::SeqCst)
+            && matches!(
+                source,
+                RecognitionSource::Microphone(_) | RecognitionSource::Monitor(_)
+            );
+        if split_channels && channels >= 2 && preprocessing.output_channels(channels) < 2 {
+            warn!(
+                "{}",
+                gettext("The downmix of the preprocessing steps leaves one channel, so the channels aren't recognized separately")
+            );
+        }
+        let split_channels = split_channels && preprocessing.output_channels(channels) >= 2;
+        let device_level = match source {
+            RecognitionSource::Microphone(ref device_name)
+            | RecognitionSource::Monitor(ref device_name) => {
+                Some(auto_device::device_level(device_name))
+            }
+            _ => None,
+        };
+        CaptureProcessor {
+            processing_tx,
+            gui_tx,
+            channels,
+            sample_rate,
+            twelve_seconds_buffer: vec![0.0f32; 16000 * BUFFER_SIZE_SECS].into_boxed_slice(),
+            right_channel_buffer: split_channels
+                .then(|| vec![0.0f32; 16000 * BUFFER_SIZE_SECS].into_boxed_slice()),
+            number_unprocessed_samples: 0,
+            number_unmeasured_samples: 0,
+            processing_already_ongoing,
+            preferences_interface,
+            source,
+            dump: None,
+            window_start: Instant::now(),
+            resampling_time: Duration::ZERO,
+            clock_drift: ClockDriftEstimator::new(sample_rate, Instant::now()),
+            preprocessing,
+            realtime_requested: false,
+            pre_rolling: Arc::new(AtomicBool::new(false)),
+            no_audio_detected: false,
+            device_level,
+        }
+    }
 
-    let raw_pcm_samples: Vec<f32> = converted_file.collect();
+    /// Only fill the buffer while the flag is set, sending the first window
+    /// as soon as it is cleared
+    pub fn pre_roll_with(&mut self, pre_rolling: Arc<AtomicBool>) {
+        self.pre_rolling = pre_rolling;
+    }
 
-    let request_interval_secs;
-    {
-        let preferences = &state.preferences_interface.lock().unwrap().preferences;
-        request_interval_secs = preferences.request_interval_secs_v3.unwrap() as usize;
+    /// Also write the samples to a new session file of the directory, see
+    /// `CaptureDump`
+    pub fn dump_to(&mut self, directory: &Path) {
+        let header = CaptureSessionHeader {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            request_interval_secs: self
+                .preferences_interface
+                .lock()
+                .unwrap()
+                .preferences
+                .request_interval_secs_v3
+                .unwrap(),
+            source: self.source.to_string(),
+        };
+        self.dump = Some(CaptureDump::start(directory.to_path_buf(), header));
     }
 
-    let twelve_seconds_buffer = &mut state.twelve_seconds_buffer[..16000 * BUFFER_SIZE_SECS];
+    /// Interleaved samples, at the sample rate and channel count of the stream
+    pub fn write_samples(&mut self, mut input_samples: Vec<f32>) {
+        if !self.realtime_requested {
+            self.realtime_requested = true;
+            if matches!(
+                self.source,
+                RecognitionSource::Microphone(_) | RecognitionSource::Monitor(_)
+            ) {
+                realtime::promote_current_thread();
+            }
+        }
+        LAST_CAPTURE_MILLIS.store(
+            CAPTURE_EPOCH.elapsed().as_millis() as u64,
+            Ordering::Relaxed,
+        );
+
+        if let Some(ref dump) = self.dump {
+            if !dump.write_chunk(&input_samples) {
+                self.dump = None;
+            }
+        }
+        let pre_rolling = self.pre_rolling.load(Ordering::SeqCst);
+        if !pre_rolling
+            && matches!(
+                self.source,
+                RecognitionSource::Microphone(_) | RecognitionSource::Monitor(_)
+            )
+        {
+            session_recording::record_samples(&input_samples, self.channels, self.sample_rate);
+        }
+
+        // Reassemble data into a 12-second buffer, and do recognition
+        // every 4 seconds if the queue to "processing_tx" is empty
 
-    // Update our buffer with data from CPAL
+        let resampling_start = Instant::now();
 
-    if raw_pcm_samples.len() >= 16000 * BUFFER_SIZE_SECS {
-        twelve_seconds_buffer
-            .copy_from_slice(&raw_pcm_samples[raw_pcm_samples.len() - 16000 * BUFFER_SIZE_SECS..]);
-    } else {
-        let latter_data = twelve_seconds_buffer[raw_pcm_samples.len()..].to_vec();
+        // Resample from the rate at which the device really runs
+        let sample_rate = self.clock_drift.observe(
+            input_samples.len() / self.channels as usize,
+            resampling_start,
+        );
 
-        twelve_seconds_buffer[..16000 * BUFFER_SIZE_SECS - raw_pcm_samples.len()]
-            .copy_from_slice(&latter_data);
-        twelve_seconds_buffer[16000 * BUFFER_SIZE_SECS - raw_pcm_samples.len()..]
-            .copy_from_slice(&raw_pcm_samples);
-    }
+        let channels = if self.preprocessing.is_empty() {
+            self.channels
+        } else {
+            self.preprocessing
+                .process(&mut input_samples, self.channels, self.sample_rate)
+        };
 
-    *state.number_unprocessed_samples += raw_pcm_samples.len();
+        let (raw_pcm_samples, right_pcm_samples) = if self.right_channel_buffer.is_some() {
+            let (left, right) = split_channels(&input_samples, channels);
+            (
+                resample_to_16khz_mono(left, 1, sample_rate),
+                Some(resample_to_16khz_mono(right, 1, sample_rate)),
+            )
+        } else {
+            (
+                resample_to_16khz_mono(input_samples, channels, sample_rate),
+                None,
+            )
+        };
 
-    if *state.number_unprocessed_samples >= 16000 * request_interval_secs
-        && !state.processing_already_ongoing.load(Ordering::SeqCst)
-    {
-        if !twelve_seconds_buffer.iter().all(|x| *x == 0.0) {
-            state
-                .processing_tx
-                .try_send(ProcessingMessage::ProcessAudioSamples(
-                    twelve_seconds_buffer.to_vec(),
-                ))
-                .unwrap();
+        self.resampling_time += resampling_start.elapsed();
 
-            state
-                .processing_already_ongoing
-                .store(true, Ordering::SeqCst);
+        let request_interval_secs;
+        {
+            let preferences = &self.preferences_interface.lock().unwrap().preferences;
+            request_interval_secs = preferences
+                .request_interval_secs_v3
+                .unwrap()
+                .max(MIN_REQUEST_INTERVAL_SECS.load(Ordering::SeqCst))
+                as usize;
         }
 
-        *state.number_unprocessed_samples = 0;
-    }
+        let twelve_seconds_buffer = &mut self.twelve_seconds_buffer[..];
 
-    // Do microphone volume measurement every 24th of second (so that we can
-    // update it at 24 FPS) and over the last two 100th of second (so that we
-    // can be sure to measure volume for at most 100 Hz)
+        // Update our buffer with data from CPAL
 
-    *state.number_unmeasured_samples += raw_pcm_samples.len();
+        push_samples(twelve_seconds_buffer, &raw_pcm_samples);
+        if let (Some(buffer), Some(samples)) =
+            (self.right_channel_buffer.as_deref_mut(), right_pcm_samples)
+        {
+            push_samples(buffer, &samples);
+        }
 
-    if *state.number_unmeasured_samples >= 16000 / 24 {
-        let mut max_f32_amplitude = 0.0f32;
+        self.number_unprocessed_samples += raw_pcm_samples.len();
 
-        for item in twelve_seconds_buffer
-            .iter()
-            .take(16000 * BUFFER_SIZE_SECS)
-            .skip(16000 * BUFFER_SIZE_SECS - 16000 / 100 * 2)
+        if pre_rolling {
+            // The capture of the first window is over once it is sent
+            self.window_start = Instant::now();
+            self.resampling_time = Duration::ZERO;
+            return;
+        }
+
+        if self.number_unprocessed_samples >= 16000 * request_interval_secs
+            && !self.processing_already_ongoing.load(Ordering::SeqCst)
         {
-            if item.abs() > max_f32_amplitude {
-                max_f32_amplitude = item.abs();
+            if matches!(
+                self.source,
+                RecognitionSource::Microphone(_) | RecognitionSource::Monitor(_)
+            ) {
+                // Shazam would only answer that it doesn't know the song.
+                // The channels recognized separately are checked once mixed,
+                // as a setup may only use one of them.
+                let audible = match self.right_channel_buffer {
+                    Some(ref right_channel_buffer) => has_signal(
+                        twelve_seconds_buffer
+                            .iter()
+                            .zip(right_channel_buffer.iter())
+                            .map(|(left, right)| (left + right) / 2.0),
+                    ),
+                    None => has_signal(twelve_seconds_buffer.iter().copied()),
+                };
+                if !audible && !self.no_audio_detected {
+                    warn!("{}", gettext("No audio detected, check your input device"));
+                }
+                self.no_audio_detected = !audible;
+                self.gui_tx
+                    .try_send(GUIMessage::NoAudioDetected(self.no_audio_detected))
+                    .unwrap();
             }
+
+            if !self.no_audio_detected && !twelve_seconds_buffer.iter().all(|x| *x == 0.0) {
+                metrics::observe_stage(Stage::CaptureFill, self.window_start.elapsed());
+                metrics::observe_stage(Stage::Resample, self.resampling_time);
+
+                match self.right_channel_buffer {
+                    Some(ref right_channel_buffer) => {
+                        for (channel, buffer) in [&*twelve_seconds_buffer, right_channel_buffer]
+                            .iter()
+                            .enumerate()
+                        {
+                            self.processing_tx
+                                .try_send(ProcessingMessage::ProcessAudioSamples(
+                                    buffer.to_vec(),
+                                    self.source.with_channel(channel),
+                                ))
+                                .unwrap();
+                        }
+                    }
+                    None => {
+                        self.processing_tx
+                            .try_send(ProcessingMessage::ProcessAudioSamples(
+                                twelve_seconds_buffer.to_vec(),
+                                self.source.clone(),
+                            ))
+                            .unwrap();
+                    }
+                }
+
+                self.processing_already_ongoing
+                    .store(true, Ordering::SeqCst);
+            }
+
+            self.number_unprocessed_samples = 0;
+            self.window_start = Instant::now();
+            self.resampling_time = Duration::ZERO;
         }
 
-        state
-            .gui_tx
-            .try_send(GUIMessage::MicrophoneVolumePercent(
-                max_f32_amplitude * 100.0,
-            ))
-            .unwrap();
+        // Do microphone volume measurement every 24th of second (so that we can
+        // update it at 24 FPS) and over the last two 100th of second (so that we
+        // can be sure to measure volume for at most 100 Hz)
+
+        self.number_unmeasured_samples += raw_pcm_samples.len();
+
+        if self.number_unmeasured_samples >= 16000 / 24 {
+            let mut max_f32_amplitude = 0.0f32;
+
+            for item in twelve_seconds_buffer
+                .iter()
+                .take(16000 * BUFFER_SIZE_SECS)
+                .skip(16000 * BUFFER_SIZE_SECS - 16000 / 100 * 2)
+            {
+                if item.abs() > max_f32_amplitude {
+                    max_f32_amplitude = item.abs();
+                }
+            }
+
+            if let Some(ref device_level) = self.device_level {
+                auto_device::record_level(device_level, max_f32_amplitude);
+            }
 
-        *state.number_unmeasured_samples = 0;
+            self.gui_tx
+                .try_send(GUIMessage::MicrophoneVolumePercent(
+                    max_f32_amplitude * 100.0,
+                ))
+                .unwrap();
+
+            self.number_unmeasured_samples = 0;
+        }
+    }
+}
+
+fn resample_to_16khz_mono(samples: Vec<f32>, channels: u16, sample_rate: u32) -> Vec<f32> {
+    let input_buffer = rodio::buffer::SamplesBuffer::new(
+        NonZero::new(channels).unwrap(),
+        NonZero::new(sample_rate).unwrap(),
+        samples,
+    );
+
+    rodio::source::UniformSourceIterator::new(input_buffer, nz!(1), nz!(16000)).collect()
+}
+
+/// The left and right channels of interleaved samples, in a single pass
+/// over them. The buffers are allocated at their final size up front, as
+/// they are handed over to the resampler.
+fn split_channels(samples: &[f32], channels: u16) -> (Vec<f32>, Vec<f32>) {
+    let frames = samples.chunks_exact(channels as usize);
+    let mut left = Vec::with_capacity(frames.len());
+    let mut right = Vec::with_capacity(frames.len());
+    for frame in frames {
+        left.push(frame[0]);
+        right.push(frame[1]);
     }
+    (left, right)
+}
+
+/// Append the samples at the end of the buffer, shifting out the oldest ones
+fn push_samples(buffer: &mut [f32], samples: &[f32]) {
+    if samples.len() >= buffer.len() {
+        buffer.copy_from_slice(&samples[samples.len() - buffer.len()..]);
+    } else {
+        buffer.copy_within(samples.len().., 0);
+        let start = buffer.len() - samples.len();
+        buffer[start..].copy_from_slice(samples);
+    }
+}
+
+#[test]
+fn test_push_samples() {
+    let mut buffer = [1.0, 2.0, 3.0, 4.0];
+    push_samples(&mut buffer, &[5.0]);
+    assert_eq!(buffer, [2.0, 3.0, 4.0, 5.0]);
+    push_samples(&mut buffer, &[6.0, 7.0, 8.0, 9.0, 10.0]);
+    assert_eq!(buffer, [7.0, 8.0, 9.0, 10.0]);
+}
+
+#[test]
+fn test_split_channels() {
+    assert_eq!(
+        split_channels(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 3),
+        (vec![1.0, 4.0], vec![2.0, 5.0])
+    );
+    // An incomplete last frame is left out
+    assert_eq!(split_channels(&[1.0, 2.0, 3.0], 2), (vec![1.0], vec![2.0]));
+
+    let source = RecognitionSource::Microphone("front".to_string()).with_channel(1);
+    assert_eq!(source.channel(), Some("right"));
+    assert_eq!(
+        RecognitionSource::Microphone("front".to_string()).channel(),
+        None
+    );
+}
+
+#[test]
+fn test_xrun_tracker() {
+    let start = Instant::now();
+    let mut tracker = XrunTracker::default();
+    assert_eq!(tracker.record(start), 1);
+    assert_eq!(tracker.record(start + Duration::from_secs(30)), 2);
+    assert_eq!(tracker.record(start + Duration::from_secs(75)), 2);
+    assert_eq!(tracker.record(start + Duration::from_secs(200)), 1);
 }
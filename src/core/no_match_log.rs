@@ -0,0 +1,188 @@
+//! Statistics of the captures that no provider recognized, to diagnose the
+//! setups where most songs go unrecognized (a microphone too far from the
+//! speakers, a saturated input, the wrong device...). Each no-match is
+//! appended to "no_matches.csv", next to the history, with the level and
+//! the duration of the capture but not its audio. The file is written from a
+//! thread of its own, and moved to "no_matches.old.csv" once it is larger
+//! than 1 MiB, so that at most the last two files are kept.
+//!
+//! The outcomes of the last recognitions are also counted per device, and
+//! the GUI shows a hint while most of them are no-matches.
+
+use log::{debug, error, warn};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{LazyLock, Mutex, OnceLock};
+
+use crate::core::thread_messages::RecognitionSource;
+use crate::utils::clock;
+use crate::utils::filesystem_operations::{is_incognito, obtain_no_matches_csv_path};
+
+const SAMPLE_RATE: f32 = 16000.0;
+
+/// Size from which the log is moved aside and started over
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// No-matches waiting to be written; more are dropped
+const PENDING_EVENTS: usize = 16;
+
+/// Number of recognitions of a device over which the rate is computed
+const RECENT_OUTCOMES: usize = 20;
+
+/// No-match rate from which the GUI shows a hint, and under which it hides
+/// it again
+const HIGH_NO_MATCH_RATE: f32 = 0.8;
+const NORMAL_NO_MATCH_RATE: f32 = 0.5;
+
+#[derive(Serialize)]
+struct NoMatchEvent {
+    date: String,
+    source: String,
+    duration_secs: f32,
+    rms_dbfs: f32,
+    peak_dbfs: f32,
+}
+
+fn to_dbfs(amplitude: f32) -> f32 {
+    // -inf can't be written to CSV
+    (20.0 * amplitude.log10()).max(-120.0)
+}
+
+/// The RMS and peak levels of the samples, in dBFS
+fn level_stats(samples: &[f32]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (-120.0, -120.0);
+    }
+    let mean_square =
+        samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+    let peak = samples
+        .iter()
+        .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    (to_dbfs(mean_square.sqrt()), to_dbfs(peak))
+}
+
+fn append_event(event: &NoMatchEvent) -> Result<(), Box<dyn Error>> {
+    let path = obtain_no_matches_csv_path()?;
+    if std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() >= MAX_LOG_BYTES) {
+        std::fs::rename(&path, path.with_file_name("no_matches.old.csv"))?;
+    }
+    let is_new = !path.exists();
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(is_new)
+        .from_writer(file);
+    writer.serialize(event)?;
+    writer.flush()?;
+    Ok(())
+}
+
+static PENDING: OnceLock<SyncSender<NoMatchEvent>> = OnceLock::new();
+
+/// Append a no-match of captured audio to the log
+pub fn record_no_match(samples: &[f32], source: &RecognitionSource) {
+    if is_incognito() {
+        return;
+    }
+    let (rms_dbfs, peak_dbfs) = level_stats(samples);
+    let event = NoMatchEvent {
+        date: clock::now_zoned().to_rfc3339(),
+        source: source.to_string(),
+        duration_secs: samples.len() as f32 / SAMPLE_RATE,
+        rms_dbfs,
+        peak_dbfs,
+    };
+    let pending = PENDING.get_or_init(|| {
+        let (events_tx, events_rx) = sync_channel::<NoMatchEvent>(PENDING_EVENTS);
+        std::thread::spawn(move || {
+            for event in events_rx {
+                if let Err(error) = append_event(&event) {
+                    error!("Could not log the no-match: {}", error);
+                }
+            }
+        });
+        events_tx
+    });
+    if let Err(TrySendError::Full(_)) = pending.try_send(event) {
+        debug!("Not logging the no-match, the previous ones are still being written");
+    }
+}
+
+#[derive(Default)]
+struct DeviceOutcomes {
+    /// Whether each of the last recognitions matched
+    recent: VecDeque<bool>,
+    high_no_match_rate: bool,
+}
+
+impl DeviceOutcomes {
+    /// Returns the new state when the rate became high, or normal again
+    fn record(&mut self, matched: bool) -> Option<bool> {
+        self.recent.push_back(matched);
+        if self.recent.len() > RECENT_OUTCOMES {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < RECENT_OUTCOMES {
+            return None;
+        }
+        let no_matches = self.recent.iter().filter(|matched| !**matched).count();
+        let rate = no_matches as f32 / self.recent.len() as f32;
+        let high_no_match_rate = if self.high_no_match_rate {
+            rate >= NORMAL_NO_MATCH_RATE
+        } else {
+            rate >= HIGH_NO_MATCH_RATE
+        };
+        if high_no_match_rate == self.high_no_match_rate {
+            return None;
+        }
+        self.high_no_match_rate = high_no_match_rate;
+        Some(high_no_match_rate)
+    }
+}
+
+static DEVICE_OUTCOMES: LazyLock<Mutex<HashMap<String, DeviceOutcomes>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Count the outcome of a recognition; returns the device and whether its
+/// no-match rate is abnormally high, when this changed
+pub fn record_outcome(source: &RecognitionSource, matched: bool) -> Option<(String, bool)> {
+    let (RecognitionSource::Microphone(device_name) | RecognitionSource::Monitor(device_name)) =
+        source
+    else {
+        return None;
+    };
+    let high_no_match_rate = DEVICE_OUTCOMES
+        .lock()
+        .unwrap()
+        .entry(device_name.clone())
+        .or_default()
+        .record(matched)?;
+    if high_no_match_rate {
+        warn!(
+            "Most of the songs captured from {} aren't recognized, check the volume of the input and the position of the microphone",
+            device_name
+        );
+    }
+    Some((device_name.clone(), high_no_match_rate))
+}
+
+#[test]
+fn test_no_match_rate() {
+    let mut outcomes = DeviceOutcomes::default();
+    for _ in 0..RECENT_OUTCOMES - 1 {
+        assert_eq!(outcomes.record(false), None);
+    }
+    assert_eq!(outcomes.record(false), Some(true));
+    // It must fall under the lower rate to be normal again
+    for _ in 0..10 {
+        assert_eq!(outcomes.record(true), None);
+    }
+    assert_eq!(outcomes.record(true), Some(false));
+
+    let (rms_dbfs, peak_dbfs) = level_stats(&[0.5, -0.5]);
+    assert!((rms_dbfs + 6.02).abs() < 0.01);
+    assert!((peak_dbfs + 6.02).abs() < 0.01);
+    assert_eq!(level_stats(&[0.0; 4]), (-120.0, -120.0));
+}
@@ -2,10 +2,14 @@ use gettextrs::gettext;
 use log::{debug, error};
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::error::Error;
 use std::path::PathBuf;
 
+use crate::core::preprocessing::PreprocessorConfig;
 use crate::utils::filesystem_operations::obtain_preferences_file_path;
+use crate::utils::open_in::{default_open_in_handlers, OpenInHandler};
+use crate::utils::share_text::DEFAULT_SHARE_TEMPLATE;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
@@ -14,14 +18,68 @@ pub struct Preferences {
     pub enable_systray: Option<bool>,
     pub enable_mpris: Option<bool>, // Legacy, before setting default to true
     pub enable_mpris_v2: Option<bool>,
+    pub enable_kdeconnect: Option<bool>,
+    /// See `speech`
+    pub announce_songs: Option<bool>,
+    pub check_for_updates: Option<bool>,
     pub no_duplicates: Option<bool>,
     pub buffer_size_secs: Option<u64>,         // Removed in 0.7.3
     pub request_interval_secs: Option<u64>,    // Legacy, before increasing default from 4 to 10
     pub request_interval_secs_v2: Option<u64>, // before decreasing from 10 to 8
     pub request_interval_secs_v3: Option<u64>,
     pub current_device_name: Option<String>,
+    pub prefer_echo_cancelled_source: Option<bool>,
+    /// Keep capturing from the selected device while not recognizing, see
+    /// `MicrophoneWarmStart`
+    pub warm_start: Option<bool>,
     pub website_search_url: Option<String>,
     pub website_search_text: Option<String>,
+    pub share_template: Option<String>,
+    /// Webhook to which "Share" also posts the share text (empty for none),
+    /// see `webhook`
+    pub share_webhook_url: Option<String>,
+    /// Retention policy of the song history, enforced at startup (0 when
+    /// unlimited)
+    pub history_max_entries: Option<u32>,
+    pub history_max_months: Option<u32>,
+    /// See `history_encryption`
+    pub encrypt_history: Option<bool>,
+    /// Visible columns of the history list, in order, separated by commas
+    pub history_columns: Option<String>,
+    /// See `open_in`
+    pub open_in_handlers: Option<Vec<OpenInHandler>>,
+    pub prefer_bandcamp: Option<bool>,
+    /// See `clock`
+    pub time_zone: Option<String>,
+    pub history_date_offsets: Option<bool>,
+    /// See `unknown_clips`
+    pub keep_unrecognized_clips: Option<bool>,
+    /// See `resource_limits`
+    pub max_decodes: Option<usize>,
+    pub memory_limit_mb: Option<u64>,
+    pub requests_per_minute: Option<u32>,
+    /// See `transliteration`
+    pub transliteration: Option<String>,
+    pub transliterated_scripts: Option<String>,
+    /// See `text_normalization`
+    pub normalize: Option<String>,
+    /// See `user_agent::ClientIdentity`
+    pub user_agent: Option<String>,
+    /// Size of the main window for each configuration of monitors, see
+    /// `window_state`
+    pub window_states: Option<HashMap<String, WindowState>>,
+    /// See `preprocessing`
+    pub preprocessing: Option<Vec<PreprocessorConfig>>,
+    /// See `recognition_locations`
+    pub recognition_venue: Option<String>,
+    pub attach_geolocation: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WindowState {
+    pub width: i32,
+    pub height: i32,
+    pub maximized: bool,
 }
 
 impl Preferences {
@@ -31,14 +89,41 @@ impl Preferences {
             enable_systray: None,
             enable_mpris: None,
             enable_mpris_v2: None,
+            enable_kdeconnect: None,
+            announce_songs: None,
+            check_for_updates: None,
             no_duplicates: None,
             buffer_size_secs: None,
             request_interval_secs: None,
             request_interval_secs_v2: None,
             request_interval_secs_v3: None,
             current_device_name: None,
+            prefer_echo_cancelled_source: None,
+            warm_start: None,
             website_search_url: None,
             website_search_text: None,
+            share_template: None,
+            share_webhook_url: None,
+            history_max_entries: None,
+            history_max_months: None,
+            encrypt_history: None,
+            history_columns: None,
+            open_in_handlers: None,
+            prefer_bandcamp: None,
+            time_zone: None,
+            history_date_offsets: None,
+            keep_unrecognized_clips: None,
+            max_decodes: None,
+            memory_limit_mb: None,
+            requests_per_minute: None,
+            transliteration: None,
+            transliterated_scripts: None,
+            normalize: None,
+            user_agent: None,
+            window_states: None,
+            preprocessing: None,
+            recognition_venue: None,
+            attach_geolocation: None,
         }
     }
 
@@ -48,14 +133,41 @@ impl Preferences {
             enable_systray: Some(false),
             enable_mpris: None,
             enable_mpris_v2: Some(true),
+            enable_kdeconnect: Some(false),
+            announce_songs: Some(false),
+            check_for_updates: Some(false),
             no_duplicates: Some(false),
             buffer_size_secs: None,
             request_interval_secs: None,
             request_interval_secs_v2: None,
             request_interval_secs_v3: Some(interval),
             current_device_name: None,
+            prefer_echo_cancelled_source: Some(false),
+            warm_start: Some(false),
             website_search_url: Some("https://www.youtube.com/results?search_query=".to_string()),
             website_search_text: Some(gettext("Search on YouTube".to_string())),
+            share_template: Some(DEFAULT_SHARE_TEMPLATE.to_string()),
+            share_webhook_url: Some(String::new()),
+            history_max_entries: Some(0),
+            history_max_months: Some(0),
+            encrypt_history: Some(false),
+            history_columns: None,
+            open_in_handlers: Some(default_open_in_handlers()),
+            prefer_bandcamp: Some(false),
+            time_zone: None,
+            history_date_offsets: Some(false),
+            keep_unrecognized_clips: Some(false),
+            max_decodes: None,
+            memory_limit_mb: None,
+            requests_per_minute: None,
+            transliteration: None,
+            transliterated_scripts: None,
+            normalize: None,
+            user_agent: None,
+            window_states: None,
+            preprocessing: None,
+            recognition_venue: None,
+            attach_geolocation: Some(false),
         }
     }
 }
@@ -67,14 +179,41 @@ impl Default for Preferences {
             enable_systray: Some(false),
             enable_mpris: None,
             enable_mpris_v2: Some(true),
+            enable_kdeconnect: Some(false),
+            announce_songs: Some(false),
+            check_for_updates: Some(false),
             no_duplicates: Some(false),
             buffer_size_secs: None,
             request_interval_secs: None,
             request_interval_secs_v2: None,
             request_interval_secs_v3: Some(8),
             current_device_name: None,
+            prefer_echo_cancelled_source: Some(false),
+            warm_start: Some(false),
             website_search_url: Some("https://www.youtube.com/results?search_query=".to_string()),
             website_search_text: Some(gettext("Search on YouTube".to_string())),
+            share_template: Some(DEFAULT_SHARE_TEMPLATE.to_string()),
+            share_webhook_url: Some(String::new()),
+            history_max_entries: Some(0),
+            history_max_months: Some(0),
+            encrypt_history: Some(false),
+            history_columns: None,
+            open_in_handlers: Some(default_open_in_handlers()),
+            prefer_bandcamp: Some(false),
+            time_zone: None,
+            history_date_offsets: Some(false),
+            keep_unrecognized_clips: Some(false),
+            max_decodes: None,
+            memory_limit_mb: None,
+            requests_per_minute: None,
+            transliteration: None,
+            transliterated_scripts: None,
+            normalize: None,
+            user_agent: None,
+            window_states: None,
+            preprocessing: None,
+            recognition_venue: None,
+            attach_geolocation: Some(false),
         }
     }
 }
@@ -125,6 +264,15 @@ impl PreferencesInterface {
                 .enable_mpris_v2
                 .or(current_preferences.enable_mpris_v2)
                 .or(current_preferences.enable_mpris),
+            enable_kdeconnect: update_preferences
+                .enable_kdeconnect
+                .or(current_preferences.enable_kdeconnect),
+            announce_songs: update_preferences
+                .announce_songs
+                .or(current_preferences.announce_songs),
+            check_for_updates: update_preferences
+                .check_for_updates
+                .or(current_preferences.check_for_updates),
             enable_systray: update_preferences
                 .enable_systray
                 .or(current_preferences.enable_systray),
@@ -150,12 +298,84 @@ impl PreferencesInterface {
             current_device_name: update_preferences
                 .current_device_name
                 .or_else(|| current_preferences.current_device_name.clone()),
+            prefer_echo_cancelled_source: update_preferences
+                .prefer_echo_cancelled_source
+                .or(current_preferences.prefer_echo_cancelled_source),
+            warm_start: update_preferences
+                .warm_start
+                .or(current_preferences.warm_start),
             website_search_url: update_preferences
                 .website_search_url
                 .or_else(|| current_preferences.website_search_url.clone()),
             website_search_text: update_preferences
                 .website_search_text
                 .or_else(|| current_preferences.website_search_text.clone()),
+            share_template: update_preferences
+                .share_template
+                .or_else(|| current_preferences.share_template.clone()),
+            share_webhook_url: update_preferences
+                .share_webhook_url
+                .or_else(|| current_preferences.share_webhook_url.clone()),
+            history_max_entries: update_preferences
+                .history_max_entries
+                .or(current_preferences.history_max_entries),
+            history_max_months: update_preferences
+                .history_max_months
+                .or(current_preferences.history_max_months),
+            encrypt_history: update_preferences
+                .encrypt_history
+                .or(current_preferences.encrypt_history),
+            history_columns: update_preferences
+                .history_columns
+                .or_else(|| current_preferences.history_columns.clone()),
+            open_in_handlers: update_preferences
+                .open_in_handlers
+                .or_else(|| current_preferences.open_in_handlers.clone()),
+            prefer_bandcamp: update_preferences
+                .prefer_bandcamp
+                .or(current_preferences.prefer_bandcamp),
+            time_zone: update_preferences
+                .time_zone
+                .or_else(|| current_preferences.time_zone.clone()),
+            history_date_offsets: update_preferences
+                .history_date_offsets
+                .or(current_preferences.history_date_offsets),
+            keep_unrecognized_clips: update_preferences
+                .keep_unrecognized_clips
+                .or(current_preferences.keep_unrecognized_clips),
+            max_decodes: update_preferences
+                .max_decodes
+                .or(current_preferences.max_decodes),
+            memory_limit_mb: update_preferences
+                .memory_limit_mb
+                .or(current_preferences.memory_limit_mb),
+            requests_per_minute: update_preferences
+                .requests_per_minute
+                .or(current_preferences.requests_per_minute),
+            transliteration: update_preferences
+                .transliteration
+                .or_else(|| current_preferences.transliteration.clone()),
+            transliterated_scripts: update_preferences
+                .transliterated_scripts
+                .or_else(|| current_preferences.transliterated_scripts.clone()),
+            normalize: update_preferences
+                .normalize
+                .or_else(|| current_preferences.normalize.clone()),
+            user_agent: update_preferences
+                .user_agent
+                .or_else(|| current_preferences.user_agent.clone()),
+            window_states: update_preferences
+                .window_states
+                .or_else(|| current_preferences.window_states.clone()),
+            preprocessing: update_preferences
+                .preprocessing
+                .or_else(|| current_preferences.preprocessing.clone()),
+            recognition_venue: update_preferences
+                .recognition_venue
+                .or_else(|| current_preferences.recognition_venue.clone()),
+            attach_geolocation: update_preferences
+                .attach_geolocation
+                .or(current_preferences.attach_geolocation),
         };
         if let Err(error) = self.write() {
             error!("{} {}", gettext("When saving the preferences file:"), error);
@@ -0,0 +1,350 @@
+//! Optional processing of the captured audio before its signature is
+//! computed, for the setups where Shazam struggles with the raw input: a
+//! quiet line input, the rumble of a venue, the hiss of a cassette deck
+//! between songs, or the one good channel of a stereo input. The steps are
+//! listed in order in the preferences file of the profile:
+//!
+//! ```toml
+//! [[preprocessing]]
+//! type = "downmix"
+//! channel = "left"  # or "right", or "mix" (the default)
+//!
+//! [[preprocessing]]
+//! type = "filter"
+//! kind = "highpass"  # or "lowpass"
+//! cutoff_hz = 120.0
+//!
+//! [[preprocessing]]
+//! type = "gain"
+//! db = 6.0
+//!
+//! [[preprocessing]]
+//! type = "denoise"
+//! threshold_db = -50.0
+//! ```
+//!
+//! They apply to the interleaved samples of the stream, at its own sample
+//! rate and before the downmix and resampling to 16 KHz mono, so a step
+//! keeps its state (e.g. the memory of a filter) from one chunk to the next.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Beyond it, a gain is rather a mistake in the preferences
+const MAX_GAIN_DB: f32 = 60.0;
+
+/// A step of the chain
+pub trait Preprocessor: Send {
+    /// Process a chunk of interleaved samples in place; a step may reduce
+    /// the number of channels
+    fn process(&mut self, samples: &mut Vec<f32>, channels: &mut u16, sample_rate: u32);
+
+    /// The number of channels of the output for this number in input
+    fn output_channels(&self, channels: u16) -> u16 {
+        channels
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    Highpass,
+    Lowpass,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DownmixChannel {
+    Left,
+    Right,
+    #[default]
+    Mix,
+}
+
+fn default_threshold_db() -> f32 {
+    -50.0
+}
+
+/// A step of the chain, as written in the preferences
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PreprocessorConfig {
+    Gain {
+        db: f32,
+    },
+    Filter {
+        kind: FilterKind,
+        cutoff_hz: f32,
+    },
+    /// A noise gate muting the parts quieter than the threshold
+    Denoise {
+        #[serde(default = "default_threshold_db")]
+        threshold_db: f32,
+    },
+    Downmix {
+        #[serde(default)]
+        channel: DownmixChannel,
+    },
+}
+
+impl PreprocessorConfig {
+    /// Whether the values of the step make sense; the cutoff frequencies
+    /// above the Nyquist frequency of a stream are only known when it is
+    /// processed, see `Biquad`
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            PreprocessorConfig::Gain { db } if !db.is_finite() || db.abs() > MAX_GAIN_DB => Err(
+                format!("the gain must be between -{0} and {0} dB", MAX_GAIN_DB),
+            ),
+            PreprocessorConfig::Filter { cutoff_hz, .. }
+                if !cutoff_hz.is_finite() || cutoff_hz <= 0.0 =>
+            {
+                Err("the cutoff frequency must be positive".to_string())
+            }
+            PreprocessorConfig::Denoise { threshold_db }
+                if !threshold_db.is_finite() || threshold_db > 0.0 =>
+            {
+                Err("the threshold must be at or below 0 dB".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn Preprocessor> {
+        match *self {
+            PreprocessorConfig::Gain { db } => Box::new(Gain {
+                factor: 10f32.powf(db / 20.0),
+            }),
+            PreprocessorConfig::Filter { kind, cutoff_hz } => Box::new(Biquad {
+                kind,
+                cutoff_hz,
+                sample_rate: 0,
+                coefficients: [0.0; 5],
+                states: vec![],
+            }),
+            PreprocessorConfig::Denoise { threshold_db } => Box::new(NoiseGate {
+                threshold: 10f32.powf(threshold_db / 20.0),
+                gain: 1.0,
+            }),
+            PreprocessorConfig::Downmix { channel } => Box::new(Downmix { channel }),
+        }
+    }
+}
+
+struct Gain {
+    factor: f32,
+}
+
+impl Preprocessor for Gain {
+    fn process(&mut self, samples: &mut Vec<f32>, _: &mut u16, _: u32) {
+        for sample in samples.iter_mut() {
+            *sample = (*sample * self.factor).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// Second-order filter, with the coefficients of the Audio EQ Cookbook
+struct Biquad {
+    kind: FilterKind,
+    cutoff_hz: f32,
+    /// The one the coefficients were computed for
+    sample_rate: u32,
+    /// b0, b1, b2, a1, a2, normalized by a0
+    coefficients: [f32; 5],
+    /// x1, x2, y1, y2 of each channel
+    states: Vec<[f32; 4]>,
+}
+
+impl Biquad {
+    fn update_coefficients(&mut self, sample_rate: u32) {
+        let max_cutoff_hz = sample_rate as f32 * 0.45;
+        if self.cutoff_hz > max_cutoff_hz {
+            warn!(
+                "The cutoff frequency of {} Hz is above the Nyquist frequency of the stream, using {} Hz",
+                self.cutoff_hz, max_cutoff_hz
+            );
+        }
+        let omega =
+            2.0 * std::f32::consts::PI * self.cutoff_hz.min(max_cutoff_hz) / sample_rate as f32;
+        let alpha = omega.sin() / std::f32::consts::SQRT_2;
+        let cos = omega.cos();
+        let (b0, b1, b2) = match self.kind {
+            FilterKind::Highpass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0),
+            FilterKind::Lowpass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0),
+        };
+        let a0 = 1.0 + alpha;
+        self.coefficients = [
+            b0 / a0,
+            b1 / a0,
+            b2 / a0,
+            -2.0 * cos / a0,
+            (1.0 - alpha) / a0,
+        ];
+        self.sample_rate = sample_rate;
+    }
+}
+
+impl Preprocessor for Biquad {
+    fn process(&mut self, samples: &mut Vec<f32>, channels: &mut u16, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.update_coefficients(sample_rate);
+        }
+        self.states.resize(*channels as usize, [0.0; 4]);
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+        for frame in samples.chunks_mut(*channels as usize) {
+            for (sample, state) in frame.iter_mut().zip(self.states.iter_mut()) {
+                let [x1, x2, y1, y2] = *state;
+                let output = b0 * *sample + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+                *state = [*sample, x1, output, y1];
+                *sample = output;
+            }
+        }
+    }
+}
+
+struct NoiseGate {
+    threshold: f32,
+    /// Applied to the current block, moved smoothly to avoid clicks
+    gain: f32,
+}
+
+impl Preprocessor for NoiseGate {
+    fn process(&mut self, samples: &mut Vec<f32>, channels: &mut u16, sample_rate: u32) {
+        // Blocks of 10 ms
+        let block_len = (sample_rate as usize / 100).max(1) * *channels as usize;
+        for block in samples.chunks_mut(block_len) {
+            let rms = (block.iter().map(|sample| sample * sample).sum::<f32>()
+                / block.len() as f32)
+                .sqrt();
+            let target = if rms >= self.threshold { 1.0 } else { 0.0 };
+            let step = (target - self.gain) / block.len() as f32;
+            for sample in block.iter_mut() {
+                self.gain += step;
+                *sample *= self.gain;
+            }
+            self.gain = target;
+        }
+    }
+}
+
+struct Downmix {
+    channel: DownmixChannel,
+}
+
+impl Preprocessor for Downmix {
+    fn process(&mut self, samples: &mut Vec<f32>, channels: &mut u16, _: u32) {
+        let count = *channels as usize;
+        if count < 2 {
+            return;
+        }
+        // In place: the output sample of a frame is never after its input
+        let frames = samples.len().div_ceil(count);
+        for index in 0..frames {
+            let frame = &samples[index * count..samples.len().min((index + 1) * count)];
+            let sample = match self.channel {
+                DownmixChannel::Left => frame[0],
+                DownmixChannel::Right => frame[1.min(frame.len() - 1)],
+                DownmixChannel::Mix => frame.iter().sum::<f32>() / frame.len() as f32,
+            };
+            samples[index] = sample;
+        }
+        samples.truncate(frames);
+        *channels = 1;
+    }
+
+    fn output_channels(&self, _: u16) -> u16 {
+        1
+    }
+}
+
+/// The steps of the preferences, in order
+pub struct PreprocessingChain {
+    steps: Vec<Box<dyn Preprocessor>>,
+}
+
+impl PreprocessingChain {
+    /// The invalid steps are left out, with a warning
+    pub fn new(configs: &[PreprocessorConfig]) -> Self {
+        PreprocessingChain {
+            steps: configs
+                .iter()
+                .filter(|config| match config.validate() {
+                    Ok(()) => true,
+                    Err(error) => {
+                        warn!("Ignoring the preprocessing step {:?}: {}", config, error);
+                        false
+                    }
+                })
+                .map(PreprocessorConfig::build)
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Returns the new number of channels
+    pub fn process(&mut self, samples: &mut Vec<f32>, channels: u16, sample_rate: u32) -> u16 {
+        let mut channels = channels;
+        for step in &mut self.steps {
+            step.process(samples, &mut channels, sample_rate);
+        }
+        channels
+    }
+
+    pub fn output_channels(&self, channels: u16) -> u16 {
+        self.steps
+            .iter()
+            .fold(channels, |channels, step| step.output_channels(channels))
+    }
+}
+
+#[test]
+fn test_preprocessing_chain() {
+    #[derive(Deserialize)]
+    struct Config {
+        preprocessing: Vec<PreprocessorConfig>,
+    }
+    let config: Config = toml::from_str(
+        "[[preprocessing]]\ntype = \"downmix\"\nchannel = \"right\"\n\
+        [[preprocessing]]\ntype = \"gain\"\ndb = 6.0\n\
+        [[preprocessing]]\ntype = \"filter\"\nkind = \"highpass\"\ncutoff_hz = 100.0\n\
+        [[preprocessing]]\ntype = \"denoise\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.preprocessing[3],
+        PreprocessorConfig::Denoise {
+            threshold_db: -50.0
+        }
+    );
+
+    let mut chain = PreprocessingChain::new(&config.preprocessing[..2]);
+    assert_eq!(chain.output_channels(2), 1);
+    let mut samples = vec![0.9, 0.1, -0.9, -0.2];
+    assert_eq!(chain.process(&mut samples, 2, 44100), 1);
+    assert_eq!(samples.len(), 2);
+    assert!((samples[0] - 0.1995).abs() < 0.001);
+
+    // A constant offset is removed by the high-pass filter
+    let mut chain = PreprocessingChain::new(&config.preprocessing[2..3]);
+    let mut samples = vec![0.5; 16000];
+    chain.process(&mut samples, 1, 16000);
+    assert!(samples[15999].abs() < 0.001);
+
+    assert!(PreprocessorConfig::Filter {
+        kind: FilterKind::Lowpass,
+        cutoff_hz: f32::NAN
+    }
+    .validate()
+    .is_err());
+    assert!(PreprocessorConfig::Gain { db: 600.0 }.validate().is_err());
+    assert!(PreprocessingChain::new(&[PreprocessorConfig::Gain { db: -600.0 }]).is_empty());
+
+    // What is under the threshold is muted
+    let mut chain = PreprocessingChain::new(&config.preprocessing[3..]);
+    let mut samples = vec![0.001; 1600];
+    chain.process(&mut samples, 1, 16000);
+    assert!(samples[800..].iter().all(|sample| *sample == 0.0));
+}
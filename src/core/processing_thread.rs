@@ -1,6 +1,15 @@
+use gettextrs::gettext;
+use log::warn;
+use std::path::Path;
+use std::time::Duration;
+
 use crate::core::thread_messages::{ProcessingMessage::*, *};
 
 use crate::core::fingerprinting::algorithm::SignatureGenerator;
+use crate::core::fingerprinting::local_index::list_audio_files;
+use crate::core::metrics;
+use crate::core::tasks;
+use crate::core::trace_profile;
 
 pub fn processing_thread(
     processing_rx: async_channel::Receiver<ProcessingMessage>,
@@ -8,22 +17,34 @@ pub fn processing_thread(
     gui_tx: async_channel::Sender<GUIMessage>,
 ) {
     while let Ok(message) = processing_rx.recv_blocking() {
-        let signature = match message {
-            ProcessAudioFile(input_file_string) => {
-                SignatureGenerator::make_signature_from_file(&input_file_string)
-            }
-            ProcessAudioSamples(audio_samples) => Ok(
-                SignatureGenerator::make_signature_from_buffer(&audio_samples),
+        let _span = trace_profile::span("signature", "pipeline");
+        let (signature, source, samples) = match message {
+            ProcessAudioFile(input_file_string) => (
+                SignatureGenerator::make_signature_from_file(&input_file_string),
+                RecognitionSource::File(input_file_string),
+                None,
+            ),
+            ProcessAudioSamples(audio_samples, source) => (
+                Ok(SignatureGenerator::make_signature_from_buffer(
+                    &audio_samples,
+                )),
+                source,
+                Some(audio_samples),
             ),
         };
 
         match signature {
             Ok(signature) => {
                 http_tx
-                    .try_send(HTTPMessage::RecognizeSignature(Box::new(signature)))
+                    .try_send(HTTPMessage::RecognizeSignature(
+                        Box::new(signature),
+                        source,
+                        samples,
+                    ))
                     .unwrap();
             }
             Err(error) => {
+                metrics::record_signature_error();
                 gui_tx
                     .try_send(GUIMessage::ErrorMessage(error.to_string()))
                     .unwrap();
@@ -33,3 +54,59 @@ pub fn processing_thread(
 
     processing_rx.close();
 }
+
+/// Recognize all the audio files of a directory, as a cancellable task.
+/// The next file is only fingerprinted once the recognition of the previous
+/// ones is under way, so that the progress follows the recognitions and a
+/// cancelled task doesn't leave a long queue of requests behind.
+pub fn recognize_directory(
+    directory: &Path,
+    http_tx: async_channel::Sender<HTTPMessage>,
+    gui_tx: async_channel::Sender<GUIMessage>,
+) {
+    let task = tasks::start(
+        &gettext("Recognizing the files of %s").replace(
+            "%s",
+            &directory
+                .file_name()
+                .unwrap_or(directory.as_os_str())
+                .to_string_lossy(),
+        ),
+    );
+    let files = match list_audio_files(directory) {
+        Ok(files) => files,
+        Err(error) => {
+            gui_tx
+                .try_send(GUIMessage::ErrorMessage(error.to_string()))
+                .unwrap();
+            return;
+        }
+    };
+    task.set_total(files.len());
+
+    for file in files {
+        while http_tx.len() > 1 && !task.is_cancelled() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        if task.is_cancelled() {
+            break;
+        }
+        let file = file.to_string_lossy().into_owned();
+        match SignatureGenerator::make_signature_from_file(&file) {
+            Ok(signature) => {
+                http_tx
+                    .try_send(HTTPMessage::RecognizeSignature(
+                        Box::new(signature),
+                        RecognitionSource::File(file),
+                        None,
+                    ))
+                    .unwrap();
+            }
+            Err(error) => {
+                metrics::record_signature_error();
+                warn!("{}: {}", file, error);
+            }
+        }
+        task.advance();
+    }
+}
@@ -0,0 +1,128 @@
+//! Recognition providers which may be queried with a signature. Besides
+//! Shazam, the offline index of the user's own files (see `local_index`)
+//! can identify songs that Shazam does not know, from the tags of the
+//! matching file.
+
+use gettextrs::gettext;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::ItemKey;
+use serde_json::json;
+use std::error::Error;
+use std::str::FromStr;
+
+use crate::core::fingerprinting::local_index::{landmarks_from_signature, LocalIndex};
+use crate::core::fingerprinting::signature_format::DecodedSignature;
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::utils::track_identifiers::{normalize_isrc, normalize_upc};
+
+/// Minimal number of matching landmarks for a local match to be trusted
+const LOCAL_INDEX_MIN_MATCHES: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    Shazam,
+    LocalIndex,
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "shazam" => Ok(Provider::Shazam),
+            "local" => Ok(Provider::LocalIndex),
+            _ => Err(format!("Unknown recognition provider: {}", name)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProviderSettings {
+    /// Providers, by order of preference
+    pub providers: Vec<Provider>,
+    /// Query all the providers concurrently and keep the first match,
+    /// rather than trying them one after the other
+    pub race: bool,
+}
+
+impl Default for ProviderSettings {
+    fn default() -> Self {
+        ProviderSettings {
+            providers: vec![Provider::Shazam],
+            race: false,
+        }
+    }
+}
+
+impl ProviderSettings {
+    pub fn uses(&self, provider: Provider) -> bool {
+        self.providers.contains(&provider)
+    }
+}
+
+/// Identify a signature from the local index, describing the song with the
+/// tags of the matching file. This is CPU-bound, so it should be called
+/// outside of the main loop.
+pub fn recognize_from_local_index(
+    index: &LocalIndex,
+    signature: &DecodedSignature,
+) -> Result<SongRecognizedMessage, Box<dyn Error>> {
+    let query = landmarks_from_signature(signature);
+    let found = index
+        .search(&query, None, LOCAL_INDEX_MIN_MATCHES)
+        .into_iter()
+        .next()
+        .ok_or_else(|| gettext("No match for this song"))?;
+    let path = &index.files[found.file_index].path;
+
+    let tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    let tag = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag());
+
+    let fallback_title = || {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    let isrc = tag
+        .and_then(|tag| tag.get_string(&ItemKey::Isrc))
+        .and_then(normalize_isrc);
+    let upc = tag
+        .and_then(|tag| tag.get_string(&ItemKey::Barcode))
+        .and_then(normalize_upc);
+
+    Ok(SongRecognizedMessage {
+        artist_name: tag
+            .and_then(|tag| tag.artist().map(|value| value.to_string()))
+            .unwrap_or_default(),
+        album_name: tag.and_then(|tag| tag.album().map(|value| value.to_string())),
+        song_name: tag
+            .and_then(|tag| tag.title().map(|value| value.to_string()))
+            .unwrap_or_else(fallback_title),
+        cover_image: tag
+            .and_then(|tag| tag.pictures().first())
+            .map(|picture| picture.data().to_vec()),
+        cover_url: None,
+        track_key: format!("local:{}", path.display()),
+        release_year: tag.and_then(|tag| {
+            tag.get_string(&ItemKey::Year)
+                .map(|value| value.to_string())
+        }),
+        genre: tag.and_then(|tag| tag.genre().map(|value| value.to_string())),
+        shazam_json: json!({
+            "provider": "local",
+            "file": path,
+            "offset_secs": found.offset_secs(),
+            "matching_landmarks": found.matching_landmarks,
+            "isrc": isrc,
+            "upc": upc,
+        })
+        .to_string(),
+        isrc,
+        upc,
+        source: None,
+    })
+}
@@ -0,0 +1,474 @@
+//! Where the songs of the history were heard, for the users who log songs
+//! across venues or trips. When the GUI has a venue name in its preferences,
+//! or the coordinates of this computer from GeoClue (only once the user
+//! enabled it, GeoClue then asking for their consent in turn), each new
+//! entry of the history gets a row in "recognition_locations.csv", next to
+//! the history, rather than new columns of the history itself. The rows are
+//! matched to the entries by their recognition date and song name, and
+//! exported as the waypoints of a GPX file (see `history_export`), and
+//! deleted along with their entries once these leave both the history and
+//! its recycle bin.
+//!
+//! The file is encrypted along with the history, see `history_encryption`.
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Mutex, OnceLock};
+
+use crate::utils::clock;
+use crate::utils::csv_song_history::{DeletedSongHistoryRecord, SongHistoryRecord};
+use crate::utils::filesystem_operations::{
+    is_incognito, obtain_recognition_history_csv_path, obtain_recognition_locations_csv_path,
+    read_only_history,
+};
+use crate::utils::history_encryption::{read_history_file, write_history_file};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct RecognitionLocation {
+    pub recognition_date: String,
+    pub song_name: String,
+    #[serde(default)]
+    pub venue: Option<String>,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+}
+
+impl RecognitionLocation {
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        self.latitude.zip(self.longitude)
+    }
+
+    fn entry(&self) -> EntryKey {
+        (self.recognition_date.clone(), self.song_name.clone())
+    }
+}
+
+/// The recognition date and song name of an entry of the history
+pub type EntryKey = (String, String);
+
+pub fn entry_key(record: &SongHistoryRecord) -> EntryKey {
+    (record.recognition_date.clone(), record.song_name.clone())
+}
+
+/// The last coordinates received from GeoClue, while it is enabled
+static COORDINATES: Mutex<Option<(f64, f64)>> = Mutex::new(None);
+
+pub fn current_coordinates() -> Option<(f64, f64)> {
+    *COORDINATES.lock().unwrap()
+}
+
+fn parse_locations(contents: &[u8]) -> Vec<RecognitionLocation> {
+    csv::Reader::from_reader(contents)
+        .deserialize()
+        .filter_map(|row| match row {
+            Ok(location) => Some(location),
+            Err(error) => {
+                warn!("Ignoring an invalid recognition location: {}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn load_locations() -> Vec<RecognitionLocation> {
+    let Ok(path) = obtain_recognition_locations_csv_path() else {
+        return vec![];
+    };
+    match read_history_file(&path) {
+        Ok(contents) => parse_locations(&contents),
+        Err(_) => vec![],
+    }
+}
+
+/// The locations, by recognition date and song name
+pub fn locations_by_entry() -> HashMap<EntryKey, RecognitionLocation> {
+    load_locations()
+        .into_iter()
+        .map(|location| (location.entry(), location))
+        .collect()
+}
+
+/// The existing locations, without losing them to a file that can't be
+/// read for now (e.g. while the keyring is locked) on the next write
+fn read_locations() -> Result<Vec<RecognitionLocation>, Box<dyn Error>> {
+    let path = obtain_recognition_locations_csv_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    Ok(parse_locations(&read_history_file(&path)?))
+}
+
+fn write_locations(locations: &[RecognitionLocation]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for location in locations {
+        writer.serialize(location)?;
+    }
+    write_history_file(
+        &obtain_recognition_locations_csv_path()?,
+        &writer.into_inner()?,
+    )
+}
+
+fn append_location(location: RecognitionLocation) -> Result<(), Box<dyn Error>> {
+    let mut locations = read_locations()?;
+    locations.push(location);
+    write_locations(&locations)
+}
+
+/// Delete the locations of the entries that are not in `kept`, writing the
+/// file only when there are some
+fn retain_locations(kept: &HashSet<EntryKey>) -> Result<(), Box<dyn Error>> {
+    if read_only_history().is_some() || !obtain_recognition_locations_csv_path()?.exists() {
+        return Ok(());
+    }
+    let mut locations = read_locations()?;
+    let count = locations.len();
+    locations.retain(|location| kept.contains(&location.entry()));
+    if locations.len() == count {
+        return Ok(());
+    }
+    write_locations(&locations)
+}
+
+/// Follow an entry whose date or song name was corrected
+fn move_location(from: &EntryKey, to: &EntryKey) -> Result<(), Box<dyn Error>> {
+    if read_only_history().is_some() || !obtain_recognition_locations_csv_path()?.exists() {
+        return Ok(());
+    }
+    let mut locations = read_locations()?;
+    let mut moved = false;
+    for location in locations
+        .iter_mut()
+        .filter(|location| location.entry() == *from)
+    {
+        (location.recognition_date, location.song_name) = to.clone();
+        moved = true;
+    }
+    if !moved {
+        return Ok(());
+    }
+    write_locations(&locations)
+}
+
+/// The entries of the recycle bin that can still be restored, which keep
+/// their locations
+fn recycled_entries() -> Result<HashSet<EntryKey>, Box<dyn Error>> {
+    let path = obtain_recognition_history_csv_path()?.with_file_name("deleted_songs.csv");
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    let now = clock::now();
+    Ok(csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(read_history_file(&path)?.as_slice())
+        .deserialize::<DeletedSongHistoryRecord>()
+        .filter_map(Result::ok)
+        .filter(|deleted| !deleted.is_expired(now))
+        .map(|deleted| entry_key(&deleted.to_record()))
+        .collect())
+}
+
+/// Delete the locations of the entries removed from the history by the
+/// command line, once it was written
+pub fn prune_locations(history: &[SongHistoryRecord]) -> Result<(), Box<dyn Error>> {
+    let mut kept = recycled_entries()?;
+    kept.extend(history.iter().map(entry_key));
+    retain_locations(&kept)
+}
+
+enum LocationTask {
+    Append(RecognitionLocation),
+    Retain(HashSet<EntryKey>),
+    Move(EntryKey, EntryKey),
+    Rewrite,
+}
+
+/// The GUI saves the locations from a thread of their own, reading and
+/// writing the whole file (possibly decrypting and encrypting it) in turn
+static TASKS: OnceLock<Sender<LocationTask>> = OnceLock::new();
+
+fn queue(task: LocationTask) {
+    let tasks = TASKS.get_or_init(|| {
+        let (tasks_tx, tasks_rx) = channel();
+        std::thread::spawn(move || {
+            for task in tasks_rx {
+                let result = match task {
+                    LocationTask::Append(location) => append_location(location),
+                    LocationTask::Retain(kept) => retain_locations(&kept),
+                    LocationTask::Move(from, to) => move_location(&from, &to),
+                    LocationTask::Rewrite => rewrite_locations(),
+                };
+                if let Err(error) = result {
+                    error!("Could not save the locations of the songs: {}", error);
+                }
+            }
+        });
+        tasks_tx
+    });
+    // The thread never stops
+    tasks.send(task).unwrap();
+}
+
+/// Delete the locations of the entries that left the history of the GUI
+/// and its recycle bin, i.e. the ones not in `kept`
+pub fn queue_prune(kept: HashSet<EntryKey>) {
+    queue(LocationTask::Retain(kept));
+}
+
+/// Keep the location of an entry corrected in the GUI
+pub fn queue_move(from: EntryKey, to: EntryKey) {
+    if from != to {
+        queue(LocationTask::Move(from, to));
+    }
+}
+
+/// Write the file again from the GUI, once the encryption of the history
+/// was turned on or off
+pub fn queue_rewrite() {
+    queue(LocationTask::Rewrite);
+}
+
+/// Write the file again, once the encryption of the history was turned on
+/// or off
+fn rewrite_locations() -> Result<(), Box<dyn Error>> {
+    let path = obtain_recognition_locations_csv_path()?;
+    if !path.exists() {
+        return Ok(());
+    }
+    write_locations(&read_locations()?)
+}
+
+/// Remember where a new entry of the history was heard, if this is known
+pub fn record_location(record: &SongHistoryRecord, venue: Option<&str>) {
+    let venue = venue
+        .map(str::trim)
+        .filter(|venue| !venue.is_empty())
+        .map(str::to_string);
+    let coordinates = current_coordinates();
+    if is_incognito() || read_only_history().is_some() || (venue.is_none() && coordinates.is_none())
+    {
+        return;
+    }
+    let location = RecognitionLocation {
+        recognition_date: record.recognition_date.clone(),
+        song_name: record.song_name.clone(),
+        venue,
+        latitude: coordinates.map(|(latitude, _)| latitude),
+        longitude: coordinates.map(|(_, longitude)| longitude),
+    };
+    queue(LocationTask::Append(location));
+}
+
+/// Follow the location of this computer through GeoClue, or stop
+pub fn set_geolocation(enabled: bool) {
+    #[cfg(target_os = "linux")]
+    geoclue::set_enabled(enabled);
+    #[cfg(not(target_os = "linux"))]
+    if enabled {
+        warn!("Geolocation requires GeoClue, which is only available on Linux");
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod geoclue {
+    use glib::variant::ToVariant;
+    use log::{debug, info, warn};
+    use soup::gio;
+    use std::error::Error;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use super::COORDINATES;
+
+    const GEOCLUE_SERVICE: &str = "org.freedesktop.GeoClue2";
+    const MANAGER_PATH: &str = "/org/freedesktop/GeoClue2/Manager";
+    const MANAGER_INTERFACE: &str = "org.freedesktop.GeoClue2.Manager";
+    const CLIENT_INTERFACE: &str = "org.freedesktop.GeoClue2.Client";
+    const LOCATION_INTERFACE: &str = "org.freedesktop.GeoClue2.Location";
+
+    /// GCLUE_ACCURACY_LEVEL_STREET, enough to tell venues apart
+    const ACCURACY_LEVEL: u32 = 6;
+
+    const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+    /// Before trying again after an error, e.g. while GeoClue restarts
+    const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+
+    pub fn set_enabled(enabled: bool) {
+        ENABLED.store(enabled, Ordering::SeqCst);
+        if !enabled {
+            *COORDINATES.lock().unwrap() = None;
+        } else if !RUNNING.swap(true, Ordering::SeqCst) {
+            std::thread::spawn(|| {
+                let mut retry_interval = POLL_INTERVAL;
+                while ENABLED.load(Ordering::SeqCst) {
+                    match follow_location() {
+                        Ok(()) => retry_interval = POLL_INTERVAL,
+                        Err(error) => {
+                            warn!("Could not obtain the location from GeoClue: {}", error);
+                            std::thread::sleep(retry_interval);
+                            retry_interval = (retry_interval * 2).min(MAX_RETRY_INTERVAL);
+                        }
+                    }
+                }
+                RUNNING.store(false, Ordering::SeqCst);
+            });
+        }
+    }
+
+    fn call(
+        connection: &gio::DBusConnection,
+        path: &str,
+        interface: &str,
+        method: &str,
+        parameters: glib::Variant,
+    ) -> Result<glib::Variant, Box<dyn Error>> {
+        Ok(connection.call_sync(
+            Some(GEOCLUE_SERVICE),
+            path,
+            interface,
+            method,
+            Some(&parameters),
+            None,
+            gio::DBusCallFlags::NONE,
+            // GeoClue may wait for the user to grant the access
+            60_000,
+            None::<&gio::Cancellable>,
+        )?)
+    }
+
+    fn property<T: glib::variant::FromVariant>(
+        connection: &gio::DBusConnection,
+        path: &str,
+        interface: &str,
+        name: &str,
+    ) -> Result<T, Box<dyn Error>> {
+        call(
+            connection,
+            path,
+            "org.freedesktop.DBus.Properties",
+            "Get",
+            (interface, name).into(),
+        )?
+        .child_value(0)
+        .as_variant()
+        .and_then(|value| value.get::<T>())
+        .ok_or_else(|| format!("Invalid {} property", name).into())
+    }
+
+    fn set_property(
+        connection: &gio::DBusConnection,
+        path: &str,
+        name: &str,
+        value: glib::Variant,
+    ) -> Result<(), Box<dyn Error>> {
+        call(
+            connection,
+            path,
+            "org.freedesktop.DBus.Properties",
+            "Set",
+            (CLIENT_INTERFACE, name, value).into(),
+        )?;
+        Ok(())
+    }
+
+    fn follow_location() -> Result<(), Box<dyn Error>> {
+        let connection = gio::bus_get_sync(gio::BusType::System, None::<&gio::Cancellable>)?;
+        let client_path = call(
+            &connection,
+            MANAGER_PATH,
+            MANAGER_INTERFACE,
+            "GetClient",
+            ().to_variant(),
+        )?
+        .child_value(0)
+        .get::<glib::variant::ObjectPath>()
+        .ok_or("Invalid GeoClue client")?;
+        let client_path = client_path.as_str();
+
+        // GeoClue asks its agent for the consent of the user to this
+        // desktop file
+        let desktop_id = glib::prgname().unwrap_or_default().to_string();
+        set_property(
+            &connection,
+            client_path,
+            "DesktopId",
+            desktop_id.to_variant(),
+        )?;
+        set_property(
+            &connection,
+            client_path,
+            "RequestedAccuracyLevel",
+            ACCURACY_LEVEL.to_variant(),
+        )?;
+        call(
+            &connection,
+            client_path,
+            CLIENT_INTERFACE,
+            "Start",
+            ().to_variant(),
+        )?;
+        info!("Following the location of this computer through GeoClue");
+
+        let mut last_location = String::new();
+        while ENABLED.load(Ordering::SeqCst) {
+            let location_path: glib::variant::ObjectPath =
+                property(&connection, client_path, CLIENT_INTERFACE, "Location")?;
+            if location_path.as_str() != "/" && location_path.as_str() != last_location {
+                let latitude: f64 = property(
+                    &connection,
+                    location_path.as_str(),
+                    LOCATION_INTERFACE,
+                    "Latitude",
+                )?;
+                let longitude: f64 = property(
+                    &connection,
+                    location_path.as_str(),
+                    LOCATION_INTERFACE,
+                    "Longitude",
+                )?;
+                debug!("GeoClue location: {}, {}", latitude, longitude);
+                // Unless it was disabled meanwhile
+                if ENABLED.load(Ordering::SeqCst) {
+                    *COORDINATES.lock().unwrap() = Some((latitude, longitude));
+                }
+                last_location = location_path.as_str().to_string();
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        call(
+            &connection,
+            client_path,
+            CLIENT_INTERFACE,
+            "Stop",
+            ().to_variant(),
+        )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_locations() {
+    let locations = parse_locations(
+        concat!(
+            "recognition_date,song_name,venue,latitude,longitude\n",
+            "Sat Aug 17 22:44:43 2024,Daft Punk - Da Funk,Le Rex,48.8707,2.3477\n",
+            "Sat Aug 17 23:02:10 2024,Justice - D.A.N.C.E.,Le Rex,,\n",
+            "invalid\n",
+        )
+        .as_bytes(),
+    );
+    assert_eq!(locations.len(), 2);
+    assert_eq!(locations[0].coordinates(), Some((48.8707, 2.3477)));
+    assert_eq!(locations[1].venue.as_deref(), Some("Le Rex"));
+    assert_eq!(locations[1].coordinates(), None);
+}
@@ -0,0 +1,163 @@
+//! Limits shared by the operations that can run for a long time on many
+//! inputs (`index` and library scans, stream monitoring, recognitions), so
+//! that SongRec stays usable on small machines such as a NAS. They are set
+//! with `--max-decodes`, `--memory-limit` and `--requests-per-minute`, or
+//! the matching preferences, and are all unlimited by default.
+
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Default)]
+pub struct ResourceLimits {
+    /// Audio files or stream chunks decoded at the same time
+    pub max_decodes: Option<usize>,
+    /// Memory used by the decoded audio, and by FFMpeg
+    pub memory_limit_mb: Option<u64>,
+    /// Recognition requests sent to Shazam
+    pub requests_per_minute: Option<u32>,
+}
+
+// Zero means unlimited
+static MAX_DECODES: AtomicUsize = AtomicUsize::new(0);
+static MEMORY_LIMIT: AtomicU64 = AtomicU64::new(0);
+static REQUESTS_PER_MINUTE: AtomicU32 = AtomicU32::new(0);
+
+static RUNNING_DECODES: Mutex<usize> = Mutex::new(0);
+static DECODE_FINISHED: Condvar = Condvar::new();
+
+/// Times of the requests sent during the last minute
+static REQUEST_TIMES: Mutex<VecDeque<Instant>> = Mutex::new(VecDeque::new());
+
+const REQUEST_WINDOW: Duration = Duration::from_secs(60);
+
+pub fn set_resource_limits(limits: ResourceLimits) {
+    MAX_DECODES.store(limits.max_decodes.unwrap_or(0), Ordering::SeqCst);
+    MEMORY_LIMIT.store(
+        limits.memory_limit_mb.unwrap_or(0).saturating_mul(1 << 20),
+        Ordering::SeqCst,
+    );
+    REQUESTS_PER_MINUTE.store(limits.requests_per_minute.unwrap_or(0), Ordering::SeqCst);
+}
+
+/// In bytes
+pub fn memory_limit() -> Option<u64> {
+    Some(MEMORY_LIMIT.load(Ordering::Relaxed)).filter(|limit| *limit != 0)
+}
+
+/// Held while decoding
+pub struct DecodePermit(());
+
+impl Drop for DecodePermit {
+    fn drop(&mut self) {
+        *RUNNING_DECODES.lock().unwrap() -= 1;
+        DECODE_FINISHED.notify_one();
+    }
+}
+
+/// Wait until fewer than the maximum number of decodes are running; this
+/// blocks, so it must not be called from the GLib main loop
+pub fn acquire_decode() -> DecodePermit {
+    let mut running = RUNNING_DECODES.lock().unwrap();
+    loop {
+        let max_decodes = MAX_DECODES.load(Ordering::Relaxed);
+        if max_decodes == 0 || *running < max_decodes {
+            break;
+        }
+        running = DECODE_FINISHED.wait(running).unwrap();
+    }
+    *running += 1;
+    DecodePermit(())
+}
+
+/// The number of samples that a decode may keep in memory, the memory
+/// limit being shared between the concurrent decodes
+pub fn max_decoded_samples() -> Option<usize> {
+    let decodes = MAX_DECODES.load(Ordering::Relaxed).max(1) as u64;
+    memory_limit().map(|limit| (limit / decodes) as usize / std::mem::size_of::<f32>())
+}
+
+/// Collect decoded samples, stopping at the memory limit
+pub fn collect_samples(samples: impl Iterator<Item = f32>, file_name: &str) -> Vec<f32> {
+    match max_decoded_samples() {
+        Some(max_samples) => {
+            let samples: Vec<f32> = samples.take(max_samples).collect();
+            if samples.len() == max_samples {
+                warn!(
+                    "{} is too long for the memory limit, only its beginning is used",
+                    file_name
+                );
+            }
+            samples
+        }
+        None => samples.collect(),
+    }
+}
+
+/// How long to wait before sending a request, or None if it can be sent
+/// now, in which case it is counted
+fn request_delay(
+    times: &mut VecDeque<Instant>,
+    requests_per_minute: u32,
+    now: Instant,
+) -> Option<Duration> {
+    while times
+        .front()
+        .is_some_and(|time| now.duration_since(*time) >= REQUEST_WINDOW)
+    {
+        times.pop_front();
+    }
+    if requests_per_minute == 0 || times.len() < requests_per_minute as usize {
+        times.push_back(now);
+        return None;
+    }
+    Some(REQUEST_WINDOW - now.duration_since(times[0]))
+}
+
+/// Wait until a request can be sent under the request rate limit
+pub async fn wait_for_request_slot() {
+    loop {
+        let delay = request_delay(
+            &mut REQUEST_TIMES.lock().unwrap(),
+            REQUESTS_PER_MINUTE.load(Ordering::Relaxed),
+            Instant::now(),
+        );
+        match delay {
+            Some(delay) => {
+                debug!(
+                    "Request rate limit reached, waiting {} ms",
+                    delay.as_millis()
+                );
+                glib::timeout_future(delay).await;
+            }
+            None => return,
+        }
+    }
+}
+
+#[test]
+fn test_request_delay() {
+    let start = Instant::now();
+    let mut times = VecDeque::new();
+    assert_eq!(request_delay(&mut times, 2, start), None);
+    assert_eq!(
+        request_delay(&mut times, 2, start + Duration::from_secs(10)),
+        None
+    );
+    assert_eq!(
+        request_delay(&mut times, 2, start + Duration::from_secs(20)),
+        Some(Duration::from_secs(40))
+    );
+    // The first request left the window
+    assert_eq!(
+        request_delay(&mut times, 2, start + Duration::from_secs(60)),
+        None
+    );
+    assert_eq!(times.len(), 2);
+    assert_eq!(
+        request_delay(&mut times, 0, start + Duration::from_secs(61)),
+        None
+    );
+}
@@ -0,0 +1,214 @@
+//! Progress of the "scan" subcommand, so that an interrupted scan of a large
+//! library resumes where it stopped. Each file is appended to a log of the
+//! "scans" folder of the data directory once it is fingerprinted, with its
+//! signature, and again once Shazam answered for it. A new scan of the same
+//! directory then replays the results of the recognized files, and only
+//! sends the stored signatures of the others, without decoding them again.
+//! The files which changed since are processed again, and the log is
+//! removed once all the files were recognized. Nothing is written in
+//! incognito mode.
+
+use log::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::core::fingerprinting::signature_format::DecodedSignature;
+use crate::utils::filesystem_operations::{is_incognito, obtain_scans_directory};
+
+/// An element of the output of "scan --json", one per line
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ScanResult {
+    pub file: PathBuf,
+    pub track_key: Option<String>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    /// Why the file couldn't be recognized, if so; the other fields are
+    /// empty when it wasn't recognized
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProgressLine {
+    file: PathBuf,
+    size: u64,
+    modified_secs: u64,
+    /// As a data URI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<ScanResult>,
+}
+
+fn size_and_modification(file: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(file).ok()?;
+    let modified_secs = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    Some((metadata.len(), modified_secs))
+}
+
+pub struct ScanProgress {
+    path: PathBuf,
+    /// The last state of each file
+    entries: HashMap<PathBuf, ProgressLine>,
+    writer: Option<File>,
+}
+
+impl ScanProgress {
+    /// The progress of the last scan of this directory, unless `restart`
+    pub fn open(directory: &Path, restart: bool) -> Result<Self, Box<dyn Error>> {
+        let directory = directory.canonicalize()?;
+        let mut path = obtain_scans_directory()?;
+        path.push(format!(
+            "scan-{:08x}.jsonl",
+            crc32fast::hash(directory.to_string_lossy().as_bytes())
+        ));
+        if restart && path.exists() && !is_incognito() {
+            std::fs::remove_file(&path)?;
+        }
+        let entries = match File::open(&path) {
+            Ok(file) if !restart => read_entries(BufReader::new(file)),
+            _ => HashMap::new(),
+        };
+        Ok(ScanProgress {
+            path,
+            entries,
+            writer: None,
+        })
+    }
+
+    /// The entry of a file, if it didn't change since
+    fn entry(&self, file: &Path) -> Option<&ProgressLine> {
+        let entry = self.entries.get(file)?;
+        (size_and_modification(file)? == (entry.size, entry.modified_secs)).then_some(entry)
+    }
+
+    pub fn result(&self, file: &Path) -> Option<ScanResult> {
+        self.entry(file)?.result.clone()
+    }
+
+    pub fn signature(&self, file: &Path) -> Option<DecodedSignature> {
+        DecodedSignature::decode_from_uri(self.entry(file)?.signature.as_deref()?).ok()
+    }
+
+    fn append(&mut self, line: ProgressLine) -> Result<(), Box<dyn Error>> {
+        if is_incognito() {
+            merge_entry(&mut self.entries, line);
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            self.writer = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        let writer = self.writer.as_mut().unwrap();
+        writeln!(writer, "{}", serde_json::to_string(&line)?)?;
+        writer.flush()?;
+        merge_entry(&mut self.entries, line);
+        Ok(())
+    }
+
+    fn record(&mut self, file: &Path, signature: Option<String>, result: Option<ScanResult>) {
+        let Some((size, modified_secs)) = size_and_modification(file) else {
+            return;
+        };
+        let line = ProgressLine {
+            file: file.to_path_buf(),
+            size,
+            modified_secs,
+            signature,
+            result,
+        };
+        if let Err(error) = self.append(line) {
+            warn!("Could not save the progress of the scan: {}", error);
+        }
+    }
+
+    pub fn record_signature(&mut self, file: &Path, signature: &DecodedSignature) {
+        if let Ok(uri) = signature.encode_to_uri() {
+            self.record(file, Some(uri), None);
+        }
+    }
+
+    pub fn record_result(&mut self, result: &ScanResult) {
+        self.record(&result.file, None, Some(result.clone()));
+    }
+
+    /// Forget the progress once the scan went through, so that the next
+    /// one starts over
+    pub fn complete(&mut self) {
+        self.writer = None;
+        self.entries.clear();
+        if self.path.exists() && !is_incognito() {
+            if let Err(error) = std::fs::remove_file(&self.path) {
+                warn!("Could not remove {}: {}", self.path.display(), error);
+            }
+        }
+    }
+}
+
+/// A signature and a result may be recorded on separate lines
+fn merge_entry(entries: &mut HashMap<PathBuf, ProgressLine>, line: ProgressLine) {
+    match entries.get_mut(&line.file) {
+        Some(entry) if (entry.size, entry.modified_secs) == (line.size, line.modified_secs) => {
+            entry.signature = line.signature.or(entry.signature.take());
+            entry.result = line.result.or(entry.result.take());
+        }
+        _ => {
+            entries.insert(line.file.clone(), line);
+        }
+    }
+}
+
+/// The last line may be cut if the scan was killed while writing it
+fn read_entries(reader: impl BufRead) -> HashMap<PathBuf, ProgressLine> {
+    let mut entries = HashMap::new();
+    for line in reader.lines().map_while(Result::ok) {
+        if let Ok(line) = serde_json::from_str(&line) {
+            merge_entry(&mut entries, line);
+        }
+    }
+    entries
+}
+
+#[test]
+fn test_read_entries() {
+    let result = ScanResult {
+        file: PathBuf::from("/music/a.mp3"),
+        track_key: Some("1".to_string()),
+        artist: Some("Daft Punk".to_string()),
+        title: Some("Get Lucky".to_string()),
+        error: None,
+    };
+    let log = format!(
+        "{}\n{}\n{}\n{{\"file\": \"/music/c.mp3\", \"si",
+        r#"{"file": "/music/a.mp3", "size": 10, "modified_secs": 5, "signature": "data:a"}"#,
+        r#"{"file": "/music/b.mp3", "size": 20, "modified_secs": 5, "signature": "data:b"}"#,
+        serde_json::to_string(&ProgressLine {
+            file: PathBuf::from("/music/a.mp3"),
+            size: 10,
+            modified_secs: 5,
+            signature: None,
+            result: Some(result.clone()),
+        })
+        .unwrap()
+    );
+    let entries = read_entries(log.as_bytes());
+    assert_eq!(entries.len(), 2);
+    let entry = &entries[Path::new("/music/a.mp3")];
+    assert_eq!(entry.signature.as_deref(), Some("data:a"));
+    assert_eq!(entry.result.as_ref(), Some(&result));
+    assert!(entries[Path::new("/music/b.mp3")].result.is_none());
+}
@@ -0,0 +1,333 @@
+//! Recording of the whole listening session by "songrec listen
+//! --record-session FILE.ogg", e.g. to keep an annotated copy of a live
+//! radio show. The captured audio is spooled raw to "FILE.ogg.part" while
+//! listening, and encoded to Ogg Vorbis once the session stops, with a
+//! chapter at each recognized song (the CHAPTERxxx and CHAPTERxxxNAME
+//! comments of the Vorbis chapter extension, read by most players), since
+//! the comments of an Ogg file come before its audio. The spool is written
+//! by its own thread, so that the capture callback only hands the samples
+//! over, and is left behind if SongRec is killed; the chunks it couldn't
+//! keep up with are replaced by silence, so that the chapters stay at the
+//! right positions. Nothing is recorded in incognito mode. The "chapters"
+//! subcommand exports the chapters for the podcast players, see
+//! `utils::chapters`.
+
+use gettextrs::gettext;
+use log::{info, warn};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+use crate::utils::chapters::format_timestamp;
+use crate::utils::filesystem_operations::is_incognito;
+
+/// How long a song may have been playing before it was recognized: the
+/// length of the recognized window
+const RECOGNITION_WINDOW_SECS: f64 = 12.0;
+
+/// The chunks of samples waiting for the writer thread, a few seconds of
+/// audio; the ones arriving while it is full are dropped
+const PENDING_CHUNKS: usize = 256;
+
+/// The raw spool takes 1.4 GB per hour in stereo at 48 kHz: past this
+/// size, the end of the session isn't recorded any more
+const MAX_SPOOL_BYTES: u64 = 16 * 1024 * 1024 * 1024;
+
+enum SpoolMessage {
+    /// Interleaved samples, with the channel count and sample rate of the
+    /// stream
+    Samples(Vec<f32>, u16, u32),
+    Finish,
+}
+
+/// What the writer thread spooled so far
+#[derive(Clone, Copy)]
+struct SpoolProgress {
+    /// Channels and sample rate of the first stream; the ones of devices
+    /// selected later are converted to it
+    format: Option<(u16, u32)>,
+    frames: u64,
+}
+
+struct SessionRecorder {
+    output: PathBuf,
+    spool_path: PathBuf,
+    writer: JoinHandle<std::io::Result<()>>,
+    /// Position in seconds and title of each song
+    chapters: Vec<(f64, String)>,
+}
+
+static RECORDER: Mutex<Option<SessionRecorder>> = Mutex::new(None);
+
+/// Set once, so that the capture callback doesn't wait for a lock
+static SAMPLES_TX: OnceLock<SyncSender<SpoolMessage>> = OnceLock::new();
+
+static PROGRESS: Mutex<SpoolProgress> = Mutex::new(SpoolProgress {
+    format: None,
+    frames: 0,
+});
+
+static DROPPED_CHUNKS: AtomicU64 = AtomicU64::new(0);
+
+/// Duration of the dropped chunks not yet replaced by silence, in
+/// nanoseconds
+static DROPPED_NANOS: AtomicU64 = AtomicU64::new(0);
+
+pub fn start(output: &Path) -> Result<(), Box<dyn Error>> {
+    if is_incognito() {
+        info!(
+            "{}",
+            gettext("The session isn't recorded in incognito mode")
+        );
+        return Ok(());
+    }
+    let mut spool_path = output.as_os_str().to_owned();
+    spool_path.push(".part");
+    let spool_path = PathBuf::from(spool_path);
+    let spool = BufWriter::new(File::create(&spool_path)?);
+    let (samples_tx, samples_rx) = sync_channel(PENDING_CHUNKS);
+    SAMPLES_TX
+        .set(samples_tx)
+        .map_err(|_| "The session is already being recorded")?;
+    *RECORDER.lock().unwrap() = Some(SessionRecorder {
+        output: output.to_path_buf(),
+        spool_path,
+        writer: std::thread::spawn(move || write_spool(spool, samples_rx)),
+        chapters: vec![],
+    });
+    Ok(())
+}
+
+/// Interleaved samples, at the sample rate and channel count of the stream.
+/// Called from the capture callback: the samples are only handed over to
+/// the writer thread.
+pub fn record_samples(samples: &[f32], channels: u16, sample_rate: u32) {
+    let Some(samples_tx) = SAMPLES_TX.get() else {
+        return;
+    };
+    if is_incognito() {
+        return;
+    }
+    let message = SpoolMessage::Samples(samples.to_vec(), channels, sample_rate);
+    if let Err(TrySendError::Full(_)) = samples_tx.try_send(message) {
+        DROPPED_CHUNKS.fetch_add(1, Ordering::Relaxed);
+        let frames = (samples.len() / channels.max(1) as usize) as u64;
+        DROPPED_NANOS.fetch_add(
+            frames * 1_000_000_000 / sample_rate.max(1) as u64,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// The writer thread, until `finish` or until the spool is full
+fn write_spool(
+    mut spool: BufWriter<File>,
+    samples_rx: Receiver<SpoolMessage>,
+) -> std::io::Result<()> {
+    let mut spooled_bytes = 0;
+    let mut bytes = vec![];
+    while let Ok(SpoolMessage::Samples(samples, channels, sample_rate)) = samples_rx.recv() {
+        let format = *PROGRESS
+            .lock()
+            .unwrap()
+            .format
+            .get_or_insert((channels, sample_rate));
+        let mut samples = if (channels, sample_rate) == format {
+            samples
+        } else {
+            convert(&samples, (channels, sample_rate), format)
+        };
+        let silent_frames =
+            DROPPED_NANOS.swap(0, Ordering::Relaxed) * format.1 as u64 / 1_000_000_000;
+        if silent_frames > 0 {
+            samples.splice(
+                0..0,
+                std::iter::repeat_n(0.0, silent_frames as usize * format.0 as usize),
+            );
+        }
+
+        if spooled_bytes + samples.len() as u64 * 4 > MAX_SPOOL_BYTES {
+            warn!(
+                "{}",
+                gettext("The recording of the session reached %d GB, the rest of the session isn't recorded")
+                    .replace("%d", &(MAX_SPOOL_BYTES / 1024 / 1024 / 1024).to_string())
+            );
+            break;
+        }
+        bytes.clear();
+        bytes.extend(samples.iter().flat_map(|sample| sample.to_le_bytes()));
+        spool.write_all(&bytes)?;
+        spooled_bytes += bytes.len() as u64;
+        PROGRESS.lock().unwrap().frames += (samples.len() / format.0 as usize) as u64;
+    }
+    spool.flush()
+}
+
+fn convert(samples: &[f32], from: (u16, u32), to: (u16, u32)) -> Vec<f32> {
+    let input = rodio::buffer::SamplesBuffer::new(
+        NonZero::new(from.0).unwrap(),
+        NonZero::new(from.1).unwrap(),
+        samples.to_vec(),
+    );
+    rodio::source::UniformSourceIterator::new(
+        input,
+        NonZero::new(to.0).unwrap(),
+        NonZero::new(to.1).unwrap(),
+    )
+    .collect()
+}
+
+/// Mark the start of a recognized song, at the start of the window it was
+/// recognized from
+pub fn add_chapter(title: &str) {
+    if is_incognito() {
+        return;
+    }
+    let mut recorder = RECORDER.lock().unwrap();
+    let Some(ref mut session) = *recorder else {
+        return;
+    };
+    let progress = *PROGRESS.lock().unwrap();
+    let Some((_, sample_rate)) = progress.format else {
+        return;
+    };
+    let last_position = session
+        .chapters
+        .last()
+        .map_or(0.0, |(position, _)| *position);
+    // Including the dropped chunks which the writer thread didn't replace
+    // by silence yet
+    let spooled_secs = progress.frames as f64 / sample_rate as f64
+        + DROPPED_NANOS.load(Ordering::Relaxed) as f64 / 1e9;
+    let position = (spooled_secs - RECOGNITION_WINDOW_SECS).max(last_position);
+    session.chapters.push((position, title.to_string()));
+}
+
+/// The Vorbis comments of the chapters
+fn chapter_comments(chapters: &[(f64, String)]) -> Vec<(String, String)> {
+    chapters
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (position, title))| {
+            let key = format!("CHAPTER{:03}", index + 1);
+            [
+                (key.clone(), format_timestamp(*position)),
+                (key + "NAME", title.clone()),
+            ]
+        })
+        .collect()
+}
+
+/// Encode the spooled audio to the output file, once the session stopped
+pub fn finish() {
+    let Some(session) = RECORDER.lock().unwrap().take() else {
+        return;
+    };
+    // The writer thread may have stopped on an error, or on the size limit
+    if let Some(samples_tx) = SAMPLES_TX.get() {
+        samples_tx.send(SpoolMessage::Finish).ok();
+    }
+    let result = match session.writer.join() {
+        Ok(result) => result.map_err(Box::<dyn Error>::from),
+        Err(_) => Err("The writer thread panicked".into()),
+    };
+    let dropped_chunks = DROPPED_CHUNKS.load(Ordering::Relaxed);
+    if dropped_chunks > 0 {
+        warn!(
+            "{}",
+            gettext("%d chunks of audio were dropped from the recording of the session, as the disk was too slow")
+                .replace("%d", &dropped_chunks.to_string())
+        );
+    }
+    let result = result.and_then(|_| encode(&session, *PROGRESS.lock().unwrap()));
+    match result {
+        Ok(()) => {
+            std::fs::remove_file(&session.spool_path).ok();
+            info!(
+                "{}",
+                gettext("Recorded the session to %s, with %d chapters")
+                    .replace("%s", &session.output.display().to_string())
+                    .replace("%d", &session.chapters.len().to_string())
+            );
+        }
+        Err(error) => warn!(
+            "{} {}: {}",
+            gettext("Could not encode the recording of the session, the raw audio was kept in"),
+            session.spool_path.display(),
+            error
+        ),
+    }
+}
+
+fn encode(session: &SessionRecorder, progress: SpoolProgress) -> Result<(), Box<dyn Error>> {
+    let Some((channels, sample_rate)) = progress.format else {
+        return Err(gettext("No audio was captured").into());
+    };
+    let mut builder = vorbis_rs::VorbisEncoderBuilder::new(
+        NonZero::new(sample_rate).ok_or("Invalid sample rate")?,
+        NonZero::new(u8::try_from(channels)?).ok_or("Invalid channel count")?,
+        BufWriter::new(File::create(&session.output)?),
+    )?;
+    builder.add_comment_tag("ENCODER", format!("SongRec {}", env!("CARGO_PKG_VERSION")))?;
+    for (key, value) in chapter_comments(&session.chapters) {
+        builder.add_comment_tag(key, value)?;
+    }
+    let mut encoder = builder.build()?;
+
+    // One second at a time, de-interleaved
+    let mut reader = BufReader::new(File::open(&session.spool_path)?);
+    let mut bytes = vec![0u8; sample_rate as usize * channels as usize * 4];
+    loop {
+        let mut length = 0;
+        while length < bytes.len() {
+            match reader.read(&mut bytes[length..])? {
+                0 => break,
+                count => length += count,
+            }
+        }
+        let frames = length / 4 / channels as usize;
+        if frames == 0 {
+            break;
+        }
+        let mut planes = vec![Vec::with_capacity(frames); channels as usize];
+        for (position, sample) in bytes[..frames * channels as usize * 4]
+            .chunks_exact(4)
+            .enumerate()
+        {
+            planes[position % channels as usize]
+                .push(f32::from_le_bytes(sample.try_into().unwrap()));
+        }
+        encoder.encode_audio_block(&planes)?;
+    }
+    encoder.finish()?;
+    Ok(())
+}
+
+#[test]
+fn test_chapter_comments() {
+    assert_eq!(format_timestamp(3723.5), "01:02:03.500");
+    assert_eq!(
+        chapter_comments(&[
+            (0.0, "Daft Punk - Get Lucky".to_string()),
+            (245.25, "Justice - D.A.N.C.E.".to_string())
+        ]),
+        vec![
+            ("CHAPTER001".to_string(), "00:00:00.000".to_string()),
+            (
+                "CHAPTER001NAME".to_string(),
+                "Daft Punk - Get Lucky".to_string()
+            ),
+            ("CHAPTER002".to_string(), "00:04:05.250".to_string()),
+            (
+                "CHAPTER002NAME".to_string(),
+                "Justice - D.A.N.C.E.".to_string()
+            ),
+        ]
+    );
+}
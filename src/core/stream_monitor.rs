@@ -0,0 +1,772 @@
+//! Monitor Internet radio streams (Icecast/Shoutcast, or HLS playlists)
+//! rather than a sound card, several at once: each stream is fetched through
+//! the HTTP client shared by all the monitors, decoded in a thread of its
+//! own and recognized every request interval, its songs being tagged with
+//! the "stream:<tag>" source. Streams are given on the command line or in a
+//! TOML file, which may also give each of them its own sinks:
+//!
+//! ```toml
+//! [[stream]]
+//! tag = "fip"
+//! url = "https://icecast.radiofrance.fr/fip-midfi.mp3"
+//!
+//! [[stream.sink]]
+//! type = "telegram"
+//! bot_token = "123456:ABC..."
+//! chat_id = "@fip_log"
+//! ```
+//!
+//! Icecast streams are decoded by chunks, so they must use a codec with
+//! self-contained frames (MP3 or AAC). HLS segments are decoded whole, MPEG-TS
+//! ones requiring FFmpeg.
+//!
+//! Most Icecast and Shoutcast stations announce the current song in their
+//! ICY metadata: while it reads like "Artist - Title", the song is reported
+//! from there with the "icy:<tag>" source, and the audio is not recognized,
+//! which saves requests. The audio of stations without such tags, or sending
+//! jingles and station names in them, is recognized every request interval of
+//! the stream. Streams can also be verified: their audio is then recognized
+//! all along, and the songs not matching the announced ones are reported.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use gettextrs::gettext;
+use glib::Priority;
+use log::{debug, info, warn};
+use rodio::Source;
+use serde::Deserialize;
+use serde_json::json;
+use soup::gio::prelude::InputStreamExt;
+use soup::prelude::SessionExt;
+
+use crate::core::aliases;
+use crate::core::microphone_thread::CaptureProcessor;
+use crate::core::preferences::PreferencesInterface;
+use crate::core::resource_limits;
+use crate::core::thread_messages::{
+    GUIMessage, ProcessingMessage, RecognitionSource, SongRecognizedMessage,
+};
+#[cfg(feature = "ffmpeg")]
+use crate::plugins::ffmpeg_wrapper::decode_with_ffmpeg;
+use crate::plugins::sinks::SinkConfig;
+
+/// Amount of Icecast data decoded at once, about two seconds at 128 kbit/s
+const CHUNK_BYTES: usize = 32 * 1024;
+const READ_SIZE: usize = 8 * 1024;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Prefix of the track keys of the songs announced in the ICY metadata
+pub const ICY_TRACK_KEY_PREFIX: &str = "icy:";
+
+/// Segments of a live HLS playlist that we start from, like players do
+/// rather than from its oldest segment
+const LIVE_START_SEGMENTS: usize = 2;
+
+#[derive(Clone, Deserialize)]
+pub struct StreamConfig {
+    /// Name of the stream in the history, e.g. the name of the station
+    pub tag: String,
+    pub url: String,
+    /// Sinks receiving the songs of this stream only
+    #[serde(default)]
+    pub sink: Vec<SinkConfig>,
+    /// Take the songs from the ICY metadata when it names them, rather than
+    /// recognizing the audio
+    #[serde(default = "default_use_metadata")]
+    pub use_metadata: bool,
+    /// Also recognize the audio while the metadata names the song, and warn
+    /// when they don't match
+    #[serde(default)]
+    pub verify_metadata: bool,
+    /// Request interval of this stream, instead of the global one
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+}
+
+fn default_use_metadata() -> bool {
+    true
+}
+
+impl StreamConfig {
+    /// Parse a "TAG=URL" command-line argument
+    pub fn parse_argument(argument: &str) -> Result<Self, Box<dyn Error>> {
+        match argument.split_once('=') {
+            Some((tag, url)) if !tag.is_empty() && url.contains("://") => Ok(StreamConfig {
+                tag: tag.to_string(),
+                url: url.to_string(),
+                sink: vec![],
+                use_metadata: true,
+                verify_metadata: false,
+                interval_secs: None,
+            }),
+            _ => Err(gettext("Invalid stream %s, expected TAG=URL")
+                .replace("%s", argument)
+                .into()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StreamsFile {
+    #[serde(default)]
+    stream: Vec<StreamConfig>,
+}
+
+pub fn parse_streams_config(contents: &str) -> Result<Vec<StreamConfig>, Box<dyn Error>> {
+    Ok(toml::from_str::<StreamsFile>(contents)?.stream)
+}
+
+pub fn load_streams_config(path: &Path) -> Result<Vec<StreamConfig>, Box<dyn Error>> {
+    parse_streams_config(&std::fs::read_to_string(path)?)
+        .map_err(|error| format!("{}: {}", path.display(), error).into())
+}
+
+/// The last song announced by each verified stream, as (artist, title,
+/// whether it was reported from the metadata)
+static ANNOUNCED_SONGS: LazyLock<Mutex<HashMap<String, (String, String, bool)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Whether a recognition is ongoing for each stream, since the streams are
+/// recognized independently from each other and from the microphone
+static PENDING_RECOGNITIONS: LazyLock<Mutex<HashMap<String, Arc<AtomicBool>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Called once the recognition of a window of the stream is over
+pub fn recognition_done(tag: &str) {
+    if let Some(pending) = PENDING_RECOGNITIONS.lock().unwrap().get(tag) {
+        pending.store(false, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct HlsPlaylist {
+    media_sequence: u64,
+    target_duration: u64,
+    /// URLs of the variants of a master playlist
+    variants: Vec<String>,
+    /// URLs of the segments of a media playlist
+    segments: Vec<String>,
+    ended: bool,
+}
+
+fn parse_hls_playlist(base_url: &str, text: &str) -> Result<HlsPlaylist, Box<dyn Error>> {
+    if !text.trim_start().starts_with("#EXTM3U") {
+        return Err(gettext("Invalid HLS playlist").into());
+    }
+    let mut playlist = HlsPlaylist::default();
+    let mut next_is_variant = false;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            playlist.media_sequence = value.parse().unwrap_or(0);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            playlist.target_duration = value.parse().unwrap_or(0);
+        } else if line.starts_with("#EXT-X-STREAM-INF") {
+            next_is_variant = true;
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if !line.starts_with('#') {
+            let url = glib::Uri::resolve_relative(Some(base_url), line, glib::UriFlags::NONE)?
+                .to_string();
+            if next_is_variant {
+                playlist.variants.push(url);
+            } else {
+                playlist.segments.push(url);
+            }
+            next_is_variant = false;
+        }
+    }
+    Ok(playlist)
+}
+
+fn is_hls_url(url: &str) -> bool {
+    url.split(['?', '#'])
+        .next()
+        .is_some_and(|path| path.to_lowercase().ends_with(".m3u8"))
+}
+
+fn is_hls_content_type(content_type: &str) -> bool {
+    content_type.to_lowercase().contains("mpegurl")
+}
+
+/// Where the last MP3 or AAC (ADTS) frame of the buffer starts, so that a
+/// chunk ends on a frame boundary; the rest starts the next chunk
+fn last_frame_start(data: &[u8]) -> Option<usize> {
+    (1..data.len().saturating_sub(1))
+        .rev()
+        .find(|&offset| data[offset] == 0xff && data[offset + 1] & 0xe0 == 0xe0)
+}
+
+/// Separates the audio of an Icecast stream from the metadata blocks that
+/// the server inserts every `metaint` bytes, when asked to
+struct IcyDemuxer {
+    metaint: usize,
+    /// Audio bytes before the next metadata block
+    audio_left: usize,
+    /// The metadata block being read, and its size once known
+    metadata: Vec<u8>,
+    metadata_size: Option<usize>,
+}
+
+impl IcyDemuxer {
+    fn new(metaint: usize) -> Self {
+        IcyDemuxer {
+            metaint,
+            audio_left: metaint,
+            metadata: vec![],
+            metadata_size: None,
+        }
+    }
+
+    /// Append the audio of `data` to `audio`, and return the metadata blocks
+    /// completed by it
+    fn feed(&mut self, mut data: &[u8], audio: &mut Vec<u8>) -> Vec<String> {
+        let mut blocks = vec![];
+        while !data.is_empty() {
+            match self.metadata_size {
+                None if self.audio_left > 0 => {
+                    let length = self.audio_left.min(data.len());
+                    audio.extend_from_slice(&data[..length]);
+                    self.audio_left -= length;
+                    data = &data[length..];
+                }
+                None => {
+                    self.metadata_size = Some(data[0] as usize * 16);
+                    data = &data[1..];
+                }
+                Some(size) => {
+                    let length = (size - self.metadata.len()).min(data.len());
+                    self.metadata.extend_from_slice(&data[..length]);
+                    data = &data[length..];
+                }
+            }
+            if self.metadata_size == Some(self.metadata.len()) {
+                if !self.metadata.is_empty() {
+                    // Older servers send Latin-1
+                    let block = String::from_utf8(self.metadata.clone()).unwrap_or_else(|_| {
+                        self.metadata.iter().map(|&byte| byte as char).collect()
+                    });
+                    blocks.push(block.trim_end_matches('\0').to_string());
+                }
+                self.metadata.clear();
+                self.metadata_size = None;
+                self.audio_left = self.metaint;
+            }
+        }
+        blocks
+    }
+}
+
+/// The "StreamTitle='...';" field of an ICY metadata block
+fn stream_title(metadata: &str) -> Option<&str> {
+    let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let length = metadata[start..]
+        .find("';")
+        .unwrap_or(metadata[start..].trim_end_matches('\'').len());
+    Some(metadata[start..start + length].trim())
+}
+
+/// The song named by a stream title, when it reads like "Artist - Title"
+fn song_from_stream_title(tag: &str, title: &str) -> Option<SongRecognizedMessage> {
+    let (artist_name, song_name) = title.split_once(" - ")?;
+    let (artist_name, song_name) = (artist_name.trim(), song_name.trim());
+    if artist_name.is_empty() || song_name.is_empty() {
+        return None;
+    }
+    Some(SongRecognizedMessage {
+        artist_name: artist_name.to_string(),
+        album_name: None,
+        song_name: song_name.to_string(),
+        cover_image: None,
+        cover_url: None,
+        track_key: format!("{}{} - {}", ICY_TRACK_KEY_PREFIX, artist_name, song_name),
+        release_year: None,
+        genre: None,
+        isrc: None,
+        upc: None,
+        shazam_json: json!({
+            "icy": {
+                "stream": tag,
+                "title": title,
+            }
+        })
+        .to_string(),
+        source: Some(RecognitionSource::StreamMetadata(tag.to_string())),
+    })
+}
+
+/// Lowercase letters and digits only, since stations and Shazam rarely
+/// write names the same way
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|character| character.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn names_match(announced: &str, recognized: &str) -> bool {
+    let (announced, recognized) = (normalize_name(announced), normalize_name(recognized));
+    // Featured artists and version names are often left out by either side
+    !announced.is_empty()
+        && !recognized.is_empty()
+        && (announced.contains(&recognized) || recognized.contains(&announced))
+}
+
+/// Warn when the audio of a verified stream was recognized as another song
+/// than the one that its metadata names. Returns whether it was recognized
+/// as the song that was already reported from the metadata, which should
+/// not be forwarded again.
+pub fn verify_announced_song(message: &SongRecognizedMessage) -> bool {
+    let Some(RecognitionSource::Stream(ref tag)) = message.source else {
+        return false;
+    };
+    let announced_songs = ANNOUNCED_SONGS.lock().unwrap();
+    let Some((artist_name, song_name, reported)) = announced_songs.get(tag) else {
+        return false;
+    };
+    if names_match(song_name, &message.song_name) && names_match(artist_name, &message.artist_name)
+    {
+        debug!("Stream {}: the announced song was recognized", tag);
+        *reported
+    } else {
+        warn!(
+            "{}",
+            gettext("The stream %s announced %s, but %s was recognized")
+                .replacen("%s", tag, 1)
+                .replacen("%s", &format!("{} - {}", artist_name, song_name), 1)
+                .replacen(
+                    "%s",
+                    &format!("{} - {}", message.artist_name, message.song_name),
+                    1
+                )
+        );
+        false
+    }
+}
+
+fn check_status(message: &soup::Message, url: &str) -> Result<(), Box<dyn Error>> {
+    match message.status_code() {
+        200..=299 => Ok(()),
+        status => Err(format!("{}: HTTP status {}", url, status).into()),
+    }
+}
+
+async fn fetch(session: &soup::Session, url: &str) -> Result<glib::Bytes, Box<dyn Error>> {
+    let message = soup::Message::new("GET", url)?;
+    let body = session
+        .send_and_read_future(&message, Priority::DEFAULT)
+        .await?;
+    check_status(&message, url)?;
+    Ok(body)
+}
+
+async fn follow_hls(
+    session: &soup::Session,
+    url: &str,
+    chunk_tx: &async_channel::Sender<Vec<u8>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut playlist_url = url.to_string();
+    let mut next_sequence: Option<u64> = None;
+    let mut followed_variant = false;
+
+    loop {
+        let text = fetch(session, &playlist_url).await?;
+        let playlist = parse_hls_playlist(&playlist_url, &String::from_utf8_lossy(&text))?;
+
+        if let Some(variant) = playlist.variants.first() {
+            if followed_variant {
+                return Err(gettext("Invalid HLS playlist").into());
+            }
+            debug!("Following the HLS variant {}", variant);
+            playlist_url = variant.clone();
+            followed_variant = true;
+            continue;
+        }
+
+        let first_sequence = next_sequence.unwrap_or(
+            playlist.media_sequence
+                + playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS) as u64,
+        );
+        for (index, segment_url) in playlist.segments.iter().enumerate() {
+            let sequence = playlist.media_sequence + index as u64;
+            if sequence < first_sequence {
+                continue;
+            }
+            let segment = fetch(session, segment_url).await?;
+            chunk_tx.send(segment.to_vec()).await?;
+            next_sequence = Some(sequence + 1);
+        }
+
+        if playlist.ended {
+            return Err(gettext("The stream ended").into());
+        }
+        glib::timeout_future(Duration::from_secs(playlist.target_duration.max(2)) / 2).await;
+    }
+}
+
+/// An Icecast stream, or an HLS playlist under an URL not telling so
+async fn follow_http(
+    session: &soup::Session,
+    stream: &StreamConfig,
+    chunk_tx: &async_channel::Sender<Vec<u8>>,
+    gui_tx: &async_channel::Sender<GUIMessage>,
+) -> Result<(), Box<dyn Error>> {
+    let url = stream.url.as_str();
+    let message = soup::Message::new("GET", url)?;
+    if stream.use_metadata || stream.verify_metadata {
+        message
+            .request_headers()
+            .unwrap()
+            .append("Icy-MetaData", "1");
+    }
+    let input = session.send_future(&message, Priority::DEFAULT).await?;
+    check_status(&message, url)?;
+
+    let header = |name: &str| {
+        message
+            .response_headers()
+            .and_then(|headers| headers.one(name))
+            .map(|value| value.to_string())
+    };
+    if is_hls_content_type(&header("Content-Type").unwrap_or_default()) {
+        input.close_future(Priority::DEFAULT).await.ok();
+        return follow_hls(session, url, chunk_tx).await;
+    }
+    let mut demuxer = header("icy-metaint")
+        .and_then(|metaint| metaint.trim().parse::<usize>().ok())
+        .filter(|&metaint| metaint > 0)
+        .map(IcyDemuxer::new);
+
+    // Whether the current song is named by the metadata, in which case the
+    // audio is not recognized
+    let mut tagged = false;
+
+    let mut pending: Vec<u8> = vec![];
+    loop {
+        let bytes = input
+            .read_bytes_future(READ_SIZE, Priority::DEFAULT)
+            .await?;
+        if bytes.is_empty() {
+            return Err(gettext("The stream ended").into());
+        }
+        match demuxer {
+            Some(ref mut demuxer) => {
+                for block in demuxer.feed(&bytes, &mut pending) {
+                    let Some(title) = stream_title(&block) else {
+                        continue;
+                    };
+                    let mut song = song_from_stream_title(&stream.tag, title);
+                    // Before it is kept, since the recognized songs are
+                    // compared after their aliases
+                    if let Some(ref mut song) = song {
+                        aliases::apply(song);
+                    }
+                    if stream.verify_metadata {
+                        let mut announced_songs = ANNOUNCED_SONGS.lock().unwrap();
+                        match song {
+                            Some(ref song) => announced_songs.insert(
+                                stream.tag.clone(),
+                                (
+                                    song.artist_name.clone(),
+                                    song.song_name.clone(),
+                                    stream.use_metadata,
+                                ),
+                            ),
+                            None => announced_songs.remove(&stream.tag),
+                        };
+                    }
+                    // Only compared with the recognized songs otherwise
+                    if !stream.use_metadata {
+                        continue;
+                    }
+                    match song {
+                        Some(song) => {
+                            debug!("Stream {} announced {}", stream.tag, title);
+                            gui_tx
+                                .try_send(GUIMessage::SongRecognized(Box::new(song)))
+                                .unwrap();
+                            tagged = true;
+                        }
+                        None => tagged = false,
+                    }
+                }
+            }
+            None => pending.extend_from_slice(&bytes),
+        }
+        if tagged && !stream.verify_metadata {
+            pending.clear();
+            continue;
+        }
+
+        if pending.len() >= CHUNK_BYTES {
+            let rest = match last_frame_start(&pending) {
+                Some(offset) => pending.split_off(offset),
+                None => vec![],
+            };
+            chunk_tx.send(std::mem::replace(&mut pending, rest)).await?;
+        }
+    }
+}
+
+/// Fetch the stream until its decoding thread stopped, reconnecting when it
+/// is interrupted
+async fn fetch_stream(
+    session: soup::Session,
+    stream: StreamConfig,
+    chunk_tx: async_channel::Sender<Vec<u8>>,
+    gui_tx: async_channel::Sender<GUIMessage>,
+) {
+    while !chunk_tx.is_closed() {
+        let result = if is_hls_url(&stream.url) {
+            follow_hls(&session, &stream.url, &chunk_tx).await
+        } else {
+            follow_http(&session, &stream, &chunk_tx, &gui_tx).await
+        };
+        if let Err(error) = result {
+            warn!("{} {}: {}", gettext("Stream"), stream.tag, error);
+        }
+        glib::timeout_future(RECONNECT_DELAY).await;
+    }
+}
+
+/// Interleaved samples, with the channel count and sample rate of a chunk,
+/// within the memory limit as for the files
+fn decode_chunk(tag: &str, data: Vec<u8>) -> Result<(u16, u32, Vec<f32>), Box<dyn Error>> {
+    let _permit = resource_limits::acquire_decode();
+    #[cfg(feature = "ffmpeg")]
+    let copy = data.clone();
+
+    match rodio::Decoder::new(Cursor::new(data)) {
+        Ok(decoder) => Ok((
+            decoder.channels().get(),
+            decoder.sample_rate().get(),
+            resource_limits::collect_samples(decoder, tag),
+        )),
+        // MPEG-TS segments of HLS streams
+        #[cfg(feature = "ffmpeg")]
+        Err(error) => {
+            let segment = tempfile::Builder::new().suffix(".ts").tempfile()?;
+            std::fs::write(segment.path(), copy)?;
+            let decoder = decode_with_ffmpeg(&segment.path().to_string_lossy()).ok_or(error)?;
+            Ok((
+                decoder.channels().get(),
+                decoder.sample_rate().get(),
+                resource_limits::collect_samples(decoder, tag),
+            ))
+        }
+        #[cfg(not(feature = "ffmpeg"))]
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn decode_stream(
+    tag: String,
+    chunk_rx: async_channel::Receiver<Vec<u8>>,
+    processing_tx: async_channel::Sender<ProcessingMessage>,
+    gui_tx: async_channel::Sender<GUIMessage>,
+    preferences_interface: Arc<Mutex<PreferencesInterface>>,
+    processing_already_ongoing: Arc<AtomicBool>,
+) {
+    // The format of the stream, and the capture processor for it
+    let mut capture: Option<((u16, u32), CaptureProcessor)> = None;
+
+    while let Ok(data) = chunk_rx.recv_blocking() {
+        let (channels, sample_rate, samples) = match decode_chunk(&tag, data) {
+            Ok(decoded) => decoded,
+            Err(error) => {
+                warn!("Could not decode the stream {}: {}", tag, error);
+                continue;
+            }
+        };
+        if capture
+            .as_ref()
+            .is_none_or(|(format, _)| *format != (channels, sample_rate))
+        {
+            info!(
+                "Monitoring the stream {}: {} Hz, {} channels",
+                tag, sample_rate, channels
+            );
+            if capture.is_none() {
+                gui_tx.try_send(GUIMessage::MicrophoneRecording).unwrap();
+            }
+            capture = Some((
+                (channels, sample_rate),
+                CaptureProcessor::new(
+                    processing_tx.clone(),
+                    gui_tx.clone(),
+                    channels,
+                    sample_rate,
+                    processing_already_ongoing.clone(),
+                    preferences_interface.clone(),
+                    RecognitionSource::Stream(tag.clone()),
+                ),
+            ));
+        }
+        if let Some((_, ref mut capture_processor)) = capture {
+            capture_processor.write_samples(samples);
+        }
+    }
+}
+
+/// Start monitoring the streams; this must be called from the thread running
+/// the GLib main loop, which fetches them
+pub fn spawn_stream_monitors(
+    streams: &[StreamConfig],
+    session: soup::Session,
+    processing_tx: async_channel::Sender<ProcessingMessage>,
+    gui_tx: async_channel::Sender<GUIMessage>,
+    preferences_interface: Arc<Mutex<PreferencesInterface>>,
+) {
+    for stream in streams {
+        let processing_already_ongoing = Arc::new(AtomicBool::new(false));
+        PENDING_RECOGNITIONS
+            .lock()
+            .unwrap()
+            .insert(stream.tag.clone(), processing_already_ongoing.clone());
+
+        let preferences_interface = match stream.interval_secs {
+            Some(interval_secs) => {
+                let mut preferences = preferences_interface.lock().unwrap().preferences.clone();
+                preferences.request_interval_secs_v3 = Some(interval_secs);
+                Arc::new(Mutex::new(PreferencesInterface {
+                    preferences_file_path: None,
+                    preferences,
+                }))
+            }
+            None => preferences_interface.clone(),
+        };
+
+        let (chunk_tx, chunk_rx) = async_channel::bounded(16);
+        let tag = stream.tag.clone();
+        let processing_tx = processing_tx.clone();
+        let decoder_gui_tx = gui_tx.clone();
+        std::thread::Builder::new()
+            .name(format!("stream {}", stream.tag))
+            .spawn(move || {
+                decode_stream(
+                    tag,
+                    chunk_rx,
+                    processing_tx,
+                    decoder_gui_tx,
+                    preferences_interface,
+                    processing_already_ongoing,
+                )
+            })
+            .unwrap();
+
+        glib::spawn_future_local(fetch_stream(
+            session.clone(),
+            stream.clone(),
+            chunk_tx,
+            gui_tx.clone(),
+        ));
+    }
+}
+
+#[test]
+fn test_stream_monitor() {
+    let streams = parse_streams_config(
+        r#"
+        [[stream]]
+        tag = "fip"
+        url = "https://icecast.radiofrance.fr/fip-midfi.mp3"
+
+        [[stream]]
+        tag = "nts"
+        url = "https://example.com/live/nts.m3u8"
+        use_metadata = false
+        verify_metadata = true
+        interval_secs = 30
+
+        [[stream.sink]]
+        type = "telegram"
+        bot_token = "123456:ABC"
+        chat_id = "@nts"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(streams.len(), 2);
+    assert!(streams[0].sink.is_empty());
+    assert!(streams[0].use_metadata && !streams[1].use_metadata);
+    assert!(!streams[0].verify_metadata && streams[1].verify_metadata);
+    assert_eq!(streams[1].interval_secs, Some(30));
+    assert_eq!(streams[1].sink.len(), 1);
+    assert!(!is_hls_url(&streams[0].url));
+    assert!(is_hls_url(&streams[1].url));
+    assert!(is_hls_content_type("application/vnd.apple.mpegURL"));
+
+    let stream = StreamConfig::parse_argument("jazz=http://radio.local:8000/jazz").unwrap();
+    assert_eq!(stream.tag, "jazz");
+    assert_eq!(stream.url, "http://radio.local:8000/jazz");
+    assert!(StreamConfig::parse_argument("http://radio.local/jazz").is_err());
+
+    let playlist = parse_hls_playlist(
+        "https://example.com/live/nts.m3u8",
+        "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:42\n\
+         #EXTINF:6.0,\nsegment42.aac\n#EXTINF:6.0,\nhttps://cdn.example.com/segment43.aac\n",
+    )
+    .unwrap();
+    assert_eq!(
+        playlist,
+        HlsPlaylist {
+            media_sequence: 42,
+            target_duration: 6,
+            variants: vec![],
+            segments: vec![
+                "https://example.com/live/segment42.aac".to_string(),
+                "https://cdn.example.com/segment43.aac".to_string()
+            ],
+            ended: false,
+        }
+    );
+    let master = parse_hls_playlist(
+        "https://example.com/live/master.m3u8",
+        "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=128000\naudio/128k.m3u8\n",
+    )
+    .unwrap();
+    assert_eq!(
+        master.variants,
+        vec!["https://example.com/live/audio/128k.m3u8".to_string()]
+    );
+    assert!(parse_hls_playlist("https://example.com/", "<html>").is_err());
+
+    assert_eq!(
+        last_frame_start(&[0xff, 0xfb, 1, 2, 0xff, 0xfb, 3]),
+        Some(4)
+    );
+    assert_eq!(last_frame_start(&[1, 2, 3]), None);
+
+    let mut demuxer = IcyDemuxer::new(4);
+    let mut audio = vec![];
+    let metadata = b"StreamTitle='Daft Punk - Digital Love';\0\0\0\0\0\0\0\0\0";
+    assert_eq!(metadata.len(), 48);
+    let mut data = vec![1, 2, 3, 4, 3];
+    data.extend_from_slice(metadata);
+    data.extend_from_slice(&[5, 6, 7, 8, 0, 9]);
+    let (first, second) = data.split_at(20);
+    assert!(demuxer.feed(first, &mut audio).is_empty());
+    let blocks = demuxer.feed(second, &mut audio);
+    assert_eq!(audio, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    assert_eq!(blocks, vec!["StreamTitle='Daft Punk - Digital Love';"]);
+
+    let title = stream_title(&blocks[0]).unwrap();
+    let song = song_from_stream_title("fip", title).unwrap();
+    assert_eq!(song.artist_name, "Daft Punk");
+    assert_eq!(song.song_name, "Digital Love");
+    assert_eq!(song.track_key, "icy:Daft Punk - Digital Love");
+    assert_eq!(stream_title("StreamTitle='';StreamUrl='';"), Some(""));
+    assert_eq!(song.source.unwrap().to_string(), "icy:fip");
+    assert!(song_from_stream_title("fip", "FIP, la radio musicale").is_none());
+
+    assert!(names_match("Daft Punk", "DAFT PUNK"));
+    assert!(names_match("Digital Love", "Digital Love (Radio Edit)"));
+    assert!(!names_match("Digital Love", "One More Time"));
+    assert!(!names_match("", "One More Time"));
+}
@@ -0,0 +1,122 @@
+//! Long operations running in the background (e.g. recognizing all the files
+//! of a folder), registered here so that the GUI lists them with their
+//! progress and lets the user cancel them. A task is started from any
+//! thread with `start()`, reports its progress through its `TaskHandle`,
+//! checks `is_cancelled()` between two steps, and is removed from the list
+//! once its handle is dropped.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct Task {
+    id: u64,
+    title: String,
+    done: AtomicUsize,
+    /// Zero while it is unknown
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+static TASKS: Mutex<Vec<Arc<Task>>> = Mutex::new(Vec::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A snapshot of a running task, for display
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaskStatus {
+    pub id: u64,
+    pub title: String,
+    pub done: usize,
+    pub total: usize,
+    pub cancelled: bool,
+}
+
+impl TaskStatus {
+    /// From 0 to 1, or None when the total is unknown
+    pub fn fraction(&self) -> Option<f64> {
+        (self.total > 0).then(|| (self.done as f64 / self.total as f64).min(1.0))
+    }
+}
+
+pub struct TaskHandle {
+    task: Arc<Task>,
+}
+
+pub fn start(title: &str) -> TaskHandle {
+    let task = Arc::new(Task {
+        id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+        title: title.to_string(),
+        done: AtomicUsize::new(0),
+        total: AtomicUsize::new(0),
+        cancelled: AtomicBool::new(false),
+    });
+    TASKS.lock().unwrap().push(task.clone());
+    TaskHandle { task }
+}
+
+impl TaskHandle {
+    pub fn set_total(&self, total: usize) {
+        self.task.total.store(total, Ordering::SeqCst);
+    }
+
+    /// One more step is done
+    pub fn advance(&self) {
+        self.task.done.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.task.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for TaskHandle {
+    fn drop(&mut self) {
+        TASKS
+            .lock()
+            .unwrap()
+            .retain(|task| !Arc::ptr_eq(task, &self.task));
+    }
+}
+
+/// The running tasks, in the order they were started
+pub fn list() -> Vec<TaskStatus> {
+    TASKS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|task| TaskStatus {
+            id: task.id,
+            title: task.title.clone(),
+            done: task.done.load(Ordering::SeqCst),
+            total: task.total.load(Ordering::SeqCst),
+            cancelled: task.cancelled.load(Ordering::SeqCst),
+        })
+        .collect()
+}
+
+/// Ask a task to stop; it is removed from the list once it did
+pub fn cancel(id: u64) {
+    for task in TASKS.lock().unwrap().iter() {
+        if task.id == id {
+            task.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[test]
+fn test_tasks() {
+    let task = start("Test task");
+    task.set_total(4);
+    task.advance();
+    let status = list()
+        .into_iter()
+        .find(|status| status.title == "Test task");
+    let status = status.unwrap();
+    assert_eq!(status.fraction(), Some(0.25));
+    assert!(!task.is_cancelled());
+
+    cancel(status.id);
+    assert!(task.is_cancelled());
+    drop(task);
+    assert!(list().iter().all(|other| other.id != status.id));
+}
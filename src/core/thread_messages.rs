@@ -4,6 +4,7 @@ use crate::core::fingerprinting::signature_format::DecodedSignature;
 #[cfg(feature = "gui")]
 use crate::core::preferences::Preferences;
 
+use std::path::PathBuf;
 use std::thread;
 
 pub fn spawn_big_thread<F, T>(argument: F)
@@ -18,19 +19,117 @@ where
         .unwrap();
 }
 
-#[derive(Debug)]
+/// Where the audio of a recognition came from. It is stored in the song
+/// history as "microphone:<device>", "monitor:<device>" or "file:<path>",
+/// "stream:<tag>" for a monitored radio stream. The songs which were
+/// reported rather than recognized are stored as "icy:<tag>" when a radio
+/// stream named them in its metadata, or as "cast:<device>" when a Cast
+/// device announced them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecognitionSource {
+    Microphone(String),
+    Monitor(String), // Loopback recording of what the speakers play
+    File(String),
+    Stream(String),         // Tag of an Icecast or HLS stream
+    StreamMetadata(String), // Named by the ICY metadata of the stream
+    Cast(String),           // Reported by a Chromecast rather than recognized
+}
+
+/// The channels recognized separately with `--split-channels`, whose names
+/// are appended to the device, as in "microphone:<device>#left"
+pub const CHANNEL_NAMES: [&str; 2] = ["left", "right"];
+
+impl RecognitionSource {
+    /// The source of a channel of the capture
+    pub fn with_channel(&self, channel: usize) -> Self {
+        match self {
+            RecognitionSource::Microphone(device) => {
+                RecognitionSource::Microphone(format!("{}#{}", device, CHANNEL_NAMES[channel]))
+            }
+            RecognitionSource::Monitor(device) => {
+                RecognitionSource::Monitor(format!("{}#{}", device, CHANNEL_NAMES[channel]))
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// The channel of the capture recognized separately, if any
+    pub fn channel(&self) -> Option<&str> {
+        match self {
+            RecognitionSource::Microphone(device) | RecognitionSource::Monitor(device) => device
+                .rsplit_once('#')
+                .map(|(_, channel)| channel)
+                .filter(|channel| CHANNEL_NAMES.contains(channel)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RecognitionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecognitionSource::Microphone(device) => write!(f, "microphone:{}", device),
+            RecognitionSource::Monitor(device) => write!(f, "monitor:{}", device),
+            RecognitionSource::File(path) => write!(f, "file:{}", path),
+            RecognitionSource::Stream(tag) => write!(f, "stream:{}", tag),
+            RecognitionSource::StreamMetadata(tag) => write!(f, "icy:{}", tag),
+            RecognitionSource::Cast(device) => write!(f, "cast:{}", device),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct SongRecognizedMessage {
     pub artist_name: String,
     pub album_name: Option<String>,
     pub song_name: String,
     pub cover_image: Option<Vec<u8>>,
+    pub cover_url: Option<String>,
 
     // Used only in the CSV export for now:
     pub track_key: String,
     pub release_year: Option<String>,
     pub genre: Option<String>,
+    /// See `track_identifiers`
+    pub isrc: Option<String>,
+    pub upc: Option<String>,
 
     pub shazam_json: String,
+    pub source: Option<RecognitionSource>,
+}
+
+impl SongRecognizedMessage {
+    /// The response of Shazam with the names given to the song by SongRec
+    /// (after the corrections, aliases and transliteration), for `--json`
+    pub fn displayed_shazam_json(&self) -> String {
+        let Ok(mut json) = serde_json::from_str::<serde_json::Value>(&self.shazam_json) else {
+            return self.shazam_json.clone();
+        };
+        let Some(track) = json
+            .get_mut("track")
+            .and_then(|track| track.as_object_mut())
+        else {
+            return self.shazam_json.clone();
+        };
+        track.insert("title".to_string(), self.song_name.clone().into());
+        track.insert("subtitle".to_string(), self.artist_name.clone().into());
+        if let (Some(album_name), Some(sections)) = (
+            self.album_name.as_ref(),
+            track
+                .get_mut("sections")
+                .and_then(|sections| sections.as_array_mut()),
+        ) {
+            for metadatum in sections
+                .iter_mut()
+                .filter_map(|section| section.get_mut("metadata")?.as_array_mut())
+                .flatten()
+                .filter(|metadatum| metadatum["title"] == "Album")
+            {
+                metadatum["text"] = album_name.clone().into();
+            }
+        }
+        json.to_string()
+    }
 }
 
 #[derive(Debug)]
@@ -40,6 +139,25 @@ pub struct DeviceListItem {
     // The checkbox option on the UI should select the first monitor
     // device present in the combo box, when specified
     pub is_monitor: bool,
+    // A Bluetooth headset microphone in a hands-free (HSP/HFP) profile,
+    // with telephone quality audio
+    pub is_hands_free: bool,
+    pub sample_rate: Option<u32>,
+}
+
+#[cfg(test)]
+impl DeviceListItem {
+    /// A device displayed under its inner name, of a full-band profile and
+    /// an unknown sample rate
+    pub fn for_test(inner_name: &str, is_monitor: bool) -> Self {
+        DeviceListItem {
+            inner_name: inner_name.to_string(),
+            display_name: inner_name.to_string(),
+            is_monitor,
+            is_hands_free: false,
+            sample_rate: None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -47,21 +165,50 @@ pub enum GUIMessage {
     ErrorMessage(String),
     ShowWindow,
     QuitApplication,
+    // From the macOS menu bar (GUI only)
+    #[cfg(feature = "gui")]
+    ShowPreferences,
+    #[cfg(feature = "gui")]
+    RecognizeNow,
     // A list of audio devices, received from the microphone thread
     // because CPAL can't be called from the same thread as the GUI
     // under Windows
     DevicesList(Vec<DeviceListItem>),
     #[cfg(feature = "gui")]
     UpdatePreference(Preferences),
-    NetworkStatus(bool),  // Is the network reachable?
-    RateLimitState(bool), // Are we rate-limited?
+    NetworkStatus(bool),        // Is the network reachable?
+    RateLimitState(bool),       // Are we rate-limited?
+    NetworkRequiresLogin(bool), // Are we behind a captive portal?
     #[cfg(feature = "gui")]
     WipeSongHistory,
     #[cfg(feature = "gui")]
+    ShowRecentlyDeleted,
+    #[cfg(feature = "gui")]
+    ShowUnknownClips,
+    #[cfg(feature = "gui")]
     AppendToLog(String),
     MicrophoneRecording,
     MicrophoneVolumePercent(f32),
     SongRecognized(Box<SongRecognizedMessage>),
+    // SIGHUP received or configuration file modified (CLI only)
+    ReloadConfiguration,
+    // Sink name and duration in seconds, from D-Bus, with where to send
+    // whether the sink exists (CLI only)
+    MuteSink(String, u32, async_channel::Sender<Result<(), String>>),
+    // A capture session was entirely replayed, or the file of a "file:"
+    // device entirely played (CLI only)
+    CaptureEnded,
+    // Number of capture overruns or underruns within the last minute
+    CaptureXruns(u32),
+    // The capture comes from a Bluetooth headset in hands-free mode
+    HandsFreeCapture,
+    // Whether the last captured window was silent, and wasn't sent
+    NoAudioDetected(bool),
+    // Whether most of the recent captures of a device weren't recognized
+    HighNoMatchRate(String, bool),
+    // The monitor of the speakers, to record from (GUI only)
+    #[cfg(feature = "gui")]
+    SelectMonitorDevice(String),
 }
 
 pub enum MicrophoneMessage {
@@ -70,13 +217,47 @@ pub enum MicrophoneMessage {
     RefreshDevices,
     MicrophoneRecordStop,
     ProcessingDone,
+    DumpCapture(PathBuf), // Directory to which the next captures are dumped (CLI only)
+    EnlargeBuffer,        // Sent after repeated xruns
+    VirtualCaptureEnded,  // The samples of a file or of a capture session ran out
+    MicrophoneWarmStart(Option<String>), // Device to keep filling the pre-roll from while not recognizing, or None (GUI only)
+    #[cfg(feature = "gui")]
+    FindOutputMonitor, // Answered with GUIMessage::SelectMonitorDevice
 }
 
 pub enum ProcessingMessage {
     ProcessAudioFile(String),
-    ProcessAudioSamples(Vec<f32>), // Prefer to use heap across threads to avoid stack overflow
+    ProcessAudioSamples(Vec<f32>, RecognitionSource), // Prefer to use heap across threads to avoid stack overflow
 }
 
 pub enum HTTPMessage {
-    RecognizeSignature(Box<DecodedSignature>),
+    // The samples are kept in order to save the clip when it isn't recognized
+    RecognizeSignature(Box<DecodedSignature>, RecognitionSource, Option<Vec<f32>>),
+}
+
+#[test]
+fn test_displayed_shazam_json() {
+    let message = SongRecognizedMessage {
+        artist_name: "Kino".to_string(),
+        album_name: Some("Gruppa krovi".to_string()),
+        song_name: "Gruppa krovi".to_string(),
+        cover_image: None,
+        cover_url: None,
+        track_key: "123".to_string(),
+        release_year: None,
+        genre: None,
+        isrc: None,
+        upc: None,
+        shazam_json: r#"{"track": {"title": "Группа крови", "subtitle": "Кино",
+            "sections": [{"metadata": [{"title": "Album", "text": "Группа крови"}]}]}}"#
+            .to_string(),
+        source: None,
+    };
+    let json: serde_json::Value = serde_json::from_str(&message.displayed_shazam_json()).unwrap();
+    assert_eq!(json["track"]["title"], "Gruppa krovi");
+    assert_eq!(json["track"]["subtitle"], "Kino");
+    assert_eq!(
+        json["track"]["sections"][0]["metadata"][0]["text"],
+        "Gruppa krovi"
+    );
 }
@@ -0,0 +1,219 @@
+//! Timeline of the pipeline activity during a session, written with
+//! `--profile-out trace.json` so that stutters reported by users can be
+//! analyzed on their machine. The file is in the Chrome trace event format,
+//! which https://ui.perfetto.dev and chrome://tracing open.
+//!
+//! Events are written and flushed by their own thread as they come: the
+//! format allows the final "]" to be missing, so a trace stays readable when
+//! SongRec is killed.
+//!
+//! See: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::thread::{JoinHandle, Thread};
+use std::time::{Duration, Instant};
+
+/// The events waiting for the writer thread; the ones recorded while it is
+/// full are dropped
+const PENDING_EVENTS: usize = 4096;
+
+/// Builds the events and hands them over to a thread writing them, so that
+/// the audio threads never wait for the disk
+struct TraceRecorder {
+    events_tx: SyncSender<(Value, Thread)>,
+    start: Instant,
+}
+
+static PROFILE: OnceLock<TraceRecorder> = OnceLock::new();
+
+/// Checked before building the events, so that tracing costs nothing when
+/// it is not enabled
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Small identifiers, as the viewers expect, given to threads when they
+    /// record their first event
+    static THREAD_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn start(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"[\n")?;
+    writer.flush()?;
+    PROFILE
+        .set(TraceRecorder::new(writer).0)
+        .map_err(|_| "A trace is already being recorded")?;
+    ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn thread_id() -> u64 {
+    THREAD_ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+/// The events are flushed once the ones waiting are written, and each
+/// thread is named before its first event
+fn write_events(
+    mut writer: impl Write,
+    events_rx: Receiver<(Value, Thread)>,
+) -> std::io::Result<()> {
+    let mut named_threads = HashSet::new();
+    while let Ok(first_event) = events_rx.recv() {
+        for (event, thread) in std::iter::once(first_event).chain(events_rx.try_iter()) {
+            if named_threads.insert(event["tid"].as_u64()) {
+                let metadata = json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": event["pid"],
+                    "tid": event["tid"],
+                    "args": { "name": thread.name().unwrap_or("unnamed") },
+                });
+                writeln!(writer, "{},", metadata)?;
+            }
+            writeln!(writer, "{},", event)?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+impl TraceRecorder {
+    /// The writer thread ends once the recorder is dropped
+    fn new(writer: impl Write + Send + 'static) -> (Self, JoinHandle<std::io::Result<()>>) {
+        let (events_tx, events_rx) = sync_channel(PENDING_EVENTS);
+        let recorder = TraceRecorder {
+            events_tx,
+            start: Instant::now(),
+        };
+        (
+            recorder,
+            std::thread::spawn(move || write_events(writer, events_rx)),
+        )
+    }
+
+    /// Record an event ending now, with the process and thread fields set
+    fn record(&self, mut event: Value, duration: Duration) {
+        let end = self.start.elapsed();
+        event["ts"] = json!(end.saturating_sub(duration).as_micros() as u64);
+        event["pid"] = json!(std::process::id());
+        event["tid"] = json!(thread_id());
+        // A failing disk only ends the trace
+        if let Err(TrySendError::Disconnected(_)) =
+            self.events_tx.try_send((event, std::thread::current()))
+        {
+            ENABLED.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// An activity which took `duration` and just ended
+    fn complete(&self, name: &str, category: &str, duration: Duration) {
+        self.record(
+            json!({
+                "name": name,
+                "cat": category,
+                "ph": "X",
+                "dur": duration.as_micros() as u64,
+            }),
+            duration,
+        );
+    }
+
+    /// Something which happened now, e.g. an overrun of the audio buffer
+    fn instant(&self, name: &str, category: &str) {
+        self.record(
+            json!({
+                "name": name,
+                "cat": category,
+                "ph": "i",
+                "s": "t",
+            }),
+            Duration::ZERO,
+        );
+    }
+}
+
+/// An activity which took `duration` and just ended
+pub fn complete(name: &str, category: &str, duration: Duration) {
+    if !is_enabled() {
+        return;
+    }
+    if let Some(profile) = PROFILE.get() {
+        profile.complete(name, category, duration);
+    }
+}
+
+/// Something which happened now, e.g. an overrun of the audio buffer
+pub fn instant(name: &str, category: &str) {
+    if !is_enabled() {
+        return;
+    }
+    if let Some(profile) = PROFILE.get() {
+        profile.instant(name, category);
+    }
+}
+
+/// Records the time until it is dropped
+pub struct Span {
+    name: String,
+    category: &'static str,
+    start: Instant,
+}
+
+pub fn span(name: &str, category: &'static str) -> Option<Span> {
+    is_enabled().then(|| Span {
+        name: name.to_string(),
+        category,
+        start: Instant::now(),
+    })
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        complete(&self.name, self.category, self.start.elapsed());
+    }
+}
+
+#[test]
+fn test_trace_profile() {
+    let path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+    let mut writer = BufWriter::new(File::create(&path).unwrap());
+    writer.write_all(b"[\n").unwrap();
+    // Not the global recorder, which would trace the other tests too
+    let (recorder, writer_thread) = TraceRecorder::new(writer);
+    recorder.complete("span", "pipeline", Duration::from_millis(5));
+    recorder.instant("xrun", "audio");
+    drop(recorder);
+    writer_thread.join().unwrap().unwrap();
+
+    let mut contents = std::fs::read_to_string(&path).unwrap();
+    // As a viewer would close the array
+    contents = format!("{}]", contents.trim_end().trim_end_matches(','));
+    let events: Vec<Value> = serde_json::from_str(&contents).unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["name"], "thread_name");
+    assert_eq!(events[1]["name"], "span");
+    assert_eq!(events[1]["ph"], "X");
+    assert_eq!(events[1]["dur"], 5000);
+    assert_eq!(events[2]["ph"], "i");
+    assert_eq!(events[2]["tid"], events[0]["tid"]);
+}
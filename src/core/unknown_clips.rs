@@ -0,0 +1,238 @@
+//! Clips that no provider recognized, kept when the "keep_unrecognized_clips"
+//! preference is on, so that they can be retried later (Shazam learns new
+//! songs every day) or be given their metadata by hand from the GUI.
+//!
+//! Each clip is stored in the "unknown_clips" folder of the data directory
+//! as a 16 kHz mono WAV file, next to a JSON file telling when and from
+//! where it was captured. Only the latest `MAX_CLIPS` clips are kept.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{LazyLock, OnceLock};
+
+use crate::core::preferences::PreferencesInterface;
+use crate::core::thread_messages::RecognitionSource;
+use crate::utils::clock;
+use crate::utils::csv_song_history::SongHistoryRecord;
+use crate::utils::filesystem_operations::{is_incognito, obtain_unknown_clips_directory};
+
+const MAX_CLIPS: usize = 100;
+
+const SAMPLE_RATE: u32 = 16000;
+
+/// Clips waiting to be written, beyond which new ones are dropped
+const PENDING_CLIPS: usize = 4;
+
+/// Read from the preferences when a clip is first missed, rather than at
+/// the start of every command
+static KEEP_UNRECOGNIZED_CLIPS: LazyLock<AtomicBool> = LazyLock::new(|| {
+    AtomicBool::new(
+        PreferencesInterface::new()
+            .preferences
+            .keep_unrecognized_clips
+            == Some(true),
+    )
+});
+
+pub fn set_keep_unrecognized_clips(enabled: bool) {
+    KEEP_UNRECOGNIZED_CLIPS.store(enabled, Ordering::SeqCst);
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClipInfo {
+    recognition_date: String,
+    source: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct UnknownClip {
+    pub audio_path: PathBuf,
+    pub recognition_date: String,
+    pub source: Option<String>,
+}
+
+fn encode_wav(samples: &[f32]) -> Vec<u8> {
+    let data_size = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + samples.len() * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes());
+    }
+    wav
+}
+
+/// The peak level of each of `buckets` slices of a WAV file written by
+/// `encode_wav`, between 0 and 1
+fn wav_peaks(wav: &[u8], buckets: usize) -> Vec<f32> {
+    let samples: Vec<f32> = wav
+        .get(44..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|sample| (i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0).abs())
+        .collect();
+    if samples.is_empty() || buckets == 0 {
+        return vec![];
+    }
+    let bucket_size = samples.len().div_ceil(buckets);
+    samples
+        .chunks(bucket_size)
+        .map(|bucket| bucket.iter().cloned().fold(0.0, f32::max))
+        .collect()
+}
+
+fn info_path(audio_path: &Path) -> PathBuf {
+    audio_path.with_extension("json")
+}
+
+struct PendingClip {
+    samples: Vec<f32>,
+    source: RecognitionSource,
+    date: chrono::DateTime<chrono::FixedOffset>,
+}
+
+/// The clips are encoded and written, and the oldest ones deleted, by a
+/// thread of their own rather than by the recognition loop
+static PENDING: OnceLock<SyncSender<PendingClip>> = OnceLock::new();
+
+/// Save the 16 kHz samples of a window that wasn't recognized, when enabled.
+/// Files and streams already exist elsewhere, so only captures are saved.
+pub fn keep_clip(samples: &[f32], source: &RecognitionSource) {
+    if !KEEP_UNRECOGNIZED_CLIPS.load(Ordering::SeqCst)
+        || is_incognito()
+        || !matches!(
+            source,
+            RecognitionSource::Microphone(_) | RecognitionSource::Monitor(_)
+        )
+    {
+        return;
+    }
+    let pending = PENDING.get_or_init(|| {
+        let (clips_tx, clips_rx) = sync_channel::<PendingClip>(PENDING_CLIPS);
+        std::thread::spawn(move || {
+            for clip in clips_rx {
+                if let Err(error) = save_clip(&clip) {
+                    warn!("Could not keep the unrecognized clip: {}", error);
+                }
+            }
+        });
+        clips_tx
+    });
+    let clip = PendingClip {
+        samples: samples.to_vec(),
+        source: source.clone(),
+        date: clock::now_zoned(),
+    };
+    if let Err(TrySendError::Full(_)) = pending.try_send(clip) {
+        warn!("Dropping an unrecognized clip, the previous ones are still being written");
+    }
+}
+
+fn save_clip(clip: &PendingClip) -> Result<(), Box<dyn Error>> {
+    let now = clip.date;
+    let mut audio_path = obtain_unknown_clips_directory()?;
+    audio_path.push(format!("{}.wav", now.format("%Y%m%d-%H%M%S%.3f")));
+
+    std::fs::write(&audio_path, encode_wav(&clip.samples))?;
+    let info = ClipInfo {
+        recognition_date: clock::format_history_date(&now),
+        source: Some(clip.source.to_string()),
+    };
+    std::fs::write(info_path(&audio_path), serde_json::to_vec(&info)?)?;
+    info!("Kept the unrecognized clip {}", audio_path.display());
+
+    for clip in list_clips().into_iter().skip(MAX_CLIPS) {
+        clip.delete();
+    }
+    Ok(())
+}
+
+/// The kept clips, latest first
+pub fn list_clips() -> Vec<UnknownClip> {
+    let Ok(Ok(entries)) = obtain_unknown_clips_directory().map(std::fs::read_dir) else {
+        return vec![];
+    };
+    let mut clips: Vec<UnknownClip> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "wav"))
+        .filter_map(|audio_path| {
+            let info: ClipInfo =
+                serde_json::from_slice(&std::fs::read(info_path(&audio_path)).ok()?).ok()?;
+            Some(UnknownClip {
+                audio_path,
+                recognition_date: info.recognition_date,
+                source: info.source,
+            })
+        })
+        .collect();
+    // File names are timestamps
+    clips.sort_by(|first, second| second.audio_path.cmp(&first.audio_path));
+    clips
+}
+
+/// The clip being recognized again, when a recognition comes from one
+pub fn find_clip(source: &RecognitionSource) -> Option<UnknownClip> {
+    let RecognitionSource::File(path) = source else {
+        return None;
+    };
+    list_clips()
+        .into_iter()
+        .find(|clip| clip.audio_path == Path::new(path))
+}
+
+impl UnknownClip {
+    pub fn delete(&self) {
+        std::fs::remove_file(&self.audio_path).ok();
+        std::fs::remove_file(info_path(&self.audio_path)).ok();
+    }
+
+    pub fn waveform(&self, buckets: usize) -> Vec<f32> {
+        std::fs::read(&self.audio_path)
+            .map(|wav| wav_peaks(&wav, buckets))
+            .unwrap_or_default()
+    }
+
+    /// The history entry of the clip, to be named by the user
+    pub fn to_record(&self) -> SongHistoryRecord {
+        SongHistoryRecord {
+            song_name: String::new(),
+            album: None,
+            track_key: None,
+            release_year: None,
+            genre: None,
+            recognition_date: self.recognition_date.clone(),
+            source: self.source.clone(),
+        }
+    }
+}
+
+#[test]
+fn test_unknown_clip_wav() {
+    let samples: Vec<f32> = (0..1600)
+        .map(|index| (index % 100) as f32 / 200.0)
+        .collect();
+    let wav = encode_wav(&samples);
+    assert_eq!(wav.len(), 44 + 3200);
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 16000);
+
+    let peaks = wav_peaks(&wav, 4);
+    assert_eq!(peaks.len(), 4);
+    assert!(peaks.iter().all(|peak| (peak - 0.495).abs() < 0.001));
+    assert!(wav_peaks(&wav[..44], 4).is_empty());
+}
@@ -0,0 +1,300 @@
+//! "songrec doctor", which checks what SongRec needs to recognize songs and
+//! forward them (audio devices, access to Shazam, the Spotify access token,
+//! the configuration files and the history), and tells how to fix what
+//! doesn't work, so that the setup problems are solved before they are
+//! reported as bugs.
+
+use gettextrs::{gettext, ngettext};
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::core::aliases::Aliases;
+use crate::core::audio_controllers::audio_backend::{get_any_backend, AudioBackend};
+use crate::core::fingerprinting::communication::check_shazam_reachable;
+use crate::core::preferences::Preferences;
+use crate::core::thread_messages::DeviceListItem;
+use crate::plugins::sinks::parse_sinks_config;
+use crate::plugins::spotify_playlist::SpotifyPlaylist;
+use crate::utils::filesystem_operations::{
+    obtain_aliases_path, obtain_preferences_file_path, obtain_recognition_history_csv_path,
+    obtain_sinks_config_path,
+};
+use crate::utils::history_check::check_history;
+use crate::utils::history_encryption::read_history_file;
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok,
+    Warning,
+    Failed,
+    Skipped,
+}
+
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+    /// What to do about a warning or a failure, one line per problem
+    fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: String, status: Status, detail: String) -> Self {
+        Check {
+            name,
+            status,
+            detail,
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: String) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
+    fn print(&self) {
+        let label = match self.status {
+            Status::Ok => gettext("OK"),
+            Status::Warning => gettext("Warning"),
+            Status::Failed => gettext("Failed"),
+            Status::Skipped => gettext("Skipped"),
+        };
+        println!("[{}] {}: {}", label, self.name, self.detail);
+        for line in self.fix.iter().flat_map(|fix| fix.lines()) {
+            println!("    {}", line);
+        }
+    }
+}
+
+fn check_audio_devices(devices: &[DeviceListItem]) -> Check {
+    let name = gettext("Audio devices");
+    let monitors = devices.iter().filter(|device| device.is_monitor).count();
+    let inputs = devices.len() - monitors;
+    if devices.is_empty() {
+        return Check::new(name, Status::Failed, gettext("No audio device was found")).with_fix(
+            gettext("Check that PipeWire or PulseAudio is running, and that SongRec may use the microphone (the \"audio\" permission of Flatpak or Snap, or the privacy settings of the system)"),
+        );
+    }
+    let detail = gettext("%d inputs, %d monitors of the outputs")
+        .replacen("%d", &inputs.to_string(), 1)
+        .replacen("%d", &monitors.to_string(), 1);
+    let mut fixes = vec![];
+    if monitors == 0 {
+        fixes.push(gettext(
+            "The audio of this computer can't be recognized without a monitor device: use PulseAudio or PipeWire with its PulseAudio server, or a loopback device",
+        ));
+    }
+    if inputs == 0 {
+        fixes.push(gettext(
+            "No microphone was found, plug one in or recognize the audio of this computer",
+        ));
+    } else if devices
+        .iter()
+        .all(|device| device.is_monitor || device.is_hands_free)
+    {
+        fixes.push(gettext(
+            "The only microphones are Bluetooth headsets in a hands-free profile, whose telephone quality audio is rarely recognized: switch them to a high fidelity profile or use another microphone",
+        ));
+    }
+    if fixes.is_empty() {
+        Check::new(name, Status::Ok, detail)
+    } else {
+        Check::new(name, Status::Warning, detail).with_fix(fixes.join("\n"))
+    }
+}
+
+fn check_shazam(session: &soup::Session) -> Check {
+    let name = gettext("Recognition service");
+    match glib::MainContext::default().block_on(check_shazam_reachable(session)) {
+        Ok(()) => Check::new(name, Status::Ok, gettext("Shazam is reachable")),
+        Err(error) => Check::new(name, Status::Failed, error.to_string()).with_fix(gettext(
+            "Check the network connection, log in to the network if it has a login page, and check the proxy settings (the http_proxy and https_proxy environment variables)",
+        )),
+    }
+}
+
+fn check_spotify(access_token: Option<String>) -> Check {
+    let name = gettext("Spotify access token");
+    let Some(access_token) = access_token else {
+        return Check::new(
+            name,
+            Status::Skipped,
+            gettext("No token given with the SPOTIFY_ACCESS_TOKEN environment variable"),
+        );
+    };
+    let spotify = SpotifyPlaylist::new(access_token, String::new());
+    match glib::MainContext::default().block_on(spotify.user_name()) {
+        Ok(user_name) => Check::new(
+            name,
+            Status::Ok,
+            gettext("Valid, for the account of %s").replace("%s", &user_name),
+        ),
+        Err(error) => Check::new(name, Status::Failed, error.to_string()).with_fix(gettext(
+            "Access tokens expire after an hour: generate a new one, with the \"playlist-read-private\" and \"playlist-modify-private\" or \"playlist-modify-public\" scopes",
+        )),
+    }
+}
+
+/// The check of a file whose directory can't be created
+fn failed_path_check(name: String, error: Box<dyn Error>) -> Check {
+    Check::new(name, Status::Failed, error.to_string()).with_fix(gettext(
+        "Check that the configuration and data directories of SongRec can be created, and that the name given to --profile is valid",
+    ))
+}
+
+/// A configuration file is optional, but must be valid when present
+fn check_config_file(
+    name: String,
+    path: Result<PathBuf, Box<dyn Error>>,
+    parse: impl FnOnce(&str) -> Result<String, Box<dyn Error>>,
+) -> Check {
+    let path = match path {
+        Ok(path) => path,
+        Err(error) => return failed_path_check(name, error),
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Check::new(
+                name,
+                Status::Ok,
+                gettext("%s doesn't exist, the defaults apply")
+                    .replace("%s", &path.to_string_lossy()),
+            );
+        }
+        Err(error) => {
+            return Check::new(name, Status::Failed, error.to_string())
+                .with_fix(gettext("Make %s readable").replace("%s", &path.to_string_lossy()));
+        }
+    };
+    match parse(&contents) {
+        Ok(detail) => Check::new(name, Status::Ok, detail),
+        Err(error) => Check::new(name, Status::Failed, error.to_string()).with_fix(
+            gettext("Correct %s, or remove it to return to the defaults")
+                .replace("%s", &path.to_string_lossy()),
+        ),
+    }
+}
+
+fn check_config_files() -> Vec<Check> {
+    vec![
+        check_config_file(
+            gettext("Preferences"),
+            obtain_preferences_file_path(),
+            |contents| {
+                toml::from_str::<Preferences>(contents)?;
+                Ok(gettext("Valid"))
+            },
+        ),
+        check_config_file(gettext("Sinks"), obtain_sinks_config_path(), |contents| {
+            let sinks = parse_sinks_config(contents)?;
+            Ok(ngettext(
+                "%d sink configured",
+                "%d sinks configured",
+                sinks.len() as u32,
+            )
+            .replace("%d", &sinks.len().to_string()))
+        }),
+        check_config_file(gettext("Aliases"), obtain_aliases_path(), |contents| {
+            Aliases::parse(contents)?;
+            Ok(gettext("Valid"))
+        }),
+    ]
+}
+
+fn check_song_history() -> Check {
+    let name = gettext("Song history");
+    let csv_path = match obtain_recognition_history_csv_path() {
+        Ok(csv_path) => csv_path,
+        Err(error) => return failed_path_check(name, error),
+    };
+    if !csv_path.exists() {
+        return Check::new(name, Status::Ok, gettext("No song was recognized yet"));
+    }
+    let check = match read_history_file(&csv_path).and_then(|contents| check_history(&contents)) {
+        Ok(check) => check,
+        Err(error) => {
+            return Check::new(name, Status::Failed, error.to_string()).with_fix(gettext(
+                "If the history is encrypted, check that the keyring of the session is unlocked",
+            ));
+        }
+    };
+    if check.problems.is_empty() {
+        Check::new(
+            name,
+            Status::Ok,
+            ngettext("%d song", "%d songs", check.records.len() as u32)
+                .replace("%d", &check.records.len().to_string()),
+        )
+    } else {
+        Check::new(
+            name,
+            Status::Warning,
+            ngettext(
+                "%d row can't be loaded",
+                "%d rows can't be loaded",
+                check.problems.len() as u32,
+            )
+            .replace("%d", &check.problems.len().to_string()),
+        )
+        .with_fix(gettext(
+            "Run \"songrec history check --fix\" to repair them, after closing the SongRec window",
+        ))
+    }
+}
+
+/// The Spotify token is only read from the environment, as the command
+/// line of a process is visible to the other users
+pub fn doctor_main() -> Result<(), Box<dyn Error>> {
+    let session = soup::Session::new();
+    session.set_timeout(10);
+
+    let mut checks = vec![
+        check_audio_devices(&get_any_backend().list_devices(&cpal::default_host())),
+        check_shazam(&session),
+        check_spotify(std::env::var("SPOTIFY_ACCESS_TOKEN").ok()),
+    ];
+    checks.extend(check_config_files());
+    checks.push(check_song_history());
+
+    for check in &checks {
+        check.print();
+    }
+    let failures = checks
+        .iter()
+        .filter(|check| check.status == Status::Failed)
+        .count();
+    if failures > 0 {
+        return Err(
+            ngettext("%d check failed", "%d checks failed", failures as u32)
+                .replace("%d", &failures.to_string())
+                .into(),
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_audio_devices() {
+    let device = |is_monitor: bool, is_hands_free: bool| DeviceListItem {
+        is_hands_free,
+        ..DeviceListItem::for_test("", is_monitor)
+    };
+    assert_eq!(check_audio_devices(&[]).status, Status::Failed);
+    assert_eq!(
+        check_audio_devices(&[device(false, false), device(true, false)]).status,
+        Status::Ok
+    );
+    assert_eq!(
+        check_audio_devices(&[device(false, false)]).status,
+        Status::Warning
+    );
+    let hands_free = check_audio_devices(&[device(false, true), device(true, false)]);
+    assert_eq!(hands_free.status, Status::Warning);
+    assert!(hands_free.fix.is_some());
+    // Both problems are reported
+    let no_monitor = check_audio_devices(&[device(false, true)]);
+    assert_eq!(no_monitor.status, Status::Warning);
+    assert_eq!(no_monitor.fix.unwrap().lines().count(), 2);
+}
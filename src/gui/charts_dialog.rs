@@ -0,0 +1,193 @@
+//! Dialog showing the personal charts of the week or the month (see
+//! `charts`), from the history menu, and saving them as Markdown or JSON.
+
+use adw::prelude::*;
+use gettextrs::{gettext, ngettext};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::core::aliases;
+use crate::gui::export_dialog::show_export_error;
+use crate::utils::charts::{
+    build_chart, movement_text, render_chart, Chart, ChartEntry, ChartFormat,
+};
+use crate::utils::clock;
+use crate::utils::csv_song_history::SongHistoryRecord;
+use crate::utils::genres::display_genre;
+use crate::utils::history_report::ReportPeriod;
+
+/// As "songrec charts" by default
+const CHART_SIZE: usize = 10;
+
+fn chart_list(entries: &[ChartEntry], display_name: fn(&str) -> String) -> gtk::ListBox {
+    let list_box = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(["boxed-list"])
+        .build();
+    for entry in entries {
+        let row = adw::ActionRow::builder()
+            .title(glib::markup_escape_text(&display_name(&entry.name)))
+            .subtitle(movement_text(entry))
+            .build();
+        row.add_prefix(
+            &gtk::Label::builder()
+                .label(entry.position.to_string())
+                .width_chars(2)
+                .css_classes(["title-2"])
+                .build(),
+        );
+        row.add_suffix(
+            &gtk::Label::builder()
+                .label(
+                    ngettext("%d time", "%d times", entry.times_heard as u32)
+                        .replace("%d", &entry.times_heard.to_string()),
+                )
+                .css_classes(["dim-label"])
+                .build(),
+        );
+        list_box.append(&row);
+    }
+    list_box
+}
+
+fn fill_charts(container: &gtk::Box, chart: &Chart) {
+    while let Some(child) = container.first_child() {
+        container.remove(&child);
+    }
+    if chart.tracks.is_empty() {
+        container.append(
+            &gtk::Label::builder()
+                .label(gettext("No song was recognized during this period"))
+                .css_classes(["dim-label"])
+                .build(),
+        );
+        return;
+    }
+    let as_received: fn(&str) -> String = str::to_string;
+    for (heading, entries, display_name) in [
+        (gettext("Top songs"), &chart.tracks, as_received),
+        (gettext("Top artists"), &chart.artists, as_received),
+        // Grouped by their English name, shown in the language of the
+        // interface
+        (gettext("Top genres"), &chart.genres, display_genre),
+    ] {
+        if entries.is_empty() {
+            continue;
+        }
+        container.append(
+            &gtk::Label::builder()
+                .label(heading)
+                .xalign(0.0)
+                .css_classes(["heading"])
+                .build(),
+        );
+        container.append(&chart_list(entries, display_name));
+    }
+}
+
+/// The records are the ones of the history list, so that an encrypted
+/// history doesn't need to be decrypted again
+pub fn show_charts(window: &adw::ApplicationWindow, mut records: Vec<SongHistoryRecord>) {
+    aliases::apply_to_records(&mut records);
+    let now = clock::now();
+    let chart = Rc::new(RefCell::new(build_chart(
+        &records,
+        ReportPeriod::Week,
+        now,
+        CHART_SIZE,
+    )));
+
+    let week_button = gtk::ToggleButton::builder()
+        .label(gettext("This Week"))
+        .active(true)
+        .build();
+    let month_button = gtk::ToggleButton::builder()
+        .label(gettext("This Month"))
+        .group(&week_button)
+        .build();
+    let period_buttons = gtk::Box::builder()
+        .css_classes(["linked"])
+        .halign(gtk::Align::Center)
+        .homogeneous(true)
+        .build();
+    period_buttons.append(&week_button);
+    period_buttons.append(&month_button);
+
+    let lists = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .build();
+    fill_charts(&lists, &chart.borrow());
+
+    let records = Rc::new(records);
+    for (button, period) in [
+        (&week_button, ReportPeriod::Week),
+        (&month_button, ReportPeriod::Month),
+    ] {
+        let chart = chart.clone();
+        let lists = lists.clone();
+        let records = records.clone();
+        button.connect_toggled(move |button| {
+            if button.is_active() {
+                *chart.borrow_mut() = build_chart(&records, period, now, CHART_SIZE);
+                fill_charts(&lists, &chart.borrow());
+            }
+        });
+    }
+
+    let content = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .build();
+    content.append(&period_buttons);
+    content.append(
+        &gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .propagate_natural_height(true)
+            .max_content_height(400)
+            .child(&lists)
+            .build(),
+    );
+
+    let dialog = adw::AlertDialog::builder()
+        .heading(gettext("Charts"))
+        .body(gettext("The songs, artists and genres you heard the most"))
+        .extra_child(&content)
+        .close_response("close")
+        .build();
+    dialog.add_response("close", &gettext("_Close"));
+    dialog.add_response("export", &gettext("_Export..."));
+
+    let parent = window.clone();
+    dialog.choose(Some(window), None::<&gio::Cancellable>, move |result| {
+        if result.as_str() != "export" {
+            return;
+        }
+        glib::spawn_future_local(async move {
+            let save_dialog = gtk::FileDialog::builder()
+                .title(gettext("Export the charts"))
+                .initial_name("charts.md")
+                .build();
+            let Ok(file) = save_dialog.save_future(Some(&parent)).await else {
+                return;
+            };
+            let Some(path) = file.path() else {
+                return;
+            };
+            let format = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("json") => ChartFormat::Json,
+                _ => ChartFormat::Markdown,
+            };
+            let text = render_chart(&chart.borrow(), format);
+            let exported_path = path.clone();
+            let result = gio::spawn_blocking(move || {
+                std::fs::write(&exported_path, text).map_err(|error| error.to_string())
+            })
+            .await
+            .unwrap_or_else(|_| Err(gettext("The export was interrupted")));
+            if let Err(error) = result {
+                show_export_error(&parent, &path, &error);
+            }
+        });
+    });
+}
@@ -0,0 +1,287 @@
+//! Ctrl+K palette running the actions of the main window by typing a few
+//! letters of their name, for keyboard users who don't want to go through
+//! the menus and the preferences page.
+
+use adw::prelude::*;
+use gettextrs::gettext;
+
+use crate::utils::filesystem_operations;
+
+struct Command {
+    label: String,
+    /// Name of the window action, and its parameter
+    action: &'static str,
+    target: Option<glib::Variant>,
+}
+
+/// The actions of the window that make sense without a selected song, and
+/// the toggles of the sinks of "sinks.toml"
+fn commands(window: &adw::ApplicationWindow, sink_states: &[(&str, bool)]) -> Vec<Command> {
+    let mut commands: Vec<Command> = [
+        ("recognize-now", gettext("Recognize now")),
+        ("recognize-file", gettext("Recognize a file...")),
+        ("recognize-folder", gettext("Recognize a folder...")),
+        (
+            "recognize-computer-audio",
+            gettext("Recognize what this computer plays"),
+        ),
+        ("refresh-devices", gettext("Refresh the audio devices")),
+        ("copy-share-text", gettext("Copy the last song")),
+        ("kiosk-mode", gettext("Kiosk mode")),
+        ("show-preferences", gettext("Preferences")),
+        ("export-history-as", gettext("Export the history...")),
+        ("show-charts", gettext("Charts of the week or month...")),
+        ("export-favorites", gettext("Export the favorites...")),
+        (
+            "add-song-to-history",
+            gettext("Add a song to the history..."),
+        ),
+        (
+            "show-recently-deleted",
+            gettext("Recently deleted songs..."),
+        ),
+        ("show-unknown-clips", gettext("Unrecognized clips...")),
+        ("wipe-history", gettext("Delete the history")),
+        ("incognito-setting", gettext("Toggle the incognito mode")),
+        ("notification-setting", gettext("Toggle the notifications")),
+        ("systray-setting", gettext("Toggle the system tray icon")),
+        ("mpris-setting", gettext("Toggle MPRIS")),
+        ("kdeconnect-setting", gettext("Toggle KDE Connect")),
+        (
+            "announce-setting",
+            gettext("Toggle the spoken announcements"),
+        ),
+        ("update-check-setting", gettext("Toggle the update checks")),
+        ("no-dupes-setting", gettext("Toggle the duplicate songs")),
+        (
+            "encrypt-history-setting",
+            gettext("Toggle the history encryption"),
+        ),
+        (
+            "keep-unrecognized-clips-setting",
+            gettext("Toggle keeping the unrecognized clips"),
+        ),
+        (
+            "prefer-bandcamp-setting",
+            gettext("Toggle preferring Bandcamp"),
+        ),
+        (
+            "echo-cancel-setting",
+            gettext("Toggle the echo cancellation"),
+        ),
+        (
+            "attach-geolocation-setting",
+            gettext("Toggle remembering where songs were heard"),
+        ),
+        ("display-shortcuts", gettext("Keyboard shortcuts")),
+        ("show-about", gettext("About SongRec")),
+        ("close", gettext("Quit")),
+    ]
+    .into_iter()
+    // Some depend on the platform and on the compile-time features
+    .filter(|(name, _)| window.lookup_action(name).is_some())
+    .map(|(action, label)| Command {
+        label,
+        action,
+        target: None,
+    })
+    .collect();
+
+    for &(name, enabled) in sink_states {
+        commands.push(Command {
+            label: if enabled {
+                gettext("Turn off the %s sink")
+            } else {
+                gettext("Turn on the %s sink")
+            }
+            .replace("%s", name),
+            action: "toggle-sink",
+            target: Some(name.to_variant()),
+        });
+    }
+
+    if window.lookup_action("switch-profile").is_some() {
+        let profiles = filesystem_operations::list_profiles();
+        // The default profile is switched to with an empty name
+        for profile in std::iter::once(None).chain(profiles.iter().map(Some)) {
+            if profile.map(String::as_str) == filesystem_operations::current_profile() {
+                continue;
+            }
+            commands.push(Command {
+                label: gettext("Switch to the %s profile").replace(
+                    "%s",
+                    &profile.cloned().unwrap_or_else(|| gettext("Default")),
+                ),
+                action: "switch-profile",
+                target: Some(profile.map(String::as_str).unwrap_or_default().to_variant()),
+            });
+        }
+    }
+    commands
+}
+
+/// Whether the letters of the query appear in order in the text, and how
+/// well: consecutive letters and the starts of words score higher
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous_match: Option<usize> = None;
+    for character in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + text[position..].iter().position(|c| *c == character)?;
+        score += 1;
+        if previous_match.is_some_and(|previous| previous + 1 == found) {
+            score += 3;
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 2;
+        }
+        previous_match = Some(found);
+        position = found + 1;
+    }
+    // Shorter names first among the equal matches
+    Some(score * 100 - text.len() as i32)
+}
+
+pub fn show_command_palette(window: &adw::ApplicationWindow, sink_states: &[(&str, bool)]) {
+    let commands = commands(window, sink_states);
+
+    let search_entry = gtk::SearchEntry::builder()
+        .placeholder_text(gettext("Type a command"))
+        .build();
+    let list_box = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::Browse)
+        .css_classes(["boxed-list"])
+        .build();
+    let content = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    content.append(&search_entry);
+    content.append(
+        &gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .min_content_height(320)
+            .child(&list_box)
+            .build(),
+    );
+
+    let dialog = adw::Dialog::builder()
+        .title(gettext("Commands"))
+        .content_width(420)
+        .child(&content)
+        .build();
+
+    let rows: Vec<(adw::ActionRow, String)> = commands
+        .iter()
+        .map(|command| {
+            let row = adw::ActionRow::builder()
+                .title(glib::markup_escape_text(&command.label))
+                .activatable(true)
+                .build();
+            (row, command.label.clone())
+        })
+        .collect();
+
+    let update_rows = {
+        let list_box = list_box.clone();
+        let rows = rows.clone();
+        move |query: &str| {
+            list_box.remove_all();
+            let mut matching: Vec<(i32, &adw::ActionRow)> = rows
+                .iter()
+                .filter_map(|(row, label)| Some((fuzzy_score(query, label)?, row)))
+                .collect();
+            if !query.trim().is_empty() {
+                matching.sort_by(|first, second| second.0.cmp(&first.0));
+            }
+            for (_, row) in matching {
+                list_box.append(row);
+            }
+            list_box.select_row(list_box.row_at_index(0).as_ref());
+        }
+    };
+    update_rows("");
+    search_entry.connect_search_changed({
+        let update_rows = update_rows.clone();
+        move |entry| update_rows(&entry.text())
+    });
+
+    let run = {
+        let dialog = dialog.clone();
+        let window = window.clone();
+        move |row: &gtk::ListBoxRow| {
+            let Some(command) = rows
+                .iter()
+                .position(|(command_row, _)| command_row.upcast_ref::<gtk::ListBoxRow>() == row)
+                .map(|index| &commands[index])
+            else {
+                return;
+            };
+            dialog.close();
+            let _ = WidgetExt::activate_action(
+                &window,
+                &format!("win.{}", command.action),
+                command.target.as_ref(),
+            );
+        }
+    };
+    list_box.connect_row_activated({
+        let run = run.clone();
+        move |_, row| run(row)
+    });
+    search_entry.connect_activate({
+        let list_box = list_box.clone();
+        move |_| {
+            if let Some(row) = list_box.selected_row() {
+                run(&row);
+            }
+        }
+    });
+    // Up and down move in the list while typing
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.connect_key_pressed(move |_, key, _, _| match key {
+        gdk::Key::Up => {
+            move_selection(&list_box, -1);
+            glib::Propagation::Stop
+        }
+        gdk::Key::Down => {
+            move_selection(&list_box, 1);
+            glib::Propagation::Stop
+        }
+        _ => glib::Propagation::Proceed,
+    });
+    search_entry.add_controller(key_controller);
+
+    dialog.present(Some(window));
+    search_entry.grab_focus();
+}
+
+fn move_selection(list_box: &gtk::ListBox, offset: i32) {
+    let index = list_box
+        .selected_row()
+        .map_or(0, |row| row.index() + offset)
+        .max(0);
+    if let Some(row) = list_box.row_at_index(index) {
+        list_box.select_row(Some(&row));
+    }
+}
+
+#[test]
+fn test_fuzzy_score() {
+    assert!(fuzzy_score("pref", "Preferences").is_some());
+    assert!(fuzzy_score("exh", "Export the history...").is_some());
+    assert_eq!(fuzzy_score("xyz", "Preferences"), None);
+    assert!(
+        fuzzy_score("rec", "Recognize now").unwrap()
+            > fuzzy_score("rec", "Recently deleted songs... preferences").unwrap()
+    );
+    assert!(
+        fuzzy_score("prof", "Switch to the work profile").unwrap()
+            > fuzzy_score("prof", "Toggle preferring Bandcamp, often").unwrap_or(0)
+    );
+}
@@ -4,6 +4,7 @@ use glib::Propagation;
 use gtk::glib::clone;
 use gtk::prelude::*;
 
+use gettextrs::gettext;
 use log::{debug, error, info};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use std::cell::RefCell;
@@ -13,9 +14,17 @@ use std::sync::Mutex;
 
 use crate::gui::song_history_interface::FavoritesInterface;
 
+use crate::core::match_corrections::{self, MatchCorrection};
+use crate::core::metadata_cache;
 use crate::core::preferences::PreferencesInterface;
 use crate::gui::history_entry::HistoryEntry;
+use crate::gui::song_details::{self, DetailsKind};
+use crate::gui::song_dialog;
 use crate::gui::song_history_interface::{RecognitionHistoryInterface, SongRecordInterface};
+use crate::utils::csv_song_history::SongHistoryRecord;
+use crate::utils::filesystem_operations::read_only_history;
+use crate::utils::open_in::{menu_open_in_handlers, render_open_in_url, OpenInHandler};
+use crate::utils::share_text::{render_share_text, DEFAULT_SHARE_TEMPLATE};
 
 pub struct ContextMenuUtil;
 
@@ -156,6 +165,20 @@ impl ContextMenuUtil {
         }); */
     }
 
+    /// List the handlers of the "Open in" submenu, which target the
+    /// "open-in" action with their index
+    pub fn fill_open_in_menu(menu: &gio::Menu, handlers: &[OpenInHandler]) {
+        menu.remove_all();
+        for (index, handler) in handlers.iter().enumerate() {
+            let item = gio::MenuItem::new(Some(&handler.name), None);
+            item.set_action_and_target_value(
+                Some("history-menu.open-in"),
+                Some(&(index as u32).to_variant()),
+            );
+            menu.append_item(&item);
+        }
+    }
+
     pub fn bind_actions(
         window: adw::ApplicationWindow,
         popover_menu: gtk::PopoverMenu,
@@ -218,6 +241,28 @@ impl ContextMenuUtil {
             })
             .build();
 
+        let item = ctx_selected_item.clone();
+        let preferences = preferences_interface.clone();
+        let action_copy_share_text = gio::ActionEntry::builder("copy-share-text")
+            .activate(move |_, _, _| {
+                if let Some(entry) = &*item.borrow() {
+                    if let Some(display) = gdk::Display::default() {
+                        let template = preferences
+                            .lock()
+                            .unwrap()
+                            .preferences
+                            .share_template
+                            .clone();
+                        display.clipboard().set(&render_share_text(
+                            template.as_deref().unwrap_or(DEFAULT_SHARE_TEMPLATE),
+                            &entry.get_song_history_record(),
+                            None,
+                        ));
+                    }
+                }
+            })
+            .build();
+
         let item = ctx_selected_item.clone();
         let preferences = preferences_interface.clone();
         let action_search_youtube = gio::ActionEntry::builder("search-on-youtube")
@@ -258,6 +303,60 @@ impl ContextMenuUtil {
             ))
             .build();
 
+        let item = ctx_selected_item.clone();
+        let preferences = preferences_interface.clone();
+        let action_open_in = gio::ActionEntry::builder("open-in")
+            .parameter_type(Some(&u32::static_variant_type()))
+            .activate(clone!(
+                #[weak]
+                window,
+                move |_, _, parameter| {
+                    let index = parameter
+                        .and_then(|parameter| parameter.get::<u32>())
+                        .unwrap();
+                    let Some(handler) = ({
+                        let lock = preferences.lock().unwrap();
+                        menu_open_in_handlers(
+                            lock.preferences.open_in_handlers.clone(),
+                            lock.preferences.prefer_bandcamp == Some(true),
+                        )
+                    })
+                    .get(index as usize)
+                    .cloned() else {
+                        return;
+                    };
+                    if let Some(entry) = &*item.borrow() {
+                        let shazam_json = entry
+                            .track_key()
+                            .and_then(|track_key| metadata_cache::get_track(&track_key))
+                            .map(|track| serde_json::json!({ "track": track }).to_string());
+                        let Some(url) = render_open_in_url(
+                            &handler.url,
+                            &entry.get_song_history_record(),
+                            shazam_json.as_deref(),
+                        ) else {
+                            error!(
+                                "{}",
+                                gettext("%s can't be opened for this song")
+                                    .replace("%s", &handler.name)
+                            );
+                            return;
+                        };
+
+                        glib::spawn_future_local(async move {
+                            info!("Launching URL: {}", url);
+                            if let Err(err) = gtk::UriLauncher::new(&url)
+                                .launch_future(Some(&window))
+                                .await
+                            {
+                                error!("Could not launch URL {}: {:?}", url, err);
+                            }
+                        });
+                    }
+                }
+            ))
+            .build();
+
         let item = ctx_selected_item.clone();
         let favorites = favorites_interface.clone();
         let action_add_favorites = gio::ActionEntry::builder("add-to-favorites")
@@ -277,7 +376,7 @@ impl ContextMenuUtil {
             .activate(move |_, _, _| {
                 popover.unparent();
                 if let Some(entry) = &*item.borrow() {
-                    history.borrow_mut().remove(entry.get_song_history_record());
+                    history.borrow_mut().delete(entry.get_song_history_record());
                 }
                 *item.borrow_mut() = None;
             })
@@ -298,17 +397,178 @@ impl ContextMenuUtil {
             })
             .build();
 
+        let item = ctx_selected_item.clone();
+        let favorites = favorites_interface.clone();
+        let action_show_artist = gio::ActionEntry::builder("show-artist")
+            .activate(clone!(
+                #[weak]
+                window,
+                move |_, _, _| {
+                    if let Some(entry) = &*item.borrow() {
+                        let song_name = entry.song_name();
+                        if let Some((artist, _track_name)) = song_name.split_once(" - ") {
+                            song_details::show_details(
+                                &window,
+                                DetailsKind::Artist,
+                                artist,
+                                favorites.clone(),
+                            );
+                        }
+                    }
+                }
+            ))
+            .build();
+
+        let item = ctx_selected_item.clone();
+        let favorites = favorites_interface.clone();
+        let action_show_album = gio::ActionEntry::builder("show-album")
+            .activate(clone!(
+                #[weak]
+                window,
+                move |_, _, _| {
+                    if let Some(entry) = &*item.borrow() {
+                        // Albums are empty strings in the history when unknown
+                        if let Some(album) = entry
+                            .get_song_history_record()
+                            .album
+                            .filter(|album| !album.is_empty())
+                        {
+                            song_details::show_details(
+                                &window,
+                                DetailsKind::Album,
+                                &album,
+                                favorites.clone(),
+                            );
+                        }
+                    }
+                }
+            ))
+            .build();
+
+        let item = ctx_selected_item.clone();
+        let history = history_interface.clone();
+        let favorites = favorites_interface.clone();
+        let action_edit_entry = gio::ActionEntry::builder("edit-entry")
+            .activate(clone!(
+                #[weak]
+                window,
+                move |_, _, _| {
+                    if let Some(entry) = &*item.borrow() {
+                        let record = entry.get_song_history_record();
+                        let history = history.clone();
+                        let favorites = favorites.clone();
+                        song_dialog::ask_song(
+                            &window,
+                            &gettext("Edit the song"),
+                            &gettext("Correct the song, when the wrong one or the wrong version was recognized"),
+                            record.clone(),
+                            false,
+                            move |corrected| {
+                                history.borrow_mut().edit(&record, &corrected);
+                                favorites.borrow_mut().edit(&record, &corrected);
+                            },
+                        );
+                    }
+                }
+            ))
+            .build();
+
+        let item = ctx_selected_item.clone();
+        let history = history_interface.clone();
+        let favorites = favorites_interface.clone();
+        let action_report_wrong_match = gio::ActionEntry::builder("report-wrong-match")
+            .activate(clone!(
+                #[weak]
+                window,
+                move |_, _, _| {
+                    let Some(entry) = &*item.borrow() else {
+                        return;
+                    };
+                    let record = entry.get_song_history_record();
+                    let Some(track_key) = record.track_key.clone() else {
+                        return;
+                    };
+                    // Until the right song is entered, its matches aren't
+                    // forwarded to the sinks
+                    if let Err(error) = match_corrections::report_wrong_match(&track_key) {
+                        error!("Could not report the wrong match: {}", error);
+                    }
+
+                    let history = history.clone();
+                    let favorites = favorites.clone();
+                    let cancelled_track_key = track_key.clone();
+                    song_dialog::ask_song_or_cancel(
+                        &window,
+                        &gettext("This match is wrong"),
+                        &gettext("Enter the right song, it will replace this one in the history and the next times it is recognized"),
+                        record.clone(),
+                        false,
+                        move |corrected| {
+                            let (artist_name, song_name) = match corrected.song_name.split_once(" - ") {
+                                Some((artist, title)) => (Some(artist.to_string()), title.to_string()),
+                                None => (None, corrected.song_name.clone()),
+                            };
+                            let correction = MatchCorrection {
+                                track_key: track_key.clone(),
+                                artist_name,
+                                song_name: Some(song_name),
+                                album_name: corrected.album.clone(),
+                            };
+                            if let Err(error) = match_corrections::save_correction(correction) {
+                                error!("Could not save the correction: {}", error);
+                            }
+                            // Keep the track key, for the correction to be
+                            // reported again
+                            let corrected = SongHistoryRecord {
+                                track_key: Some(track_key.clone()),
+                                ..corrected
+                            };
+                            history.borrow_mut().edit(&record, &corrected);
+                            favorites.borrow_mut().edit(&record, &corrected);
+                        },
+                        move || {
+                            if let Err(error) = match_corrections::withdraw_report(&cancelled_track_key) {
+                                error!("Could not withdraw the report of the wrong match: {}", error);
+                            }
+                        },
+                    );
+                }
+            ))
+            .build();
+
         let actions = gio::SimpleActionGroup::new();
         actions.add_action_entries([
             action_copy_artist_track,
             action_copy_artist,
             action_copy_track,
             action_copy_album,
+            action_copy_share_text,
             action_add_favorites,
             action_remove_history,
             action_remove_favorites,
             action_search_youtube,
+            action_open_in,
+            action_show_artist,
+            action_show_album,
+            action_edit_entry,
+            action_report_wrong_match,
         ]);
+        if read_only_history().is_some() {
+            for name in [
+                "add-to-favorites",
+                "remove-from-history",
+                "remove-from-favorites",
+                "edit-entry",
+                "report-wrong-match",
+            ] {
+                if let Some(action) = actions
+                    .lookup_action(name)
+                    .and_downcast::<gio::SimpleAction>()
+                {
+                    action.set_enabled(false);
+                }
+            }
+        }
         window.insert_action_group("history-menu", Some(&actions));
     }
 
@@ -0,0 +1,244 @@
+//! Dialog exporting the song history or the favorites to a file, in one of
+//! the formats of `history_export`, after keeping the songs of a period, a
+//! kind of source, or some genre, artist or album. The columns of the CSV,
+//! TSV and Excel files are the ones of `--csv-columns`.
+
+use adw::prelude::*;
+use gettextrs::{gettext, ngettext};
+use log::{error, info};
+use std::error::Error;
+use std::path::Path;
+
+use crate::core::aliases;
+use crate::utils::csv_song_history::{
+    parse_csv_columns, read_song_history, CsvColumn, DateRange, DEFAULT_CSV_COLUMNS,
+};
+use crate::utils::filesystem_operations::{
+    obtain_favorites_csv_path, obtain_recognition_history_csv_path,
+};
+use crate::utils::history_export::{export_history, ExportFilter, ExportFormat};
+
+/// M3U playlists are left out, since their entries would be the pages of the
+/// songs on Shazam rather than files a player could open
+fn formats() -> Vec<(ExportFormat, String)> {
+    vec![
+        (ExportFormat::Csv, gettext("CSV")),
+        (ExportFormat::Tsv, gettext("TSV (spreadsheets)")),
+        #[cfg(feature = "xlsx")]
+        (ExportFormat::Xlsx, gettext("Excel workbook")),
+        (ExportFormat::Json, gettext("JSON")),
+        (ExportFormat::Xspf, gettext("XSPF playlist")),
+        (ExportFormat::ItunesXml, gettext("iTunes XML playlist")),
+        (ExportFormat::Html, gettext("HTML page")),
+        (ExportFormat::Gpx, gettext("GPX map of the places")),
+    ]
+}
+
+/// The kinds of sources to keep, "All sources" first
+const SOURCE_KINDS: [Option<&str>; 4] = [None, Some("microphone"), Some("monitor"), Some("file")];
+
+/// Returns the number of exported songs. This reads and writes files, so it
+/// should be called outside of the main loop.
+fn export_to(
+    path: &Path,
+    favorites: bool,
+    format: ExportFormat,
+    columns: &[CsvColumn],
+    filter: &ExportFilter,
+) -> Result<usize, Box<dyn Error>> {
+    let csv_path = if favorites {
+        obtain_favorites_csv_path()?
+    } else {
+        obtain_recognition_history_csv_path()?
+    };
+    let mut records = if csv_path.exists() {
+        read_song_history(&csv_path)?
+    } else {
+        vec![]
+    };
+    // Filter on the names displayed in the history
+    aliases::apply_to_records(&mut records);
+    records.retain(|record| filter.matches(record));
+    std::fs::write(path, export_history(&records, columns, format, true)?)?;
+    Ok(records.len())
+}
+
+/// Tell the user that an export failed, rather than only logging it
+pub fn show_export_error(window: &impl IsA<gtk::Widget>, path: &Path, error: &str) {
+    error!("Could not export to {}: {}", path.display(), error);
+    let dialog = adw::AlertDialog::builder()
+        .heading(gettext("Could not export the song history"))
+        .body(
+            gettext("Could not write %s: %s")
+                .replacen("%s", &path.display().to_string(), 1)
+                .replacen("%s", error, 1),
+        )
+        .close_response("ok")
+        .default_response("ok")
+        .build();
+    dialog.add_responses(&[("ok", &gettext("_Ok"))]);
+    dialog.present(Some(window));
+}
+
+fn optional_text(row: &adw::EntryRow) -> Option<String> {
+    let text = row.text();
+    (!text.trim().is_empty()).then(|| text.trim().to_string())
+}
+
+/// Dates are "YYYY-MM-DD" or "YYYY-MM-DD HH:MM", and either may be empty
+fn parse_period(
+    start: &adw::EntryRow,
+    end: &adw::EntryRow,
+) -> Result<Option<DateRange>, chrono::ParseError> {
+    if optional_text(start).is_none() && optional_text(end).is_none() {
+        return Ok(None);
+    }
+    format!("{}..{}", start.text(), end.text())
+        .parse()
+        .map(Some)
+}
+
+pub fn show_export_dialog(window: &adw::ApplicationWindow, favorites: bool) {
+    let formats = formats();
+    let format_names: Vec<&str> = formats.iter().map(|(_, name)| name.as_str()).collect();
+    let format_row = adw::ComboRow::builder()
+        .title(gettext("Format"))
+        .model(&gtk::StringList::new(&format_names))
+        .build();
+    let favorites_row = adw::SwitchRow::builder()
+        .title(gettext("Only the favorites"))
+        .active(favorites)
+        .build();
+    let source_row = adw::ComboRow::builder()
+        .title(gettext("Source"))
+        .model(&gtk::StringList::new(&[
+            &gettext("All sources"),
+            &gettext("Microphone"),
+            &gettext("Speakers"),
+            &gettext("Files"),
+        ]))
+        .build();
+    let start_row = adw::EntryRow::builder()
+        .title(gettext("From (YYYY-MM-DD)"))
+        .build();
+    let end_row = adw::EntryRow::builder()
+        .title(gettext("To (YYYY-MM-DD)"))
+        .build();
+    let genre_row = adw::EntryRow::builder().title(gettext("Genre")).build();
+    let artist_row = adw::EntryRow::builder().title(gettext("Artist")).build();
+    let album_row = adw::EntryRow::builder().title(gettext("Album")).build();
+    let default_columns: Vec<&str> = DEFAULT_CSV_COLUMNS.iter().map(CsvColumn::name).collect();
+    let columns_row = adw::EntryRow::builder()
+        .title(gettext("Columns of the CSV, TSV and Excel files"))
+        .text(default_columns.join(","))
+        .build();
+
+    let group = adw::PreferencesGroup::new();
+    for row in [
+        format_row.upcast_ref::<gtk::Widget>(),
+        favorites_row.upcast_ref(),
+        source_row.upcast_ref(),
+        start_row.upcast_ref(),
+        end_row.upcast_ref(),
+        genre_row.upcast_ref(),
+        artist_row.upcast_ref(),
+        album_row.upcast_ref(),
+        columns_row.upcast_ref(),
+    ] {
+        group.add(row);
+    }
+
+    let dialog = adw::AlertDialog::builder()
+        .heading(gettext("Export the song history"))
+        .body(gettext(
+            "Only the songs matching all the filters are exported; the genre, artist and album must contain the given text.",
+        ))
+        .extra_child(&group)
+        .close_response("cancel")
+        .default_response("export")
+        .build();
+    dialog.add_response("cancel", &gettext("_Cancel"));
+    dialog.add_response("export", &gettext("_Export..."));
+    dialog.set_response_appearance("export", adw::ResponseAppearance::Suggested);
+
+    let set_valid = |row: &adw::EntryRow, is_valid: bool| {
+        if is_valid {
+            row.remove_css_class("error");
+        } else {
+            row.add_css_class("error");
+        }
+    };
+    for row in [&start_row, &end_row, &columns_row] {
+        let dialog = dialog.clone();
+        let start_row = start_row.clone();
+        let end_row = end_row.clone();
+        let columns_row = columns_row.clone();
+        row.connect_changed(move |_| {
+            let is_period_valid = parse_period(&start_row, &end_row).is_ok();
+            set_valid(&start_row, is_period_valid);
+            set_valid(&end_row, is_period_valid);
+            let are_columns_valid = parse_csv_columns(&columns_row.text()).is_ok();
+            set_valid(&columns_row, are_columns_valid);
+            dialog.set_response_enabled("export", is_period_valid && are_columns_valid);
+        });
+    }
+
+    let parent = window.clone();
+    dialog.choose(Some(window), None::<&gio::Cancellable>, move |response| {
+        if response != "export" {
+            return;
+        }
+        let format = formats[format_row.selected() as usize].0;
+        let favorites = favorites_row.is_active();
+        let columns =
+            parse_csv_columns(&columns_row.text()).unwrap_or_else(|_| DEFAULT_CSV_COLUMNS.to_vec());
+        let filter = ExportFilter {
+            period: parse_period(&start_row, &end_row).unwrap_or_default(),
+            source_kind: SOURCE_KINDS[source_row.selected() as usize].map(str::to_string),
+            genre: optional_text(&genre_row),
+            artist: optional_text(&artist_row),
+            album: optional_text(&album_row),
+        };
+        glib::spawn_future_local(async move {
+            // Goes through the file chooser portal when sandboxed
+            let save_dialog = gtk::FileDialog::builder()
+                .title(gettext("Export the song history"))
+                .initial_name(format!(
+                    "{}.{}",
+                    if favorites {
+                        "favorites"
+                    } else {
+                        "song_history"
+                    },
+                    format.extension()
+                ))
+                .build();
+            let Ok(file) = save_dialog.save_future(Some(&parent)).await else {
+                return;
+            };
+            let Some(path) = file.path() else {
+                return;
+            };
+            let exported_path = path.clone();
+            let result = gio::spawn_blocking(move || {
+                export_to(&exported_path, favorites, format, &columns, &filter)
+                    .map_err(|error| error.to_string())
+            })
+            .await
+            .unwrap_or_else(|_| Err(gettext("The export was interrupted")));
+            match result {
+                Ok(count) => info!(
+                    "{}",
+                    ngettext(
+                        "Exported %d song to %s",
+                        "Exported %d songs to %s",
+                        count as u32
+                    )
+                    .replace("%d", &count.to_string())
+                    .replace("%s", &path.display().to_string())
+                ),
+                Err(error) => show_export_error(&parent, &path, &error),
+            }
+        });
+    });
+}
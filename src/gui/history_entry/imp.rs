@@ -21,6 +21,8 @@ pub struct HistoryEntry {
     genre: RefCell<Option<String>>,
     #[property(construct_only, get)]
     recognition_date: RefCell<String>,
+    #[property(construct_only, get)]
+    source: RefCell<Option<String>>,
 }
 
 // The central trait for subclassing a GObject
@@ -15,6 +15,7 @@ impl HistoryEntry {
             .property("release_year", &song.release_year)
             .property("genre", &song.genre)
             .property("recognition_date", &song.recognition_date)
+            .property("source", &song.source)
             .build()
 
         /*
@@ -43,6 +44,7 @@ impl HistoryEntry {
             release_year: self.release_year(),
             genre: self.genre(),
             recognition_date: self.recognition_date(),
+            source: self.source(),
         }
     }
 
@@ -0,0 +1,169 @@
+//! Read-only fullscreen display of the last recognized song, for parties,
+//! bars or radio studios: big cover art, title and artist, and a QR code
+//! linking to the song. Press Escape to leave it.
+
+use adw::prelude::*;
+use gettextrs::gettext;
+use log::error;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::utils::qr_code;
+use crate::utils::song_link::song_link;
+
+/// Size of a QR code module, in pixels
+const QR_CODE_MODULE_PIXELS: usize = 8;
+
+#[derive(Clone)]
+pub struct KioskWindow {
+    window: adw::Window,
+    cover: gtk::Picture,
+    title: gtk::Label,
+    artist: gtk::Label,
+    qr_code: gtk::Picture,
+}
+
+impl KioskWindow {
+    pub fn new(application: &adw::Application) -> Self {
+        let cover = gtk::Picture::builder()
+            .content_fit(gtk::ContentFit::Contain)
+            .hexpand(true)
+            .vexpand(true)
+            .build();
+        let title = gtk::Label::builder()
+            .label(gettext("Waiting for a song..."))
+            .css_classes(["title-1"])
+            .wrap(true)
+            .justify(gtk::Justification::Center)
+            .build();
+        let artist = gtk::Label::builder()
+            .css_classes(["title-2", "dim-label"])
+            .wrap(true)
+            .justify(gtk::Justification::Center)
+            .build();
+        let qr_code = gtk::Picture::builder()
+            .content_fit(gtk::ContentFit::ScaleDown)
+            .can_shrink(false)
+            .visible(false)
+            .build();
+
+        let song_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(12)
+            .valign(gtk::Align::Center)
+            .build();
+        song_box.append(&title);
+        song_box.append(&artist);
+        song_box.append(&qr_code);
+
+        let content = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(48)
+            .margin_top(48)
+            .margin_bottom(48)
+            .margin_start(48)
+            .margin_end(48)
+            .build();
+        content.append(&cover);
+        content.append(&song_box);
+
+        let window = adw::Window::builder()
+            .application(application)
+            .title(gettext("SongRec"))
+            .content(&content)
+            .build();
+
+        let key_controller = gtk::EventControllerKey::new();
+        let window_ptr = window.clone();
+        key_controller.connect_key_pressed(move |_controller, key, _code, _modifiers| {
+            if key == gdk::Key::Escape {
+                window_ptr.close();
+                glib::Propagation::Stop
+            } else {
+                glib::Propagation::Proceed
+            }
+        });
+        window.add_controller(key_controller);
+
+        KioskWindow {
+            window,
+            cover,
+            title,
+            artist,
+            qr_code,
+        }
+    }
+
+    pub fn present(&self) {
+        self.window.fullscreen();
+        self.window.present();
+    }
+
+    pub fn show_song(&self, message: &SongRecognizedMessage) {
+        self.title.set_label(&message.song_name);
+        self.artist.set_label(&message.artist_name);
+
+        let cover = message
+            .cover_image
+            .as_ref()
+            .and_then(|cover_image| gdk::Texture::from_bytes(&glib::Bytes::from(cover_image)).ok());
+        self.cover.set_paintable(cover.as_ref());
+
+        let qr_code = song_link(&message.track_key, &message.shazam_json)
+            .and_then(|link| qr_code_texture(&link));
+        self.qr_code.set_visible(qr_code.is_some());
+        self.qr_code.set_paintable(qr_code.as_ref());
+    }
+}
+
+/// The kiosk window, created when the kiosk mode is entered and destroyed
+/// when it is left, so that it doesn't keep the application running once
+/// the main window is closed
+#[derive(Clone, Default)]
+pub struct Kiosk {
+    window: Rc<RefCell<Option<KioskWindow>>>,
+    last_song: Rc<RefCell<Option<SongRecognizedMessage>>>,
+}
+
+impl Kiosk {
+    pub fn enter(&self, application: &adw::Application) {
+        let mut window = self.window.borrow_mut();
+        let kiosk_window = window.get_or_insert_with(|| {
+            let kiosk_window = KioskWindow::new(application);
+            if let Some(ref message) = *self.last_song.borrow() {
+                kiosk_window.show_song(message);
+            }
+            let window = self.window.clone();
+            kiosk_window.window.connect_close_request(move |_| {
+                window.borrow_mut().take();
+                glib::Propagation::Proceed
+            });
+            kiosk_window
+        });
+        kiosk_window.present();
+    }
+
+    pub fn show_song(&self, message: &SongRecognizedMessage) {
+        if let Some(ref kiosk_window) = *self.window.borrow() {
+            kiosk_window.show_song(message);
+        }
+        *self.last_song.borrow_mut() = Some(message.clone());
+    }
+}
+
+pub fn qr_code_texture(link: &str) -> Option<gdk::MemoryTexture> {
+    match qr_code::render_rgb(link, QR_CODE_MODULE_PIXELS) {
+        Ok((side, pixels)) => Some(gdk::MemoryTexture::new(
+            side as i32,
+            side as i32,
+            gdk::MemoryFormat::R8g8b8,
+            &glib::Bytes::from_owned(pixels),
+            side * 3,
+        )),
+        Err(error) => {
+            error!("Could not render a QR code for {}: {}", link, error);
+            None
+        }
+    }
+}
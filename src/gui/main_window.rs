@@ -1,42 +1,86 @@
 use adw::prelude::*;
-use chrono::Local;
-use gettextrs::gettext;
+use gettextrs::{gettext, ngettext};
 use log::{debug, error, info, trace};
 #[cfg(all(target_os = "linux", feature = "mpris"))]
 use mpris_server::PlaybackStatus;
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use serde_json::json;
+use soup::prelude::SessionExt;
 use std::cell::RefCell;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use crate::core::http_task::http_task;
+use crate::core::audio_controllers::audio_backend::get_any_backend;
+use crate::core::audio_controllers::virtual_capture::is_virtual_device;
+use crate::core::crash_report;
+use crate::core::http_task::{http_task, NetworkSettings};
 use crate::core::logging::Logging;
-use crate::core::microphone_thread::microphone_thread;
-use crate::core::processing_thread::processing_thread;
+use crate::core::metadata_cache;
+use crate::core::microphone_thread::{microphone_thread, set_min_request_interval};
+use crate::core::processing_thread::{processing_thread, recognize_directory};
+use crate::core::providers::ProviderSettings;
+use crate::core::recognition_locations;
 use crate::core::thread_messages::{GUIMessage::*, *};
+use crate::core::unknown_clips::{self, set_keep_unrecognized_clips, UnknownClip};
 
 use crate::gui::song_history_interface::FavoritesInterface;
 
 use crate::gui::song_history_interface::{RecognitionHistoryInterface, SongRecordInterface};
+use crate::gui::source_switch;
+use crate::gui::task_manager;
+use crate::gui::update_checker::{self, is_managed_by_package_manager};
+use crate::gui::window_state;
 #[cfg(target_os = "linux")]
 use crate::plugins::ksni::SystrayInterface;
+#[cfg(target_os = "macos")]
+use crate::plugins::macos_menu_bar::MenuBarItem;
 #[cfg(all(target_os = "linux", feature = "mpris"))]
 use crate::plugins::mpris_player::{get_player, update_song};
-use crate::utils::csv_song_history::SongHistoryRecord;
+use crate::plugins::sinks::{load_sinks_config, should_forward, SinkConfig, SinkRegistry};
+use crate::plugins::speech::SpeechSettings;
+use crate::plugins::webhook::{post_to_webhook, webhook_session};
+use crate::utils::clock;
+use crate::utils::csv_song_history::{
+    prune_song_history, CsvColumn, SongHistoryRecord, RECYCLE_BIN_DAYS,
+};
 use crate::utils::filesystem_operations::{
-    clear_cache, obtain_favorites_csv_path, obtain_recognition_history_csv_path,
+    self, clear_cache, is_incognito, obtain_favorites_csv_path,
+    obtain_recognition_history_csv_path, obtain_sinks_config_path, read_only_history,
+    set_incognito,
+};
+use crate::utils::genres;
+use crate::utils::history_encryption::{
+    check_history_key, is_history_encryption_enabled, set_history_encryption,
 };
+use crate::utils::loudness;
+use crate::utils::open_in::menu_open_in_handlers;
+use crate::utils::recognize_uri::{self, paths_from_recognize_uri};
+use crate::utils::share_text::{render_share_text, DEFAULT_SHARE_TEMPLATE};
+use crate::utils::single_instance::{application_id, lock_profile};
+use crate::utils::song_link::{preview_url, song_link};
+use crate::utils::transliteration;
 
 use crate::core::preferences::{Preferences, PreferencesInterface};
 
+use crate::gui::charts_dialog::show_charts;
+use crate::gui::command_palette::show_command_palette;
 use crate::gui::context_menu::ContextMenuUtil;
+use crate::gui::export_dialog::show_export_dialog;
 use crate::gui::history_entry::HistoryEntry;
+use crate::gui::kiosk_window::{qr_code_texture, Kiosk};
 use crate::gui::listed_device::ListedDevice;
+use crate::gui::song_dialog;
+
+/// The preview being played: track key, media stream and the button of its
+/// history row
+type PreviewPlayer = Rc<RefCell<Option<(String, gtk::MediaFile, glib::WeakRef<gtk::Button>)>>>;
+
+/// The oldest previews are removed from the cache beyond this count
+const MAX_CACHED_PREVIEWS: usize = 100;
 
-#[cfg(windows)]
-use std::os::windows::process::CommandExt;
+const ISSUES_URL: &str = "https://github.com/marin-m/SongRec/issues/new";
 
 pub fn gui_main(
     log_object: Logging,
@@ -44,13 +88,99 @@ pub fn gui_main(
     input_file: Option<String>,
     enable_mpris_cli: bool,
     enable_pipewire_cli: bool,
+    kiosk: bool,
 ) -> Result<(), Box<dyn Error>> {
     let app = App::new(log_object);
-    app.run(recording, enable_mpris_cli, enable_pipewire_cli, input_file);
+    app.run(
+        recording,
+        enable_mpris_cli,
+        enable_pipewire_cli,
+        kiosk,
+        input_file,
+    );
 
     Ok(())
 }
 
+/// Sent to the task forwarding the songs to the sinks
+enum SinkTaskMessage {
+    SongRecognized(SongRecognizedMessage),
+    /// Turn a sink of "sinks.toml" off or on again, from the command palette
+    ToggleSink(String),
+}
+
+fn file_sink_configs() -> Vec<SinkConfig> {
+    match obtain_sinks_config_path().and_then(|path| load_sinks_config(&path)) {
+        Ok(configs) => configs,
+        Err(error) => {
+            error!("Could not load the sinks: {}", error);
+            vec![]
+        }
+    }
+}
+
+/// KDE Connect and the speech announcements, when enabled in the
+/// preferences
+fn preference_sink_configs(preferences: &Preferences) -> Vec<SinkConfig> {
+    let mut configs = vec![];
+    #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+    if preferences.enable_kdeconnect == Some(true) {
+        configs.push(SinkConfig::KdeConnect { device: None });
+    }
+    if preferences.announce_songs == Some(true) {
+        configs.push(SinkConfig::Speech(SpeechSettings::default()));
+    }
+    configs
+}
+
+/// Forwards the songs to the sinks of "sinks.toml", built again when the
+/// file is modified, and to the sinks of the preferences, built again when
+/// they change. The states of the former are kept for the command palette.
+fn spawn_sink_task(
+    preferences_interface: Arc<Mutex<PreferencesInterface>>,
+    sink_rx: async_channel::Receiver<SinkTaskMessage>,
+    sink_states: Rc<RefCell<Vec<(&'static str, bool)>>>,
+) {
+    glib::spawn_future_local(async move {
+        let mut file_sinks = SinkRegistry::default();
+        let mut file_modified = None;
+        let mut preference_sinks = SinkRegistry::default();
+        let mut enabled = None;
+        loop {
+            let modified = obtain_sinks_config_path()
+                .ok()
+                .and_then(|path| std::fs::metadata(path).ok())
+                .and_then(|metadata| metadata.modified().ok());
+            if file_modified != Some(modified) {
+                file_sinks.replace(file_sink_configs());
+                file_modified = Some(modified);
+            }
+            let preferences = preferences_interface.lock().unwrap().preferences.clone();
+            let now_enabled = (preferences.enable_kdeconnect, preferences.announce_songs);
+            if enabled != Some(now_enabled) {
+                preference_sinks.replace(preference_sink_configs(&preferences));
+                enabled = Some(now_enabled);
+            }
+            *sink_states.borrow_mut() = file_sinks.states();
+
+            match sink_rx.recv().await {
+                Ok(SinkTaskMessage::SongRecognized(message)) => {
+                    // Songs played by the local Spotify client are always
+                    // held back, as the GUI often listens to the computer
+                    if should_forward(&message, true).await {
+                        file_sinks.song_recognized(&message).await;
+                        preference_sinks.song_recognized(&message).await;
+                    }
+                }
+                Ok(SinkTaskMessage::ToggleSink(name)) => file_sinks.toggle(&name),
+                Err(_) => break,
+            }
+        }
+        file_sinks.close();
+        preference_sinks.close();
+    });
+}
+
 struct App {
     builder: gtk::Builder,
 
@@ -60,9 +190,13 @@ struct App {
     old_preferences: Preferences,
 
     ctx_selected_item: Rc<RefCell<Option<HistoryEntry>>>,
+    /// The last recognized song, with its Shazam JSON
+    ctx_last_song: Rc<RefCell<Option<(SongHistoryRecord, String)>>>,
     ctx_buffered_log: Rc<RefCell<String>>,
     #[cfg(target_os = "linux")]
     ctx_systray_handle: Rc<RefCell<Option<ksni::Handle<SystrayInterface>>>>,
+    #[cfg(target_os = "macos")]
+    ctx_menu_bar: Rc<RefCell<Option<MenuBarItem>>>,
     ctx_logger_source_id: Rc<RefCell<Option<glib::source::SourceId>>>,
 
     gui_tx: async_channel::Sender<GUIMessage>,
@@ -73,6 +207,12 @@ struct App {
     processing_rx: async_channel::Receiver<ProcessingMessage>,
     http_tx: async_channel::Sender<HTTPMessage>,
     http_rx: async_channel::Receiver<HTTPMessage>,
+    sink_tx: async_channel::Sender<SinkTaskMessage>,
+    sink_rx: async_channel::Receiver<SinkTaskMessage>,
+    /// The sinks of "sinks.toml", and whether they are turned on
+    ctx_sink_states: Rc<RefCell<Vec<(&'static str, bool)>>>,
+    /// Used by the requests to Shazam and the downloads of the previews
+    network_settings: NetworkSettings,
 }
 
 // #[gtk::template_callbacks(functions)]
@@ -82,6 +222,7 @@ impl App {
         let (microphone_tx, microphone_rx) = async_channel::unbounded();
         let (processing_tx, processing_rx) = async_channel::unbounded();
         let (http_tx, http_rx) = async_channel::unbounded();
+        let (sink_tx, sink_rx) = async_channel::unbounded();
 
         log_object.connect_to_gui_logger(gui_tx.clone());
 
@@ -92,6 +233,26 @@ impl App {
         let ctx_logger_source_id: Rc<RefCell<Option<glib::source::SourceId>>> =
             Rc::new(RefCell::new(None));
 
+        let preferences_interface: PreferencesInterface = PreferencesInterface::new();
+        let old_preferences: Preferences = preferences_interface.preferences.clone();
+        let preferences_interface = Arc::new(Mutex::new(preferences_interface));
+
+        let mut history_pruned = false;
+        if let (Ok(csv_path), None) = (obtain_recognition_history_csv_path(), read_only_history()) {
+            match prune_song_history(
+                &csv_path,
+                old_preferences.history_max_entries.unwrap_or(0),
+                old_preferences.history_max_months.unwrap_or(0),
+            ) {
+                Ok(0) => {}
+                Ok(removed) => {
+                    info!("Removed {} entries from the song history", removed);
+                    history_pruned = true;
+                }
+                Err(error) => error!("Could not prune the song history: {}", error),
+            }
+        }
+
         let history_list_store: gio::ListStore = gio::ListStore::new::<HistoryEntry>();
         let song_history_interface = Rc::new(RefCell::new(
             RecognitionHistoryInterface::new(
@@ -100,6 +261,9 @@ impl App {
             )
             .unwrap(),
         ));
+        if history_pruned {
+            song_history_interface.borrow().prune_locations();
+        }
 
         let favorites_list_store = gio::ListStore::new::<HistoryEntry>();
         let favorites_interface = Rc::new(RefCell::new(
@@ -113,10 +277,11 @@ impl App {
         // Self::add_callbacks_to_scope(&scope);
         builder.set_scope(Some(&builder_scope));
 
-        let preferences_interface: PreferencesInterface = PreferencesInterface::new();
-        let old_preferences: Preferences = preferences_interface.preferences.clone();
-        let preferences_interface = Arc::new(Mutex::new(preferences_interface));
+        let mut network_settings = NetworkSettings::default();
+        network_settings.detect_metered_connection();
+        set_min_request_interval(network_settings.min_request_interval());
 
+        Self::setup_preview_callbacks(&builder_scope, network_settings.clone());
         Self::setup_callbacks(
             microphone_tx.clone(),
             gui_tx.clone(),
@@ -130,8 +295,60 @@ impl App {
             .add_from_resource("/re/fossplant/songrec/interface.ui")
             .unwrap();
 
+        // Entries can be filtered by the kind of source they were recognized from
+        let history_filter = gtk::CustomFilter::new(|_| true);
+        let history_filter_model = gtk::FilterListModel::new(
+            Some(history_list_store.clone()),
+            Some(history_filter.clone()),
+        );
+
+        // Entries are grouped under a header per day, in the same order
+        let history_day_sorter = gtk::CustomSorter::new(|first, second| {
+            Self::recognition_day(second)
+                .cmp(&Self::recognition_day(first))
+                .into()
+        });
+        let history_sections_model =
+            gtk::SortListModel::new(Some(history_filter_model), None::<gtk::Sorter>);
+        history_sections_model.set_section_sorter(Some(&history_day_sorter));
+
         let history_selection: gtk::SingleSelection = builder.object("history_selection").unwrap();
-        history_selection.set_model(Some(&history_list_store));
+        history_selection.set_model(Some(&history_sections_model));
+
+        let history_header_factory = gtk::SignalListItemFactory::new();
+        history_header_factory.connect_setup(|_, header| {
+            let header = header.downcast_ref::<gtk::ListHeader>().unwrap();
+            let label = gtk::Label::new(None);
+            label.set_xalign(0.0);
+            label.add_css_class("heading");
+            label.add_css_class("day_header");
+            header.set_child(Some(&label));
+            // Songs are added to the current day while it is displayed
+            header.connect_n_items_notify(Self::update_day_header);
+        });
+        history_header_factory.connect_bind(|_, header| {
+            Self::update_day_header(header.downcast_ref::<gtk::ListHeader>().unwrap());
+        });
+        let history_view: gtk::ColumnView = builder.object("history_view").unwrap();
+        history_view.set_header_factory(Some(&history_header_factory));
+
+        let history_source_filter: adw::ComboRow = builder.object("history_source_filter").unwrap();
+        history_source_filter.connect_selected_notify(move |row| {
+            let kind = match row.selected() {
+                1 => Some("microphone"),
+                2 => Some("monitor"),
+                3 => Some("file"),
+                _ => None,
+            };
+            history_filter.set_filter_func(move |item| {
+                kind.is_none_or(|kind| {
+                    item.downcast_ref::<HistoryEntry>()
+                        .unwrap()
+                        .get_song_history_record()
+                        .has_source_kind(kind)
+                })
+            });
+        });
 
         let favorites_selection: gtk::SingleSelection =
             builder.object("favorites_selection").unwrap();
@@ -140,6 +357,14 @@ impl App {
         let request_interval_value: gtk::Adjustment = builder.object("interval_value").unwrap();
         request_interval_value.set_value(old_preferences.request_interval_secs_v3.unwrap() as f64);
 
+        let history_max_entries_value: gtk::Adjustment =
+            builder.object("history_max_entries_value").unwrap();
+        history_max_entries_value
+            .set_value(old_preferences.history_max_entries.unwrap_or(0) as f64);
+        let history_max_months_value: gtk::Adjustment =
+            builder.object("history_max_months_value").unwrap();
+        history_max_months_value.set_value(old_preferences.history_max_months.unwrap_or(0) as f64);
+
         App {
             builder,
 
@@ -150,8 +375,11 @@ impl App {
 
             #[cfg(target_os = "linux")]
             ctx_systray_handle: Rc::new(RefCell::new(None)),
+            #[cfg(target_os = "macos")]
+            ctx_menu_bar: Rc::new(RefCell::new(None)),
 
             ctx_selected_item,
+            ctx_last_song: Rc::new(RefCell::new(None)),
             ctx_buffered_log,
             ctx_logger_source_id,
 
@@ -163,6 +391,10 @@ impl App {
             processing_rx,
             http_tx,
             http_rx,
+            sink_tx,
+            sink_rx,
+            ctx_sink_states: Rc::new(RefCell::new(vec![])),
+            network_settings,
         }
     }
 
@@ -186,10 +418,11 @@ impl App {
         set_recording: bool,
         enable_mpris_cli: bool,
         enable_pipewire_cli: bool,
+        kiosk: bool,
         input_file: Option<String>,
     ) {
         let application = adw::Application::new(
-            glib::prgname().as_deref(), // Set the DBus ID of the program.
+            Some(&application_id()), // Set the DBus ID of the program.
             gio::ApplicationFlags::HANDLES_OPEN,
         );
 
@@ -202,9 +435,20 @@ impl App {
 
         let processing_tx = self.processing_tx.clone();
 
-        application.connect_open(move |_application, files, _hint| {
-            if !files.is_empty() {
-                if let Some(file_path) = files[0].path() {
+        application.connect_open(move |application, files, _hint| {
+            // Files opened from a second launch are recognized by this
+            // instance, which is raised
+            if let Some(main_window) = application.windows().first() {
+                main_window.present();
+            }
+            for file in files {
+                // songrec://recognize?path=... from the file managers
+                let file_paths = if file.has_uri_scheme(recognize_uri::SCHEME) {
+                    paths_from_recognize_uri(&file.uri())
+                } else {
+                    file.path().into_iter().collect()
+                };
+                for file_path in file_paths {
                     let file_path_string = file_path.into_os_string().into_string().unwrap();
 
                     processing_tx
@@ -228,6 +472,7 @@ impl App {
                 set_recording,
                 enable_mpris_cli,
                 enable_pipewire_cli,
+                kiosk,
             );
         });
 
@@ -302,6 +547,347 @@ impl App {
         search_url_row.set_text(&url);
 
         search_row.set_title(&label);
+
+        let share_template_row: adw::EntryRow = self.builder.object("share_template").unwrap();
+        share_template_row.set_text(
+            preferences
+                .share_template
+                .as_deref()
+                .unwrap_or(DEFAULT_SHARE_TEMPLATE),
+        );
+
+        let share_webhook_row: adw::EntryRow = self.builder.object("share_webhook_url").unwrap();
+        share_webhook_row.set_text(preferences.share_webhook_url.as_deref().unwrap_or_default());
+
+        let venue_row: adw::EntryRow = self.builder.object("recognition_venue").unwrap();
+        venue_row.set_text(preferences.recognition_venue.as_deref().unwrap_or_default());
+    }
+
+    /// Previews are only known for the songs whose metadata is still cached
+    fn entry_preview_url(entry: &HistoryEntry) -> Option<String> {
+        let track = metadata_cache::get_track(&entry.track_key()?)?;
+        preview_url(&track)
+    }
+
+    /// Play the preview of a history entry, or stop it when it is playing
+    fn toggle_preview(
+        player: &PreviewPlayer,
+        network_settings: &NetworkSettings,
+        button: &gtk::Button,
+        entry: &HistoryEntry,
+    ) {
+        let track_key = entry.track_key().unwrap_or_default();
+        if let Some((playing_track_key, media, playing_button)) = player.borrow_mut().take() {
+            media.set_playing(false);
+            if let Some(playing_button) = playing_button.upgrade() {
+                playing_button.set_icon_name("media-playback-start-symbolic");
+            }
+            if playing_track_key == track_key {
+                return;
+            }
+        }
+        let Some(url) = Self::entry_preview_url(entry) else {
+            return;
+        };
+
+        let media = gtk::MediaFile::new();
+        let button_ref = button.downgrade();
+        media.connect_ended_notify(move |media| {
+            if media.is_ended() {
+                if let Some(button) = button_ref.upgrade() {
+                    button.set_icon_name("media-playback-start-symbolic");
+                }
+            }
+        });
+        media.connect_error_notify(|media| {
+            if let Some(error) = media.error() {
+                error!("{} {}", gettext("Could not play the preview:"), error);
+            }
+        });
+        button.set_icon_name("media-playback-stop-symbolic");
+        *player.borrow_mut() = Some((track_key.clone(), media.clone(), button.downgrade()));
+
+        let player = player.clone();
+        let network_settings = network_settings.clone();
+        glib::spawn_future_local(async move {
+            // The preview is downloaded in order to measure its loudness,
+            // otherwise GTK streams it with its media backend at full volume,
+            // as in incognito mode, where nothing is cached on the disk
+            let downloaded = if is_incognito() {
+                Err(gettext("Incognito mode").into())
+            } else {
+                Self::download_preview(&network_settings, &track_key, &url).await
+            };
+            let file = match downloaded {
+                Ok(path) => {
+                    media.set_volume(loudness::file_playback_volume(path.clone()).await);
+                    gio::File::for_path(&path)
+                }
+                Err(error) => {
+                    debug!("Could not download the preview {}: {}", url, error);
+                    gio::File::for_uri(&url)
+                }
+            };
+            // Unless it was stopped in the meantime
+            if player
+                .borrow()
+                .as_ref()
+                .is_some_and(|(_, playing_media, _)| *playing_media == media)
+            {
+                media.set_file(Some(&file));
+                media.play();
+            }
+        });
+    }
+
+    /// The previews are kept in the cache folder, to be played again
+    async fn download_preview(
+        network_settings: &NetworkSettings,
+        track_key: &str,
+        url: &str,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let directory = filesystem_operations::obtain_cache_directory()?.join("previews");
+        let path = directory.join(track_key.replace(['/', '\\', ':'], "_"));
+        if path.exists() {
+            return Ok(path);
+        }
+        // Set up as the session of the requests to Shazam, which goes
+        // through the proxy of the system
+        let session = network_settings.new_session()?;
+        let message = soup::Message::new("GET", url)?;
+        let response = session
+            .send_and_read_future(&message, glib::Priority::DEFAULT)
+            .await?;
+        if message.status_code() != 200 {
+            return Err(format!("HTTP status {}", message.status_code()).into());
+        }
+        if response.is_empty() {
+            return Err("Empty preview".into());
+        }
+        std::fs::create_dir_all(&directory)?;
+        // Not to keep a truncated preview if SongRec stops midway
+        filesystem_operations::write_atomically(&path, &response)?;
+        Self::prune_previews(&directory);
+        Ok(path)
+    }
+
+    /// Keep the last `MAX_CACHED_PREVIEWS` downloaded previews
+    fn prune_previews(directory: &Path) {
+        let Ok(contents) = std::fs::read_dir(directory) else {
+            return;
+        };
+        let mut previews: Vec<(std::time::SystemTime, PathBuf)> = contents
+            .flatten()
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        if previews.len() <= MAX_CACHED_PREVIEWS {
+            return;
+        }
+        previews.sort();
+        for (_, path) in &previews[..previews.len() - MAX_CACHED_PREVIEWS] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    fn recognition_day(item: &glib::Object) -> Option<chrono::NaiveDate> {
+        let entry = item.downcast_ref::<HistoryEntry>()?;
+        clock::parse_history_date(&entry.recognition_date()).map(|date| date.date())
+    }
+
+    /// Show the day of the songs of a section of the history list, and their
+    /// count
+    fn update_day_header(header: &gtk::ListHeader) {
+        let (Some(label), Some(item)) =
+            (header.child().and_downcast::<gtk::Label>(), header.item())
+        else {
+            return;
+        };
+        let today = clock::now().date();
+        let day = match Self::recognition_day(&item) {
+            Some(day) if day == today => gettext("Today"),
+            Some(day) if today.pred_opt() == Some(day) => gettext("Yesterday"),
+            Some(day) => clock::display_day(day),
+            None => gettext("Unknown date"),
+        };
+        let count = ngettext("%d song", "%d songs", header.n_items())
+            .replace("%d", &header.n_items().to_string());
+        label.set_text(&format!("{} — {}", day, count));
+    }
+
+    /// Restore the saved layout of the history list, and let its columns be
+    /// shown, hidden and moved from the menu of their headers
+    fn setup_history_columns(&self) {
+        let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
+        let history_view: gtk::ColumnView = self.builder.object("history_view").unwrap();
+
+        if let Some(layout) = &self.old_preferences.history_columns {
+            Self::apply_history_column_layout(&history_view, layout);
+        }
+
+        let columns: Vec<gtk::ColumnViewColumn> = history_view
+            .columns()
+            .iter::<gtk::ColumnViewColumn>()
+            .flatten()
+            .collect();
+
+        let visibility_section = gio::Menu::new();
+        for column in &columns {
+            let id = column.id().unwrap_or_default();
+            // The song name is always shown
+            if id == "song_name" {
+                continue;
+            }
+            let action_name = format!("history-column-{}", id);
+            let action = gio::SimpleAction::new_stateful(
+                &action_name,
+                None,
+                &column.is_visible().to_variant(),
+            );
+            let column = column.clone();
+            let history_view = history_view.clone();
+            let gui_tx = self.gui_tx.clone();
+            action.connect_activate(move |action, _| {
+                let visible = !action.state().unwrap().get::<bool>().unwrap();
+                action.set_state(&visible.to_variant());
+                column.set_visible(visible);
+                Self::save_history_column_layout(&history_view, &gui_tx);
+            });
+            window.add_action(&action);
+            visibility_section.append(
+                column.title().as_deref(),
+                Some(&format!("win.{}", action_name)),
+            );
+        }
+
+        for (name, offset) in [
+            ("move-history-column-left", -1),
+            ("move-history-column-right", 1),
+        ] {
+            let action = gio::SimpleAction::new(name, Some(glib::VariantTy::STRING));
+            let history_view = history_view.clone();
+            let gui_tx = self.gui_tx.clone();
+            action.connect_activate(move |_, parameter| {
+                let id: String = parameter.unwrap().get().unwrap();
+                let columns = history_view.columns();
+                let Some(position) = columns
+                    .iter::<gtk::ColumnViewColumn>()
+                    .flatten()
+                    .position(|column| column.id().as_deref() == Some(id.as_str()))
+                else {
+                    return;
+                };
+                // Step over the hidden columns
+                let mut new_position = position as i32 + offset;
+                while (0..columns.n_items() as i32).contains(&new_position)
+                    && !columns
+                        .item(new_position as u32)
+                        .and_downcast::<gtk::ColumnViewColumn>()
+                        .unwrap()
+                        .is_visible()
+                {
+                    new_position += offset;
+                }
+                if new_position < 0 || new_position >= columns.n_items() as i32 {
+                    return;
+                }
+                let column: gtk::ColumnViewColumn =
+                    columns.item(position as u32).and_downcast().unwrap();
+                history_view.insert_column(new_position as u32, &column);
+                Self::save_history_column_layout(&history_view, &gui_tx);
+            });
+            window.add_action(&action);
+        }
+
+        for column in &columns {
+            let id = column.id().unwrap_or_default();
+            let move_section = gio::Menu::new();
+            for (label, name) in [
+                (gettext("Move left"), "win.move-history-column-left"),
+                (gettext("Move right"), "win.move-history-column-right"),
+            ] {
+                let item = gio::MenuItem::new(Some(&label), None);
+                item.set_action_and_target_value(Some(name), Some(&id.to_variant()));
+                move_section.append_item(&item);
+            }
+            let header_menu = gio::Menu::new();
+            header_menu.append_section(None, &move_section);
+            header_menu.append_section(Some(&gettext("Columns")), &visibility_section);
+            column.set_header_menu(Some(&header_menu));
+        }
+    }
+
+    /// Show the columns of a saved layout of the history list in its order,
+    /// and hide the others
+    fn apply_history_column_layout(history_view: &gtk::ColumnView, layout: &str) {
+        let columns: Vec<gtk::ColumnViewColumn> = history_view
+            .columns()
+            .iter::<gtk::ColumnViewColumn>()
+            .flatten()
+            .collect();
+        let mut position = 0;
+        for id in layout.split(',') {
+            if let Some(column) = columns
+                .iter()
+                .find(|column| column.id().as_deref() == Some(id))
+            {
+                history_view.insert_column(position, column);
+                column.set_visible(true);
+                position += 1;
+            }
+        }
+        for column in columns {
+            if !layout
+                .split(',')
+                .any(|id| column.id().as_deref() == Some(id))
+            {
+                column.set_visible(column.id().as_deref() == Some("song_name"));
+            }
+        }
+    }
+
+    fn save_history_column_layout(
+        history_view: &gtk::ColumnView,
+        gui_tx: &async_channel::Sender<GUIMessage>,
+    ) {
+        let layout: Vec<String> = history_view
+            .columns()
+            .iter::<gtk::ColumnViewColumn>()
+            .flatten()
+            .filter(|column| column.is_visible())
+            .filter_map(|column| column.id().map(|id| id.to_string()))
+            .collect();
+        let mut new_preference = Preferences::new();
+        new_preference.history_columns = Some(layout.join(","));
+        gui_tx
+            .try_send(GUIMessage::UpdatePreference(new_preference))
+            .unwrap();
+    }
+
+    /// Tell that the history comes from elsewhere, and disable the actions
+    /// that would modify it
+    fn setup_read_only_history(&self, csv_path: &Path) {
+        let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
+        let read_only_banner: adw::Banner = self.builder.object("read_only_banner").unwrap();
+        read_only_banner.set_title(
+            &gettext("Browsing the history of %s, read-only: new songs won't be added to it")
+                .replace("%s", &csv_path.display().to_string()),
+        );
+        read_only_banner.set_revealed(true);
+
+        for name in [
+            "add-song-to-history",
+            "wipe-history",
+            "show-recently-deleted",
+            "show-unknown-clips",
+            "encrypt-history-setting",
+        ] {
+            if let Some(action) = window
+                .lookup_action(name)
+                .and_downcast::<gio::SimpleAction>()
+            {
+                action.set_enabled(false);
+            }
+        }
     }
 
     fn on_startup(
@@ -310,23 +896,260 @@ impl App {
         set_recording: bool,
         enable_mpris_cli: bool,
         enable_pipewire_cli: bool,
+        enable_kiosk: bool,
     ) {
+        // This instance is the only GUI of its profile, but `songrec listen`
+        // may already capture audio for it
+        let set_recording = match lock_profile() {
+            Ok(()) => set_recording,
+            Err(error) => {
+                self.gui_tx
+                    .try_send(GUIMessage::ErrorMessage(error.to_string()))
+                    .unwrap();
+                false
+            }
+        };
         clear_cache();
+        let kiosk = Kiosk::default();
         self.setup_intercom(
             application,
             set_recording,
             enable_mpris_cli,
             enable_pipewire_cli,
+            kiosk.clone(),
         );
-        self.setup_actions(application, enable_mpris_cli);
+        self.setup_actions(application, enable_mpris_cli, kiosk.clone());
         #[cfg(target_os = "linux")]
         if self.old_preferences.enable_systray == Some(true) {
             let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
             Self::setup_systray(self.ctx_systray_handle.clone(), window, self.gui_tx.clone());
         }
+        #[cfg(target_os = "macos")]
+        if self.old_preferences.enable_systray == Some(true) {
+            let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
+            Self::setup_menu_bar(self.ctx_menu_bar.clone(), window, self.gui_tx.clone());
+        }
         self.setup_context_menus();
+        self.setup_history_columns();
+        if let Some(csv_path) = read_only_history() {
+            self.setup_read_only_history(csv_path);
+        }
+        self.setup_profile_switcher();
         self.update_website_search_text();
         self.show_window(application);
+        if enable_kiosk {
+            kiosk.enter(application);
+        }
+
+        // Only offer the latest crash report
+        let mut crash_reports = crash_report::unseen_crash_reports();
+        if let Some(report) = crash_reports.pop() {
+            for older_report in crash_reports {
+                crash_report::mark_as_seen(&older_report);
+            }
+            let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
+            Self::offer_crash_report(window, report);
+        }
+
+        if self.old_preferences.check_for_updates == Some(true) && !is_managed_by_package_manager()
+        {
+            let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
+            Self::check_for_updates(window);
+        }
+    }
+
+    fn offer_crash_report(window: adw::ApplicationWindow, report: PathBuf) {
+        glib::spawn_future_local(async move {
+            let dialog = adw::AlertDialog::builder()
+                .heading(gettext("SongRec crashed"))
+                .body(
+                    gettext("A crash report was saved to %s. Please attach it when reporting the issue, after checking that it contains nothing private.")
+                        .replace("%s", &report.display().to_string()),
+                )
+                .close_response("close")
+                .default_response("report")
+                .build();
+            dialog.add_responses(&[
+                ("close", &gettext("_Close")),
+                ("open-folder", &gettext("_Open Folder")),
+                ("report", &gettext("_Report Issue")),
+            ]);
+            dialog.set_response_appearance("report", adw::ResponseAppearance::Suggested);
+
+            let response = dialog.choose_future(Some(&window)).await;
+            let report = crash_report::mark_as_seen(&report);
+
+            match response.as_str() {
+                "open-folder" => {
+                    let file = gio::File::for_path(&report);
+                    if let Err(err) = gtk::FileLauncher::new(Some(&file))
+                        .open_containing_folder_future(Some(&window))
+                        .await
+                    {
+                        error!("Could not open the crash reports folder: {:?}", err);
+                    }
+                }
+                "report" => {
+                    if let Err(err) = gtk::UriLauncher::new(ISSUES_URL)
+                        .launch_future(Some(&window))
+                        .await
+                    {
+                        error!("Could not launch URL {}: {:?}", ISSUES_URL, err);
+                    }
+                }
+                _ => {}
+            }
+        });
+    }
+
+    /// The history and preferences files are opened at startup, so
+    /// switching to another profile takes effect on the next start.
+    fn setup_profile_switcher(&self) {
+        let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
+        let profile_setting: adw::ComboRow = self.builder.object("profile_setting").unwrap();
+        let profile_model: gtk::StringList = self.builder.object("profile_model").unwrap();
+        let new_profile_entry: adw::EntryRow = self.builder.object("new_profile_entry").unwrap();
+
+        profile_model.append(&gettext("Default"));
+        for (position, profile) in filesystem_operations::list_profiles().iter().enumerate() {
+            profile_model.append(profile);
+            if Some(profile.as_str()) == filesystem_operations::current_profile() {
+                profile_setting.set_selected(position as u32 + 1);
+            }
+        }
+
+        // Used by the command palette; the parameter is the name of the
+        // profile, empty for the default one
+        let action_switch_profile = gio::ActionEntry::builder("switch-profile")
+            .parameter_type(Some(&String::static_variant_type()))
+            .activate({
+                let profile_setting = profile_setting.clone();
+                let profile_model = profile_model.clone();
+                move |_: &adw::ApplicationWindow, _, parameter| {
+                    let Some(profile) = parameter.and_then(|parameter| parameter.str()) else {
+                        return;
+                    };
+                    let position = match profile {
+                        "" => Some(0),
+                        _ => (1..profile_model.n_items()).find(|position| {
+                            profile_model.string(*position).as_deref() == Some(profile)
+                        }),
+                    };
+                    if let Some(position) = position {
+                        profile_setting.set_selected(position);
+                    }
+                }
+            })
+            .build();
+        window.add_action_entries([action_switch_profile]);
+
+        profile_setting.connect_selected_notify(move |row| {
+            let profile = match row.selected() {
+                0 => None,
+                _ => row
+                    .selected_item()
+                    .and_downcast::<gtk::StringObject>()
+                    .map(|item| item.string().to_string()),
+            };
+            if profile.as_deref() == filesystem_operations::current_profile() {
+                return;
+            }
+            if let Err(error) = filesystem_operations::save_last_profile(profile.as_deref()) {
+                error!("Could not save the selected profile: {}", error);
+                return;
+            }
+
+            let window = window.clone();
+            glib::spawn_future_local(async move {
+                let dialog = adw::AlertDialog::builder()
+                    .heading(gettext("Restart SongRec to switch profiles"))
+                    .body(
+                        gettext("The %s profile will be used the next time SongRec is started.")
+                            .replace("%s", &profile.unwrap_or_else(|| gettext("Default"))),
+                    )
+                    .close_response("later")
+                    .default_response("quit")
+                    .build();
+                dialog.add_responses(&[("later", &gettext("_Later")), ("quit", &gettext("_Quit"))]);
+
+                if dialog.choose_future(Some(&window)).await.as_str() == "quit" {
+                    if let Some(application) = window.application() {
+                        application.quit();
+                    }
+                }
+            });
+        });
+
+        new_profile_entry.connect_apply(move |entry| {
+            let name = entry.text().trim().to_string();
+            entry.remove_css_class("error");
+            match filesystem_operations::create_profile(&name) {
+                Ok(()) => {
+                    entry.set_text("");
+                    let profiles = filesystem_operations::list_profiles();
+                    let names: Vec<&str> =
+                        profiles.iter().map(|profile| profile.as_str()).collect();
+                    profile_model.splice(1, profile_model.n_items() - 1, &names);
+                    if let Some(position) = profiles.iter().position(|profile| profile == &name) {
+                        profile_setting.set_selected(position as u32 + 1);
+                    }
+                }
+                Err(error) => {
+                    entry.add_css_class("error");
+                    error!("Could not create profile {}: {}", name, error);
+                }
+            }
+        });
+    }
+
+    fn check_for_updates(window: adw::ApplicationWindow) {
+        glib::spawn_future_local(async move {
+            let release = match update_checker::check_for_update().await {
+                Ok(Some(release)) => release,
+                Ok(None) => {
+                    info!("SongRec is up to date");
+                    return;
+                }
+                Err(error) => {
+                    error!("Could not check for updates: {}", error);
+                    return;
+                }
+            };
+
+            let notes = gtk::Label::builder()
+                .label(&release.notes)
+                .wrap(true)
+                .selectable(true)
+                .xalign(0.0)
+                .build();
+            let scrolled_notes = gtk::ScrolledWindow::builder()
+                .child(&notes)
+                .hscrollbar_policy(gtk::PolicyType::Never)
+                .propagate_natural_height(true)
+                .max_content_height(300)
+                .build();
+
+            let dialog = adw::AlertDialog::builder()
+                .heading(gettext("SongRec %s is available").replace("%s", &release.version))
+                .extra_child(&scrolled_notes)
+                .close_response("later")
+                .default_response("download")
+                .build();
+            dialog.add_responses(&[
+                ("later", &gettext("_Later")),
+                ("download", &gettext("_Download")),
+            ]);
+            dialog.set_response_appearance("download", adw::ResponseAppearance::Suggested);
+
+            if dialog.choose_future(Some(&window)).await.as_str() == "download" {
+                if let Err(err) = gtk::UriLauncher::new(&release.url)
+                    .launch_future(Some(&window))
+                    .await
+                {
+                    error!("Could not launch URL {}: {:?}", release.url, err);
+                }
+            }
+        });
     }
 
     #[cfg(target_os = "linux")]
@@ -368,6 +1191,35 @@ impl App {
         });
     }
 
+    #[cfg(target_os = "macos")]
+    fn setup_menu_bar(
+        ctx_menu_bar: Rc<RefCell<Option<MenuBarItem>>>,
+        window: adw::ApplicationWindow,
+        gui_tx: async_channel::Sender<GUIMessage>,
+    ) {
+        if ctx_menu_bar.borrow().is_some() {
+            return;
+        }
+        match MenuBarItem::new(gui_tx) {
+            Ok(menu_bar) => {
+                *ctx_menu_bar.borrow_mut() = Some(menu_bar);
+                window.set_hide_on_close(true);
+            }
+            Err(err) => {
+                error!("{}: {:?}", gettext("Unable to show the menu bar item"), err);
+            }
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn unsetup_menu_bar(
+        ctx_menu_bar: Rc<RefCell<Option<MenuBarItem>>>,
+        window: adw::ApplicationWindow,
+    ) {
+        window.set_hide_on_close(false);
+        *ctx_menu_bar.borrow_mut() = None;
+    }
+
     fn setup_context_menus(&self) {
         ContextMenuUtil::connect_menu_key_actions(
             self.builder.clone(),
@@ -394,12 +1246,90 @@ impl App {
             self.preferences_interface.clone(),
         );
 
+        // Double-clicking a row shows the other songs of its artist
+        for view_id in ["history_view", "favorites_view"] {
+            let view: gtk::ColumnView = self.builder.object(view_id).unwrap();
+            let ctx_selected_item = self.ctx_selected_item.clone();
+            view.connect_activate(move |view, position| {
+                let Some(entry) = view
+                    .model()
+                    .and_then(|model| model.item(position))
+                    .and_downcast::<HistoryEntry>()
+                else {
+                    return;
+                };
+                *ctx_selected_item.borrow_mut() = Some(entry);
+                view.activate_action("history-menu.show-artist", None).ok();
+            });
+        }
+
+        let open_in_handlers = menu_open_in_handlers(
+            self.old_preferences.open_in_handlers.clone(),
+            self.old_preferences.prefer_bandcamp == Some(true),
+        );
+        for menu_id in ["open_in_menu", "open_in_menu_faved"] {
+            ContextMenuUtil::fill_open_in_menu(
+                &self.builder.object(menu_id).unwrap(),
+                &open_in_handlers,
+            );
+        }
+
         // See:
         // https://github.com/shartrec/kelpie-flight-planner/blob/a5575a5/src/window/airport_view.rs#L266 (right click)
         // https://github.com/shartrec/kelpie-flight-planner/blob/a5575a5/src/window/airport_view.rs#L349 (context menu key)
         // https://discourse.gnome.org/t/adding-a-context-menu-to-a-listview-using-gtk4-rs/19995/5
     }
 
+    /// The buttons playing the previews of the songs of the history list
+    fn setup_preview_callbacks(
+        builder_scope: &gtk::BuilderRustScope,
+        network_settings: NetworkSettings,
+    ) {
+        // Only one preview plays at a time
+        let preview_player: PreviewPlayer = Rc::new(RefCell::new(None));
+        let player = preview_player.clone();
+
+        builder_scope.add_callback("history_preview_setup_cb", move |values| {
+            let cell = values[1].get::<gtk::ColumnViewCell>().unwrap();
+            let button = gtk::Button::from_icon_name("media-playback-start-symbolic");
+            button.add_css_class("flat");
+            button.set_tooltip_text(Some(&gettext("Play a preview")));
+
+            let player = player.clone();
+            let network_settings = network_settings.clone();
+            let cell_ref = cell.downgrade();
+            button.connect_clicked(move |button| {
+                if let Some(entry) = cell_ref
+                    .upgrade()
+                    .and_then(|cell| cell.item())
+                    .and_downcast::<HistoryEntry>()
+                {
+                    Self::toggle_preview(&player, &network_settings, button, &entry);
+                }
+            });
+            cell.set_child(Some(&button));
+            None
+        });
+
+        builder_scope.add_callback("history_preview_bind_cb", move |values| {
+            let cell = values[1].get::<gtk::ColumnViewCell>().unwrap();
+            let button = cell.child().and_downcast::<gtk::Button>().unwrap();
+            let entry = cell.item().and_downcast::<HistoryEntry>().unwrap();
+
+            button.set_sensitive(Self::entry_preview_url(&entry).is_some());
+            let playing = preview_player
+                .borrow()
+                .as_ref()
+                .is_some_and(|(track_key, _, _)| entry.track_key().as_ref() == Some(track_key));
+            button.set_icon_name(if playing {
+                "media-playback-stop-symbolic"
+            } else {
+                "media-playback-start-symbolic"
+            });
+            None
+        });
+    }
+
     fn setup_callbacks(
         microphone_tx_shared: async_channel::Sender<MicrophoneMessage>,
         gui_tx_shared: async_channel::Sender<GUIMessage>,
@@ -434,13 +1364,19 @@ impl App {
         let gui_tx = gui_tx_shared.clone();
         let preferences = preferences_shared.clone();
 
-        builder_scope.add_callback("search_engine_url_changed", move |values| {
+        builder_scope.add_callback("recognition_venue_changed", move |values| {
             let entry_row = values[0].get::<adw::EntryRow>().unwrap();
 
             let lock = preferences.lock().unwrap();
-            if lock.preferences.website_search_url != Some(entry_row.text().to_string()) {
+            if lock
+                .preferences
+                .recognition_venue
+                .as_deref()
+                .unwrap_or_default()
+                != entry_row.text().as_str()
+            {
                 let mut new_preference = Preferences::new();
-                new_preference.website_search_url = Some(entry_row.text().to_string());
+                new_preference.recognition_venue = Some(entry_row.text().to_string());
                 gui_tx
                     .try_send(GUIMessage::UpdatePreference(new_preference))
                     .unwrap();
@@ -449,30 +1385,84 @@ impl App {
             None
         });
 
-        let microphone_tx = microphone_tx_shared.clone();
-        let builder = builder_shared.clone();
+        let gui_tx = gui_tx_shared.clone();
+        let preferences = preferences_shared.clone();
 
-        builder_scope.add_callback("history_cell_setup_cb", move |values| {
-            let popover_menu: gtk::PopoverMenu = builder.object("history_context_menu").unwrap();
+        builder_scope.add_callback("share_webhook_url_changed", move |values| {
+            let entry_row = values[0].get::<adw::EntryRow>().unwrap();
 
-            let builder = builder.clone();
-            let favorites = favorites.clone();
-            let ctx_selected_item = ctx_selected_item.clone();
+            let lock = preferences.lock().unwrap();
+            if lock.preferences.share_webhook_url != Some(entry_row.text().to_string()) {
+                let mut new_preference = Preferences::new();
+                new_preference.share_webhook_url = Some(entry_row.text().to_string());
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            }
 
-            let cell = values[1].get::<gtk::ColumnViewCell>().unwrap();
-            /* let column_view = values[0]
-            .get::<gtk::ColumnViewColumn>()
-            .unwrap()
-            .column_view()
-            .unwrap(); */
+            None
+        });
 
-            let label = gtk::Label::new(None);
-            label.set_xalign(0.0);
-            label.add_css_class("cell_label");
-            cell.set_child(Some(&label));
+        let gui_tx = gui_tx_shared.clone();
+        let preferences = preferences_shared.clone();
 
-            ContextMenuUtil::connect_menu_mouse_actions(
-                builder,
+        builder_scope.add_callback("share_template_changed", move |values| {
+            let entry_row = values[0].get::<adw::EntryRow>().unwrap();
+
+            let lock = preferences.lock().unwrap();
+            if lock.preferences.share_template != Some(entry_row.text().to_string()) {
+                let mut new_preference = Preferences::new();
+                new_preference.share_template = Some(entry_row.text().to_string());
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            }
+
+            None
+        });
+
+        let gui_tx = gui_tx_shared.clone();
+        let preferences = preferences_shared.clone();
+
+        builder_scope.add_callback("search_engine_url_changed", move |values| {
+            let entry_row = values[0].get::<adw::EntryRow>().unwrap();
+
+            let lock = preferences.lock().unwrap();
+            if lock.preferences.website_search_url != Some(entry_row.text().to_string()) {
+                let mut new_preference = Preferences::new();
+                new_preference.website_search_url = Some(entry_row.text().to_string());
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            }
+
+            None
+        });
+
+        let microphone_tx = microphone_tx_shared.clone();
+        let builder = builder_shared.clone();
+
+        builder_scope.add_callback("history_cell_setup_cb", move |values| {
+            let popover_menu: gtk::PopoverMenu = builder.object("history_context_menu").unwrap();
+
+            let builder = builder.clone();
+            let favorites = favorites.clone();
+            let ctx_selected_item = ctx_selected_item.clone();
+
+            let cell = values[1].get::<gtk::ColumnViewCell>().unwrap();
+            /* let column_view = values[0]
+            .get::<gtk::ColumnViewColumn>()
+            .unwrap()
+            .column_view()
+            .unwrap(); */
+
+            let label = gtk::Label::new(None);
+            label.set_xalign(0.0);
+            label.add_css_class("cell_label");
+            cell.set_child(Some(&label));
+
+            ContextMenuUtil::connect_menu_mouse_actions(
+                builder,
                 cell,
                 label,
                 popover_menu,
@@ -493,6 +1483,22 @@ impl App {
             let text = match prop_name.as_str() {
                 "song_name" => entry.song_name(),
                 "album" => entry.album().unwrap_or_default(),
+                "genre" => entry
+                    .genre()
+                    .map(|genre| genres::display_genre(&genre))
+                    .unwrap_or_default(),
+                "source" => entry.source().unwrap_or_default(),
+                // Only known when the metadata of the track is still cached
+                "spotify_uri" => entry
+                    .track_key()
+                    .and_then(|track_key| metadata_cache::get_track(&track_key))
+                    .map(|track| {
+                        CsvColumn::SpotifyUri.value(
+                            &entry.get_song_history_record(),
+                            Some(&json!({ "track": track })),
+                        )
+                    })
+                    .unwrap_or_default(),
                 "recognition_date" => entry.recognition_date(),
                 _ => unreachable!(),
             };
@@ -622,6 +1628,7 @@ impl App {
         let microphone_tx = microphone_tx_shared.clone();
         let gui_tx = gui_tx_shared.clone();
         let builder = builder_shared.clone();
+        let preferences = preferences_shared.clone();
 
         builder_scope.add_callback("input_device_switched", move |values| {
             let microphone_switch: adw::SwitchRow = builder.object("microphone_switch").unwrap();
@@ -668,6 +1675,13 @@ impl App {
                             device_name.to_owned(),
                         ))
                         .unwrap();
+                } else if preferences.lock().unwrap().preferences.warm_start == Some(true) {
+                    // Fill the pre-roll from the new device
+                    microphone_tx
+                        .try_send(MicrophoneMessage::MicrophoneWarmStart(Some(
+                            device_name.to_owned(),
+                        )))
+                        .unwrap();
                 }
             }
             None
@@ -686,6 +1700,30 @@ impl App {
             None
         });
 
+        let gui_tx = gui_tx_shared.clone();
+
+        builder_scope.add_callback("history_max_entries_changed", move |values| {
+            let adjustment = values[0].get::<gtk::Adjustment>().unwrap();
+            let mut new_preference = Preferences::new();
+            new_preference.history_max_entries = Some(adjustment.value() as u32);
+            gui_tx
+                .try_send(GUIMessage::UpdatePreference(new_preference))
+                .unwrap();
+            None
+        });
+
+        let gui_tx = gui_tx_shared.clone();
+
+        builder_scope.add_callback("history_max_months_changed", move |values| {
+            let adjustment = values[0].get::<gtk::Adjustment>().unwrap();
+            let mut new_preference = Preferences::new();
+            new_preference.history_max_months = Some(adjustment.value() as u32);
+            gui_tx
+                .try_send(GUIMessage::UpdatePreference(new_preference))
+                .unwrap();
+            None
+        });
+
         let builder = builder_shared;
 
         builder_scope.add_callback("about_dialog_closed", move |_values| {
@@ -701,6 +1739,7 @@ impl App {
         set_recording: bool,
         _enable_mpris_cli: bool,
         enable_pipewire_cli: bool,
+        kiosk: Kiosk,
     ) {
         // Setup communication using threads + smol-rs/async-channel::unbounded listener
 
@@ -715,6 +1754,7 @@ impl App {
         let preferences_interface = self.preferences_interface.clone();
         spawn_big_thread(move || {
             microphone_thread(
+                get_any_backend,
                 microphone_rx,
                 microphone_tx,
                 processing_tx,
@@ -734,18 +1774,34 @@ impl App {
         let http_rx = self.http_rx.clone();
         let gui_tx = self.gui_tx.clone();
         let microphone_tx = self.microphone_tx.clone();
-        glib::spawn_future_local(http_task(http_rx, gui_tx, microphone_tx));
+        let network_settings = self.network_settings.clone();
+
+        glib::spawn_future_local(http_task(
+            http_rx,
+            gui_tx,
+            microphone_tx,
+            ProviderSettings::default(),
+            network_settings,
+        ));
 
         let gui_rx = self.gui_rx.clone();
+        let microphone_tx = self.microphone_tx.clone();
         let preferences_interface_ptr = self.preferences_interface.clone();
-
-        let old_device_name = self.old_preferences.current_device_name.clone();
+        let processing_tx = self.processing_tx.clone();
 
         let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
         let systray_setting: adw::SwitchRow = self.builder.object("systray_setting").unwrap();
         let _mpris_setting: adw::SwitchRow = self.builder.object("mpris_setting").unwrap();
+        let _kdeconnect_setting: adw::SwitchRow =
+            self.builder.object("kdeconnect_setting").unwrap();
+        let update_check_setting: adw::SwitchRow =
+            self.builder.object("update_check_setting").unwrap();
+        let _encrypt_history_setting: adw::SwitchRow =
+            self.builder.object("encrypt_history_setting").unwrap();
         let adw_combo_row: adw::ComboRow = self.builder.object("audio_inputs").unwrap();
         let g_list_store: gio::ListStore = self.builder.object("audio_inputs_model").unwrap();
+        let missing_device_banner: adw::Banner =
+            self.builder.object("missing_device_banner").unwrap();
         let microphone_switch: adw::SwitchRow = self.builder.object("microphone_switch").unwrap();
         let recognize_file_row: adw::PreferencesRow =
             self.builder.object("recognize_file_row").unwrap();
@@ -756,23 +1812,76 @@ impl App {
             self.builder.object("results_section").unwrap();
         let no_network_message: gtk::Label = self.builder.object("no_network_message").unwrap();
         let rate_limited_message: gtk::Label = self.builder.object("rate_limited_message").unwrap();
+        let xruns_message: gtk::Label = self.builder.object("xruns_message").unwrap();
+        let hands_free_message: gtk::Label = self.builder.object("hands_free_message").unwrap();
+        let no_audio_message: gtk::Label = self.builder.object("no_audio_message").unwrap();
+        let no_match_rate_message: gtk::Label =
+            self.builder.object("no_match_rate_message").unwrap();
+        let login_required_message: gtk::Label =
+            self.builder.object("login_required_message").unwrap();
         let results_image: gtk::Image = self.builder.object("results_image").unwrap();
         let results_label: gtk::Label = self.builder.object("results_label").unwrap();
+        let heard_before_label: gtk::Label = self.builder.object("heard_before_label").unwrap();
+        let qr_code_row: adw::ActionRow = self.builder.object("qr_code_row").unwrap();
+        let qr_code_button: gtk::Button = self.builder.object("qr_code_button").unwrap();
         let loopback_switch: adw::SwitchRow = self.builder.object("loopback_switch").unwrap();
+        let computer_audio_row: adw::ActionRow = self.builder.object("computer_audio_row").unwrap();
 
         #[cfg(target_os = "linux")]
         systray_setting.set_visible(true);
 
+        #[cfg(target_os = "macos")]
+        {
+            systray_setting.set_title(&gettext("Show in the menu bar"));
+            systray_setting.set_subtitle(&gettext("Show the last recognized song in the menu bar, and keep listening when closing the window. Please note that you may have to increase the \"request interval\" setting when leaving SongRec running in the background to reduce your changes of getting rate-limited."));
+            systray_setting.set_visible(true);
+        }
+        #[cfg(target_os = "macos")]
+        let ctx_menu_bar = self.ctx_menu_bar.clone();
+
         #[cfg(all(target_os = "linux", feature = "mpris"))]
         _mpris_setting.set_visible(true);
 
+        #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+        _kdeconnect_setting.set_visible(true);
+
+        update_check_setting.set_visible(!is_managed_by_package_manager());
+
+        #[cfg(feature = "encryption")]
+        _encrypt_history_setting.set_visible(true);
+
         microphone_switch.set_active(set_recording);
 
+        let gui_tx = self.gui_tx.clone();
+        let combo_row = adw_combo_row.clone();
+
+        // Forget the missing device, and remember the one used instead
+        missing_device_banner.connect_button_clicked(move |banner| {
+            if let Some(device) = combo_row.selected_item() {
+                let device = device.downcast::<ListedDevice>().unwrap();
+                let mut new_preference = Preferences::new();
+                new_preference.current_device_name = Some(device.inner_name().to_string());
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            }
+            banner.set_revealed(false);
+        });
+
+        let gui_tx = self.gui_tx.clone();
         let song_history_interface = self.song_history_interface.clone();
         let _old_preferences = self.old_preferences.clone();
         let ctx_buffered_log = self.ctx_buffered_log.clone();
+        let ctx_last_song = self.ctx_last_song.clone();
         let application = application.clone();
 
+        spawn_sink_task(
+            preferences_interface_ptr.clone(),
+            self.sink_rx.clone(),
+            self.ctx_sink_states.clone(),
+        );
+        let sink_tx = self.sink_tx.clone();
+
         glib::spawn_future_local(async move {
             #[cfg(all(target_os = "linux", feature = "mpris"))]
             let mut mpris_obj = {
@@ -833,6 +1942,8 @@ impl App {
                                 "track_key": msg.track_key,
                                 "release_year": msg.release_year,
                                 "genre": msg.genre,
+                                "isrc": msg.isrc,
+                                "upc": msg.upc,
                                 "shazam_json": msg.shazam_json,
                             })
                         );
@@ -841,7 +1952,10 @@ impl App {
                     }
 
                     match gui_message {
-                        ErrorMessage(_) | NetworkStatus(_) | SongRecognized(_) => {
+                        ErrorMessage(_)
+                        | NetworkStatus(_)
+                        | NetworkRequiresLogin(_)
+                        | SongRecognized(_) => {
                             recognize_file_row.set_sensitive(true);
                             spinner_row.set_visible(false);
                         }
@@ -923,6 +2037,20 @@ impl App {
                             }
                             rate_limited_message.set_visible(is_rate_limited);
                         }
+                        NetworkRequiresLogin(requires_login) => {
+                            if requires_login && !login_required_message.is_visible() {
+                                Self::notify_network_error(
+                                    preferences_interface_ptr.clone(),
+                                    &login_required_message.label(),
+                                    &application,
+                                    false,
+                                );
+                            }
+                            login_required_message.set_visible(requires_login);
+                            if requires_login {
+                                no_network_message.set_visible(false);
+                            }
+                        }
                         NetworkStatus(network_is_reachable) => {
                             if !network_is_reachable && !no_network_message.is_visible() {
                                 Self::notify_network_error(
@@ -963,8 +2091,21 @@ impl App {
                                 }
                             }
                         }
-                        SongRecognized(message) => {
+                        SongRecognized(mut message) => {
+                            // The sinks searching catalogs get the names
+                            // that were recognized, the window, notifications
+                            // and history their transliteration
+                            let recognized = (*message).clone();
+                            transliteration::apply(&mut message);
+
                             results_section.set_visible(true);
+                            kiosk.show_song(&message);
+
+                            let link = song_link(&message.track_key, &message.shazam_json);
+                            qr_code_row.set_visible(link.is_some());
+                            qr_code_button.set_action_target_value(
+                                link.map(|link| link.to_variant()).as_ref(),
+                            );
 
                             // https://gtk-rs.org/gtk4-rs/git/docs/gdk4/struct.Texture.html#method.from_bytes
                             // https://docs.gtk.org/gdk4/ctor.Texture.new_from_bytes.html
@@ -979,6 +2120,11 @@ impl App {
                             if results_label.text().as_str() != song_name {
                                 results_label.set_label(&song_name);
 
+                                #[cfg(target_os = "macos")]
+                                if let Some(ref menu_bar) = *ctx_menu_bar.borrow() {
+                                    menu_bar.show_song(&song_name);
+                                }
+
                                 let notification =
                                     gio::Notification::new(&gettext("Song recognized"));
                                 notification.set_body(Some(&song_name));
@@ -1028,35 +2174,90 @@ impl App {
                                         .send_notification(Some("recognized-song"), &notification);
                                 }
 
-                                let new_entry = SongHistoryRecord {
+                                sink_tx
+                                    .try_send(SinkTaskMessage::SongRecognized(recognized))
+                                    .unwrap();
+
+                                // Before this recognition is added
+                                match song_history_interface.borrow().song_stats(&song_name) {
+                                    Some(stats) => {
+                                        heard_before_label.set_label(&stats.heard_before_text())
+                                    }
+                                    None => heard_before_label
+                                        .set_label(&gettext("Heard for the first time")),
+                                }
+
+                                let mut new_entry = SongHistoryRecord {
                                     song_name,
                                     album: Some(message.album_name.unwrap_or_default()),
                                     track_key: Some(message.track_key),
                                     release_year: Some(message.release_year.unwrap_or_default()),
                                     genre: Some(message.genre.unwrap_or_default()),
-                                    recognition_date: Local::now().format("%c").to_string(),
+                                    recognition_date: clock::history_date_now(),
+                                    source: message
+                                        .source
+                                        .as_ref()
+                                        .map(|source| source.to_string()),
                                 };
 
-                                if preferences_interface_ptr
-                                    .lock()
-                                    .unwrap()
-                                    .preferences
-                                    .no_duplicates
-                                    == Some(true)
-                                {
+                                // A clip that was retried is dated from its capture
+                                let retried_clip =
+                                    message.source.as_ref().and_then(unknown_clips::find_clip);
+                                if let Some(ref clip) = retried_clip {
+                                    new_entry.recognition_date = clip.recognition_date.clone();
+                                    new_entry.source = clip.source.clone();
+                                }
+
+                                *ctx_last_song.borrow_mut() =
+                                    Some((new_entry.clone(), message.shazam_json));
+                                if !is_incognito() && read_only_history().is_none() {
+                                    if preferences_interface_ptr
+                                        .lock()
+                                        .unwrap()
+                                        .preferences
+                                        .no_duplicates
+                                        == Some(true)
+                                    {
+                                        song_history_interface
+                                            .borrow_mut()
+                                            .remove(new_entry.clone());
+                                    }
+                                    // A retried clip may have been heard elsewhere
+                                    if retried_clip.is_none() {
+                                        let venue = preferences_interface_ptr
+                                            .lock()
+                                            .unwrap()
+                                            .preferences
+                                            .recognition_venue
+                                            .clone();
+                                        recognition_locations::record_location(
+                                            &new_entry,
+                                            venue.as_deref(),
+                                        );
+                                    }
                                     song_history_interface
                                         .borrow_mut()
-                                        .remove(new_entry.clone());
+                                        .add_row_and_save(new_entry);
+                                    if let Some(clip) = retried_clip {
+                                        clip.delete();
+                                    }
                                 }
-                                song_history_interface
-                                    .borrow_mut()
-                                    .add_row_and_save(new_entry);
                             }
                         }
                         DevicesList(devices) => {
                             let mut initial_device_index: u32 = 0;
                             let mut initial_device: Option<ListedDevice> = None;
                             let mut found_monitor_device = false;
+                            let mut found_old_device = false;
+
+                            // The device of the current profile chosen the
+                            // last time, or since the last refresh
+                            let old_device_name = preferences_interface_ptr
+                                .lock()
+                                .unwrap()
+                                .preferences
+                                .current_device_name
+                                .clone();
 
                             // Fill in the list of available devices, and
                             // set back the old device if it was recorded
@@ -1072,11 +2273,10 @@ impl App {
                                 );
                                 g_list_store.append(&listed_device);
 
-                                if (old_device_name == Some(device.inner_name.to_string()))
-                                    || (old_device_name.is_none()
-                                        && device.is_monitor
-                                        && !found_monitor_device)
-                                {
+                                // Without a remembered device, the first one
+                                // is the automatic choice
+                                if old_device_name == Some(device.inner_name.to_string()) {
+                                    found_old_device = true;
                                     initial_device_index = current_index as u32;
                                     initial_device = Some(listed_device);
                                 } else if current_index == 0 {
@@ -1092,6 +2292,7 @@ impl App {
                                 // device here is a: ListedDevice
                                 adw_combo_row.set_selected(initial_device_index);
                                 loopback_switch.set_visible(found_monitor_device);
+                                computer_audio_row.set_visible(found_monitor_device);
 
                                 debug!(
                                     "Initially selected audio input device: {:?} / {:?}",
@@ -1105,6 +2306,45 @@ impl App {
                                 );
 
                                 // Will trigger the "input_device_switched" callback
+
+                                let warm_start = preferences_interface_ptr
+                                    .lock()
+                                    .unwrap()
+                                    .preferences
+                                    .warm_start
+                                    == Some(true);
+                                if warm_start
+                                    && !microphone_switch.is_active()
+                                    && !loopback_switch.is_active()
+                                {
+                                    microphone_tx
+                                        .try_send(MicrophoneMessage::MicrophoneWarmStart(Some(
+                                            device.inner_name().to_owned(),
+                                        )))
+                                        .unwrap();
+                                }
+                            }
+
+                            match old_device_name {
+                                Some(old_device_name)
+                                    if !found_old_device
+                                        && !is_virtual_device(&old_device_name) =>
+                                {
+                                    missing_device_banner.set_title(
+                                        &gettext("The audio device %s is missing, another one is used until it is plugged again")
+                                            .replace("%s", &old_device_name),
+                                    );
+                                    missing_device_banner.set_revealed(true);
+
+                                    // Still remember the missing device, rather
+                                    // than the one just selected instead
+                                    let mut new_preference = Preferences::new();
+                                    new_preference.current_device_name = Some(old_device_name);
+                                    gui_tx
+                                        .try_send(GUIMessage::UpdatePreference(new_preference))
+                                        .unwrap();
+                                }
+                                _ => missing_device_banner.set_revealed(false),
                             }
                         }
                         MicrophoneRecording => {
@@ -1112,6 +2352,58 @@ impl App {
                                 microphone_switch.is_active() || loopback_switch.is_active(),
                             );
                             volume_gauge.set_fraction(0.0);
+                            // A new stream, possibly with a larger buffer
+                            xruns_message.set_visible(false);
+                            hands_free_message.set_visible(false);
+                            no_audio_message.set_visible(false);
+                        }
+
+                        HandsFreeCapture => {
+                            hands_free_message.set_visible(true);
+                        }
+
+                        NoAudioDetected(no_audio_detected) => {
+                            no_audio_message.set_visible(no_audio_detected);
+                        }
+
+                        HighNoMatchRate(device_name, high_rate) => {
+                            let display_name = (0..g_list_store.n_items())
+                                .filter_map(|position| {
+                                    g_list_store.item(position).and_downcast::<ListedDevice>()
+                                })
+                                .find(|device| device.inner_name() == device_name)
+                                .map(|device| device.display_name())
+                                .unwrap_or(device_name);
+                            no_match_rate_message.set_label(
+                                &gettext("Most of the songs captured from %s aren't recognized: check the volume of the input and the position of the microphone, or try another device")
+                                    .replace("%s", &display_name),
+                            );
+                            no_match_rate_message.set_visible(high_rate);
+                        }
+
+                        SelectMonitorDevice(monitor_name) => {
+                            let position = (0..g_list_store.n_items()).find(|position| {
+                                g_list_store
+                                    .item(*position)
+                                    .and_downcast::<ListedDevice>()
+                                    .is_some_and(|device| device.inner_name() == monitor_name)
+                            });
+                            match position {
+                                Some(position) => {
+                                    // Will restart the capture with this device
+                                    // if the speakers were already recorded
+                                    adw_combo_row.set_selected(position);
+                                    loopback_switch.set_active(true);
+                                }
+                                None => {
+                                    error!("The monitor {} is not listed", monitor_name);
+                                }
+                            }
+                        }
+
+                        CaptureXruns(recent_xruns) => {
+                            // A lone xrun is harmless
+                            xruns_message.set_visible(recent_xruns > 1);
                         }
 
                         MicrophoneVolumePercent(percent) => {
@@ -1121,7 +2413,10 @@ impl App {
 
                         WipeSongHistory => {
                             let dialog = adw::AlertDialog::builder()
-                                .body(gettext("Are you sure you want to wipe history?"))
+                                .body(
+                                    gettext("Are you sure you want to wipe history? It can be restored from \"Recently deleted\" for %d days.")
+                                        .replace("%d", &RECYCLE_BIN_DAYS.to_string()),
+                                )
                                 .default_response("yes")
                                 .close_response("no")
                                 .build();
@@ -1137,16 +2432,40 @@ impl App {
                                 None::<&gio::Cancellable>,
                                 move |result| {
                                     if result == "yes" {
-                                        song_history_interface.borrow_mut().wipe_and_save();
+                                        song_history_interface.borrow_mut().delete_all();
                                     }
                                 },
                             );
                         }
 
+                        ShowRecentlyDeleted => {
+                            Self::show_recently_deleted(&window, song_history_interface.clone());
+                        }
+
+                        ShowUnknownClips => {
+                            Self::show_unknown_clips(
+                                &window,
+                                song_history_interface.clone(),
+                                processing_tx.clone(),
+                            );
+                        }
+
                         ShowWindow => {
                             window.present();
                         }
 
+                        ShowPreferences => {
+                            window.present();
+                            let _ =
+                                WidgetExt::activate_action(&window, "win.show-preferences", None);
+                        }
+
+                        RecognizeNow => {
+                            if !microphone_switch.is_active() && !loopback_switch.is_active() {
+                                microphone_switch.set_active(true);
+                            }
+                        }
+
                         QuitApplication => {
                             application.quit();
                         }
@@ -1162,7 +2481,238 @@ impl App {
         });
     }
 
-    fn setup_actions(&self, application: &adw::Application, _enable_mpris_cli: bool) {
+    /// List the entries of the recycle bin of the history, each with a button
+    /// restoring it
+    fn show_recently_deleted(
+        window: &adw::ApplicationWindow,
+        song_history_interface: Rc<RefCell<RecognitionHistoryInterface>>,
+    ) {
+        let deleted = song_history_interface.borrow().recently_deleted();
+
+        let list_box = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(["boxed-list"])
+            .build();
+        for record in deleted.iter().cloned() {
+            let row = adw::ActionRow::builder()
+                .title(glib::markup_escape_text(&record.song_name))
+                .subtitle(
+                    gettext("Recognized on %s, deleted on %s")
+                        .replacen("%s", &record.recognition_date, 1)
+                        .replacen("%s", &record.deletion_date, 1),
+                )
+                .build();
+            let restore_button = gtk::Button::builder()
+                .label(gettext("Restore"))
+                .valign(gtk::Align::Center)
+                .build();
+            let song_history_interface = song_history_interface.clone();
+            let list_box_ref = list_box.clone();
+            let row_ref = row.clone();
+            restore_button.connect_clicked(move |_| {
+                song_history_interface.borrow_mut().restore(&record);
+                list_box_ref.remove(&row_ref);
+            });
+            row.add_suffix(&restore_button);
+            list_box.append(&row);
+        }
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(gettext("Recently deleted"))
+            .body(if deleted.is_empty() {
+                gettext("No song was deleted during the last %d days")
+                    .replace("%d", &RECYCLE_BIN_DAYS.to_string())
+            } else {
+                gettext("Deleted songs are kept for %d days")
+                    .replace("%d", &RECYCLE_BIN_DAYS.to_string())
+            })
+            .close_response("close")
+            .build();
+        if !deleted.is_empty() {
+            dialog.set_extra_child(Some(
+                &gtk::ScrolledWindow::builder()
+                    .hscrollbar_policy(gtk::PolicyType::Never)
+                    .min_content_height(300)
+                    .child(&list_box)
+                    .build(),
+            ));
+            dialog.add_response("empty", &gettext("_Empty"));
+            dialog.set_response_appearance("empty", adw::ResponseAppearance::Destructive);
+        }
+        dialog.add_response("close", &gettext("_Close"));
+
+        dialog.choose(Some(window), None::<&gio::Cancellable>, move |result| {
+            if result == "empty" {
+                song_history_interface.borrow_mut().empty_recycle_bin();
+            }
+        });
+    }
+
+    fn show_unknown_clips(
+        window: &adw::ApplicationWindow,
+        song_history_interface: Rc<RefCell<RecognitionHistoryInterface>>,
+        processing_tx: async_channel::Sender<ProcessingMessage>,
+    ) {
+        let clips = unknown_clips::list_clips();
+        // The clip being played, only one at a time
+        let clip_player: Rc<RefCell<Option<gtk::MediaFile>>> = Rc::new(RefCell::new(None));
+
+        let list_box = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(["boxed-list"])
+            .build();
+        for clip in clips.iter().cloned() {
+            let row = adw::ActionRow::builder()
+                .title(glib::markup_escape_text(&clip.recognition_date))
+                .subtitle(glib::markup_escape_text(
+                    clip.source.as_deref().unwrap_or_default(),
+                ))
+                .build();
+            row.add_prefix(&Self::waveform_thumbnail(&clip));
+
+            let play_button = gtk::Button::builder()
+                .icon_name("media-playback-start-symbolic")
+                .tooltip_text(gettext("Play"))
+                .valign(gtk::Align::Center)
+                .build();
+            let clip_player_ref = clip_player.clone();
+            let audio_path = clip.audio_path.clone();
+            play_button.connect_clicked(move |_| {
+                if let Some(media) = clip_player_ref.borrow_mut().take() {
+                    media.set_playing(false);
+                }
+                let media = gtk::MediaFile::new();
+                *clip_player_ref.borrow_mut() = Some(media.clone());
+                let audio_path = audio_path.clone();
+                let clip_player = clip_player_ref.clone();
+                glib::spawn_future_local(async move {
+                    // Captures are much quieter than the previews
+                    media.set_volume(loudness::file_playback_volume(audio_path.clone()).await);
+                    if clip_player.borrow().as_ref() == Some(&media) {
+                        media.set_file(Some(&gio::File::for_path(&audio_path)));
+                        media.play();
+                    }
+                });
+            });
+            row.add_suffix(&play_button);
+
+            let retry_button = gtk::Button::builder()
+                .icon_name("view-refresh-symbolic")
+                .tooltip_text(gettext("Retry"))
+                .valign(gtk::Align::Center)
+                .build();
+            let processing_tx = processing_tx.clone();
+            let audio_path = clip.audio_path.display().to_string();
+            retry_button.connect_clicked(move |_| {
+                processing_tx
+                    .try_send(ProcessingMessage::ProcessAudioFile(audio_path.clone()))
+                    .unwrap();
+            });
+            row.add_suffix(&retry_button);
+
+            let name_button = gtk::Button::builder()
+                .icon_name("document-edit-symbolic")
+                .tooltip_text(gettext("Enter the song..."))
+                .valign(gtk::Align::Center)
+                .build();
+            let song_history_interface = song_history_interface.clone();
+            let list_box_ref = list_box.clone();
+            let row_ref = row.clone();
+            name_button.connect_clicked(move |button| {
+                let song_history_interface = song_history_interface.clone();
+                let clip_ref = clip.clone();
+                let list_box_ref = list_box_ref.clone();
+                let row_ref = row_ref.clone();
+                song_dialog::ask_song(
+                    button,
+                    &gettext("Enter the song"),
+                    &gettext("The song captured on %s will be added to the history")
+                        .replace("%s", &clip.recognition_date),
+                    clip.to_record(),
+                    false,
+                    move |record| {
+                        song_history_interface.borrow_mut().insert_and_save(record);
+                        clip_ref.delete();
+                        list_box_ref.remove(&row_ref);
+                    },
+                );
+            });
+            row.add_suffix(&name_button);
+            list_box.append(&row);
+        }
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(gettext("Unrecognized clips"))
+            .body(if clips.is_empty() {
+                gettext("No unrecognized clip was kept. Turn on \"Keep unrecognized clips\" in the preferences to save them.")
+            } else {
+                gettext("Clips that are recognized once retried are added to the history, at the date they were captured")
+            })
+            .close_response("close")
+            .build();
+        if !clips.is_empty() {
+            dialog.set_extra_child(Some(
+                &gtk::ScrolledWindow::builder()
+                    .hscrollbar_policy(gtk::PolicyType::Never)
+                    .min_content_height(300)
+                    .child(&list_box)
+                    .build(),
+            ));
+            dialog.add_response("delete", &gettext("_Delete all"));
+            dialog.set_response_appearance("delete", adw::ResponseAppearance::Destructive);
+            dialog.add_response("retry", &gettext("_Retry all"));
+        }
+        dialog.add_response("close", &gettext("_Close"));
+
+        dialog.choose(Some(window), None::<&gio::Cancellable>, move |result| {
+            if let Some(media) = clip_player.borrow_mut().take() {
+                media.set_playing(false);
+            }
+            for clip in unknown_clips::list_clips() {
+                match result.as_str() {
+                    "retry" => processing_tx
+                        .try_send(ProcessingMessage::ProcessAudioFile(
+                            clip.audio_path.display().to_string(),
+                        ))
+                        .unwrap(),
+                    "delete" => clip.delete(),
+                    _ => break,
+                }
+            }
+        });
+    }
+
+    fn waveform_thumbnail(clip: &UnknownClip) -> gtk::DrawingArea {
+        let peaks = clip.waveform(60);
+        let drawing_area = gtk::DrawingArea::builder()
+            .content_width(120)
+            .content_height(32)
+            .valign(gtk::Align::Center)
+            .build();
+        drawing_area.set_draw_func(move |area, context, width, height| {
+            let color = area.color();
+            context.set_source_rgba(
+                color.red() as f64,
+                color.green() as f64,
+                color.blue() as f64,
+                0.6,
+            );
+            let bar_width = width as f64 / peaks.len().max(1) as f64;
+            for (index, peak) in peaks.iter().enumerate() {
+                let bar_height = (*peak as f64 * height as f64).max(1.0);
+                context.rectangle(
+                    index as f64 * bar_width,
+                    (height as f64 - bar_height) / 2.0,
+                    (bar_width - 1.0).max(1.0),
+                    bar_height,
+                );
+            }
+            context.fill().ok();
+        });
+        drawing_area
+    }
+
+    fn setup_actions(&self, application: &adw::Application, _enable_mpris_cli: bool, kiosk: Kiosk) {
         let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
         let file_picker: gtk::FileDialog = self.builder.object("file_picker").unwrap();
         let shortcuts_dialog: gtk::ShortcutsWindow =
@@ -1175,6 +2725,14 @@ impl App {
         let recognize_file_row: adw::PreferencesRow =
             self.builder.object("recognize_file_row").unwrap();
         let spinner_row: adw::PreferencesRow = self.builder.object("spinner_row").unwrap();
+        let title_bar: adw::HeaderBar = self.builder.object("title_bar").unwrap();
+
+        task_manager::setup_task_manager(&title_bar);
+        source_switch::setup_source_switch(
+            &title_bar,
+            &self.builder.object("microphone_switch").unwrap(),
+            &self.builder.object("loopback_switch").unwrap(),
+        );
 
         let ctx_buffered_log = self.ctx_buffered_log.clone();
         let ctx_logger_source_id = self.ctx_logger_source_id.clone();
@@ -1267,6 +2825,29 @@ impl App {
             })
             .build();
 
+        let http_tx = self.http_tx.clone();
+        let gui_tx = self.gui_tx.clone();
+
+        let action_recognize_folder = gio::ActionEntry::builder("recognize-folder")
+            .activate(move |window: &adw::ApplicationWindow, _action, _obj| {
+                let http_tx = http_tx.clone();
+                let gui_tx = gui_tx.clone();
+
+                gtk::FileDialog::builder()
+                    .title(gettext("Select a folder to recognize"))
+                    .build()
+                    .select_folder(Some(window), None::<&gio::Cancellable>, move |folder| {
+                        let Some(directory) = folder.ok().and_then(|folder| folder.path()) else {
+                            return;
+                        };
+                        info!("Picked folder: {:?}", directory);
+                        spawn_big_thread(move || {
+                            recognize_directory(&directory, http_tx, gui_tx);
+                        });
+                    });
+            })
+            .build();
+
         let preferences_interface_ptr = self.preferences_interface.clone();
 
         let action_search_youtube = gio::ActionEntry::builder("search-youtube")
@@ -1302,65 +2883,123 @@ impl App {
             })
             .build();
 
-        let action_export_to_csv = gio::ActionEntry::builder("export-to-csv")
+        let action_export_history_as = gio::ActionEntry::builder("export-history-as")
             .activate(move |window: &adw::ApplicationWindow, _action, _obj| {
-                #[cfg(not(windows))]
-                {
-                    let window = window.clone();
-
-                    glib::spawn_future_local(async move {
-                        let launch_path = obtain_recognition_history_csv_path().unwrap();
-                        info!("Launching file: {}", launch_path.display());
-                        let launch_file = gio::File::for_path(&launch_path);
-                        if let Err(err) = gtk::FileLauncher::new(Some(&launch_file))
-                            .launch_future(Some(&window))
-                            .await
-                        {
-                            error!("Could not launch file {}: {:?}", launch_path.display(), err);
-                        }
-                    });
-                }
+                show_export_dialog(window, false);
+            })
+            .build();
 
-                #[cfg(windows)]
-                std::process::Command::new("cmd")
-                    .args(&[
-                        "/c",
-                        &format!("start {}", obtain_recognition_history_csv_path().unwrap()),
-                    ])
-                    .creation_flags(0x00000008) // Set "CREATE_NO_WINDOW" on Windows
-                    .output()
-                    .ok();
+        let song_history_interface = self.song_history_interface.clone();
+        let action_show_charts = gio::ActionEntry::builder("show-charts")
+            .activate(move |window: &adw::ApplicationWindow, _action, _obj| {
+                show_charts(window, song_history_interface.borrow().records());
             })
             .build();
 
-        let action_export_favorites_to_csv = gio::ActionEntry::builder("export-favorites-to-csv")
+        let action_export_favorites = gio::ActionEntry::builder("export-favorites")
             .activate(move |window: &adw::ApplicationWindow, _action, _obj| {
-                #[cfg(not(windows))]
-                {
-                    let window = window.clone();
-
-                    glib::spawn_future_local(async move {
-                        let launch_path = obtain_favorites_csv_path().unwrap();
-                        info!("Launching file: {}", launch_path.display());
-                        let launch_file = gio::File::for_path(&launch_path);
-                        if let Err(err) = gtk::FileLauncher::new(Some(&launch_file))
-                            .launch_future(Some(&window))
-                            .await
-                        {
-                            error!("Could not launch file {}: {:?}", launch_path.display(), err);
-                        }
-                    });
+                show_export_dialog(window, true);
+            })
+            .build();
+
+        let action_show_qr_code = gio::ActionEntry::builder("show-qr-code")
+            .parameter_type(Some(&String::static_variant_type()))
+            .activate(move |window: &adw::ApplicationWindow, _action, parameter| {
+                let Some(link) = parameter.and_then(|parameter| parameter.str()) else {
+                    return;
+                };
+                let Some(texture) = qr_code_texture(link) else {
+                    return;
+                };
+
+                let dialog = adw::AlertDialog::builder()
+                    .heading(gettext("Scan to open the song"))
+                    .body(link)
+                    .extra_child(&gtk::Picture::for_paintable(&texture))
+                    .close_response("close")
+                    .build();
+                dialog.add_response("close", &gettext("_Close"));
+                dialog.present(Some(window));
+            })
+            .build();
+
+        let preferences_interface_ptr = self.preferences_interface.clone();
+        let ctx_last_song = self.ctx_last_song.clone();
+
+        let action_copy_share_text = gio::ActionEntry::builder("copy-share-text")
+            .activate(move |_window: &adw::ApplicationWindow, _action, _obj| {
+                if let Some((ref record, ref shazam_json)) = *ctx_last_song.borrow() {
+                    let preferences = preferences_interface_ptr
+                        .lock()
+                        .unwrap()
+                        .preferences
+                        .clone();
+                    let text = render_share_text(
+                        preferences
+                            .share_template
+                            .as_deref()
+                            .unwrap_or(DEFAULT_SHARE_TEMPLATE),
+                        record,
+                        Some(shazam_json),
+                    );
+                    if let Some(display) = gdk::Display::default() {
+                        display.clipboard().set(&text);
+                    }
+                    if let Some(url) = preferences.share_webhook_url.filter(|url| !url.is_empty()) {
+                        glib::spawn_future_local(async move {
+                            let body = serde_json::json!({ "text": text });
+                            if let Err(error) =
+                                post_to_webhook(&webhook_session(), &url, &body).await
+                            {
+                                error!("Could not send the share text to the webhook: {}", error);
+                            }
+                        });
+                    }
                 }
+            })
+            .build();
+
+        let action_incognito_setting = gio::ActionEntry::builder("incognito-setting")
+            .state(is_incognito().to_variant())
+            .activate(move |_, action, _| {
+                let state = action.state().unwrap();
+                let action_state: bool = state.get().unwrap();
+                let new_state = !action_state; // toggle
+                action.set_state(&new_state.to_variant());
+                set_incognito(new_state);
+            })
+            .build();
+
+        let application_ptr = application.clone();
+        let action_kiosk_mode = gio::ActionEntry::builder("kiosk-mode")
+            .activate(move |_window: &adw::ApplicationWindow, _action, _obj| {
+                kiosk.enter(&application_ptr);
+            })
+            .build();
+
+        let song_history_interface = self.song_history_interface.clone();
 
-                #[cfg(windows)]
-                std::process::Command::new("cmd")
-                    .args(&[
-                        "/c",
-                        &format!("start {}", obtain_favorites_csv_path().unwrap()),
-                    ])
-                    .creation_flags(0x00000008) // Set "CREATE_NO_WINDOW" on Windows
-                    .output()
-                    .ok();
+        let action_add_song_to_history = gio::ActionEntry::builder("add-song-to-history")
+            .activate(move |window: &adw::ApplicationWindow, _, _| {
+                let song_history_interface = song_history_interface.clone();
+                song_dialog::ask_song(
+                    window,
+                    &gettext("Add a song"),
+                    &gettext("Add a song that SongRec didn't recognize to the history"),
+                    SongHistoryRecord {
+                        song_name: String::new(),
+                        album: None,
+                        track_key: None,
+                        release_year: None,
+                        genre: None,
+                        recognition_date: clock::history_date_now(),
+                        source: None,
+                    },
+                    true,
+                    move |record| {
+                        song_history_interface.borrow_mut().insert_and_save(record);
+                    },
+                );
             })
             .build();
 
@@ -1372,6 +3011,22 @@ impl App {
             })
             .build();
 
+        let gui_tx = self.gui_tx.clone();
+
+        let action_show_recently_deleted = gio::ActionEntry::builder("show-recently-deleted")
+            .activate(move |_window, _action, _obj| {
+                gui_tx.try_send(GUIMessage::ShowRecentlyDeleted).unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+
+        let action_show_unknown_clips = gio::ActionEntry::builder("show-unknown-clips")
+            .activate(move |_window, _action, _obj| {
+                gui_tx.try_send(GUIMessage::ShowUnknownClips).unwrap();
+            })
+            .build();
+
         #[cfg(all(target_os = "linux", feature = "mpris"))]
         let gui_tx = self.gui_tx.clone();
 
@@ -1418,8 +3073,10 @@ impl App {
         let gui_tx = self.gui_tx.clone();
         #[cfg(target_os = "linux")]
         let ctx_systray_handle = self.ctx_systray_handle.clone();
+        #[cfg(target_os = "macos")]
+        let ctx_menu_bar = self.ctx_menu_bar.clone();
 
-        #[cfg(target_os = "linux")]
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
         let action_systray_setting = gio::ActionEntry::builder("systray-setting")
             .state(self.old_preferences.enable_systray.unwrap().to_variant())
             .activate(
@@ -1429,12 +3086,21 @@ impl App {
                     let new_state = !action_state; // toggle
                     action.set_state(&new_state.to_variant());
 
-                    let ctx_systray_handle = ctx_systray_handle.clone();
+                    #[cfg(target_os = "linux")]
+                    {
+                        let ctx_systray_handle = ctx_systray_handle.clone();
 
+                        if new_state {
+                            Self::setup_systray(ctx_systray_handle, window.clone(), gui_tx.clone());
+                        } else {
+                            Self::unsetup_systray(ctx_systray_handle, window.clone());
+                        }
+                    }
+                    #[cfg(target_os = "macos")]
                     if new_state {
-                        Self::setup_systray(ctx_systray_handle, window.clone(), gui_tx.clone());
+                        Self::setup_menu_bar(ctx_menu_bar.clone(), window.clone(), gui_tx.clone());
                     } else {
-                        Self::unsetup_systray(ctx_systray_handle, window.clone());
+                        Self::unsetup_menu_bar(ctx_menu_bar.clone(), window.clone());
                     }
 
                     let mut new_preference: Preferences = Preferences::new();
@@ -1448,6 +3114,163 @@ impl App {
 
         let gui_tx = self.gui_tx.clone();
 
+        #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+        let action_kdeconnect_setting = gio::ActionEntry::builder("kdeconnect-setting")
+            .state(
+                self.old_preferences
+                    .enable_kdeconnect
+                    .unwrap_or(false)
+                    .to_variant(),
+            )
+            .activate(move |_, action, _| {
+                let state = action.state().unwrap();
+                let action_state: bool = state.get().unwrap();
+                let new_state = !action_state; // toggle
+                action.set_state(&new_state.to_variant());
+
+                let mut new_preference: Preferences = Preferences::new();
+                new_preference.enable_kdeconnect = Some(new_state);
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+
+        let action_announce_setting = gio::ActionEntry::builder("announce-setting")
+            .state((self.old_preferences.announce_songs == Some(true)).to_variant())
+            .activate(move |_, action, _| {
+                let state = action.state().unwrap();
+                let action_state: bool = state.get().unwrap();
+                let new_state = !action_state; // toggle
+                action.set_state(&new_state.to_variant());
+
+                let mut new_preference: Preferences = Preferences::new();
+                new_preference.announce_songs = Some(new_state);
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+
+        let action_update_check_setting = gio::ActionEntry::builder("update-check-setting")
+            .state(
+                self.old_preferences
+                    .check_for_updates
+                    .unwrap_or(false)
+                    .to_variant(),
+            )
+            .activate(
+                move |window: &adw::ApplicationWindow, action: &gio::SimpleAction, _| {
+                    let state = action.state().unwrap();
+                    let action_state: bool = state.get().unwrap();
+                    let new_state = !action_state; // toggle
+                    action.set_state(&new_state.to_variant());
+
+                    if new_state {
+                        Self::check_for_updates(window.clone());
+                    }
+
+                    let mut new_preference: Preferences = Preferences::new();
+                    new_preference.check_for_updates = Some(new_state);
+                    gui_tx
+                        .try_send(GUIMessage::UpdatePreference(new_preference))
+                        .unwrap();
+                },
+            )
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+        let song_history_interface = self.song_history_interface.clone();
+        let favorites_interface = self.favorites_interface.clone();
+
+        let action_encrypt_history_setting = gio::ActionEntry::builder("encrypt-history-setting")
+            .state(is_history_encryption_enabled().to_variant())
+            .activate(move |_, action, _| {
+                let state = action.state().unwrap();
+                let action_state: bool = state.get().unwrap();
+                let new_state = !action_state; // toggle
+                if new_state {
+                    // Keep the history readable when the keyring is locked or missing
+                    if let Err(error) = check_history_key() {
+                        gui_tx
+                            .try_send(GUIMessage::ErrorMessage(format!(
+                                "{} {}",
+                                gettext("Could not turn on the encryption of the history:"),
+                                error
+                            )))
+                            .unwrap();
+                        return;
+                    }
+                }
+                action.set_state(&new_state.to_variant());
+
+                set_history_encryption(new_state);
+                song_history_interface.borrow_mut().rewrite_files();
+                favorites_interface.borrow_mut().save();
+
+                let mut new_preference: Preferences = Preferences::new();
+                new_preference.encrypt_history = Some(new_state);
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+
+        let action_keep_unrecognized_clips_setting =
+            gio::ActionEntry::builder("keep-unrecognized-clips-setting")
+                .state((self.old_preferences.keep_unrecognized_clips == Some(true)).to_variant())
+                .activate(move |_, action, _| {
+                    let state = action.state().unwrap();
+                    let action_state: bool = state.get().unwrap();
+                    let new_state = !action_state; // toggle
+                    action.set_state(&new_state.to_variant());
+                    set_keep_unrecognized_clips(new_state);
+
+                    let mut new_preference: Preferences = Preferences::new();
+                    new_preference.keep_unrecognized_clips = Some(new_state);
+                    gui_tx
+                        .try_send(GUIMessage::UpdatePreference(new_preference))
+                        .unwrap();
+                })
+                .build();
+
+        let gui_tx = self.gui_tx.clone();
+        let open_in_handlers = self.old_preferences.open_in_handlers.clone();
+        let open_in_menus: Vec<gio::Menu> = ["open_in_menu", "open_in_menu_faved"]
+            .iter()
+            .map(|menu_id| self.builder.object(menu_id).unwrap())
+            .collect();
+
+        let action_prefer_bandcamp_setting = gio::ActionEntry::builder("prefer-bandcamp-setting")
+            .state((self.old_preferences.prefer_bandcamp == Some(true)).to_variant())
+            .activate(move |_, action, _| {
+                let state = action.state().unwrap();
+                let action_state: bool = state.get().unwrap();
+                let new_state = !action_state; // toggle
+                action.set_state(&new_state.to_variant());
+                for menu in &open_in_menus {
+                    ContextMenuUtil::fill_open_in_menu(
+                        menu,
+                        &menu_open_in_handlers(open_in_handlers.clone(), new_state),
+                    );
+                }
+
+                let mut new_preference: Preferences = Preferences::new();
+                new_preference.prefer_bandcamp = Some(new_state);
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+
         let action_no_dupes_setting = gio::ActionEntry::builder("no-dupes-setting")
             .state(self.old_preferences.no_duplicates.unwrap().to_variant())
             .activate(move |_, action, _| {
@@ -1464,6 +3287,87 @@ impl App {
             })
             .build();
 
+        let gui_tx = self.gui_tx.clone();
+
+        let action_echo_cancel_setting = gio::ActionEntry::builder("echo-cancel-setting")
+            .state(
+                self.old_preferences
+                    .prefer_echo_cancelled_source
+                    .unwrap_or(false)
+                    .to_variant(),
+            )
+            .activate(move |_, action, _| {
+                let state = action.state().unwrap();
+                let action_state: bool = state.get().unwrap();
+                let new_state = !action_state; // toggle
+                action.set_state(&new_state.to_variant());
+
+                let mut new_preference: Preferences = Preferences::new();
+                new_preference.prefer_echo_cancelled_source = Some(new_state);
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+        let microphone_tx = self.microphone_tx.clone();
+        let audio_inputs: adw::ComboRow = self.builder.object("audio_inputs").unwrap();
+
+        let action_warm_start_setting = gio::ActionEntry::builder("warm-start-setting")
+            .state(
+                self.old_preferences
+                    .warm_start
+                    .unwrap_or(false)
+                    .to_variant(),
+            )
+            .activate(move |_, action, _| {
+                let state = action.state().unwrap();
+                let action_state: bool = state.get().unwrap();
+                let new_state = !action_state; // toggle
+                action.set_state(&new_state.to_variant());
+
+                let device_name = audio_inputs
+                    .selected_item()
+                    .and_downcast::<ListedDevice>()
+                    .map(|device| device.inner_name().to_owned());
+                microphone_tx
+                    .try_send(MicrophoneMessage::MicrophoneWarmStart(
+                        device_name.filter(|_| new_state),
+                    ))
+                    .unwrap();
+
+                let mut new_preference: Preferences = Preferences::new();
+                new_preference.warm_start = Some(new_state);
+                gui_tx
+                    .try_send(GUIMessage::UpdatePreference(new_preference))
+                    .unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+
+        recognition_locations::set_geolocation(
+            self.old_preferences.attach_geolocation == Some(true),
+        );
+        let action_attach_geolocation_setting =
+            gio::ActionEntry::builder("attach-geolocation-setting")
+                .state((self.old_preferences.attach_geolocation == Some(true)).to_variant())
+                .activate(move |_, action, _| {
+                    let state = action.state().unwrap();
+                    let action_state: bool = state.get().unwrap();
+                    let new_state = !action_state; // toggle
+                    action.set_state(&new_state.to_variant());
+                    recognition_locations::set_geolocation(new_state);
+
+                    let mut new_preference: Preferences = Preferences::new();
+                    new_preference.attach_geolocation = Some(new_state);
+                    gui_tx
+                        .try_send(GUIMessage::UpdatePreference(new_preference))
+                        .unwrap();
+                })
+                .build();
+
         let action_close = gio::ActionEntry::builder("close")
             .activate(move |window: &adw::ApplicationWindow, _, _| {
                 window.close();
@@ -1492,6 +3396,45 @@ impl App {
             })
             .build();
 
+        let microphone_tx = self.microphone_tx.clone();
+
+        let action_recognize_computer_audio = gio::ActionEntry::builder("recognize-computer-audio")
+            .activate(move |_, _, _| {
+                microphone_tx
+                    .try_send(MicrophoneMessage::FindOutputMonitor)
+                    .unwrap();
+            })
+            .build();
+
+        let gui_tx = self.gui_tx.clone();
+
+        let action_recognize_now = gio::ActionEntry::builder("recognize-now")
+            .activate(move |_, _, _| {
+                gui_tx.try_send(GUIMessage::RecognizeNow).unwrap();
+            })
+            .build();
+
+        let ctx_sink_states = self.ctx_sink_states.clone();
+        let action_command_palette = gio::ActionEntry::builder("command-palette")
+            .activate(move |window: &adw::ApplicationWindow, _, _| {
+                show_command_palette(window, &ctx_sink_states.borrow());
+            })
+            .build();
+
+        // Used by the command palette; the parameter is the name of the sink
+        let sink_tx = self.sink_tx.clone();
+        let action_toggle_sink = gio::ActionEntry::builder("toggle-sink")
+            .parameter_type(Some(&String::static_variant_type()))
+            .activate(move |_: &adw::ApplicationWindow, _, parameter| {
+                let Some(name) = parameter.and_then(|parameter| parameter.str()) else {
+                    return;
+                };
+                sink_tx
+                    .try_send(SinkTaskMessage::ToggleSink(name.to_string()))
+                    .unwrap();
+            })
+            .build();
+
         let action_show_menu = gio::ActionEntry::builder("show-menu")
             .activate(move |_, _, _| {
                 menu_button.activate();
@@ -1501,17 +3444,40 @@ impl App {
         window.add_action_entries([
             action_show_about,
             action_recognize_file,
+            action_recognize_folder,
             action_search_youtube,
-            action_export_to_csv,
-            action_export_favorites_to_csv,
+            action_export_history_as,
+            action_show_charts,
+            action_export_favorites,
+            action_show_qr_code,
+            action_copy_share_text,
+            action_incognito_setting,
+            action_kiosk_mode,
+            action_add_song_to_history,
             action_wipe_history,
+            action_show_recently_deleted,
+            action_show_unknown_clips,
             action_display_shortcuts,
             action_show_preferences,
             action_notification_setting,
-            #[cfg(target_os = "linux")]
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
             action_systray_setting,
+            #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+            action_kdeconnect_setting,
+            action_announce_setting,
+            action_update_check_setting,
             action_no_dupes_setting,
+            action_encrypt_history_setting,
+            action_keep_unrecognized_clips_setting,
+            action_prefer_bandcamp_setting,
+            action_echo_cancel_setting,
+            action_warm_start_setting,
+            action_attach_geolocation_setting,
             action_refresh_devices,
+            action_recognize_computer_audio,
+            action_recognize_now,
+            action_command_palette,
+            action_toggle_sink,
             action_close,
             action_show_menu,
         ]);
@@ -1530,12 +3496,29 @@ impl App {
         application
             .set_accels_for_action("win.show-preferences", &["<Primary>comma", "<Primary>P"]);
         application.set_accels_for_action("win.show-menu", &["F10"]);
+        application.set_accels_for_action("win.kiosk-mode", &["F11"]);
+        application.set_accels_for_action("win.command-palette", &["<Primary>K"]);
     }
 
     fn show_window(&self, application: &adw::Application) {
         let window: adw::ApplicationWindow = self.builder.object("main_window").unwrap();
         window.set_application(Some(application));
 
+        window_state::restore(&window, &self.old_preferences);
+        // Also when hiding the window to the system tray
+        let preferences_interface = self.preferences_interface.clone();
+        window.connect_close_request(move |window| {
+            window_state::save(window, &preferences_interface);
+            glib::Propagation::Proceed
+        });
+        let preferences_interface = self.preferences_interface.clone();
+        let window_ref = window.downgrade();
+        application.connect_shutdown(move |_| {
+            if let Some(window) = window_ref.upgrade() {
+                window_state::save(&window, &preferences_interface);
+            }
+        });
+
         window.present();
     }
 }
@@ -0,0 +1,263 @@
+//! Dialog gathering all the recognitions of an artist or an album from the
+//! history, with the number of times and the dates each of their songs was
+//! heard (see `track_stats`), their aliases included.
+
+use adw::prelude::*;
+use gettextrs::{gettext, ngettext};
+use log::error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::core::aliases::{self, Aliases};
+use crate::gui::export_dialog::show_export_error;
+use crate::gui::song_history_interface::{FavoritesInterface, SongRecordInterface};
+use crate::utils::csv_song_history::{read_song_history, SongHistoryRecord, DEFAULT_CSV_COLUMNS};
+use crate::utils::filesystem_operations::{obtain_recognition_history_csv_path, read_only_history};
+use crate::utils::history_export::{export_history, ExportFormat};
+use crate::utils::track_stats::TrackStats;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DetailsKind {
+    Artist,
+    Album,
+}
+
+/// The recognitions of a song of the artist or album
+pub struct SongRecognitions {
+    pub song_name: String,
+    /// Latest first
+    pub records: Vec<SongHistoryRecord>,
+    pub stats: TrackStats,
+}
+
+fn matches(kind: DetailsKind, name: &str, record: &SongHistoryRecord) -> bool {
+    match kind {
+        DetailsKind::Artist => record
+            .song_name
+            .split_once(" - ")
+            .is_some_and(|(artist, _title)| artist == name),
+        DetailsKind::Album => record.album.as_deref() == Some(name),
+    }
+}
+
+/// Group the recognitions of the artist or album by song, the most
+/// recognized songs first
+pub fn gather_recognitions(
+    records: &[SongHistoryRecord],
+    kind: DetailsKind,
+    name: &str,
+) -> Vec<SongRecognitions> {
+    let mut songs: Vec<SongRecognitions> = vec![];
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    // The history file is sorted from the oldest to the latest
+    for record in records.iter().rev() {
+        if !matches(kind, name, record) {
+            continue;
+        }
+        match positions.get(record.song_name.as_str()) {
+            Some(&position) => {
+                songs[position].records.push(record.clone());
+                songs[position].stats.add(record);
+            }
+            None => {
+                positions.insert(&record.song_name, songs.len());
+                songs.push(SongRecognitions {
+                    song_name: record.song_name.clone(),
+                    records: vec![record.clone()],
+                    stats: TrackStats::new(record),
+                });
+            }
+        }
+    }
+    // Stable, so that songs recognized as often stay latest first
+    songs.sort_by(|first, second| second.records.len().cmp(&first.records.len()));
+    songs
+}
+
+fn export_recognitions(path: &Path, songs: &[SongRecognitions]) -> Result<(), Box<dyn Error>> {
+    let format = ExportFormat::from_path(path).unwrap_or(ExportFormat::Tsv);
+    let records: Vec<SongHistoryRecord> = songs
+        .iter()
+        .flat_map(|song| song.records.iter().cloned())
+        .collect();
+    std::fs::write(
+        path,
+        export_history(&records, DEFAULT_CSV_COLUMNS, format, true)?,
+    )?;
+    Ok(())
+}
+
+pub fn show_details(
+    window: &adw::ApplicationWindow,
+    kind: DetailsKind,
+    name: &str,
+    favorites_interface: Rc<RefCell<FavoritesInterface>>,
+) {
+    let mut records =
+        match obtain_recognition_history_csv_path().and_then(|path| read_song_history(&path)) {
+            Ok(records) => records,
+            Err(error) => {
+                error!("Could not read the song history: {}", error);
+                return;
+            }
+        };
+    aliases::apply_to_records(&mut records);
+    let name = match kind {
+        DetailsKind::Artist => Aliases::load().artist(name),
+        DetailsKind::Album => name.to_string(),
+    };
+    let name = name.as_str();
+    let songs = gather_recognitions(&records, kind, name);
+    let recognitions: usize = songs.iter().map(|song| song.records.len()).sum();
+
+    let list_box = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(["boxed-list"])
+        .build();
+    for song in songs.iter() {
+        let stats = &song.stats;
+        let row = adw::ActionRow::builder()
+            .title(glib::markup_escape_text(&song.song_name))
+            .subtitle(if stats.times_heard == 1 {
+                gettext("Recognized on %s").replace("%s", &stats.last_heard)
+            } else {
+                gettext("Recognized from %s to %s")
+                    .replacen("%s", &stats.first_heard, 1)
+                    .replacen("%s", &stats.last_heard, 1)
+            })
+            .build();
+        row.add_suffix(
+            &gtk::Label::builder()
+                .label(
+                    ngettext("%d time", "%d times", stats.times_heard as u32)
+                        .replace("%d", &stats.times_heard.to_string()),
+                )
+                .css_classes(["dim-label"])
+                .build(),
+        );
+        list_box.append(&row);
+    }
+
+    let dialog = adw::AlertDialog::builder()
+        .heading(name)
+        .body(
+            ngettext(
+                "Recognized %d time",
+                "Recognized %d times",
+                recognitions as u32,
+            )
+            .replace("%d", &recognitions.to_string())
+                + " · "
+                + &ngettext("%d song", "%d songs", songs.len() as u32)
+                    .replace("%d", &songs.len().to_string()),
+        )
+        .extra_child(
+            &gtk::ScrolledWindow::builder()
+                .hscrollbar_policy(gtk::PolicyType::Never)
+                .propagate_natural_height(true)
+                .max_content_height(400)
+                .child(&list_box)
+                .build(),
+        )
+        .close_response("close")
+        .build();
+    dialog.add_response("close", &gettext("_Close"));
+    if read_only_history().is_none() {
+        dialog.add_response("favorites", &gettext("Add to _Favorites"));
+    }
+    dialog.add_response("export", &gettext("_Export..."));
+    dialog.add_response("playlist", &gettext("Export as _Playlist..."));
+
+    let parent = window.clone();
+    let name = name.to_string();
+    dialog.choose(Some(window), None::<&gio::Cancellable>, move |result| {
+        let initial_name = match result.as_str() {
+            "favorites" => {
+                let mut favorites = favorites_interface.borrow_mut();
+                for song in songs.iter() {
+                    let record = song.records[0].clone();
+                    if !favorites.is_favorite(record.clone()) {
+                        favorites.add_row_and_save(record);
+                    }
+                }
+                return;
+            }
+            "export" if cfg!(feature = "xlsx") => format!("{}.xlsx", name),
+            "export" => format!("{}.tsv", name),
+            // Apple Music imports iTunes XML files as playlists
+            "playlist" => format!("{}.xml", name),
+            _ => return,
+        };
+        glib::spawn_future_local(async move {
+            let save_dialog = gtk::FileDialog::builder()
+                .title(gettext("Export the recognitions of %s").replace("%s", &name))
+                .initial_name(initial_name.replace('/', "-"))
+                .build();
+            let Ok(file) = save_dialog.save_future(Some(&parent)).await else {
+                return;
+            };
+            let Some(path) = file.path() else {
+                return;
+            };
+            let exported_path = path.clone();
+            let result = gio::spawn_blocking(move || {
+                export_recognitions(&exported_path, &songs).map_err(|error| error.to_string())
+            })
+            .await
+            .unwrap_or_else(|_| Err(gettext("The export was interrupted")));
+            if let Err(error) = result {
+                show_export_error(&parent, &path, &error);
+            }
+        });
+    });
+}
+
+#[test]
+fn test_gather_recognitions() {
+    let record = |song_name: &str, album: &str, recognition_date: &str| SongHistoryRecord {
+        album: Some(album.to_string()),
+        ..SongHistoryRecord::for_test(song_name, recognition_date)
+    };
+    let records = vec![
+        record(
+            "Daft Punk - Da Funk",
+            "Homework",
+            "Sat Aug 17 22:00:00 2024",
+        ),
+        record(
+            "Daft Punk - Around the World",
+            "Homework",
+            "Sat Aug 17 22:10:00 2024",
+        ),
+        record("Justice - Genesis", "†", "Sat Aug 17 22:20:00 2024"),
+        record(
+            "Daft Punk - Around the World",
+            "Homework",
+            "Sat Aug 17 22:30:00 2024",
+        ),
+        record(
+            "Daft Punk - One More Time",
+            "Discovery",
+            "Sat Aug 17 22:40:00 2024",
+        ),
+    ];
+
+    let songs = gather_recognitions(&records, DetailsKind::Artist, "Daft Punk");
+    assert_eq!(songs.len(), 3);
+    assert_eq!(songs[0].song_name, "Daft Punk - Around the World");
+    assert_eq!(songs[0].records.len(), 2);
+    assert_eq!(
+        songs[0].records[0].recognition_date,
+        "Sat Aug 17 22:30:00 2024"
+    );
+    assert_eq!(songs[0].stats.times_heard, 2);
+    assert_eq!(songs[0].stats.first_heard, "Sat Aug 17 22:10:00 2024");
+    assert_eq!(songs[1].song_name, "Daft Punk - One More Time");
+
+    let songs = gather_recognitions(&records, DetailsKind::Album, "Homework");
+    assert_eq!(songs.len(), 2);
+    assert!(gather_recognitions(&records, DetailsKind::Artist, "Daft").is_empty());
+}
@@ -0,0 +1,119 @@
+//! Dialog asking for the artist, title and album of a song, to add history
+//! entries by hand or correct the ones that were misrecognized.
+
+use adw::prelude::*;
+use gettextrs::gettext;
+
+use crate::utils::clock;
+use crate::utils::csv_song_history::SongHistoryRecord;
+
+/// Let the user fill in or change the song of a history entry. `on_done` is
+/// given the entry once changed; its track key is dropped when the song
+/// changes, as it designates the song that was wrongly recognized.
+pub fn ask_song(
+    parent: &impl IsA<gtk::Widget>,
+    heading: &str,
+    body: &str,
+    initial: SongHistoryRecord,
+    edit_date: bool,
+    on_done: impl Fn(SongHistoryRecord) + 'static,
+) {
+    ask_song_or_cancel(parent, heading, body, initial, edit_date, on_done, || {});
+}
+
+/// As `ask_song`, calling `on_cancel` when the dialog is dismissed
+pub fn ask_song_or_cancel(
+    parent: &impl IsA<gtk::Widget>,
+    heading: &str,
+    body: &str,
+    initial: SongHistoryRecord,
+    edit_date: bool,
+    on_done: impl Fn(SongHistoryRecord) + 'static,
+    on_cancel: impl FnOnce() + 'static,
+) {
+    let (initial_artist, initial_title) = initial
+        .song_name
+        .split_once(" - ")
+        .unwrap_or(("", &initial.song_name));
+
+    let fields = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(["boxed-list"])
+        .build();
+    let artist_row = adw::EntryRow::builder()
+        .title(gettext("Artist"))
+        .text(initial_artist)
+        .build();
+    let title_row = adw::EntryRow::builder()
+        .title(gettext("Title"))
+        .text(initial_title)
+        .build();
+    let album_row = adw::EntryRow::builder()
+        .title(gettext("Album"))
+        .text(initial.album.as_deref().unwrap_or_default())
+        .build();
+    let date_row = adw::EntryRow::builder()
+        .title(gettext("Recognition date"))
+        .tooltip_text(gettext("As shown in the history, or as YYYY-MM-DD HH:MM"))
+        .text(&initial.recognition_date)
+        .visible(edit_date)
+        .build();
+    fields.append(&artist_row);
+    fields.append(&title_row);
+    fields.append(&album_row);
+    fields.append(&date_row);
+
+    let dialog = adw::AlertDialog::builder()
+        .heading(heading)
+        .body(body)
+        .extra_child(&fields)
+        .close_response("cancel")
+        .default_response("save")
+        .build();
+    dialog.add_response("cancel", &gettext("_Cancel"));
+    dialog.add_response("save", &gettext("_Save"));
+    dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+
+    let validate = {
+        let dialog = dialog.clone();
+        let title_row = title_row.clone();
+        let date_row = date_row.clone();
+        move || {
+            let valid_date = clock::parse_entered_date(&date_row.text()).is_some();
+            if valid_date {
+                date_row.remove_css_class("error");
+            } else {
+                date_row.add_css_class("error");
+            }
+            dialog.set_response_enabled("save", !title_row.text().trim().is_empty() && valid_date);
+        }
+    };
+    validate();
+    let validate_title = validate.clone();
+    title_row.connect_changed(move |_| validate_title());
+    date_row.connect_changed(move |_| validate());
+
+    dialog.choose(Some(parent), None::<&gio::Cancellable>, move |result| {
+        if result != "save" {
+            on_cancel();
+            return;
+        }
+        let artist = artist_row.text().trim().to_string();
+        let title = title_row.text().trim().to_string();
+        let album = album_row.text().trim().to_string();
+
+        let mut record = initial.clone();
+        record.song_name = if artist.is_empty() {
+            title
+        } else {
+            format!("{} - {}", artist, title)
+        };
+        if record.song_name != initial.song_name {
+            record.track_key = None;
+        }
+        record.album = Some(album).filter(|album| !album.is_empty());
+        record.recognition_date = clock::parse_entered_date(&date_row.text())
+            .unwrap_or_else(|| date_row.text().trim().to_string());
+        on_done(record);
+    });
+}
@@ -1,19 +1,33 @@
 /// This file contains code for interfacing between the CSV Song history
 /// format defined within the "src/utils/csv_song_history.rs" file, the
 /// GTK-rs GUI of SongRec and the filesystem while using the GUI.
+use crate::core::aliases::Aliases;
+use crate::core::recognition_locations::{entry_key, queue_move, queue_prune, queue_rewrite};
 use crate::gui::history_entry::HistoryEntry;
-use crate::utils::csv_song_history::{HasSong, Song, SongHistoryRecord};
+use crate::utils::clock;
+use crate::utils::csv_song_history::{DeletedSongHistoryRecord, HasSong, Song, SongHistoryRecord};
+use crate::utils::filesystem_operations::read_only_history;
+use crate::utils::history_check::set_aside_rows;
+use crate::utils::history_encryption::{
+    read_history_file, write_history_file, UndecryptableHistory,
+};
+use crate::utils::track_stats::{song_stats, TrackStats};
 use gettextrs::gettext;
 use gtk::prelude::*;
-use log::error;
+use log::{error, warn};
 use std::collections::HashSet;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 trait SongHistoryRecordListStore {
     fn add_song_history_record(&mut self, to_add: &SongHistoryRecord);
     fn remove_song(&mut self, to_remove: Song);
     fn remove_song_history_record(&mut self, to_remove: SongHistoryRecord);
+    fn replace_song_history_record(
+        &mut self,
+        to_replace: &SongHistoryRecord,
+        replacement: &SongHistoryRecord,
+    ) -> bool;
 }
 
 // Extend gio::ListStore to integrate with SongHistoryRecord
@@ -40,6 +54,117 @@ impl SongHistoryRecordListStore for gio::ListStore {
     fn remove_song_history_record(&mut self, to_remove: SongHistoryRecord) {
         self.remove_song(to_remove.get_song());
     }
+
+    /// Replace an entry in place, rather than all the entries of its song
+    fn replace_song_history_record(
+        &mut self,
+        to_replace: &SongHistoryRecord,
+        replacement: &SongHistoryRecord,
+    ) -> bool {
+        let position = self
+            .iter::<HistoryEntry>()
+            .position(|item| &item.unwrap().get_song_history_record() == to_replace);
+        if let Some(position) = position {
+            self.splice(position as u32, 1, &[HistoryEntry::new(replacement)]);
+        }
+        position.is_some()
+    }
+}
+
+/// Set aside a history file that can't be decrypted or parsed, so that
+/// saving the history doesn't overwrite it. Files that can't be accessed
+/// for now, e.g. while the keyring is locked, are left in place, as are
+/// the files of a read-only history. Each file set aside gets a name of its
+/// own, so that a previous one isn't overwritten.
+fn set_aside(csv_path: &Path, error: Box<dyn Error>) -> Box<dyn Error> {
+    if read_only_history().is_some() {
+        return error;
+    }
+    let timestamp = clock::now().format("%Y%m%d-%H%M%S").to_string();
+    let aside_path = (0..)
+        .map(|index| {
+            let mut aside_path = csv_path.as_os_str().to_owned();
+            aside_path.push(format!(".unreadable-{}", timestamp));
+            if index > 0 {
+                aside_path.push(format!("-{}", index));
+            }
+            PathBuf::from(aside_path)
+        })
+        .find(|aside_path| !aside_path.exists())
+        .unwrap();
+    if let Err(rename_error) = std::fs::rename(csv_path, &aside_path) {
+        return format!("{} ({})", error, rename_error).into();
+    }
+    format!(
+        "{} ({})",
+        error,
+        gettext("moved to %s").replace("%s", &aside_path.to_string_lossy())
+    )
+    .into()
+}
+
+/// Read a history file, setting it aside when its contents can't be
+/// decrypted (for example when its encryption key is wrong)
+fn read_history_contents(csv_path: &Path) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    if !csv_path.exists() {
+        return Ok(None);
+    }
+    match read_history_file(csv_path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(error) if error.is::<UndecryptableHistory>() => Err(set_aside(csv_path, error)),
+        Err(error) => Err(error),
+    }
+}
+
+/// The entries of a history file. The rows that can't be read, e.g. after
+/// the file was edited by hand, are moved to its ".rejected" file as
+/// `history check --fix` does, rather than losing them on the next save.
+fn read_records(csv_path: &Path) -> Result<Vec<SongHistoryRecord>, Box<dyn Error>> {
+    let Some(contents) = read_history_contents(csv_path)? else {
+        return Ok(vec![]);
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(contents.as_slice());
+    let headers = reader.headers()?.clone();
+    let mut records = vec![];
+    let mut rejected = vec![];
+    for row in reader.records() {
+        let row = match row {
+            Ok(row) => row,
+            Err(error) => return Err(set_aside(csv_path, error.into())),
+        };
+        match row.deserialize::<SongHistoryRecord>(Some(&headers)) {
+            Ok(record) => records.push(record),
+            Err(error) => {
+                warn!("Setting aside a history row that can't be read: {}", error);
+                rejected.push(row.iter().map(str::to_string).collect::<Vec<_>>());
+            }
+        }
+    }
+    if !rejected.is_empty() && read_only_history().is_none() {
+        let header: Vec<String> = headers.iter().map(str::to_string).collect();
+        match set_aside_rows(csv_path, &header, &rejected) {
+            Ok(rejected_path) => warn!(
+                "{}",
+                gettext("Moved %d rows to %s")
+                    .replace("%d", &rejected.len().to_string())
+                    .replace("%s", &rejected_path.to_string_lossy())
+            ),
+            Err(error) => return Err(set_aside(csv_path, error)),
+        }
+    }
+    Ok(records)
+}
+
+/// Write the entries of a list store, reporting rather than panicking when
+/// e.g. the keyring holding the encryption key is locked
+fn save_list_store(list_store: &gio::ListStore, csv_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for item in list_store.iter::<HistoryEntry>() {
+        writer.serialize(item?.get_song_history_record())?;
+    }
+    write_history_file(csv_path, &writer.into_inner()?)
 }
 
 #[derive(Debug, Clone)]
@@ -101,19 +226,10 @@ impl SongRecordInterface for RecognitionHistoryInterface {
     }
 
     fn load(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Ok(mut reader) = csv::ReaderBuilder::new()
-            .flexible(true)
-            .from_path(&self.csv_path)
-        {
-            let mut read = reader.deserialize().collect::<Vec<_>>();
-            fn item_date(item: &csv::Result<SongHistoryRecord>) -> Option<chrono::NaiveDateTime> {
-                let s = &item.as_ref().ok()?.recognition_date;
-                chrono::NaiveDateTime::parse_from_str(s, "%c").ok()
-            }
-            read.sort_by_cached_key(item_date);
-            for result in read {
-                self.list_store.add_song_history_record(&result?)
-            }
+        let mut records = read_records(&self.csv_path)?;
+        records.sort_by_cached_key(|record| clock::parse_history_date(&record.recognition_date));
+        for record in records {
+            self.list_store.add_song_history_record(&record);
         }
         Ok(())
     }
@@ -121,9 +237,14 @@ impl SongRecordInterface for RecognitionHistoryInterface {
     fn wipe_and_save(&mut self) {
         self.list_store.remove_all();
 
-        let mut writer = csv::Writer::from_path(&self.csv_path).unwrap();
-
-        writer.flush().unwrap();
+        if let Err(error_info) = write_history_file(&self.csv_path, b"") {
+            error!(
+                "{} {}",
+                gettext("Error when saving on the disk:"),
+                error_info
+            );
+        }
+        self.prune_locations();
     }
 
     fn add_row_and_save(&mut self, record: SongHistoryRecord) {
@@ -133,18 +254,19 @@ impl SongRecordInterface for RecognitionHistoryInterface {
     }
 
     fn save(&mut self) {
-        let mut writer = csv::Writer::from_path(&self.csv_path).unwrap();
-
-        for item in self.list_store.iter::<glib::Object>() {
-            let item = item.unwrap().downcast::<HistoryEntry>().unwrap();
-            writer.serialize(item.get_song_history_record()).unwrap();
+        if let Err(error_info) = save_list_store(&self.list_store, &self.csv_path) {
+            error!(
+                "{} {}",
+                gettext("Error when saving on the disk:"),
+                error_info
+            );
         }
-        writer.flush().unwrap();
     }
 
     fn remove(&mut self, song_record: SongHistoryRecord) {
         self.list_store.remove_song_history_record(song_record);
-        self.save()
+        self.save();
+        self.prune_locations();
     }
 }
 
@@ -171,15 +293,9 @@ impl SongRecordInterface for FavoritesInterface {
     }
 
     fn load(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Ok(mut reader) = csv::ReaderBuilder::new()
-            .flexible(true)
-            .from_path(&self.csv_path)
-        {
-            for result in reader.deserialize() {
-                let record = result?;
-                self.list_store.add_song_history_record(&record);
-                self.is_favorite.insert(record.get_song());
-            }
+        for record in read_records(&self.csv_path)? {
+            self.list_store.add_song_history_record(&record);
+            self.is_favorite.insert(record.get_song());
         }
         Ok(())
     }
@@ -187,9 +303,13 @@ impl SongRecordInterface for FavoritesInterface {
     fn wipe_and_save(&mut self) {
         self.list_store.remove_all();
         self.is_favorite.clear();
-        let mut writer = csv::Writer::from_path(&self.csv_path).unwrap();
-
-        writer.flush().unwrap();
+        if let Err(error_info) = write_history_file(&self.csv_path, b"") {
+            error!(
+                "{} {}",
+                gettext("Error when saving on the disk:"),
+                error_info
+            );
+        }
     }
 
     fn add_row_and_save(&mut self, record: SongHistoryRecord) {
@@ -199,13 +319,13 @@ impl SongRecordInterface for FavoritesInterface {
     }
 
     fn save(&mut self) {
-        let mut writer = csv::Writer::from_path(&self.csv_path).unwrap();
-
-        for item in self.list_store.iter::<glib::Object>() {
-            let item = item.unwrap().downcast::<HistoryEntry>().unwrap();
-            writer.serialize(item.get_song_history_record()).unwrap();
+        if let Err(error_info) = save_list_store(&self.list_store, &self.csv_path) {
+            error!(
+                "{} {}",
+                gettext("Error when saving on the disk:"),
+                error_info
+            );
         }
-        writer.flush().unwrap();
     }
 
     fn remove(&mut self, song_record: SongHistoryRecord) {
@@ -216,8 +336,219 @@ impl SongRecordInterface for FavoritesInterface {
     }
 }
 
+/// The entries deleted from the history are moved to a recycle bin stored
+/// next to it, from which the "Recently deleted" dialog restores them
+impl RecognitionHistoryInterface {
+    fn deleted_csv_path(&self) -> PathBuf {
+        self.csv_path.with_file_name("deleted_songs.csv")
+    }
+
+    /// The entries of the recycle bin, most recently deleted first; expired
+    /// ones are left out
+    pub fn recently_deleted(&self) -> Vec<DeletedSongHistoryRecord> {
+        let now = clock::now();
+        let contents = match read_history_contents(&self.deleted_csv_path()) {
+            Ok(contents) => contents.unwrap_or_default(),
+            Err(error_info) => {
+                error!(
+                    "{} {}",
+                    gettext("Error when reading the deleted songs on the disk:"),
+                    error_info
+                );
+                vec![]
+            }
+        };
+        let mut deleted: Vec<DeletedSongHistoryRecord> = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(contents.as_slice())
+            .deserialize()
+            .filter_map(Result::ok)
+            .collect();
+        deleted.retain(|record| !record.is_expired(now));
+        deleted.reverse();
+        deleted
+    }
+
+    fn save_recycle_bin(&self, deleted: &[DeletedSongHistoryRecord]) {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        let result = deleted
+            .iter()
+            .rev()
+            .try_for_each(|record| writer.serialize(record))
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|_| {
+                writer
+                    .into_inner()
+                    .map_err(|error| error.to_string().into())
+            })
+            .and_then(|contents| write_history_file(&self.deleted_csv_path(), &contents));
+        if let Err(error_info) = result {
+            error!(
+                "{} {}",
+                gettext("Error when saving the deleted songs on the disk:"),
+                error_info
+            );
+        }
+        self.prune_locations();
+    }
+
+    /// Delete the locations of the entries that are neither in the history
+    /// nor in its recycle bin anymore
+    pub fn prune_locations(&self) {
+        if read_only_history().is_some() {
+            return;
+        }
+        let mut kept: HashSet<_> = self
+            .recently_deleted()
+            .iter()
+            .map(|deleted| entry_key(&deleted.to_record()))
+            .collect();
+        kept.extend(
+            self.list_store
+                .iter::<HistoryEntry>()
+                .map(|item| entry_key(&item.unwrap().get_song_history_record())),
+        );
+        queue_prune(kept);
+    }
+
+    fn move_to_recycle_bin(&mut self, records: Vec<SongHistoryRecord>) {
+        let deletion_date = clock::history_date_now();
+        let mut deleted = self.recently_deleted();
+        for record in records {
+            deleted.insert(
+                0,
+                DeletedSongHistoryRecord::new(record, deletion_date.clone()),
+            );
+        }
+        self.save_recycle_bin(&deleted);
+    }
+
+    /// Delete the entries of a song, keeping them in the recycle bin
+    pub fn delete(&mut self, song_record: SongHistoryRecord) {
+        let song = song_record.get_song();
+        let records: Vec<SongHistoryRecord> = self
+            .list_store
+            .iter::<HistoryEntry>()
+            .map(|item| item.unwrap())
+            .filter(|item| item.get_song() == song)
+            .map(|item| item.get_song_history_record())
+            .collect();
+        // Before the locations of the song are pruned along with it
+        self.move_to_recycle_bin(records);
+        self.remove(song_record);
+    }
+
+    /// The entries of the history list, as loaded from the disk and
+    /// recognized since
+    pub fn records(&self) -> Vec<SongHistoryRecord> {
+        self.list_store
+            .iter::<HistoryEntry>()
+            .map(|item| item.unwrap().get_song_history_record())
+            .collect()
+    }
+
+    /// Delete the whole history, keeping it in the recycle bin
+    pub fn delete_all(&mut self) {
+        let records = self.records();
+        self.move_to_recycle_bin(records);
+        self.wipe_and_save();
+    }
+
+    /// Put back an entry of the recycle bin at its place in the history
+    pub fn restore(&mut self, deleted_record: &DeletedSongHistoryRecord) {
+        self.insert_and_save(deleted_record.to_record());
+
+        let mut deleted = self.recently_deleted();
+        if let Some(index) = deleted.iter().position(|other| other == deleted_record) {
+            deleted.remove(index);
+        }
+        self.save_recycle_bin(&deleted);
+    }
+
+    /// Add an entry at the place of its date, as for the songs added by hand
+    pub fn insert_and_save(&mut self, record: SongHistoryRecord) {
+        let date = |record: &SongHistoryRecord| clock::parse_history_date(&record.recognition_date);
+        let position = self
+            .list_store
+            .iter::<HistoryEntry>()
+            .position(|item| date(&item.unwrap().get_song_history_record()) <= date(&record))
+            .unwrap_or(self.list_store.n_items() as usize);
+        self.list_store
+            .insert(position as u32, &HistoryEntry::new(&record));
+        self.save();
+    }
+
+    /// How many times a song was heard, and when, counting the entries
+    /// under its aliases as "history stats" does. Only the entries of the
+    /// song are copied out of the list.
+    pub fn song_stats(&self, song_name: &str) -> Option<TrackStats> {
+        let aliases = Aliases::load();
+        let records: Vec<SongHistoryRecord> = self
+            .list_store
+            .iter::<HistoryEntry>()
+            .map(Result::unwrap)
+            .filter(|item| aliases.song_name(&item.song_name()) == song_name)
+            .map(|item| SongHistoryRecord {
+                song_name: song_name.to_string(),
+                ..item.get_song_history_record()
+            })
+            .collect();
+        song_stats(&records, song_name)
+    }
+
+    /// Correct an entry, e.g. when the wrong version of a song was recognized
+    pub fn edit(&mut self, record: &SongHistoryRecord, corrected: &SongHistoryRecord) {
+        if self
+            .list_store
+            .replace_song_history_record(record, corrected)
+        {
+            self.save();
+            queue_move(entry_key(record), entry_key(corrected));
+        }
+    }
+
+    pub fn empty_recycle_bin(&mut self) {
+        self.save_recycle_bin(&[]);
+    }
+
+    /// Save the history, its recycle bin and the locations again, after history encryption
+    /// has been turned on or off
+    pub fn rewrite_files(&mut self) {
+        self.save();
+        let deleted = self.recently_deleted();
+        self.save_recycle_bin(&deleted);
+        queue_rewrite();
+    }
+}
+
 impl FavoritesInterface {
     pub fn is_favorite<T: HasSong>(&self, has_song: T) -> bool {
         self.is_favorite.contains(&has_song.get_song())
     }
+
+    /// Correct the favorite of the song of a history entry, keeping the date
+    /// it was added to the favorites on
+    pub fn edit(&mut self, record: &SongHistoryRecord, corrected: &SongHistoryRecord) {
+        let song = record.clone().get_song();
+        let favorite = self
+            .list_store
+            .iter::<HistoryEntry>()
+            .map(|item| item.unwrap().get_song_history_record())
+            .find(|favorite| favorite.clone().get_song() == song);
+        let Some(favorite) = favorite else {
+            return;
+        };
+        let replacement = SongHistoryRecord {
+            recognition_date: favorite.recognition_date.clone(),
+            ..corrected.clone()
+        };
+        if self
+            .list_store
+            .replace_song_history_record(&favorite, &replacement)
+        {
+            self.is_favorite.remove(&song);
+            self.is_favorite.insert(replacement.get_song());
+            self.save();
+        }
+    }
 }
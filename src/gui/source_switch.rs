@@ -0,0 +1,51 @@
+//! Pair of buttons at the start of the header bar switching the recognition
+//! between the microphone and the audio of this computer, mirroring the
+//! "microphone_switch" and "loopback_switch" rows of the main page so that
+//! the source can be changed without scrolling to them. Turning a button
+//! off stops the recognition.
+
+use adw::prelude::*;
+use gettextrs::gettext;
+
+fn source_button(icon_name: &str, tooltip: &str, switch: &adw::SwitchRow) -> gtk::ToggleButton {
+    let button = gtk::ToggleButton::builder()
+        .icon_name(icon_name)
+        .tooltip_text(tooltip)
+        .build();
+    // Activating a row deactivates the other one through its callback, which
+    // updates the other button in turn
+    switch
+        .bind_property("active", &button, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    switch
+        .bind_property("visible", &button, "visible")
+        .sync_create()
+        .build();
+    button
+}
+
+pub fn setup_source_switch(
+    header_bar: &adw::HeaderBar,
+    microphone_switch: &adw::SwitchRow,
+    loopback_switch: &adw::SwitchRow,
+) {
+    let buttons = gtk::Box::builder().css_classes(["linked"]).build();
+    buttons.append(&source_button(
+        "audio-input-microphone-symbolic",
+        &gettext("Recognize from microphone"),
+        microphone_switch,
+    ));
+    buttons.append(&source_button(
+        "audio-speakers-symbolic",
+        &gettext("Recognize from my speakers"),
+        loopback_switch,
+    ));
+    // Hidden until the devices are listed
+    microphone_switch
+        .bind_property("visible", &buttons, "visible")
+        .sync_create()
+        .build();
+    header_bar.pack_start(&buttons);
+}
@@ -0,0 +1,125 @@
+//! Button of the header bar listing the long operations of `core::tasks`
+//! with their progress, each with a button to cancel it. It is only shown
+//! while a task runs.
+
+use adw::prelude::*;
+use gettextrs::gettext;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::core::tasks::{self, TaskStatus};
+
+/// The row of a task, updated in place as long as the task runs
+struct TaskRow {
+    row: gtk::Box,
+    cancel_button: gtk::Button,
+    progress_bar: gtk::ProgressBar,
+}
+
+impl TaskRow {
+    fn new(status: &TaskStatus) -> Self {
+        let row = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(6)
+            .margin_top(6)
+            .margin_bottom(6)
+            .margin_start(6)
+            .margin_end(6)
+            .build();
+
+        let header = gtk::Box::builder().spacing(12).build();
+        let title = gtk::Label::builder()
+            .label(&status.title)
+            .xalign(0.0)
+            .hexpand(true)
+            .ellipsize(gtk::pango::EllipsizeMode::Middle)
+            .build();
+        header.append(&title);
+        let cancel_button = gtk::Button::builder()
+            .icon_name("process-stop-symbolic")
+            .tooltip_text(gettext("Cancel"))
+            .css_classes(["flat", "circular"])
+            .build();
+        let id = status.id;
+        cancel_button.connect_clicked(move |button| {
+            tasks::cancel(id);
+            button.set_sensitive(false);
+        });
+        header.append(&cancel_button);
+        row.append(&header);
+
+        let progress_bar = gtk::ProgressBar::builder().show_text(true).build();
+        row.append(&progress_bar);
+
+        let task_row = TaskRow {
+            row,
+            cancel_button,
+            progress_bar,
+        };
+        task_row.update(status);
+        task_row
+    }
+
+    fn update(&self, status: &TaskStatus) {
+        self.cancel_button.set_sensitive(!status.cancelled);
+        if status.cancelled {
+            self.progress_bar.set_text(Some(&gettext("Cancelling...")));
+            return;
+        }
+        match status.fraction() {
+            Some(fraction) => {
+                self.progress_bar.set_fraction(fraction);
+                self.progress_bar.set_text(Some(
+                    &gettext("%d of %d")
+                        .replacen("%d", &status.done.to_string(), 1)
+                        .replacen("%d", &status.total.to_string(), 1),
+                ));
+            }
+            None => self.progress_bar.pulse(),
+        }
+    }
+}
+
+pub fn setup_task_manager(header_bar: &adw::HeaderBar) {
+    let list = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .width_request(320)
+        .build();
+    let button = gtk::MenuButton::builder()
+        .icon_name("emblem-synchronizing-symbolic")
+        .tooltip_text(gettext("Running tasks"))
+        .popover(&gtk::Popover::builder().child(&list).build())
+        .visible(false)
+        .build();
+    header_bar.pack_end(&button);
+
+    let rows: RefCell<HashMap<u64, TaskRow>> = RefCell::new(HashMap::new());
+    glib::timeout_add_local(Duration::from_millis(500), move || {
+        let running_tasks = tasks::list();
+        let mut rows = rows.borrow_mut();
+        rows.retain(|id, task_row| {
+            let is_running = running_tasks.iter().any(|status| status.id == *id);
+            if !is_running {
+                list.remove(&task_row.row);
+            }
+            is_running
+        });
+        // Tasks are listed in the order they were started
+        for status in &running_tasks {
+            match rows.get(&status.id) {
+                Some(task_row) => task_row.update(status),
+                None => {
+                    let task_row = TaskRow::new(status);
+                    list.append(&task_row.row);
+                    rows.insert(status.id, task_row);
+                }
+            }
+        }
+        if running_tasks.is_empty() && button.is_visible() {
+            button.popdown();
+        }
+        button.set_visible(!running_tasks.is_empty());
+        glib::ControlFlow::Continue
+    });
+}
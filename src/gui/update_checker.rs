@@ -0,0 +1,86 @@
+//! Opt-in check for new SongRec releases, through the GitHub releases API.
+//! Users who got SongRec from a package manager (distribution packages,
+//! Flatpak, Snap) receive updates from there, so we don't offer the check
+//! to them.
+
+use glib::source::Priority;
+use serde_json::Value;
+use soup::prelude::SessionExt;
+use std::error::Error;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/marin-m/SongRec/releases/latest";
+
+pub struct Release {
+    pub version: String,
+    pub notes: String,
+    pub url: String,
+}
+
+pub fn is_managed_by_package_manager() -> bool {
+    if std::env::var_os("FLATPAK_ID").is_some() || std::env::var_os("SNAP_NAME").is_some() {
+        return true;
+    }
+    // Distribution packages install to /usr, "cargo install" and manual
+    // installs go to the home directory or /usr/local
+    #[cfg(target_os = "linux")]
+    if let Ok(executable) = std::env::current_exe() {
+        return executable.starts_with("/usr") && !executable.starts_with("/usr/local");
+    }
+    false
+}
+
+/// "v0.7.10" => [0, 7, 10]
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| {
+            part.chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+                .parse()
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+/// Returns the latest release when it is newer than the running version
+pub async fn check_for_update() -> Result<Option<Release>, Box<dyn Error>> {
+    let session = soup::Session::new();
+    session.set_timeout(20);
+    // The GitHub API rejects requests without an user agent
+    session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+    let message = soup::Message::new("GET", LATEST_RELEASE_URL)?;
+    message
+        .request_headers()
+        .unwrap()
+        .append("Accept", "application/vnd.github+json");
+
+    let response = session
+        .send_and_read_future(&message, Priority::DEFAULT)
+        .await?;
+    if message.status_code() != 200 {
+        return Err(format!("GitHub API returned status {}", message.status_code()).into());
+    }
+
+    let release: Value = serde_json::from_slice(&response[..])?;
+    let version = release["tag_name"]
+        .as_str()
+        .ok_or("No version in the GitHub API response")?
+        .trim_start_matches('v')
+        .to_string();
+
+    if parse_version(&version) <= parse_version(env!("CARGO_PKG_VERSION")) {
+        return Ok(None);
+    }
+
+    Ok(Some(Release {
+        version,
+        notes: release["body"].as_str().unwrap_or_default().to_string(),
+        url: release["html_url"]
+            .as_str()
+            .unwrap_or("https://github.com/marin-m/SongRec/releases")
+            .to_string(),
+    }))
+}
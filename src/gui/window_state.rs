@@ -0,0 +1,76 @@
+//! Size of the main window, remembered for each configuration of monitors
+//! (e.g. a laptop alone, or docked to a bigger screen) and restored at
+//! startup. GTK doesn't let applications place their windows, which is up
+//! to the compositor on Wayland, so only the size and the maximized state
+//! are kept.
+
+use adw::prelude::*;
+use std::sync::{Arc, Mutex};
+
+use crate::core::preferences::{Preferences, PreferencesInterface, WindowState};
+
+/// The connected monitors and their resolutions, e.g. "DP-1
+/// 2560x1440+eDP-1 1920x1200"
+fn monitor_configuration(window: &adw::ApplicationWindow) -> String {
+    let mut monitors: Vec<String> = window
+        .display()
+        .monitors()
+        .iter::<gdk::Monitor>()
+        .flatten()
+        .map(|monitor| {
+            let geometry = monitor.geometry();
+            format!(
+                "{} {}x{}",
+                monitor
+                    .connector()
+                    .or_else(|| monitor.model())
+                    .unwrap_or_else(|| "monitor".into()),
+                geometry.width() * monitor.scale_factor(),
+                geometry.height() * monitor.scale_factor()
+            )
+        })
+        .collect();
+    monitors.sort();
+    monitors.join("+")
+}
+
+pub fn restore(window: &adw::ApplicationWindow, preferences: &Preferences) {
+    let configuration = monitor_configuration(window);
+    let Some(state) = preferences
+        .window_states
+        .as_ref()
+        .and_then(|states| states.get(&configuration))
+    else {
+        return;
+    };
+    window.set_default_size(state.width, state.height);
+    window.set_maximized(state.maximized);
+}
+
+pub fn save(
+    window: &adw::ApplicationWindow,
+    preferences_interface: &Arc<Mutex<PreferencesInterface>>,
+) {
+    // While maximized, the default size stays the one to restore after
+    let (width, height) = window.default_size();
+    if width <= 0 || height <= 0 {
+        return;
+    }
+    let state = WindowState {
+        width,
+        height,
+        maximized: window.is_maximized(),
+    };
+    let mut preferences_interface = preferences_interface.lock().unwrap();
+    let mut window_states = preferences_interface
+        .preferences
+        .window_states
+        .clone()
+        .unwrap_or_default();
+    if window_states.insert(monitor_configuration(window), state.clone()) == Some(state) {
+        return;
+    }
+    let mut new_preference = Preferences::new();
+    new_preference.window_states = Some(window_states);
+    preferences_interface.update(new_preference);
+}
@@ -0,0 +1,498 @@
+use gettextrs::gettext;
+use log::info;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+use crate::core::aliases;
+use crate::core::preferences::{Preferences, PreferencesInterface};
+use crate::core::recognition_locations::prune_locations;
+use crate::core::unknown_clips::{list_clips, UnknownClip};
+use crate::plugins::spotify_playlist::{
+    diff_playlist, parse_playlist_id, resolve_track_uri, SpotifyPlaylist,
+};
+use crate::utils::charts::{build_chart, render_chart, ChartFormat};
+use crate::utils::clock;
+use crate::utils::csv_song_history::{
+    apply_retention, dedupe_history, read_song_history, write_song_history, CsvColumn, DateRange,
+};
+use crate::utils::filesystem_operations::{
+    obtain_favorites_csv_path, obtain_recognition_history_csv_path,
+    obtain_recognition_locations_csv_path,
+};
+use crate::utils::history_bundle::write_bundle;
+use crate::utils::history_check::{check_history, set_aside_rows};
+use crate::utils::history_encryption::{
+    is_encrypted, read_history_file, set_history_encryption, write_history_file,
+};
+use crate::utils::history_export::{export_history, ExportFormat};
+use crate::utils::history_report::{
+    build_report, render_report, MailHeaders, ReportFormat, ReportPeriod,
+};
+use crate::utils::track_stats::track_stats;
+
+pub struct HistoryExportOptions {
+    pub favorites: bool,
+    pub format: Option<ExportFormat>,
+    pub output: Option<String>,
+    pub columns: Vec<CsvColumn>,
+    pub header: bool,
+    /// Only export the songs recognized from this kind of source
+    pub source_kind: Option<String>,
+}
+
+pub fn export_main(options: HistoryExportOptions) -> Result<(), Box<dyn Error>> {
+    let csv_path = if options.favorites {
+        obtain_favorites_csv_path()?
+    } else {
+        obtain_recognition_history_csv_path()?
+    };
+    let mut records = if csv_path.exists() {
+        read_song_history(&csv_path)?
+    } else {
+        vec![]
+    };
+    if let Some(ref kind) = options.source_kind {
+        records.retain(|record| record.has_source_kind(kind));
+    }
+    aliases::apply_to_records(&mut records);
+
+    let format = options
+        .format
+        .or_else(|| {
+            options
+                .output
+                .as_ref()
+                .and_then(|output| ExportFormat::from_path(Path::new(output)))
+        })
+        .unwrap_or(ExportFormat::Csv);
+
+    let contents = export_history(&records, &options.columns, format, options.header)?;
+
+    match options.output {
+        Some(output) => {
+            std::fs::write(&output, contents)?;
+            info!(
+                "{}",
+                gettext("Exported %d songs to %s")
+                    .replace("%d", &records.len().to_string())
+                    .replace("%s", &output)
+            );
+        }
+        None => std::io::stdout().write_all(&contents)?,
+    }
+    Ok(())
+}
+
+/// Remove the duplicate entries of the history, keeping a copy of the
+/// previous file next to it
+pub fn dedupe_main(
+    window_minutes: u32,
+    favorites: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let csv_path = if favorites {
+        obtain_favorites_csv_path()?
+    } else {
+        obtain_recognition_history_csv_path()?
+    };
+    if !csv_path.exists() {
+        return Ok(());
+    }
+    let records = read_song_history(&csv_path)?;
+    let total = records.len();
+    let (kept, duplicates) =
+        dedupe_history(records, chrono::Duration::minutes(window_minutes as i64));
+
+    for duplicate in &duplicates {
+        println!(
+            "{}",
+            gettext("%s: %s (duplicate of the entry of %s)")
+                .replacen("%s", &duplicate.removed.recognition_date, 1)
+                .replacen("%s", &duplicate.removed.song_name, 1)
+                .replacen("%s", &duplicate.kept.recognition_date, 1)
+        );
+    }
+    if dry_run {
+        println!(
+            "{}",
+            gettext("%d of %d entries would be removed")
+                .replacen("%d", &duplicates.len().to_string(), 1)
+                .replacen("%d", &total.to_string(), 1)
+        );
+        return Ok(());
+    }
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+
+    let mut backup_path = csv_path.clone().into_os_string();
+    backup_path.push(".bak");
+    std::fs::copy(&csv_path, &backup_path)?;
+    write_song_history(&csv_path, &kept)?;
+    if !favorites {
+        prune_locations(&kept)?;
+    }
+    info!(
+        "{}",
+        gettext("Removed %d of %d entries, the previous history was saved to %s")
+            .replacen("%d", &duplicates.len().to_string(), 1)
+            .replacen("%d", &total.to_string(), 1)
+            .replace("%s", &backup_path.to_string_lossy())
+    );
+    Ok(())
+}
+
+/// Enforce the retention policy of the preferences, or the given limits, on
+/// the history
+pub fn prune_main(
+    max_entries: Option<u32>,
+    max_months: Option<u32>,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let preferences = PreferencesInterface::new().preferences;
+    let max_entries = max_entries.or(preferences.history_max_entries).unwrap_or(0);
+    let max_months = max_months.or(preferences.history_max_months).unwrap_or(0);
+    if max_entries == 0 && max_months == 0 {
+        return Err(gettext(
+            "No retention limit is set, pass --max-entries or --max-months, or set one in the preferences",
+        )
+        .into());
+    }
+
+    let csv_path = obtain_recognition_history_csv_path()?;
+    if !csv_path.exists() {
+        return Ok(());
+    }
+    let mut records = read_song_history(&csv_path)?;
+    let total = records.len();
+    let removed = apply_retention(&mut records, max_entries, max_months, clock::now());
+
+    if dry_run {
+        println!(
+            "{}",
+            gettext("%d of %d entries would be removed")
+                .replacen("%d", &removed.to_string(), 1)
+                .replacen("%d", &total.to_string(), 1)
+        );
+    } else if removed > 0 {
+        write_song_history(&csv_path, &records)?;
+        prune_locations(&records)?;
+        info!(
+            "{}",
+            gettext("Removed %d of %d entries")
+                .replacen("%d", &removed.to_string(), 1)
+                .replacen("%d", &total.to_string(), 1)
+        );
+    }
+    Ok(())
+}
+
+/// Turn the encryption of the history, favorites, deleted songs and
+/// recognition locations on or off, rewriting the existing files accordingly
+pub fn encryption_main(enable: bool) -> Result<(), Box<dyn Error>> {
+    if enable && !cfg!(feature = "encryption") {
+        return Err(gettext("SongRec was built without the \"encryption\" feature").into());
+    }
+    let history_path = obtain_recognition_history_csv_path()?;
+    let paths = [
+        history_path.with_file_name("deleted_songs.csv"),
+        obtain_favorites_csv_path()?,
+        obtain_recognition_locations_csv_path()?,
+        history_path,
+    ];
+
+    set_history_encryption(enable);
+    for path in paths.iter().filter(|path| path.exists()) {
+        let contents = read_history_file(path)?;
+        if is_encrypted(&std::fs::read(path)?) != enable {
+            write_history_file(path, &contents)?;
+            info!(
+                "{}",
+                if enable {
+                    gettext("Encrypted %s")
+                } else {
+                    gettext("Decrypted %s")
+                }
+                .replace("%s", &path.to_string_lossy())
+            );
+        }
+    }
+
+    let mut new_preference = Preferences::new();
+    new_preference.encrypt_history = Some(enable);
+    PreferencesInterface::new().update(new_preference);
+    Ok(())
+}
+
+/// Report the rows of the history that the GUI can't load and, with `fix`,
+/// repair them or move them to a ".rejected" file next to the history
+pub fn check_main(favorites: bool, fix: bool) -> Result<(), Box<dyn Error>> {
+    let csv_path = if favorites {
+        obtain_favorites_csv_path()?
+    } else {
+        obtain_recognition_history_csv_path()?
+    };
+    if !csv_path.exists() {
+        return Ok(());
+    }
+    let check = check_history(&read_history_file(&csv_path)?)?;
+
+    for problem in &check.problems {
+        println!(
+            "{}",
+            gettext("Line %d: %s (%s)")
+                .replacen("%d", &problem.line.to_string(), 1)
+                .replacen("%s", &problem.description, 1)
+                .replacen(
+                    "%s",
+                    &if problem.repaired {
+                        gettext("repairable")
+                    } else {
+                        gettext("will be set aside")
+                    },
+                    1
+                )
+        );
+    }
+    if check.problems.is_empty() {
+        println!("{}", gettext("No problem found"));
+        return Ok(());
+    }
+    if !fix {
+        return Err(gettext("%d problems found, run with --fix to repair them")
+            .replace("%d", &check.problems.len().to_string())
+            .into());
+    }
+
+    let mut backup_path = csv_path.clone().into_os_string();
+    backup_path.push(".bak");
+    std::fs::copy(&csv_path, &backup_path)?;
+    if !check.rejected.is_empty() {
+        let rejected_path = set_aside_rows(&csv_path, &check.header, &check.rejected)?;
+        info!(
+            "{}",
+            gettext("Moved %d rows to %s")
+                .replace("%d", &check.rejected.len().to_string())
+                .replace("%s", &rejected_path.to_string_lossy())
+        );
+    }
+    write_song_history(&csv_path, &check.records)?;
+    info!(
+        "{}",
+        gettext("Repaired the history, the previous file was saved to %s")
+            .replace("%s", &backup_path.to_string_lossy())
+    );
+    Ok(())
+}
+
+pub fn bundle_main(
+    range_text: &str,
+    output: &str,
+    favorites: bool,
+    source_kind: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let range: DateRange = range_text.parse()?;
+    let csv_path = if favorites {
+        obtain_favorites_csv_path()?
+    } else {
+        obtain_recognition_history_csv_path()?
+    };
+    let records: Vec<_> = read_song_history(&csv_path)?
+        .into_iter()
+        .filter(|record| range.contains(record))
+        .filter(|record| source_kind.is_none_or(|kind| record.has_source_kind(kind)))
+        .collect();
+    // The unrecognized clips belong to the history, not to the favorites
+    let clips: Vec<UnknownClip> = if favorites {
+        vec![]
+    } else {
+        list_clips()
+            .into_iter()
+            .filter(|clip| {
+                let record = clip.to_record();
+                range.contains(&record)
+                    && source_kind.is_none_or(|kind| record.has_source_kind(kind))
+            })
+            .collect()
+    };
+    if records.is_empty() && clips.is_empty() {
+        return Err(gettext("No song was recognized during this period").into());
+    }
+
+    let title = gettext("Songs recognized with SongRec (%s)").replace("%s", range_text);
+    let summary = write_bundle(&records, &clips, &title, Path::new(output))?;
+    info!(
+        "{}",
+        gettext("Bundled %d songs, %d covers and %d unrecognized clips into %s")
+            .replacen("%d", &summary.songs.to_string(), 1)
+            .replacen("%d", &summary.covers.to_string(), 1)
+            .replacen("%d", &summary.clips.to_string(), 1)
+            .replace("%s", output)
+    );
+    Ok(())
+}
+
+pub fn stats_main(limit: Option<usize>, json: bool) -> Result<(), Box<dyn Error>> {
+    let csv_path = obtain_recognition_history_csv_path()?;
+    let mut records = if csv_path.exists() {
+        read_song_history(&csv_path)?
+    } else {
+        vec![]
+    };
+    aliases::apply_to_records(&mut records);
+    let mut stats = track_stats(&records);
+    if let Some(limit) = limit {
+        stats.truncate(limit);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    for song in &stats {
+        println!(
+            "{}",
+            gettext("%d × %s (first heard on %s, last heard on %s)")
+                .replacen("%d", &song.times_heard.to_string(), 1)
+                .replacen("%s", &song.song_name, 1)
+                .replacen("%s", &song.first_heard, 1)
+                .replacen("%s", &song.last_heard, 1)
+        );
+    }
+    Ok(())
+}
+
+pub fn charts_main(
+    period: ReportPeriod,
+    format: ChartFormat,
+    limit: usize,
+    output: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let csv_path = obtain_recognition_history_csv_path()?;
+    let mut records = if csv_path.exists() {
+        read_song_history(&csv_path)?
+    } else {
+        vec![]
+    };
+    aliases::apply_to_records(&mut records);
+    let chart = build_chart(&records, period, clock::now(), limit);
+    let contents = render_chart(&chart, format);
+
+    match output {
+        Some(output) => std::fs::write(output, contents)?,
+        None => std::io::stdout().write_all(contents.as_bytes())?,
+    }
+    Ok(())
+}
+
+pub fn report_main(
+    period: ReportPeriod,
+    format: ReportFormat,
+    mail_to: Option<&str>,
+    mail_from: Option<&str>,
+    output: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let csv_path = obtain_recognition_history_csv_path()?;
+    let mut records = if csv_path.exists() {
+        read_song_history(&csv_path)?
+    } else {
+        vec![]
+    };
+    aliases::apply_to_records(&mut records);
+    let report = build_report(&records, period, clock::now());
+    // Mailed to oneself unless another sender is given
+    let mail = mail_to.map(|to| MailHeaders {
+        to: to.to_string(),
+        from: mail_from.unwrap_or(to).to_string(),
+        date: clock::now_zoned(),
+    });
+    let contents = render_report(&report, format, mail.as_ref());
+
+    match output {
+        Some(output) => std::fs::write(output, contents)?,
+        None => std::io::stdout().write_all(contents.as_bytes())?,
+    }
+    Ok(())
+}
+
+pub fn spotify_diff_main(
+    playlist: &str,
+    access_token: String,
+    range_text: Option<&str>,
+    fix: bool,
+) -> Result<(), Box<dyn Error>> {
+    let range: Option<DateRange> = range_text.map(|range| range.parse()).transpose()?;
+    let records: Vec<_> = read_song_history(&obtain_recognition_history_csv_path()?)?
+        .into_iter()
+        .filter(|record| range.is_none_or(|range| range.contains(record)))
+        .collect();
+
+    // The tracks of the history, in the order they were first recognized
+    let mut expected: Vec<String> = vec![];
+    let mut song_names: std::collections::HashMap<String, String> = Default::default();
+    let mut unresolved: Vec<String> = vec![];
+    for record in &records {
+        match resolve_track_uri(record) {
+            Some(uri) => {
+                if !song_names.contains_key(&uri) {
+                    song_names.insert(uri.clone(), record.song_name.clone());
+                    expected.push(uri);
+                }
+            }
+            None => {
+                if !unresolved.contains(&record.song_name) {
+                    unresolved.push(record.song_name.clone());
+                }
+            }
+        }
+    }
+
+    let playlist = SpotifyPlaylist::new(access_token, parse_playlist_id(playlist));
+    glib::MainContext::default().block_on(async move {
+        let diff = diff_playlist(&expected, &playlist.track_uris().await?);
+        let song_name = |uri: &String| song_names.get(uri).cloned().unwrap_or_default();
+
+        for uri in &diff.missing {
+            println!(
+                "{}",
+                gettext("Missing: %s (%s)")
+                    .replacen("%s", &song_name(uri), 1)
+                    .replacen("%s", uri, 1)
+            );
+        }
+        for uri in &diff.out_of_order {
+            println!(
+                "{}",
+                gettext("Out of order: %s (%s)")
+                    .replacen("%s", &song_name(uri), 1)
+                    .replacen("%s", uri, 1)
+            );
+        }
+        for song_name in &unresolved {
+            println!(
+                "{}",
+                gettext("Not resolved to a Spotify track: %s").replace("%s", song_name)
+            );
+        }
+        println!(
+            "{}",
+            gettext("%d tracks missing, %d out of order, %d songs not resolved")
+                .replacen("%d", &diff.missing.len().to_string(), 1)
+                .replacen("%d", &diff.out_of_order.len().to_string(), 1)
+                .replacen("%d", &unresolved.len().to_string(), 1)
+        );
+
+        if fix {
+            for change in &diff.changes {
+                playlist.apply_change(change).await?;
+            }
+            info!(
+                "{}",
+                gettext("Fixed the playlist with %d changes")
+                    .replace("%d", &diff.changes.len().to_string())
+            );
+        }
+        Ok(())
+    })
+}
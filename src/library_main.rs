@@ -0,0 +1,363 @@
+use gettextrs::gettext;
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::Cell;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::core::fingerprinting::algorithm::SignatureGenerator;
+use crate::core::fingerprinting::communication::recognize_song_from_signature;
+use crate::core::fingerprinting::local_index::{landmarks_from_file, list_audio_files, LocalIndex};
+use crate::core::fingerprinting::shazam_response::parse_response;
+use crate::core::fingerprinting::signature_format::DecodedSignature;
+use crate::core::http_task::NetworkSettings;
+use crate::core::scan_progress::{ScanProgress, ScanResult};
+use crate::utils::filesystem_operations::obtain_fingerprint_index_path;
+
+/// How long a request waits after the first time Shazam rate-limited it,
+/// doubled at each new refusal
+const INITIAL_RATE_LIMIT_DELAY: Duration = Duration::from_secs(5);
+/// Past this delay, the file is left for the next run of the scan
+const MAX_RATE_LIMIT_DELAY: Duration = Duration::from_secs(320);
+
+/// Load the local fingerprint index, bring it up to date with the contents
+/// of the given directory, and save it back. Returns the index together with
+/// the positions of the files of the directory.
+fn load_and_update_index(directory: &str) -> Result<(LocalIndex, Vec<usize>), Box<dyn Error>> {
+    let index_path = obtain_fingerprint_index_path()?;
+    let mut index = LocalIndex::load(&index_path)?;
+    let files = index.update_directory(Path::new(directory))?;
+    index.save(&index_path)?;
+    Ok((index, files))
+}
+
+/// An element of the JSON output of "dedupe"
+#[derive(Serialize, JsonSchema)]
+pub struct DuplicateGroup {
+    /// From 0 to 1
+    pub similarity: f32,
+    /// The copy worth keeping: lossless first, then the highest bitrate
+    pub keep: PathBuf,
+    pub delete: Vec<PathBuf>,
+}
+
+/// An element of the JSON output of "which-file"
+#[derive(Serialize, JsonSchema)]
+pub struct IndexMatch {
+    pub file: PathBuf,
+    /// Position of the clip in the file
+    pub offset_secs: f32,
+    pub matching_landmarks: usize,
+    pub clip_landmarks: usize,
+}
+
+pub fn index_main(directory: &str) -> Result<(), Box<dyn Error>> {
+    let (index, files) = load_and_update_index(directory)?;
+    info!(
+        "{}",
+        gettext("%d files indexed from this directory, %d files in the index")
+            .replacen("%d", &files.len().to_string(), 1)
+            .replacen("%d", &index.files.len().to_string(), 1)
+    );
+    Ok(())
+}
+
+pub fn dedupe_main(
+    directory: &str,
+    min_similarity: f32,
+    enable_json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let (index, files) = load_and_update_index(directory)?;
+    let groups = index.find_duplicates(&files, min_similarity);
+
+    if enable_json {
+        let groups: Vec<DuplicateGroup> = groups
+            .iter()
+            .map(|group| DuplicateGroup {
+                similarity: group.similarity,
+                keep: index.files[group.files[0]].path.clone(),
+                delete: group.files[1..]
+                    .iter()
+                    .map(|&file| index.files[file].path.clone())
+                    .collect(),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+        return Ok(());
+    }
+
+    if groups.is_empty() {
+        println!("{}", gettext("No duplicate files found"));
+    }
+    for (number, group) in groups.iter().enumerate() {
+        println!(
+            "{}",
+            gettext("Group %d (%s similar):")
+                .replace("%d", &(number + 1).to_string())
+                .replace("%s", &format!("{}%", (group.similarity * 100.0) as u32))
+        );
+        for (position, &file) in group.files.iter().enumerate() {
+            println!(
+                "  {:<8} {}",
+                if position == 0 {
+                    gettext("keep")
+                } else {
+                    gettext("delete")
+                },
+                index.files[file].path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn format_offset(offset_secs: f32) -> String {
+    let total_secs = offset_secs.max(0.0) as u32;
+    format!("{}:{:02}", total_secs / 60, total_secs % 60)
+}
+
+pub fn which_file_main(
+    clip: &str,
+    directory: Option<&str>,
+    min_matching_landmarks: usize,
+    enable_json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let index = match directory {
+        Some(directory) => load_and_update_index(directory)?.0,
+        None => LocalIndex::load(&obtain_fingerprint_index_path()?)?,
+    };
+    if index.files.is_empty() {
+        return Err(gettext(
+            "The local index is empty, please run \"songrec index <directory>\" first",
+        )
+        .into());
+    }
+
+    let (_, query) = landmarks_from_file(Path::new(clip))?;
+    let matches = index.search(&query, None, min_matching_landmarks);
+
+    if enable_json {
+        let matches: Vec<IndexMatch> = matches
+            .iter()
+            .map(|found| IndexMatch {
+                file: index.files[found.file_index].path.clone(),
+                offset_secs: found.offset_secs(),
+                matching_landmarks: found.matching_landmarks,
+                clip_landmarks: query.len(),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&matches)?);
+        return Ok(());
+    }
+
+    if matches.is_empty() {
+        println!("{}", gettext("No indexed file contains this audio"));
+    }
+    for found in &matches {
+        println!(
+            "{}",
+            gettext("%s at %s (%s matching landmarks)")
+                .replacen(
+                    "%s",
+                    &index.files[found.file_index].path.display().to_string(),
+                    1
+                )
+                .replacen("%s", &format_offset(found.offset_secs()), 1)
+                .replacen("%s", &found.matching_landmarks.to_string(), 1)
+        );
+    }
+    Ok(())
+}
+
+impl ScanResult {
+    fn new(file: PathBuf, response: Result<Value, String>) -> Self {
+        let (track, error) = match response {
+            Ok(json) => (parse_response(&json).track, None),
+            Err(error) => (None, Some(error)),
+        };
+        let track = track.unwrap_or_default();
+        ScanResult {
+            file,
+            track_key: track.key,
+            artist: track.subtitle,
+            title: track.title,
+            error,
+        }
+    }
+
+    fn print(&self, enable_json: bool) {
+        if enable_json {
+            println!("{}", serde_json::to_string(self).unwrap());
+            return;
+        }
+        match (&self.error, &self.artist, &self.title) {
+            (Some(error), _, _) => warn!("{}: {}", self.file.display(), error),
+            (None, artist, Some(title)) => println!(
+                "{}: {} - {}",
+                self.file.display(),
+                artist.as_deref().unwrap_or("?"),
+                title
+            ),
+            (None, _, None) => println!(
+                "{}: {}",
+                self.file.display(),
+                gettext("No match for this song")
+            ),
+        }
+    }
+}
+
+/// Recognize all the audio files of a directory with Shazam. Its API takes
+/// a single signature per request, so the files are fingerprinted by
+/// several threads while up to `concurrency` requests are in flight over
+/// the same session, which keeps its connections to Shazam alive (and
+/// multiplexes the requests when HTTP/2 is negotiated) rather than waiting
+/// for each answer in turn. An interrupted scan resumes from its
+/// `ScanProgress`, unless `restart`.
+pub fn scan_main(
+    directory: &str,
+    concurrency: usize,
+    enable_json: bool,
+    restart: bool,
+) -> Result<(), Box<dyn Error>> {
+    let files = list_audio_files(Path::new(directory))?;
+    let progress = ScanProgress::open(Path::new(directory), restart)?;
+
+    let mut pending_files = vec![];
+    for file in files.iter() {
+        match progress.result(file) {
+            Some(result) => result.print(enable_json),
+            None => pending_files.push(file.clone()),
+        }
+    }
+    if pending_files.len() < files.len() {
+        info!(
+            "{}",
+            gettext("Resuming the scan: %d of %d files were already recognized")
+                .replacen("%d", &(files.len() - pending_files.len()).to_string(), 1)
+                .replacen("%d", &files.len().to_string(), 1)
+        );
+    } else {
+        info!(
+            "{}",
+            gettext("%d audio files to recognize").replace("%d", &files.len().to_string())
+        );
+    }
+    let progress = Arc::new(Mutex::new(progress));
+
+    let (signature_tx, signature_rx) =
+        async_channel::bounded::<(PathBuf, Result<DecodedSignature, String>)>(concurrency);
+    let pending_files = Arc::new(Mutex::new(pending_files.into_iter()));
+    let decoders = std::thread::available_parallelism()
+        .map_or(1, |count| count.get())
+        .min(concurrency);
+    for _ in 0..decoders {
+        let pending_files = pending_files.clone();
+        let signature_tx = signature_tx.clone();
+        let progress = progress.clone();
+        std::thread::spawn(move || loop {
+            let Some(file) = pending_files.lock().unwrap().next() else {
+                break;
+            };
+            // Fingerprinted before the scan was interrupted
+            let stored_signature = progress.lock().unwrap().signature(&file);
+            let signature = match stored_signature {
+                Some(signature) => Ok(signature),
+                None => file
+                    .to_str()
+                    .ok_or_else(|| "Invalid file name".to_string())
+                    .and_then(|path| {
+                        SignatureGenerator::make_signature_from_file(path)
+                            .map_err(|error| error.to_string())
+                    })
+                    .inspect(|signature| {
+                        progress.lock().unwrap().record_signature(&file, signature)
+                    }),
+            };
+            if signature_tx.send_blocking((file, signature)).is_err() {
+                break;
+            }
+        });
+    }
+    drop(signature_tx);
+
+    // Soup opens at most 2 connections per host by default, which would
+    // serialize the requests over HTTP/1.1
+    let session = NetworkSettings::default().new_session_with_connections(concurrency as u32)?;
+
+    let main_loop = glib::MainLoop::new(None, false);
+    let running_requests = Rc::new(Cell::new(concurrency));
+    // The files to try again on the next run, after an error of the network
+    // or of the decoding, which may be a file still being copied or a
+    // network share which went away
+    let failed_requests = Rc::new(Cell::new(0));
+    for _ in 0..concurrency {
+        let signature_rx = signature_rx.clone();
+        let session = session.clone();
+        let main_loop = main_loop.clone();
+        let running_requests = running_requests.clone();
+        let failed_requests = failed_requests.clone();
+        let progress = progress.clone();
+        glib::spawn_future_local(async move {
+            while let Ok((file, signature)) = signature_rx.recv().await {
+                let (response, is_final) = match signature {
+                    Ok(signature) => {
+                        let mut delay = INITIAL_RATE_LIMIT_DELAY;
+                        let response = loop {
+                            let response =
+                                recognize_song_from_signature(&session, &signature, None)
+                                    .await
+                                    .map_err(|error| error.to_string());
+                            match response {
+                                Err(ref error)
+                                    if *error == gettext("Your IP has been rate-limited")
+                                        && delay <= MAX_RATE_LIMIT_DELAY =>
+                                {
+                                    warn!(
+                                        "Rate-limited by Shazam, sending {} again in {} seconds",
+                                        file.display(),
+                                        delay.as_secs()
+                                    );
+                                    glib::timeout_future(delay).await;
+                                    delay *= 2;
+                                }
+                                response => break response,
+                            }
+                        };
+                        let is_final = response.is_ok();
+                        (response, is_final)
+                    }
+                    Err(error) => (Err(error), false),
+                };
+                let result = ScanResult::new(file, response);
+                if is_final {
+                    progress.lock().unwrap().record_result(&result);
+                } else {
+                    failed_requests.set(failed_requests.get() + 1);
+                }
+                result.print(enable_json);
+            }
+            running_requests.set(running_requests.get() - 1);
+            if running_requests.get() == 0 {
+                main_loop.quit();
+            }
+        });
+    }
+    main_loop.run();
+
+    if failed_requests.get() == 0 {
+        progress.lock().unwrap().complete();
+    } else {
+        warn!(
+            "{}",
+            gettext("%d files couldn't be decoded or sent to Shazam, run the same scan again to retry them")
+                .replace("%d", &failed_requests.get().to_string())
+        );
+    }
+    Ok(())
+}
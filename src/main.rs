@@ -1,28 +1,65 @@
 #![cfg_attr(feature = "gui", windows_subsystem = "windows")]
 
 pub mod cli_main;
+pub mod doctor_main;
+pub mod history_main;
+pub mod library_main;
+pub mod schema_main;
 
 mod core {
+    pub mod aliases;
+    pub mod crash_report;
     pub mod http_task;
     pub mod logging;
+    pub mod match_corrections;
+    pub mod metadata_cache;
+    pub mod metrics;
     pub mod microphone_thread;
+    pub mod no_match_log;
     pub mod preferences;
+    pub mod preprocessing;
     pub mod processing_thread;
+    pub mod providers;
+    pub mod recognition_locations;
+    pub mod resource_limits;
+    pub mod scan_progress;
+    pub mod session_recording;
+    pub mod stream_monitor;
+    pub mod tasks;
     pub mod thread_messages;
+    pub mod trace_profile;
+    pub mod unknown_clips;
 
-    mod audio_controllers {
+    pub mod audio_controllers {
         pub mod audio_backend;
+        pub mod auto_device;
+        pub mod bluetooth;
+        pub mod capture_session;
+        pub mod clock_drift;
         pub mod cpal;
+        pub mod echo_cancel;
         #[cfg(all(target_os = "linux", feature = "pulse"))]
         pub mod pulseaudio;
+        pub mod realtime;
+        pub mod snapcast;
+        pub mod stream_negotiation;
+        pub mod virtual_capture;
     }
 
+    #[cfg(test)]
+    mod end_to_end_tests;
+
     pub mod fingerprinting {
         pub mod algorithm;
         pub mod communication;
+        mod dsp;
         mod hanning;
+        pub mod local_index;
+        pub mod shazam_response;
+        pub mod signature_cache;
         pub mod signature_format;
-        mod user_agent;
+        pub mod signature_info;
+        pub mod user_agent;
     }
 }
 
@@ -31,42 +68,191 @@ mod gui {
     pub mod main_window;
     pub mod song_history_interface;
 
+    pub mod charts_dialog;
+    pub mod command_palette;
     pub mod context_menu;
+    pub mod export_dialog;
     pub mod history_entry;
+    pub mod kiosk_window;
     pub mod listed_device;
+    pub mod song_details;
+    pub mod song_dialog;
+    pub mod source_switch;
+    pub mod task_manager;
+    pub mod update_checker;
+    pub mod window_state;
 }
 
 mod utils {
+    pub mod chapters;
+    pub mod charts;
+    pub mod cli_translation;
+    pub mod clock;
     pub mod csv_song_history;
+    pub mod file_cache;
     pub mod filesystem_operations;
+    pub mod genres;
+    pub mod history_bundle;
+    pub mod history_check;
+    pub mod history_encryption;
+    pub mod history_export;
+    pub mod history_report;
     pub mod internationalization;
+    #[cfg(feature = "gui")]
+    pub mod loudness;
+    pub mod open_in;
+    pub mod qr_code;
+    #[cfg(feature = "gui")]
+    pub mod recognize_uri;
+    pub mod share_text;
+    pub mod single_instance;
+    pub mod song_link;
+    pub mod tag_diff;
+    pub mod text_normalization;
+    pub mod track_identifiers;
+    pub mod track_stats;
+    pub mod transliteration;
 }
 
 mod plugins {
+    pub mod apple_music;
+    pub mod chromecast;
+    #[cfg(all(target_os = "linux", feature = "dbus"))]
+    pub mod dbus_service;
+    #[cfg(feature = "deezer")]
+    pub mod deezer;
     #[cfg(feature = "ffmpeg")]
     pub mod ffmpeg_wrapper;
+    pub mod jellyfin;
+    #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+    pub mod kdeconnect;
     #[cfg(feature = "gui")]
     #[cfg(target_os = "linux")]
     pub mod ksni;
+    #[cfg(all(target_os = "macos", feature = "gui"))]
+    pub mod macos_menu_bar;
+    pub mod mastodon;
+    pub mod matrix;
     #[cfg(all(target_os = "linux", feature = "mpris"))]
     pub mod mpris_player;
+    #[cfg(feature = "mqtt")]
+    pub mod mqtt;
+    pub mod plex;
+    pub mod prometheus_exporter;
+    #[cfg(feature = "scripting")]
+    pub mod scripting;
+    pub mod service_resolution;
+    pub mod sinks;
+    pub mod speech;
+    #[cfg(all(target_os = "linux", feature = "mpris"))]
+    pub mod spotify_guard;
+    pub mod spotify_playlist;
+    pub mod subsonic;
+    #[cfg(target_os = "linux")]
+    pub mod systemd_notify;
+    pub mod telegram;
+    #[cfg(feature = "tidal")]
+    pub mod tidal;
+    pub mod webhook;
+    #[cfg(all(windows, feature = "windows-service"))]
+    pub mod windows_service;
 }
 
+use crate::core::audio_controllers::capture_session::{CaptureSession, SESSION_DEVICE_PREFIX};
 use crate::core::fingerprinting::algorithm::SignatureGenerator;
 use crate::core::fingerprinting::communication::recognize_song_from_signature;
+use crate::core::fingerprinting::signature_cache;
 use crate::core::fingerprinting::signature_format::DecodedSignature;
+use crate::core::fingerprinting::signature_info::{describe_signature, load_signature};
+use crate::core::fingerprinting::user_agent::{self, ClientIdentity};
 
 use crate::cli_main::{cli_main, CLIOutputType, CLIParameters};
-use crate::core::logging::Logging;
+use crate::core::crash_report;
+use crate::core::http_task::{IpVersion, NetworkSettings};
+use crate::core::logging::{LogFileOptions, LogRotation, Logging};
+use crate::core::preferences::PreferencesInterface;
+use crate::core::providers::ProviderSettings;
+use crate::core::resource_limits::{self, ResourceLimits};
+use crate::core::stream_monitor::{load_streams_config, StreamConfig};
+use crate::core::trace_profile;
+use crate::doctor_main::doctor_main;
 #[cfg(feature = "gui")]
 use crate::gui::main_window::gui_main;
+use crate::history_main::{
+    bundle_main, charts_main, check_main, dedupe_main as history_dedupe_main, encryption_main,
+    export_main, prune_main, report_main, spotify_diff_main, stats_main, HistoryExportOptions,
+};
+use crate::library_main::{dedupe_main, index_main, scan_main, which_file_main};
+use crate::plugins::apple_music::AppleMusicSettings;
+#[cfg(feature = "deezer")]
+use crate::plugins::deezer::DeezerSettings;
+use crate::plugins::jellyfin::JellyfinSettings;
+use crate::plugins::mastodon::{
+    default_hashtags, default_min_interval_secs, default_visibility, MastodonSettings,
+};
+use crate::plugins::matrix::MatrixSettings;
+#[cfg(feature = "mqtt")]
+use crate::plugins::mqtt::MqttSettings;
+use crate::plugins::plex::PlexSettings;
+use crate::plugins::sinks::SinkConfig;
+use crate::plugins::speech::SpeechSettings;
+use crate::plugins::subsonic::SubsonicSettings;
+use crate::plugins::telegram::TelegramSettings;
+#[cfg(feature = "tidal")]
+use crate::plugins::tidal::TidalSettings;
+use crate::plugins::webhook::WebhookSettings;
+#[cfg(all(windows, feature = "windows-service"))]
+use crate::plugins::windows_service;
+use crate::schema_main::schema_main;
+use crate::utils::chapters::{podcast_chapters, read_chapters, write_id3_chapters};
+use crate::utils::cli_translation;
+use crate::utils::clock;
+use crate::utils::csv_song_history::{CsvColumn, DEFAULT_CSV_COLUMNS};
+use crate::utils::filesystem_operations;
+use crate::utils::history_export::ExportFormat;
 use crate::utils::internationalization::setup_internationalization;
+use crate::utils::share_text::default_share_template;
+use crate::utils::single_instance;
+use crate::utils::text_normalization;
+use crate::utils::transliteration::{self, TransliterationMode};
 
-use clap::{command, Arg, ArgAction, Command};
+use clap::{command, Arg, ArgAction, ArgMatches, Command};
 use gettextrs::gettext;
-use log::debug;
+use log::{debug, warn};
 use soup::prelude::SessionExt;
+use std::cell::LazyCell;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// The --echo-cancel option of the subcommands recording the microphone
+macro_rules! echo_cancel_arg {
+    () => {
+        Arg::new("echo-cancel")
+            .long("echo-cancel")
+            .action(ArgAction::SetTrue)
+            .help(gettext("Record from the echo-cancelled copy of the microphone provided by PulseAudio or PipeWire, if any, so that the songs played by this computer don't drown the ones to recognize"))
+    };
+}
+
+/// The --trim-start and --trim-duration options of the subcommands sending a
+/// fingerprint, read by `trim_signature`
+macro_rules! trim_args {
+    () => {
+        [
+            Arg::new("trim-start")
+                .long("trim-start")
+                .value_name("SECONDS")
+                .default_value("0")
+                .value_parser(clap::value_parser!(f32))
+                .help(gettext("Drop the part of the fingerprint located before this position")),
+            Arg::new("trim-duration")
+                .long("trim-duration")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(f32))
+                .help(gettext("Only keep this many seconds of the fingerprint, so that no more information than needed about the recorded audio is sent")),
+        ]
+    };
+}
 
 macro_rules! base_app {
     () => {
@@ -95,6 +281,150 @@ SongRec {version}
 -vv: Set the log level to DEBUG for SongRec-related messages and INFO for library-related messages\n\
 -vvv: Set the log level to TRACE"))
         )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .global(true)
+                .help(gettext("Use a separate history, favorites and preferences, stored under the given profile name"))
+        )
+        .arg(
+            Arg::new("read-only-history")
+                .long("read-only-history")
+                .value_name("PATH")
+                .global(true)
+                .help(gettext("Browse and export the song history stored at this path (a history file, or the SongRec data folder of another computer on a network share) without ever writing to it"))
+        )
+        .arg(
+            Arg::new("timezone")
+                .long("timezone")
+                .value_name("TIME_ZONE")
+                .global(true)
+                .help(gettext("Date the recognitions, reports and logs in this time zone, e.g. \"Europe/Paris\", rather than the one of the system (also read from the SONGREC_TIMEZONE environment variable)"))
+        )
+        .arg(
+            Arg::new("no-signature-cache")
+                .long("no-signature-cache")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help(gettext("Always decode and fingerprint the audio files again, rather than reusing the signatures cached from a previous run on the same contents"))
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .value_name("LANGUAGE")
+                .global(true)
+                .help(gettext("Print the help and the messages in this language, e.g. \"fr\" or \"pt_BR\", rather than the one of the system"))
+        )
+        .arg(
+            Arg::new("no-history")
+                .long("no-history")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help(gettext("Incognito mode: display the recognized songs, but don't write them to the history, forward them to the sinks or cache anything about them on the disk"))
+        )
+        .arg(
+            Arg::new("max-decodes")
+                .long("max-decodes")
+                .value_name("COUNT")
+                .global(true)
+                .value_parser(clap::value_parser!(usize).range(1..))
+                .help(gettext("Decode at most this number of audio files or stream chunks at the same time, e.g. when indexing a library on a small machine"))
+        )
+        .arg(
+            Arg::new("memory-limit")
+                .long("memory-limit")
+                .value_name("MB")
+                .global(true)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help(gettext("Limit the memory used by the decoded audio and by FFMpeg to this number of megabytes; only the beginning of longer files is used"))
+        )
+        .arg(
+            Arg::new("requests-per-minute")
+                .long("requests-per-minute")
+                .value_name("COUNT")
+                .global(true)
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help(gettext("Send at most this number of recognition requests per minute, waiting when needed"))
+        )
+        .arg(
+            Arg::new("transliterate")
+                .long("transliterate")
+                .value_name("MODE")
+                .global(true)
+                .value_parser(["off", "replace", "dual"])
+                .help(gettext("Transliterate the titles, artists and albums written in other scripts into Latin, replacing them (\"Utro\") or appending the transliteration (\"Утро (Utro)\"), in every output and the history"))
+        )
+        .arg(
+            Arg::new("transliterate-scripts")
+                .long("transliterate-scripts")
+                .value_name("SCRIPTS")
+                .global(true)
+                .help(gettext("Transliterate only these scripts, separated by commas, among latin (for the accents), greek, cyrillic, armenian, hebrew, arabic, devanagari, thai, georgian, hangul, kana and han"))
+        )
+        .arg(
+            Arg::new("normalize")
+                .long("normalize")
+                .value_name("RULES")
+                .global(true)
+                .help(gettext("Normalize the titles and artists looked up in other services with these rules, separated by commas: featuring (drop the featured artists), remaster (drop the remaster versions) and case (write the names in capitals in title case)"))
+        )
+        .arg(
+            Arg::new("user-agent")
+                .long("user-agent")
+                .value_name("IDENTITY")
+                .global(true)
+                .help(gettext("User agent of the requests to Shazam: rotate (a different one of the built-in set for each request, the default), fixed (one of them for the whole session), the number of one of them from 1 to 100, or any other string to send as is"))
+        )
+        .arg(
+            Arg::new("profile-out")
+                .long("profile-out")
+                .value_name("PATH")
+                .global(true)
+                .help(gettext("Write a timeline of the audio, signature, HTTP and sink activity to this file, in the Chrome trace format that Perfetto opens, for analyzing performance issues"))
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .value_name("PATH")
+                .global(true)
+                .help(gettext("Also write log messages to the given file"))
+        )
+        .arg(
+            Arg::new("log-file-level")
+                .long("log-file-level")
+                .value_name("LEVEL")
+                .global(true)
+                .value_parser(["error", "warn", "info", "debug", "trace"])
+                .default_value("info")
+                .help(gettext("Log level used for the log file, independently of the standard error output"))
+        )
+        .arg(
+            Arg::new("log-max-size")
+                .long("log-max-size")
+                .value_name("MB")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10")
+                .help(gettext("Rotate the log file once it reaches this size in megabytes (0 to disable)"))
+        )
+        .arg(
+            Arg::new("log-rotate")
+                .long("log-rotate")
+                .global(true)
+                .value_parser(["never", "hourly", "daily"])
+                .default_value("never")
+                .help(gettext("Also rotate the log file at the start of each hour or day"))
+        )
+        .arg(
+            Arg::new("log-keep")
+                .long("log-keep")
+                .value_name("COUNT")
+                .global(true)
+                .value_parser(clap::value_parser!(usize))
+                .default_value("5")
+                .help(gettext("Number of rotated log files to keep"))
+        )
         .subcommand(
             Command::new("listen")
                 .about(gettext("Run as a command-line program listening the microphone and printing recognized songs to stdout, exposing current song info via MPRIS"))
@@ -109,7 +439,14 @@ SongRec {version}
                     Arg::new("audio-device")
                         .short('d')
                         .long("audio-device")
-                        .help(gettext("Specify the audio device to use"))
+                        .help(gettext("Specify the audio device to use, auto to pick the most promising one, snapcast:HOST[:PORT] to record a Snapcast stream, or file:PATH to play a sound file as if it was being recorded (file:PATH?speed=4 plays it four times faster)"))
+                )
+                .arg(echo_cancel_arg!())
+                .arg(
+                    Arg::new("split-channels")
+                        .long("split-channels")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Recognize the left and right channels of the audio device separately, when two sources are panned hard (e.g. the two decks of a DJ monitoring setup), and print the channel of each song"))
                 )
                 .arg(
                     Arg::new("request-interval")
@@ -119,77 +456,1012 @@ SongRec {version}
                         .value_parser(clap::value_parser!(u64))
                         .help(gettext("Shazam interval between requests in seconds (increase if you are rate-limited)"))
                 )
+                .arg(
+                    Arg::new("providers")
+                        .long("providers")
+                        .value_name("PROVIDERS")
+                        .value_delimiter(',')
+                        .value_parser(["shazam", "local"])
+                        .default_value("shazam")
+                        .help(gettext("Comma-separated list of the recognition providers to query, by order of preference: \"shazam\", and \"local\" for the local fingerprint index (see the \"index\" subcommand)"))
+                )
+                .arg(
+                    Arg::new("race-providers")
+                        .long("race-providers")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Query all the providers at the same time and keep the first match, rather than trying them one after the other"))
+                )
+                .arg(
+                    Arg::new("low-data")
+                        .long("low-data")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Reduce network usage: don't download cover art, and send requests at most every 30 seconds. Enabled automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("ignore-metered")
+                        .long("ignore-metered")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("low-data")
+                        .help(gettext("Don't enable the low-data mode automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("qr")
+                        .long("qr")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print a QR code linking to each recognized song to the standard error"))
+                )
+                .arg(
+                    Arg::new("diagnostics")
+                        .long("diagnostics")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the time taken by each stage of the recognition (capture, resampling, FFT, encoding, HTTP, parsing) to the standard error"))
+                )
+                .arg(
+                    Arg::new("share-template")
+                        .long("share-template")
+                        .value_name("TEMPLATE")
+                        .conflicts_with_all(["json", "csv"])
+                        .help(gettext("Print each recognized song with this template, e.g. \"Now playing: {title} by {artist} {link}\" (other placeholders: {album}, {year}, {genre}, {date}, {bandcamp}, {isrc}, {upc})"))
+                )
+                .arg(
+                    Arg::new("script")
+                        .long("script")
+                        .value_name("PATH")
+                        .help(gettext("Run the on_song function of this Rhai script on each recognized song, in order to filter or rewrite it"))
+                )
+                .arg(
+                    Arg::new("ipv4")
+                        .short('4')
+                        .long("ipv4")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("ipv6")
+                        .help(gettext("Only connect to Shazam over IPv4"))
+                )
+                .arg(
+                    Arg::new("ipv6")
+                        .short('6')
+                        .long("ipv6")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Only connect to Shazam over IPv6"))
+                )
+                .arg(
+                    Arg::new("interface")
+                        .long("interface")
+                        .value_name("INTERFACE")
+                        .help(gettext("Send requests from this network interface, e.g. a VPN tunnel"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .conflicts_with("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in JSON"))
+                )
+                .arg(
+                    Arg::new("csv")
+                        .short('c')
+                        .long("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in the CSV format"))
+                )
+                .arg(
+                    Arg::new("csv-columns")
+                        .long("csv-columns")
+                        .value_name("COLUMNS")
+                        .value_delimiter(',')
+                        .value_parser([
+                            "song_name", "title", "artist", "album", "track_key", "release_year",
+                            "genre", "recognition_date", "timestamp", "shazam_url", "spotify_uri",
+                            "source", "bandcamp_url", "isrc", "upc"
+                        ])
+                        .requires("csv")
+                        .help(gettext("Comma-separated list of the columns of the CSV output, in order (default: song_name,album,track_key,release_year,genre,recognition_date)"))
+                )
+                .arg(
+                    Arg::new("no-csv-header")
+                        .long("no-csv-header")
+                        .action(ArgAction::SetTrue)
+                        .requires("csv")
+                        .help(gettext("Don't print a header row before the CSV output"))
+                )
+                .arg(
+                    Arg::new("disable-mpris")
+                        .long("disable-mpris")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Disable MPRIS support"))
+                )
+                .arg(
+                    Arg::new("metrics-address")
+                        .long("metrics-address")
+                        .value_name("ADDRESS:PORT")
+                        .help(gettext("Expose Prometheus metrics over HTTP at the given address (e.g. 127.0.0.1:9898), under /metrics"))
+                )
+                .arg(
+                    Arg::new("watch-chromecast")
+                        .long("watch-chromecast")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Also report the songs played by the Chromecast and Google Cast devices of the local network, as announced by their apps"))
+                )
+                .arg(
+                    Arg::new("ignore-own-spotify-playback")
+                        .long("ignore-own-spotify-playback")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Do not forward recognized songs to MQTT or KDE Connect when they are being played by the local Spotify client (read over MPRIS), to avoid feedback loops"))
+                )
+                .arg(
+                    Arg::new("kdeconnect")
+                        .long("kdeconnect")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Push recognized songs to paired phones through KDE Connect"))
+                )
+                .arg(
+                    Arg::new("kdeconnect-device")
+                        .long("kdeconnect-device")
+                        .value_name("DEVICE")
+                        .requires("kdeconnect")
+                        .help(gettext("Only push songs to the KDE Connect device with this name or identifier"))
+                )
+                .arg(
+                    Arg::new("announce")
+                        .long("announce")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Announce recognized songs aloud (\"Now playing: X by Y\") through speech-dispatcher"))
+                )
+                .arg(
+                    Arg::new("mqtt-broker")
+                        .long("mqtt-broker")
+                        .value_name("HOST[:PORT]")
+                        .help(gettext("Publish recognized songs to the given MQTT broker"))
+                )
+                .arg(
+                    Arg::new("mqtt-username")
+                        .long("mqtt-username")
+                        .requires("mqtt-broker")
+                        .help(gettext("Username used to authenticate to the MQTT broker, with the password read from the SONGREC_MQTT_PASSWORD environment variable"))
+                )
+                .arg(
+                    Arg::new("mqtt-topic")
+                        .long("mqtt-topic")
+                        .default_value("songrec")
+                        .help(gettext("Base MQTT topic under which song information is published"))
+                )
+                .arg(
+                    Arg::new("mqtt-discovery-prefix")
+                        .long("mqtt-discovery-prefix")
+                        .default_value("homeassistant")
+                        .help(gettext("Home Assistant MQTT discovery prefix"))
+                )
+                .arg(
+                    Arg::new("disable-mqtt-discovery")
+                        .long("disable-mqtt-discovery")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Do not publish Home Assistant MQTT discovery messages"))
+                )
+                .arg(
+                    Arg::new("mastodon-instance")
+                        .long("mastodon-instance")
+                        .value_name("URL")
+                        .help(gettext("Post recognized songs to a Mastodon account of this instance, e.g. https://mastodon.social (the access token is read from the SONGREC_MASTODON_TOKEN environment variable, or from the file given with --mastodon-token-file)"))
+                )
+                .arg(
+                    Arg::new("mastodon-token-file")
+                        .long("mastodon-token-file")
+                        .value_name("PATH")
+                        .requires("mastodon-instance")
+                        .help(gettext("File containing the access token of the Mastodon application, with the write:statuses scope"))
+                )
+                .arg(
+                    Arg::new("mastodon-template")
+                        .long("mastodon-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the Mastodon posts, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("mastodon-hashtags")
+                        .long("mastodon-hashtags")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Hashtags appended to the Mastodon posts, which may use placeholders too, e.g. \"#NowPlaying #{genre}\" (#NowPlaying by default)"))
+                )
+                .arg(
+                    Arg::new("mastodon-visibility")
+                        .long("mastodon-visibility")
+                        .value_parser(["public", "unlisted", "private", "direct"])
+                        .help(gettext("Visibility of the Mastodon posts, unlisted by default"))
+                )
+                .arg(
+                    Arg::new("mastodon-interval")
+                        .long("mastodon-interval")
+                        .value_name("SECONDS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help(gettext("Minimum number of seconds between two Mastodon posts, 180 by default"))
+                )
+                .arg(
+                    Arg::new("telegram-chat")
+                        .long("telegram-chat")
+                        .value_name("CHAT_ID")
+                        .help(gettext("Send recognized songs to this Telegram chat, through a bot whose token is read from the SONGREC_TELEGRAM_TOKEN environment variable"))
+                )
+                .arg(
+                    Arg::new("telegram-template")
+                        .long("telegram-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the Telegram messages, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("matrix-room")
+                        .long("matrix-room")
+                        .value_name("ROOM_ID")
+                        .requires("matrix-homeserver")
+                        .help(gettext("Announce recognized songs in this Matrix room, e.g. !abcdef:matrix.org, as the account whose access token is read from the SONGREC_MATRIX_TOKEN environment variable"))
+                )
+                .arg(
+                    Arg::new("matrix-homeserver")
+                        .long("matrix-homeserver")
+                        .value_name("URL")
+                        .requires("matrix-room")
+                        .help(gettext("Homeserver of the Matrix account, e.g. https://matrix.org"))
+                )
+                .arg(
+                    Arg::new("matrix-template")
+                        .long("matrix-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the Matrix messages, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("webhook-url")
+                        .long("webhook-url")
+                        .value_name("URL")
+                        .help(gettext("Post the share text of recognized songs to this webhook, as JSON, e.g. a Slack, Mattermost or Discord incoming webhook"))
+                )
+                .arg(
+                    Arg::new("webhook-template")
+                        .long("webhook-template")
+                        .value_name("TEMPLATE")
+                        .help(gettext("Template of the text posted to the webhook, with the {title}, {artist}, {album}, {year}, {genre}, {date}, {link}, {bandcamp}, {isrc} and {upc} placeholders"))
+                )
+                .arg(
+                    Arg::new("webhook-text-field")
+                        .long("webhook-text-field")
+                        .value_name("FIELD")
+                        .default_value("text")
+                        .requires("webhook-url")
+                        .help(gettext("JSON field of the text posted to the webhook: \"text\" for Slack and Mattermost, \"content\" for Discord"))
+                )
+                .arg(
+                    Arg::new("apple-music-playlist")
+                        .long("apple-music-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .help(gettext("Add recognized songs to this Apple Music library playlist, e.g. p.AbCdEfGh, with the developer and user tokens read from the SONGREC_APPLE_MUSIC_DEVELOPER_TOKEN and SONGREC_APPLE_MUSIC_USER_TOKEN environment variables"))
+                )
+                .arg(
+                    Arg::new("apple-music-library")
+                        .long("apple-music-library")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Add recognized songs to the Apple Music library"))
+                )
+                .arg(
+                    Arg::new("apple-music-storefront")
+                        .long("apple-music-storefront")
+                        .value_name("COUNTRY")
+                        .default_value("us")
+                        .help(gettext("Country code of the Apple Music catalog searched for the songs that Shazam doesn't link to Apple Music"))
+                )
+                .arg(
+                    Arg::new("subsonic-url")
+                        .long("subsonic-url")
+                        .value_name("URL")
+                        .help(gettext("Look recognized songs up on this Subsonic-compatible server (Navidrome, Airsonic...), with the password read from the SONGREC_SUBSONIC_PASSWORD environment variable"))
+                )
+                .arg(
+                    Arg::new("subsonic-user")
+                        .long("subsonic-user")
+                        .requires("subsonic-url")
+                        .help(gettext("User name on the Subsonic server"))
+                )
+                .arg(
+                    Arg::new("subsonic-playlist")
+                        .long("subsonic-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .requires("subsonic-url")
+                        .help(gettext("Add the songs found on the Subsonic server to this playlist"))
+                )
+                .arg(
+                    Arg::new("subsonic-star")
+                        .long("subsonic-star")
+                        .action(ArgAction::SetTrue)
+                        .requires("subsonic-url")
+                        .help(gettext("Star the songs found on the Subsonic server, adding them to the favorites"))
+                )
+                .arg(
+                    Arg::new("plex-url")
+                        .long("plex-url")
+                        .value_name("URL")
+                        .help(gettext("Add recognized songs which are in the music library of this Plex server to a collection, with the token read from the SONGREC_PLEX_TOKEN environment variable"))
+                )
+                .arg(
+                    Arg::new("plex-collection")
+                        .long("plex-collection")
+                        .value_name("NAME")
+                        .default_value("Recognized by SongRec")
+                        .help(gettext("Name of the Plex collection"))
+                )
+                .arg(
+                    Arg::new("jellyfin-url")
+                        .long("jellyfin-url")
+                        .value_name("URL")
+                        .requires("jellyfin-collection")
+                        .help(gettext("Add recognized songs which are in the music library of this Jellyfin server to a collection, with the API key read from the SONGREC_JELLYFIN_API_KEY environment variable"))
+                )
+                .arg(
+                    Arg::new("jellyfin-collection")
+                        .long("jellyfin-collection")
+                        .value_name("COLLECTION_ID")
+                        .requires("jellyfin-url")
+                        .help(gettext("Identifier of the Jellyfin collection"))
+                )
+                .arg(
+                    Arg::new("tidal-playlist")
+                        .long("tidal-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .help(gettext("Add recognized songs to this Tidal playlist, with the access token read from the SONGREC_TIDAL_TOKEN environment variable, with the \"playlists.write\" scope (requires the \"tidal\" compile-time feature)"))
+                )
+                .arg(
+                    Arg::new("tidal-country")
+                        .long("tidal-country")
+                        .value_name("COUNTRY")
+                        .default_value("US")
+                        .help(gettext("Country code of the Tidal catalog to look the songs up in"))
+                )
+                .arg(
+                    Arg::new("deezer-playlist")
+                        .long("deezer-playlist")
+                        .value_name("PLAYLIST_ID")
+                        .help(gettext("Add recognized songs to this Deezer playlist, with the access token read from the SONGREC_DEEZER_TOKEN environment variable, with the \"manage_library\" permission (requires the \"deezer\" compile-time feature)"))
+                )
+                .arg(
+                    Arg::new("sinks-config")
+                        .long("sinks-config")
+                        .value_name("PATH")
+                        .help(gettext("Forward the recognized songs to the sinks listed in this TOML file, instead of the sinks.toml file of the preferences directory"))
+                )
+                .arg(
+                    Arg::new("stream")
+                        .long("stream")
+                        .value_name("TAG=URL")
+                        .action(ArgAction::Append)
+                        .help(gettext("Also recognize the songs of this Icecast or HLS stream, tagged with this name in the history (can be repeated; the microphone is only recorded along with streams when --audio-device is given)"))
+                )
+                .arg(
+                    Arg::new("streams-config")
+                        .long("streams-config")
+                        .value_name("PATH")
+                        .help(gettext("Also recognize the songs of the streams listed in this TOML file, each one possibly with its own sinks"))
+                )
+                .arg(
+                    Arg::new("dump-capture")
+                        .long("dump-capture")
+                        .value_name("DIRECTORY")
+                        .help(gettext("Write the raw captured audio to a session file of this directory, which can be fed again to the recognition with the replay command"))
+                )
+                .arg(
+                    Arg::new("record-session")
+                        .long("record-session")
+                        .value_name("FILE")
+                        .help(gettext("Record the whole session to this Ogg Vorbis file once listening stops, with a chapter at each recognized song"))
+                )
+                .arg(
+                    Arg::new("windows-service")
+                        .long("windows-service")
+                        .hide(true)
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Run as the Windows service installed with \"songrec service install\""))
+                )
+        )
+        .subcommand(
+            Command::new("service")
+                .about(gettext("Install or remove the Windows service running \"songrec listen\" from the system startup, without any logged in user"))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("install")
+                        .about(gettext("Install the service, started with the system, which logs to the Windows event log"))
+                        .arg(
+                            Arg::new("listen-options")
+                                .num_args(0..)
+                                .trailing_var_arg(true)
+                                .allow_hyphen_values(true)
+                                .value_name("LISTEN_OPTIONS")
+                                .help(gettext("Options of the listen subcommand used by the service, after \"--\", e.g. \"-- --audio-device Microphone --telegram-chat 1234\""))
+                        )
+                )
+                .subcommand(
+                    Command::new("uninstall")
+                        .about(gettext("Stop and remove the service"))
+                )
+        )
+        .subcommand(
+            Command::new("recognize")
+                .about(gettext("Recognize one song from a sound file or microphone and print its info."))
+                .arg(
+                    Arg::new("list-devices")
+                        .short('l')
+                        .long("list-devices")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("List available audio devices and quit"))
+                )
+                .arg(
+                    Arg::new("audio-device")
+                        .short('d')
+                        .long("audio-device")
+                        .action(ArgAction::Set)
+                        .help(gettext("Specify the audio device to use, auto to pick the most promising one, snapcast:HOST[:PORT] to record a Snapcast stream, or file:PATH to play a sound file as if it was being recorded (file:PATH?speed=4 plays it four times faster)"))
+                )
+                .arg(echo_cancel_arg!())
+                .arg(
+                    Arg::new("request-interval")
+                        .short('i')
+                        .long("request-interval")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(u64))
+                        .help(gettext("Shazam interval between requests in seconds (increase if you are rate-limited)"))
+                )
+                .arg(
+                    Arg::new("providers")
+                        .long("providers")
+                        .value_name("PROVIDERS")
+                        .value_delimiter(',')
+                        .value_parser(["shazam", "local"])
+                        .default_value("shazam")
+                        .help(gettext("Comma-separated list of the recognition providers to query, by order of preference: \"shazam\", and \"local\" for the local fingerprint index (see the \"index\" subcommand)"))
+                )
+                .arg(
+                    Arg::new("race-providers")
+                        .long("race-providers")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Query all the providers at the same time and keep the first match, rather than trying them one after the other"))
+                )
+                .arg(
+                    Arg::new("low-data")
+                        .long("low-data")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Reduce network usage: don't download cover art, and send requests at most every 30 seconds. Enabled automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("ignore-metered")
+                        .long("ignore-metered")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("low-data")
+                        .help(gettext("Don't enable the low-data mode automatically on metered connections"))
+                )
+                .arg(
+                    Arg::new("qr")
+                        .long("qr")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print a QR code linking to each recognized song to the standard error"))
+                )
+                .arg(
+                    Arg::new("diagnostics")
+                        .long("diagnostics")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the time taken by each stage of the recognition (capture, resampling, FFT, encoding, HTTP, parsing) to the standard error"))
+                )
+                .arg(
+                    Arg::new("share-template")
+                        .long("share-template")
+                        .value_name("TEMPLATE")
+                        .conflicts_with_all(["json", "csv"])
+                        .help(gettext("Print each recognized song with this template, e.g. \"Now playing: {title} by {artist} {link}\" (other placeholders: {album}, {year}, {genre}, {date}, {bandcamp}, {isrc}, {upc})"))
+                )
+                .arg(
+                    Arg::new("script")
+                        .long("script")
+                        .value_name("PATH")
+                        .help(gettext("Run the on_song function of this Rhai script on each recognized song, in order to filter or rewrite it"))
+                )
+                .arg(
+                    Arg::new("ipv4")
+                        .short('4')
+                        .long("ipv4")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("ipv6")
+                        .help(gettext("Only connect to Shazam over IPv4"))
+                )
+                .arg(
+                    Arg::new("ipv6")
+                        .short('6')
+                        .long("ipv6")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Only connect to Shazam over IPv6"))
+                )
+                .arg(
+                    Arg::new("interface")
+                        .long("interface")
+                        .value_name("INTERFACE")
+                        .help(gettext("Send requests from this network interface, e.g. a VPN tunnel"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .conflicts_with("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in JSON"))
+                )
+                .arg(
+                    Arg::new("csv")
+                        .short('c')
+                        .long("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in the CSV format"))
+                )
+                .arg(
+                    Arg::new("csv-columns")
+                        .long("csv-columns")
+                        .value_name("COLUMNS")
+                        .value_delimiter(',')
+                        .value_parser([
+                            "song_name", "title", "artist", "album", "track_key", "release_year",
+                            "genre", "recognition_date", "timestamp", "shazam_url", "spotify_uri",
+                            "source", "bandcamp_url", "isrc", "upc"
+                        ])
+                        .requires("csv")
+                        .help(gettext("Comma-separated list of the columns of the CSV output, in order (default: song_name,album,track_key,release_year,genre,recognition_date)"))
+                )
+                .arg(
+                    Arg::new("no-csv-header")
+                        .long("no-csv-header")
+                        .action(ArgAction::SetTrue)
+                        .requires("csv")
+                        .help(gettext("Don't print a header row before the CSV output"))
+                )
+                .arg(
+                    Arg::new("diff-tags")
+                        .long("diff-tags")
+                        .requires("input_file")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Compare the recognized song with the tags of the input file, and print the fields that differ"))
+                )
+                .arg(
+                    Arg::new("apply")
+                        .long("apply")
+                        .requires("input_file")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Like --diff-tags, but also rewrite the mismatching tags of the input file with the recognized values"))
+                )
+                .arg(
+                    Arg::new("input_file")
+                        .required(false)
+                        .help(gettext("Recognize a file instead of using mic input"))
+                )
+        )
+        .subcommand(
+            Command::new("replay")
+                .about(gettext("Feed a capture session dumped by listen --dump-capture to the recognition again, as it was recorded, and print the recognized songs"))
+                .arg(
+                    Arg::new("diagnostics")
+                        .long("diagnostics")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the time taken by each stage of the recognition (capture, resampling, FFT, encoding, HTTP, parsing) to the standard error"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .conflicts_with("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in JSON"))
+                )
+                .arg(
+                    Arg::new("csv")
+                        .short('c')
+                        .long("csv")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Enable printing full song info in the CSV format"))
+                )
+                .arg(
+                    Arg::new("session")
+                        .required(true)
+                        .help(gettext("The capture session file to replay"))
+                )
+        )
+        .subcommand(
+            Command::new("chapters")
+                .about(gettext("Export the chapters of a session recorded by listen --record-session for the podcast players, as a Podcasting 2.0 chapters file"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help(gettext("Write the chapters file there rather than to the standard output"))
+                )
+                .arg(
+                    Arg::new("mp3")
+                        .long("mp3")
+                        .value_name("FILE")
+                        .help(gettext("Also write the chapters as the ID3v2 chapter frames of this MP3 copy of the recording"))
+                )
+                .arg(
+                    Arg::new("recording")
+                        .required(true)
+                        .help(gettext("The Ogg file recorded with listen --record-session"))
+                )
+        )
+        .subcommand(
+            Command::new("index")
+                .about(gettext("Fingerprint the audio files of a directory into the local index, without contacting Shazam"))
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help(gettext("The directory to scan recursively"))
+                )
+        )
+        .subcommand(
+            Command::new("dedupe")
+                .about(gettext("Find audio files of a directory which contain the same recording, even encoded in different formats or bitrates"))
+                .arg(
+                    Arg::new("min-similarity")
+                        .long("min-similarity")
+                        .value_name("PERCENT")
+                        .default_value("30")
+                        .value_parser(clap::value_parser!(u8).range(1..=100))
+                        .help(gettext("Proportion of the fingerprint which has to match for two files to be considered duplicates"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the groups of duplicate files in JSON"))
+                )
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help(gettext("The directory to scan recursively"))
+                )
+        )
+        .subcommand(
+            Command::new("which-file")
+                .about(gettext("Find which files of the local index contain the audio of a short clip, and at which position"))
+                .arg(
+                    Arg::new("directory")
+                        .long("directory")
+                        .value_name("DIRECTORY")
+                        .help(gettext("Update the local index with the contents of this directory before searching"))
+                )
+                .arg(
+                    Arg::new("min-matches")
+                        .long("min-matches")
+                        .value_name("COUNT")
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(usize))
+                        .help(gettext("Minimal number of matching fingerprint landmarks for a file to be reported"))
+                )
+                .arg(
+                    Arg::new("json")
+                        .short('j')
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Print the matching files in JSON"))
+                )
+                .arg(
+                    Arg::new("clip")
+                        .required(true)
+                        .help(gettext("The audio clip to search for"))
+                )
+        )
+        .subcommand(
+            Command::new("scan")
+                .about(gettext("Recognize all the audio files of a directory with Shazam, sending several requests at the same time"))
+                .arg(
+                    Arg::new("concurrency")
+                        .long("concurrency")
+                        .value_name("COUNT")
+                        .default_value("4")
+                        .value_parser(clap::value_parser!(u8).range(1..=16))
+                        .help(gettext("Number of requests sent to Shazam at the same time, and of files fingerprinted in advance"))
+                )
                 .arg(
                     Arg::new("json")
                         .short('j')
                         .long("json")
-                        .conflicts_with("csv")
                         .action(ArgAction::SetTrue)
-                        .help(gettext("Enable printing full song info in JSON"))
+                        .help(gettext("Print the result of each file as a line of JSON"))
                 )
                 .arg(
-                    Arg::new("csv")
-                        .short('c')
-                        .long("csv")
+                    Arg::new("restart")
+                        .long("restart")
                         .action(ArgAction::SetTrue)
-                        .help(gettext("Enable printing full song info in the CSV format"))
+                        .help(gettext("Start over rather than resuming the interrupted scan of this directory"))
                 )
                 .arg(
-                    Arg::new("disable-mpris")
-                        .long("disable-mpris")
-                        .action(ArgAction::SetTrue)
-                        .help(gettext("Disable MPRIS support"))
+                    Arg::new("directory")
+                        .required(true)
+                        .help(gettext("The directory to scan recursively"))
                 )
         )
         .subcommand(
-            Command::new("recognize")
-                .about(gettext("Recognize one song from a sound file or microphone and print its info."))
+            Command::new("history")
+                .about(gettext("Work with the history of recognized songs"))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about(gettext("Export the song history to CSV, TSV, XLSX, JSON, an HTML page, or an M3U, XSPF or iTunes XML playlist"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Export the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("source")
+                                .long("source")
+                                .value_name("KIND")
+                                .value_parser(["microphone", "monitor", "file"])
+                                .help(gettext("Only keep the songs recognized from this kind of source: \"microphone\", \"monitor\" (the speakers), or \"file\""))
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .value_parser(["csv", "tsv", "xlsx", "itunes", "json", "m3u", "xspf", "html", "gpx"])
+                                .help(gettext("Output format, guessed from the extension of the output file by default (XLSX requires the \"xlsx\" compile-time feature). \"itunes\" produces an iTunes library XML playlist, which can be imported into Apple Music, and \"gpx\" a map of the places where the songs were heard"))
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .value_name("FILE")
+                                .help(gettext("Write to this file rather than to the standard output"))
+                        )
+                        .arg(
+                            Arg::new("columns")
+                                .long("columns")
+                                .value_name("COLUMNS")
+                                .value_delimiter(',')
+                                .value_parser([
+                                    "song_name", "title", "artist", "album", "track_key", "release_year",
+                                    "genre", "recognition_date", "timestamp", "shazam_url", "spotify_uri",
+                                    "source", "bandcamp_url", "isrc", "upc"
+                                ])
+                                .help(gettext("Comma-separated list of the columns to export, in order (default: song_name,album,track_key,release_year,genre,recognition_date)"))
+                        )
+                        .arg(
+                            Arg::new("no-header")
+                                .long("no-header")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Don't write a header row"))
+                        )
+                )
+                .subcommand(
+                    Command::new("bundle")
+                        .about(gettext("Package the songs of a period, their cover art and an HTML page listing them into a ZIP archive"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Bundle the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("source")
+                                .long("source")
+                                .value_name("KIND")
+                                .value_parser(["microphone", "monitor", "file"])
+                                .help(gettext("Only keep the songs recognized from this kind of source: \"microphone\", \"monitor\" (the speakers), or \"file\""))
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .value_name("FILE")
+                                .required(true)
+                                .help(gettext("Path of the ZIP archive to create"))
+                        )
+                        .arg(
+                            Arg::new("range")
+                                .required(true)
+                                .help(gettext("The period to bundle: a day (\"2024-08-17\") or a range (\"2024-08-17 20:00..2024-08-18 04:00\"), either bound of which may be omitted"))
+                        )
+                )
+                .subcommand(
+                    Command::new("dedupe")
+                        .about(gettext("Remove the duplicate entries of the song history, such as a song recognized several times in a row, keeping a backup of the previous file. Close the SongRec window first"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Clean up the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("window")
+                                .long("window")
+                                .value_name("MINUTES")
+                                .default_value("10")
+                                .value_parser(clap::value_parser!(u32))
+                                .help(gettext("Entries of a song recognized again within this many minutes of its previous entry are duplicates"))
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Only list the entries that would be removed"))
+                        )
+                )
+                .subcommand(
+                    Command::new("stats")
+                        .about(gettext("List how many times each song of the history was heard, and when it was first and last heard, the most heard first"))
+                        .arg(
+                            Arg::new("limit")
+                                .long("limit")
+                                .value_name("COUNT")
+                                .value_parser(clap::value_parser!(usize))
+                                .help(gettext("Only list this many songs"))
+                        )
+                        .arg(
+                            Arg::new("json")
+                                .short('j')
+                                .long("json")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Print the statistics in JSON"))
+                        )
+                )
+                .subcommand(
+                    Command::new("prune")
+                        .about(gettext("Remove the oldest entries of the song history according to the retention limits of the preferences, which the GUI also enforces at startup. Close the SongRec window first"))
+                        .arg(
+                            Arg::new("max-entries")
+                                .long("max-entries")
+                                .value_name("COUNT")
+                                .value_parser(clap::value_parser!(u32))
+                                .help(gettext("Keep this many entries at most, rather than the number set in the preferences (0 for no limit)"))
+                        )
+                        .arg(
+                            Arg::new("max-months")
+                                .long("max-months")
+                                .value_name("MONTHS")
+                                .value_parser(clap::value_parser!(u32))
+                                .help(gettext("Keep the entries of this many months at most, rather than the number set in the preferences (0 for no limit)"))
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Only count the entries that would be removed"))
+                        )
+                )
+                .subcommand(
+                    Command::new("encrypt")
+                        .about(gettext("Encrypt the song history, favorites and deleted songs from now on, with a key stored in the keyring of the session (requires the \"encryption\" compile-time feature). Close the SongRec window first"))
+                )
+                .subcommand(
+                    Command::new("decrypt")
+                        .about(gettext("Store the song history, favorites and deleted songs unencrypted again. Close the SongRec window first"))
+                )
+                .subcommand(
+                    Command::new("check")
+                        .about(gettext("Look for the rows of the song history that SongRec can't read, such as invalid text, extra columns or unknown date formats"))
+                        .arg(
+                            Arg::new("favorites")
+                                .long("favorites")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Check the favorites rather than the whole history"))
+                        )
+                        .arg(
+                            Arg::new("fix")
+                                .long("fix")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Repair the rows that can be, and move the others to a \".rejected\" file next to the history, keeping a backup of the previous file. Close the SongRec window first"))
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("spotify")
+                .about(gettext("Work with a Spotify playlist collecting the recognized songs"))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("diff")
+                        .about(gettext("Compare the song history with a Spotify playlist, listing the recognized tracks missing from it, the ones that couldn't be found on Spotify and the ones out of order, with the access token of the SPOTIFY_ACCESS_TOKEN environment variable, which needs the \"playlist-read-private\" and \"playlist-modify-private\" or \"playlist-modify-public\" scopes"))
+                        .arg(
+                            Arg::new("playlist")
+                                .long("playlist")
+                                .value_name("PLAYLIST")
+                                .required(true)
+                                .help(gettext("The playlist, as a link, a \"spotify:playlist:\" URI or an identifier"))
+                        )
+                        .arg(
+                            Arg::new("fix")
+                                .long("fix")
+                                .action(ArgAction::SetTrue)
+                                .help(gettext("Add the missing tracks to the playlist and move the ones out of order"))
+                        )
+                        .arg(
+                            Arg::new("range")
+                                .help(gettext("Only compare the songs of a period: a day (\"2024-08-17\") or a range (\"2024-08-17 20:00..2024-08-18 04:00\"), either bound of which may be omitted"))
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("report")
+                .about(gettext("Generate a digest of the songs recognized for the first time during the last day, week or month, in Markdown or HTML"))
                 .arg(
-                    Arg::new("list-devices")
-                        .short('l')
-                        .long("list-devices")
-                        .action(ArgAction::SetTrue)
-                        .help(gettext("List available audio devices and quit"))
+                    Arg::new("period")
+                        .long("period")
+                        .value_name("PERIOD")
+                        .value_parser(["day", "week", "month"])
+                        .default_value("week")
+                        .help(gettext("The period ending now to report on: \"day\", \"week\" or \"month\" (30 days)"))
                 )
                 .arg(
-                    Arg::new("audio-device")
-                        .short('d')
-                        .long("audio-device")
-                        .action(ArgAction::Set)
-                        .help(gettext("Specify the audio device to use"))
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(["markdown", "html"])
+                        .default_value("markdown")
+                        .help(gettext("Output format"))
                 )
                 .arg(
-                    Arg::new("request-interval")
-                        .short('i')
-                        .long("request-interval")
-                        .default_value("10")
-                        .value_parser(clap::value_parser!(u64))
-                        .help(gettext("Shazam interval between requests in seconds (increase if you are rate-limited)"))
+                    Arg::new("mail")
+                        .long("mail")
+                        .value_name("ADDRESS")
+                        .help(gettext("Precede the report with the headers of an email to this address, to pipe it to \"sendmail -t\""))
                 )
                 .arg(
-                    Arg::new("json")
-                        .short('j')
-                        .long("json")
-                        .conflicts_with("csv")
-                        .action(ArgAction::SetTrue)
-                        .help(gettext("Enable printing full song info in JSON"))
+                    Arg::new("mail-from")
+                        .long("mail-from")
+                        .value_name("ADDRESS")
+                        .requires("mail")
+                        .help(gettext("The sender of the email, rather than the address it is sent to"))
                 )
                 .arg(
-                    Arg::new("csv")
-                        .short('c')
-                        .long("csv")
-                        .action(ArgAction::SetTrue)
-                        .help(gettext("Enable printing full song info in the CSV format"))
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help(gettext("Write to this file rather than to the standard output"))
                 )
+        )
+        .subcommand(
+            Command::new("charts")
+                .about(gettext("Rank the songs and artists most heard during the last week or month, with their positions during the period before"))
                 .arg(
-                    Arg::new("input_file")
-                        .required(false)
-                        .help(gettext("Recognize a file instead of using mic input"))
+                    Arg::new("period")
+                        .long("period")
+                        .value_name("PERIOD")
+                        .value_parser(["week", "month"])
+                        .default_value("week")
+                        .help(gettext("The period ending now to rank: \"week\" or \"month\" (30 days)"))
                 )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(["markdown", "json"])
+                        .default_value("markdown")
+                        .help(gettext("Output format"))
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("COUNT")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(usize))
+                        .help(gettext("How many songs and artists to rank"))
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help(gettext("Write to this file rather than to the standard output"))
+                )
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about(gettext("Check the audio devices, the access to the recognition service, the Spotify access token of the SPOTIFY_ACCESS_TOKEN environment variable, the configuration files and the history, and tell how to fix the problems found"))
         )
         .subcommand(
             Command::new("audio-file-to-recognized-song")
                 .about(gettext("Generate a Shazam fingerprint from a sound file, perform song recognition towards Shazam's servers and print obtained information to the standard output."))
+                .args(trim_args!())
                 .arg(
                     Arg::new("input_file")
                         .required(true)
@@ -210,8 +1482,9 @@ SongRec {version}
                     Arg::new("audio-device")
                         .short('d')
                         .long("audio-device")
-                        .help(gettext("Specify the audio device to use"))
+                        .help(gettext("Specify the audio device to use, auto to pick the most promising one, snapcast:HOST[:PORT] to record a Snapcast stream, or file:PATH to play a sound file as if it was being recorded (file:PATH?speed=4 plays it four times faster)"))
                 )
+                .arg(echo_cancel_arg!())
                 .arg(
                     Arg::new("request-interval")
                         .short('i')
@@ -224,21 +1497,43 @@ SongRec {version}
         .subcommand(
             Command::new("audio-file-to-fingerprint")
                 .about(gettext("Generate a Shazam fingerprint from a sound file, and print it to the standard output."))
+                .args(trim_args!())
                 .arg(
                     Arg::new("input_file")
                         .required(true)
                         .help(gettext("The .WAV or .MP3 file to generate an audio fingerprint for."))
                 )
         )
+        .subcommand(
+            Command::new("fingerprint-info")
+                .about(gettext("Decode a Shazam fingerprint and print its sample rate, duration, frequency peak statistics and a heatmap of its peaks."))
+                .arg(
+                    Arg::new("fingerprint")
+                        .required(true)
+                        .help(gettext("A data-URI Shazam fingerprint, or a file containing a fingerprint (as a data URI or in binary form), or an audio file to fingerprint."))
+                )
+        )
         .subcommand(
             Command::new("fingerprint-to-recognized-song")
                 .about(gettext("Take a data-URI Shazam fingerprint, perform song recognition towards Shazam's servers and print obtained information to the standard output."))
+                .args(trim_args!())
                 .arg(
                     Arg::new("fingerprint")
                         .required(true)
                         .help(gettext("The data-URI Shazam fingerprint to recognize."))
                 )
         )
+        .subcommand(
+            Command::new("schema")
+                .about(gettext("Print the JSON schema of the machine-readable output of a subcommand, e.g. \"songrec schema history export\", to validate it or generate code from it"))
+                .arg(
+                    Arg::new("subcommand")
+                        .required(true)
+                        .num_args(1..)
+                        .value_name("SUBCOMMAND")
+                        .help(gettext("The subcommand, among listen, recognize, replay, microphone-to-recognized-song, audio-file-to-recognized-song, fingerprint-to-recognized-song, dedupe, which-file, scan, charts, history export and history stats"))
+                )
+        )
     };
 }
 
@@ -252,7 +1547,7 @@ macro_rules! gui_app {
                 .arg(
                     Arg::new("input_file")
                         .required(false)
-                        .help(gettext("An optional audio file to recognize on the launch of the application."))
+                        .help(gettext("An optional audio file to recognize on the launch of the application, or a songrec://recognize?path=FILE URI. When SongRec is already running, the file is recognized by the running instance."))
                 )
                 .arg(
                     Arg::new("disable-mpris")
@@ -266,6 +1561,12 @@ macro_rules! gui_app {
                         .action(ArgAction::SetTrue)
                         .help(gettext("Disable PipeWire native support"))
                 )
+                .arg(
+                    Arg::new("kiosk")
+                        .long("kiosk")
+                        .action(ArgAction::SetTrue)
+                        .help(gettext("Show the recognized songs fullscreen, with their cover art and a QR code linking to them"))
+                )
         )
         .subcommand(
             Command::new("gui-norecording")
@@ -273,7 +1574,7 @@ macro_rules! gui_app {
                 .arg(
                     Arg::new("input_file")
                         .required(false)
-                        .help(gettext("An optional audio file to recognize on the launch of the application."))
+                        .help(gettext("An optional audio file to recognize on the launch of the application, or a songrec://recognize?path=FILE URI. When SongRec is already running, the file is recognized by the running instance."))
                 )
                 .arg(
                     Arg::new("disable-mpris")
@@ -305,28 +1606,453 @@ macro_rules! app {
     };
 }
 
+fn csv_columns(subcommand_args: &ArgMatches) -> Vec<CsvColumn> {
+    match subcommand_args.get_many::<String>("csv-columns") {
+        Some(columns) => columns.map(|column| column.parse().unwrap()).collect(),
+        None => DEFAULT_CSV_COLUMNS.to_vec(),
+    }
+}
+
+fn stream_configs(subcommand_args: &ArgMatches) -> Result<Vec<StreamConfig>, Box<dyn Error>> {
+    let mut streams = match subcommand_args.get_one::<String>("streams-config") {
+        Some(path) => load_streams_config(Path::new(path))?,
+        None => vec![],
+    };
+    for argument in subcommand_args
+        .get_many::<String>("stream")
+        .into_iter()
+        .flatten()
+    {
+        streams.push(StreamConfig::parse_argument(argument)?);
+    }
+    for (index, stream) in streams.iter().enumerate() {
+        if streams[..index].iter().any(|other| other.tag == stream.tag) {
+            return Err(gettext("Several streams are tagged %s")
+                .replace("%s", &stream.tag)
+                .into());
+        }
+    }
+    Ok(streams)
+}
+
+/// The sinks enabled from the command line, the ones of the sinks
+/// configuration file are loaded by cli_main()
+fn sink_configs(subcommand_args: &ArgMatches) -> Result<Vec<SinkConfig>, Box<dyn Error>> {
+    let mut sinks: Vec<SinkConfig> = vec![];
+
+    #[cfg(not(all(target_os = "linux", feature = "kdeconnect")))]
+    if subcommand_args.get_flag("kdeconnect") {
+        return Err(gettext("SongRec was compiled without KDE Connect support").into());
+    }
+    #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+    if subcommand_args.get_flag("kdeconnect") {
+        sinks.push(SinkConfig::KdeConnect {
+            device: subcommand_args
+                .get_one::<String>("kdeconnect-device")
+                .cloned(),
+        });
+    }
+
+    if subcommand_args.get_flag("announce") {
+        sinks.push(SinkConfig::Speech(SpeechSettings::default()));
+    }
+
+    #[cfg(not(feature = "mqtt"))]
+    if subcommand_args.contains_id("mqtt-broker") {
+        return Err(gettext("SongRec was compiled without MQTT support").into());
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = subcommand_args.get_one::<String>("mqtt-broker") {
+        let (host, port) = MqttSettings::parse_broker(broker);
+        sinks.push(SinkConfig::Mqtt(MqttSettings {
+            host,
+            port,
+            username: subcommand_args.get_one::<String>("mqtt-username").cloned(),
+            password: std::env::var("SONGREC_MQTT_PASSWORD").ok(),
+            base_topic: subcommand_args
+                .get_one::<String>("mqtt-topic")
+                .unwrap()
+                .clone(),
+            discovery_prefix: if subcommand_args.get_flag("disable-mqtt-discovery") {
+                None
+            } else {
+                subcommand_args
+                    .get_one::<String>("mqtt-discovery-prefix")
+                    .cloned()
+            },
+        }));
+    }
+
+    if let Some(instance_url) = subcommand_args.get_one::<String>("mastodon-instance") {
+        sinks.push(SinkConfig::Mastodon(MastodonSettings {
+            instance_url: instance_url.clone(),
+            access_token: match subcommand_args.get_one::<String>("mastodon-token-file") {
+                Some(path) => std::fs::read_to_string(path)?.trim().to_string(),
+                None => std::env::var("SONGREC_MASTODON_TOKEN")
+                    .map_err(|_| gettext("No Mastodon access token was given"))?,
+            },
+            template: subcommand_args
+                .get_one::<String>("mastodon-template")
+                .cloned()
+                .unwrap_or_else(default_share_template),
+            hashtags: subcommand_args
+                .get_one::<String>("mastodon-hashtags")
+                .cloned()
+                .unwrap_or_else(default_hashtags),
+            visibility: subcommand_args
+                .get_one::<String>("mastodon-visibility")
+                .cloned()
+                .unwrap_or_else(default_visibility),
+            min_interval_secs: subcommand_args
+                .get_one::<u64>("mastodon-interval")
+                .copied()
+                .unwrap_or_else(default_min_interval_secs),
+        }));
+    }
+
+    if let Some(chat_id) = subcommand_args.get_one::<String>("telegram-chat") {
+        sinks.push(SinkConfig::Telegram(TelegramSettings {
+            bot_token: std::env::var("SONGREC_TELEGRAM_TOKEN")
+                .map_err(|_| gettext("No Telegram bot token was given"))?,
+            chat_id: chat_id.clone(),
+            template: subcommand_args
+                .get_one::<String>("telegram-template")
+                .cloned()
+                .unwrap_or_else(default_share_template),
+        }));
+    }
+
+    if let Some(room_id) = subcommand_args.get_one::<String>("matrix-room") {
+        sinks.push(SinkConfig::Matrix(MatrixSettings {
+            homeserver_url: subcommand_args
+                .get_one::<String>("matrix-homeserver")
+                .unwrap()
+                .clone(),
+            access_token: std::env::var("SONGREC_MATRIX_TOKEN")
+                .map_err(|_| gettext("No Matrix access token was given"))?,
+            room_id: room_id.clone(),
+            template: subcommand_args
+                .get_one::<String>("matrix-template")
+                .cloned()
+                .unwrap_or_else(default_share_template),
+        }));
+    }
+
+    if let Some(url) = subcommand_args.get_one::<String>("webhook-url") {
+        sinks.push(SinkConfig::Webhook(WebhookSettings {
+            url: url.clone(),
+            template: subcommand_args
+                .get_one::<String>("webhook-template")
+                .cloned()
+                .unwrap_or_else(default_share_template),
+            text_field: subcommand_args
+                .get_one::<String>("webhook-text-field")
+                .unwrap()
+                .clone(),
+        }));
+    }
+
+    if subcommand_args.contains_id("apple-music-playlist")
+        || subcommand_args.get_flag("apple-music-library")
+    {
+        sinks.push(SinkConfig::AppleMusic(AppleMusicSettings {
+            developer_token: std::env::var("SONGREC_APPLE_MUSIC_DEVELOPER_TOKEN")
+                .map_err(|_| gettext("No Apple Music developer token was given"))?,
+            user_token: std::env::var("SONGREC_APPLE_MUSIC_USER_TOKEN")
+                .map_err(|_| gettext("No Apple Music user token was given"))?,
+            storefront: subcommand_args
+                .get_one::<String>("apple-music-storefront")
+                .unwrap()
+                .clone(),
+            playlist: subcommand_args
+                .get_one::<String>("apple-music-playlist")
+                .cloned(),
+            add_to_library: subcommand_args.get_flag("apple-music-library"),
+        }));
+    }
+
+    if let Some(url) = subcommand_args.get_one::<String>("subsonic-url") {
+        if !subcommand_args.contains_id("subsonic-playlist")
+            && !subcommand_args.get_flag("subsonic-star")
+        {
+            return Err(gettext("Give --subsonic-playlist and/or --subsonic-star").into());
+        }
+        sinks.push(SinkConfig::Subsonic(SubsonicSettings {
+            url: url.clone(),
+            username: subcommand_args
+                .get_one::<String>("subsonic-user")
+                .cloned()
+                .ok_or_else(|| gettext("No Subsonic user name was given"))?,
+            password: std::env::var("SONGREC_SUBSONIC_PASSWORD")
+                .map_err(|_| gettext("No Subsonic password was given"))?,
+            playlist: subcommand_args
+                .get_one::<String>("subsonic-playlist")
+                .cloned(),
+            star: subcommand_args.get_flag("subsonic-star"),
+        }));
+    }
+
+    if let Some(url) = subcommand_args.get_one::<String>("plex-url") {
+        sinks.push(SinkConfig::Plex(PlexSettings {
+            url: url.clone(),
+            token: std::env::var("SONGREC_PLEX_TOKEN")
+                .map_err(|_| gettext("No Plex token was given"))?,
+            collection: subcommand_args
+                .get_one::<String>("plex-collection")
+                .unwrap()
+                .clone(),
+        }));
+    }
+
+    if let Some(url) = subcommand_args.get_one::<String>("jellyfin-url") {
+        sinks.push(SinkConfig::Jellyfin(JellyfinSettings {
+            url: url.clone(),
+            api_key: std::env::var("SONGREC_JELLYFIN_API_KEY")
+                .map_err(|_| gettext("No Jellyfin API key was given"))?,
+            collection: subcommand_args
+                .get_one::<String>("jellyfin-collection")
+                .unwrap()
+                .clone(),
+        }));
+    }
+
+    #[cfg(not(feature = "tidal"))]
+    if subcommand_args.contains_id("tidal-playlist") {
+        return Err(gettext("SongRec was compiled without Tidal support").into());
+    }
+    #[cfg(feature = "tidal")]
+    if let Some(playlist) = subcommand_args.get_one::<String>("tidal-playlist") {
+        sinks.push(SinkConfig::Tidal(TidalSettings {
+            access_token: std::env::var("SONGREC_TIDAL_TOKEN")
+                .map_err(|_| gettext("No Tidal access token was given"))?,
+            playlist: playlist.clone(),
+            country_code: subcommand_args
+                .get_one::<String>("tidal-country")
+                .unwrap()
+                .clone(),
+        }));
+    }
+
+    #[cfg(not(feature = "deezer"))]
+    if subcommand_args.contains_id("deezer-playlist") {
+        return Err(gettext("SongRec was compiled without Deezer support").into());
+    }
+    #[cfg(feature = "deezer")]
+    if let Some(playlist) = subcommand_args.get_one::<String>("deezer-playlist") {
+        sinks.push(SinkConfig::Deezer(DeezerSettings {
+            access_token: std::env::var("SONGREC_DEEZER_TOKEN")
+                .map_err(|_| gettext("No Deezer access token was given"))?,
+            playlist: playlist.clone(),
+        }));
+    }
+
+    Ok(sinks)
+}
+
+#[cfg(feature = "scripting")]
+fn recognition_script(subcommand_args: &ArgMatches) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    Ok(subcommand_args
+        .get_one::<String>("script")
+        .map(PathBuf::from))
+}
+
+#[cfg(not(feature = "scripting"))]
+fn recognition_script(subcommand_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if subcommand_args.contains_id("script") {
+        return Err(gettext("SongRec was compiled without scripting support").into());
+    }
+    Ok(())
+}
+
+fn network_settings(subcommand_args: &ArgMatches) -> NetworkSettings {
+    NetworkSettings {
+        low_data: subcommand_args.get_flag("low-data"),
+        ip_version: if subcommand_args.get_flag("ipv4") {
+            Some(IpVersion::V4)
+        } else if subcommand_args.get_flag("ipv6") {
+            Some(IpVersion::V6)
+        } else {
+            None
+        },
+        interface: subcommand_args.get_one::<String>("interface").cloned(),
+        shazam_endpoint: None,
+    }
+}
+
+/// Parse the --providers and --race-providers options of a subcommand
+fn provider_settings(subcommand_args: &ArgMatches) -> ProviderSettings {
+    ProviderSettings {
+        providers: subcommand_args
+            .get_many::<String>("providers")
+            .unwrap()
+            .map(|name| name.parse().unwrap())
+            .collect(),
+        race: subcommand_args.get_flag("race-providers"),
+    }
+}
+
+/// Apply the --trim-start and --trim-duration options of a subcommand
+fn trim_signature(signature: DecodedSignature, subcommand_args: &ArgMatches) -> DecodedSignature {
+    let trim_start = *subcommand_args.get_one::<f32>("trim-start").unwrap();
+    let trim_duration = subcommand_args.get_one::<f32>("trim-duration").copied();
+
+    if trim_start > 0.0 || trim_duration.is_some() {
+        signature.trimmed(trim_start, trim_duration)
+    } else {
+        signature
+    }
+}
+
+/// A setting given on the command line, where an invalid value is an
+/// error, or else in the preferences, where it is ignored with a warning
+/// so that a bad preference doesn't stop every command
+fn parse_setting<T>(
+    argument: Option<&String>,
+    preference: impl FnOnce() -> Option<String>,
+    default: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<T, Box<dyn Error>> {
+    if let Some(argument) = argument {
+        return Ok(parse(argument)?);
+    }
+    if let Some(preference) = preference() {
+        match parse(&preference) {
+            Ok(value) => return Ok(value),
+            Err(error) => warn!("Ignoring the preference: {}", error),
+        }
+    }
+    Ok(parse(default)?)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    crash_report::install_panic_hook();
+
     // Set up the translation/internationalization part
 
-    let i18n_folder = setup_internationalization();
+    let (i18n_folder, i18n_warnings) =
+        setup_internationalization(cli_translation::requested_language().as_deref());
 
     // TODO simplify the code in this module etc. path handling ^
 
     // Collect the program arguments
 
-    let args = app!().get_matches();
+    let args = cli_translation::get_matches(cli_translation::translate_command(app!()));
 
     // Set up logging
 
+    let log_file = args
+        .get_one::<String>("log-file")
+        .map(|path| LogFileOptions {
+            path: path.into(),
+            level: args
+                .get_one::<String>("log-file-level")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            max_size_bytes: match *args.get_one::<u64>("log-max-size").unwrap() {
+                0 => None,
+                megabytes => Some(megabytes * 1024 * 1024),
+            },
+            rotation: match args.get_one::<String>("log-rotate").unwrap().as_str() {
+                "hourly" => LogRotation::Hourly,
+                "daily" => LogRotation::Daily,
+                _ => LogRotation::Never,
+            },
+            keep_files: *args.get_one::<usize>("log-keep").unwrap(),
+        });
+
+    #[cfg(all(windows, feature = "windows-service"))]
+    windows_service::set_running_as_service(
+        args.subcommand_matches("listen")
+            .is_some_and(|listen_args| listen_args.get_flag("windows-service")),
+    );
+
     let log_object: Logging = match args.get_count("verbose") {
-        0 => Logging::setup_logging(log::LevelFilter::Warn, log::LevelFilter::Info),
-        1 => Logging::setup_logging(log::LevelFilter::Warn, log::LevelFilter::Debug),
-        2 => Logging::setup_logging(log::LevelFilter::Info, log::LevelFilter::Debug),
-        _ => Logging::setup_logging(log::LevelFilter::Trace, log::LevelFilter::Trace),
+        0 => Logging::setup_logging(log::LevelFilter::Warn, log::LevelFilter::Info, log_file),
+        1 => Logging::setup_logging(log::LevelFilter::Warn, log::LevelFilter::Debug, log_file),
+        2 => Logging::setup_logging(log::LevelFilter::Info, log::LevelFilter::Debug, log_file),
+        _ => Logging::setup_logging(log::LevelFilter::Trace, log::LevelFilter::Trace, log_file),
     };
 
     Logging::bind_glib_logging();
 
+    if let Some(path) = args.get_one::<String>("profile-out") {
+        trace_profile::start(Path::new(path))?;
+        info!("Writing the performance timeline to {}", path);
+    }
+
+    filesystem_operations::set_profile(
+        args.get_one::<String>("profile").cloned(),
+        matches!(args.subcommand_name(), Some("gui") | None),
+    )?;
+    filesystem_operations::set_incognito(args.get_flag("no-history"));
+    signature_cache::set_signature_cache(!args.get_flag("no-signature-cache"));
+    if let Some(path) = args.get_one::<String>("read-only-history") {
+        filesystem_operations::set_read_only_history(PathBuf::from(path))?;
+    }
+    // Only read when an option isn't given on the command line
+    let preferences = LazyCell::new(|| PreferencesInterface::new().preferences);
+    resource_limits::set_resource_limits(ResourceLimits {
+        max_decodes: args
+            .get_one::<usize>("max-decodes")
+            .copied()
+            .or_else(|| preferences.max_decodes),
+        memory_limit_mb: args
+            .get_one::<u64>("memory-limit")
+            .copied()
+            .or_else(|| preferences.memory_limit_mb),
+        requests_per_minute: args
+            .get_one::<u32>("requests-per-minute")
+            .copied()
+            .or_else(|| preferences.requests_per_minute),
+    });
+    // An invalid time zone is an error on the command line only, so that a
+    // bad preference doesn't stop every command
+    let time_zone = match args.get_one::<String>("timezone") {
+        Some(time_zone) => Some(clock::parse_time_zone(time_zone)?),
+        None => std::env::var("SONGREC_TIMEZONE")
+            .ok()
+            .or_else(|| preferences.time_zone.clone())
+            .filter(|time_zone| !time_zone.trim().is_empty())
+            .and_then(|time_zone| match clock::parse_time_zone(&time_zone) {
+                Ok(time_zone) => Some(time_zone),
+                Err(error) => {
+                    warn!("{}, using the time zone of the system", error);
+                    None
+                }
+            }),
+    };
+    clock::set_time_zone(time_zone);
+    transliteration::set_transliteration(
+        parse_setting(
+            args.get_one::<String>("transliterate"),
+            || preferences.transliteration.clone(),
+            "off",
+            TransliterationMode::parse,
+        )?,
+        parse_setting(
+            args.get_one::<String>("transliterate-scripts"),
+            || preferences.transliterated_scripts.clone(),
+            "",
+            transliteration::parse_scripts,
+        )?,
+    );
+    text_normalization::set_normalization_rules(parse_setting(
+        args.get_one::<String>("normalize"),
+        || preferences.normalize.clone(),
+        "",
+        text_normalization::parse_rules,
+    )?);
+    user_agent::set_client_identity(parse_setting(
+        args.get_one::<String>("user-agent"),
+        || preferences.user_agent.clone(),
+        "rotate",
+        ClientIdentity::parse,
+    )?);
+
+    for warning in i18n_warnings {
+        warn!("{}", warning);
+    }
     match i18n_folder {
         Some(path) => {
             debug!("Translations folder found at: {}", path.to_str().unwrap());
@@ -359,10 +2085,11 @@ fn main() -> Result<(), Box<dyn Error>> {
             let session = soup::Session::new();
             session.set_timeout(20);
 
-            let input_file_string = subcommand_args
-                .get_one::<String>("input_file")
-                .unwrap()
-                .clone();
+            let input_file_string = subcommand_args.get_one::<String>("input_file").unwrap();
+            let signature = trim_signature(
+                SignatureGenerator::make_signature_from_file(input_file_string)?,
+                subcommand_args,
+            );
 
             let main_loop = glib::MainLoop::new(None, false);
             let main_loop_inner = main_loop.clone();
@@ -370,13 +2097,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                 println!(
                     "{}",
                     serde_json::to_string_pretty(
-                        &recognize_song_from_signature(
-                            &session,
-                            &SignatureGenerator::make_signature_from_file(&input_file_string)
-                                .unwrap()
-                        )
-                        .await
-                        .unwrap()
+                        &recognize_song_from_signature(&session, &signature, None)
+                            .await
+                            .unwrap()
                     )
                     .unwrap()
                 );
@@ -384,6 +2107,188 @@ fn main() -> Result<(), Box<dyn Error>> {
             });
             main_loop.run();
         }
+        Some("schema") => {
+            let subcommand_args = args.subcommand_matches("schema").unwrap();
+            let subcommand: Vec<String> = subcommand_args
+                .get_many::<String>("subcommand")
+                .unwrap()
+                .cloned()
+                .collect();
+            schema_main(&subcommand)?;
+        }
+        Some("index") => {
+            let subcommand_args = args.subcommand_matches("index").unwrap();
+            index_main(subcommand_args.get_one::<String>("directory").unwrap())?;
+        }
+        Some("dedupe") => {
+            let subcommand_args = args.subcommand_matches("dedupe").unwrap();
+            dedupe_main(
+                subcommand_args.get_one::<String>("directory").unwrap(),
+                *subcommand_args.get_one::<u8>("min-similarity").unwrap() as f32 / 100.0,
+                subcommand_args.get_flag("json"),
+            )?;
+        }
+        Some("chapters") => {
+            let subcommand_args = args.subcommand_matches("chapters").unwrap();
+            let (chapters, duration_secs) = read_chapters(Path::new(
+                subcommand_args.get_one::<String>("recording").unwrap(),
+            ))?;
+            if chapters.is_empty() {
+                warn!("{}", gettext("This recording has no chapters"));
+            }
+            if let Some(mp3) = subcommand_args.get_one::<String>("mp3") {
+                write_id3_chapters(Path::new(mp3), &chapters, duration_secs)?;
+            }
+            let podcast_chapters =
+                serde_json::to_string_pretty(&podcast_chapters(&chapters, duration_secs))?;
+            match subcommand_args.get_one::<String>("output") {
+                Some(output) => std::fs::write(output, podcast_chapters + "\n")?,
+                None => println!("{}", podcast_chapters),
+            }
+        }
+        Some("which-file") => {
+            let subcommand_args = args.subcommand_matches("which-file").unwrap();
+            which_file_main(
+                subcommand_args.get_one::<String>("clip").unwrap(),
+                subcommand_args
+                    .get_one::<String>("directory")
+                    .map(|directory| directory.as_str()),
+                *subcommand_args.get_one::<usize>("min-matches").unwrap(),
+                subcommand_args.get_flag("json"),
+            )?;
+        }
+        Some("scan") => {
+            let subcommand_args = args.subcommand_matches("scan").unwrap();
+            scan_main(
+                subcommand_args.get_one::<String>("directory").unwrap(),
+                *subcommand_args.get_one::<u8>("concurrency").unwrap() as usize,
+                subcommand_args.get_flag("json"),
+                subcommand_args.get_flag("restart"),
+            )?;
+        }
+        Some("history") => {
+            let history_args = args.subcommand_matches("history").unwrap();
+            // Not while the GUI or `listen` run with the profile, as they
+            // would write their own history over the changes
+            let rewrites_history = match history_args.subcommand() {
+                Some(("dedupe" | "prune", subcommand_args)) => !subcommand_args.get_flag("dry-run"),
+                Some(("check", subcommand_args)) => subcommand_args.get_flag("fix"),
+                Some(("encrypt" | "decrypt", _)) => true,
+                _ => false,
+            };
+            if rewrites_history {
+                single_instance::lock_profile()?;
+            }
+            if let Some(subcommand_args) = history_args.subcommand_matches("export") {
+                export_main(HistoryExportOptions {
+                    favorites: subcommand_args.get_flag("favorites"),
+                    format: match subcommand_args.get_one::<String>("format") {
+                        Some(format) => Some(format.parse::<ExportFormat>()?),
+                        None => None,
+                    },
+                    output: subcommand_args.get_one::<String>("output").cloned(),
+                    columns: match subcommand_args.get_many::<String>("columns") {
+                        Some(columns) => columns.map(|column| column.parse().unwrap()).collect(),
+                        None => DEFAULT_CSV_COLUMNS.to_vec(),
+                    },
+                    header: !subcommand_args.get_flag("no-header"),
+                    source_kind: subcommand_args.get_one::<String>("source").cloned(),
+                })?;
+            } else if let Some(subcommand_args) = history_args.subcommand_matches("bundle") {
+                bundle_main(
+                    subcommand_args.get_one::<String>("range").unwrap(),
+                    subcommand_args.get_one::<String>("output").unwrap(),
+                    subcommand_args.get_flag("favorites"),
+                    subcommand_args
+                        .get_one::<String>("source")
+                        .map(|kind| kind.as_str()),
+                )?;
+            } else if let Some(subcommand_args) = history_args.subcommand_matches("dedupe") {
+                history_dedupe_main(
+                    *subcommand_args.get_one::<u32>("window").unwrap(),
+                    subcommand_args.get_flag("favorites"),
+                    subcommand_args.get_flag("dry-run"),
+                )?;
+            } else if let Some(subcommand_args) = history_args.subcommand_matches("stats") {
+                stats_main(
+                    subcommand_args.get_one::<usize>("limit").copied(),
+                    subcommand_args.get_flag("json"),
+                )?;
+            } else if let Some(subcommand_args) = history_args.subcommand_matches("prune") {
+                prune_main(
+                    subcommand_args.get_one::<u32>("max-entries").copied(),
+                    subcommand_args.get_one::<u32>("max-months").copied(),
+                    subcommand_args.get_flag("dry-run"),
+                )?;
+            } else if history_args.subcommand_matches("encrypt").is_some() {
+                encryption_main(true)?;
+            } else if history_args.subcommand_matches("decrypt").is_some() {
+                encryption_main(false)?;
+            } else if let Some(subcommand_args) = history_args.subcommand_matches("check") {
+                check_main(
+                    subcommand_args.get_flag("favorites"),
+                    subcommand_args.get_flag("fix"),
+                )?;
+            }
+        }
+        Some("spotify") => {
+            let spotify_args = args.subcommand_matches("spotify").unwrap();
+            if let Some(subcommand_args) = spotify_args.subcommand_matches("diff") {
+                let access_token = std::env::var("SPOTIFY_ACCESS_TOKEN").map_err(|_| {
+                    gettext("A Spotify access token is required, in the SPOTIFY_ACCESS_TOKEN environment variable")
+                })?;
+                spotify_diff_main(
+                    subcommand_args.get_one::<String>("playlist").unwrap(),
+                    access_token,
+                    subcommand_args
+                        .get_one::<String>("range")
+                        .map(|range| range.as_str()),
+                    subcommand_args.get_flag("fix"),
+                )?;
+            }
+        }
+        Some("report") => {
+            let subcommand_args = args.subcommand_matches("report").unwrap();
+            report_main(
+                subcommand_args
+                    .get_one::<String>("period")
+                    .unwrap()
+                    .parse()?,
+                subcommand_args
+                    .get_one::<String>("format")
+                    .unwrap()
+                    .parse()?,
+                subcommand_args
+                    .get_one::<String>("mail")
+                    .map(|mail| mail.as_str()),
+                subcommand_args
+                    .get_one::<String>("mail-from")
+                    .map(|mail_from| mail_from.as_str()),
+                subcommand_args
+                    .get_one::<String>("output")
+                    .map(|output| output.as_str()),
+            )?;
+        }
+        Some("charts") => {
+            let subcommand_args = args.subcommand_matches("charts").unwrap();
+            charts_main(
+                subcommand_args
+                    .get_one::<String>("period")
+                    .unwrap()
+                    .parse()?,
+                subcommand_args
+                    .get_one::<String>("format")
+                    .unwrap()
+                    .parse()?,
+                *subcommand_args.get_one::<usize>("limit").unwrap(),
+                subcommand_args
+                    .get_one::<String>("output")
+                    .map(|output| output.as_str()),
+            )?;
+        }
+        Some("doctor") => {
+            doctor_main()?;
+        }
         Some("audio-file-to-fingerprint") => {
             let subcommand_args = args
                 .subcommand_matches("audio-file-to-fingerprint")
@@ -393,7 +2298,21 @@ fn main() -> Result<(), Box<dyn Error>> {
 
             println!(
                 "{}",
-                SignatureGenerator::make_signature_from_file(input_file_string)?.encode_to_uri()?
+                trim_signature(
+                    SignatureGenerator::make_signature_from_file(input_file_string)?,
+                    subcommand_args
+                )
+                .encode_to_uri()?
+            );
+        }
+        Some("fingerprint-info") => {
+            let subcommand_args = args.subcommand_matches("fingerprint-info").unwrap();
+
+            print!(
+                "{}",
+                describe_signature(&load_signature(
+                    subcommand_args.get_one::<String>("fingerprint").unwrap()
+                )?)
             );
         }
         Some("fingerprint-to-recognized-song") => {
@@ -401,10 +2320,12 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .subcommand_matches("fingerprint-to-recognized-song")
                 .unwrap();
 
-            let fingerprint_string = subcommand_args
-                .get_one::<String>("fingerprint")
-                .unwrap()
-                .clone();
+            let signature = trim_signature(
+                DecodedSignature::decode_from_uri(
+                    subcommand_args.get_one::<String>("fingerprint").unwrap(),
+                )?,
+                subcommand_args,
+            );
 
             let session = soup::Session::new();
             session.set_timeout(20);
@@ -415,12 +2336,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                 println!(
                     "{}",
                     serde_json::to_string_pretty(
-                        &recognize_song_from_signature(
-                            &session,
-                            &DecodedSignature::decode_from_uri(&fingerprint_string).unwrap()
-                        )
-                        .await
-                        .unwrap()
+                        &recognize_song_from_signature(&session, &signature, None)
+                            .await
+                            .unwrap()
                     )
                     .unwrap()
                 );
@@ -436,14 +2354,47 @@ fn main() -> Result<(), Box<dyn Error>> {
             let enable_mpris = !subcommand_args.get_flag("disable-mpris");
             let enable_json = subcommand_args.get_flag("json");
             let enable_csv = subcommand_args.get_flag("csv");
+            let metrics_address = subcommand_args
+                .get_one::<String>("metrics-address")
+                .cloned();
 
-            cli_main(CLIParameters {
+            #[cfg(not(all(target_os = "linux", feature = "mpris")))]
+            if subcommand_args.get_flag("ignore-own-spotify-playback") {
+                return Err(gettext("SongRec was compiled without MPRIS support").into());
+            }
+
+            #[cfg(not(all(windows, feature = "windows-service")))]
+            if subcommand_args.get_flag("windows-service") {
+                return Err(gettext("SongRec was compiled without Windows service support").into());
+            }
+
+            let sinks = sink_configs(subcommand_args)?;
+
+            if !list_devices {
+                single_instance::lock_profile()?;
+            }
+
+            let parameters = CLIParameters {
                 enable_mpris,
                 list_devices,
                 recognize_once: false,
                 audio_device,
                 request_interval,
                 input_file: None,
+                metrics_address,
+                watch_chromecast: subcommand_args.get_flag("watch-chromecast"),
+                #[cfg(all(target_os = "linux", feature = "mpris"))]
+                ignore_own_spotify_playback: subcommand_args
+                    .get_flag("ignore-own-spotify-playback"),
+                sinks,
+                streams: stream_configs(subcommand_args)?,
+                sinks_config: Some(match subcommand_args.get_one::<String>("sinks-config") {
+                    Some(path) => PathBuf::from(path),
+                    None => filesystem_operations::obtain_sinks_config_path()?,
+                }),
+                providers: provider_settings(subcommand_args),
+                network: network_settings(subcommand_args),
+                detect_metered_connection: !subcommand_args.get_flag("ignore-metered"),
                 output_type: if enable_json {
                     CLIOutputType::JSON
                 } else if enable_csv {
@@ -451,6 +2402,70 @@ fn main() -> Result<(), Box<dyn Error>> {
                 } else {
                     CLIOutputType::SongName
                 },
+                csv_columns: csv_columns(subcommand_args),
+                csv_header: !subcommand_args.get_flag("no-csv-header"),
+                qr_code: subcommand_args.get_flag("qr"),
+                diagnostics: subcommand_args.get_flag("diagnostics"),
+                echo_cancel: subcommand_args.get_flag("echo-cancel"),
+                split_channels: subcommand_args.get_flag("split-channels"),
+                share_template: subcommand_args.get_one::<String>("share-template").cloned(),
+                #[cfg(feature = "scripting")]
+                script: recognition_script(subcommand_args)?,
+                dump_capture: subcommand_args
+                    .get_one::<String>("dump-capture")
+                    .map(PathBuf::from),
+                record_session: subcommand_args
+                    .get_one::<String>("record-session")
+                    .map(PathBuf::from),
+                ..Default::default()
+            };
+
+            #[cfg(all(windows, feature = "windows-service"))]
+            if subcommand_args.get_flag("windows-service") {
+                return windows_service::run_as_service(parameters);
+            }
+            cli_main(parameters)?;
+        }
+        Some("service") => {
+            #[cfg(all(windows, feature = "windows-service"))]
+            {
+                let service_args = args.subcommand_matches("service").unwrap();
+                if let Some(subcommand_args) = service_args.subcommand_matches("install") {
+                    windows_service::install_service(
+                        subcommand_args
+                            .get_many::<String>("listen-options")
+                            .into_iter()
+                            .flatten()
+                            .map(std::ffi::OsString::from)
+                            .collect(),
+                    )?;
+                } else if service_args.subcommand_matches("uninstall").is_some() {
+                    windows_service::uninstall_service()?;
+                }
+            }
+            #[cfg(not(all(windows, feature = "windows-service")))]
+            return Err(gettext("SongRec was compiled without Windows service support").into());
+        }
+        Some("replay") => {
+            let subcommand_args = args.subcommand_matches("replay").unwrap();
+            let session = subcommand_args.get_one::<String>("session").unwrap();
+            // Recognize the same windows as when the session was recorded
+            let header = CaptureSession::load_header(Path::new(session))?;
+
+            cli_main(CLIParameters {
+                enable_mpris: false,
+                audio_device: Some(format!("{}{}", SESSION_DEVICE_PREFIX, session)),
+                request_interval: header.request_interval_secs,
+                diagnostics: subcommand_args.get_flag("diagnostics"),
+                detect_metered_connection: false,
+                output_type: if subcommand_args.get_flag("json") {
+                    CLIOutputType::JSON
+                } else if subcommand_args.get_flag("csv") {
+                    CLIOutputType::CSV
+                } else {
+                    CLIOutputType::SongName
+                },
+                ..Default::default()
             })?;
         }
         Some("recognize") => {
@@ -461,6 +2476,8 @@ fn main() -> Result<(), Box<dyn Error>> {
             let input_file = subcommand_args.get_one::<String>("input_file").cloned();
             let enable_json = subcommand_args.get_flag("json");
             let enable_csv = subcommand_args.get_flag("csv");
+            let apply_tags = subcommand_args.get_flag("apply");
+            let compare_tags = subcommand_args.get_flag("diff-tags") || apply_tags;
 
             cli_main(CLIParameters {
                 enable_mpris: false,
@@ -469,7 +2486,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                 audio_device,
                 request_interval,
                 input_file,
-
+                providers: provider_settings(subcommand_args),
+                network: network_settings(subcommand_args),
+                detect_metered_connection: !subcommand_args.get_flag("ignore-metered"),
                 output_type: if enable_json {
                     CLIOutputType::JSON
                 } else if enable_csv {
@@ -477,6 +2496,17 @@ fn main() -> Result<(), Box<dyn Error>> {
                 } else {
                     CLIOutputType::SongName
                 },
+                csv_columns: csv_columns(subcommand_args),
+                csv_header: !subcommand_args.get_flag("no-csv-header"),
+                compare_tags,
+                apply_tags,
+                qr_code: subcommand_args.get_flag("qr"),
+                diagnostics: subcommand_args.get_flag("diagnostics"),
+                echo_cancel: subcommand_args.get_flag("echo-cancel"),
+                share_template: subcommand_args.get_one::<String>("share-template").cloned(),
+                #[cfg(feature = "scripting")]
+                script: recognition_script(subcommand_args)?,
+                ..Default::default()
             })?;
         }
         Some("microphone-to-recognized-song") => {
@@ -493,8 +2523,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                 recognize_once: true,
                 audio_device,
                 request_interval,
-                input_file: None,
+                echo_cancel: subcommand_args.get_flag("echo-cancel"),
                 output_type: CLIOutputType::JSON,
+                ..Default::default()
             })?;
         }
         #[cfg(feature = "gui")]
@@ -507,6 +2538,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 subcommand_args.get_one::<String>("input_file").cloned(),
                 !subcommand_args.get_flag("disable-mpris"),
                 !subcommand_args.get_flag("disable-pipewire"),
+                false,
             )?;
         }
         #[cfg(feature = "gui")]
@@ -518,21 +2550,17 @@ fn main() -> Result<(), Box<dyn Error>> {
                     subcommand_args.get_one::<String>("input_file").cloned(),
                     !subcommand_args.get_flag("disable-mpris"),
                     !subcommand_args.get_flag("disable-pipewire"),
+                    subcommand_args.get_flag("kiosk"),
                 )?;
             } else {
-                gui_main(log_object, true, None, true, true)?;
+                gui_main(log_object, true, None, true, true, false)?;
             }
         }
         #[cfg(not(feature = "gui"))]
         None => {
             cli_main(CLIParameters {
                 enable_mpris: true,
-                list_devices: false,
-                recognize_once: false,
-                audio_device: None,
-                request_interval: 10,
-                input_file: None,
-                output_type: CLIOutputType::SongName,
+                ..Default::default()
             })?;
         }
         _ => unreachable!(),
@@ -0,0 +1,215 @@
+//! Add recognized songs to the Apple Music library of the user, and/or to
+//! one of their playlists, through the Apple Music API.
+//!
+//! Requests are authorized with a developer token (a JWT signed with a
+//! MusicKit key of an Apple Developer account) and a Music User Token,
+//! which MusicKit JS or a native MusicKit application obtains once the user
+//! has allowed access to their library. Shazam usually gives the Apple
+//! Music identifier of the song; the catalog is looked up otherwise.
+//!
+//! See: https://developer.apple.com/documentation/applemusicapi
+
+use glib::source::Priority;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use soup::prelude::SessionExt;
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::service_resolution::{first_result, ResolveFuture, TrackQuery, TrackResolver};
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+const API_URL: &str = "https://api.music.apple.com/v1";
+
+fn default_storefront() -> String {
+    "us".to_string()
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AppleMusicSettings {
+    pub developer_token: String,
+    pub user_token: String,
+    /// Country code of the catalog to search, e.g. "fr"
+    #[serde(default = "default_storefront")]
+    pub storefront: String,
+    /// Identifier of a library playlist, e.g. "p.AbCdEfGh"
+    #[serde(default)]
+    pub playlist: Option<String>,
+    /// Add the songs to the library too
+    #[serde(default)]
+    pub add_to_library: bool,
+}
+
+pub struct AppleMusicSink {
+    session: soup::Session,
+    settings: AppleMusicSettings,
+    /// Songs already added during this session
+    added: HashSet<String>,
+}
+
+/// The Apple Music identifier that Shazam gives for the song
+fn shazam_song_id(shazam_json: &str) -> Option<String> {
+    let json: Value = serde_json::from_str(shazam_json).ok()?;
+    json["track"]["hub"]["actions"]
+        .as_array()?
+        .iter()
+        .find(|action| action["type"] == "applemusicplay")
+        .and_then(|action| action["id"].as_str())
+        .map(|id| id.to_string())
+}
+
+impl AppleMusicSink {
+    pub fn new(settings: AppleMusicSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        AppleMusicSink {
+            session,
+            settings,
+            added: HashSet::new(),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<Value>,
+    ) -> Result<Value, Box<dyn Error>> {
+        let request = soup::Message::new(method, url)?;
+        let headers = request.request_headers().unwrap();
+        headers.append(
+            "Authorization",
+            &format!("Bearer {}", self.settings.developer_token),
+        );
+        headers.append("Music-User-Token", &self.settings.user_token);
+        if let Some(body) = body {
+            request.set_request_body_from_bytes(
+                Some("application/json"),
+                Some(&glib::Bytes::from_owned(body.to_string().into_bytes())),
+            );
+        }
+
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        let response: Value = serde_json::from_slice(&response[..]).unwrap_or_default();
+        if !(200..300).contains(&request.status_code()) {
+            return Err(format!(
+                "Apple Music returned status {}: {}",
+                request.status_code(),
+                response["errors"][0]["detail"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+        Ok(response)
+    }
+
+    pub async fn add_song(
+        &mut self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        let song_id = match shazam_song_id(&message.shazam_json) {
+            Some(song_id) => Some(song_id),
+            None => self.resolve(&TrackQuery::from_message(message)).await?,
+        };
+        let Some(song_id) = song_id else {
+            info!("{} was not found on Apple Music", message.song_name);
+            return Ok(());
+        };
+        if self.added.contains(&song_id) {
+            debug!("{} was already added to Apple Music", message.song_name);
+            return Ok(());
+        }
+
+        if self.settings.add_to_library {
+            self.request(
+                "POST",
+                &format!("{}/me/library?ids%5Bsongs%5D={}", API_URL, song_id),
+                None,
+            )
+            .await?;
+            info!("Added {} to the Apple Music library", message.song_name);
+        }
+        if let Some(ref playlist) = self.settings.playlist {
+            self.request(
+                "POST",
+                &format!(
+                    "{}/me/library/playlists/{}/tracks",
+                    API_URL,
+                    glib::Uri::escape_string(playlist, None, false)
+                ),
+                Some(json!({ "data": [{ "id": song_id, "type": "songs" }] })),
+            )
+            .await?;
+            info!("Added {} to the Apple Music playlist", message.song_name);
+        }
+        self.added.insert(song_id);
+        Ok(())
+    }
+}
+
+impl TrackResolver for AppleMusicSink {
+    fn find_by_isrc<'a>(&'a self, isrc: &'a str) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/catalog/{}/songs?filter%5Bisrc%5D={}",
+                API_URL,
+                glib::Uri::escape_string(&self.settings.storefront, None, false),
+                glib::Uri::escape_string(isrc, None, false)
+            );
+            let response = self.request("GET", &url, None).await?;
+            Ok(first_result(&response["data"], "Apple Music")?
+                .and_then(|song| song["id"].as_str())
+                .map(|id| id.to_string()))
+        })
+    }
+
+    fn search<'a>(&'a self, query: &'a TrackQuery) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/catalog/{}/search?types=songs&limit=1&term={}",
+                API_URL,
+                glib::Uri::escape_string(&self.settings.storefront, None, false),
+                glib::Uri::escape_string(&format!("{} {}", query.artist, query.title), None, false)
+            );
+            let response = self.request("GET", &url, None).await?;
+            // The songs are left out of the results when none matched
+            let songs = &response["results"]["songs"];
+            if songs.is_null() {
+                return Ok(None);
+            }
+            Ok(first_result(&songs["data"], "Apple Music")?
+                .and_then(|song| song["id"].as_str())
+                .map(|id| id.to_string()))
+        })
+    }
+}
+
+impl RecognitionSink for AppleMusicSink {
+    fn name(&self) -> &'static str {
+        "Apple Music"
+    }
+
+    fn searches_catalog(&self) -> bool {
+        true
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.add_song(message))
+    }
+}
+
+#[test]
+fn test_shazam_song_id() {
+    let shazam_json = r#"{"track": {"hub": {"actions": [
+        {"name": "apple", "type": "applemusicplay", "id": "1440818839"},
+        {"name": "apple", "type": "uri", "uri": "https://audio-ssl.itunes.apple.com/preview.m4a"}
+    ]}}}"#;
+    assert_eq!(shazam_song_id(shazam_json).as_deref(), Some("1440818839"));
+    assert_eq!(shazam_song_id(r#"{"track": {}}"#), None);
+}
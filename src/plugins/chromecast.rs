@@ -0,0 +1,582 @@
+//! Log what the Chromecast and Google Cast devices of the local network
+//! report as playing, next to the acoustic recognitions. The devices are
+//! discovered through mDNS, then each of them is followed through the Cast
+//! protocol (protobuf messages carrying JSON, over TLS on port 8009).
+//!
+//! See: https://developers.google.com/cast/docs/media/messages
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use soup::gio;
+use soup::gio::prelude::*;
+
+use crate::core::aliases;
+use crate::core::thread_messages::{GUIMessage, RecognitionSource, SongRecognizedMessage};
+
+/// Prefix of the track keys of the songs reported by Cast devices
+pub const CAST_TRACK_KEY_PREFIX: &str = "cast:";
+
+const SERVICE_NAME: &str = "_googlecast._tcp.local";
+const MDNS_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(224, 0, 0, 251), 5353);
+const DEFAULT_PORT: u16 = 8009;
+
+const DNS_TYPE_PTR: u16 = 12;
+const DNS_TYPE_TXT: u16 = 16;
+const DNS_TYPE_SRV: u16 = 33;
+
+/// How long we wait for the answers of the devices after each query, and
+/// how often the network is queried for new devices
+const DISCOVERY_WINDOW: Duration = Duration::from_secs(3);
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Cast devices send a heartbeat every 5 seconds, so a longer pause
+/// means a broken connection
+const READ_TIMEOUT_SECONDS: u32 = 30;
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const MAX_CONNECTION_FAILURES: u32 = 3;
+
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+const SENDER_ID: &str = "sender-songrec";
+const RECEIVER_ID: &str = "receiver-0";
+
+const CONNECTION_NAMESPACE: &str = "urn:x-cast:com.google.cast.tp.connection";
+const HEARTBEAT_NAMESPACE: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+const RECEIVER_NAMESPACE: &str = "urn:x-cast:com.google.cast.receiver";
+const MEDIA_NAMESPACE: &str = "urn:x-cast:com.google.cast.media";
+
+/// A Cast device which answered our mDNS query
+#[derive(Debug, Clone, PartialEq)]
+struct CastAnnouncement {
+    friendly_name: String,
+    port: u16,
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> Option<u16> {
+    packet
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// A possibly compressed domain name, with the offset following it
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    // Bounded, in order not to follow compression loops
+    for _ in 0..128 {
+        let length = *packet.get(offset)? as usize;
+        if length == 0 {
+            return Some((labels.join("."), end.unwrap_or(offset + 1)));
+        }
+        if length & 0xc0 == 0xc0 {
+            end.get_or_insert(offset + 2);
+            offset = ((length & 0x3f) << 8) | *packet.get(offset + 1)? as usize;
+        } else {
+            let label = packet.get(offset + 1..offset + 1 + length)?;
+            labels.push(String::from_utf8_lossy(label).to_string());
+            offset += 1 + length;
+        }
+    }
+    None
+}
+
+fn build_query() -> Vec<u8> {
+    // No id nor flags, a single question
+    let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in SERVICE_NAME.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&DNS_TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    query
+}
+
+fn parse_announcement(packet: &[u8]) -> Option<CastAnnouncement> {
+    let questions = read_u16(packet, 4)?;
+    let records =
+        read_u16(packet, 6)? as u32 + read_u16(packet, 8)? as u32 + read_u16(packet, 10)? as u32;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(packet, offset)?.1 + 4;
+    }
+
+    let mut is_cast = false;
+    let mut port = None;
+    let mut friendly_name = None;
+    for _ in 0..records {
+        let (owner, end) = read_name(packet, offset)?;
+        let record_type = read_u16(packet, end)?;
+        let length = read_u16(packet, end + 8)? as usize;
+        let data = packet.get(end + 10..end + 10 + length)?;
+        offset = end + 10 + length;
+
+        if !owner.to_lowercase().ends_with(SERVICE_NAME) {
+            continue;
+        }
+        match record_type {
+            DNS_TYPE_PTR => is_cast = true,
+            DNS_TYPE_SRV => port = read_u16(data, 4),
+            DNS_TYPE_TXT => {
+                let mut entry = 0;
+                while let Some(&entry_length) = data.get(entry) {
+                    let text = data.get(entry + 1..entry + 1 + entry_length as usize)?;
+                    if let Some(name) = text.strip_prefix(b"fn=") {
+                        friendly_name = Some(String::from_utf8_lossy(name).to_string());
+                    }
+                    entry += 1 + entry_length as usize;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !is_cast && port.is_none() {
+        return None;
+    }
+    Some(CastAnnouncement {
+        friendly_name: friendly_name.unwrap_or_else(|| "Chromecast".to_string()),
+        port: port.unwrap_or(DEFAULT_PORT),
+    })
+}
+
+/// Query the local network for Cast devices
+fn discover_devices() -> Result<Vec<(SocketAddr, CastAnnouncement)>, Box<dyn Error>> {
+    // Queries sent from another port than 5353 are answered in unicast
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+    socket.send_to(&build_query(), MDNS_ADDRESS)?;
+
+    let mut devices: Vec<(SocketAddr, CastAnnouncement)> = vec![];
+    let mut packet = [0u8; 9000];
+    let started = Instant::now();
+    while started.elapsed() < DISCOVERY_WINDOW {
+        let (size, sender) = match socket.recv_from(&mut packet) {
+            Ok(answer) => answer,
+            Err(_) => continue,
+        };
+        if let Some(announcement) = parse_announcement(&packet[..size]) {
+            let address = SocketAddr::new(sender.ip(), announcement.port);
+            if !devices.iter().any(|(known, _)| *known == address) {
+                devices.push((address, announcement));
+            }
+        }
+    }
+    Ok(devices)
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer.push(byte);
+            return;
+        }
+        buffer.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*offset)?;
+        *offset += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The string messages of the CastMessage protobuf, encoded by hand in
+/// order not to depend on a protobuf library for seven fields
+#[derive(Debug, PartialEq)]
+struct CastMessage {
+    source_id: String,
+    destination_id: String,
+    namespace: String,
+    payload: String,
+}
+
+impl CastMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        // protocol_version: CASTV2_1_0
+        buffer.extend_from_slice(&[0x08, 0]);
+        for (key, value) in [
+            (0x12, &self.source_id),
+            (0x1a, &self.destination_id),
+            (0x22, &self.namespace),
+        ] {
+            buffer.push(key);
+            write_varint(&mut buffer, value.len() as u64);
+            buffer.extend_from_slice(value.as_bytes());
+        }
+        // payload_type: STRING
+        buffer.extend_from_slice(&[0x28, 0]);
+        buffer.push(0x32);
+        write_varint(&mut buffer, self.payload.len() as u64);
+        buffer.extend_from_slice(self.payload.as_bytes());
+        buffer
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut message = CastMessage {
+            source_id: String::new(),
+            destination_id: String::new(),
+            namespace: String::new(),
+            payload: String::new(),
+        };
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let key = read_varint(bytes, &mut offset).ok_or("Truncated Cast message")?;
+            match key & 7 {
+                0 => {
+                    read_varint(bytes, &mut offset).ok_or("Truncated Cast message")?;
+                }
+                2 => {
+                    let length =
+                        read_varint(bytes, &mut offset).ok_or("Truncated Cast message")? as usize;
+                    let value = bytes
+                        .get(offset..offset + length)
+                        .ok_or("Truncated Cast message")?;
+                    offset += length;
+                    let value = String::from_utf8_lossy(value).to_string();
+                    match key >> 3 {
+                        2 => message.source_id = value,
+                        3 => message.destination_id = value,
+                        4 => message.namespace = value,
+                        6 => message.payload = value,
+                        // Binary payloads are not used by the media namespace
+                        _ => {}
+                    }
+                }
+                _ => return Err("Unsupported Cast message field".into()),
+            }
+        }
+        Ok(message)
+    }
+}
+
+/// The song described by the metadata of a MEDIA_STATUS message
+fn song_from_metadata(device_name: &str, metadata: &Value) -> Option<SongRecognizedMessage> {
+    let song_name = metadata["title"]
+        .as_str()
+        .filter(|title| !title.is_empty())?;
+    let artist_name = ["artist", "albumArtist", "subtitle"]
+        .iter()
+        .find_map(|field| metadata[field].as_str().filter(|value| !value.is_empty()))
+        .unwrap_or_default();
+
+    Some(SongRecognizedMessage {
+        artist_name: artist_name.to_string(),
+        album_name: metadata["albumName"].as_str().map(str::to_string),
+        song_name: song_name.to_string(),
+        cover_image: None,
+        cover_url: metadata["images"][0]["url"].as_str().map(str::to_string),
+        track_key: format!("{}{} - {}", CAST_TRACK_KEY_PREFIX, artist_name, song_name),
+        release_year: metadata["releaseDate"]
+            .as_str()
+            .and_then(|date| date.get(0..4))
+            .map(str::to_string),
+        genre: None,
+        isrc: None,
+        upc: None,
+        shazam_json: json!({
+            "cast": {
+                "device": device_name,
+                "metadata": metadata,
+            }
+        })
+        .to_string(),
+        source: Some(RecognitionSource::Cast(device_name.to_string())),
+    })
+}
+
+struct CastWatcher {
+    device_name: String,
+    address: SocketAddr,
+    gui_tx: async_channel::Sender<GUIMessage>,
+    metadata: Option<Value>,
+    last_track_key: Option<String>,
+}
+
+impl CastWatcher {
+    fn send(
+        writer: &mut impl Write,
+        destination_id: &str,
+        namespace: &str,
+        payload: Value,
+    ) -> Result<(), Box<dyn Error>> {
+        let message = CastMessage {
+            source_id: SENDER_ID.to_string(),
+            destination_id: destination_id.to_string(),
+            namespace: namespace.to_string(),
+            payload: payload.to_string(),
+        }
+        .encode();
+        writer.write_all(&(message.len() as u32).to_be_bytes())?;
+        writer.write_all(&message)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn receive(reader: &mut impl Read) -> Result<CastMessage, Box<dyn Error>> {
+        let mut length = [0u8; 4];
+        reader.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_MESSAGE_SIZE {
+            return Err("Invalid Cast message size".into());
+        }
+        let mut message = vec![0u8; length];
+        reader.read_exact(&mut message)?;
+        CastMessage::decode(&message)
+    }
+
+    fn on_media_status(&mut self, status: &Value) {
+        // Updates of the player state may come without the metadata
+        if let Some(metadata) = status["media"].get("metadata") {
+            self.metadata = Some(metadata.clone());
+        }
+        match status["playerState"].as_str() {
+            Some("PLAYING") => {
+                let song = self
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| song_from_metadata(&self.device_name, metadata));
+                if let Some(mut song) = song {
+                    if self.last_track_key.as_ref() != Some(&song.track_key) {
+                        aliases::apply(&mut song);
+                        debug!("{} is playing {}", self.device_name, song.track_key);
+                        self.last_track_key = Some(song.track_key.clone());
+                        self.gui_tx
+                            .try_send(GUIMessage::SongRecognized(Box::new(song)))
+                            .ok();
+                    }
+                }
+            }
+            Some("IDLE") => {
+                self.metadata = None;
+                self.last_track_key = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Follow the device until the connection is lost
+    fn watch(&mut self) -> Result<(), Box<dyn Error>> {
+        let client = gio::SocketClient::new();
+        client.set_tls(true);
+        client.set_timeout(READ_TIMEOUT_SECONDS);
+        client.connect_event(|_, event, _, connection| {
+            if event == gio::SocketClientEvent::TlsHandshaking {
+                if let Some(tls) = connection
+                    .and_then(|connection| connection.downcast_ref::<gio::TlsConnection>())
+                {
+                    // Cast devices use self-signed certificates
+                    tls.connect_accept_certificate(|_, _, _| true);
+                }
+            }
+        });
+        let connection = client.connect(
+            &gio::InetSocketAddress::from(self.address),
+            gio::Cancellable::NONE,
+        )?;
+        let mut reader = connection.input_stream().into_read();
+        let mut writer = connection.output_stream().into_write();
+
+        info!(
+            "Watching the Cast device {} ({})",
+            self.device_name, self.address
+        );
+
+        Self::send(
+            &mut writer,
+            RECEIVER_ID,
+            CONNECTION_NAMESPACE,
+            json!({"type": "CONNECT"}),
+        )?;
+        Self::send(
+            &mut writer,
+            RECEIVER_ID,
+            RECEIVER_NAMESPACE,
+            json!({"type": "GET_STATUS", "requestId": 1}),
+        )?;
+
+        // The application playing media, if any
+        let mut transport_id: Option<String> = None;
+        let mut request_id = 1;
+
+        loop {
+            let message = Self::receive(&mut reader)?;
+            let payload: Value = serde_json::from_str(&message.payload).unwrap_or_default();
+
+            match message.namespace.as_str() {
+                HEARTBEAT_NAMESPACE if payload["type"] == "PING" => {
+                    Self::send(
+                        &mut writer,
+                        &message.source_id,
+                        HEARTBEAT_NAMESPACE,
+                        json!({"type": "PONG"}),
+                    )?;
+                }
+                RECEIVER_NAMESPACE => {
+                    let media_application = payload["status"]["applications"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .find(|application| {
+                            application["namespaces"]
+                                .as_array()
+                                .into_iter()
+                                .flatten()
+                                .any(|namespace| namespace["name"] == MEDIA_NAMESPACE)
+                        });
+                    let new_transport_id = media_application
+                        .and_then(|application| application["transportId"].as_str())
+                        .map(str::to_string);
+
+                    if new_transport_id != transport_id {
+                        if let Some(ref destination_id) = new_transport_id {
+                            request_id += 1;
+                            Self::send(
+                                &mut writer,
+                                destination_id,
+                                CONNECTION_NAMESPACE,
+                                json!({"type": "CONNECT"}),
+                            )?;
+                            Self::send(
+                                &mut writer,
+                                destination_id,
+                                MEDIA_NAMESPACE,
+                                json!({"type": "GET_STATUS", "requestId": request_id}),
+                            )?;
+                        } else {
+                            self.metadata = None;
+                            self.last_track_key = None;
+                        }
+                        transport_id = new_transport_id;
+                    }
+                }
+                MEDIA_NAMESPACE if payload["type"] == "MEDIA_STATUS" => {
+                    for status in payload["status"].as_array().into_iter().flatten() {
+                        self.on_media_status(status);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Discover the Cast devices of the local network in the background, and
+/// report the songs that they play as recognitions
+pub fn spawn_chromecast_watcher(gui_tx: async_channel::Sender<GUIMessage>) {
+    let watched: Arc<Mutex<HashSet<SocketAddr>>> = Arc::new(Mutex::new(HashSet::new()));
+
+    std::thread::Builder::new()
+        .name("chromecast discovery".to_string())
+        .spawn(move || loop {
+            match discover_devices() {
+                Ok(devices) => {
+                    for (address, announcement) in devices {
+                        if !watched.lock().unwrap().insert(address) {
+                            continue;
+                        }
+                        let watched = watched.clone();
+                        let mut watcher = CastWatcher {
+                            device_name: announcement.friendly_name,
+                            address,
+                            gui_tx: gui_tx.clone(),
+                            metadata: None,
+                            last_track_key: None,
+                        };
+                        std::thread::spawn(move || {
+                            let mut failures = 0;
+                            while failures < MAX_CONNECTION_FAILURES {
+                                let started = Instant::now();
+                                if let Err(error) = watcher.watch() {
+                                    warn!("Cast device {}: {}", watcher.device_name, error);
+                                }
+                                // A device which stayed connected for a while
+                                // is retried as long as it comes back
+                                if started.elapsed() > DISCOVERY_INTERVAL {
+                                    failures = 0;
+                                }
+                                failures += 1;
+                                std::thread::sleep(RECONNECT_DELAY);
+                            }
+                            // Until it is discovered again
+                            watched.lock().unwrap().remove(&watcher.address);
+                        });
+                    }
+                }
+                Err(error) => warn!("Could not discover Cast devices: {}", error),
+            }
+            std::thread::sleep(DISCOVERY_INTERVAL);
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_chromecast_protocol() {
+    let mut packet = build_query();
+    // The answer of a device, repeating the question
+    packet[2] = 0x84;
+    packet[7] = 3;
+    let instance = b"\x0fChromecast-1234\xc0\x0c";
+    packet.extend_from_slice(&[0xc0, 0x0c, 0, 12, 0, 1, 0, 0, 0, 120]);
+    packet.extend_from_slice(&(instance.len() as u16).to_be_bytes());
+    packet.extend_from_slice(instance);
+    let instance_name = [0xc0, 52];
+    packet.extend_from_slice(&instance_name);
+    packet.extend_from_slice(&[
+        0, 33, 0x80, 1, 0, 0, 0, 120, 0, 8, 0, 0, 0, 0, 0x1f, 0x49, 0xc0, 0x0c,
+    ]);
+    packet.extend_from_slice(&instance_name);
+    let text = b"\x07md=Cast\x0efn=Living Room";
+    packet.extend_from_slice(&[0, 16, 0x80, 1, 0, 0, 0, 120]);
+    packet.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    packet.extend_from_slice(text);
+
+    assert_eq!(
+        read_name(&packet, 12).unwrap(),
+        (SERVICE_NAME.to_string(), 12 + 24)
+    );
+    assert_eq!(
+        parse_announcement(&packet),
+        Some(CastAnnouncement {
+            friendly_name: "Living Room".to_string(),
+            port: 8009,
+        })
+    );
+    assert_eq!(parse_announcement(&build_query()), None);
+
+    let message = CastMessage {
+        source_id: SENDER_ID.to_string(),
+        destination_id: RECEIVER_ID.to_string(),
+        namespace: MEDIA_NAMESPACE.to_string(),
+        payload: "x".repeat(200),
+    };
+    assert_eq!(CastMessage::decode(&message.encode()).unwrap(), message);
+
+    let song = song_from_metadata(
+        "Living Room",
+        &json!({"title": "Song", "artist": "Artist", "releaseDate": "1999-01-01"}),
+    )
+    .unwrap();
+    assert_eq!(song.track_key, "cast:Artist - Song");
+    assert_eq!(song.release_year.as_deref(), Some("1999"));
+    assert!(song_from_metadata("Living Room", &json!({"artist": "Artist"})).is_none());
+}
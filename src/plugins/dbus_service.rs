@@ -0,0 +1,96 @@
+//! D-Bus interface of a running "songrec listen", for the automations of
+//! the desktop. It is exported at /re/fossplant/songrec under the
+//! "re.fossplant.songrec.Listen" bus name (or the Snap equivalent):
+//!
+//! - `MuteSink(name, seconds)` stops forwarding songs to a sink ("mqtt",
+//!   "kdeconnect", "mastodon", "telegram" or "matrix") for a while, e.g.
+//!   during a meeting; 0 seconds unmutes it. It fails with
+//!   `org.freedesktop.DBus.Error.InvalidArgs` when no such sink is enabled.
+//!
+//! ```sh
+//! gdbus call --session --dest re.fossplant.songrec.Listen \
+//!     --object-path /re/fossplant/songrec \
+//!     --method re.fossplant.songrec.Sinks.MuteSink mastodon 3600
+//! ```
+
+use gio::prelude::*;
+use log::{debug, error, warn};
+
+use crate::core::thread_messages::GUIMessage;
+use crate::utils::single_instance::application_id;
+
+const OBJECT_PATH: &str = "/re/fossplant/songrec";
+const SINKS_INTERFACE: &str = "re.fossplant.songrec.Sinks";
+
+const INTROSPECTION_XML: &str = r#"
+<node>
+  <interface name="re.fossplant.songrec.Sinks">
+    <method name="MuteSink">
+      <arg type="s" name="name" direction="in"/>
+      <arg type="u" name="seconds" direction="in"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+fn register_object(
+    connection: &gio::DBusConnection,
+    gui_tx: async_channel::Sender<GUIMessage>,
+) -> Result<gio::RegistrationId, glib::Error> {
+    let node = gio::DBusNodeInfo::for_xml(INTROSPECTION_XML)?;
+    let interface = node.lookup_interface(SINKS_INTERFACE).unwrap();
+
+    connection
+        .register_object(OBJECT_PATH, &interface)
+        .method_call(
+            move |_connection, _sender, _path, _interface, method, parameters, invocation| match (
+                method,
+                parameters.get::<(String, u32)>(),
+            ) {
+                ("MuteSink", Some((name, seconds))) => {
+                    let (reply_tx, reply_rx) = async_channel::bounded(1);
+                    // Closed once SongRec is stopping
+                    if gui_tx
+                        .try_send(GUIMessage::MuteSink(name, seconds, reply_tx))
+                        .is_err()
+                    {
+                        invocation.return_error(gio::DBusError::Failed, "SongRec is stopping");
+                        return;
+                    }
+                    glib::spawn_future_local(async move {
+                        match reply_rx.recv().await {
+                            Ok(Ok(())) => invocation.return_value(None),
+                            Ok(Err(error)) => {
+                                invocation.return_error(gio::DBusError::InvalidArgs, &error)
+                            }
+                            Err(_) => invocation
+                                .return_error(gio::DBusError::Failed, "SongRec is stopping"),
+                        }
+                    });
+                }
+                _ => invocation.return_error(
+                    gio::DBusError::UnknownMethod,
+                    &format!("Unknown method: {}", method),
+                ),
+            },
+        )
+        .build()
+}
+
+/// Export the interface for as long as the main loop runs
+pub fn serve(gui_tx: async_channel::Sender<GUIMessage>) {
+    let bus_name = format!("{}.Listen", application_id());
+
+    gio::bus_own_name(
+        gio::BusType::Session,
+        &bus_name,
+        gio::BusNameOwnerFlags::NONE,
+        move |connection, _name| {
+            if let Err(error) = register_object(&connection, gui_tx.clone()) {
+                error!("Could not export the D-Bus interface: {}", error);
+            }
+        },
+        |_connection, name| debug!("Acquired the {} D-Bus name", name),
+        |_connection, name| warn!("Could not acquire the {} D-Bus name", name),
+    );
+}
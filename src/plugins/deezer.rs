@@ -0,0 +1,148 @@
+//! Add recognized songs to a Deezer playlist, through the Deezer API. The
+//! access token must have been granted the "manage_library" permission.
+//!
+//! See: https://developers.deezer.com/api/playlist/tracks
+
+use glib::source::Priority;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::Value;
+use soup::prelude::SessionExt;
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::service_resolution::{first_result, ResolveFuture, TrackQuery, TrackResolver};
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+const API_URL: &str = "https://api.deezer.com";
+
+#[derive(Clone, Deserialize)]
+pub struct DeezerSettings {
+    pub access_token: String,
+    /// Numeric identifier of the playlist
+    pub playlist: String,
+}
+
+pub struct DeezerSink {
+    session: soup::Session,
+    settings: DeezerSettings,
+    /// Tracks already added during this session
+    added: HashSet<String>,
+}
+
+impl DeezerSink {
+    pub fn new(settings: DeezerSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        DeezerSink {
+            session,
+            settings,
+            added: HashSet::new(),
+        }
+    }
+
+    /// Deezer reports errors in the body of successful responses
+    async fn request(&self, method: &str, url: &str) -> Result<Value, Box<dyn Error>> {
+        let request = soup::Message::new(method, url)?;
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        let response: Value = serde_json::from_slice(&response[..]).unwrap_or_default();
+        if request.status_code() != 200 || response["error"].is_object() {
+            return Err(format!(
+                "Deezer returned status {}: {}",
+                request.status_code(),
+                response["error"]["message"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+        Ok(response)
+    }
+
+    pub async fn add_song(
+        &mut self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(track_id) = self.resolve(&TrackQuery::from_message(message)).await? else {
+            info!("{} was not found on Deezer", message.song_name);
+            return Ok(());
+        };
+        if self.added.contains(&track_id) {
+            debug!("{} was already added to Deezer", message.song_name);
+            return Ok(());
+        }
+
+        self.request(
+            "POST",
+            &format!(
+                "{}/playlist/{}/tracks?access_token={}&songs={}",
+                API_URL,
+                glib::Uri::escape_string(&self.settings.playlist, None, false),
+                glib::Uri::escape_string(&self.settings.access_token, None, false),
+                track_id
+            ),
+        )
+        .await?;
+        info!("Added {} to the Deezer playlist", message.song_name);
+        self.added.insert(track_id);
+        Ok(())
+    }
+}
+
+fn track_id(track: &Value) -> Option<String> {
+    track["id"].as_u64().map(|id| id.to_string())
+}
+
+impl TrackResolver for DeezerSink {
+    fn find_by_isrc<'a>(&'a self, isrc: &'a str) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/track/isrc:{}",
+                API_URL,
+                glib::Uri::escape_string(isrc, None, false)
+            );
+            // Unknown ISRCs are reported as errors
+            Ok(self
+                .request("GET", &url)
+                .await
+                .ok()
+                .as_ref()
+                .and_then(track_id))
+        })
+    }
+
+    fn search<'a>(&'a self, query: &'a TrackQuery) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let terms = format!(
+                "artist:\"{}\" track:\"{}\"",
+                query.artist.replace('"', ""),
+                query.title.replace('"', "")
+            );
+            let url = format!(
+                "{}/search?limit=1&q={}",
+                API_URL,
+                glib::Uri::escape_string(&terms, None, false)
+            );
+            let response = self.request("GET", &url).await?;
+            Ok(first_result(&response["data"], "Deezer")?.and_then(track_id))
+        })
+    }
+}
+
+impl RecognitionSink for DeezerSink {
+    fn name(&self) -> &'static str {
+        "Deezer"
+    }
+
+    fn searches_catalog(&self) -> bool {
+        true
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.add_song(message))
+    }
+}
@@ -1,10 +1,199 @@
 use log::{debug, error};
 use std::io::BufReader;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
 
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 
-use std::process::Command;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt as UnixCommandExt;
+
+/// FFMpeg parses files from anywhere (downloads, streams, `scan` folders),
+/// so it runs with limits that keep a hostile or broken file from hanging
+/// or exhausting the system: a deadline after which it is killed, a cap on
+/// the size of the decoded file and, on Unix, on the CPU time and memory it
+/// may use. On Linux it also can't open network sockets, through a seccomp
+/// filter, and on every system it may only read local files.
+const DECODING_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// About 90 minutes of 48 KHz stereo audio, once decoded to 32-bit samples
+const MAX_DECODED_SIZE: u64 = 2 << 30;
+
+#[cfg(unix)]
+const MAX_CPU_SECONDS: u64 = 300;
+
+#[cfg(unix)]
+const MAX_MEMORY: u64 = 1 << 30;
+
+#[cfg(unix)]
+fn set_resource_limits(max_memory: u64) -> std::io::Result<()> {
+    let limits = [
+        (libc::RLIMIT_CPU, MAX_CPU_SECONDS),
+        (libc::RLIMIT_DATA, max_memory),
+        // FFMpeg stops by itself at the size given with "-fs", the margin
+        // is for the headers it rewrites when finishing
+        (libc::RLIMIT_FSIZE, MAX_DECODED_SIZE + (1 << 20)),
+        (libc::RLIMIT_CORE, 0),
+    ];
+    for (resource, value) in limits {
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The seccomp filter denying the creation and use of sockets to FFMpeg.
+/// Processes of another architecture (e.g. 32-bit system calls) are killed,
+/// as the filter only knows the system call numbers of the native one, as
+/// are the x32 system calls, which share the architecture of x86_64 but
+/// have their own numbers. Built before forking, as allocating between fork
+/// and exec could deadlock.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn network_filter() -> Vec<libc::sock_filter> {
+    // From linux/filter.h and linux/audit.h
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    #[cfg(target_arch = "x86_64")]
+    const BPF_JMP_JGE_K: u16 = 0x35;
+    const BPF_RET_K: u16 = 0x06;
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000003e; // AUDIT_ARCH_X86_64
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc00000b7; // AUDIT_ARCH_AARCH64
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x40000000;
+    // Offsets in struct seccomp_data
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000;
+
+    let statement = |code, k| libc::sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |k, jt, jf| libc::sock_filter {
+        code: BPF_JMP_JEQ_K,
+        jt,
+        jf,
+        k,
+    };
+    let deny = libc::SECCOMP_RET_ERRNO | libc::EACCES as u32;
+    let mut filter = vec![
+        statement(BPF_LD_W_ABS, ARCH_OFFSET),
+        jump(AUDIT_ARCH, 1, 0),
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD_W_ABS, NR_OFFSET),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        libc::sock_filter {
+            code: BPF_JMP_JGE_K,
+            jt: 0,
+            jf: 1,
+            k: X32_SYSCALL_BIT,
+        },
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+    ]);
+    for syscall in [
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_connect,
+        libc::SYS_sendto,
+        libc::SYS_sendmsg,
+        libc::SYS_sendmmsg,
+    ] {
+        filter.extend([jump(syscall as u32, 0, 1), statement(BPF_RET_K, deny)]);
+    }
+    filter.push(statement(BPF_RET_K, libc::SECCOMP_RET_ALLOW));
+    filter
+}
+
+/// Install the filter of `network_filter`, without allocating
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn deny_network(filter: &[libc::sock_filter]) -> std::io::Result<()> {
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+
+    unsafe {
+        // Required to install a filter without privileges
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            ) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Apply the limits above to the child process, between fork and exec
+fn sandbox(command: &mut Command) {
+    #[cfg(unix)]
+    unsafe {
+        // Computed before forking, see `resource_limits`
+        let max_memory = crate::core::resource_limits::memory_limit()
+            .map_or(MAX_MEMORY, |limit| limit.min(MAX_MEMORY));
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        let filter = network_filter();
+        command.pre_exec(move || {
+            set_resource_limits(max_memory)?;
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            deny_network(&filter)?;
+            Ok(())
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// Whether the process exited successfully before the deadline, after
+/// which it is killed
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> std::io::Result<bool> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                error!("ffmpeg returned an error: {:?}", status);
+            }
+            return Ok(status.success());
+        }
+        if start.elapsed() > timeout {
+            error!(
+                "ffmpeg took more than {} seconds, stopping it",
+                timeout.as_secs()
+            );
+            child.kill()?;
+            child.wait()?;
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
 
 /// This function used to decode a file with FFMpeg, if it is installed on
 /// the system, in the case where Rodio can't decode the concerned format
@@ -54,13 +243,33 @@ pub fn decode_with_ffmpeg(file_path: &str) -> Option<rodio::Decoder<BufReader<st
 
         let sink_file_path = sink_file.into_temp_path();
 
-        // Try to convert the input video or audio file to a standard
-        // .WAV s16le PCM file using FFMpeg, and pass it to Rodio
-        // later in the case where it succeeded
+        // Try to convert the input video or audio file to a .WAV f32le
+        // PCM file using FFMpeg (so that the audio is not quantized before
+        // the signature generation), and pass it to Rodio later in the
+        // case where it succeeded
 
         let mut command = Command::new(ffmpeg_path);
 
-        let command = command.args(["-y", "-i", file_path, sink_file_path.to_str().unwrap()]);
+        let max_decoded_size = MAX_DECODED_SIZE.to_string();
+        let command = command
+            .args([
+                "-nostdin",
+                "-hide_banner",
+                // Playlists and containers may reference other files or URLs
+                "-protocol_whitelist",
+                "file",
+                "-y",
+                "-i",
+                file_path,
+                "-c:a",
+                "pcm_f32le",
+                "-fs",
+                &max_decoded_size,
+                sink_file_path.to_str().unwrap(),
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
 
         debug!("Spawning ffmpeg: {:?}", command);
 
@@ -69,19 +278,19 @@ pub fn decode_with_ffmpeg(file_path: &str) -> Option<rodio::Decoder<BufReader<st
         #[cfg(windows)]
         let command = command.creation_flags(0x00000008);
 
-        match command.output() {
-            Ok(process) => {
-                if process.status.success() {
-                    return Some(
-                        rodio::Decoder::new(BufReader::new(
-                            std::fs::File::open(sink_file_path.to_str().unwrap()).unwrap(),
-                        ))
-                        .unwrap(),
-                    );
-                } else {
-                    error!("ffmpeg returned an error: {:?}", process.status);
-                }
+        sandbox(command);
+
+        match command
+            .spawn()
+            .and_then(|child| wait_with_timeout(child, DECODING_TIMEOUT))
+        {
+            Ok(true) => {
+                return rodio::Decoder::new(BufReader::new(
+                    std::fs::File::open(sink_file_path.to_str().unwrap()).ok()?,
+                ))
+                .ok();
             }
+            Ok(false) => {}
             Err(err) => {
                 error!("Could not spawn ffmpeg: {:?}", err);
             }
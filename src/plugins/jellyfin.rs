@@ -0,0 +1,157 @@
+//! Add recognized songs that are already in a Jellyfin music library to one
+//! of its collections, so that the discoveries which the user owns can be
+//! found back from Jellyfin. The API key is created from the administration
+//! dashboard, and the collection from any item of the library.
+//!
+//! See: https://api.jellyfin.org
+
+use glib::source::Priority;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::Value;
+use soup::prelude::SessionExt;
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::service_resolution::TrackQuery;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+#[derive(Clone, Deserialize)]
+pub struct JellyfinSettings {
+    /// Address of the server, e.g. "http://localhost:8096"
+    pub url: String,
+    pub api_key: String,
+    /// Identifier of the collection, from its link
+    pub collection: String,
+}
+
+pub struct JellyfinSink {
+    session: soup::Session,
+    settings: JellyfinSettings,
+    /// Items already collected during this session
+    added: HashSet<String>,
+}
+
+/// The item of the search results with the artist and title of the song
+fn owned_item_id(response: &Value, query: &TrackQuery) -> Option<String> {
+    response["Items"]
+        .as_array()?
+        .iter()
+        .find(|item| {
+            let title = item["Name"].as_str().unwrap_or_default();
+            item["Artists"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .chain(std::iter::once(&item["AlbumArtist"]))
+                .filter_map(|artist| artist.as_str())
+                .any(|artist| query.matches(artist, title))
+        })
+        .and_then(|item| item["Id"].as_str())
+        .map(|id| id.to_string())
+}
+
+impl JellyfinSink {
+    pub fn new(settings: JellyfinSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        JellyfinSink {
+            session,
+            settings,
+            added: HashSet::new(),
+        }
+    }
+
+    /// Call an endpoint of the server, with the parameters already escaped
+    async fn request(&self, method: &str, path: &str) -> Result<Value, Box<dyn Error>> {
+        let url = format!("{}{}", self.settings.url.trim_end_matches('/'), path);
+        let request = soup::Message::new(method, &url)?;
+        request.request_headers().unwrap().append(
+            "Authorization",
+            &format!(
+                "MediaBrowser Client=\"SongRec\", Version=\"{}\", Token=\"{}\"",
+                env!("CARGO_PKG_VERSION"),
+                self.settings.api_key
+            ),
+        );
+
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        if !(200..300).contains(&request.status_code()) {
+            return Err(format!("Jellyfin returned status {}", request.status_code()).into());
+        }
+        Ok(serde_json::from_slice(&response[..]).unwrap_or_default())
+    }
+
+    pub async fn collect_song(
+        &mut self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        let query = TrackQuery::from_message(message);
+        let response = self
+            .request(
+                "GET",
+                &format!(
+                    "/Items?searchTerm={}&includeItemTypes=Audio&recursive=true&limit=20",
+                    glib::Uri::escape_string(&query.title, None, false)
+                ),
+            )
+            .await?;
+        let Some(item_id) = owned_item_id(&response, &query) else {
+            debug!("{} is not in the Jellyfin library", message.song_name);
+            return Ok(());
+        };
+        if self.added.contains(&item_id) {
+            return Ok(());
+        }
+
+        self.request(
+            "POST",
+            &format!(
+                "/Collections/{}/Items?ids={}",
+                glib::Uri::escape_string(&self.settings.collection, None, false),
+                item_id
+            ),
+        )
+        .await?;
+        info!("Added {} to the Jellyfin collection", message.song_name);
+        self.added.insert(item_id);
+        Ok(())
+    }
+}
+
+impl RecognitionSink for JellyfinSink {
+    fn name(&self) -> &'static str {
+        "Jellyfin"
+    }
+
+    fn searches_catalog(&self) -> bool {
+        true
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.collect_song(message))
+    }
+}
+
+#[test]
+fn test_owned_item_id() {
+    let query = TrackQuery {
+        isrc: None,
+        artist: "Daft Punk".to_string(),
+        title: "Around the World".to_string(),
+    };
+    let response: Value = serde_json::from_str(
+        r#"{"Items": [
+            {"Id": "a1", "Name": "Around the World", "Artists": ["ATC"]},
+            {"Id": "b2", "Name": "Around The World", "Artists": [], "AlbumArtist": "Daft Punk"}
+        ]}"#,
+    )
+    .unwrap();
+    assert_eq!(owned_item_id(&response, &query).as_deref(), Some("b2"));
+}
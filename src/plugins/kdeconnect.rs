@@ -0,0 +1,110 @@
+//! Forward recognized songs to paired phones through the D-Bus interface of
+//! the KDE Connect daemon ("kdeconnectd"), using its "ping" plugin, which
+//! displays the custom message as a notification on the phone.
+
+use gettextrs::gettext;
+use gio::prelude::*;
+use log::{debug, error, warn};
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+const KDECONNECT_BUS_NAME: &str = "org.kde.kdeconnect";
+const DBUS_TIMEOUT_MSEC: i32 = 5000;
+
+/// Return the (identifier, name) pairs of the paired devices which are
+/// currently reachable.
+async fn reachable_devices(
+    connection: &gio::DBusConnection,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let reply = connection
+        .call_future(
+            Some(KDECONNECT_BUS_NAME),
+            "/modules/kdeconnect",
+            "org.kde.kdeconnect.daemon",
+            "deviceNames",
+            Some(&(true, true).to_variant()),
+            Some(glib::VariantTy::new("(a{ss})").unwrap()),
+            gio::DBusCallFlags::NONE,
+            DBUS_TIMEOUT_MSEC,
+        )
+        .await?;
+
+    let (devices,) = reply
+        .get::<(HashMap<String, String>,)>()
+        .ok_or("Unexpected reply from the KDE Connect daemon")?;
+    Ok(devices)
+}
+
+async fn send_ping(
+    connection: &gio::DBusConnection,
+    device_id: &str,
+    text: &str,
+) -> Result<(), Box<dyn Error>> {
+    connection
+        .call_future(
+            Some(KDECONNECT_BUS_NAME),
+            &format!("/modules/kdeconnect/devices/{}/ping", device_id),
+            "org.kde.kdeconnect.device.ping",
+            "sendPing",
+            Some(&(text,).to_variant()),
+            None,
+            gio::DBusCallFlags::NONE,
+            DBUS_TIMEOUT_MSEC,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Push a recognized song to every reachable paired device, or only to the
+/// device whose identifier or name matches `device_filter` when provided.
+pub async fn push_song(
+    message: &SongRecognizedMessage,
+    device_filter: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let connection = gio::bus_get_future(gio::BusType::Session).await?;
+    let devices = reachable_devices(&connection).await?;
+
+    let text = gettext("Song recognized: %s").replace(
+        "%s",
+        &format!("{} - {}", message.artist_name, message.song_name),
+    );
+
+    let mut sent = false;
+    for (device_id, device_name) in devices.iter() {
+        if let Some(filter) = device_filter {
+            if filter != device_id && filter != device_name {
+                continue;
+            }
+        }
+        debug!("Pushing song to KDE Connect device {}", device_name);
+        if let Err(error) = send_ping(&connection, device_id, &text).await {
+            error!(
+                "Could not push song to KDE Connect device {}: {}",
+                device_name, error
+            );
+        }
+        sent = true;
+    }
+
+    if !sent {
+        warn!("{}", gettext("No reachable KDE Connect device to notify"));
+    }
+    Ok(())
+}
+
+pub struct KdeConnectSink {
+    pub device: Option<String>,
+}
+
+impl RecognitionSink for KdeConnectSink {
+    fn name(&self) -> &'static str {
+        "KDE Connect"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(push_song(message, self.device.as_deref()))
+    }
+}
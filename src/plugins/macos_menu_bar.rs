@@ -0,0 +1,78 @@
+//! Menu bar item (NSStatusItem) of the macOS build, the counterpart of the
+//! KSNI notification icon of Linux: it shows the last recognized song and
+//! lets the window be closed while SongRec keeps listening.
+
+use crate::core::thread_messages::GUIMessage;
+use gettextrs::gettext;
+use std::error::Error;
+use tray_icon::menu::{Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+pub struct MenuBarItem {
+    // Removed from the menu bar when dropped
+    _tray_icon: TrayIcon,
+    current_song: MenuItem,
+}
+
+impl MenuBarItem {
+    /// This must be called from the main thread, once the application
+    /// started
+    pub fn new(gui_tx: async_channel::Sender<GUIMessage>) -> Result<Self, Box<dyn Error>> {
+        let image = image::load_from_memory_with_format(
+            include_bytes!("../../packaging/rootfs/usr/share/icons/hicolor/32x32/apps/re.fossplant.songrec-symbolic.png"),
+            image::ImageFormat::Png,
+        )?
+        .into_rgba8();
+        let (width, height) = image.dimensions();
+        let icon = Icon::from_rgba(image.into_raw(), width, height)?;
+
+        let current_song = MenuItem::new(gettext("No song recognized yet"), false, None);
+        let recognize_now = MenuItem::new(gettext("Recognize Now"), true, None);
+        let open_window = MenuItem::new(gettext("Open SongRec"), true, None);
+        let show_preferences = MenuItem::new(gettext("Preferences…"), true, None);
+        let quit = MenuItem::new(gettext("Quit SongRec"), true, None);
+
+        let menu = Menu::new();
+        menu.append_items(&[
+            &current_song,
+            &PredefinedMenuItem::separator(),
+            &recognize_now,
+            &open_window,
+            &show_preferences,
+            &PredefinedMenuItem::separator(),
+            &quit,
+        ])?;
+
+        // The events are received on the main thread, but outside of GTK
+        let actions: Vec<(MenuId, fn() -> GUIMessage)> = vec![
+            (recognize_now.id().clone(), || GUIMessage::RecognizeNow),
+            (open_window.id().clone(), || GUIMessage::ShowWindow),
+            (show_preferences.id().clone(), || {
+                GUIMessage::ShowPreferences
+            }),
+            (quit.id().clone(), || GUIMessage::QuitApplication),
+        ];
+        MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+            if let Some((_, message)) = actions.iter().find(|(id, _)| *id == event.id) {
+                gui_tx.try_send(message()).unwrap();
+            }
+        }));
+
+        let tray_icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_icon(icon)
+            // Drawn in the color of the menu bar, as the other items
+            .with_icon_as_template(true)
+            .with_tooltip("SongRec")
+            .build()?;
+
+        Ok(MenuBarItem {
+            _tray_icon: tray_icon,
+            current_song,
+        })
+    }
+
+    pub fn show_song(&self, song_name: &str) {
+        self.current_song.set_text(song_name);
+    }
+}
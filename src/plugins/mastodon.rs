@@ -0,0 +1,239 @@
+//! Post recognized songs to a Mastodon (or compatible Fediverse) account
+//! through its REST API, for "now playing" bot accounts of radio stations.
+//! Posts are rate-limited, so that short songs or jingles don't flood the
+//! followers of the account. The posts refused by the rate limit of the
+//! instance are queued, and sent once it is reset.
+//!
+//! See: https://docs.joinmastodon.org/methods/statuses/#create
+
+use glib::source::Priority;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use soup::prelude::SessionExt;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+use crate::utils::share_text::{
+    default_share_template, render_message_hashtags, render_message_share_text,
+};
+
+/// Mastodon counts characters rather than bytes
+const MAX_STATUS_CHARACTERS: usize = 500;
+
+/// Older posts are dropped beyond this, when the instance keeps refusing them
+const MAX_QUEUED_POSTS: usize = 20;
+
+/// Used when the instance doesn't tell when its rate limit is reset
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Deserialize)]
+pub struct MastodonSettings {
+    /// e.g. "https://mastodon.social"
+    pub instance_url: String,
+    pub access_token: String,
+    #[serde(default = "default_share_template")]
+    pub template: String,
+    /// e.g. "#NowPlaying #{genre}"
+    #[serde(default = "default_hashtags")]
+    pub hashtags: String,
+    /// "public", "unlisted", "private" or "direct"
+    #[serde(default = "default_visibility")]
+    pub visibility: String,
+    #[serde(default = "default_min_interval_secs")]
+    pub min_interval_secs: u64,
+}
+
+pub fn default_hashtags() -> String {
+    "#NowPlaying".to_string()
+}
+
+pub fn default_visibility() -> String {
+    "unlisted".to_string()
+}
+
+pub fn default_min_interval_secs() -> u64 {
+    180
+}
+
+enum PostResult {
+    Posted,
+    /// Until the rate limit of the instance is reset
+    RateLimited(Duration),
+}
+
+/// The "X-RateLimit-Reset" header gives the date of the reset
+fn retry_delay(request: &soup::Message) -> Duration {
+    request
+        .response_headers()
+        .and_then(|headers| headers.one("X-RateLimit-Reset"))
+        .and_then(|reset| chrono::DateTime::parse_from_rfc3339(&reset).ok())
+        .map(|reset| {
+            (reset.to_utc() - chrono::Utc::now())
+                .to_std()
+                .unwrap_or_default()
+        })
+        .unwrap_or(DEFAULT_RETRY_DELAY)
+        .max(Duration::from_secs(1))
+}
+
+async fn send_status(
+    session: &soup::Session,
+    settings: &MastodonSettings,
+    status: &str,
+) -> Result<PostResult, Box<dyn Error>> {
+    let url = format!(
+        "{}/api/v1/statuses",
+        settings.instance_url.trim_end_matches('/')
+    );
+    let body = json!({
+        "status": status,
+        "visibility": settings.visibility,
+    })
+    .to_string();
+
+    let request = soup::Message::new("POST", &url)?;
+    request.request_headers().unwrap().append(
+        "Authorization",
+        &format!("Bearer {}", settings.access_token),
+    );
+    request.set_request_body_from_bytes(
+        Some("application/json"),
+        Some(&glib::Bytes::from_owned(body.into_bytes())),
+    );
+
+    let response = session
+        .send_and_read_future(&request, Priority::DEFAULT)
+        .await?;
+    if request.status_code() == 429 {
+        return Ok(PostResult::RateLimited(retry_delay(&request)));
+    }
+    if request.status_code() != 200 {
+        return Err(format!(
+            "Mastodon returned status {}: {}",
+            request.status_code(),
+            String::from_utf8_lossy(&response[..])
+        )
+        .into());
+    }
+    Ok(PostResult::Posted)
+}
+
+pub struct MastodonPoster {
+    session: soup::Session,
+    settings: MastodonSettings,
+    last_post: Option<Instant>,
+    /// Posts waiting for the rate limit of the instance to be reset, sent
+    /// in order by the task started by `retry_later`
+    queue: Rc<RefCell<VecDeque<String>>>,
+}
+
+impl MastodonPoster {
+    pub fn new(settings: MastodonSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        MastodonPoster {
+            session,
+            settings,
+            last_post: None,
+            queue: Rc::new(RefCell::new(VecDeque::new())),
+        }
+    }
+
+    fn enqueue(&self, status: String) {
+        let mut queue = self.queue.borrow_mut();
+        if queue.len() >= MAX_QUEUED_POSTS {
+            warn!("Too many posts refused by the Mastodon instance, dropping the oldest one");
+            queue.pop_front();
+        }
+        queue.push_back(status);
+    }
+
+    fn retry_later(&self, delay: Duration) {
+        info!(
+            "Rate-limited by the Mastodon instance, posting again in {} seconds",
+            delay.as_secs()
+        );
+        let session = self.session.clone();
+        let settings = self.settings.clone();
+        let queue = self.queue.clone();
+        glib::spawn_future_local(async move {
+            let mut delay = delay;
+            loop {
+                glib::timeout_future(delay).await;
+                let Some(status) = queue.borrow().front().cloned() else {
+                    break;
+                };
+                match send_status(&session, &settings, &status).await {
+                    Ok(PostResult::Posted) => {
+                        info!("Posted a queued song to Mastodon");
+                        queue.borrow_mut().pop_front();
+                        delay = Duration::from_secs(1);
+                    }
+                    Ok(PostResult::RateLimited(retry_delay)) => delay = retry_delay,
+                    Err(error) => {
+                        error!("Could not post a queued song to Mastodon: {}", error);
+                        queue.borrow_mut().pop_front();
+                        delay = Duration::from_secs(1);
+                    }
+                }
+            }
+        });
+    }
+
+    pub async fn post_song(
+        &mut self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(last_post) = self.last_post {
+            if last_post.elapsed() < Duration::from_secs(self.settings.min_interval_secs) {
+                debug!(
+                    "Not posting {} to Mastodon yet: rate-limited",
+                    message.song_name
+                );
+                return Ok(());
+            }
+        }
+
+        let mut status = render_message_share_text(&self.settings.template, message);
+        let hashtags = render_message_hashtags(&self.settings.hashtags, message);
+        if !hashtags.is_empty() {
+            status = format!("{}\n\n{}", status, hashtags);
+        }
+        let status: String = status.chars().take(MAX_STATUS_CHARACTERS).collect();
+
+        // Keep the order of the posts while the queued ones wait
+        if !self.queue.borrow().is_empty() {
+            debug!("Queued {} for Mastodon", message.song_name);
+            self.enqueue(status);
+            self.last_post = Some(Instant::now());
+            return Ok(());
+        }
+        match send_status(&self.session, &self.settings, &status).await? {
+            PostResult::Posted => info!("Posted {} to Mastodon", message.song_name),
+            PostResult::RateLimited(delay) => {
+                self.enqueue(status);
+                self.retry_later(delay);
+            }
+        }
+        self.last_post = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl RecognitionSink for MastodonPoster {
+    fn name(&self) -> &'static str {
+        "Mastodon"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.post_song(message))
+    }
+}
@@ -0,0 +1,97 @@
+//! Announce recognized songs in a Matrix room, as a "now playing" bot for
+//! community radio channels. Messages are sent as notices, which other bots
+//! are expected to ignore.
+//!
+//! See: https://spec.matrix.org/latest/client-server-api/#put_matrixclientv3roomsroomidsendeventtypetxnid
+
+use glib::source::Priority;
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use soup::prelude::SessionExt;
+use std::error::Error;
+use uuid::Uuid;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+use crate::utils::share_text::{default_share_template, render_message_share_text};
+
+#[derive(Clone, Deserialize)]
+pub struct MatrixSettings {
+    /// e.g. "https://matrix.org"
+    pub homeserver_url: String,
+    pub access_token: String,
+    /// e.g. "!roomid:matrix.org"; the bot account must have joined it
+    pub room_id: String,
+    #[serde(default = "default_share_template")]
+    pub template: String,
+}
+
+pub struct MatrixAnnouncer {
+    session: soup::Session,
+    settings: MatrixSettings,
+}
+
+impl MatrixAnnouncer {
+    pub fn new(settings: MatrixSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        MatrixAnnouncer { session, settings }
+    }
+
+    pub async fn announce_song(
+        &self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        // The transaction identifier lets the homeserver deduplicate retries
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.settings.homeserver_url.trim_end_matches('/'),
+            glib::Uri::escape_string(&self.settings.room_id, None, false),
+            Uuid::new_v4().simple()
+        );
+        let body = json!({
+            "msgtype": "m.notice",
+            "body": render_message_share_text(&self.settings.template, message),
+        })
+        .to_string();
+
+        let request = soup::Message::new("PUT", &url)?;
+        request.request_headers().unwrap().append(
+            "Authorization",
+            &format!("Bearer {}", self.settings.access_token),
+        );
+        request.set_request_body_from_bytes(
+            Some("application/json"),
+            Some(&glib::Bytes::from_owned(body.into_bytes())),
+        );
+
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        if request.status_code() != 200 {
+            return Err(format!(
+                "The Matrix homeserver returned status {}: {}",
+                request.status_code(),
+                String::from_utf8_lossy(&response[..])
+            )
+            .into());
+        }
+
+        info!("Announced {} in the Matrix room", message.song_name);
+        Ok(())
+    }
+}
+
+impl RecognitionSink for MatrixAnnouncer {
+    fn name(&self) -> &'static str {
+        "Matrix"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.announce_song(message))
+    }
+}
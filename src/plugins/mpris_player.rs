@@ -9,7 +9,7 @@ use std::fs;
 use mpris_server::{Metadata, PlaybackStatus, Player};
 
 use crate::core::thread_messages::SongRecognizedMessage;
-use crate::utils::filesystem_operations::obtain_cache_directory;
+use crate::utils::filesystem_operations::{is_incognito, obtain_cache_directory};
 use std::os::unix::fs::MetadataExt;
 use std::time::SystemTime;
 
@@ -92,7 +92,7 @@ pub async fn update_song(
             process_uid, timestamp, mime_ext
         ));
         debug!("Writing cover file to {:?}", tmp);
-        if fs::write(&tmp, buf).is_ok() {
+        if !is_incognito() && fs::write(&tmp, buf).is_ok() {
             // Use file:// URL for better compatibility with MPRIS clients
             metadata = metadata.art_url(format!("file://{}", tmp.display()));
             *last_cover_path = Some(tmp);
@@ -0,0 +1,250 @@
+//! Publish recognized songs to a MQTT broker, and announce them to Home
+//! Assistant through its MQTT discovery protocol, so that a "SongRec now
+//! playing" sensor appears without any manual configuration.
+//!
+//! See: https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+
+use log::{debug, error, info};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+use crate::utils::clock;
+
+#[derive(Clone, Deserialize)]
+pub struct MqttSettings {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_base_topic")]
+    pub base_topic: String,
+    /// Set to None in order to disable Home Assistant discovery
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: Option<String>,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_base_topic() -> String {
+    "songrec".to_string()
+}
+
+fn default_discovery_prefix() -> Option<String> {
+    Some("homeassistant".to_string())
+}
+
+impl MqttSettings {
+    /// Parse a "host" or "host:port" broker address
+    pub fn parse_broker(broker: &str) -> (String, u16) {
+        match broker.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => {
+                (host.to_string(), port.parse().unwrap())
+            }
+            _ => (broker.to_string(), 1883),
+        }
+    }
+}
+
+pub struct MqttPublisher {
+    client: Client,
+    base_topic: String,
+    last_song: Option<serde_json::Value>,
+    listening: bool,
+}
+
+impl MqttPublisher {
+    pub fn connect(settings: MqttSettings) -> Self {
+        let node_id = format!(
+            "songrec_{}",
+            glib::host_name()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+        );
+        let availability_topic = format!("{}/status", settings.base_topic);
+
+        let mut options = MqttOptions::new(node_id.clone(), &settings.host, settings.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(
+            &availability_topic,
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(ref username) = settings.username {
+            options.set_credentials(username, settings.password.clone().unwrap_or_default());
+        }
+
+        let (client, mut connection) = Client::new(options, 16);
+
+        // Messages to (re-)send each time that we are connected to the broker
+        let mut on_connect_messages: Vec<(String, String)> =
+            vec![(availability_topic.clone(), "online".to_string())];
+
+        if let Some(ref discovery_prefix) = settings.discovery_prefix {
+            on_connect_messages.extend(Self::discovery_messages(
+                discovery_prefix,
+                &settings.base_topic,
+                &node_id,
+            ));
+        }
+
+        let connection_client = client.clone();
+        let host = settings.host.clone();
+
+        std::thread::Builder::new()
+            .name("mqtt".to_string())
+            .spawn(move || {
+                for notification in connection.iter() {
+                    match notification {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            info!("Connected to the MQTT broker at {}", host);
+                            for (topic, payload) in &on_connect_messages {
+                                if let Err(error) = connection_client.try_publish(
+                                    topic,
+                                    QoS::AtLeastOnce,
+                                    true,
+                                    payload.clone(),
+                                ) {
+                                    error!("Could not publish to MQTT: {:?}", error);
+                                }
+                            }
+                        }
+                        Ok(event) => {
+                            debug!("MQTT event: {:?}", event);
+                        }
+                        Err(error) => {
+                            error!("MQTT connection error: {:?}", error);
+                            std::thread::sleep(Duration::from_secs(5));
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        Self {
+            client,
+            base_topic: settings.base_topic,
+            last_song: None,
+            listening: false,
+        }
+    }
+
+    fn discovery_messages(
+        discovery_prefix: &str,
+        base_topic: &str,
+        node_id: &str,
+    ) -> Vec<(String, String)> {
+        let device = json!({
+            "identifiers": [node_id],
+            "name": "SongRec",
+            "manufacturer": "SongRec",
+            "model": "SongRec",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let availability_topic = format!("{}/status", base_topic);
+        let state_topic = format!("{}/now_playing", base_topic);
+
+        vec![
+            (
+                format!("{}/sensor/{}/now_playing/config", discovery_prefix, node_id),
+                json!({
+                    "name": "Now playing",
+                    "unique_id": format!("{}_now_playing", node_id),
+                    "object_id": format!("{}_now_playing", node_id),
+                    "icon": "mdi:music",
+                    "state_topic": state_topic,
+                    "value_template": "{{ value_json.artist }} - {{ value_json.title }}",
+                    "json_attributes_topic": state_topic,
+                    "availability_topic": availability_topic,
+                    "device": device,
+                })
+                .to_string(),
+            ),
+            (
+                format!(
+                    "{}/binary_sensor/{}/listening/config",
+                    discovery_prefix, node_id
+                ),
+                json!({
+                    "name": "Listening",
+                    "unique_id": format!("{}_listening", node_id),
+                    "object_id": format!("{}_listening", node_id),
+                    "icon": "mdi:microphone",
+                    "state_topic": state_topic,
+                    "value_template": "{{ 'ON' if value_json.listening else 'OFF' }}",
+                    "availability_topic": availability_topic,
+                    "device": device,
+                })
+                .to_string(),
+            ),
+        ]
+    }
+
+    fn publish_state(&mut self) {
+        let mut state = self
+            .last_song
+            .clone()
+            .unwrap_or_else(|| json!({ "title": null, "artist": null }));
+        state["listening"] = json!(self.listening);
+
+        if let Err(error) = self.client.try_publish(
+            format!("{}/now_playing", self.base_topic),
+            QoS::AtLeastOnce,
+            true,
+            state.to_string(),
+        ) {
+            error!("Could not publish to MQTT: {:?}", error);
+        }
+    }
+}
+
+impl RecognitionSink for MqttPublisher {
+    fn name(&self) -> &'static str {
+        "MQTT"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        self.last_song = Some(json!({
+            "title": message.song_name,
+            "artist": message.artist_name,
+            "album": message.album_name,
+            "genre": message.genre,
+            "release_year": message.release_year,
+            "artwork_url": message.cover_url,
+            "track_key": message.track_key,
+            "recognized_at": clock::now_zoned().to_rfc3339(),
+        }));
+        self.publish_state();
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn set_listening(&mut self, listening: bool) {
+        if self.listening != listening {
+            self.listening = listening;
+            self.publish_state();
+        }
+    }
+
+    fn close(&mut self) {
+        self.set_listening(false);
+        self.client
+            .try_publish(
+                format!("{}/status", self.base_topic),
+                QoS::AtLeastOnce,
+                true,
+                "offline",
+            )
+            .ok();
+        self.client.try_disconnect().ok();
+    }
+}
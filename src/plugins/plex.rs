@@ -0,0 +1,205 @@
+//! Add recognized songs that are already in a Plex music library to one of
+//! its collections, so that the discoveries which the user owns can be
+//! found back from Plex.
+//!
+//! See: https://plexapi.dev/api-reference/library/get-media-metadata
+
+use glib::source::Priority;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::Value;
+use soup::prelude::SessionExt;
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::service_resolution::TrackQuery;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+/// The type of tracks in the Plex API
+const TRACK_TYPE: u32 = 10;
+
+/// The track with the artist and title of the song among the results of a
+/// search
+fn find_track<'a>(response: &'a Value, query: &TrackQuery) -> Option<&'a Value> {
+    response["MediaContainer"]["Metadata"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|track| {
+            // Tracks of compilations are credited to their own artist
+            let artist = track["originalTitle"]
+                .as_str()
+                .filter(|artist| !artist.is_empty())
+                .or(track["grandparentTitle"].as_str())
+                .unwrap_or_default();
+            query.matches(artist, track["title"].as_str().unwrap_or_default())
+        })
+}
+
+fn default_collection() -> String {
+    "Recognized by SongRec".to_string()
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PlexSettings {
+    /// Address of the server, e.g. "http://localhost:32400"
+    pub url: String,
+    pub token: String,
+    /// Name of the collection, created by Plex when needed
+    #[serde(default = "default_collection")]
+    pub collection: String,
+}
+
+pub struct PlexSink {
+    session: soup::Session,
+    settings: PlexSettings,
+    /// Tracks already collected during this session
+    added: HashSet<String>,
+}
+
+impl PlexSink {
+    pub fn new(settings: PlexSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        PlexSink {
+            session,
+            settings,
+            added: HashSet::new(),
+        }
+    }
+
+    /// Call an endpoint of the server, with the parameters already escaped
+    async fn request(&self, method: &str, path: &str) -> Result<Value, Box<dyn Error>> {
+        let url = format!("{}{}", self.settings.url.trim_end_matches('/'), path);
+        let request = soup::Message::new(method, &url)?;
+        let headers = request.request_headers().unwrap();
+        headers.append("X-Plex-Token", &self.settings.token);
+        headers.append("X-Plex-Product", "SongRec");
+        headers.append("Accept", "application/json");
+
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        if !(200..300).contains(&request.status_code()) {
+            return Err(format!("Plex returned status {}", request.status_code()).into());
+        }
+        Ok(serde_json::from_slice(&response[..]).unwrap_or_default())
+    }
+
+    /// The track of the library with the artist and title of the song
+    async fn find_owned_track(&self, query: &TrackQuery) -> Result<Option<Value>, Box<dyn Error>> {
+        let response = self
+            .request(
+                "GET",
+                &format!(
+                    "/search?type={}&query={}",
+                    TRACK_TYPE,
+                    glib::Uri::escape_string(&query.title, None, false)
+                ),
+            )
+            .await?;
+        Ok(find_track(&response, query).cloned())
+    }
+
+    pub async fn collect_song(
+        &mut self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(track) = self
+            .find_owned_track(&TrackQuery::from_message(message))
+            .await?
+        else {
+            debug!("{} is not in the Plex library", message.song_name);
+            return Ok(());
+        };
+        let Some(rating_key) = track["ratingKey"].as_str().map(|key| key.to_string()) else {
+            return Ok(());
+        };
+        if self.added.contains(&rating_key) {
+            return Ok(());
+        }
+
+        // The tags of a track are replaced as a whole, so the full metadata
+        // is fetched for the collections it already belongs to
+        let metadata = self
+            .request("GET", &format!("/library/metadata/{}", rating_key))
+            .await?;
+        let metadata = &metadata["MediaContainer"]["Metadata"][0];
+        let mut collections: Vec<String> = metadata["Collection"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|collection| collection["tag"].as_str().map(|tag| tag.to_string()))
+            .collect();
+        if !collections.contains(&self.settings.collection) {
+            collections.push(self.settings.collection.clone());
+            let mut path = format!(
+                "/library/sections/{}/all?type={}&id={}",
+                metadata["librarySectionID"]
+                    .as_u64()
+                    .or(track["librarySectionID"].as_u64())
+                    .ok_or("Plex didn't give the library of the track")?,
+                TRACK_TYPE,
+                rating_key
+            );
+            for (index, collection) in collections.iter().enumerate() {
+                path.push_str(&format!(
+                    "&collection%5B{}%5D.tag.tag={}",
+                    index,
+                    glib::Uri::escape_string(collection, None, false)
+                ));
+            }
+            self.request("PUT", &path).await?;
+            info!(
+                "Added {} to the \"{}\" Plex collection",
+                message.song_name, self.settings.collection
+            );
+        }
+        self.added.insert(rating_key);
+        Ok(())
+    }
+}
+
+impl RecognitionSink for PlexSink {
+    fn name(&self) -> &'static str {
+        "Plex"
+    }
+
+    fn searches_catalog(&self) -> bool {
+        true
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.collect_song(message))
+    }
+}
+
+#[test]
+fn test_find_track() {
+    let response: Value = serde_json::from_str(
+        r#"{"MediaContainer": {"Metadata": [
+            {"ratingKey": "1", "title": "Around the World", "grandparentTitle": "Daft Punk Tribute Band"},
+            {"ratingKey": "2", "title": "Around the World", "grandparentTitle": "Various Artists", "originalTitle": ""},
+            {"ratingKey": "3", "title": "Around the World", "grandparentTitle": "Various Artists", "originalTitle": "Daft Punk"}
+        ]}}"#,
+    )
+    .unwrap();
+    let query = TrackQuery {
+        isrc: None,
+        artist: "Daft Punk".to_string(),
+        title: "Around the World".to_string(),
+    };
+    assert_eq!(
+        find_track(&response, &query).and_then(|track| track["ratingKey"].as_str()),
+        Some("3")
+    );
+    let query = TrackQuery {
+        title: "One More Time".to_string(),
+        ..query
+    };
+    assert!(find_track(&response, &query).is_none());
+}
@@ -0,0 +1,82 @@
+use log::{debug, error, info};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::core::metrics;
+
+/// Serve the `/metrics` endpoint expected by Prometheus scrapers on the given
+/// address (e.g. "127.0.0.1:9898"), from a dedicated thread. Scrapes are rare
+/// and cheap, so connections are handled one at a time.
+pub fn spawn_metrics_server(address: &str) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving Prometheus metrics on http://{}/metrics", address);
+
+    std::thread::Builder::new()
+        .name("prometheus-exporter".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(error) = handle_connection(stream) {
+                            debug!("Error while serving metrics: {:?}", error);
+                        }
+                    }
+                    Err(error) => {
+                        error!("Could not accept metrics connection: {:?}", error);
+                    }
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the request headers, we don't use them
+    let mut header_line = String::new();
+    while reader.read_line(&mut header_line)? > 2 {
+        header_line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path.split('?').next().unwrap()) {
+        ("GET", "/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics::render(),
+        ),
+        ("GET", "/") => (
+            "200 OK",
+            "text/html; charset=utf-8",
+            "<html><body><a href=\"/metrics\">Metrics</a></body></html>\n".to_string(),
+        ),
+        _ => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            "Not found\n".to_string(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,200 @@
+//! User scripts, written in Rhai (https://rhai.rs/book/), run on each
+//! recognized song. A script defines an `on_song` function receiving the
+//! song as a map with the "title", "artist", "album", "year", "genre",
+//! "track_key" and "source" fields, and returning either:
+//!
+//! - `false`, to ignore the song,
+//! - a map, whose fields replace the ones of the song; a "tags" map is added
+//!   to the JSON output as "songrec_tags", and the "output" and "forward"
+//!   booleans decide whether the song is printed and forwarded to the sinks,
+//! - anything else, to keep the song as is.
+//!
+//! ```rhai
+//! fn on_song(song) {
+//!     if song.title.contains("Jingle") { return false; }
+//!     song.title.replace(" (Radio Edit)", "");
+//!     song.tags = #{ station: "Radio Nova" };
+//!     song
+//! }
+//! ```
+
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::error::Error;
+use std::path::Path;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+
+/// Prevents a buggy script from hanging the recognition loop
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+pub struct ScriptVerdict {
+    /// Print the song (or write it to the CSV output)
+    pub output: bool,
+    /// Forward the song to MQTT, chat networks...
+    pub forward: bool,
+}
+
+/// Keep the song
+impl Default for ScriptVerdict {
+    fn default() -> Self {
+        ScriptVerdict {
+            output: true,
+            forward: true,
+        }
+    }
+}
+
+pub struct RecognitionScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl RecognitionScript {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::compile(&std::fs::read_to_string(path)?)
+            .map_err(|error| format!("{}: {}", path.display(), error).into())
+    }
+
+    pub fn compile(source: &str) -> Result<Self, Box<dyn Error>> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let ast = engine.compile(source)?;
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == "on_song")
+        {
+            return Err("The script does not define an on_song(song) function".into());
+        }
+
+        Ok(RecognitionScript { engine, ast })
+    }
+
+    pub fn on_song(
+        &self,
+        message: &mut SongRecognizedMessage,
+    ) -> Result<ScriptVerdict, Box<dyn Error>> {
+        let mut song = Map::new();
+        song.insert("title".into(), message.song_name.clone().into());
+        song.insert("artist".into(), message.artist_name.clone().into());
+        song.insert("album".into(), optional_string(&message.album_name));
+        song.insert("year".into(), optional_string(&message.release_year));
+        song.insert("genre".into(), optional_string(&message.genre));
+        song.insert("track_key".into(), message.track_key.clone().into());
+        song.insert("isrc".into(), optional_string(&message.isrc));
+        song.insert("upc".into(), optional_string(&message.upc));
+        song.insert(
+            "source".into(),
+            optional_string(&message.source.as_ref().map(|source| source.to_string())),
+        );
+
+        let result: Dynamic =
+            self.engine
+                .call_fn(&mut Scope::new(), &self.ast, "on_song", (song,))?;
+
+        if let Ok(keep) = result.as_bool() {
+            return Ok(ScriptVerdict {
+                output: keep,
+                forward: keep,
+            });
+        }
+        let song = match result.try_cast::<Map>() {
+            Some(song) => song,
+            None => return Ok(ScriptVerdict::default()),
+        };
+
+        if let Some(title) = read_string(&song, "title") {
+            message.song_name = title;
+        }
+        if let Some(artist) = read_string(&song, "artist") {
+            message.artist_name = artist;
+        }
+        if song.contains_key("album") {
+            message.album_name = read_string(&song, "album");
+        }
+        if song.contains_key("year") {
+            message.release_year = read_string(&song, "year");
+        }
+        if song.contains_key("genre") {
+            message.genre = read_string(&song, "genre");
+        }
+
+        if let Some(tags) = song.get("tags") {
+            let tags: serde_json::Value = rhai::serde::from_dynamic(tags)?;
+            let mut shazam_json: serde_json::Value = serde_json::from_str(&message.shazam_json)?;
+            if let Some(object) = shazam_json.as_object_mut() {
+                object.insert("songrec_tags".to_string(), tags);
+                message.shazam_json = shazam_json.to_string();
+            }
+        }
+
+        Ok(ScriptVerdict {
+            output: read_bool(&song, "output"),
+            forward: read_bool(&song, "forward"),
+        })
+    }
+}
+
+fn optional_string(value: &Option<String>) -> Dynamic {
+    match value {
+        Some(value) => value.clone().into(),
+        None => Dynamic::UNIT,
+    }
+}
+
+fn read_string(song: &Map, field: &str) -> Option<String> {
+    song.get(field)
+        .filter(|value| !value.is_unit())
+        .map(|value| value.to_string())
+}
+
+/// Missing fields default to true
+fn read_bool(song: &Map, field: &str) -> bool {
+    song.get(field)
+        .and_then(|value| value.as_bool().ok())
+        .unwrap_or(true)
+}
+
+#[test]
+fn test_recognition_script() {
+    let script = RecognitionScript::compile(
+        r#"
+        fn on_song(song) {
+            if song.title == "Jingle" { return false; }
+            song.title.replace(" (Radio Edit)", "");
+            song.album = ();
+            song.tags = #{ mood: "happy" };
+            song.forward = song.artist != "Nobody";
+            song
+        }
+        "#,
+    )
+    .unwrap();
+
+    let mut message = SongRecognizedMessage {
+        artist_name: "Daft Punk".to_string(),
+        album_name: Some("Homework".to_string()),
+        song_name: "Around the World (Radio Edit)".to_string(),
+        cover_image: None,
+        cover_url: None,
+        track_key: "123".to_string(),
+        release_year: Some("1997".to_string()),
+        genre: None,
+        isrc: None,
+        upc: None,
+        shazam_json: "{}".to_string(),
+        source: None,
+    };
+    let verdict = script.on_song(&mut message).unwrap();
+    assert!(verdict.output && verdict.forward);
+    assert_eq!(message.song_name, "Around the World");
+    assert_eq!(message.album_name, None);
+    assert_eq!(message.release_year.as_deref(), Some("1997"));
+    assert_eq!(message.shazam_json, r#"{"songrec_tags":{"mood":"happy"}}"#);
+
+    message.song_name = "Jingle".to_string();
+    let verdict = script.on_song(&mut message).unwrap();
+    assert!(!verdict.output && !verdict.forward);
+
+    assert!(RecognitionScript::compile("fn other() {}").is_err());
+}
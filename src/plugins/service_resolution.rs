@@ -0,0 +1,122 @@
+//! Finding a recognized song in the catalog of a streaming service, for
+//! the sinks adding songs to playlists. The ISRC that Shazam gives for most
+//! songs designates the exact recording, so it is tried first; the catalog
+//! is searched by artist and title otherwise, normalized as configured
+//! (see `text_normalization`).
+
+use serde_json::Value;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::utils::text_normalization::{normalize_artist, normalize_title};
+
+pub type ResolveFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<String>, Box<dyn Error>>> + 'a>>;
+
+pub struct TrackQuery {
+    pub isrc: Option<String>,
+    pub artist: String,
+    pub title: String,
+}
+
+impl TrackQuery {
+    pub fn from_message(message: &SongRecognizedMessage) -> Self {
+        TrackQuery {
+            isrc: message.isrc.clone(),
+            artist: normalize_artist(&message.artist_name),
+            title: normalize_title(&message.song_name),
+        }
+    }
+
+    /// Whether a track of a library, as tagged there, is this one. Both
+    /// the artist and the title must be equal once normalized, so that
+    /// an untagged track or a longer artist name doesn't match.
+    pub fn matches(&self, artist: &str, title: &str) -> bool {
+        let normalize = |text: &str| text.trim().to_lowercase();
+        let (artist, title) = (
+            normalize(&normalize_artist(artist)),
+            normalize(&normalize_title(title)),
+        );
+        !artist.is_empty()
+            && !title.is_empty()
+            && title == normalize(&self.title)
+            && artist == normalize(&self.artist)
+    }
+}
+
+/// The first of the results of a catalog, or None when there are none. An
+/// answer without the list of results isn't taken for a song not found.
+pub fn first_result<'a>(
+    results: &'a Value,
+    service: &str,
+) -> Result<Option<&'a Value>, Box<dyn Error>> {
+    match results.as_array() {
+        Some(results) => Ok(results.first()),
+        None => Err(format!("{} returned no list of results", service).into()),
+    }
+}
+
+pub trait TrackResolver {
+    /// The identifier of the track of this ISRC in the catalog
+    fn find_by_isrc<'a>(&'a self, isrc: &'a str) -> ResolveFuture<'a>;
+
+    /// The identifier of the first track found by artist and title
+    fn search<'a>(&'a self, query: &'a TrackQuery) -> ResolveFuture<'a>;
+
+    fn resolve<'a>(&'a self, query: &'a TrackQuery) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            if let Some(ref isrc) = query.isrc {
+                if let Some(id) = self.find_by_isrc(isrc).await? {
+                    return Ok(Some(id));
+                }
+            }
+            self.search(query).await
+        })
+    }
+}
+
+#[test]
+fn test_track_query() {
+    let message = SongRecognizedMessage {
+        artist_name: "Daft Punk".to_string(),
+        album_name: None,
+        song_name: "Around the World".to_string(),
+        cover_image: None,
+        cover_url: None,
+        track_key: "123".to_string(),
+        release_year: None,
+        genre: None,
+        isrc: Some("GBDUW0000053".to_string()),
+        upc: None,
+        shazam_json: "{}".to_string(),
+        source: None,
+    };
+    let query = TrackQuery::from_message(&message);
+    assert_eq!(query.isrc.as_deref(), Some("GBDUW0000053"));
+    assert_eq!(query.title, "Around the World");
+    assert!(query.matches("daft punk", "around the world "));
+    assert!(!query.matches("ATC", "Around the World"));
+    assert!(!query.matches("Daft Punk & Friends", "Around the World"));
+    assert!(!query.matches("", "Around the World"));
+    let untitled = TrackQuery {
+        isrc: None,
+        artist: "Daft Punk".to_string(),
+        title: String::new(),
+    };
+    assert!(!untitled.matches("Daft Punk", ""));
+}
+
+#[test]
+fn test_first_result() {
+    let response: Value = serde_json::from_str(r#"{"data": [{"id": "1"}, {"id": "2"}]}"#).unwrap();
+    assert_eq!(
+        first_result(&response["data"], "Tidal").unwrap(),
+        Some(&response["data"][0])
+    );
+    let response: Value = serde_json::from_str(r#"{"data": []}"#).unwrap();
+    assert_eq!(first_result(&response["data"], "Tidal").unwrap(), None);
+    let response: Value = serde_json::from_str(r#"{"errors": []}"#).unwrap();
+    assert!(first_result(&response["data"], "Tidal").is_err());
+}
@@ -0,0 +1,435 @@
+//! Destinations to which the recognized songs are forwarded (MQTT, KDE
+//! Connect, chat and social networks...), behind a common trait. Sinks are
+//! enabled from the command line or the switches of the GUI, or from a
+//! "sinks.toml" file of the preferences directory listing them:
+//!
+//! ```toml
+//! [[sink]]
+//! type = "telegram"
+//! bot_token = "123456:ABC..."
+//! chat_id = "@my_radio_log"
+//! ```
+//!
+//! MPRIS is not a sink: it is also used to control SongRec. Neither is the
+//! history, which the other windows and commands read back.
+
+use gettextrs::gettext;
+use log::{debug, error, info};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use crate::core::match_corrections;
+use crate::core::stream_monitor::verify_announced_song;
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::core::trace_profile;
+use crate::plugins::apple_music::{AppleMusicSettings, AppleMusicSink};
+#[cfg(feature = "deezer")]
+use crate::plugins::deezer::{DeezerSettings, DeezerSink};
+use crate::plugins::jellyfin::{JellyfinSettings, JellyfinSink};
+#[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+use crate::plugins::kdeconnect::KdeConnectSink;
+use crate::plugins::mastodon::{MastodonPoster, MastodonSettings};
+use crate::plugins::matrix::{MatrixAnnouncer, MatrixSettings};
+#[cfg(feature = "mqtt")]
+use crate::plugins::mqtt::{MqttPublisher, MqttSettings};
+use crate::plugins::plex::{PlexSettings, PlexSink};
+use crate::plugins::speech::{SpeechAnnouncer, SpeechSettings};
+#[cfg(all(target_os = "linux", feature = "mpris"))]
+use crate::plugins::spotify_guard;
+use crate::plugins::subsonic::{SubsonicSettings, SubsonicSink};
+use crate::plugins::telegram::{TelegramNotifier, TelegramSettings};
+#[cfg(feature = "tidal")]
+use crate::plugins::tidal::{TidalSettings, TidalSink};
+use crate::plugins::webhook::{WebhookSettings, WebhookSink};
+use crate::utils::filesystem_operations::is_incognito;
+use crate::utils::transliteration;
+
+/// Whether a recognized song should be forwarded to the sinks, by the
+/// command line and the GUI alike, logging why when it isn't: not in
+/// incognito mode, nor when the local Spotify client plays it (a feedback
+/// loop), when its match was reported wrong, or when it was already
+/// reported from the metadata of its stream
+pub async fn should_forward(
+    message: &SongRecognizedMessage,
+    ignore_own_spotify_playback: bool,
+) -> bool {
+    let song_name = format!("{} - {}", message.artist_name, message.song_name);
+    // Before the other checks, as it logs the mismatches of the streams
+    let already_reported = verify_announced_song(message);
+
+    #[cfg(all(target_os = "linux", feature = "mpris"))]
+    let is_own_playback =
+        ignore_own_spotify_playback && spotify_guard::is_own_spotify_playback(message).await;
+    #[cfg(not(all(target_os = "linux", feature = "mpris")))]
+    let is_own_playback = {
+        let _ = ignore_own_spotify_playback;
+        false
+    };
+
+    if is_incognito() {
+        debug!("Not forwarding {}: incognito mode", song_name);
+    } else if is_own_playback {
+        info!(
+            "{}",
+            gettext("Not forwarding %s: it is being played by Spotify").replace("%s", &song_name)
+        );
+    } else if match_corrections::is_pending(&message.track_key) {
+        info!(
+            "{}",
+            gettext("Not forwarding %s: its match was reported wrong").replace("%s", &song_name)
+        );
+    } else if already_reported {
+        debug!(
+            "Not forwarding {}: it was reported from the metadata of the stream",
+            song_name
+        );
+    } else {
+        return true;
+    }
+    false
+}
+
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>>;
+
+pub trait RecognitionSink {
+    /// Used in log messages
+    fn name(&self) -> &'static str;
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a>;
+
+    /// Sinks looking the song up in a catalog by its names get the names
+    /// recognized, the other ones their transliteration (see
+    /// `transliteration`)
+    fn searches_catalog(&self) -> bool {
+        false
+    }
+
+    /// Called when SongRec starts or stops being able to recognize songs
+    /// (recording started, network lost...)
+    fn set_listening(&mut self, _listening: bool) {}
+
+    fn close(&mut self) {}
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    #[cfg(feature = "mqtt")]
+    Mqtt(MqttSettings),
+    #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+    KdeConnect {
+        /// Identifier or name of the device, all the reachable devices
+        /// are notified otherwise
+        #[serde(default)]
+        device: Option<String>,
+    },
+    Mastodon(MastodonSettings),
+    Telegram(TelegramSettings),
+    Matrix(MatrixSettings),
+    Webhook(WebhookSettings),
+    AppleMusic(AppleMusicSettings),
+    Subsonic(SubsonicSettings),
+    Plex(PlexSettings),
+    Jellyfin(JellyfinSettings),
+    Speech(SpeechSettings),
+    #[cfg(feature = "tidal")]
+    Tidal(TidalSettings),
+    #[cfg(feature = "deezer")]
+    Deezer(DeezerSettings),
+}
+
+impl SinkConfig {
+    pub fn build(self) -> Box<dyn RecognitionSink> {
+        match self {
+            #[cfg(feature = "mqtt")]
+            SinkConfig::Mqtt(settings) => Box::new(MqttPublisher::connect(settings)),
+            #[cfg(all(target_os = "linux", feature = "kdeconnect"))]
+            SinkConfig::KdeConnect { device } => Box::new(KdeConnectSink { device }),
+            SinkConfig::Mastodon(settings) => Box::new(MastodonPoster::new(settings)),
+            SinkConfig::Telegram(settings) => Box::new(TelegramNotifier::new(settings)),
+            SinkConfig::Matrix(settings) => Box::new(MatrixAnnouncer::new(settings)),
+            SinkConfig::Webhook(settings) => Box::new(WebhookSink::new(settings)),
+            SinkConfig::AppleMusic(settings) => Box::new(AppleMusicSink::new(settings)),
+            SinkConfig::Subsonic(settings) => Box::new(SubsonicSink::new(settings)),
+            SinkConfig::Plex(settings) => Box::new(PlexSink::new(settings)),
+            SinkConfig::Jellyfin(settings) => Box::new(JellyfinSink::new(settings)),
+            SinkConfig::Speech(settings) => Box::new(SpeechAnnouncer::new(settings)),
+            #[cfg(feature = "tidal")]
+            SinkConfig::Tidal(settings) => Box::new(TidalSink::new(settings)),
+            #[cfg(feature = "deezer")]
+            SinkConfig::Deezer(settings) => Box::new(DeezerSink::new(settings)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SinksFile {
+    #[serde(default)]
+    sink: Vec<SinkConfig>,
+}
+
+pub fn parse_sinks_config(contents: &str) -> Result<Vec<SinkConfig>, Box<dyn Error>> {
+    Ok(toml::from_str::<SinksFile>(contents)?.sink)
+}
+
+/// A missing file means that no sink is configured
+pub fn load_sinks_config(path: &Path) -> Result<Vec<SinkConfig>, Box<dyn Error>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => parse_sinks_config(&contents)
+            .map_err(|error| format!("{}: {}", path.display(), error).into()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[derive(Default)]
+pub struct SinkRegistry {
+    sinks: Vec<Box<dyn RecognitionSink>>,
+    /// Last listening state, given to the sinks registered afterwards
+    listening: Option<bool>,
+    /// Sinks temporarily not receiving songs, by normalized name
+    muted_until: HashMap<String, Instant>,
+    /// Sinks turned off from the GUI until turned on again, by normalized
+    /// name
+    turned_off: HashSet<String>,
+}
+
+/// "KDE Connect" and "kdeconnect" designate the same sink
+fn normalize_sink_name(name: &str) -> String {
+    name.chars()
+        .filter(|character| character.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+impl SinkRegistry {
+    pub fn from_configs(configs: Vec<SinkConfig>) -> Self {
+        let mut registry = SinkRegistry::default();
+        for config in configs {
+            registry.register(config.build());
+        }
+        registry
+    }
+
+    pub fn register(&mut self, mut sink: Box<dyn RecognitionSink>) {
+        debug!("Forwarding the recognized songs to {}", sink.name());
+        if let Some(listening) = self.listening {
+            sink.set_listening(listening);
+        }
+        self.sinks.push(sink);
+    }
+
+    /// Close the current sinks and replace them, when the configuration
+    /// is reloaded
+    pub fn replace(&mut self, configs: Vec<SinkConfig>) {
+        self.close();
+        self.sinks.clear();
+        for config in configs {
+            self.register(config.build());
+        }
+    }
+
+    /// Stop forwarding songs to the sinks of this name for a while, or
+    /// forward them again when the duration is zero. The mute survives
+    /// configuration reloads.
+    pub fn mute(&mut self, name: &str, duration: Duration) -> Result<(), String> {
+        let name = normalize_sink_name(name);
+        if !self
+            .sinks
+            .iter()
+            .any(|sink| normalize_sink_name(sink.name()) == name)
+        {
+            return Err(format!("No {} sink is enabled", name));
+        }
+
+        if duration.is_zero() {
+            info!("Unmuted {}", name);
+            self.muted_until.remove(&name);
+        } else {
+            info!("Muted {} for {} seconds", name, duration.as_secs());
+            self.muted_until.insert(name, Instant::now() + duration);
+        }
+        Ok(())
+    }
+
+    /// Turn the sinks of this name off, or on again. Like a mute, this
+    /// survives configuration reloads.
+    pub fn toggle(&mut self, name: &str) {
+        let name = normalize_sink_name(name);
+        if self.turned_off.remove(&name) {
+            info!("Turned {} on", name);
+        } else {
+            info!("Turned {} off", name);
+            self.turned_off.insert(name);
+        }
+    }
+
+    /// The names of the sinks, once each, and whether they are turned on
+    pub fn states(&self) -> Vec<(&'static str, bool)> {
+        let mut states: Vec<(&'static str, bool)> = vec![];
+        for sink in &self.sinks {
+            let name = normalize_sink_name(sink.name());
+            if !states
+                .iter()
+                .any(|(listed, _)| normalize_sink_name(listed) == name)
+            {
+                states.push((sink.name(), !self.turned_off.contains(&name)));
+            }
+        }
+        states
+    }
+
+    /// A sink failing does not prevent the others from receiving the song
+    pub async fn song_recognized(&mut self, message: &SongRecognizedMessage) {
+        self.muted_until
+            .retain(|_name, until| Instant::now() < *until);
+
+        let mut transliterated = message.clone();
+        transliteration::apply(&mut transliterated);
+        for sink in self.sinks.iter_mut() {
+            let name = normalize_sink_name(sink.name());
+            if self.turned_off.contains(&name) {
+                debug!("Not forwarding the song to {}: turned off", sink.name());
+                continue;
+            }
+            if self.muted_until.contains_key(&name) {
+                debug!("Not forwarding the song to {}: muted", sink.name());
+                continue;
+            }
+            let _span = trace_profile::span(sink.name(), "sink");
+            let message = if sink.searches_catalog() {
+                message
+            } else {
+                &transliterated
+            };
+            if let Err(error) = sink.song_recognized(message).await {
+                error!("Could not forward the song to {}: {}", sink.name(), error);
+            }
+        }
+    }
+
+    pub fn set_listening(&mut self, listening: bool) {
+        self.listening = Some(listening);
+        for sink in self.sinks.iter_mut() {
+            sink.set_listening(listening);
+        }
+    }
+
+    pub fn close(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            sink.close();
+        }
+    }
+}
+
+#[test]
+fn test_parse_sinks_config() {
+    let configs = parse_sinks_config(
+        r#"
+        [[sink]]
+        type = "telegram"
+        bot_token = "123456:ABC"
+        chat_id = "@radio"
+
+        [[sink]]
+        type = "mastodon"
+        instance_url = "https://mastodon.social"
+        access_token = "secret"
+        visibility = "public"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(configs.len(), 2);
+    assert!(matches!(&configs[0], SinkConfig::Telegram(settings) if settings.chat_id == "@radio"));
+    assert!(matches!(
+        &configs[1],
+        SinkConfig::Mastodon(settings) if settings.visibility == "public" && settings.min_interval_secs == 180
+    ));
+
+    assert!(parse_sinks_config("").unwrap().is_empty());
+    assert!(parse_sinks_config("[[sink]]\ntype = \"carrier-pigeon\"").is_err());
+}
+
+#[test]
+fn test_sink_registry() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct FailingSink;
+    impl RecognitionSink for FailingSink {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+        fn song_recognized<'a>(&'a mut self, _: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+            Box::pin(async { Err("unreachable".into()) })
+        }
+    }
+
+    struct RecordingSink(Rc<RefCell<Vec<String>>>);
+    impl RecognitionSink for RecordingSink {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+        fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+            self.0.borrow_mut().push(message.song_name.clone());
+            Box::pin(async { Ok(()) })
+        }
+        fn set_listening(&mut self, listening: bool) {
+            self.0
+                .borrow_mut()
+                .push(format!("listening: {}", listening));
+        }
+    }
+
+    let received = Rc::new(RefCell::new(vec![]));
+    let mut registry = SinkRegistry::default();
+    registry.register(Box::new(FailingSink));
+    registry.register(Box::new(RecordingSink(received.clone())));
+
+    let message = SongRecognizedMessage {
+        artist_name: "Daft Punk".to_string(),
+        album_name: None,
+        song_name: "Around the World".to_string(),
+        cover_image: None,
+        cover_url: None,
+        track_key: "123".to_string(),
+        release_year: None,
+        genre: None,
+        isrc: None,
+        upc: None,
+        shazam_json: "{}".to_string(),
+        source: None,
+    };
+    registry.set_listening(true);
+    glib::MainContext::default().block_on(registry.song_recognized(&message));
+
+    assert_eq!(
+        *received.borrow(),
+        vec![
+            "listening: true".to_string(),
+            "Around the World".to_string()
+        ]
+    );
+
+    registry.mute("Recording", Duration::from_secs(60)).unwrap();
+    assert!(registry.mute("Telegram", Duration::from_secs(60)).is_err());
+    glib::MainContext::default().block_on(registry.song_recognized(&message));
+    assert_eq!(received.borrow().len(), 2);
+
+    registry.mute("recording", Duration::ZERO).unwrap();
+    glib::MainContext::default().block_on(registry.song_recognized(&message));
+    assert_eq!(received.borrow().len(), 3);
+
+    registry.toggle("Recording");
+    assert_eq!(
+        registry.states(),
+        vec![("failing", true), ("recording", false)]
+    );
+    glib::MainContext::default().block_on(registry.song_recognized(&message));
+    assert_eq!(received.borrow().len(), 3);
+}
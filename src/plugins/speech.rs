@@ -0,0 +1,113 @@
+//! Spoken announcement of the recognized songs ("Now playing: X by Y"),
+//! for eyes-free use in a car or a kitchen, or with a screen reader. The
+//! text is handed to speech-dispatcher through its "spd-say" client, so
+//! that it is spoken with the voice, rate and output module configured for
+//! the session, and queued with the messages of the other applications.
+
+use gettextrs::gettext;
+use log::debug;
+use serde::Deserialize;
+use soup::gio;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SpeechSettings {
+    /// Language code of the voice (e.g. "fr"), the one of the session by
+    /// default
+    #[serde(default)]
+    pub language: Option<String>,
+    /// From -100 to 100
+    #[serde(default)]
+    pub rate: Option<i32>,
+}
+
+pub fn announcement_text(message: &SongRecognizedMessage) -> String {
+    if message.artist_name.is_empty() {
+        return gettext("Now playing: %s").replace("%s", &message.song_name);
+    }
+    gettext("Now playing: %s by %s")
+        .replacen("%s", &message.song_name, 1)
+        .replacen("%s", &message.artist_name, 1)
+}
+
+fn spd_say_arguments(text: &str, settings: &SpeechSettings) -> Vec<String> {
+    let mut arguments = vec![
+        "spd-say".to_string(),
+        "--application-name".to_string(),
+        "SongRec".to_string(),
+    ];
+    if let Some(ref language) = settings.language {
+        arguments.extend(["--language".to_string(), language.clone()]);
+    }
+    if let Some(rate) = settings.rate {
+        arguments.extend(["--rate".to_string(), rate.clamp(-100, 100).to_string()]);
+    }
+    // The text could otherwise be taken for an option
+    arguments.extend(["--".to_string(), text.to_string()]);
+    arguments
+}
+
+pub async fn announce(
+    message: &SongRecognizedMessage,
+    settings: &SpeechSettings,
+) -> Result<(), Box<dyn Error>> {
+    let text = announcement_text(message);
+    debug!("Announcing: {}", text);
+    let arguments = spd_say_arguments(&text, settings);
+    let arguments: Vec<&std::ffi::OsStr> = arguments.iter().map(|arg| arg.as_ref()).collect();
+    let subprocess =
+        gio::Subprocess::newv(&arguments, gio::SubprocessFlags::NONE).map_err(|error| {
+            format!(
+                "{} ({})",
+                gettext("Could not run spd-say, is speech-dispatcher installed?"),
+                error
+            )
+        })?;
+    subprocess.wait_check_future().await?;
+    Ok(())
+}
+
+pub struct SpeechAnnouncer {
+    settings: SpeechSettings,
+}
+
+impl SpeechAnnouncer {
+    pub fn new(settings: SpeechSettings) -> Self {
+        SpeechAnnouncer { settings }
+    }
+}
+
+impl RecognitionSink for SpeechAnnouncer {
+    fn name(&self) -> &'static str {
+        "Speech"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(announce(message, &self.settings))
+    }
+}
+
+#[test]
+fn test_spd_say_arguments() {
+    let settings = SpeechSettings {
+        language: Some("fr".to_string()),
+        rate: Some(150),
+    };
+    assert_eq!(
+        spd_say_arguments("-Now playing", &settings),
+        [
+            "spd-say",
+            "--application-name",
+            "SongRec",
+            "--language",
+            "fr",
+            "--rate",
+            "100",
+            "--",
+            "-Now playing"
+        ]
+    );
+}
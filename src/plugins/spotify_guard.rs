@@ -0,0 +1,125 @@
+//! Detect when the song that we just recognized is actually being played by
+//! the local Spotify client, by reading its MPRIS metadata on the session
+//! bus. This prevents feedback loops when SongRec listens to the output of
+//! the computer it runs on (e.g. through a monitor device): the song is not
+//! forwarded to the sinks, and the captured audio isn't recognized again
+//! for as long as Spotify keeps playing it.
+
+use log::debug;
+use mpris_server::zbus::zvariant::{OwnedValue, Value};
+use mpris_server::zbus::{Connection, Proxy};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::utils::text_normalization::{normalize_artist, normalize_title};
+
+const SPOTIFY_BUS_NAME: &str = "org.mpris.MediaPlayer2.spotify";
+
+#[derive(Clone, PartialEq)]
+struct NowPlaying {
+    title: String,
+    artists: Vec<String>,
+}
+
+/// The song of the Spotify client that was last recognized
+static GUARDED_SONG: Mutex<Option<NowPlaying>> = Mutex::new(None);
+
+async fn spotify_now_playing() -> Result<Option<NowPlaying>, Box<dyn Error>> {
+    let connection = Connection::session().await?;
+    let proxy = Proxy::new(
+        &connection,
+        SPOTIFY_BUS_NAME,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2.Player",
+    )
+    .await?;
+
+    let status: String = proxy.get_property("PlaybackStatus").await?;
+    if status != "Playing" {
+        return Ok(None);
+    }
+
+    let metadata: HashMap<String, OwnedValue> = proxy.get_property("Metadata").await?;
+
+    let title = match metadata.get("xesam:title").map(|value| &**value) {
+        Some(Value::Str(title)) => title.to_string(),
+        _ => return Ok(None),
+    };
+    let artists = match metadata.get("xesam:artist").map(|value| &**value) {
+        Some(Value::Array(artists)) => artists
+            .iter()
+            .filter_map(|artist| <&str>::try_from(artist).ok())
+            .map(|artist| artist.to_string())
+            .collect(),
+        _ => vec![],
+    };
+
+    Ok(Some(NowPlaying { title, artists }))
+}
+
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Spotify and Shazam frequently disagree on suffixes such as " - Remastered
+/// 2011" or "(feat. ...)", so titles only need to share a prefix.
+fn is_same_song(now_playing: &NowPlaying, message: &SongRecognizedMessage) -> bool {
+    let spotify_title = normalize(&normalize_title(&now_playing.title));
+    let recognized_title = normalize(&normalize_title(&message.song_name));
+    if spotify_title.is_empty() || recognized_title.is_empty() {
+        return false;
+    }
+    if !spotify_title.starts_with(&recognized_title)
+        && !recognized_title.starts_with(&spotify_title)
+    {
+        return false;
+    }
+
+    let recognized_artist = normalize(&normalize_artist(&message.artist_name));
+    now_playing.artists.is_empty()
+        || now_playing.artists.iter().any(|artist| {
+            let artist = normalize(&normalize_artist(artist));
+            !artist.is_empty()
+                && (recognized_artist.contains(&artist) || artist.contains(&recognized_artist))
+        })
+}
+
+/// Whether the recognized song is the one currently played by the local
+/// Spotify client. Returns false when Spotify is not running.
+pub async fn is_own_spotify_playback(message: &SongRecognizedMessage) -> bool {
+    match spotify_now_playing().await {
+        Ok(Some(now_playing)) => {
+            let is_own_playback = is_same_song(&now_playing, message);
+            if is_own_playback {
+                *GUARDED_SONG.lock().unwrap() = Some(now_playing);
+            }
+            is_own_playback
+        }
+        Ok(None) => false,
+        Err(error) => {
+            debug!("Could not read the Spotify MPRIS metadata: {}", error);
+            false
+        }
+    }
+}
+
+/// Whether Spotify still plays the song it was last recognized playing, so
+/// that the captured audio needn't be recognized. Spotify is only asked
+/// once such a song was recognized.
+pub async fn is_guarded_playback() -> bool {
+    if GUARDED_SONG.lock().unwrap().is_none() {
+        return false;
+    }
+    let now_playing = spotify_now_playing().await.ok().flatten();
+    let mut guarded_song = GUARDED_SONG.lock().unwrap();
+    if now_playing.is_some() && *guarded_song == now_playing {
+        return true;
+    }
+    *guarded_song = None;
+    false
+}
@@ -0,0 +1,310 @@
+//! Compare the songs of the history with a Spotify playlist collecting
+//! them, through the Spotify Web API, and optionally fix the playlist.
+//!
+//! Songs are matched by the Spotify track that Shazam links them to, which
+//! is only known while their metadata is cached. The playlist is expected
+//! to hold the recognized tracks in the order of the history; other tracks
+//! of the playlist are left alone.
+//!
+//! See: https://developer.spotify.com/documentation/web-api/reference/get-playlists-tracks
+
+use glib::source::Priority;
+use log::info;
+use serde_json::{json, Value};
+use soup::prelude::SessionExt;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::core::metadata_cache;
+use crate::utils::csv_song_history::{CsvColumn, SongHistoryRecord};
+
+const API_URL: &str = "https://api.spotify.com/v1";
+
+const TRACK_URI_PREFIX: &str = "spotify:track:";
+
+/// "spotify:playlist:ID", "https://open.spotify.com/playlist/ID?si=..." or
+/// the identifier alone
+pub fn parse_playlist_id(playlist: &str) -> String {
+    let playlist = playlist.trim();
+    let id = playlist
+        .strip_prefix("spotify:playlist:")
+        .or_else(|| {
+            playlist
+                .split_once("open.spotify.com/playlist/")
+                .map(|(_, id)| id)
+        })
+        .unwrap_or(playlist);
+    id.split(['?', '/']).next().unwrap_or_default().to_string()
+}
+
+/// The Spotify track of a history entry, when Shazam linked it to one
+pub fn resolve_track_uri(record: &SongHistoryRecord) -> Option<String> {
+    let track = metadata_cache::get_track(record.track_key.as_deref()?)?;
+    Some(CsvColumn::SpotifyUri.value(record, Some(&json!({ "track": track }))))
+        .filter(|uri| uri.starts_with(TRACK_URI_PREFIX))
+}
+
+/// A change to the playlist, as accepted by the Spotify Web API
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PlaylistChange {
+    Insert { uri: String, position: usize },
+    Move { from: usize, insert_before: usize },
+}
+
+#[derive(Debug, Default)]
+pub struct PlaylistDiff {
+    /// Tracks of the history missing from the playlist
+    pub missing: Vec<String>,
+    /// Tracks of the playlist which aren't in the order of the history
+    pub out_of_order: Vec<String>,
+    /// The changes putting the missing and misplaced tracks where they
+    /// belong, in order
+    pub changes: Vec<PlaylistChange>,
+}
+
+/// The indices of a longest increasing subsequence of `values`
+fn longest_increasing_subsequence(values: &[usize]) -> HashSet<usize> {
+    // Index in `values` of the smallest tail of each subsequence length
+    let mut tails: Vec<usize> = vec![];
+    let mut predecessors: Vec<Option<usize>> = vec![None; values.len()];
+    for (index, value) in values.iter().enumerate() {
+        let length = tails.partition_point(|&tail| values[tail] < *value);
+        predecessors[index] = length.checked_sub(1).map(|previous| tails[previous]);
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+    let mut indices = HashSet::new();
+    let mut current = tails.last().copied();
+    while let Some(index) = current {
+        indices.insert(index);
+        current = predecessors[index];
+    }
+    indices
+}
+
+/// Compare the tracks of the history (oldest first, without duplicates)
+/// with the ones of the playlist. The tracks that are already in order are
+/// the longest sequence of them found in the order of the history, so that
+/// as few tracks as possible are moved.
+pub fn diff_playlist(expected: &[String], playlist: &[String]) -> PlaylistDiff {
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for (position, uri) in playlist.iter().enumerate() {
+        positions.entry(uri.as_str()).or_insert(position);
+    }
+    let present: Vec<&String> = expected
+        .iter()
+        .filter(|uri| positions.contains_key(uri.as_str()))
+        .collect();
+    let present_positions: Vec<usize> = present.iter().map(|uri| positions[uri.as_str()]).collect();
+    let in_order: HashSet<&String> = longest_increasing_subsequence(&present_positions)
+        .into_iter()
+        .map(|index| present[index])
+        .collect();
+
+    let mut diff = PlaylistDiff::default();
+    let mut current: Vec<String> = playlist.to_vec();
+    let mut previous: Option<&String> = None;
+    for uri in expected {
+        // Right after the previous track of the history, or before the first
+        // track in order when it is the first one
+        let target = match previous {
+            Some(previous) => current.iter().position(|other| other == previous).unwrap() + 1,
+            None => current
+                .iter()
+                .position(|other| in_order.contains(other))
+                .unwrap_or(current.len()),
+        };
+        match current.iter().position(|other| other == uri) {
+            None => {
+                diff.missing.push(uri.clone());
+                diff.changes.push(PlaylistChange::Insert {
+                    uri: uri.clone(),
+                    position: target,
+                });
+                current.insert(target, uri.clone());
+            }
+            Some(from) if !in_order.contains(uri) => {
+                diff.out_of_order.push(uri.clone());
+                diff.changes.push(PlaylistChange::Move {
+                    from,
+                    insert_before: target,
+                });
+                let track = current.remove(from);
+                current.insert(if target > from { target - 1 } else { target }, track);
+            }
+            Some(_) => {}
+        }
+        previous = Some(uri);
+    }
+    diff
+}
+
+pub struct SpotifyPlaylist {
+    session: soup::Session,
+    access_token: String,
+    playlist_id: String,
+}
+
+impl SpotifyPlaylist {
+    pub fn new(access_token: String, playlist_id: String) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+
+        SpotifyPlaylist {
+            session,
+            access_token,
+            playlist_id,
+        }
+    }
+
+    async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<Value>,
+    ) -> Result<Value, Box<dyn Error>> {
+        let request = soup::Message::new(method, url)?;
+        request
+            .request_headers()
+            .unwrap()
+            .append("Authorization", &format!("Bearer {}", self.access_token));
+        if let Some(body) = body {
+            request.set_request_body_from_bytes(
+                Some("application/json"),
+                Some(&glib::Bytes::from_owned(body.to_string().into_bytes())),
+            );
+        }
+
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        let response: Value = serde_json::from_slice(&response[..]).unwrap_or_default();
+        if !(200..300).contains(&request.status_code()) {
+            return Err(format!(
+                "Spotify returned status {}: {}",
+                request.status_code(),
+                response["error"]["message"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+        Ok(response)
+    }
+
+    /// The name of the account of the access token, which tells whether
+    /// the token is still valid
+    pub async fn user_name(&self) -> Result<String, Box<dyn Error>> {
+        let user = self
+            .request("GET", &format!("{}/me", API_URL), None)
+            .await?;
+        Ok(user["display_name"]
+            .as_str()
+            .or(user["id"].as_str())
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    /// The URIs of the tracks of the playlist, in order
+    pub async fn track_uris(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut uris = vec![];
+        let mut url = Some(format!(
+            "{}/playlists/{}/tracks?limit=100&fields=next,items(track(uri))",
+            API_URL, self.playlist_id
+        ));
+        while let Some(page_url) = url {
+            let page = self.request("GET", &page_url, None).await?;
+            for item in page["items"].as_array().into_iter().flatten() {
+                // Local files and removed tracks have no URI
+                uris.push(
+                    item["track"]["uri"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                );
+            }
+            url = page["next"].as_str().map(|next| next.to_string());
+        }
+        Ok(uris)
+    }
+
+    pub async fn apply_change(&self, change: &PlaylistChange) -> Result<(), Box<dyn Error>> {
+        let url = format!("{}/playlists/{}/tracks", API_URL, self.playlist_id);
+        match change {
+            PlaylistChange::Insert { uri, position } => {
+                self.request(
+                    "POST",
+                    &url,
+                    Some(json!({ "uris": [uri], "position": position })),
+                )
+                .await?;
+                info!("Added {} at position {}", uri, position);
+            }
+            PlaylistChange::Move {
+                from,
+                insert_before,
+            } => {
+                self.request(
+                    "PUT",
+                    &url,
+                    Some(json!({ "range_start": from, "insert_before": insert_before })),
+                )
+                .await?;
+                info!(
+                    "Moved the track at position {} before {}",
+                    from, insert_before
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_diff_playlist() {
+    let uris = |names: &[&str]| -> Vec<String> {
+        names
+            .iter()
+            .map(|name| format!("spotify:track:{}", name))
+            .collect()
+    };
+    let expected = uris(&["a", "b", "c", "d", "e"]);
+    let playlist = uris(&["x", "a", "c", "b", "e", "y"]);
+
+    let diff = diff_playlist(&expected, &playlist);
+    assert_eq!(diff.missing, uris(&["d"]));
+    assert_eq!(diff.out_of_order.len(), 1);
+
+    // Applying the changes puts the tracks of the history in order
+    let mut fixed = playlist.clone();
+    for change in &diff.changes {
+        match change {
+            PlaylistChange::Insert { uri, position } => fixed.insert(*position, uri.clone()),
+            PlaylistChange::Move {
+                from,
+                insert_before,
+            } => {
+                let track = fixed.remove(*from);
+                let position = if insert_before > from {
+                    insert_before - 1
+                } else {
+                    *insert_before
+                };
+                fixed.insert(position, track);
+            }
+        }
+    }
+    assert_eq!(fixed, uris(&["x", "a", "b", "c", "d", "e", "y"]));
+
+    assert!(diff_playlist(&expected, &expected).changes.is_empty());
+    assert_eq!(
+        parse_playlist_id("https://open.spotify.com/playlist/37i9dQZF1DX?si=abc"),
+        "37i9dQZF1DX"
+    );
+    assert_eq!(
+        parse_playlist_id("spotify:playlist:37i9dQZF1DX"),
+        "37i9dQZF1DX"
+    );
+}
@@ -0,0 +1,211 @@
+//! Add recognized songs to a playlist of a self-hosted server implementing
+//! the Subsonic API (Navidrome, Airsonic, Gonic...), and/or star them there.
+//!
+//! Requests are authenticated with a salted MD5 token of the password, as
+//! the API requires since its version 1.13.0, so that the password itself
+//! is never sent.
+//!
+//! See: https://www.subsonic.org/pages/api.jsp
+
+use glib::source::Priority;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::Value;
+use soup::prelude::SessionExt;
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+const API_VERSION: &str = "1.16.1";
+
+#[derive(Clone, Deserialize)]
+pub struct SubsonicSettings {
+    /// Address of the server, e.g. "https://music.example.org"
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    /// Identifier of the playlist to add the songs to
+    #[serde(default)]
+    pub playlist: Option<String>,
+    /// Star the songs, which adds them to the favorites of the user
+    #[serde(default)]
+    pub star: bool,
+}
+
+pub struct SubsonicSink {
+    session: soup::Session,
+    settings: SubsonicSettings,
+    /// Songs already added during this session
+    added: HashSet<String>,
+}
+
+/// The song of the search results by this artist, if any, rather than a
+/// song of the same title by another artist
+fn best_match(response: &Value, artist: &str) -> Option<String> {
+    let songs = response["subsonic-response"]["searchResult3"]["song"].as_array()?;
+    songs
+        .iter()
+        .find(|song| {
+            song["artist"]
+                .as_str()
+                .is_some_and(|other| other.eq_ignore_ascii_case(artist))
+        })
+        .and_then(|song| song["id"].as_str())
+        .map(|id| id.to_string())
+}
+
+impl SubsonicSink {
+    pub fn new(settings: SubsonicSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        SubsonicSink {
+            session,
+            settings,
+            added: HashSet::new(),
+        }
+    }
+
+    /// Call an endpoint of the API, with the parameters already escaped
+    async fn request(&self, endpoint: &str, parameters: &str) -> Result<Value, Box<dyn Error>> {
+        let salt = uuid::Uuid::new_v4().simple().to_string();
+        let token = glib::compute_checksum_for_string(
+            glib::ChecksumType::Md5,
+            &format!("{}{}", self.settings.password, salt),
+        )
+        .ok_or("Could not hash the Subsonic password")?;
+        let url = format!(
+            "{}/rest/{}?u={}&t={}&s={}&v={}&c=SongRec&f=json&{}",
+            self.settings.url.trim_end_matches('/'),
+            endpoint,
+            glib::Uri::escape_string(&self.settings.username, None, false),
+            token,
+            salt,
+            API_VERSION,
+            parameters
+        );
+
+        let request = soup::Message::new("GET", &url)?;
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        let response: Value = serde_json::from_slice(&response[..]).unwrap_or_default();
+        // Failures are reported in the body, with a 200 status
+        if request.status_code() != 200 || response["subsonic-response"]["status"] != "ok" {
+            return Err(format!(
+                "The Subsonic server returned status {}: {}",
+                request.status_code(),
+                response["subsonic-response"]["error"]["message"]
+                    .as_str()
+                    .unwrap_or_default()
+            )
+            .into());
+        }
+        Ok(response)
+    }
+
+    async fn find_song(
+        &self,
+        message: &SongRecognizedMessage,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let query = format!("{} {}", message.artist_name, message.song_name);
+        let response = self
+            .request(
+                "search3",
+                &format!(
+                    "query={}&songCount=5&artistCount=0&albumCount=0",
+                    glib::Uri::escape_string(&query, None, false)
+                ),
+            )
+            .await?;
+        if let Some(id) = best_match(&response, &message.artist_name) {
+            return Ok(Some(id));
+        }
+        // Servers that match all the words against the title alone
+        let response = self
+            .request(
+                "search3",
+                &format!(
+                    "query={}&songCount=5&artistCount=0&albumCount=0",
+                    glib::Uri::escape_string(&message.song_name, None, false)
+                ),
+            )
+            .await?;
+        Ok(best_match(&response, &message.artist_name))
+    }
+
+    pub async fn add_song(
+        &mut self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(song_id) = self.find_song(message).await? else {
+            info!("{} was not found on the Subsonic server", message.song_name);
+            return Ok(());
+        };
+        if self.added.contains(&song_id) {
+            debug!(
+                "{} was already added on the Subsonic server",
+                message.song_name
+            );
+            return Ok(());
+        }
+        let escaped_id = glib::Uri::escape_string(&song_id, None, false);
+
+        if self.settings.star {
+            self.request("star", &format!("id={}", escaped_id)).await?;
+            info!("Starred {} on the Subsonic server", message.song_name);
+        }
+        if let Some(ref playlist) = self.settings.playlist {
+            self.request(
+                "updatePlaylist",
+                &format!(
+                    "playlistId={}&songIdToAdd={}",
+                    glib::Uri::escape_string(playlist, None, false),
+                    escaped_id
+                ),
+            )
+            .await?;
+            info!(
+                "Added {} to the playlist of the Subsonic server",
+                message.song_name
+            );
+        }
+        self.added.insert(song_id);
+        Ok(())
+    }
+}
+
+impl RecognitionSink for SubsonicSink {
+    fn name(&self) -> &'static str {
+        "Subsonic"
+    }
+
+    fn searches_catalog(&self) -> bool {
+        true
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.add_song(message))
+    }
+}
+
+#[test]
+fn test_best_match() {
+    let response: Value = serde_json::from_str(
+        r#"{"subsonic-response": {"status": "ok", "searchResult3": {"song": [
+            {"id": "1", "title": "Around the World", "artist": "Red Hot Chili Peppers"},
+            {"id": "2", "title": "Around the World", "artist": "Daft Punk"}
+        ]}}}"#,
+    )
+    .unwrap();
+    assert_eq!(best_match(&response, "daft punk").as_deref(), Some("2"));
+    assert_eq!(best_match(&response, "ATC"), None);
+    assert_eq!(
+        best_match(&serde_json::json!({"subsonic-response": {}}), "ATC"),
+        None
+    );
+}
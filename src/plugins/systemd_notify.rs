@@ -0,0 +1,56 @@
+//! Minimal implementation of the sd_notify(3) protocol, so that `songrec
+//! listen` can be run as a `Type=notify` systemd service with a watchdog
+//! (see "packaging/systemd/songrec-listen.service").
+
+use log::{debug, warn};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// Send a state string (e.g. "READY=1" or "WATCHDOG=1") to the service
+/// manager. Returns false when we are not running under systemd, or when
+/// the notification could not be delivered.
+pub fn notify(state: &str) -> bool {
+    let socket_path = match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) if !path.is_empty() => path,
+        _ => return false,
+    };
+
+    let address = if let Some(abstract_name) = socket_path.strip_prefix('@') {
+        SocketAddr::from_abstract_name(abstract_name.as_bytes())
+    } else {
+        SocketAddr::from_pathname(&socket_path)
+    };
+
+    let result = address.and_then(|address| {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &address)
+    });
+
+    match result {
+        Ok(_) => {
+            debug!("Sent systemd notification: {:?}", state);
+            true
+        }
+        Err(error) => {
+            warn!("Could not notify systemd ({:?}): {:?}", state, error);
+            false
+        }
+    }
+}
+
+/// The interval at which the service manager expects keepalives, when the
+/// unit has a `WatchdogSec=` setting targeting our process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
@@ -0,0 +1,105 @@
+//! Send recognized songs, with their cover art, to a Telegram chat through
+//! the Bot API, e.g. to monitor a remote SongRec instance logging a radio
+//! stream from a phone.
+//!
+//! See: https://core.telegram.org/bots/api#sendphoto
+
+use glib::source::Priority;
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use soup::prelude::SessionExt;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+use crate::utils::share_text::{default_share_template, render_message_share_text};
+
+/// Maximal length of a photo caption
+const MAX_CAPTION_CHARACTERS: usize = 1024;
+
+#[derive(Clone, Deserialize)]
+pub struct TelegramSettings {
+    pub bot_token: String,
+    /// Numeric identifier of the chat, or "@channelusername"
+    pub chat_id: String,
+    #[serde(default = "default_share_template")]
+    pub template: String,
+}
+
+pub struct TelegramNotifier {
+    session: soup::Session,
+    settings: TelegramSettings,
+}
+
+impl TelegramNotifier {
+    pub fn new(settings: TelegramSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+
+        TelegramNotifier { session, settings }
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!(
+            "https://api.telegram.org/bot{}/{}",
+            self.settings.bot_token, method
+        )
+    }
+
+    pub async fn send_song(&self, message: &SongRecognizedMessage) -> Result<(), Box<dyn Error>> {
+        let text = render_message_share_text(&self.settings.template, message);
+
+        // Telegram downloads the cover art itself
+        let (method, body) = match message.cover_url {
+            Some(ref cover_url) => (
+                "sendPhoto",
+                json!({
+                    "chat_id": self.settings.chat_id,
+                    "photo": cover_url,
+                    "caption": text.chars().take(MAX_CAPTION_CHARACTERS).collect::<String>(),
+                }),
+            ),
+            None => (
+                "sendMessage",
+                json!({
+                    "chat_id": self.settings.chat_id,
+                    "text": text,
+                }),
+            ),
+        };
+
+        let request = soup::Message::new("POST", &self.method_url(method))?;
+        request.set_request_body_from_bytes(
+            Some("application/json"),
+            Some(&glib::Bytes::from_owned(body.to_string().into_bytes())),
+        );
+
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        let response: Value = serde_json::from_slice(&response[..]).unwrap_or_default();
+        if response["ok"] != true {
+            return Err(format!(
+                "Telegram returned status {}: {}",
+                request.status_code(),
+                response["description"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+
+        info!("Sent {} to Telegram", message.song_name);
+        Ok(())
+    }
+}
+
+impl RecognitionSink for TelegramNotifier {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.send_song(message))
+    }
+}
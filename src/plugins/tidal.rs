@@ -0,0 +1,166 @@
+//! Add recognized songs to a Tidal playlist, through the JSON:API of the
+//! Tidal developer platform. The access token is the one of a user, with
+//! the "playlists.write" scope.
+//!
+//! See: https://developer.tidal.com/apiref
+
+use glib::source::Priority;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use soup::prelude::SessionExt;
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::service_resolution::{first_result, ResolveFuture, TrackQuery, TrackResolver};
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+
+const API_URL: &str = "https://openapi.tidal.com/v2";
+
+const CONTENT_TYPE: &str = "application/vnd.api+json";
+
+fn default_country_code() -> String {
+    "US".to_string()
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TidalSettings {
+    pub access_token: String,
+    /// Identifier of the playlist, from its link
+    pub playlist: String,
+    /// Country of the catalog to search
+    #[serde(default = "default_country_code")]
+    pub country_code: String,
+}
+
+pub struct TidalSink {
+    session: soup::Session,
+    settings: TidalSettings,
+    /// Tracks already added during this session
+    added: HashSet<String>,
+}
+
+impl TidalSink {
+    pub fn new(settings: TidalSettings) -> Self {
+        let session = soup::Session::new();
+        session.set_timeout(20);
+        session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+
+        TidalSink {
+            session,
+            settings,
+            added: HashSet::new(),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<Value>,
+    ) -> Result<Value, Box<dyn Error>> {
+        let request = soup::Message::new(method, url)?;
+        let headers = request.request_headers().unwrap();
+        headers.append(
+            "Authorization",
+            &format!("Bearer {}", self.settings.access_token),
+        );
+        headers.append("Accept", CONTENT_TYPE);
+        if let Some(body) = body {
+            request.set_request_body_from_bytes(
+                Some(CONTENT_TYPE),
+                Some(&glib::Bytes::from_owned(body.to_string().into_bytes())),
+            );
+        }
+
+        let response = self
+            .session
+            .send_and_read_future(&request, Priority::DEFAULT)
+            .await?;
+        let response: Value = serde_json::from_slice(&response[..]).unwrap_or_default();
+        if !(200..300).contains(&request.status_code()) {
+            return Err(format!(
+                "Tidal returned status {}: {}",
+                request.status_code(),
+                response["errors"][0]["detail"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+        Ok(response)
+    }
+
+    pub async fn add_song(
+        &mut self,
+        message: &SongRecognizedMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(track_id) = self.resolve(&TrackQuery::from_message(message)).await? else {
+            info!("{} was not found on Tidal", message.song_name);
+            return Ok(());
+        };
+        if self.added.contains(&track_id) {
+            debug!("{} was already added to Tidal", message.song_name);
+            return Ok(());
+        }
+
+        self.request(
+            "POST",
+            &format!(
+                "{}/playlists/{}/relationships/items",
+                API_URL,
+                glib::Uri::escape_string(&self.settings.playlist, None, false)
+            ),
+            Some(json!({ "data": [{ "id": track_id, "type": "tracks" }] })),
+        )
+        .await?;
+        info!("Added {} to the Tidal playlist", message.song_name);
+        self.added.insert(track_id);
+        Ok(())
+    }
+}
+
+fn first_track_id(response: &Value) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(first_result(&response["data"], "Tidal")?
+        .and_then(|track| track["id"].as_str())
+        .map(|id| id.to_string()))
+}
+
+impl TrackResolver for TidalSink {
+    fn find_by_isrc<'a>(&'a self, isrc: &'a str) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/tracks?countryCode={}&filter%5Bisrc%5D={}",
+                API_URL,
+                glib::Uri::escape_string(&self.settings.country_code, None, false),
+                glib::Uri::escape_string(isrc, None, false)
+            );
+            first_track_id(&self.request("GET", &url, None).await?)
+        })
+    }
+
+    fn search<'a>(&'a self, query: &'a TrackQuery) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/searchResults/{}/relationships/tracks?countryCode={}",
+                API_URL,
+                glib::Uri::escape_string(&format!("{} {}", query.artist, query.title), None, false),
+                glib::Uri::escape_string(&self.settings.country_code, None, false)
+            );
+            first_track_id(&self.request("GET", &url, None).await?)
+        })
+    }
+}
+
+impl RecognitionSink for TidalSink {
+    fn name(&self) -> &'static str {
+        "Tidal"
+    }
+
+    fn searches_catalog(&self) -> bool {
+        true
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.add_song(message))
+    }
+}
@@ -0,0 +1,107 @@
+//! Post the share text of recognized songs to a webhook, such as the
+//! incoming webhooks of Slack, Mattermost or Discord, or any HTTP endpoint
+//! accepting JSON. The body holds the text under `text_field` ("text" for
+//! Slack and Mattermost, "content" for Discord), along with the details of
+//! the song:
+//!
+//! ```json
+//! {"text": "🎵 Now playing: ...", "title": "...", "artist": "...",
+//!  "album": "...", "track_key": "..."}
+//! ```
+
+use glib::source::Priority;
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use soup::prelude::SessionExt;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::plugins::sinks::{RecognitionSink, SinkFuture};
+use crate::utils::share_text::{default_share_template, render_message_share_text};
+
+fn default_text_field() -> String {
+    "text".to_string()
+}
+
+#[derive(Clone, Deserialize)]
+pub struct WebhookSettings {
+    pub url: String,
+    #[serde(default = "default_share_template")]
+    pub template: String,
+    #[serde(default = "default_text_field")]
+    pub text_field: String,
+}
+
+/// Send a JSON body to the webhook, for the sink and the "Share" button of
+/// the GUI
+pub async fn post_to_webhook(
+    session: &soup::Session,
+    url: &str,
+    body: &Value,
+) -> Result<(), Box<dyn Error>> {
+    let request = soup::Message::new("POST", url)?;
+    request.set_request_body_from_bytes(
+        Some("application/json"),
+        Some(&glib::Bytes::from_owned(body.to_string().into_bytes())),
+    );
+
+    let response = session
+        .send_and_read_future(&request, Priority::DEFAULT)
+        .await?;
+    if !(200..300).contains(&request.status_code()) {
+        return Err(format!(
+            "The webhook returned status {}: {}",
+            request.status_code(),
+            String::from_utf8_lossy(&response[..])
+        )
+        .into());
+    }
+    Ok(())
+}
+
+pub fn webhook_session() -> soup::Session {
+    let session = soup::Session::new();
+    session.set_timeout(20);
+    session.set_user_agent(&format!("SongRec/{}", env!("CARGO_PKG_VERSION")));
+    session
+}
+
+pub struct WebhookSink {
+    session: soup::Session,
+    settings: WebhookSettings,
+}
+
+impl WebhookSink {
+    pub fn new(settings: WebhookSettings) -> Self {
+        WebhookSink {
+            session: webhook_session(),
+            settings,
+        }
+    }
+
+    pub async fn send_song(&self, message: &SongRecognizedMessage) -> Result<(), Box<dyn Error>> {
+        let mut body = json!({
+            "title": message.song_name,
+            "artist": message.artist_name,
+            "album": message.album_name,
+            "track_key": message.track_key,
+        });
+        body[self.settings.text_field.as_str()] =
+            render_message_share_text(&self.settings.template, message).into();
+
+        post_to_webhook(&self.session, &self.settings.url, &body).await?;
+        info!("Sent {} to the webhook", message.song_name);
+        Ok(())
+    }
+}
+
+impl RecognitionSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn song_recognized<'a>(&'a mut self, message: &'a SongRecognizedMessage) -> SinkFuture<'a> {
+        Box::pin(self.send_song(message))
+    }
+}
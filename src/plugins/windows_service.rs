@@ -0,0 +1,177 @@
+//! Run `songrec listen` as a Windows service, for kiosk and venue
+//! installations that must keep recognizing songs when nobody is logged
+//! in. `songrec service install -- <listen options>` registers a service
+//! started at boot with these options, which logs to the Windows event log.
+//!
+//! See: https://learn.microsoft.com/windows/win32/services/service-programs
+
+use gettextrs::gettext;
+use log::{error, info};
+use std::error::Error;
+use std::ffi::OsString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceDependency, ServiceErrorControl,
+    ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use crate::cli_main::{cli_main, CLIParameters};
+
+pub const SERVICE_NAME: &str = "SongRec";
+
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+static RUNNING_AS_SERVICE: AtomicBool = AtomicBool::new(false);
+
+/// Handed over to the thread started by the service manager
+static PARAMETERS: Mutex<Option<CLIParameters>> = Mutex::new(None);
+
+/// Set before the logging is set up, which then also logs to the event log
+pub fn set_running_as_service(enabled: bool) {
+    RUNNING_AS_SERVICE.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_running_as_service() -> bool {
+    RUNNING_AS_SERVICE.load(Ordering::SeqCst)
+}
+
+pub fn install_service(listen_arguments: Vec<OsString>) -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+
+    let mut launch_arguments = vec![
+        OsString::from("listen"),
+        OsString::from("--windows-service"),
+    ];
+    launch_arguments.extend(listen_arguments);
+
+    let service = manager.create_service(
+        &ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from("SongRec"),
+            service_type: SERVICE_TYPE,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: std::env::current_exe()?,
+            launch_arguments,
+            // The Windows Audio service
+            dependencies: vec![ServiceDependency::Service(OsString::from("Audiosrv"))],
+            account_name: None,
+            account_password: None,
+        },
+        ServiceAccess::CHANGE_CONFIG,
+    )?;
+    service.set_description(gettext(
+        "Recognizes the songs played around the computer and forwards them to the configured sinks",
+    ))?;
+
+    eventlog::register(SERVICE_NAME)?;
+
+    println!(
+        "{}",
+        gettext("The SongRec service was installed, and will be started with the system; start it now with \"sc start SongRec\"")
+    );
+    Ok(())
+}
+
+pub fn uninstall_service() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+
+    if let Err(error) = eventlog::deregister(SERVICE_NAME) {
+        error!("Could not unregister the event log source: {}", error);
+    }
+
+    println!("{}", gettext("The SongRec service was uninstalled"));
+    Ok(())
+}
+
+fn service_status(state: ServiceState, exit_code: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted: if state == ServiceState::Running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        },
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+fn run_service() -> Result<(), Box<dyn Error>> {
+    let mut parameters = PARAMETERS
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or("The service was started twice")?;
+
+    let (stop_tx, stop_rx) = async_channel::bounded(1);
+    parameters.stop_requests = Some(stop_rx);
+
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                info!("Stop requested by the service manager");
+                stop_tx.try_send(()).ok();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+    status_handle.set_service_status(service_status(ServiceState::Running, 0))?;
+    info!("SongRec service started");
+
+    let result = cli_main(parameters);
+    if let Err(ref error) = result {
+        error!("The SongRec service stopped after an error: {}", error);
+    }
+
+    status_handle.set_service_status(service_status(
+        ServiceState::Stopped,
+        if result.is_ok() { 0 } else { 1 },
+    ))?;
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+// The arguments are the ones given to "sc start", the options of the
+// service being the ones of the command line
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(error) = run_service() {
+        error!("Could not run the SongRec service: {}", error);
+    }
+}
+
+/// Listen from the thread started by the service manager, until it stops
+/// the service; only returns once the service stopped
+pub fn run_as_service(parameters: CLIParameters) -> Result<(), Box<dyn Error>> {
+    *PARAMETERS.lock().unwrap() = Some(parameters);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(|error| {
+        format!(
+            "{}: {}",
+            gettext("Could not connect to the Windows service manager, \"--windows-service\" is only used by the installed service"),
+            error
+        )
+    })?;
+    Ok(())
+}
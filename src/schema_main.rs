@@ -0,0 +1,97 @@
+//! The JSON schemas of the machine-readable outputs of the subcommands,
+//! generated from the types that they serialize, for the integrations that
+//! validate this output or generate code from it.
+
+use gettextrs::gettext;
+use schemars::schema::RootSchema;
+use schemars::{schema_for, JsonSchema};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::core::fingerprinting::shazam_response::ShazamResponse;
+use crate::core::scan_progress::ScanResult;
+use crate::library_main::{DuplicateGroup, IndexMatch};
+use crate::utils::chapters::PodcastChapters;
+use crate::utils::charts::Chart;
+use crate::utils::tag_diff::TagDiff;
+use crate::utils::track_stats::TrackStats;
+
+/// "recognize --json" prints the response of Shazam, or the differences
+/// with the tags of the file with --diff-tags
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[serde(untagged)]
+enum RecognizeOutput {
+    Song(ShazamResponse),
+    TagDiff(TagDiff),
+}
+
+/// "history export --format json" writes an object per song, whose keys
+/// are the exported columns
+type ExportedSongs = Vec<BTreeMap<String, String>>;
+
+/// The subcommands having a JSON output
+pub const SUBCOMMANDS: &[&str] = &[
+    "listen",
+    "recognize",
+    "replay",
+    "microphone-to-recognized-song",
+    "audio-file-to-recognized-song",
+    "fingerprint-to-recognized-song",
+    "dedupe",
+    "which-file",
+    "scan",
+    "chapters",
+    "charts",
+    "history export",
+    "history stats",
+];
+
+/// The schema of the JSON printed by a subcommand, e.g. "history export";
+/// "listen", "replay" and "scan" print one such document per line
+pub fn output_schema(subcommand: &str) -> Option<RootSchema> {
+    Some(match subcommand {
+        "listen"
+        | "replay"
+        | "microphone-to-recognized-song"
+        | "audio-file-to-recognized-song"
+        | "fingerprint-to-recognized-song" => schema_for!(ShazamResponse),
+        "recognize" => schema_for!(RecognizeOutput),
+        "dedupe" => schema_for!(Vec<DuplicateGroup>),
+        "which-file" => schema_for!(Vec<IndexMatch>),
+        "scan" => schema_for!(ScanResult),
+        "chapters" => schema_for!(PodcastChapters),
+        "charts" => schema_for!(Chart),
+        "history export" => schema_for!(ExportedSongs),
+        "history stats" => schema_for!(Vec<TrackStats>),
+        _ => return None,
+    })
+}
+
+pub fn schema_main(subcommand: &[String]) -> Result<(), Box<dyn Error>> {
+    let subcommand = subcommand.join(" ");
+    let schema = output_schema(&subcommand).ok_or_else(|| {
+        gettext("The \"%s\" subcommand has no JSON output, these ones do: %s")
+            .replacen("%s", &subcommand, 1)
+            .replacen("%s", &SUBCOMMANDS.join(", "), 1)
+    })?;
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+#[test]
+fn test_output_schema() {
+    for subcommand in SUBCOMMANDS {
+        assert!(output_schema(subcommand).is_some(), "{}", subcommand);
+    }
+    assert!(output_schema("index").is_none());
+
+    let schema = serde_json::to_value(output_schema("dedupe").unwrap()).unwrap();
+    assert_eq!(schema["type"], "array");
+    assert_eq!(
+        schema["definitions"]["DuplicateGroup"]["properties"]["keep"]["type"],
+        "string"
+    );
+    let schema = serde_json::to_value(output_schema("listen").unwrap()).unwrap();
+    assert!(schema["properties"]["track"].is_object());
+}
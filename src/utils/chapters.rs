@@ -0,0 +1,214 @@
+//! The chapters of a session recorded by "listen --record-session", read
+//! from its Vorbis comments and exported for the podcast players: as a
+//! Podcasting 2.0 chapters file (the JSON linked from the <podcast:chapters>
+//! tag of a feed), or as the ID3v2 CHAP and CTOC frames of an MP3 copy of
+//! the recording.
+
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, TagType};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::error::Error;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub start_secs: f64,
+    pub title: String,
+}
+
+/// As in the CHAPTERxxx comments, "HH:MM:SS.mmm"
+pub fn format_timestamp(position_secs: f64) -> String {
+    let millis = (position_secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut secs = 0.0;
+    for part in timestamp.split(':') {
+        secs = secs * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(secs)
+}
+
+/// The chapters of the CHAPTERxxx and CHAPTERxxxNAME comments, in order
+fn parse_chapters<'a>(comments: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<Chapter> {
+    let mut starts = std::collections::BTreeMap::new();
+    let mut titles = std::collections::HashMap::new();
+    for (key, value) in comments {
+        let key = key.to_ascii_uppercase();
+        let Some(number) = key.strip_prefix("CHAPTER") else {
+            continue;
+        };
+        match number.strip_suffix("NAME") {
+            Some(number) => {
+                titles.insert(number.to_string(), value.to_string());
+            }
+            None => {
+                if let (Ok(index), Some(start_secs)) =
+                    (number.parse::<u32>(), parse_timestamp(value))
+                {
+                    starts.insert(index, (number.to_string(), start_secs));
+                }
+            }
+        }
+    }
+    starts
+        .into_values()
+        .map(|(number, start_secs)| Chapter {
+            start_secs,
+            title: titles.remove(&number).unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// The chapters of a recording, and its duration in seconds
+pub fn read_chapters(recording: &Path) -> Result<(Vec<Chapter>, f64), Box<dyn Error>> {
+    let tagged_file = Probe::open(recording)?.read()?;
+    let duration_secs = tagged_file.properties().duration().as_secs_f64();
+    let Some(tag) = tagged_file.tag(TagType::VorbisComments) else {
+        return Ok((vec![], duration_secs));
+    };
+    // They are not among the comments that lofty knows of
+    let comments = tag
+        .items()
+        .filter_map(|item| match (item.key(), item.value().text()) {
+            (ItemKey::Unknown(key), Some(value)) => Some((key.as_str(), value)),
+            _ => None,
+        });
+    Ok((parse_chapters(comments), duration_secs))
+}
+
+/// A chapters file of the Podcasting 2.0 namespace
+#[derive(Serialize, JsonSchema)]
+pub struct PodcastChapters {
+    pub version: String,
+    pub chapters: Vec<PodcastChapter>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PodcastChapter {
+    /// In seconds
+    pub start_time: f64,
+    pub end_time: f64,
+    pub title: String,
+}
+
+/// Each chapter ends where the next one starts, the last one with the
+/// recording
+fn chapter_ends(chapters: &[Chapter], duration_secs: f64) -> Vec<f64> {
+    chapters
+        .iter()
+        .skip(1)
+        .map(|chapter| chapter.start_secs)
+        .chain(std::iter::once(duration_secs))
+        .collect()
+}
+
+pub fn podcast_chapters(chapters: &[Chapter], duration_secs: f64) -> PodcastChapters {
+    PodcastChapters {
+        version: "1.2.0".to_string(),
+        chapters: chapters
+            .iter()
+            .zip(chapter_ends(chapters, duration_secs))
+            .map(|(chapter, end_secs)| PodcastChapter {
+                start_time: chapter.start_secs,
+                end_time: end_secs,
+                title: chapter.title.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Replace the chapters of the ID3v2 tag of an MP3 file
+pub fn write_id3_chapters(
+    mp3: &Path,
+    chapters: &[Chapter],
+    duration_secs: f64,
+) -> Result<(), Box<dyn Error>> {
+    use id3::frame::{Chapter as ChapterFrame, TableOfContents};
+    use id3::{Frame, Tag, TagLike, Version};
+
+    let mut tag = match Tag::read_from_path(mp3) {
+        Ok(tag) => tag,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => Tag::new(),
+        Err(error) => return Err(error.into()),
+    };
+    tag.remove("CHAP");
+    tag.remove("CTOC");
+
+    let mut element_ids = vec![];
+    for (number, (chapter, end_secs)) in chapters
+        .iter()
+        .zip(chapter_ends(chapters, duration_secs))
+        .enumerate()
+    {
+        let element_id = format!("chp{}", number + 1);
+        tag.add_frame(ChapterFrame {
+            element_id: element_id.clone(),
+            start_time: (chapter.start_secs * 1000.0) as u32,
+            end_time: (end_secs * 1000.0) as u32,
+            // The offsets are unused
+            start_offset: u32::MAX,
+            end_offset: u32::MAX,
+            frames: vec![Frame::text("TIT2", chapter.title.clone())],
+        });
+        element_ids.push(element_id);
+    }
+    tag.add_frame(TableOfContents {
+        element_id: "toc".to_string(),
+        top_level: true,
+        ordered: true,
+        elements: element_ids,
+        frames: vec![],
+    });
+    tag.write_to_path(mp3, Version::Id3v24)?;
+    Ok(())
+}
+
+#[test]
+fn test_parse_chapters() {
+    let chapters = parse_chapters(
+        [
+            ("CHAPTER002NAME", "Justice - D.A.N.C.E."),
+            ("CHAPTER001", "00:00:00.000"),
+            ("chapter001name", "Daft Punk - Get Lucky"),
+            ("CHAPTER002", "00:04:05.250"),
+            ("ENCODER", "SongRec"),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(
+        chapters,
+        vec![
+            Chapter {
+                start_secs: 0.0,
+                title: "Daft Punk - Get Lucky".to_string()
+            },
+            Chapter {
+                start_secs: 245.25,
+                title: "Justice - D.A.N.C.E.".to_string()
+            },
+        ]
+    );
+    assert_eq!(format_timestamp(245.25), "00:04:05.250");
+
+    let podcast = podcast_chapters(&chapters, 600.0);
+    assert_eq!(podcast.chapters[0].end_time, 245.25);
+    assert_eq!(podcast.chapters[1].end_time, 600.0);
+    assert_eq!(
+        serde_json::to_value(&podcast.chapters[1]).unwrap()["startTime"],
+        245.25
+    );
+}
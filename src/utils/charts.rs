@@ -0,0 +1,283 @@
+//! Personal charts of the songs, artists and genres most heard during the last
+//! week or month, ranked by the statistics of `track_stats` as Billboard
+//! ranks the records: with the position each one had during the period
+//! before, or as a new entry. Shown in the GUI from the history menu, and
+//! printed by "songrec charts" in Markdown or JSON.
+
+use chrono::NaiveDateTime;
+use gettextrs::gettext;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::utils::clock;
+use crate::utils::csv_song_history::{CsvColumn, SongHistoryRecord};
+use crate::utils::genres::genre_name;
+use crate::utils::history_report::{escape_markdown, ReportPeriod};
+use crate::utils::track_stats::track_stats;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChartFormat {
+    Markdown,
+    Json,
+}
+
+impl FromStr for ChartFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "markdown" | "md" => Ok(ChartFormat::Markdown),
+            "json" => Ok(ChartFormat::Json),
+            _ => Err(gettext("Unknown chart format: %s").replace("%s", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ChartEntry {
+    /// From 1
+    pub position: usize,
+    pub name: String,
+    pub times_heard: usize,
+    /// None for a new entry
+    pub previous_position: Option<usize>,
+}
+
+/// The JSON output of "charts"
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Chart {
+    pub title: String,
+    /// As in the history
+    pub start: String,
+    pub end: String,
+    pub tracks: Vec<ChartEntry>,
+    pub artists: Vec<ChartEntry>,
+    /// Named in the language of the interface
+    pub genres: Vec<ChartEntry>,
+}
+
+fn chart_title(period: ReportPeriod) -> String {
+    match period {
+        ReportPeriod::Day => gettext("The charts of the day"),
+        ReportPeriod::Week => gettext("The charts of the week"),
+        ReportPeriod::Month => gettext("The charts of the month"),
+    }
+}
+
+/// The names, most heard first
+fn ranking<'a>(records: impl IntoIterator<Item = &'a SongHistoryRecord>) -> Vec<(String, usize)> {
+    track_stats(records)
+        .into_iter()
+        .map(|stats| (stats.song_name, stats.times_heard))
+        .collect()
+}
+
+/// The artists are ranked as songs named after them
+fn artist_records(records: &[&SongHistoryRecord]) -> Vec<SongHistoryRecord> {
+    records
+        .iter()
+        .filter_map(|record| {
+            let artist = CsvColumn::Artist.value(record, None);
+            (!artist.is_empty()).then(|| SongHistoryRecord {
+                song_name: artist,
+                ..(*record).clone()
+            })
+        })
+        .collect()
+}
+
+/// The genres are ranked by their English name, whatever the way Shazam
+/// writes them, and shown translated
+fn genre_records(records: &[&SongHistoryRecord]) -> Vec<SongHistoryRecord> {
+    records
+        .iter()
+        .filter_map(|record| {
+            let genre = record.genre.as_deref().unwrap_or_default();
+            (!genre.trim().is_empty()).then(|| SongHistoryRecord {
+                song_name: genre_name(genre),
+                ..(*record).clone()
+            })
+        })
+        .collect()
+}
+
+fn chart_entries(
+    current: Vec<(String, usize)>,
+    previous: Vec<(String, usize)>,
+    limit: usize,
+) -> Vec<ChartEntry> {
+    let previous_positions: HashMap<String, usize> = previous
+        .into_iter()
+        .enumerate()
+        .map(|(index, (name, _))| (name, index + 1))
+        .collect();
+    current
+        .into_iter()
+        .take(limit)
+        .enumerate()
+        .map(|(index, (name, times_heard))| ChartEntry {
+            position: index + 1,
+            previous_position: previous_positions.get(&name).copied(),
+            name,
+            times_heard,
+        })
+        .collect()
+}
+
+/// The `limit` songs and artists most heard during the period ending now,
+/// compared with the period before
+pub fn build_chart(
+    records: &[SongHistoryRecord],
+    period: ReportPeriod,
+    now: NaiveDateTime,
+    limit: usize,
+) -> Chart {
+    let range = period.range(now);
+    let start = range.start.unwrap();
+    let previous_range = period.range(start);
+    let current: Vec<&SongHistoryRecord> = records
+        .iter()
+        .filter(|record| range.contains(record))
+        .collect();
+    let previous: Vec<&SongHistoryRecord> = records
+        .iter()
+        .filter(|record| previous_range.contains(record) && !range.contains(record))
+        .collect();
+
+    Chart {
+        title: chart_title(period),
+        start: start.format(clock::HISTORY_DATE_FORMAT).to_string(),
+        end: now.format(clock::HISTORY_DATE_FORMAT).to_string(),
+        tracks: chart_entries(
+            ranking(current.iter().copied()),
+            ranking(previous.iter().copied()),
+            limit,
+        ),
+        artists: chart_entries(
+            ranking(&artist_records(&current)),
+            ranking(&artist_records(&previous)),
+            limit,
+        ),
+        genres: chart_entries(
+            ranking(&genre_records(&current)),
+            ranking(&genre_records(&previous)),
+            limit,
+        ),
+    }
+}
+
+/// The previous position, "=" when unchanged, or "New"
+pub fn movement_text(entry: &ChartEntry) -> String {
+    match entry.previous_position {
+        None => gettext("New"),
+        Some(previous) if previous == entry.position => "=".to_string(),
+        Some(previous) if previous > entry.position => format!("▲ {}", previous),
+        Some(previous) => format!("▼ {}", previous),
+    }
+}
+
+fn render_entries(heading: &str, entries: &[ChartEntry]) -> String {
+    let mut text = format!(
+        "\n## {}\n\n| # | {} | {} | {} |\n|---:|:---:|---|---:|\n",
+        heading,
+        gettext("Previous"),
+        gettext("Name"),
+        gettext("Heard")
+    );
+    for entry in entries {
+        text.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            entry.position,
+            movement_text(entry),
+            escape_markdown(&entry.name),
+            entry.times_heard
+        ));
+    }
+    text
+}
+
+pub fn render_chart(chart: &Chart, format: ChartFormat) -> String {
+    if format == ChartFormat::Json {
+        return serde_json::to_string_pretty(chart).unwrap() + "\n";
+    }
+    let mut text = format!("# {}\n\n{} – {}\n", chart.title, chart.start, chart.end);
+    if chart.tracks.is_empty() {
+        text.push_str(&format!(
+            "\n{}\n",
+            gettext("No song was recognized during this period")
+        ));
+        return text;
+    }
+    text.push_str(&render_entries(&gettext("Top songs"), &chart.tracks));
+    if !chart.artists.is_empty() {
+        text.push_str(&render_entries(&gettext("Top artists"), &chart.artists));
+    }
+    if !chart.genres.is_empty() {
+        text.push_str(&render_entries(&gettext("Top genres"), &chart.genres));
+    }
+    text
+}
+
+#[test]
+fn test_build_chart() {
+    let record = |song_name: &str, recognition_date: &str| SongHistoryRecord {
+        genre: Some(
+            if song_name.starts_with("Air") {
+                "Electronica"
+            } else {
+                "Electronic"
+            }
+            .to_string(),
+        ),
+        ..SongHistoryRecord::for_test(song_name, recognition_date)
+    };
+    let records = vec![
+        // The week before
+        record("Justice - Genesis", "Sat Aug 10 22:00:00 2024"),
+        record("Justice - Genesis", "Sat Aug 10 22:10:00 2024"),
+        record("Daft Punk - Da Funk", "Sun Aug 11 22:00:00 2024"),
+        // The week of the chart
+        record("Daft Punk - Da Funk", "Sat Aug 17 22:00:00 2024"),
+        record("Daft Punk - Da Funk", "Sat Aug 17 23:00:00 2024"),
+        record("Justice - Genesis", "Sun Aug 18 22:00:00 2024"),
+        record("Air - La femme d'argent", "Sun Aug 18 23:00:00 2024"),
+        record("Justice - D.A.N.C.E.", "Sun Aug 18 23:30:00 2024"),
+    ];
+    let now = chrono::NaiveDateTime::parse_from_str("Mon Aug 19 12:00:00 2024", "%c").unwrap();
+
+    let chart = build_chart(&records, ReportPeriod::Week, now, 3);
+    let names: Vec<&str> = chart
+        .tracks
+        .iter()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "Daft Punk - Da Funk",
+            "Justice - D.A.N.C.E.",
+            "Air - La femme d'argent"
+        ]
+    );
+    assert_eq!(chart.tracks[0].times_heard, 2);
+    assert_eq!(chart.tracks[0].previous_position, Some(2));
+    assert_eq!(movement_text(&chart.tracks[0]), "▲ 2");
+    assert_eq!(chart.tracks[1].previous_position, None);
+    assert_eq!(
+        chart.artists[0],
+        ChartEntry {
+            position: 1,
+            name: "Justice".to_string(),
+            times_heard: 2,
+            previous_position: Some(1),
+        }
+    );
+
+    assert_eq!(chart.genres.len(), 1);
+    assert_eq!(chart.genres[0].times_heard, 5);
+
+    let markdown = render_chart(&chart, ChartFormat::Markdown);
+    assert!(markdown.contains("| 1 | ▲ 2 | Daft Punk - Da Funk | 2 |"));
+}
@@ -0,0 +1,284 @@
+//! Translation of the parts of the command-line help that clap writes
+//! itself ("Usage:", "Options:", "Print help", the "help" subcommand...)
+//! and of its parsing errors. The app is walked once it is built, so any
+//! new subcommand is translated without being listed here; its own help
+//! texts go through gettext where it is declared.
+
+use clap::error::{ContextKind, ErrorKind};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use gettextrs::gettext;
+use std::ffi::OsString;
+
+/// The value of --lang, read before the app is built since its help texts
+/// are translated at that point
+pub fn requested_language() -> Option<String> {
+    let mut args = std::env::args().skip(1).take_while(|arg| arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--lang" {
+            return args.next();
+        }
+        if let Some(language) = arg.strip_prefix("--lang=") {
+            return Some(language.to_string());
+        }
+    }
+    None
+}
+
+/// Translate the app and all its subcommands
+pub fn translate_command(command: Command) -> Command {
+    translate(command, true)
+}
+
+fn translate(mut command: Command, is_root: bool) -> Command {
+    command = command
+        .help_template(format!(
+            "{{before-help}}{{about-with-newline}}\n{} {{usage}}\n\n{{all-args}}{{after-help}}",
+            gettext("Usage:")
+        ))
+        .subcommand_help_heading(gettext("Commands"))
+        .disable_help_flag(true)
+        .arg(
+            Arg::new("help")
+                .short('h')
+                .long("help")
+                .action(ArgAction::Help)
+                .help(gettext("Print help")),
+        );
+    if is_root {
+        command = command.disable_version_flag(true).arg(
+            Arg::new("version")
+                .short('V')
+                .long("version")
+                .action(ArgAction::Version)
+                .help(gettext("Print version")),
+        );
+    }
+
+    let argument_ids: Vec<_> = command
+        .get_arguments()
+        .filter(|argument| argument.get_help_heading().is_none())
+        .map(|argument| (argument.get_id().clone(), argument.is_positional()))
+        .collect();
+    for (id, is_positional) in argument_ids {
+        command = command.mut_arg(id, |argument| {
+            argument.help_heading(if is_positional {
+                gettext("Arguments")
+            } else {
+                gettext("Options")
+            })
+        });
+    }
+
+    let subcommand_names: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    for name in subcommand_names {
+        command = command.mut_subcommand(name, |subcommand| translate(subcommand, false));
+    }
+    if command.has_subcommands() {
+        // Replaces the built-in one, whose description can't be changed;
+        // it is handled by get_matches()
+        command = command.disable_help_subcommand(true).subcommand(translate(
+            Command::new("help")
+                .about(gettext(
+                    "Print this message or the help of the given subcommand(s)",
+                ))
+                .arg(
+                    Arg::new("command")
+                        .value_name("COMMAND")
+                        .num_args(0..)
+                        .help(gettext("The subcommand to print the help of")),
+                ),
+            false,
+        ));
+    }
+    command
+}
+
+/// The subcommands which a "help" subcommand was given for
+fn help_request(matches: &ArgMatches) -> Option<Vec<String>> {
+    let mut path = vec![];
+    let mut matches = matches;
+    while let Some((name, subcommand_matches)) = matches.subcommand() {
+        if name == "help" {
+            path.extend(
+                subcommand_matches
+                    .get_many::<String>("command")
+                    .into_iter()
+                    .flatten()
+                    .cloned(),
+            );
+            return Some(path);
+        }
+        path.push(name.to_string());
+        matches = subcommand_matches;
+    }
+    None
+}
+
+/// Parse the arguments of the program, printing the help and the errors
+/// in the language of the user
+pub fn get_matches(command: Command) -> ArgMatches {
+    get_matches_from(command, std::env::args_os().collect())
+}
+
+fn get_matches_from(mut command: Command, args: Vec<OsString>) -> ArgMatches {
+    match command.try_get_matches_from_mut(&args) {
+        Ok(matches) => match help_request(&matches) {
+            Some(path) => {
+                let help_args = args
+                    .iter()
+                    .take(1)
+                    .cloned()
+                    .chain(path.into_iter().map(OsString::from))
+                    .chain(std::iter::once(OsString::from("--help")))
+                    .collect();
+                get_matches_from(command, help_args)
+            }
+            None => matches,
+        },
+        Err(error) => match error.kind() {
+            ErrorKind::DisplayHelp
+            | ErrorKind::DisplayVersion
+            | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand => error.exit(),
+            _ => {
+                eprintln!("{}", translate_error(&error));
+                std::process::exit(error.exit_code());
+            }
+        },
+    }
+}
+
+fn context(error: &clap::Error, kind: ContextKind) -> Option<String> {
+    error.get(kind).map(|value| value.to_string())
+}
+
+fn translate_error(error: &clap::Error) -> String {
+    let argument = context(error, ContextKind::InvalidArg).unwrap_or_default();
+    let value = context(error, ContextKind::InvalidValue).unwrap_or_default();
+    let subcommand = context(error, ContextKind::InvalidSubcommand).unwrap_or_default();
+    let mut message = match error.kind() {
+        ErrorKind::UnknownArgument => {
+            gettext("unexpected argument '%s' found").replace("%s", &argument)
+        }
+        ErrorKind::InvalidSubcommand => {
+            gettext("unrecognized subcommand '%s'").replace("%s", &subcommand)
+        }
+        ErrorKind::InvalidValue => {
+            let mut message = gettext("invalid value '%s' for '%s'")
+                .replacen("%s", &value, 1)
+                .replacen("%s", &argument, 1);
+            if let Some(valid_values) = context(error, ContextKind::ValidValue) {
+                message += &format!(
+                    "\n  [{}]",
+                    gettext("possible values: %s").replace("%s", &valid_values)
+                );
+            }
+            message
+        }
+        ErrorKind::ValueValidation => {
+            let mut message = gettext("invalid value '%s' for '%s'")
+                .replacen("%s", &value, 1)
+                .replacen("%s", &argument, 1);
+            if let Some(source) = std::error::Error::source(error) {
+                message += &format!(": {}", source);
+            }
+            message
+        }
+        ErrorKind::MissingRequiredArgument => {
+            gettext("the following required arguments were not provided: %s")
+                .replace("%s", &argument)
+        }
+        ErrorKind::MissingSubcommand => {
+            gettext("'%s' requires a subcommand but one was not provided")
+                .replace("%s", &subcommand)
+        }
+        ErrorKind::ArgumentConflict => gettext("the argument '%s' cannot be used with '%s'")
+            .replacen("%s", &argument, 1)
+            .replacen(
+                "%s",
+                &context(error, ContextKind::PriorArg).unwrap_or_default(),
+                1,
+            ),
+        ErrorKind::TooManyValues | ErrorKind::WrongNumberOfValues => {
+            gettext("unexpected number of values for '%s'").replace("%s", &argument)
+        }
+        // Rare enough to be shown as clap writes them
+        _ => {
+            let rendered = error.render().to_string();
+            let first_line = rendered.lines().next().unwrap_or_default();
+            first_line
+                .strip_prefix("error: ")
+                .unwrap_or(first_line)
+                .to_string()
+        }
+    };
+    for (kind, tip) in [
+        (
+            ContextKind::SuggestedArg,
+            gettext("a similar argument exists: '%s'"),
+        ),
+        (
+            ContextKind::SuggestedSubcommand,
+            gettext("a similar subcommand exists: '%s'"),
+        ),
+    ] {
+        if let Some(suggestion) = context(error, kind) {
+            message += &format!(
+                "\n\n  {} {}",
+                gettext("tip:"),
+                tip.replace("%s", &suggestion)
+            );
+        }
+    }
+
+    let mut lines = vec![format!("{} {}", gettext("error:"), message)];
+    if let Some(usage) = context(error, ContextKind::Usage) {
+        let usage = usage.trim();
+        lines.push(format!(
+            "\n{} {}",
+            gettext("Usage:"),
+            usage.strip_prefix("Usage:").unwrap_or(usage).trim()
+        ));
+    }
+    lines.push(format!(
+        "\n{}",
+        gettext("For more information, try '--help'.")
+    ));
+    lines.join("\n")
+}
+
+#[test]
+fn test_translate_command() {
+    let command = translate_command(
+        Command::new("songrec")
+            .arg(
+                Arg::new("verbose")
+                    .long("verbose")
+                    .action(ArgAction::SetTrue),
+            )
+            .subcommand(
+                Command::new("history").subcommand(Command::new("export").arg(Arg::new("file"))),
+            ),
+    );
+    let history = command.find_subcommand("history").unwrap();
+    assert!(history.find_subcommand("help").is_some());
+    let export = history.find_subcommand("export").unwrap();
+    assert!(export
+        .get_arguments()
+        .all(|argument| argument.get_help_heading().is_some()));
+
+    let matches = command
+        .clone()
+        .try_get_matches_from(["songrec", "help", "history", "export"])
+        .unwrap();
+    assert_eq!(
+        help_request(&matches),
+        Some(vec!["history".to_string(), "export".to_string()])
+    );
+    let matches = command
+        .try_get_matches_from(["songrec", "--verbose", "history", "export", "x"])
+        .unwrap();
+    assert_eq!(help_request(&matches), None);
+}
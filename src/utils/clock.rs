@@ -0,0 +1,243 @@
+//! The time of the recognitions, in the time zone set with `--timezone`,
+//! the SONGREC_TIMEZONE environment variable or the "time_zone" preference
+//! (an IANA name such as "Europe/Paris"), or the one of the system.
+//!
+//! The history stores the dates of the entries as local wall-clock times,
+//! followed by their UTC offset for the new entries when the
+//! "history_date_offsets" preference is on (older versions of SongRec and
+//! the scripts reading the history only expect `HISTORY_DATE_FORMAT`).
+//! Entries without an offset are converted back to absolute times through
+//! `to_zoned`, since the same wall-clock time happens twice when clocks go
+//! back at the end of daylight saving time, and not at all when they go
+//! forward.
+
+use chrono::offset::LocalResult;
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone,
+};
+use chrono_tz::Tz;
+use gettextrs::gettext;
+use std::sync::{LazyLock, RwLock};
+
+use crate::core::preferences::PreferencesInterface;
+
+/// Format of the dates of the history, as written by older versions
+pub const HISTORY_DATE_FORMAT: &str = "%c";
+
+/// Format of the dates of the new entries of the history, with their
+/// offset, e.g. "Sat Aug 17 22:44:43 2024 +02:00"
+pub const ZONED_HISTORY_DATE_FORMAT: &str = "%c %:z";
+
+static TIME_ZONE: RwLock<Option<Tz>> = RwLock::new(None);
+
+/// Read from the preferences when a date is first written to the history
+static HISTORY_DATE_OFFSETS: LazyLock<bool> =
+    LazyLock::new(|| PreferencesInterface::new().preferences.history_date_offsets == Some(true));
+
+pub fn parse_time_zone(name: &str) -> Result<Tz, String> {
+    name.trim()
+        .parse::<Tz>()
+        .map_err(|_| format!("Unknown time zone: {}", name))
+}
+
+/// None follows the time zone of the system
+pub fn set_time_zone(time_zone: Option<Tz>) {
+    *TIME_ZONE.write().unwrap() = time_zone;
+}
+
+pub fn time_zone() -> Option<Tz> {
+    *TIME_ZONE.read().unwrap()
+}
+
+/// The current wall-clock time
+pub fn now() -> NaiveDateTime {
+    now_zoned().naive_local()
+}
+
+pub fn now_zoned() -> DateTime<FixedOffset> {
+    match time_zone() {
+        Some(time_zone) => chrono::Utc::now().with_timezone(&time_zone).fixed_offset(),
+        None => Local::now().fixed_offset(),
+    }
+}
+
+/// The current time, as stored in the history
+pub fn history_date_now() -> String {
+    format_history_date(&now_zoned())
+}
+
+/// A date as stored in the history, with its offset when enabled
+pub fn format_history_date(date: &DateTime<FixedOffset>) -> String {
+    if *HISTORY_DATE_OFFSETS {
+        format_zoned_history_date(date)
+    } else {
+        date.format(HISTORY_DATE_FORMAT).to_string()
+    }
+}
+
+pub fn format_zoned_history_date(date: &DateTime<FixedOffset>) -> String {
+    date.format(ZONED_HISTORY_DATE_FORMAT).to_string()
+}
+
+/// The wall-clock time of a date of the history, with or without an offset
+pub fn parse_history_date(date: &str) -> Option<NaiveDateTime> {
+    DateTime::parse_from_str(date, ZONED_HISTORY_DATE_FORMAT)
+        .map(|date| date.naive_local())
+        .or_else(|_| NaiveDateTime::parse_from_str(date, HISTORY_DATE_FORMAT))
+        .ok()
+}
+
+/// The absolute time of a date of the history, from its offset, or else
+/// from the time zone for the older entries
+pub fn parse_zoned_history_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(date, ZONED_HISTORY_DATE_FORMAT)
+        .ok()
+        .or_else(|| parse_history_date(date).and_then(to_zoned))
+}
+
+/// The translated name of a month, from 1 for January, as chrono only
+/// formats the English ones
+pub fn month_name(month: u32) -> String {
+    match month {
+        1 => gettext("January"),
+        2 => gettext("February"),
+        3 => gettext("March"),
+        4 => gettext("April"),
+        5 => gettext("May"),
+        6 => gettext("June"),
+        7 => gettext("July"),
+        8 => gettext("August"),
+        9 => gettext("September"),
+        10 => gettext("October"),
+        11 => gettext("November"),
+        _ => gettext("December"),
+    }
+}
+
+/// A day as shown to the user, e.g. "17 August 2024"
+pub fn display_day(day: NaiveDate) -> String {
+    gettext("%d %B %Y")
+        .replacen("%d", &day.day().to_string(), 1)
+        .replacen("%B", &month_name(day.month()), 1)
+        .replacen("%Y", &day.year().to_string(), 1)
+}
+
+/// A date of the history as shown to the user, e.g. "17 August 2024,
+/// 22:44", or as stored when it can't be parsed
+pub fn display_date(date: &str) -> String {
+    match parse_history_date(date) {
+        Some(date) => gettext("%s, %s")
+            .replacen("%s", &display_day(date.date()), 1)
+            .replacen("%s", &date.format("%H:%M").to_string(), 1),
+        None => date.to_string(),
+    }
+}
+
+/// The ISO 8601 formats accepted for the dates entered by hand
+const ENTERED_DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+];
+
+/// A date entered by hand, as a date of the history or as an ISO 8601 date
+/// such as "2024-08-17 22:44", in the format of the history
+pub fn parse_entered_date(text: &str) -> Option<String> {
+    let text = text.trim();
+    if parse_history_date(text).is_some() {
+        return Some(text.to_string());
+    }
+    let date = ENTERED_DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())?;
+    Some(format_wall_clock_history_date(date))
+}
+
+/// A wall-clock time as stored in the history
+pub fn format_wall_clock_history_date(date: NaiveDateTime) -> String {
+    match to_zoned(date) {
+        Some(zoned) => format_history_date(&zoned),
+        None => date.format(HISTORY_DATE_FORMAT).to_string(),
+    }
+}
+
+fn resolve<T: TimeZone>(result: LocalResult<DateTime<T>>) -> Option<DateTime<FixedOffset>> {
+    match result {
+        LocalResult::Single(date) => Some(date.fixed_offset()),
+        // The first occurrence, before the clocks went back
+        LocalResult::Ambiguous(earliest, _) => Some(earliest.fixed_offset()),
+        LocalResult::None => None,
+    }
+}
+
+fn to_zoned_in(date: NaiveDateTime, time_zone: Option<Tz>) -> Option<DateTime<FixedOffset>> {
+    let localize = |date: &NaiveDateTime| match time_zone {
+        Some(time_zone) => resolve(time_zone.from_local_datetime(date)),
+        None => resolve(Local.from_local_datetime(date)),
+    };
+    // Times skipped when the clocks go forward are read as the ones which
+    // follow the change, as a clock that wasn't updated yet would show them
+    localize(&date).or_else(|| localize(&(date + Duration::hours(1))))
+}
+
+/// The absolute time of a wall-clock time of the history
+pub fn to_zoned(date: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    to_zoned_in(date, time_zone())
+}
+
+#[test]
+fn test_to_zoned() {
+    let paris = parse_time_zone("Europe/Paris").unwrap();
+    let date = |text| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+
+    // The clocks went back from 3:00 to 2:00 on that night
+    assert_eq!(
+        to_zoned_in(date("2024-10-27 02:30"), Some(paris))
+            .unwrap()
+            .to_rfc3339(),
+        "2024-10-27T02:30:00+02:00"
+    );
+    // And forward from 2:00 to 3:00 in the spring
+    assert_eq!(
+        to_zoned_in(date("2024-03-31 02:30"), Some(paris))
+            .unwrap()
+            .to_rfc3339(),
+        "2024-03-31T03:30:00+02:00"
+    );
+    assert_eq!(
+        to_zoned_in(date("2024-07-01 12:00"), Some(paris))
+            .unwrap()
+            .to_rfc3339(),
+        "2024-07-01T12:00:00+02:00"
+    );
+    assert!(parse_time_zone("Mars/Olympus_Mons").is_err());
+}
+
+#[test]
+fn test_parse_history_date() {
+    let zoned = DateTime::parse_from_rfc3339("2024-10-27T02:30:00+01:00").unwrap();
+    let text = format_zoned_history_date(&zoned);
+    assert_eq!(text, "Sun Oct 27 02:30:00 2024 +01:00");
+    assert_eq!(parse_zoned_history_date(&text), Some(zoned));
+    assert_eq!(parse_history_date(&text), Some(zoned.naive_local()));
+    assert_eq!(
+        parse_entered_date(" Sat Aug 17 22:44:43 2024 ").as_deref(),
+        Some("Sat Aug 17 22:44:43 2024")
+    );
+    assert!(parse_entered_date("2024-08-17 22:44")
+        .and_then(|date| parse_history_date(&date))
+        .is_some_and(|date| date.to_string() == "2024-08-17 22:44:00"));
+    assert_eq!(parse_entered_date("17/08/2024"), None);
+    assert_eq!(
+        display_date("Sun Oct 27 02:30:00 2024 +01:00"),
+        "27 October 2024, 02:30"
+    );
+    assert_eq!(display_date("invalid"), "invalid");
+    assert_eq!(
+        parse_history_date("Sat Aug 17 22:44:43 2024")
+            .unwrap()
+            .to_string(),
+        "2024-08-17 22:44:43"
+    );
+}
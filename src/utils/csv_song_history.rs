@@ -7,6 +7,10 @@
 /// file, while antichronological order is used on the GUI list view.
 use serde::{Deserialize, Serialize};
 
+use crate::utils::clock;
+use crate::utils::genres;
+use crate::utils::track_identifiers;
+
 #[derive(Debug, Serialize, Deserialize, Hash, PartialEq, Eq, Clone)]
 pub struct SongHistoryRecord {
     pub song_name: String,
@@ -21,6 +25,66 @@ pub struct SongHistoryRecord {
     #[serde(default)]
     pub genre: Option<String>,
     pub recognition_date: String,
+
+    // Added in version 0.7.3, see `RecognitionSource`
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// Deleted history entries are kept this long in the recycle bin
+pub const RECYCLE_BIN_DAYS: i64 = 30;
+
+/// An entry of the recycle bin of the song history, which stores the
+/// entries deleted from the GUI so that they may be restored
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct DeletedSongHistoryRecord {
+    pub song_name: String,
+    #[serde(default)]
+    pub album: Option<String>,
+    #[serde(default)]
+    pub track_key: Option<String>,
+    #[serde(default)]
+    pub release_year: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    pub recognition_date: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    pub deletion_date: String,
+}
+
+impl DeletedSongHistoryRecord {
+    pub fn new(record: SongHistoryRecord, deletion_date: String) -> Self {
+        DeletedSongHistoryRecord {
+            song_name: record.song_name,
+            album: record.album,
+            track_key: record.track_key,
+            release_year: record.release_year,
+            genre: record.genre,
+            recognition_date: record.recognition_date,
+            source: record.source,
+            deletion_date,
+        }
+    }
+
+    pub fn to_record(&self) -> SongHistoryRecord {
+        SongHistoryRecord {
+            song_name: self.song_name.clone(),
+            album: self.album.clone(),
+            track_key: self.track_key.clone(),
+            release_year: self.release_year.clone(),
+            genre: self.genre.clone(),
+            recognition_date: self.recognition_date.clone(),
+            source: self.source.clone(),
+        }
+    }
+
+    /// Whether the entry was deleted more than `RECYCLE_BIN_DAYS` ago,
+    /// relative to `now`
+    pub fn is_expired(&self, now: chrono::NaiveDateTime) -> bool {
+        clock::parse_history_date(&self.deletion_date)
+            .is_some_and(|date| now - date > chrono::Duration::days(RECYCLE_BIN_DAYS))
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
@@ -55,3 +119,495 @@ impl HasSong for SongHistoryRecord {
         }
     }
 }
+
+/// A column that may be selected for CSV output, with `--csv-columns`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CsvColumn {
+    SongName,
+    Title,
+    Artist,
+    Album,
+    TrackKey,
+    ReleaseYear,
+    Genre,
+    RecognitionDate,
+    Timestamp,
+    ShazamUrl,
+    SpotifyUri,
+    Source,
+    BandcampUrl,
+    Isrc,
+    Upc,
+}
+
+/// The columns of the song history file, in order
+pub const DEFAULT_CSV_COLUMNS: &[CsvColumn] = &[
+    CsvColumn::SongName,
+    CsvColumn::Album,
+    CsvColumn::TrackKey,
+    CsvColumn::ReleaseYear,
+    CsvColumn::Genre,
+    CsvColumn::RecognitionDate,
+];
+
+const CSV_COLUMN_NAMES: &[&str] = &[
+    "song_name",
+    "title",
+    "artist",
+    "album",
+    "track_key",
+    "release_year",
+    "genre",
+    "recognition_date",
+    "timestamp",
+    "shazam_url",
+    "spotify_uri",
+    "source",
+    "bandcamp_url",
+    "isrc",
+    "upc",
+];
+
+impl std::str::FromStr for CsvColumn {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "song_name" => CsvColumn::SongName,
+            "title" => CsvColumn::Title,
+            "artist" => CsvColumn::Artist,
+            "album" => CsvColumn::Album,
+            "track_key" => CsvColumn::TrackKey,
+            "release_year" => CsvColumn::ReleaseYear,
+            "genre" => CsvColumn::Genre,
+            "recognition_date" => CsvColumn::RecognitionDate,
+            "timestamp" => CsvColumn::Timestamp,
+            "shazam_url" => CsvColumn::ShazamUrl,
+            "spotify_uri" => CsvColumn::SpotifyUri,
+            "source" => CsvColumn::Source,
+            "bandcamp_url" => CsvColumn::BandcampUrl,
+            "isrc" => CsvColumn::Isrc,
+            "upc" => CsvColumn::Upc,
+            _ => return Err(format!("Unknown CSV column: {}", name)),
+        })
+    }
+}
+
+/// A comma-separated list of columns, as given to `--csv-columns`
+pub fn parse_csv_columns(list: &str) -> Result<Vec<CsvColumn>, String> {
+    let columns = list
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<CsvColumn>, String>>()?;
+    if columns.is_empty() {
+        return Err("No CSV column was given".to_string());
+    }
+    Ok(columns)
+}
+
+impl CsvColumn {
+    pub fn name(&self) -> &'static str {
+        CSV_COLUMN_NAMES[*self as usize]
+    }
+
+    /// The Shazam response is optional, as it is not part of the song
+    /// history. Columns which can only be obtained from it are left empty
+    /// without it.
+    pub fn value(
+        &self,
+        record: &SongHistoryRecord,
+        shazam_json: Option<&serde_json::Value>,
+    ) -> String {
+        let track = shazam_json.map(|json| &json["track"]);
+        let from_track = |key: &str| {
+            track
+                .and_then(|track| track[key].as_str())
+                .map(|value| value.to_string())
+        };
+        // Older entries only store "Artist - Title"
+        let split_song_name = || record.song_name.split_once(" - ");
+
+        match self {
+            CsvColumn::SongName => record.song_name.clone(),
+            CsvColumn::Title => from_track("title")
+                .or_else(|| split_song_name().map(|(_, title)| title.to_string()))
+                .unwrap_or_else(|| record.song_name.clone()),
+            CsvColumn::Artist => from_track("subtitle")
+                .or_else(|| split_song_name().map(|(artist, _)| artist.to_string()))
+                .unwrap_or_default(),
+            CsvColumn::Album => record.album.clone().unwrap_or_default(),
+            CsvColumn::TrackKey => record.track_key.clone().unwrap_or_default(),
+            CsvColumn::ReleaseYear => record.release_year.clone().unwrap_or_default(),
+            CsvColumn::Genre => record
+                .genre
+                .as_deref()
+                .map(genres::genre_name)
+                .unwrap_or_default(),
+            CsvColumn::RecognitionDate => record.recognition_date.clone(),
+            CsvColumn::Timestamp => clock::parse_zoned_history_date(&record.recognition_date)
+                .map(|date| date.to_rfc3339())
+                .unwrap_or_default(),
+            CsvColumn::ShazamUrl => from_track("url").unwrap_or_default(),
+            CsvColumn::SpotifyUri => track
+                .and_then(|track| track["hub"]["providers"].as_array())
+                .and_then(|providers| {
+                    providers
+                        .iter()
+                        .find(|provider| provider["type"] == "SPOTIFY")
+                })
+                .and_then(|provider| provider["actions"][0]["uri"].as_str())
+                .map(|uri| uri.to_string())
+                .unwrap_or_default(),
+            CsvColumn::Source => record.source.clone().unwrap_or_default(),
+            CsvColumn::BandcampUrl => crate::utils::song_link::bandcamp_search_url(
+                &CsvColumn::Artist.value(record, shazam_json),
+                &CsvColumn::Title.value(record, shazam_json),
+            ),
+            CsvColumn::Isrc => shazam_json
+                .and_then(track_identifiers::isrc_from_response)
+                .unwrap_or_default(),
+            CsvColumn::Upc => shazam_json
+                .and_then(track_identifiers::upc_from_response)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Read a song history or favorites file, in chronological order
+pub fn read_song_history(
+    csv_path: &std::path::Path,
+) -> Result<Vec<SongHistoryRecord>, Box<dyn std::error::Error>> {
+    let contents = crate::utils::history_encryption::read_history_file(csv_path)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(contents.as_slice());
+    let mut records = reader
+        .deserialize()
+        .collect::<Result<Vec<SongHistoryRecord>, _>>()?;
+    records.sort_by_cached_key(|record| clock::parse_history_date(&record.recognition_date));
+    Ok(records)
+}
+
+/// Write a song history or favorites file, replacing its contents
+pub fn write_song_history(
+    csv_path: &std::path::Path,
+    records: &[SongHistoryRecord],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    crate::utils::history_encryption::write_history_file(csv_path, &writer.into_inner()?)
+}
+
+/// Drop the oldest entries of a chronological history beyond `max_entries`
+/// entries, or recognized more than `max_months` months before `now`; zero
+/// means no limit. Returns the number of dropped entries.
+pub fn apply_retention(
+    records: &mut Vec<SongHistoryRecord>,
+    max_entries: u32,
+    max_months: u32,
+    now: chrono::NaiveDateTime,
+) -> usize {
+    let count = records.len();
+    if max_months > 0 {
+        if let Some(oldest) = now.checked_sub_months(chrono::Months::new(max_months)) {
+            records.retain(|record| {
+                clock::parse_history_date(&record.recognition_date)
+                    .is_none_or(|date| date >= oldest)
+            });
+        }
+    }
+    if max_entries > 0 && records.len() > max_entries as usize {
+        records.drain(..records.len() - max_entries as usize);
+    }
+    count - records.len()
+}
+
+/// Enforce the retention policy on a history file, returning the number of
+/// removed entries
+pub fn prune_song_history(
+    csv_path: &std::path::Path,
+    max_entries: u32,
+    max_months: u32,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    if (max_entries == 0 && max_months == 0) || !csv_path.exists() {
+        return Ok(0);
+    }
+    let mut records = read_song_history(csv_path)?;
+    let removed = apply_retention(&mut records, max_entries, max_months, clock::now());
+    if removed > 0 {
+        write_song_history(csv_path, &records)?;
+    }
+    Ok(removed)
+}
+
+/// An entry removed by `dedupe_history`, with the one it duplicates
+pub struct DuplicateEntry {
+    pub removed: SongHistoryRecord,
+    pub kept: SongHistoryRecord,
+}
+
+/// "Daft Punk -  Around the world" and "daft punk - Around The World" are
+/// the same song
+fn normalize_song_name(song_name: &str) -> String {
+    song_name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn is_same_track(first: &SongHistoryRecord, second: &SongHistoryRecord) -> bool {
+    let shazam_key = |record: &SongHistoryRecord| {
+        record
+            .track_key
+            .clone()
+            .filter(|key| crate::utils::song_link::is_shazam_track_key(key))
+    };
+    (shazam_key(first).is_some() && shazam_key(first) == shazam_key(second))
+        || normalize_song_name(&first.song_name) == normalize_song_name(&second.song_name)
+}
+
+/// Collapse the exact duplicates of a chronological history, as well as the
+/// entries of a track recognized again less than `window` after its previous
+/// entry (a song playing on repeat is logged once). The kept entries receive
+/// the album, year and genre which only their duplicates had.
+pub fn dedupe_history(
+    records: Vec<SongHistoryRecord>,
+    window: chrono::Duration,
+) -> (Vec<SongHistoryRecord>, Vec<DuplicateEntry>) {
+    let mut kept: Vec<SongHistoryRecord> = vec![];
+    // The date of the last entry of each kept track, duplicates included
+    let mut last_seen: Vec<Option<chrono::NaiveDateTime>> = vec![];
+    let mut duplicates = vec![];
+
+    for record in records {
+        let date = clock::parse_history_date(&record.recognition_date);
+        let duplicated = kept
+            .iter()
+            .rposition(|other| is_same_track(other, &record))
+            .filter(|&index| {
+                kept[index].recognition_date == record.recognition_date
+                    || date
+                        .zip(last_seen[index])
+                        .is_some_and(|(date, last)| date >= last && date - last <= window)
+            });
+        match duplicated {
+            Some(index) => {
+                let original = &mut kept[index];
+                for (field, value) in [
+                    (&mut original.album, &record.album),
+                    (&mut original.release_year, &record.release_year),
+                    (&mut original.genre, &record.genre),
+                ] {
+                    if field.as_deref().is_none_or(str::is_empty) {
+                        field.clone_from(value);
+                    }
+                }
+                if date.is_some() {
+                    last_seen[index] = date;
+                }
+                duplicates.push(DuplicateEntry {
+                    removed: record,
+                    kept: kept[index].clone(),
+                });
+            }
+            None => {
+                kept.push(record);
+                last_seen.push(date);
+            }
+        }
+    }
+    (kept, duplicates)
+}
+
+impl SongHistoryRecord {
+    /// Whether the song was recognized from this kind of source
+    /// ("microphone", "monitor" or "file")
+    pub fn has_source_kind(&self, kind: &str) -> bool {
+        self.source
+            .as_deref()
+            .and_then(|source| source.split_once(':'))
+            .is_some_and(|(source_kind, _)| source_kind == kind)
+    }
+}
+
+#[cfg(test)]
+impl SongHistoryRecord {
+    /// A recognition of this song at this date, the rest being unknown
+    pub fn for_test(song_name: &str, recognition_date: &str) -> Self {
+        SongHistoryRecord {
+            song_name: song_name.to_string(),
+            album: None,
+            track_key: None,
+            release_year: None,
+            genre: None,
+            recognition_date: recognition_date.to_string(),
+            source: None,
+        }
+    }
+}
+
+/// A period of the history, given as "DATE" or "START..END", where dates are
+/// "YYYY-MM-DD" or "YYYY-MM-DD HH:MM" (local time). A date without a time
+/// covers the whole day, and either bound of a range may be omitted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DateRange {
+    pub start: Option<chrono::NaiveDateTime>,
+    pub end: Option<chrono::NaiveDateTime>,
+}
+
+impl DateRange {
+    fn parse_bound(
+        text: &str,
+        is_end: bool,
+    ) -> Result<Option<chrono::NaiveDateTime>, chrono::ParseError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(None);
+        }
+        for format in ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S"] {
+            if let Ok(date) = chrono::NaiveDateTime::parse_from_str(text, format) {
+                return Ok(Some(date));
+            }
+        }
+        let day = chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d")?;
+        Ok(Some(if is_end {
+            day.and_hms_opt(23, 59, 59).unwrap()
+        } else {
+            day.and_hms_opt(0, 0, 0).unwrap()
+        }))
+    }
+
+    pub fn contains(&self, record: &SongHistoryRecord) -> bool {
+        match clock::parse_history_date(&record.recognition_date) {
+            Some(date) => {
+                self.start.is_none_or(|start| date >= start)
+                    && self.end.is_none_or(|end| date <= end)
+            }
+            None => false,
+        }
+    }
+}
+
+impl std::str::FromStr for DateRange {
+    type Err = chrono::ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match text.split_once("..") {
+            Some((start, end)) => DateRange {
+                start: Self::parse_bound(start, false)?,
+                end: Self::parse_bound(end, true)?,
+            },
+            None => DateRange {
+                start: Self::parse_bound(text, false)?,
+                end: Self::parse_bound(text, true)?,
+            },
+        })
+    }
+}
+
+#[test]
+fn test_date_range() {
+    let range: DateRange = "2024-08-17".parse().unwrap();
+    let record = |date: &str| SongHistoryRecord::for_test("Artist - Title", date);
+    assert!(range.contains(&record("Sat Aug 17 22:44:43 2024")));
+    assert!(!range.contains(&record("Sun Aug 18 00:00:01 2024")));
+
+    let range: DateRange = "2024-08-17 20:00..".parse().unwrap();
+    assert!(range.contains(&record("Sun Aug 18 00:00:01 2024")));
+    assert!(!range.contains(&record("Sat Aug 17 19:59:00 2024")));
+}
+
+#[test]
+fn test_dedupe_history() {
+    let record = |song_name: &str, track_key: &str, date: &str| SongHistoryRecord {
+        track_key: Some(track_key.to_string()),
+        ..SongHistoryRecord::for_test(song_name, date)
+    };
+    let mut with_album = record(
+        "Daft Punk - Around the World",
+        "",
+        "Sat Aug 17 22:48:00 2024",
+    );
+    with_album.album = Some("Homework".to_string());
+    let records = vec![
+        record(
+            "Daft Punk - Around the World",
+            "123",
+            "Sat Aug 17 22:40:00 2024",
+        ),
+        record(
+            "Daft Punk - Around the World",
+            "123",
+            "Sat Aug 17 22:40:00 2024",
+        ),
+        record(
+            "daft punk -  Around The World",
+            "",
+            "Sat Aug 17 22:44:00 2024",
+        ),
+        with_album,
+        record("Justice - D.A.N.C.E.", "456", "Sat Aug 17 22:52:00 2024"),
+        record(
+            "Daft Punk - Around the World",
+            "123",
+            "Sat Aug 17 23:30:00 2024",
+        ),
+    ];
+    let (kept, duplicates) = dedupe_history(records, chrono::Duration::minutes(10));
+    assert_eq!(duplicates.len(), 3);
+    assert_eq!(kept.len(), 3);
+    assert_eq!(kept[0].album.as_deref(), Some("Homework"));
+    assert_eq!(kept[2].recognition_date, "Sat Aug 17 23:30:00 2024");
+}
+
+#[test]
+fn test_deleted_song_history_record() {
+    let record = SongHistoryRecord {
+        track_key: Some("123".to_string()),
+        ..SongHistoryRecord::for_test("Artist - Title", "Sat Aug 17 22:44:43 2024")
+    };
+    let deleted =
+        DeletedSongHistoryRecord::new(record.clone(), "Sun Aug 18 10:00:00 2024".to_string());
+    assert_eq!(deleted.to_record(), record);
+
+    let date = |text| chrono::NaiveDateTime::parse_from_str(text, "%c").unwrap();
+    assert!(!deleted.is_expired(date("Mon Sep 16 10:00:00 2024")));
+    assert!(deleted.is_expired(date("Wed Sep 18 10:00:00 2024")));
+}
+
+#[test]
+fn test_apply_retention() {
+    let record = |date: &str| SongHistoryRecord::for_test("Artist - Title", date);
+    let history = vec![
+        record("Mon Jan 15 20:00:00 2024"),
+        record("Sat Jun 15 20:00:00 2024"),
+        record("Sat Aug 17 22:44:43 2024"),
+    ];
+    let now = chrono::NaiveDateTime::parse_from_str("Sun Sep  1 12:00:00 2024", "%c").unwrap();
+
+    let mut records = history.clone();
+    assert_eq!(apply_retention(&mut records, 0, 6, now), 1);
+    assert_eq!(records, history[1..]);
+
+    let mut records = history.clone();
+    assert_eq!(apply_retention(&mut records, 1, 0, now), 2);
+    assert_eq!(records, history[2..]);
+
+    let mut records = history.clone();
+    assert_eq!(apply_retention(&mut records, 0, 0, now), 0);
+}
+
+#[test]
+fn test_parse_csv_columns() {
+    assert_eq!(
+        parse_csv_columns("artist, title,,isrc").unwrap(),
+        vec![CsvColumn::Artist, CsvColumn::Title, CsvColumn::Isrc]
+    );
+    assert!(parse_csv_columns(" , ").is_err());
+    assert!(parse_csv_columns("artist,lyrics").is_err());
+}
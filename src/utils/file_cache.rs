@@ -0,0 +1,43 @@
+//! Configuration files read at each recognition (the match corrections, the
+//! aliases...) are parsed again only when they change, i.e. when their
+//! path, size or modification time differ from the last time, rather than
+//! at every match.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// None when the file doesn't exist
+type FileVersion = Option<(u64, SystemTime)>;
+
+fn file_version(path: &Path) -> FileVersion {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+pub struct FileCache<T> {
+    entry: Mutex<Option<(PathBuf, FileVersion, Arc<T>)>>,
+}
+
+impl<T> FileCache<T> {
+    pub const fn new() -> Self {
+        FileCache {
+            entry: Mutex::new(None),
+        }
+    }
+
+    /// The contents parsed by `parse` when the file changed, or else the
+    /// ones parsed before
+    pub fn get(&self, path: &Path, parse: impl FnOnce(&Path) -> T) -> Arc<T> {
+        let version = file_version(path);
+        let mut entry = self.entry.lock().unwrap();
+        if let Some((ref cached_path, cached_version, ref value)) = *entry {
+            if cached_path == path && cached_version == version && version.is_some() {
+                return value.clone();
+            }
+        }
+        let value = Arc::new(parse(path));
+        *entry = Some((path.to_path_buf(), version, value.clone()));
+        value
+    }
+}
@@ -1,13 +1,16 @@
 use app_dirs::{get_app_root, AppDataType::*, AppInfo};
 use directories::ProjectDirs;
+use gettextrs::gettext;
+use log::warn;
 use std::error::Error;
 use std::fs::create_dir_all;
 #[cfg(not(windows))]
 use std::os::unix::fs::symlink;
 #[cfg(windows)]
 use std::os::windows::fs::symlink_dir;
-use std::path::PathBuf;
-use std::sync::LazyLock;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, OnceLock};
 
 const QUALIFIER: &str = "";
 const ORGANIZATION: &str = "SongRec";
@@ -16,24 +19,245 @@ const APPLICATION: &str = "SongRec";
 static PROJECT_DIRS: LazyLock<ProjectDirs> =
     LazyLock::new(|| ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION).unwrap());
 
+/// The named profile in use, if any. Each profile has its own history,
+/// favorites and preferences (including the audio device and sinks),
+/// stored in a "profiles/<name>" subdirectory.
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
+
+/// Select the profile for the rest of the execution. When none is given,
+/// the GUI uses the profile it selected last (`use_last_profile`), or the
+/// default profile if that one is no longer valid.
+pub fn set_profile(name: Option<String>, use_last_profile: bool) -> Result<(), Box<dyn Error>> {
+    let name = match name {
+        Some(name) => {
+            validate_profile_name(&name)?;
+            Some(name)
+        }
+        None if use_last_profile => obtain_last_profile().filter(|name| {
+            if let Err(error) = validate_profile_name(name) {
+                warn!(
+                    "Using the default profile rather than the last one, \"{}\": {}",
+                    name, error
+                );
+                return false;
+            }
+            true
+        }),
+        None => None,
+    };
+    PROFILE.set(name).ok();
+    Ok(())
+}
+
+/// Profile names are folder names; they are escaped in the application ID
+/// (see `single_instance`)
+fn validate_profile_name(name: &str) -> Result<(), Box<dyn Error>> {
+    if name.trim().is_empty()
+        || name == "."
+        || name == ".."
+        || name
+            .chars()
+            .any(|c| c.is_control() || c == '/' || c == '\\' || c == ':')
+    {
+        return Err(gettext(
+            "Profile names may not be empty, nor contain slashes, colons or control characters",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+pub fn create_profile(name: &str) -> Result<(), Box<dyn Error>> {
+    validate_profile_name(name)?;
+    create_dir_all(obtain_data_directory()?.join("profiles").join(name))?;
+    Ok(())
+}
+
+/// In incognito mode, recognized songs are displayed but neither written to
+/// the history nor forwarded to the sinks, and nothing is cached on the disk
+static INCOGNITO: AtomicBool = AtomicBool::new(false);
+
+pub fn set_incognito(incognito: bool) {
+    INCOGNITO.store(incognito, Ordering::SeqCst);
+}
+
+pub fn is_incognito() -> bool {
+    INCOGNITO.load(Ordering::SeqCst)
+}
+
+pub fn current_profile() -> Option<&'static str> {
+    PROFILE.get().and_then(|profile| profile.as_deref())
+}
+
+/// The existing named profiles
+pub fn list_profiles() -> Vec<String> {
+    let profiles_directory = PROJECT_DIRS.data_dir().join("profiles");
+    let mut profiles: Vec<String> = match std::fs::read_dir(profiles_directory) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect(),
+        Err(_) => vec![],
+    };
+    profiles.sort();
+    profiles
+}
+
+fn obtain_last_profile_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut path = obtain_preferences_directory()?;
+    path.push("last_profile");
+    Ok(path)
+}
+
+fn obtain_last_profile() -> Option<String> {
+    let contents = std::fs::read_to_string(obtain_last_profile_path().ok()?).ok()?;
+    Some(contents.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+/// Remember the profile to use when SongRec is started without `--profile`
+pub fn save_last_profile(name: Option<&str>) -> Result<(), Box<dyn Error>> {
+    std::fs::write(obtain_last_profile_path()?, name.unwrap_or_default())?;
+    Ok(())
+}
+
+fn with_profile(directory: PathBuf) -> Result<PathBuf, Box<dyn Error>> {
+    match current_profile() {
+        Some(profile) => {
+            let directory = directory.join("profiles").join(profile);
+            if !directory.exists() {
+                create_dir_all(&directory)?;
+            }
+            Ok(directory)
+        }
+        None => Ok(directory),
+    }
+}
+
+/// The history opened with `--read-only-history`, typically stored in the
+/// data folder of another computer on a network share. It is never written
+/// to, so that concurrent writes can't corrupt it.
+static READ_ONLY_HISTORY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Browse the history file at `path`, or the one of the data folder at
+/// `path`, rather than the history of this computer
+pub fn set_read_only_history(path: PathBuf) -> Result<(), Box<dyn Error>> {
+    let csv_path = if path.is_dir() {
+        path.join("song_history.csv")
+    } else {
+        path
+    };
+    if !csv_path.is_file() {
+        return Err(gettext("No song history found at %s")
+            .replace("%s", &csv_path.to_string_lossy())
+            .into());
+    }
+    READ_ONLY_HISTORY.set(csv_path).ok();
+    Ok(())
+}
+
+pub fn read_only_history() -> Option<&'static Path> {
+    READ_ONLY_HISTORY.get().map(PathBuf::as_path)
+}
+
 pub fn obtain_recognition_history_csv_path() -> Result<PathBuf, Box<dyn Error>> {
-    let mut csv_path = obtain_data_directory()?;
+    if let Some(csv_path) = read_only_history() {
+        return Ok(csv_path.to_path_buf());
+    }
+    let mut csv_path = with_profile(obtain_data_directory()?)?;
     csv_path.push("song_history.csv");
     Ok(csv_path)
 }
 
 pub fn obtain_favorites_csv_path() -> Result<PathBuf, Box<dyn Error>> {
-    let mut csv_path = obtain_data_directory()?;
+    if let Some(csv_path) = read_only_history() {
+        return Ok(csv_path.with_file_name("favorites.csv"));
+    }
+    let mut csv_path = with_profile(obtain_data_directory()?)?;
     csv_path.push("favorites.csv");
     Ok(csv_path)
 }
 
+pub fn obtain_match_corrections_csv_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut csv_path = with_profile(obtain_data_directory()?)?;
+    csv_path.push("match_corrections.csv");
+    Ok(csv_path)
+}
+
+/// See `no_match_log`
+pub fn obtain_no_matches_csv_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut csv_path = with_profile(obtain_data_directory()?)?;
+    csv_path.push("no_matches.csv");
+    Ok(csv_path)
+}
+
+/// See `recognition_locations`
+pub fn obtain_recognition_locations_csv_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut csv_path = with_profile(obtain_data_directory()?)?;
+    csv_path.push("recognition_locations.csv");
+    Ok(csv_path)
+}
+
+/// Held by the running instance, see `single_instance`
+pub fn obtain_instance_lock_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut lock_path = with_profile(obtain_data_directory()?)?;
+    lock_path.push("instance.lock");
+    Ok(lock_path)
+}
+
+pub fn obtain_fingerprint_index_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut index_path = obtain_data_directory()?;
+    index_path.push("fingerprint_index.bin");
+    Ok(index_path)
+}
+
+pub fn obtain_crash_reports_directory() -> Result<PathBuf, Box<dyn Error>> {
+    let mut directory = obtain_data_directory()?;
+    directory.push("crash_reports");
+    if !directory.exists() {
+        create_dir_all(&directory)?;
+    }
+    Ok(directory)
+}
+
+/// See `scan_progress`
+pub fn obtain_scans_directory() -> Result<PathBuf, Box<dyn Error>> {
+    let mut directory = with_profile(obtain_data_directory()?)?;
+    directory.push("scans");
+    if !directory.exists() {
+        create_dir_all(&directory)?;
+    }
+    Ok(directory)
+}
+
+pub fn obtain_unknown_clips_directory() -> Result<PathBuf, Box<dyn Error>> {
+    let mut directory = with_profile(obtain_data_directory()?)?;
+    directory.push("unknown_clips");
+    if !directory.exists() {
+        create_dir_all(&directory)?;
+    }
+    Ok(directory)
+}
+
 pub fn obtain_preferences_file_path() -> Result<PathBuf, Box<dyn Error>> {
-    let mut preferences_file_path = obtain_preferences_directory()?;
+    let mut preferences_file_path = with_profile(obtain_preferences_directory()?)?;
     preferences_file_path.push("preferences.toml");
     Ok(preferences_file_path)
 }
 
+/// See `aliases`
+pub fn obtain_aliases_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut aliases_path = with_profile(obtain_preferences_directory()?)?;
+    aliases_path.push("aliases.toml");
+    Ok(aliases_path)
+}
+
+pub fn obtain_sinks_config_path() -> Result<PathBuf, Box<dyn Error>> {
+    let mut sinks_config_path = with_profile(obtain_preferences_directory()?)?;
+    sinks_config_path.push("sinks.toml");
+    Ok(sinks_config_path)
+}
+
 fn obtain_data_directory() -> Result<PathBuf, Box<dyn Error>> {
     let data_dir = PROJECT_DIRS.data_dir();
     if !data_dir.exists() {
@@ -81,6 +305,22 @@ pub fn clear_cache() {
     }
 }
 
+/// Replace a file without leaving it truncated if SongRec stops midway,
+/// through a temporary file of the same directory (hence of the same file
+/// system)
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    use std::io::Write;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(directory)?;
+    file.write_all(contents)?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    Ok(())
+}
+
 // Backwards compatibility
 fn get_old_data_dir_path() -> Result<PathBuf, Box<dyn Error>> {
     let app_info = AppInfo {
@@ -0,0 +1,123 @@
+//! The names of the genres shown in the GUI and written to the exports.
+//! Shazam gives the genre of a track as an English name, which the history
+//! keeps as is, and writes the same genre in several ways depending on the
+//! catalog the track comes from ("Hip-Hop/Rap", "Hip Hop", "R&B/Soul",
+//! "RnB"...). The names are matched to the known genres once their case,
+//! spaces and punctuation are ignored, and the known genres are translated
+//! in the GUI, the exports keeping their English name; the other names,
+//! including the subgenres ("Hard Rock", "Indie Rock", "Dancehall"...), are
+//! shown as received.
+
+use gettextrs::gettext;
+
+/// Only the letters and digits, in lower case
+fn genre_key(genre: &str) -> String {
+    genre
+        .chars()
+        .filter(|character| character.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The English name of a known genre, for any of the names Shazam uses
+fn canonical_genre(genre: &str) -> Option<&'static str> {
+    Some(match genre_key(genre).as_str() {
+        "alternative" | "alternativerock" => "Alternative",
+        "anime" => "Anime",
+        "afrobeats" | "afrobeat" | "afropop" => "Afrobeats",
+        "blues" => "Blues",
+        "brazilian" | "mpb" => "Brazilian",
+        "childrensmusic" | "children" | "kids" => "Children's Music",
+        "christiangospel" | "christian" | "gospel" | "christianandgospel" => "Christian & Gospel",
+        "classical" => "Classical",
+        "country" => "Country",
+        "dance" | "dancepop" => "Dance",
+        "electronic" | "electronica" | "electro" | "edm" => "Electronic",
+        "folk" => "Folk",
+        "hiphoprap" | "hiphop" | "rap" => "Hip-Hop/Rap",
+        "house" => "House",
+        "jpop" => "J-Pop",
+        "jazz" => "Jazz",
+        "kpop" => "K-Pop",
+        "latin" | "latino" | "latinurban" | "urbanolatino" => "Latin",
+        "metal" | "heavymetal" => "Metal",
+        "pop" => "Pop",
+        "punk" => "Punk",
+        "rbsoul" | "rnbsoul" | "rb" | "rnb" | "soul" => "R&B/Soul",
+        "reggae" => "Reggae",
+        "reggaeton" => "Reggaeton",
+        "rock" => "Rock",
+        "singersongwriter" => "Singer/Songwriter",
+        "soundtrack" | "soundtracks" | "filmmusic" => "Soundtrack",
+        "techno" => "Techno",
+        "world" | "worldmusic" => "World",
+        _ => return None,
+    })
+}
+
+/// Written out for each genre so that xgettext extracts them
+fn translate_genre(genre: &'static str) -> String {
+    match genre {
+        "Alternative" => gettext("Alternative"),
+        "Anime" => gettext("Anime"),
+        "Afrobeats" => gettext("Afrobeats"),
+        "Blues" => gettext("Blues"),
+        "Brazilian" => gettext("Brazilian"),
+        "Children's Music" => gettext("Children's Music"),
+        "Christian & Gospel" => gettext("Christian & Gospel"),
+        "Classical" => gettext("Classical"),
+        "Country" => gettext("Country"),
+        "Dance" => gettext("Dance"),
+        "Electronic" => gettext("Electronic"),
+        "Folk" => gettext("Folk"),
+        "Hip-Hop/Rap" => gettext("Hip-Hop/Rap"),
+        "House" => gettext("House"),
+        "J-Pop" => gettext("J-Pop"),
+        "Jazz" => gettext("Jazz"),
+        "K-Pop" => gettext("K-Pop"),
+        "Latin" => gettext("Latin"),
+        "Metal" => gettext("Metal"),
+        "Pop" => gettext("Pop"),
+        "Punk" => gettext("Punk"),
+        "R&B/Soul" => gettext("R&B/Soul"),
+        "Reggae" => gettext("Reggae"),
+        "Reggaeton" => gettext("Reggaeton"),
+        "Rock" => gettext("Rock"),
+        "Singer/Songwriter" => gettext("Singer/Songwriter"),
+        "Soundtrack" => gettext("Soundtrack"),
+        "Techno" => gettext("Techno"),
+        "World" => gettext("World"),
+        _ => genre.to_string(),
+    }
+}
+
+/// The English name of a genre, the same for all the ways Shazam writes
+/// it, for grouping and filtering the songs regardless of the language of
+/// the interface
+pub fn genre_name(genre: &str) -> String {
+    let genre = genre.trim();
+    canonical_genre(genre)
+        .map(str::to_string)
+        .unwrap_or_else(|| genre.to_string())
+}
+
+/// The name of a genre to show in the GUI, in the language of the
+/// interface when it is a known genre. The exports keep `genre_name`.
+pub fn display_genre(genre: &str) -> String {
+    let genre = genre.trim();
+    canonical_genre(genre)
+        .map(translate_genre)
+        .unwrap_or_else(|| genre.to_string())
+}
+
+#[test]
+fn test_display_genre() {
+    assert_eq!(display_genre("Hip-Hop/Rap"), "Hip-Hop/Rap");
+    assert_eq!(display_genre("hip hop"), "Hip-Hop/Rap");
+    assert_eq!(display_genre("R&B/Soul"), display_genre("RnB"));
+    assert_eq!(display_genre("ELECTRONICA"), "Electronic");
+    assert_eq!(display_genre(" Bossa Nova "), "Bossa Nova");
+    assert_eq!(display_genre("Hard Rock"), "Hard Rock");
+    assert_eq!(genre_name("hip hop"), "Hip-Hop/Rap");
+    assert_eq!(display_genre(""), "");
+}
@@ -0,0 +1,143 @@
+//! A portable ZIP archive of a slice of the history, for sharing or keeping
+//! the soundtrack of a particular event: the songs in the history CSV
+//! format, their cached cover art, the clips kept during the period that
+//! weren't recognized, and an HTML page listing them.
+
+use gettextrs::gettext;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::core::metadata_cache;
+use crate::core::unknown_clips::UnknownClip;
+use crate::utils::csv_song_history::{CsvColumn, SongHistoryRecord};
+use crate::utils::history_export::escape_xml;
+use crate::utils::song_link::is_shazam_track_key;
+
+pub struct BundleSummary {
+    pub songs: usize,
+    pub covers: usize,
+    pub clips: usize,
+}
+
+/// The path of a clip within the archive
+fn clip_path(clip: &UnknownClip) -> String {
+    format!(
+        "clips/{}",
+        clip.audio_path.file_name().unwrap().to_string_lossy()
+    )
+}
+
+fn render_index(
+    title: &str,
+    records: &[SongHistoryRecord],
+    covers: &[Option<String>],
+    clips: &[UnknownClip],
+) -> String {
+    let mut rows = String::new();
+    for (record, cover) in records.iter().zip(covers) {
+        let cover = match cover {
+            Some(path) => format!("<img src=\"{}\" alt=\"\">", escape_xml(path)),
+            None => String::new(),
+        };
+        let link = match &record.track_key {
+            Some(track_key) if is_shazam_track_key(track_key) => {
+                format!(
+                    " <a href=\"https://www.shazam.com/track/{}\">Shazam</a>",
+                    escape_xml(track_key)
+                )
+            }
+            _ => String::new(),
+        };
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td><b>{}</b><br>{}</td><td>{}</td><td>{}{}</td></tr>\n",
+            escape_xml(&CsvColumn::Timestamp.value(record, None)),
+            cover,
+            escape_xml(&CsvColumn::Title.value(record, None)),
+            escape_xml(&CsvColumn::Artist.value(record, None)),
+            escape_xml(record.album.as_deref().unwrap_or_default()),
+            escape_xml(record.release_year.as_deref().unwrap_or_default()),
+            link
+        ));
+    }
+
+    let mut clip_rows = String::new();
+    if !clips.is_empty() {
+        clip_rows.push_str(&format!(
+            "<h2>{}</h2>\n<table>\n",
+            escape_xml(&gettext("Unrecognized clips"))
+        ));
+        for clip in clips {
+            clip_rows.push_str(&format!(
+                "<tr><td>{}</td><td><audio controls preload=\"none\" src=\"{}\"></audio></td></tr>\n",
+                escape_xml(&clip.recognition_date),
+                escape_xml(&clip_path(clip))
+            ));
+        }
+        clip_rows.push_str("</table>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+<style>body {{ font-family: sans-serif; }} td {{ padding: 4px 8px; }} img {{ width: 64px; }}</style>\n\
+</head>\n<body>\n<h1>{title}</h1>\n<table>\n{rows}</table>\n{clip_rows}</body>\n</html>\n",
+        title = escape_xml(title),
+        rows = rows,
+        clip_rows = clip_rows
+    )
+}
+
+pub fn write_bundle(
+    records: &[SongHistoryRecord],
+    clips: &[UnknownClip],
+    title: &str,
+    output: &Path,
+) -> Result<BundleSummary, Box<dyn Error>> {
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let compressed = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    // Images are already compressed
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    let mut covers: Vec<Option<String>> = vec![];
+    let mut written_covers: Vec<String> = vec![];
+    for record in records {
+        let cover = record.track_key.as_deref().and_then(|track_key| {
+            let path = format!("artwork/{}.jpg", track_key);
+            if written_covers.contains(&path) {
+                return Some(path);
+            }
+            let contents = metadata_cache::get_cover(track_key)?;
+            zip.start_file(path.as_str(), stored).ok()?;
+            zip.write_all(&contents).ok()?;
+            written_covers.push(path.clone());
+            Some(path)
+        });
+        covers.push(cover);
+    }
+
+    for clip in clips {
+        zip.start_file(clip_path(clip).as_str(), compressed)?;
+        zip.write_all(&std::fs::read(&clip.audio_path)?)?;
+    }
+
+    zip.start_file("history.csv", compressed)?;
+    let mut csv_writer = csv::Writer::from_writer(vec![]);
+    for record in records {
+        csv_writer.serialize(record)?;
+    }
+    zip.write_all(&csv_writer.into_inner()?)?;
+
+    zip.start_file("index.html", compressed)?;
+    zip.write_all(render_index(title, records, &covers, clips).as_bytes())?;
+
+    zip.finish()?;
+
+    Ok(BundleSummary {
+        songs: records.len(),
+        covers: written_covers.len(),
+        clips: clips.len(),
+    })
+}
@@ -0,0 +1,259 @@
+//! Validation of the song history and favorites files, which users edit by
+//! hand or in spreadsheet applications: rows that the GUI would fail to load
+//! are either repaired (encoding, date format) or set aside in a separate
+//! file, so that the rest of the history remains readable.
+
+use gettextrs::gettext;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::utils::clock;
+use crate::utils::csv_song_history::SongHistoryRecord;
+use crate::utils::history_encryption::{read_history_file, write_history_file};
+
+const HISTORY_COLUMNS: &[&str] = &[
+    "song_name",
+    "album",
+    "track_key",
+    "release_year",
+    "genre",
+    "recognition_date",
+    "source",
+];
+
+/// Date formats that spreadsheet applications commonly save dates in
+const REPAIRABLE_DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%d/%m/%Y %H:%M:%S",
+    "%d/%m/%Y %H:%M",
+];
+
+pub struct HistoryProblem {
+    pub line: u64,
+    pub description: String,
+    /// Whether the row is kept once repaired, or set aside
+    pub repaired: bool,
+}
+
+pub struct HistoryCheck {
+    pub problems: Vec<HistoryProblem>,
+    /// The rows of the file, repaired when possible
+    pub records: Vec<SongHistoryRecord>,
+    /// The rows which could not be repaired, as CSV fields
+    pub rejected: Vec<Vec<String>>,
+    pub header: Vec<String>,
+}
+
+/// Use Latin-1 for the fields that aren't UTF-8, as saved by older
+/// spreadsheet applications
+fn decode_field(field: &[u8]) -> (String, bool) {
+    match std::str::from_utf8(field) {
+        Ok(text) => (text.to_string(), true),
+        Err(_) => (field.iter().map(|&byte| byte as char).collect(), false),
+    }
+}
+
+fn problem(line: u64, description: String, repaired: bool) -> HistoryProblem {
+    HistoryProblem {
+        line,
+        description,
+        repaired,
+    }
+}
+
+fn repair_date(date: &str) -> Option<String> {
+    let date = date.trim();
+    if let Ok(parsed) = chrono::DateTime::parse_from_rfc3339(date) {
+        return Some(clock::format_history_date(&parsed));
+    }
+    REPAIRABLE_DATE_FORMATS.iter().find_map(|format| {
+        chrono::NaiveDateTime::parse_from_str(date, format)
+            .ok()
+            .map(clock::format_wall_clock_history_date)
+    })
+}
+
+pub fn check_history(contents: &[u8]) -> Result<HistoryCheck, Box<dyn std::error::Error>> {
+    let mut check = HistoryCheck {
+        problems: vec![],
+        records: vec![],
+        rejected: vec![],
+        header: vec![],
+    };
+
+    // Byte order mark added by spreadsheet applications
+    let contents = match contents.strip_prefix(b"\xef\xbb\xbf") {
+        Some(contents) => {
+            check.problems.push(problem(
+                1,
+                gettext("Byte order mark before the header"),
+                true,
+            ));
+            contents
+        }
+        None => contents,
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(contents);
+    let mut rows = reader.byte_records().peekable();
+
+    let first_row = match rows.peek() {
+        Some(Ok(row)) => row.iter().map(|field| decode_field(field).0).collect(),
+        Some(Err(_)) => vec![],
+        None => return Ok(check),
+    };
+    let header: Vec<String> = if first_row
+        .iter()
+        .all(|name: &String| HISTORY_COLUMNS.contains(&name.as_str()))
+        && first_row.iter().any(|name| name == "song_name")
+        && first_row.iter().any(|name| name == "recognition_date")
+    {
+        rows.next();
+        first_row
+    } else {
+        check
+            .problems
+            .push(problem(1, gettext("Missing or invalid header"), true));
+        HISTORY_COLUMNS
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+    };
+
+    for row in rows {
+        let row = row?;
+        let line = row.position().map(|position| position.line()).unwrap_or(0);
+        let mut fields = vec![];
+        let mut valid_encoding = true;
+        for field in row.iter() {
+            let (text, valid) = decode_field(field);
+            valid_encoding &= valid;
+            fields.push(text);
+        }
+        if !valid_encoding {
+            check
+                .problems
+                .push(problem(line, gettext("Invalid UTF-8 text"), true));
+        }
+
+        if fields.len() > header.len() {
+            check.problems.push(problem(
+                line,
+                gettext("%d columns instead of %d")
+                    .replacen("%d", &fields.len().to_string(), 1)
+                    .replacen("%d", &header.len().to_string(), 1),
+                false,
+            ));
+            check.rejected.push(fields);
+            continue;
+        }
+        let field = |name: &str| {
+            header
+                .iter()
+                .position(|column| column == name)
+                .and_then(|index| fields.get(index))
+                .filter(|value| !value.is_empty())
+                .cloned()
+        };
+
+        let Some(song_name) = field("song_name") else {
+            check
+                .problems
+                .push(problem(line, gettext("Missing song name"), false));
+            check.rejected.push(fields);
+            continue;
+        };
+        let date = field("recognition_date").unwrap_or_default();
+        let recognition_date = if clock::parse_history_date(&date).is_some() {
+            date
+        } else if let Some(repaired) = repair_date(&date) {
+            check.problems.push(problem(
+                line,
+                gettext("Unexpected date format: %s").replace("%s", &date),
+                true,
+            ));
+            repaired
+        } else {
+            check.problems.push(problem(
+                line,
+                gettext("Invalid date: %s").replace("%s", &date),
+                false,
+            ));
+            check.rejected.push(fields);
+            continue;
+        };
+
+        check.records.push(SongHistoryRecord {
+            song_name,
+            album: field("album"),
+            track_key: field("track_key"),
+            release_year: field("release_year"),
+            genre: field("genre"),
+            recognition_date,
+            source: field("source"),
+        });
+    }
+    check.header = header;
+    Ok(check)
+}
+
+/// Append rows that can't be read to the ".rejected" file next to a history
+/// file, starting it with the header of the history, and return its path
+pub fn set_aside_rows(
+    csv_path: &Path,
+    header: &[String],
+    rows: &[Vec<String>],
+) -> Result<PathBuf, Box<dyn Error>> {
+    let mut rejected_path = csv_path.as_os_str().to_owned();
+    rejected_path.push(".rejected");
+    let rejected_path = PathBuf::from(rejected_path);
+    let previous = if rejected_path.exists() {
+        read_history_file(&rejected_path)?
+    } else {
+        vec![]
+    };
+    let is_new = previous.is_empty();
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .from_writer(previous);
+    if is_new {
+        writer.write_record(header)?;
+    }
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    write_history_file(&rejected_path, &writer.into_inner()?)?;
+    Ok(rejected_path)
+}
+
+#[test]
+fn test_check_history() {
+    let contents = b"\xef\xbb\xbfsong_name,album,track_key,release_year,genre,recognition_date\n\
+        Daft Punk - Around the World,Homework,123,1997,House,Sat Aug 17 22:44:43 2024\n\
+        Caf\xe9 Tacvba - Eres,,456,,,Sat Aug 17 22:50:00 2024\n\
+        Justice - D.A.N.C.E.,,789,,,2024-08-17 23:00:00\n\
+        Too,many,columns,in,this,row,here,really\n\
+        Justice - Genesis,,,,,yesterday\n";
+    let check = check_history(contents).unwrap();
+    assert_eq!(check.records.len(), 3);
+    assert_eq!(check.records[1].song_name, "Café Tacvba - Eres");
+    assert_eq!(
+        check.records[2].recognition_date,
+        "Sat Aug 17 23:00:00 2024"
+    );
+    assert_eq!(check.rejected.len(), 2);
+    assert_eq!(check.problems.len(), 5);
+    assert_eq!(
+        check
+            .problems
+            .iter()
+            .filter(|problem| problem.repaired)
+            .count(),
+        3
+    );
+}
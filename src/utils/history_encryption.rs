@@ -0,0 +1,178 @@
+//! Optional at-rest encryption of the song history, favorites and deleted
+//! songs files, for shared computers. The files are encrypted with
+//! XChaCha20-Poly1305, using a random key stored in the keyring of the
+//! session (Secret Service, macOS Keychain or Windows Credential Manager),
+//! when SongRec is built with the "encryption" feature.
+//!
+//! Encrypted files start with `MAGIC`, followed by the nonce and the
+//! encrypted CSV contents. Files are read whether they are encrypted or
+//! not, so that turning encryption on or off takes effect on the next save.
+
+use gettextrs::gettext;
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+#[cfg(feature = "encryption")]
+use std::sync::Mutex;
+
+use crate::core::preferences::PreferencesInterface;
+use crate::utils::filesystem_operations::{read_only_history, write_atomically};
+
+const MAGIC: &[u8] = b"SONGREC-ENCRYPTED-1\n";
+
+#[cfg(feature = "encryption")]
+const NONCE_SIZE: usize = 24;
+
+#[cfg(feature = "encryption")]
+const KEYRING_SERVICE: &str = "SongRec";
+#[cfg(feature = "encryption")]
+const KEYRING_USER: &str = "history-key";
+
+/// Read from the preferences when the history is first read or written,
+/// rather than at the start of the commands that don't touch it
+static ENCRYPT_HISTORY: LazyLock<AtomicBool> = LazyLock::new(|| {
+    AtomicBool::new(
+        cfg!(feature = "encryption")
+            && PreferencesInterface::new().preferences.encrypt_history == Some(true),
+    )
+});
+
+/// The key, once read from the keyring, so that the keyring isn't queried
+/// on every read and write
+#[cfg(feature = "encryption")]
+static HISTORY_KEY: Mutex<Option<chacha20poly1305::Key>> = Mutex::new(None);
+
+pub fn set_history_encryption(enabled: bool) {
+    ENCRYPT_HISTORY.store(enabled && cfg!(feature = "encryption"), Ordering::SeqCst);
+}
+
+pub fn is_history_encryption_enabled() -> bool {
+    ENCRYPT_HISTORY.load(Ordering::SeqCst)
+}
+
+/// A file whose contents can't be decrypted, as opposed to a file or a
+/// keyring that can't be accessed for now
+#[derive(Debug)]
+pub struct UndecryptableHistory(String);
+
+impl std::fmt::Display for UndecryptableHistory {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+impl Error for UndecryptableHistory {}
+
+pub fn is_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(MAGIC)
+}
+
+#[cfg(feature = "encryption")]
+fn history_cipher() -> Result<chacha20poly1305::XChaCha20Poly1305, Box<dyn Error>> {
+    use base64::Engine;
+    use chacha20poly1305::aead::{KeyInit, OsRng};
+    use chacha20poly1305::{Key, XChaCha20Poly1305};
+
+    let mut cached_key = HISTORY_KEY.lock().unwrap();
+    if let Some(key) = *cached_key {
+        return Ok(XChaCha20Poly1305::new(&key));
+    }
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?;
+    let key = match entry.get_password() {
+        Ok(encoded) => {
+            let key = base64::prelude::BASE64_STANDARD.decode(encoded)?;
+            if key.len() != 32 {
+                return Err(gettext("Invalid history key in the keyring").into());
+            }
+            *Key::from_slice(&key)
+        }
+        Err(keyring::Error::NoEntry) => {
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            entry.set_password(&base64::prelude::BASE64_STANDARD.encode(key))?;
+            key
+        }
+        Err(error) => return Err(error.into()),
+    };
+    *cached_key = Some(key);
+    Ok(XChaCha20Poly1305::new(&key))
+}
+
+/// Check that the key can be read from the keyring, or created in it,
+/// before turning encryption on
+pub fn check_history_key() -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "encryption")]
+    history_cipher()?;
+    Ok(())
+}
+
+#[cfg(feature = "encryption")]
+fn encrypt(contents: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+    use chacha20poly1305::XChaCha20Poly1305;
+
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = history_cipher()?
+        .encrypt(&nonce, contents)
+        .map_err(|_| gettext("Could not encrypt the history"))?;
+    let mut encrypted = MAGIC.to_vec();
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt(contents: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    use chacha20poly1305::aead::Aead;
+    use chacha20poly1305::XNonce;
+
+    let contents = &contents[MAGIC.len()..];
+    if contents.len() < NONCE_SIZE {
+        return Err(UndecryptableHistory(gettext("The encrypted history is truncated")).into());
+    }
+    let (nonce, ciphertext) = contents.split_at(NONCE_SIZE);
+    Ok(history_cipher()?
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            UndecryptableHistory(gettext(
+                "Could not decrypt the history, its key is missing from the keyring or wrong",
+            ))
+        })?)
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(_contents: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err(UndecryptableHistory(gettext(
+        "The history is encrypted, but SongRec was built without the \"encryption\" feature",
+    ))
+    .into())
+}
+
+/// Read a history file, decrypting it if needed
+pub fn read_history_file(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let contents = std::fs::read(path)?;
+    if is_encrypted(&contents) {
+        decrypt(&contents)
+    } else {
+        Ok(contents)
+    }
+}
+
+/// Write a history file, encrypting it when history encryption is enabled.
+/// Nothing is written while browsing a read-only history.
+pub fn write_history_file(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    if read_only_history().is_some() {
+        return Err(gettext("The history is opened read-only").into());
+    }
+    #[cfg(feature = "encryption")]
+    if is_history_encryption_enabled() {
+        return write_atomically(path, &encrypt(contents)?);
+    }
+    write_atomically(path, contents)
+}
+
+#[test]
+fn test_history_file_detection() {
+    assert!(is_encrypted(b"SONGREC-ENCRYPTED-1\n\x00\x01"));
+    assert!(!is_encrypted(b"song_name,album,track_key"));
+}
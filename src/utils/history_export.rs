@@ -0,0 +1,582 @@
+//! Exporting the song history to other formats than its own CSV file. TSV
+//! and XLSX files open correctly in spreadsheet applications whatever the
+//! locale, unlike comma-separated files which are expected to use
+//! semicolons in some countries. The iTunes library XML format can be
+//! imported into Apple Music (File > Library > Import Playlist), the songs
+//! recognized from the local index linking to their files.
+//!
+//! M3U and XSPF playlists link to the pages of the songs on Shazam, since
+//! the recognized songs have no local file; the songs which Shazam doesn't
+//! know (from the local index, radio metadata or Cast devices) are left out
+//! of M3U playlists, which require a location. HTML exports are a
+//! standalone table, for sharing or printing.
+//!
+//! GPX files map where the songs were heard: each song with coordinates
+//! (see `recognition_locations`) is a waypoint, named after the song and
+//! described with its venue, the songs without coordinates being left out.
+
+use gettextrs::gettext;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::core::recognition_locations::{locations_by_entry, RecognitionLocation};
+use crate::utils::clock;
+use crate::utils::csv_song_history::{CsvColumn, DateRange, SongHistoryRecord};
+use crate::utils::genres::genre_name;
+use crate::utils::song_link::song_link;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Tsv,
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+    ItunesXml,
+    Json,
+    M3u,
+    Xspf,
+    Html,
+    Gpx,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "tsv" => Ok(ExportFormat::Tsv),
+            #[cfg(feature = "xlsx")]
+            "xlsx" => Ok(ExportFormat::Xlsx),
+            #[cfg(not(feature = "xlsx"))]
+            "xlsx" => Err(gettext("SongRec was compiled without XLSX support")),
+            "itunes" | "xml" => Ok(ExportFormat::ItunesXml),
+            "json" => Ok(ExportFormat::Json),
+            "m3u" | "m3u8" => Ok(ExportFormat::M3u),
+            "xspf" => Ok(ExportFormat::Xspf),
+            "html" | "htm" => Ok(ExportFormat::Html),
+            "gpx" => Ok(ExportFormat::Gpx),
+            _ => Err(gettext("Unknown export format: %s").replace("%s", name)),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// Guess the format from the extension of the output file
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Tsv => "tsv",
+            #[cfg(feature = "xlsx")]
+            ExportFormat::Xlsx => "xlsx",
+            ExportFormat::ItunesXml => "xml",
+            ExportFormat::Json => "json",
+            ExportFormat::M3u => "m3u8",
+            ExportFormat::Xspf => "xspf",
+            ExportFormat::Html => "html",
+            ExportFormat::Gpx => "gpx",
+        }
+    }
+}
+
+/// The entries of the history to export
+#[derive(Debug, Default, Clone)]
+pub struct ExportFilter {
+    pub period: Option<DateRange>,
+    /// Only keep the songs recognized from this kind of source (see
+    /// `SongHistoryRecord::has_source_kind`)
+    pub source_kind: Option<String>,
+    /// Text which the genre, the artist or the album must contain,
+    /// regardless of its case
+    pub genre: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+fn contains_text(value: &str, text: &Option<String>) -> bool {
+    text.as_deref()
+        .is_none_or(|text| value.to_lowercase().contains(&text.trim().to_lowercase()))
+}
+
+impl ExportFilter {
+    /// The genre as received or its English name, rather than the one
+    /// translated for the interface
+    fn matches_genre(&self, record: &SongHistoryRecord) -> bool {
+        let genre = record.genre.as_deref().unwrap_or_default();
+        contains_text(genre, &self.genre)
+            || (!genre.is_empty() && contains_text(&genre_name(genre), &self.genre))
+    }
+
+    pub fn matches(&self, record: &SongHistoryRecord) -> bool {
+        self.period.is_none_or(|period| period.contains(record))
+            && self
+                .source_kind
+                .as_deref()
+                .is_none_or(|kind| record.has_source_kind(kind))
+            && self.matches_genre(record)
+            && contains_text(&CsvColumn::Artist.value(record, None), &self.artist)
+            && contains_text(&CsvColumn::Album.value(record, None), &self.album)
+    }
+}
+
+fn export_delimited(
+    records: &[SongHistoryRecord],
+    columns: &[CsvColumn],
+    header: bool,
+    delimiter: u8,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    if header {
+        writer.write_record(columns.iter().map(CsvColumn::name))?;
+    }
+    for record in records {
+        writer.write_record(columns.iter().map(|column| column.value(record, None)))?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(feature = "xlsx")]
+fn export_xlsx(
+    records: &[SongHistoryRecord],
+    columns: &[CsvColumn],
+    header: bool,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut workbook = rust_xlsxwriter::Workbook::new();
+    let worksheet = workbook.add_worksheet();
+
+    let mut row: u32 = 0;
+    if header {
+        let bold = rust_xlsxwriter::Format::new().set_bold();
+        for (position, column) in columns.iter().enumerate() {
+            worksheet.write_string_with_format(row, position as u16, column.name(), &bold)?;
+        }
+        row += 1;
+    }
+    for record in records {
+        for (position, column) in columns.iter().enumerate() {
+            worksheet.write_string(row, position as u16, column.value(record, None))?;
+        }
+        row += 1;
+    }
+    worksheet.autofit();
+
+    Ok(workbook.save_to_buffer()?)
+}
+
+/// Also used for the HTML pages. Control characters, which XML doesn't
+/// allow even escaped, are removed.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(character),
+            _ if character.is_control() => {}
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// The "file://" URL of the songs recognized from the local index (see
+/// `providers`), whose track key is the path of their file
+fn local_file_url(record: &SongHistoryRecord) -> Option<String> {
+    let path = record.track_key.as_deref()?.strip_prefix("local:")?;
+    glib::filename_to_uri(path, None)
+        .ok()
+        .map(|url| url.to_string())
+}
+
+fn write_plist_string(output: &mut String, key: &str, value: &str) {
+    writeln!(
+        output,
+        "\t\t\t<key>{}</key><string>{}</string>",
+        key,
+        escape_xml(value)
+    )
+    .unwrap();
+}
+
+/// A playlist in the format of the "iTunes Music Library.xml" file, where
+/// each song of the history is a track. The column selection does not apply.
+fn export_itunes_xml(records: &[SongHistoryRecord], playlist_name: &str) -> Vec<u8> {
+    let mut output = String::new();
+    output.push_str(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE plist PUBLIC \"-//Apple Computer//DTD PLIST 1.0//EN\" ",
+        "\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+        "<plist version=\"1.0\">\n<dict>\n",
+        "\t<key>Major Version</key><integer>1</integer>\n",
+        "\t<key>Minor Version</key><integer>1</integer>\n",
+    ));
+    writeln!(
+        output,
+        "\t<key>Application Version</key><string>SongRec {}</string>",
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+
+    output.push_str("\t<key>Tracks</key>\n\t<dict>\n");
+    for (position, record) in records.iter().enumerate() {
+        let track_id = position + 1;
+        writeln!(output, "\t\t<key>{}</key>\n\t\t<dict>", track_id).unwrap();
+        writeln!(
+            output,
+            "\t\t\t<key>Track ID</key><integer>{}</integer>",
+            track_id
+        )
+        .unwrap();
+        write_plist_string(&mut output, "Name", &CsvColumn::Title.value(record, None));
+        write_plist_string(
+            &mut output,
+            "Artist",
+            &CsvColumn::Artist.value(record, None),
+        );
+        if let Some(album) = record.album.as_deref().filter(|album| !album.is_empty()) {
+            write_plist_string(&mut output, "Album", album);
+        }
+        if let Some(genre) = record.genre.as_deref().filter(|genre| !genre.is_empty()) {
+            write_plist_string(&mut output, "Genre", &genre_name(genre));
+        }
+        if let Some(year) = record
+            .release_year
+            .as_deref()
+            .and_then(|year| year.trim().parse::<u32>().ok())
+        {
+            writeln!(output, "\t\t\t<key>Year</key><integer>{}</integer>", year).unwrap();
+        }
+        if let Some(url) = local_file_url(record) {
+            write_plist_string(&mut output, "Location", &url);
+        }
+        if let Some(date) = clock::parse_zoned_history_date(&record.recognition_date) {
+            writeln!(
+                output,
+                "\t\t\t<key>Date Added</key><date>{}</date>",
+                date.with_timezone(&chrono::Utc)
+                    .format("%Y-%m-%dT%H:%M:%SZ")
+            )
+            .unwrap();
+        }
+        output.push_str("\t\t</dict>\n");
+    }
+    output.push_str("\t</dict>\n");
+
+    output.push_str("\t<key>Playlists</key>\n\t<array>\n\t\t<dict>\n");
+    write_plist_string(&mut output, "Name", playlist_name);
+    output.push_str("\t\t\t<key>Playlist Items</key>\n\t\t\t<array>\n");
+    for track_id in 1..=records.len() {
+        writeln!(
+            output,
+            "\t\t\t\t<dict><key>Track ID</key><integer>{}</integer></dict>",
+            track_id
+        )
+        .unwrap();
+    }
+    output.push_str("\t\t\t</array>\n\t\t</dict>\n\t</array>\n</dict>\n</plist>\n");
+
+    output.into_bytes()
+}
+
+fn export_json(records: &[SongHistoryRecord], columns: &[CsvColumn]) -> Vec<u8> {
+    let songs: Vec<serde_json::Value> = records
+        .iter()
+        .map(|record| {
+            columns
+                .iter()
+                .map(|column| {
+                    (
+                        column.name().to_string(),
+                        serde_json::Value::String(column.value(record, None)),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        })
+        .collect();
+    serde_json::to_vec_pretty(&songs).unwrap()
+}
+
+fn record_link(record: &SongHistoryRecord) -> Option<String> {
+    song_link(record.track_key.as_deref()?, "")
+}
+
+/// An extended M3U playlist, in UTF-8
+fn export_m3u(records: &[SongHistoryRecord]) -> Vec<u8> {
+    let mut output = String::from("#EXTM3U\n");
+    for record in records {
+        let Some(link) = record_link(record) else {
+            continue;
+        };
+        writeln!(
+            output,
+            "#EXTINF:-1,{}\n{}",
+            record.song_name.replace('\n', " "),
+            link
+        )
+        .unwrap();
+    }
+    output.into_bytes()
+}
+
+fn export_xspf(records: &[SongHistoryRecord], playlist_name: &str) -> Vec<u8> {
+    let mut output = String::new();
+    output.push_str(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    ));
+    writeln!(output, "\t<title>{}</title>", escape_xml(playlist_name)).unwrap();
+    output.push_str("\t<trackList>\n");
+    for record in records {
+        output.push_str("\t\t<track>\n");
+        writeln!(
+            output,
+            "\t\t\t<title>{}</title>",
+            escape_xml(&CsvColumn::Title.value(record, None))
+        )
+        .unwrap();
+        let artist = CsvColumn::Artist.value(record, None);
+        if !artist.is_empty() {
+            writeln!(output, "\t\t\t<creator>{}</creator>", escape_xml(&artist)).unwrap();
+        }
+        if let Some(album) = record.album.as_deref().filter(|album| !album.is_empty()) {
+            writeln!(output, "\t\t\t<album>{}</album>", escape_xml(album)).unwrap();
+        }
+        writeln!(
+            output,
+            "\t\t\t<annotation>{}</annotation>",
+            escape_xml(&record.recognition_date)
+        )
+        .unwrap();
+        if let Some(link) = record_link(record) {
+            writeln!(output, "\t\t\t<info>{}</info>", escape_xml(&link)).unwrap();
+        }
+        output.push_str("\t\t</track>\n");
+    }
+    output.push_str("\t</trackList>\n</playlist>\n");
+    output.into_bytes()
+}
+
+/// A standalone HTML page with a table of the columns, where the titles
+/// link to the songs
+fn export_html(
+    records: &[SongHistoryRecord],
+    columns: &[CsvColumn],
+    header: bool,
+    page_title: &str,
+) -> Vec<u8> {
+    let mut output = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(output, "<title>{}</title>", escape_xml(page_title)).unwrap();
+    output.push_str(concat!(
+        "<style>table { border-collapse: collapse; } ",
+        "th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }</style>\n",
+        "</head>\n<body>\n",
+    ));
+    writeln!(output, "<h1>{}</h1>\n<table>", escape_xml(page_title)).unwrap();
+    if header {
+        output.push_str("<tr>");
+        for column in columns {
+            write!(output, "<th>{}</th>", escape_xml(column.name())).unwrap();
+        }
+        output.push_str("</tr>\n");
+    }
+    for record in records {
+        output.push_str("<tr>");
+        for column in columns {
+            let value = escape_xml(&column.value(record, None));
+            match record_link(record) {
+                Some(link) if matches!(column, CsvColumn::SongName | CsvColumn::Title) => {
+                    write!(
+                        output,
+                        "<td><a href=\"{}\">{}</a></td>",
+                        escape_xml(&link),
+                        value
+                    )
+                }
+                _ => write!(output, "<td>{}</td>", value),
+            }
+            .unwrap();
+        }
+        output.push_str("</tr>\n");
+    }
+    output.push_str("</table>\n</body>\n</html>\n");
+    output.into_bytes()
+}
+
+fn export_gpx(
+    records: &[SongHistoryRecord],
+    locations: &HashMap<(String, String), RecognitionLocation>,
+    name: &str,
+) -> Vec<u8> {
+    let mut output = String::new();
+    output.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        output,
+        "<gpx version=\"1.1\" creator=\"SongRec {}\" xmlns=\"http://www.topografix.com/GPX/1/1\">",
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "\t<metadata><name>{}</name></metadata>",
+        escape_xml(name)
+    )
+    .unwrap();
+    for record in records {
+        let Some(location) =
+            locations.get(&(record.recognition_date.clone(), record.song_name.clone()))
+        else {
+            continue;
+        };
+        let Some((latitude, longitude)) = location.coordinates() else {
+            continue;
+        };
+        writeln!(
+            output,
+            "\t<wpt lat=\"{:.6}\" lon=\"{:.6}\">",
+            latitude, longitude
+        )
+        .unwrap();
+        if let Some(date) = clock::parse_zoned_history_date(&record.recognition_date) {
+            writeln!(
+                output,
+                "\t\t<time>{}</time>",
+                date.with_timezone(&chrono::Utc)
+                    .format("%Y-%m-%dT%H:%M:%SZ")
+            )
+            .unwrap();
+        }
+        writeln!(output, "\t\t<name>{}</name>", escape_xml(&record.song_name)).unwrap();
+        if let Some(ref venue) = location.venue {
+            writeln!(output, "\t\t<desc>{}</desc>", escape_xml(venue)).unwrap();
+        }
+        if let Some(link) = record_link(record) {
+            writeln!(output, "\t\t<link href=\"{}\"/>", escape_xml(&link)).unwrap();
+        }
+        output.push_str("\t</wpt>\n");
+    }
+    output.push_str("</gpx>\n");
+    output.into_bytes()
+}
+
+pub fn export_history(
+    records: &[SongHistoryRecord],
+    columns: &[CsvColumn],
+    format: ExportFormat,
+    header: bool,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    match format {
+        ExportFormat::Csv => export_delimited(records, columns, header, b','),
+        ExportFormat::Tsv => export_delimited(records, columns, header, b'\t'),
+        #[cfg(feature = "xlsx")]
+        ExportFormat::Xlsx => export_xlsx(records, columns, header),
+        ExportFormat::ItunesXml => Ok(export_itunes_xml(
+            records,
+            &gettext("Discovered with SongRec"),
+        )),
+        ExportFormat::Json => Ok(export_json(records, columns)),
+        ExportFormat::M3u => Ok(export_m3u(records)),
+        ExportFormat::Xspf => Ok(export_xspf(records, &gettext("Discovered with SongRec"))),
+        ExportFormat::Html => Ok(export_html(
+            records,
+            columns,
+            header,
+            &gettext("Discovered with SongRec"),
+        )),
+        ExportFormat::Gpx => Ok(export_gpx(
+            records,
+            &locations_by_entry(),
+            &gettext("Discovered with SongRec"),
+        )),
+    }
+}
+
+#[test]
+fn test_export_filter_and_playlists() {
+    let record = |song_name: &str, track_key: Option<&str>, genre: &str| SongHistoryRecord {
+        track_key: track_key.map(str::to_string),
+        genre: Some(genre.to_string()),
+        source: Some("microphone:default".to_string()),
+        ..SongHistoryRecord::for_test(song_name, "Sat Aug 17 22:44:43 2024")
+    };
+    let records = vec![
+        record("Daft Punk - Da Funk", Some("20066955"), "Electronic"),
+        record("Radio - Jingle", Some("icy:jingle"), "Pop"),
+    ];
+
+    let filter = ExportFilter {
+        period: Some("2024-08-17".parse().unwrap()),
+        source_kind: Some("microphone".to_string()),
+        genre: Some("electro".to_string()),
+        ..Default::default()
+    };
+    assert!(filter.matches(&records[0]));
+    assert!(!filter.matches(&records[1]));
+
+    let m3u = String::from_utf8(export_m3u(&records)).unwrap();
+    assert_eq!(
+        m3u,
+        "#EXTM3U\n#EXTINF:-1,Daft Punk - Da Funk\nhttps://www.shazam.com/track/20066955\n"
+    );
+    let xspf = String::from_utf8(export_xspf(&records, "Songs")).unwrap();
+    assert!(xspf.contains("<creator>Daft Punk</creator>"));
+    assert_eq!(xspf.matches("<track>").count(), 2);
+
+    let itunes_xml = String::from_utf8(export_itunes_xml(
+        &[
+            record("Daft Punk - Da\u{1}\tFunk", Some("20066955"), "Electronic"),
+            record(
+                "Air - Playground Love",
+                Some("local:/music/Air/Playground Love.flac"),
+                "",
+            ),
+        ],
+        "Songs",
+    ))
+    .unwrap();
+    assert!(itunes_xml.contains("<key>Name</key><string>Da\tFunk</string>"));
+    // Not an absolute path on Windows
+    #[cfg(unix)]
+    {
+        assert_eq!(itunes_xml.matches("<key>Location</key>").count(), 1);
+        assert!(itunes_xml.contains("<string>file:///music/Air/Playground%20Love.flac</string>"));
+    }
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&export_json(&records, &[CsvColumn::Title])).unwrap();
+    assert_eq!(json[1]["title"], "Jingle");
+
+    let locations = [RecognitionLocation {
+        recognition_date: records[0].recognition_date.clone(),
+        song_name: records[0].song_name.clone(),
+        venue: Some("Le Rex & Co".to_string()),
+        latitude: Some(48.8707),
+        longitude: Some(2.3477),
+    }]
+    .into_iter()
+    .map(|location| {
+        (
+            (
+                location.recognition_date.clone(),
+                location.song_name.clone(),
+            ),
+            location,
+        )
+    })
+    .collect();
+    let gpx = String::from_utf8(export_gpx(&records, &locations, "Songs")).unwrap();
+    assert_eq!(gpx.matches("<wpt ").count(), 1);
+    assert!(gpx.contains("<wpt lat=\"48.870700\" lon=\"2.347700\">"));
+    assert!(gpx.contains("<desc>Le Rex &amp; Co</desc>"));
+}
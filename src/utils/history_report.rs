@@ -0,0 +1,344 @@
+//! A digest of the songs discovered during the last day, week or month, in
+//! Markdown or HTML, to be posted somewhere or mailed (piped to sendmail).
+//! Discoveries are the songs recognized during the period which had never
+//! been recognized before; the report is made from the history alone.
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime};
+use gettextrs::{gettext, ngettext};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::utils::clock;
+use crate::utils::csv_song_history::{CsvColumn, DateRange, SongHistoryRecord};
+use crate::utils::history_export::escape_xml;
+use crate::utils::song_link::is_shazam_track_key;
+
+/// How many new artists the report puts forward
+const TOP_ARTISTS: usize = 10;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReportPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl FromStr for ReportPeriod {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "day" => Ok(ReportPeriod::Day),
+            "week" => Ok(ReportPeriod::Week),
+            "month" => Ok(ReportPeriod::Month),
+            _ => Err(gettext("Unknown report period: %s").replace("%s", name)),
+        }
+    }
+}
+
+impl ReportPeriod {
+    /// The period ending now
+    pub fn range(self, now: NaiveDateTime) -> DateRange {
+        let length = match self {
+            ReportPeriod::Day => Duration::days(1),
+            ReportPeriod::Week => Duration::weeks(1),
+            ReportPeriod::Month => Duration::days(30),
+        };
+        DateRange {
+            start: Some(now - length),
+            end: Some(now),
+        }
+    }
+
+    fn title(self) -> String {
+        match self {
+            ReportPeriod::Day => gettext("The discoveries of the day"),
+            ReportPeriod::Week => gettext("The discoveries of the week"),
+            ReportPeriod::Month => gettext("The discoveries of the month"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err(gettext("Unknown report format: %s").replace("%s", name)),
+        }
+    }
+}
+
+pub struct Report {
+    pub title: String,
+    /// Songs recognized during the period, including the known ones
+    pub recognitions: usize,
+    /// First recognition of the new songs, in order
+    pub discoveries: Vec<SongHistoryRecord>,
+    /// Artists never heard before the period, with their number of new songs
+    pub new_artists: Vec<(String, usize)>,
+}
+
+/// The headers of the report when it is mailed
+pub struct MailHeaders {
+    pub to: String,
+    pub from: String,
+    pub date: DateTime<FixedOffset>,
+}
+
+/// Gather the discoveries of the period from the whole history, sorted
+/// from the oldest to the latest entry
+pub fn build_report(
+    records: &[SongHistoryRecord],
+    period: ReportPeriod,
+    now: NaiveDateTime,
+) -> Report {
+    let range = period.range(now);
+    let artist = |record: &SongHistoryRecord| CsvColumn::Artist.value(record, None);
+
+    let mut known_songs: HashSet<&str> = HashSet::new();
+    let mut known_artists: HashSet<String> = HashSet::new();
+    let mut recognitions = 0;
+    let mut discoveries = vec![];
+    let mut new_artists: Vec<(String, usize)> = vec![];
+    let mut new_artist_positions: HashMap<String, usize> = HashMap::new();
+
+    for record in records {
+        if !range.contains(record) {
+            // Songs recognized after the period don't make earlier ones known
+            if range.start.is_some_and(|start| {
+                clock::parse_history_date(&record.recognition_date).is_some_and(|date| date < start)
+            }) {
+                known_songs.insert(&record.song_name);
+                known_artists.insert(artist(record));
+            }
+            continue;
+        }
+        recognitions += 1;
+        if !known_songs.insert(&record.song_name) {
+            continue;
+        }
+        discoveries.push(record.clone());
+
+        let artist = artist(record);
+        if artist.is_empty() || known_artists.contains(&artist) {
+            continue;
+        }
+        match new_artist_positions.get(&artist) {
+            Some(&position) => new_artists[position].1 += 1,
+            None => {
+                new_artist_positions.insert(artist.clone(), new_artists.len());
+                new_artists.push((artist, 1));
+            }
+        }
+    }
+    // Stable, so that artists with as many songs stay in order of discovery
+    new_artists.sort_by(|first, second| second.1.cmp(&first.1));
+    new_artists.truncate(TOP_ARTISTS);
+
+    Report {
+        title: period.title(),
+        recognitions,
+        discoveries,
+        new_artists,
+    }
+}
+
+fn shazam_url(record: &SongHistoryRecord) -> Option<String> {
+    record
+        .track_key
+        .as_deref()
+        .filter(|track_key| is_shazam_track_key(track_key))
+        .map(|track_key| format!("https://www.shazam.com/track/{}", track_key))
+}
+
+fn summary(report: &Report) -> String {
+    ngettext(
+        "%d song recognized, %d of which for the first time",
+        "%d songs recognized, %d of which for the first time",
+        report.recognitions as u32,
+    )
+    .replacen("%d", &report.recognitions.to_string(), 1)
+    .replacen("%d", &report.discoveries.len().to_string(), 1)
+}
+
+fn new_songs(count: usize) -> String {
+    ngettext("%d new song", "%d new songs", count as u32).replace("%d", &count.to_string())
+}
+
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        if "\\`*_[]()#<>|".contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+fn render_markdown(report: &Report) -> String {
+    let mut text = format!("# {}\n\n{}\n", report.title, summary(report));
+
+    if !report.new_artists.is_empty() {
+        text.push_str(&format!("\n## {}\n\n", gettext("Top new artists")));
+        for (artist, count) in &report.new_artists {
+            text.push_str(&format!(
+                "- **{}** ({})\n",
+                escape_markdown(artist),
+                new_songs(*count)
+            ));
+        }
+    }
+
+    if !report.discoveries.is_empty() {
+        text.push_str(&format!("\n## {}\n\n", gettext("New songs")));
+        for record in &report.discoveries {
+            let song = escape_markdown(&record.song_name);
+            let song = match shazam_url(record) {
+                Some(url) => format!("[{}]({})", song, url),
+                None => song,
+            };
+            let album = match record.album.as_deref() {
+                Some(album) if !album.is_empty() => format!(", *{}*", escape_markdown(album)),
+                _ => String::new(),
+            };
+            text.push_str(&format!(
+                "- {}{} — {}\n",
+                song,
+                album,
+                CsvColumn::Timestamp.value(record, None)
+            ));
+        }
+    }
+    text
+}
+
+fn render_html(report: &Report) -> String {
+    let mut body = format!(
+        "<h1>{}</h1>\n<p>{}</p>\n",
+        escape_xml(&report.title),
+        escape_xml(&summary(report))
+    );
+
+    if !report.new_artists.is_empty() {
+        body.push_str(&format!(
+            "<h2>{}</h2>\n<ol>\n",
+            escape_xml(&gettext("Top new artists"))
+        ));
+        for (artist, count) in &report.new_artists {
+            body.push_str(&format!(
+                "<li><b>{}</b> ({})</li>\n",
+                escape_xml(artist),
+                escape_xml(&new_songs(*count))
+            ));
+        }
+        body.push_str("</ol>\n");
+    }
+
+    if !report.discoveries.is_empty() {
+        body.push_str(&format!(
+            "<h2>{}</h2>\n<table>\n",
+            escape_xml(&gettext("New songs"))
+        ));
+        for record in &report.discoveries {
+            let song = match shazam_url(record) {
+                Some(url) => format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_xml(&url),
+                    escape_xml(&record.song_name)
+                ),
+                None => escape_xml(&record.song_name),
+            };
+            body.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_xml(&CsvColumn::Timestamp.value(record, None)),
+                song,
+                escape_xml(record.album.as_deref().unwrap_or_default())
+            ));
+        }
+        body.push_str("</table>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+<style>body {{ font-family: sans-serif; }} td {{ padding: 4px 8px; }}</style>\n\
+</head>\n<body>\n{}</body>\n</html>\n",
+        escape_xml(&report.title),
+        body
+    )
+}
+
+/// Render the report, preceded by the headers of an email when `mail` is
+/// given, so that it may be piped to "sendmail -t"
+pub fn render_report(report: &Report, format: ReportFormat, mail: Option<&MailHeaders>) -> String {
+    let (content_type, contents) = match format {
+        ReportFormat::Markdown => ("text/markdown", render_markdown(report)),
+        ReportFormat::Html => ("text/html", render_html(report)),
+    };
+    let Some(mail) = mail else {
+        return contents;
+    };
+    // RFC 2047 encoded, the title may not be ASCII once translated
+    let subject = format!(
+        "=?utf-8?B?{}?=",
+        base64::Engine::encode(&base64::prelude::BASE64_STANDARD, report.title.as_bytes())
+    );
+    format!(
+        "From: {}\r\nTo: {}\r\nDate: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\n\
+Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}",
+        mail.from,
+        mail.to,
+        mail.date.to_rfc2822(),
+        subject,
+        content_type,
+        contents
+    )
+}
+
+#[test]
+fn test_history_report() {
+    let record = |song_name: &str, recognition_date: &str| SongHistoryRecord {
+        track_key: Some("123".to_string()),
+        ..SongHistoryRecord::for_test(song_name, recognition_date)
+    };
+    let records = vec![
+        record("Daft Punk - Around the World", "Sat Aug 03 22:00:00 2024"),
+        record("Daft Punk - Around the World", "Sat Aug 17 22:00:00 2024"),
+        record("Daft Punk - Da Funk", "Sat Aug 17 22:10:00 2024"),
+        record("Justice - Genesis", "Sat Aug 17 22:20:00 2024"),
+        record("Justice - D.A.N.C.E.", "Sun Aug 18 22:30:00 2024"),
+        record("Justice - Genesis", "Sun Aug 18 22:40:00 2024"),
+        record("Air - La femme d'argent", "Tue Aug 20 10:00:00 2024"),
+    ];
+    let now = chrono::NaiveDateTime::parse_from_str("Mon Aug 19 12:00:00 2024", "%c").unwrap();
+
+    let report = build_report(&records, ReportPeriod::Week, now);
+    assert_eq!(report.recognitions, 5);
+    assert_eq!(report.discoveries.len(), 3);
+    assert_eq!(report.new_artists, vec![("Justice".to_string(), 2)]);
+
+    let markdown = render_report(&report, ReportFormat::Markdown, None);
+    assert!(markdown.contains("[Justice - D.A.N.C.E.](https://www.shazam.com/track/123)"));
+    assert!(!markdown.contains("Air"));
+
+    let headers = MailHeaders {
+        to: "me@example.com".to_string(),
+        from: "songrec@example.com".to_string(),
+        date: DateTime::parse_from_rfc3339("2024-08-19T12:00:00+02:00").unwrap(),
+    };
+    let mail = render_report(&report, ReportFormat::Html, Some(&headers));
+    assert!(mail.starts_with(
+        "From: songrec@example.com\r\nTo: me@example.com\r\n\
+Date: Mon, 19 Aug 2024 12:00:00 +0200\r\nSubject: =?utf-8?B?"
+    ));
+    assert!(mail.contains("Content-Type: text/html; charset=utf-8\r\n\r\n<!DOCTYPE html>"));
+}
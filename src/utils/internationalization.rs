@@ -1,9 +1,57 @@
 use gettextrs::{bind_textdomain_codeset, bindtextdomain, setlocale, textdomain, LocaleCategory};
-use log::warn;
 use std::path::PathBuf;
 
-/// Set up the translation/internationalization part
-pub fn setup_internationalization() -> Option<PathBuf> {
+/// The territory of the usual locale of a language, e.g. "JP" for "ja", for
+/// the translations whose language code isn't the one of a country
+fn territory(language: &str) -> String {
+    match language {
+        "ar" => "EG".to_string(),
+        "ca" => "ES".to_string(),
+        "cs" => "CZ".to_string(),
+        "en" => "US".to_string(),
+        "ja" => "JP".to_string(),
+        "ko" => "KR".to_string(),
+        "oc" => "FR".to_string(),
+        "pt" => "BR".to_string(),
+        _ => language.to_ascii_uppercase(),
+    }
+}
+
+/// The locale to translate the messages in a language, as gettext ignores
+/// LANGUAGE under the "C" and "POSIX" locales: the locale of the language
+/// if it is installed, or else "C.UTF-8" when the locale of the system
+/// doesn't let LANGUAGE apply
+fn set_messages_locale(language: &str, system_locale: Option<&[u8]>) -> bool {
+    let mut candidates = vec![format!("{}.UTF-8", language)];
+    if !language.contains('_') {
+        // "fr" is installed as "fr_FR.UTF-8", and "ja" as "ja_JP.UTF-8"
+        candidates.push(format!("{}_{}.UTF-8", language, territory(language)));
+    }
+    if candidates
+        .into_iter()
+        .any(|locale| setlocale(LocaleCategory::LcMessages, locale).is_some())
+    {
+        return true;
+    }
+    match system_locale {
+        Some(b"C") | Some(b"POSIX") | None => {
+            setlocale(LocaleCategory::LcMessages, "C.UTF-8").is_some()
+        }
+        Some(_) => true,
+    }
+}
+
+/// Set up the translation/internationalization part, in the given language
+/// (e.g. "fr" or "pt_BR") rather than the one of the system if any. This
+/// runs before the logger is set up, so the problems met are returned for
+/// the caller to log.
+pub fn setup_internationalization(language: Option<&str>) -> (Option<PathBuf>, Vec<String>) {
+    let mut warnings = vec![];
+    if let Some(language) = language {
+        // Read by gettext before the locale, unless the locale is "C"
+        std::env::set_var("LANGUAGE", language);
+    }
+
     // First, check for a "translations" directory in the
     // same directory as the current binary
 
@@ -46,22 +94,34 @@ pub fn setup_internationalization() -> Option<PathBuf> {
 
     if translations_path.is_dir() {
         if let Err(error) = bindtextdomain("songrec", translations_path.to_str().unwrap()) {
-            warn!("Failed to run bindtextdomain: {:?}", error);
+            warnings.push(format!("Failed to run bindtextdomain: {:?}", error));
         }
     }
 
     if let Err(error) = textdomain("songrec") {
-        warn!("Failed to run textdomain: {:?}", error);
+        warnings.push(format!("Failed to run textdomain: {:?}", error));
     }
     if let Err(error) = bind_textdomain_codeset("songrec", "UTF-8") {
-        warn!("Failed to run bind_textdomain_codeset: {:?}", error);
+        warnings.push(format!(
+            "Failed to run bind_textdomain_codeset: {:?}",
+            error
+        ));
     }
 
-    setlocale(LocaleCategory::LcAll, "");
+    let system_locale = setlocale(LocaleCategory::LcAll, "");
+    if let Some(language) = language {
+        if !set_messages_locale(language, system_locale.as_deref()) {
+            warnings.push(format!(
+                "Neither the {} locale nor C.UTF-8 is installed, the messages may not be translated",
+                language
+            ));
+        }
+    }
 
-    if translations_path.is_dir() {
+    let translations_path = if translations_path.is_dir() {
         Some(translations_path)
     } else {
         None
-    }
+    };
+    (translations_path, warnings)
 }
@@ -0,0 +1,91 @@
+//! Loudness of the previews and of the unrecognized clips played from the
+//! GUI, measured as in EBU R128, so that they are played at the same level:
+//! captures of a distant microphone are much quieter than the mastered
+//! previews of Shazam. The volume of a media stream can only be lowered,
+//! so the loud sounds are brought down to the target rather than the quiet
+//! ones up.
+
+use log::debug;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use crate::core::fingerprinting::algorithm::SignatureGenerator;
+
+/// The loudness of the programmes of EBU R128, in LUFS
+const TARGET_LOUDNESS: f64 = -23.0;
+
+/// Never play a sound so quietly that it can't be heard at all
+const MIN_VOLUME: f64 = 0.05;
+
+/// The integrated loudness of mono samples, None for silence
+pub fn integrated_loudness(samples: &[f32], sample_rate: u32) -> Option<f64> {
+    let mut meter = ebur128::EbuR128::new(1, sample_rate, ebur128::Mode::I).ok()?;
+    meter.add_frames_f32(samples).ok()?;
+    meter
+        .loudness_global()
+        .ok()
+        .filter(|loudness| loudness.is_finite())
+}
+
+/// The volume of the media stream (from 0 to 1) playing a sound of this
+/// loudness at the target one
+pub fn playback_volume(loudness: Option<f64>) -> f64 {
+    match loudness {
+        Some(loudness) => 10f64
+            .powf((TARGET_LOUDNESS - loudness) / 20.0)
+            .clamp(MIN_VOLUME, 1.0),
+        None => 1.0,
+    }
+}
+
+static MEASURED_FILES: LazyLock<Mutex<HashMap<PathBuf, Option<f64>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The loudness of an audio file, which is decoded entirely; blocks, and
+/// is remembered for the next plays
+pub fn measure_file(path: &Path) -> Option<f64> {
+    if let Some(loudness) = MEASURED_FILES.lock().unwrap().get(path) {
+        return *loudness;
+    }
+    let loudness = match SignatureGenerator::decode_file_to_16khz_mono(&path.to_string_lossy()) {
+        Ok(samples) => integrated_loudness(&samples, 16000),
+        Err(error) => {
+            debug!(
+                "Could not measure the loudness of {}: {}",
+                path.display(),
+                error
+            );
+            None
+        }
+    };
+    MEASURED_FILES
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), loudness);
+    loudness
+}
+
+/// The volume at which to play an audio file, measured in a thread
+pub async fn file_playback_volume(path: PathBuf) -> f64 {
+    let (result_tx, result_rx) = async_channel::bounded(1);
+    std::thread::spawn(move || {
+        result_tx.send_blocking(measure_file(&path)).ok();
+    });
+    playback_volume(result_rx.recv().await.ok().flatten())
+}
+
+#[test]
+fn test_loudness() {
+    // A full-scale 1 kHz sine is at about -3 LUFS
+    let samples: Vec<f32> = (0..16000 * 3)
+        .map(|index| (index as f32 * 2.0 * std::f32::consts::PI * 1000.0 / 16000.0).sin())
+        .collect();
+    let loudness = integrated_loudness(&samples, 16000).unwrap();
+    assert!((loudness + 3.0).abs() < 0.5, "{}", loudness);
+    assert!((playback_volume(Some(loudness)) - 0.1).abs() < 0.01);
+
+    assert_eq!(integrated_loudness(&[0.0; 16000], 16000), None);
+    assert_eq!(playback_volume(Some(-40.0)), 1.0);
+    assert_eq!(playback_volume(Some(20.0)), MIN_VOLUME);
+}
@@ -0,0 +1,200 @@
+//! The "Open in" menu of the history rows, whose entries are URL templates
+//! set in the preferences file, e.g.:
+//!
+//! ```toml
+//! [[open_in_handlers]]
+//! name = "Local player"
+//! url = "file:///home/me/Music/{artist}/{album}"
+//! ```
+//!
+//! The URLs are opened with the default handler of their scheme (xdg-open
+//! on Linux), so that "spotify:" URIs open the Spotify application.
+//!
+//! People who buy their music rather than stream it can have the Bandcamp
+//! searches listed first ("prefer_bandcamp" preference).
+
+use gettextrs::gettext;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::csv_song_history::{CsvColumn, SongHistoryRecord};
+use crate::utils::share_text::replace_placeholders;
+use crate::utils::song_link::song_link;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OpenInHandler {
+    pub name: String,
+    pub url: String,
+}
+
+pub fn default_open_in_handlers() -> Vec<OpenInHandler> {
+    vec![
+        OpenInHandler {
+            name: gettext("Spotify"),
+            url: "{spotify_uri}".to_string(),
+        },
+        OpenInHandler {
+            name: gettext("Web browser"),
+            url: "{link}".to_string(),
+        },
+        OpenInHandler {
+            name: gettext("YouTube search"),
+            url: "https://www.youtube.com/results?search_query={query}".to_string(),
+        },
+    ]
+    .into_iter()
+    .chain(bandcamp_handlers())
+    .collect()
+}
+
+fn bandcamp_handlers() -> Vec<OpenInHandler> {
+    vec![
+        OpenInHandler {
+            name: gettext("Bandcamp"),
+            url: "{bandcamp}".to_string(),
+        },
+        OpenInHandler {
+            name: gettext("Bandcamp artist"),
+            url: "https://bandcamp.com/search?q={artist}&item_type=b".to_string(),
+        },
+    ]
+}
+
+/// The handlers of the menu, in order. When Bandcamp is preferred, its
+/// handlers come first, and are added to lists saved without them.
+pub fn menu_open_in_handlers(
+    handlers: Option<Vec<OpenInHandler>>,
+    prefer_bandcamp: bool,
+) -> Vec<OpenInHandler> {
+    let handlers = handlers.unwrap_or_else(default_open_in_handlers);
+    if !prefer_bandcamp {
+        return handlers;
+    }
+    let (mut bandcamp, others): (Vec<OpenInHandler>, Vec<OpenInHandler>) =
+        handlers.into_iter().partition(|handler| {
+            handler.url.contains("{bandcamp}") || handler.url.contains("bandcamp.com")
+        });
+    if bandcamp.is_empty() {
+        bandcamp = bandcamp_handlers();
+    }
+    bandcamp.into_iter().chain(others).collect()
+}
+
+fn url_encode(value: String) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Replace the placeholders of the share text (see `render_share_text`),
+/// URL-encoded, as well as {query} ("artist - title"), {spotify_uri} and
+/// {bandcamp} (a Bandcamp search for the song). {link} is only left as is
+/// when the template starts with it, being the URL to open.
+/// Nothing is returned when a placeholder of the template is unknown for
+/// this song, as for songs without a Spotify URI.
+pub fn render_open_in_url(
+    template: &str,
+    record: &SongHistoryRecord,
+    shazam_json: Option<&str>,
+) -> Option<String> {
+    let parsed_json = shazam_json.and_then(|json| serde_json::from_str(json).ok());
+    let link_is_url = template.trim_start().starts_with("{link}");
+    let mut missing = false;
+    let url = replace_placeholders(template, |placeholder| {
+        let (value, encode) = match placeholder {
+            "link" => (
+                record
+                    .track_key
+                    .as_deref()
+                    .and_then(|track_key| song_link(track_key, shazam_json.unwrap_or("{}")))
+                    .unwrap_or_default(),
+                !link_is_url,
+            ),
+            _ => {
+                let column = match placeholder {
+                    "spotify_uri" => CsvColumn::SpotifyUri,
+                    "bandcamp" => CsvColumn::BandcampUrl,
+                    "isrc" => CsvColumn::Isrc,
+                    "upc" => CsvColumn::Upc,
+                    "title" => CsvColumn::Title,
+                    "artist" => CsvColumn::Artist,
+                    "album" => CsvColumn::Album,
+                    "year" => CsvColumn::ReleaseYear,
+                    "genre" => CsvColumn::Genre,
+                    "date" => CsvColumn::RecognitionDate,
+                    "query" => CsvColumn::SongName,
+                    _ => return None,
+                };
+                (
+                    column.value(record, parsed_json.as_ref()),
+                    !matches!(column, CsvColumn::SpotifyUri | CsvColumn::BandcampUrl),
+                )
+            }
+        };
+        missing |= value.is_empty();
+        Some(if encode { url_encode(value) } else { value })
+    });
+    let url = url.trim().to_string();
+    (!missing && !url.is_empty()).then_some(url)
+}
+
+#[test]
+fn test_render_open_in_url() {
+    let record = SongHistoryRecord {
+        album: Some("Homework".to_string()),
+        track_key: Some("123".to_string()),
+        ..SongHistoryRecord::for_test("Daft Punk - Around the World", "Sat Aug 17 22:44:43 2024")
+    };
+    let shazam_json = r#"{"track": {"hub": {"providers": [{"type": "SPOTIFY",
+        "actions": [{"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC"}]}]}}}"#;
+    assert_eq!(
+        render_open_in_url(
+            "https://www.youtube.com/results?search_query={query}",
+            &record,
+            None
+        ),
+        Some(
+            "https://www.youtube.com/results?search_query=Daft%20Punk%20-%20Around%20the%20World"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        render_open_in_url("{spotify_uri}", &record, Some(shazam_json)),
+        Some("spotify:track:4uLU6hMCjMI75M1A2tKUQC".to_string())
+    );
+    assert_eq!(render_open_in_url("{spotify_uri}", &record, None), None);
+    assert_eq!(
+        render_open_in_url("{link}", &record, None),
+        Some("https://www.shazam.com/track/123".to_string())
+    );
+    assert_eq!(
+        render_open_in_url("https://example.com/?url={link}&date={date}", &record, None),
+        Some("https://example.com/?url=https%3A%2F%2Fwww.shazam.com%2Ftrack%2F123&date=Sat%20Aug%2017%2022%3A44%3A43%202024".to_string())
+    );
+    assert_eq!(
+        render_open_in_url("https://example.com/{year}", &record, None),
+        None
+    );
+    assert_eq!(
+        render_open_in_url("{bandcamp}", &record, None),
+        Some(
+            "https://bandcamp.com/search?q=Daft%20Punk%20Around%20the%20World&item_type=t"
+                .to_string()
+        )
+    );
+
+    let handlers = menu_open_in_handlers(None, true);
+    assert_eq!(handlers[0].url, "{bandcamp}");
+    assert_eq!(handlers.len(), default_open_in_handlers().len());
+    let saved = vec![OpenInHandler {
+        name: "Spotify".to_string(),
+        url: "{spotify_uri}".to_string(),
+    }];
+    assert_eq!(menu_open_in_handlers(Some(saved.clone()), false), saved);
+    assert_eq!(menu_open_in_handlers(Some(saved), true).len(), 3);
+}
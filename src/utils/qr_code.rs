@@ -0,0 +1,43 @@
+//! QR codes pointing to recognized songs, so that people nearby can open
+//! them on their phone.
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{Color, QrCode};
+use std::error::Error;
+
+/// Number of light modules around the code, as required by the standard
+const QUIET_ZONE: usize = 4;
+
+/// Render a QR code as a square RGB image, returning its side in pixels
+/// along with the pixel data
+pub fn render_rgb(data: &str, module_pixels: usize) -> Result<(usize, Vec<u8>), Box<dyn Error>> {
+    let code = QrCode::new(data)?;
+    let colors = code.to_colors();
+    let modules = code.width() + 2 * QUIET_ZONE;
+    let side = modules * module_pixels;
+
+    let mut pixels = Vec::with_capacity(side * side * 3);
+    for y in 0..side {
+        for x in 0..side {
+            let (module_x, module_y) = (x / module_pixels, y / module_pixels);
+            let is_dark = (QUIET_ZONE..code.width() + QUIET_ZONE).contains(&module_x)
+                && (QUIET_ZONE..code.width() + QUIET_ZONE).contains(&module_y)
+                && colors[(module_y - QUIET_ZONE) * code.width() + module_x - QUIET_ZONE]
+                    == Color::Dark;
+            let value = if is_dark { 0 } else { 255 };
+            pixels.extend_from_slice(&[value, value, value]);
+        }
+    }
+    Ok((side, pixels))
+}
+
+/// Render a QR code with Unicode half blocks, two modules per character.
+/// Colors are inverted, as terminals are usually light text on a dark
+/// background.
+pub fn render_terminal(data: &str) -> Result<String, Box<dyn Error>> {
+    Ok(QrCode::new(data)?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
@@ -0,0 +1,47 @@
+//! The `songrec://recognize?path=/music/song.mp3` URIs, which ask the
+//! running GUI to recognize files, e.g. from the file manager actions of
+//! "packaging/rootfs/usr/share". The desktop file registers SongRec as the
+//! handler of the scheme, and the single instance forwards them to the
+//! running window (see `single_instance`).
+
+use std::path::PathBuf;
+
+pub const SCHEME: &str = "songrec";
+
+/// The files to recognize, given by one or more "path" parameters, as
+/// paths or "file://" URIs
+pub fn paths_from_recognize_uri(uri: &str) -> Vec<PathBuf> {
+    let Ok(uri) = glib::Uri::parse(uri, glib::UriFlags::ENCODED_QUERY) else {
+        return vec![];
+    };
+    if !uri.scheme().eq_ignore_ascii_case(SCHEME) || uri.host().as_deref() != Some("recognize") {
+        return vec![];
+    }
+    uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|parameter| parameter.strip_prefix("path="))
+        .filter_map(|value| glib::Uri::unescape_string(&value.replace('+', " "), None))
+        .map(|value| match glib::filename_from_uri(&value) {
+            Ok((path, _)) => path,
+            Err(_) => PathBuf::from(value.as_str()),
+        })
+        .filter(|path| path.is_absolute())
+        .collect()
+}
+
+#[test]
+fn test_paths_from_recognize_uri() {
+    assert_eq!(
+        paths_from_recognize_uri("songrec://recognize?path=%2Fmusic%2FDaft%20Punk%2Fone+more.mp3"),
+        vec![PathBuf::from("/music/Daft Punk/one more.mp3")]
+    );
+    assert_eq!(
+        paths_from_recognize_uri(
+            "songrec://recognize?path=file%3A%2F%2F%2Ftmp%2Fa.ogg&path=%2Ftmp%2Fb.flac"
+        ),
+        vec![PathBuf::from("/tmp/a.ogg"), PathBuf::from("/tmp/b.flac")]
+    );
+    assert!(paths_from_recognize_uri("songrec://recognize?path=relative.mp3").is_empty());
+    assert!(paths_from_recognize_uri("https://recognize?path=%2Ftmp%2Fa.ogg").is_empty());
+}
@@ -0,0 +1,157 @@
+//! Text describing a recognized song, for posting to chats and social
+//! media, built from a user-configurable template.
+
+use crate::core::thread_messages::SongRecognizedMessage;
+use crate::utils::clock;
+use crate::utils::csv_song_history::{CsvColumn, SongHistoryRecord};
+use crate::utils::song_link::song_link;
+
+pub const DEFAULT_SHARE_TEMPLATE: &str = "🎵 Now playing: {title} by {artist} {link}";
+
+pub fn default_share_template() -> String {
+    DEFAULT_SHARE_TEMPLATE.to_string()
+}
+
+/// Replace the {title}, {artist}, {album}, {year}, {genre}, {date}, {link},
+/// {bandcamp}, {isrc} and {upc} placeholders of the template
+pub fn render_share_text(
+    template: &str,
+    record: &SongHistoryRecord,
+    shazam_json: Option<&str>,
+) -> String {
+    render(template, record, shazam_json, |value| value)
+}
+
+pub fn render_message_share_text(template: &str, message: &SongRecognizedMessage) -> String {
+    render_share_text(
+        template,
+        &record_from_message(message),
+        Some(&message.shazam_json),
+    )
+}
+
+/// Same as `render_share_text`, but the placeholders are replaced with
+/// hashtag-friendly words ("Hip-Hop/Rap" => "HipHopRap"), e.g. for
+/// "#NowPlaying #{genre}". Hashtags left empty are removed.
+pub fn render_message_hashtags(template: &str, message: &SongRecognizedMessage) -> String {
+    let hashtags = render(
+        template,
+        &record_from_message(message),
+        Some(&message.shazam_json),
+        |value| value.chars().filter(|c| c.is_alphanumeric()).collect(),
+    );
+    hashtags
+        .split_whitespace()
+        .filter(|word| *word != "#")
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn record_from_message(message: &SongRecognizedMessage) -> SongHistoryRecord {
+    SongHistoryRecord {
+        song_name: format!("{} - {}", message.artist_name, message.song_name),
+        album: message.album_name.clone(),
+        track_key: Some(message.track_key.clone()),
+        release_year: message.release_year.clone(),
+        genre: message.genre.clone(),
+        recognition_date: clock::history_date_now(),
+        source: message.source.as_ref().map(|source| source.to_string()),
+    }
+}
+
+fn render(
+    template: &str,
+    record: &SongHistoryRecord,
+    shazam_json: Option<&str>,
+    format_value: impl Fn(String) -> String,
+) -> String {
+    let parsed_json = shazam_json.and_then(|json| serde_json::from_str(json).ok());
+    let link = record
+        .track_key
+        .as_deref()
+        .and_then(|track_key| song_link(track_key, shazam_json.unwrap_or("{}")))
+        .unwrap_or_default();
+
+    let text = replace_placeholders(template, |placeholder| {
+        let column = match placeholder {
+            "title" => CsvColumn::Title,
+            "artist" => CsvColumn::Artist,
+            "album" => CsvColumn::Album,
+            "year" => CsvColumn::ReleaseYear,
+            "genre" => CsvColumn::Genre,
+            "date" => CsvColumn::RecognitionDate,
+            "bandcamp" => CsvColumn::BandcampUrl,
+            "isrc" => CsvColumn::Isrc,
+            "upc" => CsvColumn::Upc,
+            "link" => return Some(link.clone()),
+            _ => return None,
+        };
+        Some(format_value(column.value(record, parsed_json.as_ref())))
+    });
+    text.trim().to_string()
+}
+
+/// Replace each "{name}" of the template with the value given for it, in a
+/// single pass so that the values (e.g. a title containing "{artist}") are
+/// never expanded in turn. Unknown placeholders are left as they are.
+pub fn replace_placeholders(
+    template: &str,
+    mut value_of: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest
+            .find('}')
+            .and_then(|end| Some((end, value_of(&rest[1..end])?)));
+        match value {
+            Some((end, value)) => {
+                text.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+#[test]
+fn test_render_share_text() {
+    let record = SongHistoryRecord {
+        album: Some("Homework".to_string()),
+        track_key: Some("123".to_string()),
+        release_year: Some("1997".to_string()),
+        ..SongHistoryRecord::for_test("Daft Punk - Around the World", "")
+    };
+    assert_eq!(
+        render_share_text(DEFAULT_SHARE_TEMPLATE, &record, None),
+        "🎵 Now playing: Around the World by Daft Punk https://www.shazam.com/track/123"
+    );
+    assert_eq!(
+        render_share_text("{artist} ({year}) {link}", &record, Some("invalid")),
+        "Daft Punk (1997) https://www.shazam.com/track/123"
+    );
+    assert_eq!(
+        render_share_text(
+            "{title} [{isrc}]",
+            &record,
+            Some(r#"{"track": {"isrc": "GBDUW0000053"}}"#)
+        ),
+        "Around the World [GBDUW0000053]"
+    );
+
+    let record = SongHistoryRecord {
+        song_name: "{artist} - {title} {unknown".to_string(),
+        ..record
+    };
+    assert_eq!(
+        render_share_text("{title} by {artist} {unknown}", &record, None),
+        "{title} {unknown by {artist} {unknown}"
+    );
+}
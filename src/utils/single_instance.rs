@@ -0,0 +1,116 @@
+//! Only one SongRec captures audio for each profile. The GUI is a unique
+//! GApplication whose ID depends on the profile, so that launching it again
+//! (with a file to recognize, or from `xdg-open`) forwards the request to
+//! the running window over D-Bus instead of starting another capture.
+//! Besides, the GUI, `songrec listen` and the `history` commands rewriting
+//! the history hold a lock file of the profile while they run, which is
+//! released by the system when they exit.
+
+use gettextrs::gettext;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::utils::filesystem_operations::{current_profile, obtain_instance_lock_path};
+
+/// Kept open until the process exits
+static INSTANCE_LOCK: Mutex<Option<File>> = Mutex::new(None);
+
+/// The GApplication ID, e.g. "re.fossplant.songrec.profile_venue"
+pub fn application_id() -> String {
+    let base = glib::prgname().unwrap_or_default();
+    match current_profile() {
+        Some(profile) => format!("{}.profile_{}", base, escape_id_element(profile)),
+        None => base.to_string(),
+    }
+}
+
+/// A profile name as an element of an application ID, and of the D-Bus
+/// names derived from it, which may only contain ASCII letters, digits,
+/// dashes and underscores: the other bytes of the name (and underscores, so
+/// that different names never give the same ID) are written in hexadecimal
+/// after an underscore, e.g. "Café" as "Caf_c3_a9"
+fn escape_id_element(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+#[cfg(unix)]
+fn try_lock(path: &Path) -> std::io::Result<Option<File>> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let error = std::io::Error::last_os_error();
+        if error.kind() == std::io::ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+        return Err(error);
+    }
+    Ok(Some(file))
+}
+
+#[cfg(windows)]
+fn try_lock(path: &Path) -> std::io::Result<Option<File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // ERROR_SHARING_VIOLATION
+    const SHARING_VIOLATION: i32 = 32;
+
+    match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .share_mode(0)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(error) if error.raw_os_error() == Some(SHARING_VIOLATION) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Fails when another instance already runs with the current profile
+pub fn lock_profile() -> Result<(), Box<dyn Error>> {
+    let path = obtain_instance_lock_path()?;
+    let Some(mut file) = try_lock(&path)? else {
+        let message = match current_profile() {
+            Some(profile) => {
+                gettext("SongRec is already running with the %s profile").replace("%s", profile)
+            }
+            None => gettext("SongRec is already running"),
+        };
+        // The process identifier can't be read on Windows, where the file
+        // is opened exclusively
+        return Err(match std::fs::read_to_string(&path) {
+            Ok(pid) if !pid.trim().is_empty() => format!("{} (PID {})", message, pid.trim()),
+            _ => message,
+        }
+        .into());
+    };
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+    *INSTANCE_LOCK.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+#[test]
+fn test_escape_id_element() {
+    assert_eq!(escape_id_element("venue-2"), "venue-2");
+    assert_eq!(escape_id_element("Café"), "Caf_c3_a9");
+    assert_eq!(escape_id_element("living room"), "living_20room");
+    assert_eq!(escape_id_element("a_b"), "a_5fb");
+}
@@ -0,0 +1,93 @@
+//! Public links to a recognized song, which can be opened on any device
+//! (e.g. from a QR code). Spotify tracks go through song.link so that
+//! people using other streaming services can open them too.
+
+use serde_json::Value;
+
+use crate::core::stream_monitor::ICY_TRACK_KEY_PREFIX;
+use crate::plugins::chromecast::CAST_TRACK_KEY_PREFIX;
+
+/// Songs from the local index only exist on this computer, and the ones
+/// announced by Cast devices or radio streams are not known to Shazam
+pub fn is_shazam_track_key(track_key: &str) -> bool {
+    !track_key.is_empty()
+        && !track_key.starts_with("local:")
+        && !track_key.starts_with(CAST_TRACK_KEY_PREFIX)
+        && !track_key.starts_with(ICY_TRACK_KEY_PREFIX)
+}
+
+pub fn song_link(track_key: &str, shazam_json: &str) -> Option<String> {
+    if !is_shazam_track_key(track_key) {
+        return None;
+    }
+    let json: Value = serde_json::from_str(shazam_json).unwrap_or_default();
+    let track = &json["track"];
+
+    let spotify_track_id = track["hub"]["providers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|provider| provider["type"] == "SPOTIFY")
+        .flat_map(|provider| provider["actions"].as_array().into_iter().flatten())
+        .filter_map(|action| action["uri"].as_str())
+        .find_map(|uri| uri.strip_prefix("spotify:track:"));
+
+    if let Some(track_id) = spotify_track_id {
+        return Some(format!("https://song.link/s/{}", track_id));
+    }
+    Some(match track["url"].as_str() {
+        Some(url) => url.to_string(),
+        None => format!("https://www.shazam.com/track/{}", track_key),
+    })
+}
+
+/// A Bandcamp search for the song, for buying it rather than streaming it.
+/// Shazam doesn't link to Bandcamp, which has no public API to look the
+/// song up exactly.
+pub fn bandcamp_search_url(artist: &str, title: &str) -> String {
+    format!(
+        "https://bandcamp.com/search?q={}&item_type=t",
+        glib::Uri::escape_string(format!("{} {}", artist, title).trim(), None, false)
+    )
+}
+
+/// The 30-second preview of the song, from the "track" object of the
+/// Shazam response
+pub fn preview_url(track: &Value) -> Option<String> {
+    track["hub"]["actions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|action| action["type"] == "uri")
+        .filter_map(|action| action["uri"].as_str())
+        .find(|uri| uri.starts_with("https://"))
+        .map(|uri| uri.to_string())
+}
+
+#[test]
+fn test_song_link() {
+    assert_eq!(song_link("local:/music/song.flac", "{}"), None);
+    assert_eq!(song_link("cast:Artist - Song", "{}"), None);
+    assert_eq!(song_link("icy:Artist - Song", "{}"), None);
+    assert_eq!(
+        song_link("123", "{}"),
+        Some("https://www.shazam.com/track/123".to_string())
+    );
+    let shazam_json = r#"{"track": {"url": "https://www.shazam.com/track/123/song",
+        "hub": {"providers": [{"type": "SPOTIFY", "actions": [{"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC"}]}]}}}"#;
+    assert_eq!(
+        song_link("123", shazam_json),
+        Some("https://song.link/s/4uLU6hMCjMI75M1A2tKUQC".to_string())
+    );
+
+    let track: Value = serde_json::from_str(
+        r#"{"hub": {"actions": [{"name": "apple", "type": "applemusicplay", "id": "1"},
+        {"name": "apple", "type": "uri", "uri": "https://audio-ssl.itunes.apple.com/preview.m4a"}]}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        preview_url(&track),
+        Some("https://audio-ssl.itunes.apple.com/preview.m4a".to_string())
+    );
+    assert_eq!(preview_url(&Value::Null), None);
+}
@@ -0,0 +1,137 @@
+//! Compare the metadata tags of an audio file with the information returned
+//! by Shazam for it, in order to spot mislabeled files in a music library,
+//! and optionally rewrite the mismatching tags.
+
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, Tag};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::error::Error;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+
+#[derive(Debug, Serialize, JsonSchema, Clone)]
+pub struct TagDifference {
+    pub field: &'static str,
+    pub current: Option<String>,
+    pub recognized: String,
+}
+
+/// The JSON output of "recognize --diff-tags"
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TagDiff {
+    pub file: String,
+    pub track_key: String,
+    pub differences: Vec<TagDifference>,
+    pub applied: bool,
+}
+
+fn read_field(tag: &Tag, field: &str) -> Option<String> {
+    let value = match field {
+        "title" => tag.title().map(|value| value.to_string()),
+        "artist" => tag.artist().map(|value| value.to_string()),
+        "album" => tag.album().map(|value| value.to_string()),
+        "genre" => tag.genre().map(|value| value.to_string()),
+        "year" => tag
+            .get_string(&ItemKey::Year)
+            .or_else(|| tag.get_string(&ItemKey::RecordingDate))
+            .map(|value| value.chars().take(4).collect()),
+        _ => unreachable!(),
+    };
+    value.filter(|value: &String| !value.trim().is_empty())
+}
+
+fn write_field(tag: &mut Tag, field: &str, value: &str) {
+    match field {
+        "title" => tag.set_title(value.to_string()),
+        "artist" => tag.set_artist(value.to_string()),
+        "album" => tag.set_album(value.to_string()),
+        "genre" => tag.set_genre(value.to_string()),
+        "year" => {
+            tag.insert_text(ItemKey::Year, value.to_string());
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Case and surrounding whitespace differences are not reported.
+fn same_value(current: &str, recognized: &str) -> bool {
+    current.trim().to_lowercase() == recognized.trim().to_lowercase()
+}
+
+pub fn diff_tags(
+    path: &str,
+    message: &SongRecognizedMessage,
+) -> Result<Vec<TagDifference>, Box<dyn Error>> {
+    let tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    let empty_tag = Tag::new(tagged_file.primary_tag_type());
+    let tag = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+        .unwrap_or(&empty_tag);
+
+    let recognized_fields = [
+        ("title", Some(&message.song_name)),
+        ("artist", Some(&message.artist_name)),
+        ("album", message.album_name.as_ref()),
+        ("genre", message.genre.as_ref()),
+        ("year", message.release_year.as_ref()),
+    ];
+
+    let mut differences = vec![];
+    for (field, recognized) in recognized_fields {
+        let recognized = match recognized {
+            Some(value) if !value.trim().is_empty() => value,
+            _ => continue,
+        };
+        let current = read_field(tag, field);
+        if !matches!(current, Some(ref current) if same_value(current, recognized)) {
+            differences.push(TagDifference {
+                field,
+                current,
+                recognized: recognized.to_string(),
+            });
+        }
+    }
+    Ok(differences)
+}
+
+/// Write the recognized values into the primary tag of the file, creating
+/// it when the file has no tag yet.
+pub fn apply_tags(path: &str, differences: &[TagDifference]) -> Result<(), Box<dyn Error>> {
+    let mut tagged_file = Probe::open(path)?.guess_file_type()?.read()?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file.primary_tag_mut().unwrap();
+
+    for difference in differences {
+        write_field(tag, difference.field, &difference.recognized);
+    }
+    tag.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+impl std::fmt::Display for TagDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.differences.is_empty() {
+            return write!(f, "{}: OK", self.file);
+        }
+        write!(f, "{}:", self.file)?;
+        for difference in &self.differences {
+            write!(
+                f,
+                "\n  {}: {:?} -> {:?}",
+                difference.field,
+                difference.current.as_deref().unwrap_or_default(),
+                difference.recognized
+            )?;
+        }
+        if self.applied {
+            write!(f, "\n  (fixed)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Normalization of the titles and artists of the recognized songs before
+//! they are looked up in other services (the catalog searches of the
+//! playlist sinks, and the comparison with the song played by Spotify),
+//! since Shazam and the streaming services rarely write the featured
+//! artists and the remaster versions the same way. The rules are set with
+//! `--normalize` or the "normalize" preference, e.g. "featuring,remaster":
+//!
+//! - "featuring" drops the featured artists: "Get Lucky (feat. Pharrell
+//!   Williams)" becomes "Get Lucky", "Daft Punk ft. Pharrell" "Daft Punk".
+//!   In titles, "feat.", "ft." and "featuring" only start the featured
+//!   artists between brackets or after a " - " or ", " separator, so that
+//!   "Welcome to Ft. Lauderdale" is kept.
+//! - "remaster" drops the remaster versions: "Heroes (2017 Remaster)" and
+//!   "Heroes - Remastered 2017" become "Heroes"
+//! - "case" writes the names entirely in capitals in title case
+//!
+//! The history keeps the names as recognized.
+
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizationRule {
+    Featuring,
+    Remaster,
+    Case,
+}
+
+impl NormalizationRule {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "featuring" => Ok(NormalizationRule::Featuring),
+            "remaster" => Ok(NormalizationRule::Remaster),
+            "case" => Ok(NormalizationRule::Case),
+            _ => Err(format!("Unknown normalization rule: {}", name)),
+        }
+    }
+}
+
+/// Parse a list of rules separated by commas
+pub fn parse_rules(names: &str) -> Result<Vec<NormalizationRule>, String> {
+    names
+        .split(',')
+        .filter(|name| !name.trim().is_empty())
+        .map(NormalizationRule::parse)
+        .collect()
+}
+
+static RULES: RwLock<Vec<NormalizationRule>> = RwLock::new(Vec::new());
+
+pub fn set_normalization_rules(rules: Vec<NormalizationRule>) {
+    *RULES.write().unwrap() = rules;
+}
+
+const FEATURING_WORDS: &[&str] = &["feat.", "ft.", "featuring"];
+
+/// What may precede the featured artists outside of brackets
+const TITLE_SEPARATORS: &[&str] = &[" - ", ", "];
+/// Artists are credited as "Daft Punk ft. Pharrell Williams"
+const ARTIST_SEPARATORS: &[&str] = &[" - ", ", ", " "];
+
+fn is_featuring(text: &str) -> bool {
+    let text = text.trim_start().to_ascii_lowercase();
+    FEATURING_WORDS.iter().any(|word| {
+        text.strip_prefix(word)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+    })
+}
+
+fn is_remaster(text: &str) -> bool {
+    text.to_ascii_lowercase().contains("remaster")
+}
+
+/// The length of the group opened by the bracket starting `text`, up to its
+/// closing bracket, if the brackets nested in it are balanced
+fn group_length(text: &str) -> Option<usize> {
+    let mut closings = vec![];
+    for (position, character) in text.char_indices() {
+        match character {
+            '(' => closings.push(')'),
+            '[' => closings.push(']'),
+            ')' | ']' => {
+                if closings.pop() != Some(character) {
+                    return None;
+                }
+                if closings.is_empty() {
+                    return Some(position + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Remove the groups between parentheses or brackets matching the
+/// predicate; the text from an unbalanced bracket on is kept as it is
+fn strip_bracketed(text: &str, predicate: fn(&str) -> bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['(', '[']) {
+        let Some(length) = group_length(&rest[start..]) else {
+            break;
+        };
+        let inner = &rest[start + 1..start + length - 1];
+        if predicate(inner) {
+            result.push_str(rest[..start].trim_end());
+        } else {
+            result.push_str(&rest[..start + length]);
+        }
+        rest = &rest[start + length..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Cut the text before the first of the separators followed by words
+/// matching the predicate
+fn strip_suffix<'a>(text: &'a str, predicate: fn(&str) -> bool, separators: &[&str]) -> &'a str {
+    let end = separators
+        .iter()
+        .filter_map(|separator| {
+            text.match_indices(separator)
+                .map(|(position, _)| position)
+                .find(|position| predicate(&text[position + separator.len()..]))
+        })
+        .min()
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+fn title_case(text: &str) -> String {
+    if text.chars().any(char::is_lowercase) {
+        return text.to_string();
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut word_start = true;
+    for character in text.chars() {
+        if word_start {
+            result.push(character);
+        } else {
+            result.extend(character.to_lowercase());
+        }
+        word_start = !character.is_alphanumeric() && character != '\'';
+    }
+    result
+}
+
+fn normalize(original: &str, rules: &[NormalizationRule], separators: &[&str]) -> String {
+    let mut text = original.to_string();
+    if rules.contains(&NormalizationRule::Featuring) {
+        text = strip_bracketed(&text, is_featuring);
+        text = strip_suffix(&text, is_featuring, separators).to_string();
+    }
+    if rules.contains(&NormalizationRule::Remaster) {
+        text = strip_bracketed(&text, is_remaster);
+        // " - 2011 Remaster", but not the words of the title
+        text = match text.find(" - ") {
+            Some(position) if is_remaster(&text[position..]) => text[..position].to_string(),
+            _ => text,
+        };
+    }
+    if rules.contains(&NormalizationRule::Case) {
+        text = title_case(&text);
+    }
+    // Nothing is left of names such as "(Remastered)"
+    match text.trim() {
+        "" => original.to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// The title to look up in other services
+pub fn normalize_title(title: &str) -> String {
+    normalize(title, &RULES.read().unwrap(), TITLE_SEPARATORS)
+}
+
+/// The artist to look up in other services; only the featured artists and
+/// the case apply to artists
+pub fn normalize_artist(artist: &str) -> String {
+    let rules: Vec<NormalizationRule> = RULES
+        .read()
+        .unwrap()
+        .iter()
+        .copied()
+        .filter(|rule| *rule != NormalizationRule::Remaster)
+        .collect();
+    normalize(artist, &rules, ARTIST_SEPARATORS)
+}
+
+#[test]
+fn test_normalization() {
+    use NormalizationRule::*;
+
+    let rules = parse_rules("featuring, remaster,case").unwrap();
+    let title = |text| normalize(text, &rules, TITLE_SEPARATORS);
+    assert_eq!(title("Get Lucky (feat. Pharrell Williams)"), "Get Lucky");
+    assert_eq!(
+        normalize("Daft Punk ft. Pharrell Williams", &rules, ARTIST_SEPARATORS),
+        "Daft Punk"
+    );
+    assert_eq!(title("Heroes - 2017 Remaster"), "Heroes");
+    assert_eq!(
+        title("Heroes [Remastered 2017] (Radio Edit)"),
+        "Heroes (Radio Edit)"
+    );
+    assert_eq!(title("AROUND THE WORLD"), "Around The World");
+    assert_eq!(title("Featuring Artists"), "Featuring Artists");
+    assert_eq!(
+        normalize(
+            "Get Lucky (feat. Pharrell Williams)",
+            &[Remaster],
+            TITLE_SEPARATORS
+        ),
+        "Get Lucky (feat. Pharrell Williams)"
+    );
+    assert_eq!(title("(Remastered)"), "(Remastered)");
+    assert!(parse_rules("featuring,accents").is_err());
+}
+
+#[test]
+fn test_featuring() {
+    let rules = [NormalizationRule::Featuring];
+    let title = |text| normalize(text, &rules, TITLE_SEPARATORS);
+    assert_eq!(
+        title("Welcome to Ft. Lauderdale"),
+        "Welcome to Ft. Lauderdale"
+    );
+    assert_eq!(title("Get Lucky - feat. Pharrell Williams"), "Get Lucky");
+    assert_eq!(title("Get Lucky, featuring Pharrell Williams"), "Get Lucky");
+    assert_eq!(
+        title("Lose Yourself to Dance (feat. Pharrell (Live))"),
+        "Lose Yourself to Dance"
+    );
+    assert_eq!(
+        title("Instant Crush (Live (2014)) (ft. Julian Casablancas)"),
+        "Instant Crush (Live (2014))"
+    );
+    assert_eq!(
+        title("Instant Crush (feat. Julian Casablancas"),
+        "Instant Crush (feat. Julian Casablancas"
+    );
+    assert_eq!(title("Aftermath"), "Aftermath");
+}
@@ -0,0 +1,68 @@
+//! Standard identifiers of the recognized songs: the ISRC of the recording,
+//! which is the same on every streaming service, and the UPC (or EAN)
+//! barcode of the release it was found on. Shazam gives the ISRC of most
+//! songs; the local index reads both from the tags of the matching file.
+
+use serde_json::Value;
+
+/// "gb-duw-00-00053" => "GBDUW0000053"
+pub fn normalize_isrc(isrc: &str) -> Option<String> {
+    let isrc: String = isrc
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    (isrc.len() == 12 && isrc.chars().all(|c| c.is_ascii_alphanumeric())).then_some(isrc)
+}
+
+/// 12 digits for a UPC, 13 for an EAN
+pub fn normalize_upc(upc: &str) -> Option<String> {
+    let upc: String = upc
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect();
+    (matches!(upc.len(), 12 | 13) && upc.chars().all(|c| c.is_ascii_digit())).then_some(upc)
+}
+
+/// The ISRC in a provider response: in the "track" object for Shazam, at
+/// the top level for the other providers
+pub fn isrc_from_response(json: &Value) -> Option<String> {
+    json["track"]["isrc"]
+        .as_str()
+        .or(json["isrc"].as_str())
+        .and_then(normalize_isrc)
+}
+
+pub fn upc_from_response(json: &Value) -> Option<String> {
+    json["track"]["upc"]
+        .as_str()
+        .or(json["upc"].as_str())
+        .and_then(normalize_upc)
+}
+
+#[test]
+fn test_track_identifiers() {
+    assert_eq!(
+        normalize_isrc("gb-duw-00-00053").as_deref(),
+        Some("GBDUW0000053")
+    );
+    assert_eq!(normalize_isrc("GBDUW00000"), None);
+    assert_eq!(
+        normalize_upc("0 724384 260958").as_deref(),
+        Some("0724384260958")
+    );
+    assert_eq!(normalize_upc("72438426095X"), None);
+
+    let shazam_json: Value =
+        serde_json::from_str(r#"{"track": {"isrc": "GBDUW0000053"}}"#).unwrap();
+    assert_eq!(
+        isrc_from_response(&shazam_json).as_deref(),
+        Some("GBDUW0000053")
+    );
+    assert_eq!(upc_from_response(&shazam_json), None);
+    assert_eq!(
+        upc_from_response(&serde_json::json!({ "provider": "local", "upc": "724384260958" }))
+            .as_deref(),
+        Some("724384260958")
+    );
+}
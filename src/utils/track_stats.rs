@@ -0,0 +1,140 @@
+//! How many times each song of the history was heard, and when it was
+//! first and last heard, for "history stats" and the recognition results
+//! of the GUI. The songs are told apart by their name, as in the details
+//! of an artist or an album; with the "no_duplicates" preference, the
+//! history only keeps the last recognition of each song.
+
+use chrono::NaiveDateTime;
+use gettextrs::{gettext, ngettext};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::utils::clock;
+use crate::utils::csv_song_history::SongHistoryRecord;
+
+/// An element of the JSON output of "history stats"
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct TrackStats {
+    pub song_name: String,
+    pub times_heard: usize,
+    /// As in the history
+    pub first_heard: String,
+    pub last_heard: String,
+}
+
+impl TrackStats {
+    pub fn new(record: &SongHistoryRecord) -> Self {
+        TrackStats {
+            song_name: record.song_name.clone(),
+            times_heard: 1,
+            first_heard: record.recognition_date.clone(),
+            last_heard: record.recognition_date.clone(),
+        }
+    }
+
+    /// Entries whose date can't be parsed are counted, but don't change the
+    /// first and last dates
+    pub fn add(&mut self, record: &SongHistoryRecord) {
+        let date = |date: &str| clock::parse_history_date(date);
+        self.times_heard += 1;
+        let Some(record_date) = date(&record.recognition_date) else {
+            return;
+        };
+        if date(&self.first_heard).is_none_or(|first_heard| record_date < first_heard) {
+            self.first_heard = record.recognition_date.clone();
+        }
+        if date(&self.last_heard).is_none_or(|last_heard| record_date > last_heard) {
+            self.last_heard = record.recognition_date.clone();
+        }
+    }
+
+    /// Shown with the recognition results of the GUI, for the times the
+    /// song was heard before
+    pub fn heard_before_text(&self) -> String {
+        if self.times_heard == 1 {
+            return gettext("Heard once before, on %s")
+                .replace("%s", &clock::display_date(&self.last_heard));
+        }
+        ngettext(
+            "Heard %d time before, first on %s, last on %s",
+            "Heard %d times before, first on %s, last on %s",
+            self.times_heard as u32,
+        )
+        .replacen("%d", &self.times_heard.to_string(), 1)
+        .replacen("%s", &clock::display_date(&self.first_heard), 1)
+        .replacen("%s", &clock::display_date(&self.last_heard), 1)
+    }
+
+    fn last_heard_date(&self) -> Option<NaiveDateTime> {
+        clock::parse_history_date(&self.last_heard)
+    }
+}
+
+/// The statistics of every song, the most heard first, then the most
+/// recently heard
+pub fn track_stats<'a>(
+    records: impl IntoIterator<Item = &'a SongHistoryRecord>,
+) -> Vec<TrackStats> {
+    let mut stats: Vec<TrackStats> = vec![];
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for record in records {
+        match positions.get(&record.song_name) {
+            Some(&position) => stats[position].add(record),
+            None => {
+                positions.insert(record.song_name.clone(), stats.len());
+                stats.push(TrackStats::new(record));
+            }
+        }
+    }
+    stats.sort_by(|first, second| {
+        second
+            .times_heard
+            .cmp(&first.times_heard)
+            .then_with(|| second.last_heard_date().cmp(&first.last_heard_date()))
+    });
+    stats
+}
+
+/// The statistics of a single song
+pub fn song_stats<'a>(
+    records: impl IntoIterator<Item = &'a SongHistoryRecord>,
+    song_name: &str,
+) -> Option<TrackStats> {
+    let mut stats: Option<TrackStats> = None;
+    for record in records {
+        if record.song_name != song_name {
+            continue;
+        }
+        match stats {
+            Some(ref mut stats) => stats.add(record),
+            None => stats = Some(TrackStats::new(record)),
+        }
+    }
+    stats
+}
+
+#[test]
+fn test_track_stats() {
+    let record = SongHistoryRecord::for_test;
+    let records = vec![
+        record("Daft Punk - Da Funk", "Sat Aug 17 22:44:43 2024"),
+        record("Justice - D.A.N.C.E.", "Sat Aug 17 23:02:10 2024"),
+        // Added by hand, out of order
+        record("Daft Punk - Da Funk", "Fri Aug 16 20:00:00 2024"),
+        record("Justice - D.A.N.C.E.", "Sun Aug 18 01:15:00 2024"),
+        record("Daft Punk - Da Funk", "Sun Aug 18 00:30:00 2024"),
+        record("Daft Punk - Da Funk", "invalid"),
+    ];
+    let stats = track_stats(&records);
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].song_name, "Daft Punk - Da Funk");
+    assert_eq!(stats[0].times_heard, 4);
+    assert_eq!(stats[0].first_heard, "Fri Aug 16 20:00:00 2024");
+    assert_eq!(stats[0].last_heard, "Sun Aug 18 00:30:00 2024");
+    assert_eq!(
+        song_stats(&records, "Justice - D.A.N.C.E."),
+        Some(stats[1].clone())
+    );
+    assert_eq!(song_stats(&records, "Unknown"), None);
+}
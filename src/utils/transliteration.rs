@@ -0,0 +1,188 @@
+//! Latin transliteration of the titles, artists and albums of the
+//! recognized songs, for the players, scrobblers and car stereos which
+//! can't display other scripts. It is set with `--transliterate` or the
+//! "transliteration" preference, either replacing the names ("Utro") or
+//! appending the transliteration ("Утро (Utro)"), and applies to every
+//! output including the history, once the song was recognized: the sinks
+//! looking songs up in a catalog get the names that were recognized. The
+//! scripts to transliterate can be restricted with `--transliterate-scripts`
+//! (e.g. "cyrillic,greek"); all of them are by default, including the
+//! accents of the Latin script.
+
+use std::sync::RwLock;
+
+use crate::core::thread_messages::SongRecognizedMessage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransliterationMode {
+    Replace,
+    Dual,
+}
+
+impl TransliterationMode {
+    pub fn parse(name: &str) -> Result<Option<Self>, String> {
+        match name.trim() {
+            "" | "off" => Ok(None),
+            "replace" => Ok(Some(TransliterationMode::Replace)),
+            "dual" => Ok(Some(TransliterationMode::Dual)),
+            _ => Err(format!("Unknown transliteration mode: {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    Georgian,
+    Hangul,
+    Kana,
+    Han,
+}
+
+const SCRIPT_NAMES: &[(&str, Script)] = &[
+    ("latin", Script::Latin),
+    ("greek", Script::Greek),
+    ("cyrillic", Script::Cyrillic),
+    ("armenian", Script::Armenian),
+    ("hebrew", Script::Hebrew),
+    ("arabic", Script::Arabic),
+    ("devanagari", Script::Devanagari),
+    ("thai", Script::Thai),
+    ("georgian", Script::Georgian),
+    ("hangul", Script::Hangul),
+    ("kana", Script::Kana),
+    ("han", Script::Han),
+];
+
+/// Parse a list of script names separated by commas
+pub fn parse_scripts(names: &str) -> Result<Vec<Script>, String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            SCRIPT_NAMES
+                .iter()
+                .find(|(script_name, _)| script_name.eq_ignore_ascii_case(name))
+                .map(|(_, script)| *script)
+                .ok_or_else(|| format!("Unknown script: {}", name))
+        })
+        .collect()
+}
+
+/// The script of the letters which aren't written in ASCII; punctuation
+/// and symbols (such as emoji) have none and are kept as they are
+fn script_of(character: char) -> Option<Script> {
+    match character as u32 {
+        0xC0..=0x24F | 0x1E00..=0x1EFF => Some(Script::Latin),
+        0x370..=0x3FF | 0x1F00..=0x1FFF => Some(Script::Greek),
+        0x400..=0x52F => Some(Script::Cyrillic),
+        0x530..=0x58F => Some(Script::Armenian),
+        0x590..=0x5FF => Some(Script::Hebrew),
+        0x600..=0x6FF | 0x750..=0x77F => Some(Script::Arabic),
+        0x900..=0x97F => Some(Script::Devanagari),
+        0xE00..=0xE7F => Some(Script::Thai),
+        0x10A0..=0x10FF => Some(Script::Georgian),
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Some(Script::Hangul),
+        0x3040..=0x30FF => Some(Script::Kana),
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF => Some(Script::Han),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Settings {
+    mode: TransliterationMode,
+    /// Empty for every script
+    scripts: Vec<Script>,
+}
+
+static SETTINGS: RwLock<Option<Settings>> = RwLock::new(None);
+
+/// None disables the transliteration
+pub fn set_transliteration(mode: Option<TransliterationMode>, scripts: Vec<Script>) {
+    *SETTINGS.write().unwrap() = mode.map(|mode| Settings { mode, scripts });
+}
+
+/// Transliterate the runs of letters of the given scripts
+fn transliterate(text: &str, scripts: &[Script]) -> String {
+    let is_transliterated = |character: char| {
+        script_of(character).is_some_and(|script| scripts.is_empty() || scripts.contains(&script))
+    };
+    let mut result = String::with_capacity(text.len());
+    let mut run = String::new();
+    for character in text.chars() {
+        if is_transliterated(character) {
+            run.push(character);
+            continue;
+        }
+        // A run at once, so that deunicode spaces the syllables of
+        // Chinese and Japanese properly
+        if !run.is_empty() {
+            result.push_str(&deunicode::deunicode(&run));
+            run.clear();
+        }
+        result.push(character);
+    }
+    if !run.is_empty() {
+        result.push_str(&deunicode::deunicode(&run));
+    }
+    result
+}
+
+fn apply_settings(text: &str, settings: &Settings) -> String {
+    let transliterated = transliterate(text, &settings.scripts);
+    match settings.mode {
+        TransliterationMode::Replace => transliterated,
+        TransliterationMode::Dual if transliterated != text => {
+            format!("{} ({})", text, transliterated)
+        }
+        _ => transliterated,
+    }
+}
+
+/// Transliterate the names of a recognized song, before it is displayed,
+/// stored or forwarded to the sinks which don't search a catalog
+pub fn apply(message: &mut SongRecognizedMessage) {
+    let Some(ref settings) = *SETTINGS.read().unwrap() else {
+        return;
+    };
+    message.song_name = apply_settings(&message.song_name, settings);
+    message.artist_name = apply_settings(&message.artist_name, settings);
+    message.album_name = message
+        .album_name
+        .as_deref()
+        .map(|album_name| apply_settings(album_name, settings));
+}
+
+#[test]
+fn test_transliteration() {
+    let dual = Settings {
+        mode: TransliterationMode::Dual,
+        scripts: vec![],
+    };
+    assert_eq!(apply_settings("Утро", &dual), "Утро (Utro)");
+    assert_eq!(
+        apply_settings("Around the World", &dual),
+        "Around the World"
+    );
+
+    let cyrillic_only = Settings {
+        mode: TransliterationMode::Replace,
+        scripts: parse_scripts("Cyrillic").unwrap(),
+    };
+    assert_eq!(
+        apply_settings("Beyoncé & Кино 🎵", &cyrillic_only),
+        "Beyoncé & Kino 🎵"
+    );
+    assert_eq!(transliterate("Beyoncé", &[]), "Beyonce");
+    assert!(parse_scripts("cyrillic, klingon").is_err());
+    assert_eq!(TransliterationMode::parse("off"), Ok(None));
+}